@@ -0,0 +1,35 @@
+// Generates the server of the gRPC service of proto/rapid.proto
+// for the grpc feature. The messages are declared in src/grpc.rs, so the
+// build doesn't need protoc.
+fn main() {
+    #[cfg(feature = "grpc")]
+    grpc();
+}
+
+#[cfg(feature = "grpc")]
+fn grpc() {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    println!("cargo:rerun-if-changed=build.rs");
+    let methods = [
+        ("load", "Load", "LoadRequest", "LoadReply"),
+        ("unload", "Unload", "SessionRequest", "UnloadReply"),
+        ("run", "Run", "SessionRequest", "ExecutionReply"),
+        ("step", "Step", "StepRequest", "ExecutionReply"),
+        ("set_breakpoint", "SetBreakpoint", "BreakpointRequest", "BreakpointsReply"),
+        ("clear_breakpoint", "ClearBreakpoint", "BreakpointRequest", "BreakpointsReply"),
+        ("inspect", "Inspect", "InspectRequest", "InspectReply"),
+        ("signal", "Signal", "SignalRequest", "SignalReply"),
+    ];
+    let mut service = Service::builder().name("Interpreter").package("rapid");
+    for (name, route, input, output) in methods {
+        service = service.method(Method::builder()
+            .name(name)
+            .route_name(route)
+            .input_type(format!("crate::grpc::{}", input))
+            .output_type(format!("crate::grpc::{}", output))
+            .codec_path("tonic_prost::ProstCodec")
+            .build());
+    }
+    Builder::new().build_client(false).compile(&[service.build()]);
+}
@@ -0,0 +1,81 @@
+/* C interface of rapid_rust, the shared library built with
+ * `cargo build --release --features ffi`.
+ *
+ * Text goes in and out as NUL terminated UTF-8, structured results are JSON
+ * text like the command line prints. Every function returns one of the codes
+ * below. The text a function gives back through `out` is the result, or the
+ * error message when it didn't return RAPID_OK; free it with
+ * rapid_string_free. `out` may be NULL when the text isn't wanted. */
+
+#ifndef RAPID_RUST_H
+#define RAPID_RUST_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define RAPID_OK 0
+/* A pointer is NULL, or text isn't UTF-8 or the JSON expected */
+#define RAPID_ERR_ARGUMENT 1
+/* The lexer or the parser rejected the source */
+#define RAPID_ERR_SYNTAX 2
+/* The source parsed but doesn't resolve */
+#define RAPID_ERR_SEMANTIC 3
+/* The program stopped with an error of RAPID */
+#define RAPID_ERR_RUNTIME 4
+/* No data or signal has the name */
+#define RAPID_ERR_UNKNOWN 5
+/* A bug of the library, the handle shouldn't be used anymore */
+#define RAPID_ERR_PANIC 6
+
+/* Program of a module that runs on simulated time. Its data, signals and
+ * clock keep their values from one run to the next. */
+typedef struct RapidProgram RapidProgram;
+
+/* Free text the library gave back, NULL is ignored */
+void rapid_string_free(char *text);
+
+/* Modules of a source with their spans as JSON */
+int rapid_parse(const char *source, char **out);
+
+/* Errors, warnings and lints of a module as a JSON array */
+int rapid_check(const char *source, char **out);
+
+/* Load a module with the signals of a JSON object by name and data type,
+ * like {"doGripper": "signaldo"}, or none for NULL. On an error `out` gets
+ * the errors of the module, on a line each. */
+int rapid_program_new(const char *source, const char *signals, RapidProgram **program, char **out);
+
+/* Free a program, NULL is ignored */
+void rapid_program_free(RapidProgram *program);
+
+/* Run a routine, "main" for a NULL entry, with the answers of the operator
+ * dialogs. `out` gets the output, the moves, the time and the error as
+ * JSON, also for RAPID_ERR_RUNTIME. */
+int rapid_program_run(RapidProgram *program, const char *entry, uint64_t max_instructions,
+                      const double *answers, size_t answer_count, char **out);
+
+/* Value of the data of a module as JSON */
+int rapid_program_get(RapidProgram *program, const char *name, char **out);
+
+/* Assign JSON to the data of a module: a number, bool or string, or an
+ * array for records and arrays */
+int rapid_program_set(RapidProgram *program, const char *name, const char *value, char **out);
+
+/* Set an input signal */
+int rapid_program_set_input(RapidProgram *program, const char *name, double value, char **out);
+
+/* Value of a signal */
+int rapid_program_signal(RapidProgram *program, const char *name, double *value, char **out);
+
+/* Seconds of simulated time the runs took, 0 for NULL */
+double rapid_program_time(const RapidProgram *program);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,301 @@
+use std::fs;
+use std::path::Path;
+
+use crate::cache::ParseCache;
+use crate::config::{self, Config, SignalConfig};
+use crate::error::Error;
+use crate::lazy::LazyProgram;
+use crate::lexer;
+use crate::parser::{self, Module, Program};
+
+// ------------------ Backup -----------------------/
+
+/// Program of one task of a backup, not resolved yet
+pub struct TaskProgram {
+    pub name: String,
+    // System modules first, as the controller loads them
+    pub program: Program,
+}
+
+/// Controller backup as RobotStudio or the FlexPendant save it:
+///
+/// ```text
+/// BACKINFO/backinfo.txt   Names of the tasks
+/// RAPID/TASK1/PROGMOD/    Program modules of the first task
+/// RAPID/TASK1/SYSMOD/     System modules of the first task
+/// SYSPAR/EIO.cfg          I/O configuration, and the other .cfg files
+/// ```
+pub struct Backup {
+    pub tasks: Vec<TaskProgram>,
+    // Configuration files of SYSPAR by file name, like EIO.cfg
+    pub configs: Vec<(String, Config)>,
+}
+
+impl Backup {
+    /// Configuration file of SYSPAR
+    pub fn config(&self, file: &str) -> Option<&Config> {
+        self.configs.iter().find(|(name, _)| name.eq_ignore_ascii_case(file)).map(|(_, config)| config)
+    }
+
+    /// Signals of SYSPAR/EIO.cfg, none without one
+    pub fn signals(&self) -> Result<Vec<SignalConfig>, String> {
+        self.config("EIO.cfg").map_or(Ok(Vec::new()), |config| config::signals(config).map_err(|err| format!("SYSPAR/EIO.cfg: {}", err)))
+    }
+}
+
+/// Whether a path is a backup directory, with a RAPID directory in it, or
+/// the Pack&Go or zip archive of one
+pub fn is_backup(path: &Path) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str()).map(str::to_ascii_lowercase);
+    path.join("RAPID").is_dir() || matches!(extension.as_deref(), Some("rspag" | "zip"))
+}
+
+/// Load the tasks and configuration of a backup directory or archive.
+/// Errors name the file in the backup, like `RAPID/TASK1/PROGMOD/Cell.mod`.
+pub fn load(path: &Path) -> Result<Backup, String> {
+    load_with(path, &|source| Program::from_source(source).map(|program| program.modules))
+}
+
+/// Load a backup like `load`, with the modules of its files from a cache
+pub fn load_cached(path: &Path, cache: &ParseCache) -> Result<Backup, String> {
+    load_with(path, &|source| cache.modules(source))
+}
+
+fn load_with(path: &Path, parse: &(dyn Fn(&str) -> Result<Vec<Module>, Error> + Sync)) -> Result<Backup, String> {
+    let files = if path.is_dir() { dir_files(path)? } else { archive_files(path)? };
+    from_files(files, parse).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+/// Load the tasks of a backup directory or archive for lazy parsing, each
+/// with its name. A module file is only parsed once a routine or data it
+/// declares is needed.
+pub fn load_lazy(path: &Path) -> Result<Vec<(String, LazyProgram)>, String> {
+    let files = if path.is_dir() { dir_files(path)? } else { archive_files(path)? };
+    let (tasks, _) = task_files(&files).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let mut lazy = Vec::new();
+    for (name, modules) in tasks {
+        let mut program = LazyProgram::new();
+        for (path, system, bytes) in modules {
+            program.register(&path, lexer::decode(bytes), system);
+        }
+        lazy.push((name, program));
+    }
+    Ok(lazy)
+}
+
+/// Backup of the files in it, by their path with `/`
+fn from_files(files: Vec<(String, Vec<u8>)>, parse: &(dyn Fn(&str) -> Result<Vec<Module>, Error> + Sync)) -> Result<Backup, String> {
+    let (tasks, configs) = task_files(&files)?;
+    let mut backup = Backup { tasks: Vec::new(), configs };
+    for (name, modules) in tasks {
+        let modules = parser::parse_all(&modules, |(path, system, bytes)| {
+            let mut modules = parse(&lexer::decode(bytes)).map_err(|err| err.in_file(Path::new(path)).to_string())?;
+            for module in modules.iter_mut() {
+                module.system |= system;
+            }
+            Ok::<_, String>(modules)
+        })?;
+        let program = Program { modules, ..Program::default() };
+        backup.tasks.push(TaskProgram { name, program });
+    }
+    Ok(backup)
+}
+
+/// Module files of the tasks by task name, system modules first and each
+/// kind by name, and the configuration files of SYSPAR
+fn task_files(files: &[(String, Vec<u8>)]) -> Result<(TaskFiles<'_>, Vec<(String, Config)>), String> {
+    let upper = |path: &str| path.to_ascii_uppercase();
+    let names = files.iter()
+        .find(|(path, _)| upper(path) == "BACKINFO/BACKINFO.TXT")
+        .map(|(_, bytes)| task_names(&lexer::decode(bytes)))
+        .unwrap_or_default();
+
+    // RAPID/TASK1/PROGMOD/Cell.mod by task number and directory
+    let mut tasks: Vec<(u32, String, Vec<ModuleFile>)> = Vec::new();
+    let mut configs = Vec::new();
+    for (path, bytes) in files.iter() {
+        let parts: Vec<&str> = path.split('/').collect();
+        match parts.as_slice() {
+            [rapid, task, dir, file] if upper(rapid) == "RAPID" && upper(task).starts_with("TASK") => {
+                let number = match upper(task)["TASK".len()..].parse::<u32>() {
+                    Ok(number) => number,
+                    Err(_) => continue,
+                };
+                let extension = file.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+                let system = match (upper(dir).as_str(), extension.as_deref()) {
+                    ("PROGMOD", Some("mod" | "modx")) => false,
+                    ("SYSMOD", Some("sys" | "sysx")) => true,
+                    _ => continue,
+                };
+                let idx = match tasks.iter().position(|(other, _, _)| *other == number) {
+                    Some(idx) => idx,
+                    None => {
+                        tasks.push((number, String::from(*task), Vec::new()));
+                        tasks.len() - 1
+                    },
+                };
+                tasks[idx].2.push((path.clone(), system, bytes));
+            },
+            [syspar, file] if upper(syspar) == "SYSPAR" && upper(file).ends_with(".CFG") => {
+                let config = Config::parse(&lexer::decode(bytes)).map_err(|err| format!("{}: {}", path, err))?;
+                configs.push((String::from(*file), config));
+            },
+            _ => (),
+        }
+    }
+    if tasks.is_empty() {
+        return Err(String::from("no task in RAPID"));
+    }
+    tasks.sort_by_key(|(number, _, _)| *number);
+
+    let tasks = tasks.into_iter()
+        .map(|(number, dir, mut modules)| {
+            // System modules first, each kind by name
+            modules.sort_by(|(path, system, _), (other, other_system, _)| other_system.cmp(system).then_with(|| upper(path).cmp(&upper(other))));
+            let name = names.iter().find(|(task, _)| *task == number).map_or(dir, |(_, name)| name.clone());
+            (name, modules)
+        })
+        .collect();
+    Ok((tasks, configs))
+}
+
+/// Path of a module file, whether it holds system modules and its source
+type ModuleFile<'a> = (String, bool, &'a [u8]);
+
+/// Module files of each task, by task name
+type TaskFiles<'a> = Vec<(String, Vec<ModuleFile<'a>>)>;
+
+/// Names of the tasks by number in backinfo.txt, like `>>TASK1: (T_ROB1,,)`
+fn task_names(backinfo: &str) -> Vec<(u32, String)> {
+    backinfo.lines()
+        .filter_map(|line| {
+            let (task, rest) = line.trim().strip_prefix(">>TASK")?.split_once(':')?;
+            let name = rest.trim().strip_prefix('(')?.split([',', ')']).next()?.trim();
+            Some((task.parse().ok()?, String::from(name))).filter(|(_, name)| !name.is_empty())
+        })
+        .collect()
+}
+
+/// Files of RAPID, SYSPAR and BACKINFO in a backup directory
+fn dir_files(root: &Path) -> Result<Vec<(String, Vec<u8>)>, String> {
+    fn add(dir: &Path, prefix: &str, files: &mut Vec<(String, Vec<u8>)>) -> Result<(), String> {
+        let entries = fs::read_dir(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+        for entry in entries.filter_map(Result::ok) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = entry.path();
+            if path.is_dir() {
+                add(&path, &format!("{}{}/", prefix, name), files)?;
+            } else {
+                let bytes = fs::read(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+                files.push((format!("{}{}", prefix, name), bytes));
+            }
+        }
+        Ok(())
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(root).map_err(|err| format!("{}: {}", root.display(), err))?.filter_map(Result::ok) {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.path().is_dir() && ["RAPID", "SYSPAR", "BACKINFO"].iter().any(|dir| dir.eq_ignore_ascii_case(&name)) {
+            add(&entry.path(), &format!("{}/", name), &mut files)?;
+        }
+    }
+    Ok(files)
+}
+
+/// Files of the backup in a zip archive, like the Pack&Go of a station.
+/// The backup may be in a directory of the archive, but only one.
+#[cfg(feature = "archive")]
+fn archive_files(path: &Path) -> Result<Vec<(String, Vec<u8>)>, String> {
+    use std::io::Read;
+
+    let error = |err: zip::result::ZipError| format!("{}: {}", path.display(), err);
+    let file = fs::File::open(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let mut archive = zip::ZipArchive::new(file).map_err(error)?;
+    let names: Vec<String> = archive.file_names().map(|name| name.replace('\\', "/")).collect();
+    // The backup is where the first RAPID/TASK directory is
+    let mut roots: Vec<&str> = names.iter()
+        .filter_map(|name| {
+            let idx = name.to_ascii_uppercase().find("RAPID/TASK")?;
+            Some(&name[..idx]).filter(|root| root.is_empty() || root.ends_with('/'))
+        })
+        .collect();
+    roots.sort_unstable();
+    roots.dedup();
+    let root = match roots.as_slice() {
+        [root] => String::from(*root),
+        [] => return Err(format!("{}: no backup in the archive", path.display())),
+        _ => return Err(format!("{}: more than one backup in the archive", path.display())),
+    };
+
+    let mut files = Vec::new();
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx).map_err(error)?;
+        let name = entry.name().replace('\\', "/");
+        let relative = match name.strip_prefix(&root) {
+            Some(relative) if entry.is_file() => String::from(relative),
+            _ => continue,
+        };
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes).map_err(|err| format!("{}: {}: {}", path.display(), name, err))?;
+        files.push((relative, bytes));
+    }
+    Ok(files)
+}
+
+#[cfg(not(feature = "archive"))]
+fn archive_files(path: &Path) -> Result<Vec<(String, Vec<u8>)>, String> {
+    Err(format!("{}: reading archives needs the archive feature", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn file(path: &str, text: &str) -> (String, Vec<u8>) {
+        (String::from(path), text.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn loads_tasks() {
+        let parse = |source: &str| Program::from_source(source).map(|program| program.modules);
+        let files = vec![
+            file("BACKINFO/backinfo.txt", ">>SYSTEM_ID:\nCell 1\n>>TASK1: (T_ROB1,,)\n>>TASK2: (T_ROB2,,)\n"),
+            file("RAPID/TASK2/PROGMOD/Unload.mod", "MODULE Unload\nENDMODULE"),
+            file("RAPID/TASK1/PROGMOD/Main.mod", "MODULE Main\nENDMODULE"),
+            file("RAPID/TASK1/PROGMOD/Cell.modx", "MODULE Cell\nENDMODULE"),
+            file("RAPID/TASK1/PROGMOD/notes.txt", "Not a module"),
+            file("RAPID/TASK1/SYSMOD/user.sys", "MODULE user(SYSMODULE)\nENDMODULE"),
+            file("RAPID/TASK10/PROGMOD/Vision.mod", "MODULE Vision\nENDMODULE"),
+            file("SYSPAR/EIO.cfg", "EIO:CFG_1.0:6:1::\nEIO_SIGNAL:\n      -Name \"diPartReady\" -SignalType \"DI\""),
+        ];
+        let backup = from_files(files, &parse).unwrap();
+        let tasks: Vec<(&str, Vec<&str>)> = backup.tasks.iter()
+            .map(|task| (task.name.as_str(), task.program.modules.iter().map(|module| module.name.as_str()).collect()))
+            .collect();
+        assert_eq!(tasks, vec![("T_ROB1", vec!["user", "Cell", "Main"]), ("T_ROB2", vec!["Unload"]), ("TASK10", vec!["Vision"])]);
+        assert!(backup.tasks[0].program.modules[0].system);
+        assert_eq!(backup.signals().unwrap()[0].name, "diPartReady");
+
+        let files = vec![file("RAPID/TASK1/PROGMOD/Main.mod", "MODULE Main\n    PROC main()\n        !\nENDMODULE")];
+        assert!(from_files(files, &parse).err().unwrap().starts_with("RAPID/TASK1/PROGMOD/Main.mod: "));
+        assert_eq!(from_files(vec![file("SYSPAR/SYS.cfg", "SYS:CFG_1.0:6:1::")], &parse).err().as_deref(), Some("no task in RAPID"));
+    }
+
+    #[test]
+    fn loads_tasks_lazily() {
+        let dir = std::env::temp_dir().join(format!("rapid_rust_backup_{}", std::process::id()));
+        let progmod = dir.join("RAPID").join("TASK1").join("PROGMOD");
+        fs::create_dir_all(&progmod).unwrap();
+        fs::write(progmod.join("Main.mod"), "MODULE Main\n    PROC main()\n        rPick;\n    ENDPROC\nENDMODULE").unwrap();
+        fs::write(progmod.join("Pick.mod"), "MODULE Pick\n    PROC rPick()\n    ENDPROC\nENDMODULE").unwrap();
+        fs::write(progmod.join("Old.mod"), "MODULE Old\n    PROC rOld(\nENDMODULE").unwrap();
+
+        let mut tasks = load_lazy(&dir).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        let (name, program) = &mut tasks[0];
+        assert_eq!((name.as_str(), program.len(), program.parsed()), ("TASK1", 3, 0));
+        let modules: Vec<String> = program.program("main").unwrap().modules.into_iter().map(|module| module.name).collect();
+        assert_eq!(modules, vec!["Main", "Pick"]);
+        assert!(program.routine("rOld").is_err_and(|err| err.to_string().starts_with("RAPID/TASK1/PROGMOD/Old.mod: ")));
+    }
+}
@@ -0,0 +1,26 @@
+use std::env;
+use std::net::SocketAddr;
+use std::process::ExitCode;
+
+use rapid_rust::grpc;
+
+/// Interpreter control service over gRPC, on the address of the first
+/// argument or 127.0.0.1:50051
+#[tokio::main]
+async fn main() -> ExitCode {
+    let address = env::args().nth(1).unwrap_or_else(|| String::from("127.0.0.1:50051"));
+    let address: SocketAddr = match address.parse() {
+        Ok(address) => address,
+        Err(err) => {
+            eprintln!("{}: {}", address, err);
+            return ExitCode::from(2);
+        },
+    };
+    match grpc::serve(address).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        },
+    }
+}
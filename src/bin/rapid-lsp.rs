@@ -0,0 +1,19 @@
+use std::io;
+use std::process::ExitCode;
+
+use rapid_rust::lsp;
+
+/// Language server for RAPID, speaking the protocol on standard input and
+/// output
+fn main() -> ExitCode {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    match lsp::run(&mut stdin.lock(), &mut stdout.lock()) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        },
+    }
+}
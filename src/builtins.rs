@@ -0,0 +1,3021 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Read, Seek, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::host::{self, Event, Host, IoBoard, ProgramData, Severity, Socket, SyncPoint, Target, Waypoint};
+use crate::interpreter::{LoadError, RuntimeError};
+use crate::lexer::Span;
+use crate::loader::{self, ModuleChange};
+use crate::parser::{self, Callee, DataDecl, Param, ParamMode, Storage};
+use crate::variable::{self, Variable};
+
+// ------------------ Built-in routines -----------------------/
+
+/// Implementation of a built-in routine. Arguments are indexed by parameter,
+/// `None` for optional parameters that weren't passed. The values left in
+/// VAR, PERS and INOUT arguments are copied back to the caller.
+pub type Native = fn(&mut Host, &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError>;
+
+/// Routine of the simulated system, callable from any module unless a
+/// routine of the program has the same name
+pub struct Builtin {
+    pub name: &'static str,
+    pub return_type: Option<&'static str>,
+    pub params: Vec<Param>,
+    pub call: Native,
+}
+
+// Parameters are declared like in RAPID: `[\][VAR|PERS|INOUT] type name`,
+// optional parameters that exclude each other are separated by '|'
+const BUILTINS: &[(&str, Option<&str>, &[&str], Native)] = &[
+    ("Abs", Some("num"), &["num Input"], abs),
+    ("ACos", Some("num"), &["num Value"], acos),
+    ("ASin", Some("num"), &["num Value"], asin),
+    ("ATan", Some("num"), &["num Value"], atan),
+    ("ATan2", Some("num"), &["num Y", "num X"], atan2),
+    ("Cos", Some("num"), &["num Angle"], cos),
+    ("Exp", Some("num"), &["num Exponent"], exp),
+    ("Max", Some("num"), &["num Value1", "num Value2"], max),
+    ("Min", Some("num"), &["num Value1", "num Value2"], min),
+    ("Pow", Some("num"), &["num Base", "num Exponent"], pow),
+    ("Round", Some("num"), &["num Val", "\\num Dec"], round),
+    ("Sin", Some("num"), &["num Angle"], sin),
+    ("Sqrt", Some("num"), &["num Value"], sqrt),
+    ("Tan", Some("num"), &["num Angle"], tan),
+    ("Trunc", Some("num"), &["num Val", "\\num Dec"], trunc),
+    ("DnumToNum", Some("num"), &["dnum Value", "\\switch Integer"], dnum_to_num),
+    ("NumToDnum", Some("dnum"), &["num Value"], num_to_dnum),
+    ("BitAnd", Some("byte"), &["byte BitData1", "byte BitData2"], bit_and),
+    ("BitCheck", Some("bool"), &["byte BitData", "num BitPos"], bit_check),
+    ("BitClear", None, &["VAR byte BitData", "num BitPos"], bit_clear),
+    ("BitLSh", Some("byte"), &["byte BitData", "num ShiftSteps"], bit_lsh),
+    ("BitNeg", Some("byte"), &["byte BitData"], bit_neg),
+    ("BitOr", Some("byte"), &["byte BitData1", "byte BitData2"], bit_or),
+    ("BitRSh", Some("byte"), &["byte BitData", "num ShiftSteps"], bit_rsh),
+    ("BitSet", None, &["VAR byte BitData", "num BitPos"], bit_set),
+    ("BitXOr", Some("byte"), &["byte BitData1", "byte BitData2"], bit_xor),
+    ("ClkRead", Some("num"), &["VAR clock Clock", "\\switch HighRes"], clk_read),
+    ("ClkReset", None, &["VAR clock Clock"], clk_reset),
+    ("ClkStart", None, &["VAR clock Clock"], clk_start),
+    ("ClkStop", None, &["VAR clock Clock"], clk_stop),
+    ("Close", None, &["VAR iodev IODevice"], close),
+    ("Open", None, &["string Object", "\\string File", "VAR iodev IODevice",
+        "\\switch Read|\\switch Write|\\switch Append|\\switch Bin"], open),
+    ("ReadNum", Some("num"), &["VAR iodev IODevice", "\\string Delim", "\\num Time"], read_num),
+    ("ReadStr", Some("string"), &["VAR iodev IODevice", "\\string Delim", "\\switch RemoveCR",
+        "\\switch DiscardHeaders", "\\num Time"], read_str),
+    ("Rewind", None, &["VAR iodev IODevice"], rewind),
+    ("Write", None, &["VAR iodev IODevice", "string String", "\\num Num|\\bool Bool|\\pos Pos|\\orient Orient",
+        "\\switch NoNewLine"], write),
+    ("SocketClose", None, &["VAR socketdev Socket"], socket_close),
+    ("SocketConnect", None, &["VAR socketdev Socket", "string Address", "num Port", "\\num Time"], socket_connect),
+    ("SocketCreate", None, &["VAR socketdev Socket", "\\switch UDP"], socket_create),
+    ("SocketGetStatus", Some("num"), &["VAR socketdev Socket"], socket_get_status),
+    ("SocketReceive", None, &["VAR socketdev Socket", "\\VAR string Str|\\VAR anytype Data", "\\num ReadNoOfBytes",
+        "\\VAR num NoRecBytes", "\\num Time"], socket_receive),
+    ("SocketSend", None, &["VAR socketdev Socket", "\\string Str|\\anytype Data", "\\num NoOfBytes"], socket_send),
+    ("CancelLoad", None, &["VAR loadsession LoadNo"], cancel_load),
+    ("Load", None, &["\\switch Dynamic", "string FilePath", "\\string File", "\\switch CheckRef"], load),
+    ("StartLoad", None, &["\\switch Dynamic", "string FilePath", "\\string File", "VAR loadsession LoadNo"], start_load),
+    ("UnLoad", None, &["\\switch ErrIfChanged|\\switch Save", "string FilePath", "\\string File"], unload),
+    ("WaitLoad", None, &["\\string UnloadPath", "\\string UnloadFile", "VAR loadsession LoadNo", "\\switch CheckRef"], wait_load),
+    ("ErrLog", None, &["num ErrorID", "\\switch W|\\switch I", "anytype Argument1", "anytype Argument2", "anytype Argument3",
+        "anytype Argument4", "anytype Argument5"], err_log),
+    ("ErrWrite", None, &["\\switch W|\\switch I", "string Header", "string Reason", "\\string RL2", "\\string RL3",
+        "\\string RL4"], err_write),
+    ("AccSet", None, &["num Acc", "num Ramp"], acc_set),
+    ("ConfJ", None, &["\\switch On|\\switch Off"], conf_j),
+    ("ConfL", None, &["\\switch On|\\switch Off"], conf_l),
+    ("Dim", Some("num"), &["anytype ArrPar", "num DimNo"], dim),
+    ("GetDataVal", None, &["string Object", "\\string Block", "VAR anytype Value"], get_data_val),
+    ("SearchObject", Some("bool"), &["string Pattern", "VAR string Object", "\\string Type"], search_object),
+    ("SetDataVal", None, &["string Object", "\\string Block", "anytype Value"], set_data_val),
+    ("Type", Some("string"), &["anytype Data", "\\switch BaseName"], type_of),
+    ("DInput", Some("num"), &["signaldi Signal"], signal_value),
+    ("DOutput", Some("num"), &["signaldo Signal"], signal_value),
+    ("SetAO", None, &["signalao Signal", "num Value"], set_output),
+    ("SetDO", None, &["signaldo Signal", "num Value"], set_output),
+    ("SetGO", None, &["signalgo Signal", "num Value"], set_output),
+    ("WaitSyncTask", None, &["\\switch InPos", "VAR syncident SyncID", "PERS anytype TaskList", "\\num TimeOut"], wait_sync_task),
+    ("WaitTime", None, &["\\switch InPos", "num Time"], wait_time),
+    ("WaitDI", None, &["signaldi Signal", "num Value", "\\num MaxTime"], wait_signal),
+    ("WaitDO", None, &["signaldo Signal", "num Value", "\\num MaxTime"], wait_signal),
+    ("ISignalDI", None, &["\\switch Single", "signaldi Signal", "num TriggValue", "intnum Interrupt"], isignal),
+    ("ISignalDO", None, &["\\switch Single", "signaldo Signal", "num TriggValue", "intnum Interrupt"], isignal),
+    ("ITimer", None, &["\\switch Single", "num Time", "intnum Interrupt"], itimer),
+    ("IDelete", None, &["intnum Interrupt"], idelete),
+    ("ISleep", None, &["intnum Interrupt"], isleep),
+    ("IWatch", None, &["intnum Interrupt"], iwatch),
+    ("IDisable", None, &[], idisable),
+    ("IEnable", None, &[], ienable),
+    ("MoveAbsJ", None, &["jointtarget ToJointPos", "speeddata Speed", "\\num V|\\num T",
+        "zonedata Zone", "\\num Z", "tooldata Tool", "\\wobjdata WObj"], move_abs_j),
+    ("MoveC", None, &["robtarget CirPoint", "robtarget ToPoint", "speeddata Speed", "\\num V|\\num T",
+        "zonedata Zone", "\\num Z", "tooldata Tool", "\\wobjdata WObj"], move_c),
+    ("MoveJ", None, &["robtarget ToPoint", "speeddata Speed", "\\num V|\\num T",
+        "zonedata Zone", "\\num Z", "tooldata Tool", "\\wobjdata WObj"], move_j),
+    ("MoveL", None, &["robtarget ToPoint", "speeddata Speed", "\\num V|\\num T",
+        "zonedata Zone", "\\num Z", "tooldata Tool", "\\wobjdata WObj"], move_l),
+    ("CJointT", Some("jointtarget"), &["\\num TaskRef|\\string TaskName"], c_joint_t),
+    ("CRobT", Some("robtarget"), &["\\num TaskRef|\\string TaskName", "\\tooldata Tool", "\\wobjdata WObj"], c_rob_t),
+    ("EulerZYX", Some("num"), &["\\switch X|\\switch Y|\\switch Z", "orient Rotation"], euler_zyx),
+    ("NOrient", Some("orient"), &["orient Rotation"], n_orient),
+    ("Offs", Some("robtarget"), &["robtarget Point", "num XOffset", "num YOffset", "num ZOffset"], offs),
+    ("OrientZYX", Some("orient"), &["num ZAngle", "num YAngle", "num XAngle"], orient_zyx),
+    ("PoseInv", Some("pose"), &["pose Pose"], pose_inv),
+    ("PoseMult", Some("pose"), &["pose Pose1", "pose Pose2"], pose_mult),
+    ("PoseVect", Some("pos"), &["pose Pose", "pos Pos"], pose_vect),
+    ("RelTool", Some("robtarget"), &["robtarget Point", "num Dx", "num Dy", "num Dz", "\\num Rx", "\\num Ry", "\\num Rz"], rel_tool),
+    ("NumToStr", Some("string"), &["num Val", "num Dec", "\\switch Exp"], num_to_str),
+    ("StrFind", Some("num"), &["string Str", "num ChPos", "string Set", "\\switch NotInSet"], str_find),
+    ("StrLen", Some("num"), &["string Str"], str_len),
+    ("StrMap", Some("string"), &["string Str", "string FromMap", "string ToMap"], str_map),
+    ("StrMatch", Some("num"), &["string Str", "num ChPos", "string Pattern"], str_match),
+    ("StrMemb", Some("bool"), &["string Str", "num ChPos", "string Set"], str_memb),
+    ("StrOrder", Some("bool"), &["string Str1", "string Str2", "string Order"], str_order),
+    ("StrPart", Some("string"), &["string Str", "num ChPos", "num Len"], str_part),
+    ("StrToVal", Some("bool"), &["string Str", "VAR anytype Val"], str_to_val),
+    ("ValToStr", Some("string"), &["anytype Val"], val_to_str),
+    ("VelSet", None, &["num Override", "num Max"], vel_set),
+    ("TPReadFK", None, &["VAR num TPAnswer", "string TPText", "string TPFK1", "string TPFK2",
+        "string TPFK3", "string TPFK4", "string TPFK5", "\\num MaxTime"], tp_read_fk),
+    ("TPReadNum", None, &["VAR num TPAnswer", "string TPText", "\\num MaxTime"], tp_read_num),
+    ("EXIT", None, &[], exit),
+];
+
+/// Table of all built-in routines, `Callee::Builtin` indexes into it
+pub fn builtins() -> &'static [Builtin] {
+    static TABLE: OnceLock<Vec<Builtin>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        BUILTINS.iter().map(|(name, return_type, params, call)| Builtin {
+            name,
+            return_type: *return_type,
+            params: parse_params(params),
+            call: *call,
+        }).collect()
+    })
+}
+
+/// Procedure of the host, or else a built-in procedure, with the name a late
+/// bound call evaluated to
+pub fn native_procedure(name: &str, host_routines: &[HostRoutine]) -> Option<Callee> {
+    let host = host_routines.iter().position(|routine| routine.name.eq_ignore_ascii_case(name));
+    match host {
+        Some(idx) => host_routines[idx].return_type.is_none().then_some(Callee::Host(idx)),
+        None => builtins().iter()
+            .position(|builtin| builtin.name.eq_ignore_ascii_case(name))
+            .filter(|idx| builtins()[*idx].return_type.is_none())
+            .map(Callee::Builtin),
+    }
+}
+
+fn parse_params(specs: &[&str]) -> Vec<Param> {
+    specs.iter().enumerate()
+        .flat_map(|(group, spec)| spec.split('|').map(move |spec| param(spec, group)))
+        .collect()
+}
+
+fn param(spec: &str, group: usize) -> Param {
+    let optional = spec.starts_with('\\');
+    let mut words: Vec<&str> = spec.trim_start_matches('\\').split_whitespace().collect();
+    let mode = match words[0] {
+        "VAR" => ParamMode::Var,
+        "PERS" => ParamMode::Pers,
+        "INOUT" => ParamMode::InOut,
+        _ => ParamMode::In,
+    };
+    if mode != ParamMode::In {
+        words.remove(0);
+    }
+
+    Param {
+        decl: DataDecl {
+            name: String::from(words[1]),
+            data_type: String::from(words[0]),
+            storage: Storage::Var,
+            local: false,
+            // Parameters of any type have no default value to check against
+            value: Variable::from(words[0]).unwrap_or(Variable::Void),
+            init: None,
+            span: Span::default(),
+        },
+        mode,
+        optional,
+        group,
+        dims: 0,
+    }
+}
+
+/// Value passed for a parameter, converted to the parameter's data type
+pub fn argument(param: &Param, value: Variable) -> Result<Variable, RuntimeError> {
+    let mut var = param.decl.value.clone();
+    if let Variable::Void = var {
+        return Ok(value);
+    }
+    param.bind(&mut var, value)?;
+    Ok(var)
+}
+
+fn num_arg(args: &[Option<Variable>], idx: usize) -> Result<Option<f64>, RuntimeError> {
+    args[idx].as_ref().map(Variable::number).transpose()
+}
+
+fn num(args: &[Option<Variable>], idx: usize) -> Result<f64, RuntimeError> {
+    match num_arg(args, idx)? {
+        Some(value) => Ok(value),
+        None => Err(RuntimeError::type_mismatch(format!("Missing argument {}", idx + 1))),
+    }
+}
+
+fn str_arg(args: &[Option<Variable>], idx: usize) -> Result<String, RuntimeError> {
+    match &args[idx] {
+        Some(Variable::Str(text)) => Ok(text.clone()),
+        Some(var) => Err(RuntimeError::type_mismatch(format!("Expected string, found {}", var.type_name()))),
+        None => Ok(String::new()),
+    }
+}
+
+// ------------------ Host routines -----------------------/
+
+/// Implementation of a routine registered by the host
+pub type HostCall = Box<dyn FnMut(&mut Args) -> Result<Option<Variable>, RuntimeError>>;
+
+/// Rust closure callable from RAPID like a built-in routine, see
+/// `Program::register_proc` and `Program::register_func`
+pub struct HostRoutine {
+    pub name: String,
+    pub return_type: Option<String>,
+    pub params: Vec<Param>,
+    call: RefCell<HostCall>,
+}
+
+impl Builtin {
+    /// Header of the routine, like `FUNC num Abs(num Input)`
+    pub fn declaration(&self) -> String {
+        parser::declaration(self.name, self.return_type, &self.params)
+    }
+}
+
+impl HostRoutine {
+    pub fn new(name: &str, return_type: Option<&str>, params: &[&str], call: HostCall) -> HostRoutine {
+        HostRoutine {
+            name: String::from(name),
+            return_type: return_type.map(String::from),
+            params: parse_params(params),
+            call: RefCell::new(call),
+        }
+    }
+
+    /// Call the closure with the arguments by parameter, the value it
+    /// returns is converted to the return type
+    pub fn call(&self, values: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+        let result = (self.call.borrow_mut())(&mut Args { name: &self.name, params: &self.params, values })?;
+        match (result, self.return_type.as_deref().map(Variable::from)) {
+            (Some(value), Some(Ok(mut var))) => {
+                var.set(value)?;
+                Ok(Some(var))
+            },
+            (result, _) => Ok(result),
+        }
+    }
+}
+
+/// Arguments of a call to a host routine, by parameter name
+pub struct Args<'a> {
+    name: &'a str,
+    params: &'a [Param],
+    values: &'a mut [Option<Variable>],
+}
+
+impl<'a> Args<'a> {
+    fn slot(&self, name: &str) -> Result<usize, RuntimeError> {
+        match self.params.iter().position(|param| param.decl.name.eq_ignore_ascii_case(name)) {
+            Some(slot) => Ok(slot),
+            None => Err(RuntimeError::Unsupported { message: format!("'{}' has no parameter {}", self.name, name), span: Span::default() }),
+        }
+    }
+
+    /// Whether an argument was passed for the parameter
+    pub fn present(&self, name: &str) -> bool {
+        self.slot(name).is_ok_and(|slot| self.values[slot].is_some())
+    }
+
+    /// Value of the argument, converted to the type of the parameter
+    pub fn get(&self, name: &str) -> Result<&Variable, RuntimeError> {
+        match &self.values[self.slot(name)?] {
+            Some(var) => Ok(var),
+            None => Err(RuntimeError::type_mismatch(format!("Missing argument {} in call to {}", name, self.name))),
+        }
+    }
+
+    pub fn num(&self, name: &str) -> Result<f64, RuntimeError> {
+        self.get(name)?.number()
+    }
+
+    pub fn bool(&self, name: &str) -> Result<bool, RuntimeError> {
+        self.get(name)?.condition()
+    }
+
+    pub fn string(&self, name: &str) -> Result<&str, RuntimeError> {
+        match self.get(name)? {
+            Variable::Str(text) => Ok(text),
+            var => Err(RuntimeError::type_mismatch(format!("Expected string, found {}", var.type_name()))),
+        }
+    }
+
+    /// Store a value in a VAR, PERS or INOUT argument, it's copied back to
+    /// the caller's data when the routine returns
+    pub fn set(&mut self, name: &str, value: Variable) -> Result<(), RuntimeError> {
+        let slot = self.slot(name)?;
+        if self.params[slot].mode == ParamMode::In {
+            return Err(RuntimeError::Unsupported { message: format!("Parameter {} of {} is not VAR, PERS or INOUT", name, self.name), span: Span::default() });
+        }
+        match &mut self.values[slot] {
+            Some(var) => var.set(value),
+            None => Err(RuntimeError::type_mismatch(format!("Missing argument {} in call to {}", name, self.name))),
+        }
+    }
+}
+
+// ------------------ Error numbers -----------------------/
+
+/// Error constants of the simulated system with their ERRNO value. RAISE
+/// takes the numbers 1 to 90 for errors of the program, so the system
+/// errors are numbered from 1001.
+const ERRORS: &[(&str, i32)] = &[
+    ("ERR_ARGVALERR", 1001),
+    ("ERR_DIVZERO", 1002),
+    ("ERR_FNCNORET", 1003),
+    ("ERR_INDEX", 1004),
+    ("ERR_STRTOOLONG", 1005),
+    ("ERR_TP_MAXTIME", 1006),
+    ("ERR_TP_NO_CLIENT", 1007),
+    ("ERR_NO_ALIASIO_DEF", 1008),
+    ("ERR_WAIT_MAXTIME", 1009),
+    ("ERR_WAITSYNCTASK", 1010),
+    ("ERR_FILEACC", 1011),
+    ("ERR_FILEOPEN", 1012),
+    ("ERR_RCVDATA", 1013),
+    ("ERR_FILNOTFND", 1014),
+    ("ERR_LOADED", 1015),
+    ("ERR_SYNTAX", 1016),
+    ("ERR_LINKREF", 1017),
+    ("ERR_UNLOAD", 1018),
+    ("ERR_LOADNO_INUSE", 1019),
+    ("ERR_LOADNO_NOUSE", 1020),
+    ("ERR_REFUNKPRC", 1021),
+    ("ERR_SYM_ACCESS", 1022),
+    ("ERR_SOCK_CLOSED", 1023),
+    ("ERR_SOCK_TIMEOUT", 1024),
+    ("ERR_SOCK_NET_UNREACH", 1025),
+    ("ERR_ALRDYCNT", 1026),
+    ("ERR_UNKINO", 1027),
+];
+
+/// Numbers RAISE accepts for errors of the program
+pub const USER_ERRORS: std::ops::RangeInclusive<i32> = 1..=90;
+
+/// Number of an error constant like `ERR_DIVZERO`
+pub fn error_number(name: &str) -> Option<i32> {
+    ERRORS.iter()
+        .find(|error| error.0.eq_ignore_ascii_case(name))
+        .map(|error| error.1)
+}
+
+/// Name of the error constant with the given number
+pub fn error_name(errno: i32) -> Option<&'static str> {
+    ERRORS.iter()
+        .find(|error| error.1 == errno)
+        .map(|error| error.0)
+}
+
+// ------------------ Math -----------------------/
+
+// Integers a num holds exactly, and those of a dnum
+const NUM_INT_MIN: f64 = -8_388_607.0;
+const NUM_INT_MAX: f64 = 8_388_608.0;
+const DNUM_INT_MAX: f64 = 4_503_599_627_370_496.0;
+
+/// Predefined constants of the math functions, and the limits of num and
+/// dnum values
+pub fn math_data(name: &str) -> Option<Variable> {
+    let var = match name.to_ascii_uppercase().as_str() {
+        "PI" => Variable::Num(std::f64::consts::PI),
+        "E" => Variable::Num(std::f64::consts::E),
+        "NUM_MAX" => Variable::Num(f64::from(f32::MAX)),
+        "NUM_INT_MIN" => Variable::Num(NUM_INT_MIN),
+        "NUM_INT_MAX" => Variable::Num(NUM_INT_MAX),
+        "DNUM_MAX" => Variable::Dnum(f64::MAX),
+        "DNUM_INT_MIN" => Variable::Dnum(-DNUM_INT_MAX),
+        "DNUM_INT_MAX" => Variable::Dnum(DNUM_INT_MAX),
+        _ => return None,
+    };
+    Some(var)
+}
+
+/// Result of a math function, which has no value outside its domain
+fn num_result(name: &str, value: f64) -> Result<Option<Variable>, RuntimeError> {
+    if !value.is_finite() {
+        return Err(RuntimeError::type_mismatch(format!("Argument out of range for {}", name)));
+    }
+    Ok(Some(Variable::Num(value)))
+}
+
+fn abs(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    num_result("Abs", num(args, 0)?.abs())
+}
+
+fn sqrt(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    num_result("Sqrt", num(args, 0)?.sqrt())
+}
+
+fn exp(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    num_result("Exp", num(args, 0)?.exp())
+}
+
+fn pow(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    num_result("Pow", num(args, 0)?.powf(num(args, 1)?))
+}
+
+fn min(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    num_result("Min", num(args, 0)?.min(num(args, 1)?))
+}
+
+fn max(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    num_result("Max", num(args, 0)?.max(num(args, 1)?))
+}
+
+// Angles are in degrees, like on the controller
+
+fn sin(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    num_result("Sin", num(args, 0)?.to_radians().sin())
+}
+
+fn cos(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    num_result("Cos", num(args, 0)?.to_radians().cos())
+}
+
+fn tan(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    num_result("Tan", num(args, 0)?.to_radians().tan())
+}
+
+fn asin(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    num_result("ASin", num(args, 0)?.asin().to_degrees())
+}
+
+fn acos(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    num_result("ACos", num(args, 0)?.acos().to_degrees())
+}
+
+fn atan(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    num_result("ATan", num(args, 0)?.atan().to_degrees())
+}
+
+fn atan2(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    num_result("ATan2", num(args, 0)?.atan2(num(args, 1)?).to_degrees())
+}
+
+/// Number of decimals of Round, Trunc and NumToStr, 0 to 15
+fn decimals(args: &[Option<Variable>], idx: usize) -> Result<i32, RuntimeError> {
+    let dec = num_arg(args, idx)?.unwrap_or(0.0);
+    if dec != dec.trunc() || !(0.0..=15.0).contains(&dec) {
+        return Err(RuntimeError::type_mismatch(format!("Invalid number of decimals {}", dec)));
+    }
+    Ok(dec as i32)
+}
+
+/// Value rounded to `dec` decimals, halves away from zero. A result of
+/// zero has no sign.
+fn round_to(value: f64, dec: i32) -> f64 {
+    let scale = 10f64.powi(dec);
+    let rounded = (value * scale).round() / scale;
+    if rounded == 0.0 { 0.0 } else { rounded }
+}
+
+fn round(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    num_result("Round", round_to(num(args, 0)?, decimals(args, 1)?))
+}
+
+fn trunc(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let scale = 10f64.powi(decimals(args, 1)?);
+    num_result("Trunc", (num(args, 0)? * scale).trunc() / scale)
+}
+
+/// Dnum as a num, which holds integers exactly from -8388607 to 8388608.
+/// With `\Integer` the value has to be such an integer.
+fn dnum_to_num(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let value = num(args, 0)?;
+    let integer = args[1].is_some();
+    if (integer && (value != value.trunc() || !(NUM_INT_MIN..=NUM_INT_MAX).contains(&value))) || value.abs() > f64::from(f32::MAX) {
+        return Err(RuntimeError::type_mismatch(format!("Value {} out of range for num", host::format_dnum(value))));
+    }
+    num_result("DnumToNum", value)
+}
+
+fn num_to_dnum(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    Ok(Some(Variable::Dnum(num(args, 0)?)))
+}
+
+// ------------------ Data -----------------------/
+
+/// Number of elements in dimension DimNo of an array, counting from 1
+fn dim(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let dim_no = num(args, 1)?;
+    let mut var = match &args[0] {
+        Some(var @ Variable::Array(_)) => Some(var),
+        _ => return Err(RuntimeError::type_mismatch(String::from("Dim expects an array"))),
+    };
+    if dim_no == dim_no.trunc() && dim_no >= 1.0 {
+        for _ in 1..dim_no as usize {
+            var = match var {
+                Some(Variable::Array(items)) => items.first(),
+                _ => None,
+            };
+        }
+        if let Some(Variable::Array(items)) = var {
+            return Ok(Some(Variable::Num(items.len() as f64)));
+        }
+    }
+    Err(RuntimeError::type_mismatch(format!("Array has no dimension {}", dim_no)))
+}
+
+/// Name of the data type of a value, the one of its elements for an array.
+/// Values don't keep the alias type of the data they were declared as, so
+/// `\BaseName` names the same type.
+fn type_of(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    match &args[0] {
+        Some(var) => Ok(Some(Variable::Str(String::from(var.type_name())))),
+        None => Err(RuntimeError::type_mismatch(String::from("Missing argument 1"))),
+    }
+}
+
+// Data named by a string is looked up while the program runs, in the global
+// data the routine running the instruction sees. A `\Block` names the
+// module of the data.
+
+/// Built-in routines the program lends its global data to while they run
+const DATA_ACCESS: &[&str] = &["GetDataVal", "SearchObject", "SetDataVal"];
+
+pub fn accesses_data(builtin: &Builtin) -> bool {
+    DATA_ACCESS.contains(&builtin.name)
+}
+
+/// Motion instructions, the program tells them the names of the data it
+/// passes for the waypoints they log
+const MOVES: &[&str] = &["MoveAbsJ", "MoveC", "MoveJ", "MoveL"];
+
+pub fn logs_move(builtin: &Builtin) -> bool {
+    MOVES.contains(&builtin.name)
+}
+
+fn symbol_access(message: String) -> RuntimeError {
+    RuntimeError::SymbolAccess { message, span: Span::default() }
+}
+
+/// Slot of the data with a name, the nearest one when several match
+fn find_data(data: &ProgramData, name: &str, block: Option<&str>) -> Option<usize> {
+    data.names.iter().enumerate()
+        .filter(|(_, found)| found.name.eq_ignore_ascii_case(name))
+        .filter(|(_, found)| block.is_none_or(|block| found.module.eq_ignore_ascii_case(block)))
+        .filter_map(|(slot, found)| Some((found.rank(&data.module)?, slot)))
+        .min()
+        .map(|(_, slot)| slot)
+}
+
+/// Slot of the data named by the Object and `\Block` arguments at `idx`
+fn data_slot(data: &ProgramData, args: &[Option<Variable>], idx: usize) -> Result<usize, RuntimeError> {
+    let name = str_arg(args, idx)?;
+    let block = args[idx + 1].is_some().then(|| str_arg(args, idx + 1)).transpose()?;
+    match find_data(data, &name, block.as_deref()) {
+        Some(slot) if slot < data.values.len() => Ok(slot),
+        _ => Err(symbol_access(format!("No data {} where the program runs", name))),
+    }
+}
+
+fn get_data_val(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let slot = data_slot(&host.data, args, 0)?;
+    if let Some(value) = &mut args[2] {
+        value.set(host.data.values[slot].clone())?;
+    }
+    Ok(None)
+}
+
+fn set_data_val(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let slot = data_slot(&host.data, args, 0)?;
+    if host.data.names[slot].storage == Storage::Const {
+        return Err(symbol_access(format!("{} is a constant", host.data.names[slot].name)));
+    }
+    host.data.values[slot].set(args[2].take().unwrap_or(Variable::Void))?;
+    Ok(None)
+}
+
+/// Whether a name matches a pattern where '*' matches any characters and
+/// '?' one, both lowercase
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| matches(rest, &name[skip..])),
+        Some((c, rest)) => name.split_first().is_some_and(|(first, name)| (*c == '?' || c == first) && matches(rest, name)),
+    }
+}
+
+/// Find the data after Object, in the order it's declared, with a name
+/// matching the pattern and of the `\Type`. Starting with an empty Object
+/// it goes through the data the routine sees, FALSE after the last one.
+fn search_object(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let data = &host.data;
+    let pattern: Vec<char> = str_arg(args, 0)?.to_ascii_lowercase().chars().collect();
+    let data_type = args[2].is_some().then(|| str_arg(args, 2)).transpose()?;
+    let start = match str_arg(args, 1)? {
+        object if object.is_empty() => 0,
+        object => match find_data(data, &object, None) {
+            Some(slot) => slot + 1,
+            None => return Err(symbol_access(format!("No data {} where the program runs", object))),
+        },
+    };
+
+    let found = data.names.iter().enumerate().skip(start)
+        .filter(|(_, found)| data_type.as_ref().is_none_or(|data_type| found.data_type.eq_ignore_ascii_case(data_type)))
+        .filter(|(_, found)| matches(&pattern, &found.name.to_ascii_lowercase().chars().collect::<Vec<char>>()))
+        // Data shadowed by nearer data of the same name can't be reached by name
+        .find(|(slot, found)| find_data(data, &found.name, None) == Some(*slot));
+    match found {
+        Some((_, found)) => {
+            args[1] = Some(Variable::Str(found.name.clone()));
+            Ok(Some(Variable::Bool(true)))
+        },
+        None => Ok(Some(Variable::Bool(false))),
+    }
+}
+
+// ------------------ Bits -----------------------/
+
+fn byte(args: &[Option<Variable>], idx: usize) -> Result<u8, RuntimeError> {
+    match &args[idx] {
+        Some(Variable::Byte(value)) => Ok(*value),
+        Some(var) => variable::to_byte(var.number()?),
+        None => Err(RuntimeError::type_mismatch(format!("Missing argument {}", idx + 1))),
+    }
+}
+
+/// Bit number 1 to 8 of a byte, 1 being the least significant bit
+fn bit_pos(args: &[Option<Variable>], idx: usize) -> Result<u32, RuntimeError> {
+    let pos = num(args, idx)?;
+    if pos != pos.trunc() || !(1.0..=8.0).contains(&pos) {
+        return Err(RuntimeError::type_mismatch(format!("Bit position {} out of range", pos)));
+    }
+    Ok(pos as u32 - 1)
+}
+
+/// Shift of 1 to 8 steps, shifting out all bits gives 0
+fn shift_steps(args: &[Option<Variable>], idx: usize) -> Result<u32, RuntimeError> {
+    let steps = num(args, idx)?;
+    if steps != steps.trunc() || !(1.0..=8.0).contains(&steps) {
+        return Err(RuntimeError::type_mismatch(format!("Shift of {} steps out of range", steps)));
+    }
+    Ok(steps as u32)
+}
+
+fn byte_result(value: u8) -> Result<Option<Variable>, RuntimeError> {
+    Ok(Some(Variable::Byte(value)))
+}
+
+fn bit_and(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    byte_result(byte(args, 0)? & byte(args, 1)?)
+}
+
+fn bit_or(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    byte_result(byte(args, 0)? | byte(args, 1)?)
+}
+
+fn bit_xor(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    byte_result(byte(args, 0)? ^ byte(args, 1)?)
+}
+
+fn bit_neg(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    byte_result(!byte(args, 0)?)
+}
+
+fn bit_lsh(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    byte_result(byte(args, 0)?.checked_shl(shift_steps(args, 1)?).unwrap_or(0))
+}
+
+fn bit_rsh(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    byte_result(byte(args, 0)?.checked_shr(shift_steps(args, 1)?).unwrap_or(0))
+}
+
+fn bit_check(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    Ok(Some(Variable::Bool(byte(args, 0)? & (1 << bit_pos(args, 1)?) != 0)))
+}
+
+fn bit_set(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    args[0] = Some(Variable::Byte(byte(args, 0)? | (1 << bit_pos(args, 1)?)));
+    Ok(None)
+}
+
+fn bit_clear(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    args[0] = Some(Variable::Byte(byte(args, 0)? & !(1 << bit_pos(args, 1)?)));
+    Ok(None)
+}
+
+// ------------------ Clocks -----------------------/
+
+fn clock(args: &[Option<Variable>], idx: usize) -> Result<(Duration, Option<Duration>), RuntimeError> {
+    match &args[idx] {
+        Some(Variable::Clock { elapsed, started }) => Ok((*elapsed, *started)),
+        Some(var) => Err(RuntimeError::type_mismatch(format!("Expected clock, found {}", var.type_name()))),
+        None => Err(RuntimeError::type_mismatch(format!("Missing argument {}", idx + 1))),
+    }
+}
+
+fn clk_start(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    // Starting a running clock keeps it running
+    let (elapsed, started) = clock(args, 0)?;
+    let started = started.or_else(|| Some(host.clock.now()));
+    args[0] = Some(Variable::Clock { elapsed, started });
+    Ok(None)
+}
+
+fn clk_stop(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let (mut elapsed, started) = clock(args, 0)?;
+    if let Some(started) = started {
+        elapsed += host.clock.now().saturating_sub(started);
+    }
+    args[0] = Some(Variable::Clock { elapsed, started: None });
+    Ok(None)
+}
+
+fn clk_reset(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    clock(args, 0)?;
+    args[0] = Some(Variable::Clock { elapsed: Duration::ZERO, started: None });
+    Ok(None)
+}
+
+/// Seconds measured by a running or stopped clock, in milliseconds unless `\HighRes`
+fn clk_read(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let (mut elapsed, started) = clock(args, 0)?;
+    if let Some(started) = started {
+        elapsed += host.clock.now().saturating_sub(started);
+    }
+    let seconds = elapsed.as_secs_f64();
+    if args[1].is_some() {
+        return Ok(Some(Variable::Num(seconds)));
+    }
+    Ok(Some(Variable::Num((seconds * 1000.0).trunc() / 1000.0)))
+}
+
+// ------------------ I/O signals -----------------------/
+
+/// Name of the signal of the I/O board the argument is connected to
+fn signal(args: &[Option<Variable>], idx: usize) -> Result<String, RuntimeError> {
+    match &args[idx] {
+        Some(Variable::Signal(_, name)) => Ok(name.clone()),
+        Some(var) => Err(RuntimeError::type_mismatch(format!("Expected signal, found {}", var.type_name()))),
+        None => Err(RuntimeError::type_mismatch(format!("Missing argument {}", idx + 1))),
+    }
+}
+
+fn io<'a>(host: &Host<'a>, name: &str) -> Result<&'a IoBoard, RuntimeError> {
+    host.io.ok_or_else(|| RuntimeError::UnknownSignal { name: String::from(name), span: Span::default() })
+}
+
+/// Current value of a signal, after the input changes due by now
+fn read_signal(host: &Host, name: &str) -> Result<f64, RuntimeError> {
+    let io = io(host, name)?;
+    io.update(host.clock.now())?;
+    io.read(name)
+}
+
+fn signal_value(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let name = signal(args, 0)?;
+    Ok(Some(Variable::Num(read_signal(host, &name)?)))
+}
+
+fn set_output(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let name = signal(args, 0)?;
+    io(host, &name)?.write(&name, num(args, 1)?)?;
+    Ok(None)
+}
+
+/// Wait until a signal has the value
+fn wait_signal(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let name = signal(args, 0)?;
+    let value = num(args, 1)?;
+    let max_time = num_arg(args, 2)?;
+    if max_time.is_some_and(|max_time| !(max_time >= 0.0 && max_time.is_finite())) {
+        return Err(RuntimeError::type_mismatch(format!("Invalid MaxTime {}", max_time.unwrap_or_default())));
+    }
+    let io = io(host, &name)?;
+
+    if !wait_until(host, max_time, |host| Ok(read_signal(host, &name)? == value), || io.next_change(&name))? {
+        return Err(RuntimeError::WaitTimeout { signal: name, max_time: max_time.unwrap_or(f64::INFINITY), span: Span::default() });
+    }
+    Ok(None)
+}
+
+// ------------------ Interrupts -----------------------/
+
+/// Order an interrupt for a digital signal changing to 0 or 1, or for any
+/// change with 2. With `\Single` it occurs on the first change only.
+fn isignal(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let name = signal(args, 1)?;
+    let value = num(args, 2)?;
+    if ![0.0, 1.0, 2.0].contains(&value) {
+        return Err(RuntimeError::type_mismatch(format!("Invalid TriggValue {}", value)));
+    }
+    io(host, &name)?.watch(&name)?;
+    host.interrupts.order_signal(num(args, 3)?, &name, value, args[0].is_some())
+        .map(|_| None)
+}
+
+/// Order an interrupt every `Time` seconds, or once with `\Single`
+fn itimer(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let time = num(args, 1)?;
+    if !(time >= 0.01 && time.is_finite()) {
+        return Err(RuntimeError::type_mismatch(format!("Invalid time {}", time)));
+    }
+    let now = host.clock.now();
+    host.interrupts.order_timer(num(args, 2)?, Duration::from_secs_f64(time), now, args[0].is_some())
+        .map(|_| None)
+}
+
+/// Cancel an interrupt, so its data can be connected again
+fn idelete(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    host.interrupts.delete(num(args, 0)?);
+    Ok(None)
+}
+
+/// Deactivate an interrupt, its events are lost until IWatch
+fn isleep(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    host.interrupts.sleep(num(args, 0)?, true).map(|_| None)
+}
+
+fn iwatch(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    host.interrupts.sleep(num(args, 0)?, false).map(|_| None)
+}
+
+/// Hold back the TRAP routines of all interrupts until IEnable
+fn idisable(host: &mut Host, _: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    host.interrupts.disable(true);
+    Ok(None)
+}
+
+fn ienable(host: &mut Host, _: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    host.interrupts.disable(false);
+    Ok(None)
+}
+
+// ------------------ Waits -----------------------/
+
+/// Wait until `done`, letting host time pass up to the next change that
+/// `next_change` knows of or the `\MaxTime`. Returns false when the wait
+/// timed out, or would never end. Under the task scheduler a wait that
+/// can't finish yet gives the other tasks a turn instead.
+fn wait_until(host: &mut Host, max_time: Option<f64>, done: impl Fn(&Host) -> Result<bool, RuntimeError>,
+    next_change: impl Fn() -> Option<Duration>) -> Result<bool, RuntimeError> {
+    let start = wait_start(host);
+    if let Some(task) = host.task.as_mut() {
+        task.start = Some(start);
+    }
+    let deadline = max_time.map(|max_time| start + Duration::from_secs_f64(max_time));
+
+    loop {
+        if done(host)? {
+            return Ok(finish_wait(host, true));
+        }
+        let now = host.clock.now();
+        if deadline.is_some_and(|deadline| now >= deadline) {
+            return Ok(finish_wait(host, false));
+        }
+        let wake = match (next_change(), deadline) {
+            (Some(change), Some(deadline)) => Some(change.min(deadline)),
+            (change, deadline) => change.or(deadline),
+        };
+        match (host.task.is_some(), wake) {
+            (true, wake) => return Err(RuntimeError::Blocked { wake, span: Span::default() }),
+            (false, Some(wake)) => host.clock.sleep(wake.saturating_sub(now)),
+            (false, None) => return Ok(false),
+        }
+    }
+}
+
+/// Host time the wait started, earlier than now when the scheduler resumes it
+fn wait_start(host: &Host) -> Duration {
+    match host.task.as_ref().and_then(|task| task.start) {
+        Some(start) => start,
+        None => host.clock.now(),
+    }
+}
+
+fn finish_wait(host: &mut Host, done: bool) -> bool {
+    if let Some(task) = host.task.as_mut() {
+        task.start = None;
+        task.finished += 1;
+    }
+    done
+}
+
+/// Wait the given number of seconds, with `\InPos` after the robot reached
+/// the last target
+fn wait_time(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let mut time = num(args, 1)?;
+    if !(time >= 0.0 && time.is_finite()) {
+        return Err(RuntimeError::type_mismatch(format!("Invalid time {}", time)));
+    }
+    if args[0].is_some() {
+        let start = wait_start(host);
+        let arrival = host.trajectory.last().map_or(start, |waypoint| waypoint.time);
+        time += arrival.saturating_sub(start).as_secs_f64();
+    }
+    wait_until(host, Some(time), |_| Ok(false), || None)?;
+    Ok(None)
+}
+
+/// Lowercase names of a task list, an array of tasks records
+fn task_list(args: &[Option<Variable>], idx: usize) -> Result<Vec<String>, RuntimeError> {
+    let items = match arg(args, idx)? {
+        Variable::Array(items) => items,
+        var => return Err(RuntimeError::type_mismatch(format!("Expected tasks array, found {}", var.type_name()))),
+    };
+    items.iter().map(|item| match item {
+        Variable::Record("tasks", fields) => match fields.as_slice() {
+            [Variable::Str(name), ..] => Ok(name.to_ascii_lowercase()),
+            _ => Err(RuntimeError::type_mismatch(String::from("Task list without task names"))),
+        },
+        var => Err(RuntimeError::type_mismatch(format!("Expected tasks, found {}", var.type_name()))),
+    }).collect()
+}
+
+/// Wait until every task of the list arrived at the synchronization point.
+/// The robot isn't moved, so it's always `\InPos`.
+fn wait_sync_task(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let ident = match arg(args, 1)? {
+        Variable::SyncIdent(ident) => ident.clone(),
+        var => return Err(RuntimeError::type_mismatch(format!("Expected syncident, found {}", var.type_name()))),
+    };
+    let tasks = task_list(args, 2)?;
+    let timeout = num_arg(args, 3)?;
+    if timeout.is_some_and(|timeout| !(timeout >= 0.0 && timeout.is_finite())) {
+        return Err(RuntimeError::type_mismatch(format!("Invalid TimeOut {}", timeout.unwrap_or_default())));
+    }
+    // Without the scheduler no other task ever arrives
+    let name = match &host.task {
+        Some(task) => task.name.to_ascii_lowercase(),
+        None => return Err(RuntimeError::Blocked { wake: None, span: Span::default() }),
+    };
+    if !tasks.contains(&name) {
+        return Err(RuntimeError::type_mismatch(format!("Task {} is not in the task list of {}", name, ident)));
+    }
+
+    // The first point of the ident this task didn't pass yet, a task that
+    // comes around again before the others left opens the next one
+    let idx = match host.sync_points.iter().position(|point| point.ident == ident && !point.left.contains(&name)) {
+        Some(idx) => idx,
+        None => {
+            host.sync_points.push(SyncPoint { ident: ident.clone(), tasks: tasks.clone(), arrived: Vec::new(), left: Vec::new() });
+            host.sync_points.len() - 1
+        },
+    };
+    if !host.sync_points[idx].arrived.contains(&name) {
+        host.sync_points[idx].arrived.push(name.clone());
+    }
+
+    let all_arrived = |host: &Host| Ok(tasks.iter().all(|task| host.sync_points[idx].arrived.contains(task)));
+    let done = wait_until(host, timeout, all_arrived, || None)?;
+    let point = &mut host.sync_points[idx];
+    if done {
+        point.left.push(name);
+    } else {
+        point.arrived.retain(|task| *task != name);
+    }
+    if point.arrived.is_empty() || point.tasks.iter().all(|task| point.left.contains(task)) {
+        host.sync_points.remove(idx);
+    }
+    if !done {
+        return Err(RuntimeError::SyncTimeout { ident, span: Span::default() });
+    }
+    Ok(None)
+}
+
+// ------------------ Motion -----------------------/
+
+/// TCP speeds in mm/s of the predefined speeddata, v5 to v7000
+const SPEEDS: &[f64] = &[5.0, 10.0, 20.0, 30.0, 40.0, 50.0, 60.0, 80.0, 100.0, 150.0, 200.0, 300.0,
+    400.0, 500.0, 600.0, 800.0, 1000.0, 1500.0, 2000.0, 2500.0, 3000.0, 4000.0, 5000.0, 6000.0, 7000.0];
+
+/// Predefined zonedata with the zone of the TCP path, of the tool reorientation
+/// and the zone for reorientation in degrees, all in mm
+const ZONES: &[(&str, f64, f64, f64)] = &[
+    ("z0", 0.3, 0.3, 0.03), ("z1", 1.0, 1.0, 0.1), ("z5", 5.0, 8.0, 0.8), ("z10", 10.0, 15.0, 1.5),
+    ("z15", 15.0, 23.0, 2.3), ("z20", 20.0, 30.0, 3.0), ("z30", 30.0, 45.0, 4.5), ("z40", 40.0, 60.0, 6.0),
+    ("z50", 50.0, 75.0, 7.5), ("z60", 60.0, 90.0, 9.0), ("z80", 80.0, 120.0, 12.0), ("z100", 100.0, 150.0, 15.0),
+    ("z150", 150.0, 225.0, 23.0), ("z200", 200.0, 300.0, 30.0),
+];
+
+fn nums(record: &'static str, values: &[f64]) -> Variable {
+    Variable::Record(record, values.iter().map(|value| Variable::Num(*value)).collect())
+}
+
+/// Frame without displacement or rotation
+fn unit_pose() -> Variable {
+    Variable::Record("pose", vec![nums("pos", &[0.0, 0.0, 0.0]), nums("orient", &[1.0, 0.0, 0.0, 0.0])])
+}
+
+fn load0() -> Variable {
+    Variable::Record("loaddata", vec![Variable::Num(0.001), nums("pos", &[0.0, 0.0, 0.001]),
+        nums("orient", &[1.0, 0.0, 0.0, 0.0]), Variable::Num(0.0), Variable::Num(0.0), Variable::Num(0.0)])
+}
+
+/// Predefined data of the motion instructions: speeddata like v100, zonedata
+/// like z10 and fine, tool0, wobj0 and load0
+pub fn motion_data(name: &str) -> Option<Variable> {
+    let name = name.to_ascii_lowercase();
+    if let Some(speed) = SPEEDS.iter().find(|speed| format!("v{}", speed) == name) {
+        return Some(nums("speeddata", &[*speed, 500.0, 5000.0, 1000.0]));
+    }
+    if let Some((_, tcp, ori, zone_ori)) = ZONES.iter().find(|zone| zone.0 == name) {
+        let values = [*tcp, *ori, *ori, *zone_ori, *ori, *zone_ori];
+        let mut fields = vec![Variable::Bool(false)];
+        fields.extend(values.iter().map(|value| Variable::Num(*value)));
+        return Some(Variable::Record("zonedata", fields));
+    }
+
+    let var = match name.as_str() {
+        "vmax" => nums("speeddata", &[SPEEDS[SPEEDS.len() - 1], 500.0, 5000.0, 1000.0]),
+        "fine" => {
+            let mut fields = vec![Variable::Bool(true)];
+            fields.extend((0..6).map(|_| Variable::Num(0.0)));
+            Variable::Record("zonedata", fields)
+        },
+        "tool0" => Variable::Record("tooldata", vec![Variable::Bool(true), unit_pose(), load0()]),
+        "wobj0" => Variable::Record("wobjdata", vec![Variable::Bool(false), Variable::Bool(true),
+            Variable::Str(String::new()), unit_pose(), unit_pose()]),
+        "load0" => load0(),
+        _ => return None,
+    };
+    Some(var)
+}
+
+fn arg(args: &[Option<Variable>], idx: usize) -> Result<&Variable, RuntimeError> {
+    args[idx].as_ref().ok_or_else(|| RuntimeError::type_mismatch(format!("Missing argument {}", idx + 1)))
+}
+
+/// Components of a record argument
+fn fields(args: &[Option<Variable>], idx: usize) -> Result<&[Variable], RuntimeError> {
+    match arg(args, idx)? {
+        Variable::Record(_, fields) => Ok(fields),
+        var => Err(RuntimeError::type_mismatch(format!("Expected record, found {}", var.type_name()))),
+    }
+}
+
+/// Numbers of a record with num components, like a pos
+fn components<const N: usize>(var: &Variable) -> Result<[f64; N], RuntimeError> {
+    let mut values = [0.0; N];
+    match var {
+        Variable::Record(_, fields) if fields.len() == N => {
+            for (value, field) in values.iter_mut().zip(fields) {
+                *value = field.number()?;
+            }
+            Ok(values)
+        },
+        var => Err(RuntimeError::type_mismatch(format!("Expected {} components, found {}", N, var.type_name()))),
+    }
+}
+
+fn target(args: &[Option<Variable>], idx: usize) -> Result<Target, RuntimeError> {
+    let fields = fields(args, idx)?;
+    match &args[idx] {
+        Some(Variable::Record("jointtarget", _)) => Ok(Target::Joints(components(&fields[0])?)),
+        _ => Ok(Target::Cartesian { pos: components(&fields[0])?, rot: components(&fields[1])? }),
+    }
+}
+
+fn distance(from: [f64; 3], to: [f64; 3]) -> f64 {
+    from.iter().zip(to.iter()).map(|(from, to)| (to - from).powi(2)).sum::<f64>().sqrt()
+}
+
+/// Seconds the robot takes to move to the target at the TCP speed in mm/s
+/// and the reorientation speed in degrees/s. Without kinematics the axes of
+/// a MoveAbsJ move at the reorientation speed, and a move from a position
+/// of the other kind or from the unknown start position takes no time.
+fn move_time(from: Option<Target>, to: Target, via: Option<[f64; 3]>, tcp: f64, ori: f64) -> f64 {
+    match (from, to) {
+        (Some(Target::Cartesian { pos, rot }), Target::Cartesian { pos: to_pos, rot: to_rot }) => {
+            // The arc of a MoveC is approximated by the chords through the circle point
+            let path = match via {
+                Some(via) => distance(pos, via) + distance(via, to_pos),
+                None => distance(pos, to_pos),
+            };
+            let dot: f64 = rot.iter().zip(to_rot.iter()).map(|(q, q2)| q * q2).sum();
+            let angle = 2.0 * dot.abs().min(1.0).acos().to_degrees();
+            (path / tcp).max(angle / ori)
+        },
+        (Some(Target::Joints(axes)), Target::Joints(to_axes)) => {
+            axes.iter().zip(to_axes.iter()).map(|(axis, to)| (to - axis).abs()).fold(0.0, f64::max) / ori
+        },
+        _ => 0.0,
+    }
+}
+
+/// Log a move to the target, `args` start at the Speed parameter in slot
+/// `speed_slot`. Motion runs alongside the program, so a move starts when
+/// the robot finished the previous one or now, whichever is later.
+fn log_move(host: &mut Host, instruction: &'static str, target: Target, via: Option<[f64; 3]>, args: &[Option<Variable>], speed_slot: usize) -> Result<Option<Variable>, RuntimeError> {
+    // The target comes before Speed and WObj is the last parameter. The
+    // predefined wobj0 is passed as its value.
+    let names = std::mem::take(&mut host.arg_names);
+    let name = names.get(speed_slot - 1).cloned().flatten();
+    let frame = match args.get(6) {
+        Some(Some(wobj)) => names.get(speed_slot + 6).cloned().flatten()
+            .or_else(|| motion_data("wobj0").filter(|wobj0| wobj.equals(wobj0).unwrap_or(false)).map(|_| String::from("wobj0"))),
+        _ => Some(String::from("wobj0")),
+    };
+
+    let [v_tcp, v_ori, _, _] = components(arg(args, 0)?)?;
+    // VelSet scales the programmed speeds up to its max TCP speed, the
+    // operator's override scales what's left
+    let settings = host.motion;
+    let scale = settings.velocity / 100.0 * host.speed_override / 100.0;
+    let programmed_speed = num_arg(args, 1)?.unwrap_or(v_tcp);
+    let tcp = programmed_speed * settings.velocity / 100.0;
+    let speed = tcp.min(settings.max_speed) * host.speed_override / 100.0;
+    let v_ori = v_ori * scale;
+    if !(speed > 0.0 && v_ori > 0.0) {
+        return Err(RuntimeError::type_mismatch(format!("Invalid speed {}", speed)));
+    }
+    let zone = match (fields(args, 3)?, num_arg(args, 4)?) {
+        (_, Some(radius)) => Some(radius),
+        ([Variable::Bool(true), ..], None) => None,
+        ([_, radius, ..], None) => Some(radius.number()?),
+        (_, None) => None,
+    };
+
+    let previous = host.trajectory.last();
+    let duration = match num_arg(args, 2)? {
+        Some(time) if time > 0.0 && time.is_finite() => time / scale,
+        Some(time) => return Err(RuntimeError::type_mismatch(format!("Invalid move time {}", time))),
+        None => move_time(previous.map(|waypoint| waypoint.target), target, via, speed, v_ori),
+    };
+    let start = host.clock.now().max(previous.map_or(Duration::ZERO, |waypoint| waypoint.time));
+    let time = start + Duration::from_secs_f64(duration);
+    host.trajectory.push(Waypoint { instruction, target, name, frame, via, programmed_speed, speed, zone, time, settings });
+    Ok(None)
+}
+
+fn vel_set(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let (velocity, max_speed) = (num(args, 0)?, num(args, 1)?);
+    if !(velocity > 0.0 && max_speed > 0.0) {
+        return Err(RuntimeError::type_mismatch(format!("Invalid override {} % or max speed {} mm/s", velocity, max_speed)));
+    }
+    host.motion.velocity = velocity;
+    host.motion.max_speed = max_speed;
+    Ok(None)
+}
+
+/// Percentage of AccSet, below its minimum the minimum applies
+fn percentage(args: &[Option<Variable>], idx: usize, min: f64) -> Result<f64, RuntimeError> {
+    let value = num(args, idx)?;
+    if value.is_nan() || value > 100.0 {
+        return Err(RuntimeError::type_mismatch(format!("Value {} is not a percentage", value)));
+    }
+    Ok(value.max(min))
+}
+
+fn acc_set(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    host.motion.acceleration = percentage(args, 0, 20.0)?;
+    host.motion.ramp = percentage(args, 1, 10.0)?;
+    Ok(None)
+}
+
+/// Configuration monitoring is on unless switched `\Off`
+fn conf_j(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    host.motion.conf_j = args[1].is_none();
+    Ok(None)
+}
+
+fn conf_l(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    host.motion.conf_l = args[1].is_none();
+    Ok(None)
+}
+
+/// Keep the target of a move in slot `idx` as the position of the robot.
+/// The Tool and the WObj of the move follow the target, six and seven
+/// slots further.
+fn reach(host: &mut Host, args: &[Option<Variable>], idx: usize) -> Result<Option<Variable>, RuntimeError> {
+    let point = arg(args, idx)?.clone();
+    if let Variable::Record("jointtarget", _) = point {
+        host.position.jointtarget = Some(point);
+        return Ok(None);
+    }
+    let tool = arg(args, idx + 6)?.clone();
+    let wobj = match &args[idx + 7] {
+        Some(wobj) => wobj.clone(),
+        None => motion_data("wobj0").unwrap(),
+    };
+    host.position.robtarget = Some((point, tool, wobj));
+    Ok(None)
+}
+
+fn move_j(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let target = target(args, 0)?;
+    log_move(host, "MoveJ", target, None, &args[1..], 1)?;
+    reach(host, args, 0)
+}
+
+fn move_l(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let target = target(args, 0)?;
+    log_move(host, "MoveL", target, None, &args[1..], 1)?;
+    reach(host, args, 0)
+}
+
+fn move_c(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let via = components(&fields(args, 0)?[0])?;
+    let target = target(args, 1)?;
+    log_move(host, "MoveC", target, Some(via), &args[2..], 2)?;
+    reach(host, args, 1)
+}
+
+fn move_abs_j(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let target = target(args, 0)?;
+    log_move(host, "MoveAbsJ", target, None, &args[1..], 1)?;
+    reach(host, args, 0)
+}
+
+// The simulator has a single robot, CRobT and CJointT accept the task of
+// another motion task but read the position of this one
+
+/// Axis value of the external axes that aren't used
+const NO_AXIS: f64 = 9E9;
+
+/// Position of the robot, converted to another tool or work object than
+/// the ones of its last move when they're given
+fn c_rob_t(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let (point, tool, wobj) = match &host.position.robtarget {
+        Some(position) => position.clone(),
+        // TCP of tool0 at the base frame, until the first move
+        None => (Variable::Record("robtarget", vec![nums("pos", &[0.0; 3]), nums("orient", &[1.0, 0.0, 0.0, 0.0]),
+            nums("confdata", &[0.0; 4]), nums("extjoint", &[NO_AXIS; 6])]), motion_data("tool0").unwrap(), motion_data("wobj0").unwrap()),
+    };
+    if args[2].is_none() && args[3].is_none() {
+        return Ok(Some(point));
+    }
+    let to_tool = args[2].as_ref().unwrap_or(&tool);
+    let to_wobj = args[3].as_ref().unwrap_or(&wobj);
+    let flange = compose(compose(wobj_frame(&wobj)?, frame(&point)?), invert(tool_frame(&tool)?));
+    let (pos, rot) = compose(invert(wobj_frame(to_wobj)?), compose(flange, tool_frame(to_tool)?));
+    Ok(Some(moved_target(&point, pos, rot)))
+}
+
+fn c_joint_t(host: &mut Host, _: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let joints = host.position.jointtarget.clone()
+        .unwrap_or_else(|| Variable::Record("jointtarget", vec![nums("robjoint", &[0.0; 6]), nums("extjoint", &[NO_AXIS; 6])]));
+    Ok(Some(joints))
+}
+
+// ------------------ Frames -----------------------/
+
+// Orientations are quaternions [q1, q2, q3, q4] with q1 the scalar part,
+// like orient data. Angles are in degrees.
+
+fn quat_mult(a: [f64; 4], b: [f64; 4]) -> [f64; 4] {
+    [
+        a[0] * b[0] - a[1] * b[1] - a[2] * b[2] - a[3] * b[3],
+        a[0] * b[1] + a[1] * b[0] + a[2] * b[3] - a[3] * b[2],
+        a[0] * b[2] - a[1] * b[3] + a[2] * b[0] + a[3] * b[1],
+        a[0] * b[3] + a[1] * b[2] - a[2] * b[1] + a[3] * b[0],
+    ]
+}
+
+/// Vector rotated by an orientation. Orient data that isn't normalized
+/// rotates like its normalized form.
+fn rotate(q: [f64; 4], v: [f64; 3]) -> [f64; 3] {
+    let norm: f64 = q.iter().map(|q| q * q).sum();
+    let p = quat_mult(quat_mult(q, [0.0, v[0], v[1], v[2]]), [q[0], -q[1], -q[2], -q[3]]);
+    [p[1] / norm, p[2] / norm, p[3] / norm]
+}
+
+/// Rotation around the x, y or z axis (0, 1 or 2)
+fn axis_rotation(axis: usize, angle: f64) -> [f64; 4] {
+    let half = angle.to_radians() / 2.0;
+    let mut q = [half.cos(), 0.0, 0.0, 0.0];
+    q[axis + 1] = half.sin();
+    q
+}
+
+/// Displacement and rotation of a frame
+type Pose = ([f64; 3], [f64; 4]);
+
+/// Frame b, given relative to frame a, in the frame a is given in
+fn compose((a_pos, a_rot): Pose, (b_pos, b_rot): Pose) -> Pose {
+    let pos = rotate(a_rot, b_pos);
+    ([a_pos[0] + pos[0], a_pos[1] + pos[1], a_pos[2] + pos[2]], quat_mult(a_rot, b_rot))
+}
+
+/// Frame that undoes a frame
+fn invert((pos, rot): Pose) -> Pose {
+    let inverse = [rot[0], -rot[1], -rot[2], -rot[3]];
+    let pos = rotate(inverse, pos);
+    ([-pos[0], -pos[1], -pos[2]], inverse)
+}
+
+/// Position and orientation of a robtarget or a pose
+fn frame(var: &Variable) -> Result<Pose, RuntimeError> {
+    match var {
+        Variable::Record(_, fields) if fields.len() >= 2 => Ok((components(&fields[0])?, components(&fields[1])?)),
+        var => Err(RuntimeError::type_mismatch(format!("Expected pose, found {}", var.type_name()))),
+    }
+}
+
+/// TCP frame of tooldata in the flange frame
+fn tool_frame(tool: &Variable) -> Result<Pose, RuntimeError> {
+    frame(tool.component("tframe")?)
+}
+
+/// Object frame of wobjdata, its user frame and object frame together
+fn wobj_frame(wobj: &Variable) -> Result<Pose, RuntimeError> {
+    Ok(compose(frame(wobj.component("uframe")?)?, frame(wobj.component("oframe")?)?))
+}
+
+/// Copy of a robtarget with another position and orientation
+fn moved_target(point: &Variable, pos: [f64; 3], rot: [f64; 4]) -> Variable {
+    let mut point = point.clone();
+    if let Variable::Record(_, fields) = &mut point {
+        fields[0] = nums("pos", &pos);
+        fields[1] = nums("orient", &rot);
+    }
+    point
+}
+
+/// Orientation scaled to a unit quaternion
+fn normalized(rot: [f64; 4]) -> Result<[f64; 4], RuntimeError> {
+    let norm = rot.iter().map(|q| q * q).sum::<f64>().sqrt();
+    if !(norm > 0.0 && norm.is_finite()) {
+        return Err(RuntimeError::type_mismatch(String::from("Orientation can't be normalized")));
+    }
+    Ok(rot.map(|q| q / norm))
+}
+
+fn n_orient(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    Ok(Some(nums("orient", &normalized(components(arg(args, 0)?)?)?)))
+}
+
+/// Orientation of rotations around the z axis, then the new y axis and then
+/// the new x axis
+fn orient_zyx(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let (z, y, x) = (num(args, 0)?, num(args, 1)?, num(args, 2)?);
+    let rot = quat_mult(quat_mult(axis_rotation(2, z), axis_rotation(1, y)), axis_rotation(0, x));
+    Ok(Some(nums("orient", &rot)))
+}
+
+/// Angle around the `\X`, `\Y` or `\Z` axis of the rotations of OrientZYX
+/// that give the orientation
+fn euler_zyx(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let [w, x, y, z] = normalized(components(arg(args, 3)?)?)?;
+    let angle = match args.iter().position(Option::is_some) {
+        Some(0) => (2.0 * (y * z + w * x)).atan2(1.0 - 2.0 * (x * x + y * y)),
+        Some(1) => (2.0 * (w * y - x * z)).clamp(-1.0, 1.0).asin(),
+        Some(2) => (2.0 * (x * y + w * z)).atan2(1.0 - 2.0 * (y * y + z * z)),
+        _ => return Err(RuntimeError::type_mismatch(String::from("EulerZYX needs \\X, \\Y or \\Z"))),
+    };
+    num_result("EulerZYX", angle.to_degrees())
+}
+
+fn pose_var((pos, rot): Pose) -> Variable {
+    Variable::Record("pose", vec![nums("pos", &pos), nums("orient", &rot)])
+}
+
+/// Pose 2, given relative to pose 1, in the frame pose 1 is given in
+fn pose_mult(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    Ok(Some(pose_var(compose(frame(arg(args, 0)?)?, frame(arg(args, 1)?)?))))
+}
+
+fn pose_inv(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    Ok(Some(pose_var(invert(frame(arg(args, 0)?)?))))
+}
+
+/// Position given in the frame of a pose, in the frame the pose is given in
+fn pose_vect(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let (pos, _) = compose(frame(arg(args, 0)?)?, (components(arg(args, 1)?)?, [1.0, 0.0, 0.0, 0.0]));
+    Ok(Some(nums("pos", &pos)))
+}
+
+/// Robtarget displaced along the axes of its work object
+fn offs(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let (pos, rot) = frame(arg(args, 0)?)?;
+    let pos = [pos[0] + num(args, 1)?, pos[1] + num(args, 2)?, pos[2] + num(args, 3)?];
+    Ok(Some(moved_target(arg(args, 0)?, pos, rot)))
+}
+
+/// Robtarget displaced along the axes of the tool, and rotated around the
+/// x axis of the tool, then the new y axis and then the new z axis
+fn rel_tool(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let (pos, mut rot) = frame(arg(args, 0)?)?;
+    let offset = rotate(rot, [num(args, 1)?, num(args, 2)?, num(args, 3)?]);
+    let pos = [pos[0] + offset[0], pos[1] + offset[1], pos[2] + offset[2]];
+    for axis in 0..3 {
+        if let Some(angle) = num_arg(args, 4 + axis)? {
+            rot = quat_mult(rot, axis_rotation(axis, angle));
+        }
+    }
+    Ok(Some(moved_target(arg(args, 0)?, pos, rot)))
+}
+
+// ------------------ Strings -----------------------/
+
+// Characters are counted from 1, a position one past the end of the string
+// is allowed where RAPID returns it for "not found"
+
+/// Index of the character at the 1-based position `value`, at most `max`
+fn position(value: f64, max: usize) -> Result<usize, RuntimeError> {
+    if value != value.trunc() || value < 1.0 || value > max as f64 {
+        return Err(RuntimeError::type_mismatch(format!("Character position {} out of range", value)));
+    }
+    Ok(value as usize - 1)
+}
+
+// Character sets of StrFind, StrMemb and StrMap, in the order that maps
+// between the cases, ß has no upper case letter
+const STR_DIGIT: &str = "0123456789";
+const STR_UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZÀÁÂÃÄÅÆÇÐÈÉÊËÌÍÎÏÑÒÓÔÕÖØÙÚÛÜÝÞ";
+const STR_LOWER: &str = "abcdefghijklmnopqrstuvwxyzàáâãäåæçðèéêëìíîïñòóôõöøùúûüýþß";
+const STR_WHITE: &str = " ";
+
+/// Predefined character sets of the string functions
+pub fn string_data(name: &str) -> Option<Variable> {
+    let set = match name.to_ascii_uppercase().as_str() {
+        "STR_DIGIT" => STR_DIGIT,
+        "STR_UPPER" => STR_UPPER,
+        "STR_LOWER" => STR_LOWER,
+        "STR_WHITE" => STR_WHITE,
+        _ => return None,
+    };
+    Some(Variable::Str(String::from(set)))
+}
+
+fn chars(args: &[Option<Variable>], idx: usize) -> Result<Vec<char>, RuntimeError> {
+    Ok(str_arg(args, idx)?.chars().collect())
+}
+
+fn str_len(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    Ok(Some(Variable::Num(chars(args, 0)?.len() as f64)))
+}
+
+fn str_part(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let text = chars(args, 0)?;
+    let start = position(num(args, 1)?, text.len() + 1)?;
+    let len = num(args, 2)?;
+    if len != len.trunc() || len < 0.0 || start + len as usize > text.len() {
+        return Err(RuntimeError::type_mismatch(format!("StrPart length {} out of range", len)));
+    }
+    Ok(Some(Variable::Str(text[start..start + len as usize].iter().collect())))
+}
+
+fn str_find(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let text = chars(args, 0)?;
+    let start = position(num(args, 1)?, text.len() + 1)?;
+    let set = str_arg(args, 2)?;
+    let in_set = args[3].is_none();
+    let found = text[start..].iter().position(|c| set.contains(*c) == in_set);
+    let found = found.map_or(text.len(), |idx| start + idx);
+    Ok(Some(Variable::Num(found as f64 + 1.0)))
+}
+
+fn str_match(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let text = chars(args, 0)?;
+    let start = position(num(args, 1)?, text.len() + 1)?;
+    let pattern = chars(args, 2)?;
+    let found = (start..text.len() + 1)
+        .find(|idx| text[*idx..].starts_with(&pattern))
+        .filter(|_| !pattern.is_empty())
+        .unwrap_or(text.len());
+    Ok(Some(Variable::Num(found as f64 + 1.0)))
+}
+
+fn str_memb(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let text = chars(args, 0)?;
+    let idx = position(num(args, 1)?, text.len())?;
+    Ok(Some(Variable::Bool(str_arg(args, 2)?.contains(text[idx]))))
+}
+
+fn str_map(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let from = chars(args, 1)?;
+    let to = chars(args, 2)?;
+    let mapped = chars(args, 0)?.into_iter().map(|c| {
+        match from.iter().position(|from| *from == c) {
+            Some(idx) => to.get(idx).copied().unwrap_or(c),
+            None => c,
+        }
+    });
+    Ok(Some(Variable::Str(mapped.collect())))
+}
+
+/// Whether Str1 sorts before Str2 when the characters are ordered as in
+/// Order, characters missing from Order sort after it by character code
+fn str_order(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let order = chars(args, 2)?;
+    let rank = |c: char| order.iter().position(|o| *o == c).unwrap_or(order.len() + c as usize);
+    let lhs: Vec<usize> = chars(args, 0)?.into_iter().map(rank).collect();
+    let rhs: Vec<usize> = chars(args, 1)?.into_iter().map(rank).collect();
+    Ok(Some(Variable::Bool(lhs < rhs)))
+}
+
+fn num_to_str(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let value = num(args, 0)?;
+    let dec = decimals(args, 1)?;
+    if args[2].is_none() {
+        return Ok(Some(Variable::Str(format!("{:.*}", dec as usize, round_to(value, dec)))));
+    }
+
+    // Exponent with a sign and at least two digits, like 3.852E-01. The
+    // mantissa is rounded like Round does, which can carry it to 10.
+    let mut exponent = if value == 0.0 { 0 } else { value.abs().log10().floor() as i32 };
+    let mut mantissa = round_to(value / 10f64.powi(exponent), dec);
+    if mantissa.abs() >= 10.0 {
+        exponent += 1;
+        mantissa = round_to(value / 10f64.powi(exponent), dec);
+    }
+    let sign = if exponent < 0 { '-' } else { '+' };
+    Ok(Some(Variable::Str(format!("{:.*}E{}{:02}", dec as usize, mantissa, sign, exponent.abs()))))
+}
+
+fn val_to_str(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let value = args[0].as_ref().map(Variable::to_string).unwrap_or_default();
+    Ok(Some(Variable::Str(value)))
+}
+
+/// Parse the text into data of the current type of Val, returns whether it succeeded
+fn str_to_val(_: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let text = str_arg(args, 0)?;
+    let parsed = args[1].as_ref().and_then(|template| parse_value(text.trim(), template));
+    let ok = parsed.is_some();
+    if let Some(value) = parsed {
+        args[1] = Some(value);
+    }
+    Ok(Some(Variable::Bool(ok)))
+}
+
+fn parse_value(text: &str, template: &Variable) -> Option<Variable> {
+    let var = match template {
+        Variable::Num(_) => Variable::Num(text.parse().ok().filter(|value: &f64| value.is_finite())?),
+        Variable::Dnum(_) => Variable::Dnum(text.parse().ok().filter(|value: &f64| value.is_finite())?),
+        Variable::Byte(_) => Variable::Byte(variable::to_byte(text.parse().ok()?).ok()?),
+        Variable::Bool(_) if text.eq_ignore_ascii_case("TRUE") => Variable::Bool(true),
+        Variable::Bool(_) if text.eq_ignore_ascii_case("FALSE") => Variable::Bool(false),
+        Variable::Str(_) if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') => {
+            Variable::Str(String::from(&text[1..text.len() - 1]))
+        },
+        Variable::Record(name, fields) => {
+            let inner = text.strip_prefix('[')?.strip_suffix(']')?;
+            let parts = split_fields(inner);
+            if parts.len() != fields.len() {
+                return None;
+            }
+            let values = parts.iter().zip(fields).map(|(part, field)| parse_value(part.trim(), field));
+            Variable::Record(name, values.collect::<Option<_>>()?)
+        },
+        _ => return None,
+    };
+    Some(var)
+}
+
+/// Split the fields of an aggregate at the commas outside nested brackets and strings
+fn split_fields(text: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut start = 0;
+    for (idx, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '[' if !quoted => depth += 1,
+            ']' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                fields.push(&text[start..idx]);
+                start = idx + 1;
+            },
+            _ => (),
+        }
+    }
+    fields.push(&text[start..]);
+    fields
+}
+
+// ------------------ Files -----------------------/
+
+/// What ReadStr returns at the end of the file
+const EOF: &str = "EOF";
+/// What ReadNum returns at the end of the file
+const EOF_NUM: f64 = 9.998E36;
+
+/// Predefined data of the file instructions
+pub fn file_data(name: &str) -> Option<Variable> {
+    match name.to_ascii_uppercase().as_str() {
+        "EOF" => Some(Variable::Str(String::from(EOF))),
+        "EOF_NUM" => Some(Variable::Num(EOF_NUM)),
+        _ => None,
+    }
+}
+
+fn file_access(message: &str) -> RuntimeError {
+    RuntimeError::FileAccess { message: String::from(message), span: Span::default() }
+}
+
+/// Name of a file given as a directory or device and an optional `\File`
+fn file_name(args: &[Option<Variable>], object: usize, file: usize) -> Result<String, RuntimeError> {
+    let name = str_arg(args, object)?;
+    match args[file] {
+        Some(_) => Ok(format!("{}/{}", name, str_arg(args, file)?)),
+        None => Ok(name),
+    }
+}
+
+/// Index in the files of the host of the file an iodev argument has open
+fn iodev(args: &[Option<Variable>], idx: usize) -> Result<Option<usize>, RuntimeError> {
+    match &args[idx] {
+        Some(Variable::IoDev(file)) => Ok(*file),
+        Some(var) => Err(RuntimeError::type_mismatch(format!("Expected iodev, found {}", var.type_name()))),
+        None => Err(RuntimeError::type_mismatch(format!("Missing argument {}", idx + 1))),
+    }
+}
+
+fn open_file<'h>(host: &'h mut Host, args: &[Option<Variable>], idx: usize) -> Result<&'h mut fs::File, RuntimeError> {
+    iodev(args, idx)?
+        .and_then(move |file| host.files.get_mut(file)?.as_mut())
+        .ok_or_else(|| file_access("I/O device is not open"))
+}
+
+/// Open a file in the file root of the host. Without a switch a file is
+/// opened for writing and its old content is deleted, `\Bin` opens it for
+/// reading and writing.
+fn open(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let open_error = |message: String| RuntimeError::FileOpen { message, span: Span::default() };
+    if iodev(args, 2)?.is_some() {
+        return Err(open_error(String::from("on an I/O device that is open already")));
+    }
+    let name = file_name(args, 0, 1)?;
+    let root = host.file_root.as_ref().ok_or_else(|| open_error(format!("{}: the host has no file root", name)))?;
+    let path = host::sandbox_path(root, &name).map_err(open_error)?;
+
+    let mut options = fs::OpenOptions::new();
+    match (args[3].is_some(), args[5].is_some(), args[6].is_some()) {
+        (true, _, _) => options.read(true),
+        (_, true, _) => options.append(true).create(true),
+        (_, _, true) => options.read(true).write(true).create(true),
+        _ => options.write(true).create(true).truncate(true),
+    };
+    let file = options.open(&path).map_err(|err| open_error(format!("{}: {}", name, err)))?;
+
+    let idx = match host.files.iter().position(Option::is_none) {
+        Some(idx) => idx,
+        None => {
+            host.files.push(None);
+            host.files.len() - 1
+        },
+    };
+    host.files[idx] = Some(file);
+    args[2] = Some(Variable::IoDev(Some(idx)));
+    Ok(None)
+}
+
+fn close(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    // Closing a device that isn't open does nothing
+    if let Some(file) = iodev(args, 0)?.and_then(|file| host.files.get_mut(file)) {
+        *file = None;
+    }
+    args[0] = Some(Variable::IoDev(None));
+    Ok(None)
+}
+
+fn rewind(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    open_file(host, args, 0)?.rewind().map_err(|err| file_access(&err.to_string()))?;
+    Ok(None)
+}
+
+/// Write a line of text, the optional value is appended like TPWrite does
+fn write(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let mut text = str_arg(args, 1)?;
+    if let Some(value) = args[2..6].iter().flatten().next() {
+        text.push_str(&value.to_string());
+    }
+    if args[6].is_none() {
+        text.push('\n');
+    }
+    open_file(host, args, 0)?.write_all(text.as_bytes()).map_err(|err| file_access(&err.to_string()))?;
+    Ok(None)
+}
+
+/// Characters up to the next delimiter, the delimiter itself is read too.
+/// `None` at the end of the file.
+fn read_until(file: &mut fs::File, delimiters: &str, max: usize) -> Result<Option<String>, RuntimeError> {
+    // Byte by byte, a buffer would read ahead of the next read
+    let mut bytes = Vec::new();
+    let mut byte = [0];
+    let mut read = 0;
+    while bytes.len() < max && file.read(&mut byte).map_err(|err| file_access(&err.to_string()))? > 0 {
+        read += 1;
+        if delimiters.as_bytes().contains(&byte[0]) {
+            break;
+        }
+        bytes.push(byte[0]);
+    }
+    if read == 0 {
+        return Ok(None);
+    }
+    Ok(Some(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+/// Delimiters of a read, a line feed unless `\Delim` gives others
+fn delimiters(args: &[Option<Variable>], idx: usize) -> Result<String, RuntimeError> {
+    match args[idx] {
+        Some(_) => str_arg(args, idx),
+        None => Ok(String::from("\n")),
+    }
+}
+
+/// Read a line of at most 80 characters, or "EOF" at the end of the file
+fn read_str(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let delimiters = delimiters(args, 1)?;
+    let remove_cr = args[2].is_some();
+    let mut text = match read_until(open_file(host, args, 0)?, &delimiters, variable::MAX_STRING)? {
+        Some(text) => text,
+        None => String::from(EOF),
+    };
+    if remove_cr && text.ends_with('\r') {
+        text.pop();
+    }
+    Ok(Some(Variable::Str(text)))
+}
+
+/// Read a number up to the end of the line, or EOF_NUM at the end of the file
+fn read_num(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let delimiters = delimiters(args, 1)?;
+    let text = match read_until(open_file(host, args, 0)?, &delimiters, usize::MAX)? {
+        Some(text) => text,
+        None => return Ok(Some(Variable::Num(EOF_NUM))),
+    };
+    match text.trim().parse() {
+        Ok(value) => Ok(Some(Variable::Num(value))),
+        Err(_) => Err(RuntimeError::ReceiveData { message: format!("ReadNum read \"{}\", not a number", text.trim()), span: Span::default() }),
+    }
+}
+
+// ------------------ Sockets -----------------------/
+
+// Sockets connect to real TCP services, to integration test the messages a
+// program exchanges with a PLC or a vision system. Only addresses the host
+// allows can be reached, and only as a client.
+
+/// Values of SocketGetStatus
+const SOCKET_STATUS: &[(&str, f64)] = &[
+    ("SOCKET_CREATED", 1.0),
+    ("SOCKET_CONNECTED", 2.0),
+    ("SOCKET_BOUND", 3.0),
+    ("SOCKET_LISTENING", 4.0),
+    ("SOCKET_CLOSED", 5.0),
+];
+
+/// Predefined data of the socket instructions
+pub fn socket_data(name: &str) -> Option<Variable> {
+    SOCKET_STATUS.iter()
+        .find(|status| status.0.eq_ignore_ascii_case(name))
+        .map(|status| Variable::Num(status.1))
+}
+
+fn socket_closed(message: &str) -> RuntimeError {
+    RuntimeError::SocketClosed { message: String::from(message), span: Span::default() }
+}
+
+fn socket_error(err: io::Error) -> RuntimeError {
+    let message = err.to_string();
+    match err.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => RuntimeError::SocketTimeout { message, span: Span::default() },
+        io::ErrorKind::ConnectionRefused | io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable
+        | io::ErrorKind::AddrNotAvailable => RuntimeError::SocketUnreachable { message, span: Span::default() },
+        _ => RuntimeError::SocketClosed { message, span: Span::default() },
+    }
+}
+
+/// Index in the sockets of the host of a socketdev argument
+fn socketdev(args: &[Option<Variable>], idx: usize) -> Result<Option<usize>, RuntimeError> {
+    match &args[idx] {
+        Some(Variable::SocketDev(socket)) => Ok(*socket),
+        Some(var) => Err(RuntimeError::type_mismatch(format!("Expected socketdev, found {}", var.type_name()))),
+        None => Err(RuntimeError::type_mismatch(format!("Missing argument {}", idx + 1))),
+    }
+}
+
+fn connected<'h>(host: &'h mut Host, args: &[Option<Variable>], idx: usize) -> Result<&'h mut TcpStream, RuntimeError> {
+    match socketdev(args, idx)?.and_then(move |socket| host.sockets.get_mut(socket)?.as_mut()) {
+        Some(Socket::Connected(stream)) => Ok(stream),
+        _ => Err(socket_closed("the socket is not connected")),
+    }
+}
+
+/// Time-out of `\Time` in seconds, 60 unless given. The WAIT_MAX of the
+/// controller, 8388608, or more waits forever.
+fn socket_time(args: &[Option<Variable>], idx: usize) -> Result<Option<Duration>, RuntimeError> {
+    let time = num_arg(args, idx)?.unwrap_or(60.0);
+    if time.is_nan() || time <= 0.0 {
+        return Err(RuntimeError::type_mismatch(format!("Invalid time {}", time)));
+    }
+    Ok((time.is_finite() && time < 8388608.0).then(|| Duration::from_secs_f64(time)))
+}
+
+/// Create a TCP socket, it connects with SocketConnect
+fn socket_create(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    if socketdev(args, 0)?.is_some() {
+        return Err(RuntimeError::type_mismatch(String::from("The socket is created already")));
+    }
+    if args[1].is_some() {
+        return Err(RuntimeError::Unsupported { message: String::from("UDP sockets are not simulated"), span: Span::default() });
+    }
+    let idx = match host.sockets.iter().position(Option::is_none) {
+        Some(idx) => idx,
+        None => {
+            host.sockets.push(None);
+            host.sockets.len() - 1
+        },
+    };
+    host.sockets[idx] = Some(Socket::Created);
+    args[0] = Some(Variable::SocketDev(Some(idx)));
+    Ok(None)
+}
+
+/// Connect a created socket to a port of an address the host allows
+fn socket_connect(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let address = str_arg(args, 1)?;
+    let port = num(args, 2)?;
+    if port != port.trunc() || !(0.0..=65535.0).contains(&port) {
+        return Err(RuntimeError::type_mismatch(format!("Invalid port {}", port)));
+    }
+    let port = port as u16;
+    let time = socket_time(args, 3)?;
+    let socket = match socketdev(args, 0)? {
+        Some(socket) if matches!(host.sockets.get(socket), Some(Some(Socket::Created))) => socket,
+        Some(socket) if host.sockets.get(socket).is_some_and(Option::is_some) => {
+            return Err(RuntimeError::type_mismatch(String::from("The socket is connected already")));
+        },
+        _ => return Err(socket_closed("the socket is not created")),
+    };
+    let unreachable = |message: String| RuntimeError::SocketUnreachable { message, span: Span::default() };
+    if !host.allows_socket(&address, port) {
+        return Err(unreachable(format!("{}:{}, the host doesn't allow it", address, port)));
+    }
+
+    let mut last_error = unreachable(format!("{}:{}, the address is unknown", address, port));
+    for addr in (address.as_str(), port).to_socket_addrs().map_err(|err| unreachable(format!("{}:{}, {}", address, port, err)))? {
+        let stream = match time {
+            Some(time) => TcpStream::connect_timeout(&addr, time),
+            None => TcpStream::connect(addr),
+        };
+        match stream {
+            Ok(stream) => {
+                host.sockets[socket] = Some(Socket::Connected(stream));
+                return Ok(None);
+            },
+            Err(err) => last_error = socket_error(err),
+        }
+    }
+    Err(last_error)
+}
+
+/// Bytes of a string, or of an array of bytes or numbers
+fn send_data(args: &[Option<Variable>], str_idx: usize) -> Result<Vec<u8>, RuntimeError> {
+    match (&args[str_idx], &args[str_idx + 1]) {
+        (Some(_), _) => Ok(str_arg(args, str_idx)?.into_bytes()),
+        (None, Some(Variable::Array(items))) => items.iter()
+            .map(|item| match item {
+                Variable::Byte(value) => Ok(*value),
+                item => variable::to_byte(item.number()?),
+            })
+            .collect(),
+        (None, Some(var)) => Err(RuntimeError::type_mismatch(format!("Expected byte array, found {}", var.type_name()))),
+        (None, None) => Err(RuntimeError::type_mismatch(String::from("SocketSend needs \\Str or \\Data"))),
+    }
+}
+
+/// Send a string or the bytes of an array, `\NoOfBytes` of them
+fn socket_send(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let mut data = send_data(args, 1)?;
+    if let Some(count) = num_arg(args, 3)? {
+        if count != count.trunc() || count < 1.0 || count as usize > data.len() {
+            return Err(RuntimeError::type_mismatch(format!("Invalid number of bytes {}", count)));
+        }
+        data.truncate(count as usize);
+    }
+    connected(host, args, 0)?.write_all(&data).map_err(socket_error)?;
+    Ok(None)
+}
+
+/// Receive into a string, up to 80 characters, or into the elements of an
+/// array. Waits for exactly `\ReadNoOfBytes` bytes if given, otherwise for
+/// what the other side sent. `\NoRecBytes` gets the number received.
+fn socket_receive(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let capacity = match (&args[1], &args[2]) {
+        (Some(_), _) => variable::MAX_STRING,
+        (None, Some(Variable::Array(items))) => items.len(),
+        (None, Some(var)) => return Err(RuntimeError::type_mismatch(format!("Expected byte array, found {}", var.type_name()))),
+        (None, None) => return Err(RuntimeError::type_mismatch(String::from("SocketReceive needs \\Str or \\Data"))),
+    };
+    let wanted = match num_arg(args, 3)? {
+        Some(count) if count != count.trunc() || count < 1.0 || count as usize > capacity => {
+            return Err(RuntimeError::type_mismatch(format!("Invalid number of bytes {}", count)));
+        },
+        Some(count) => Some(count as usize),
+        None => None,
+    };
+    let time = socket_time(args, 5)?;
+    let stream = connected(host, args, 0)?;
+    stream.set_read_timeout(time).map_err(socket_error)?;
+
+    let mut buffer = vec![0; wanted.unwrap_or(capacity)];
+    let received = match wanted {
+        Some(count) => stream.read_exact(&mut buffer).map(|_| count),
+        None => stream.read(&mut buffer),
+    };
+    let received = match received {
+        Ok(0) => return Err(socket_closed("the other side closed the connection")),
+        Ok(received) => received,
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Err(socket_closed("the other side closed the connection")),
+        Err(err) => return Err(socket_error(err)),
+    };
+    buffer.truncate(received);
+
+    let into_str = args[1].is_some();
+    match &mut args[2] {
+        Some(Variable::Array(items)) if !into_str => {
+            for (item, byte) in items.iter_mut().zip(buffer.iter()) {
+                item.set(Variable::Byte(*byte))?;
+            }
+        },
+        _ => args[1] = Some(Variable::Str(String::from_utf8_lossy(&buffer).into_owned())),
+    }
+    if args[4].is_some() {
+        args[4] = Some(Variable::Num(received as f64));
+    }
+    Ok(None)
+}
+
+fn socket_close(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    // Closing a socket that isn't created does nothing
+    if let Some(socket) = socketdev(args, 0)?.and_then(|socket| host.sockets.get_mut(socket)) {
+        *socket = None;
+    }
+    args[0] = Some(Variable::SocketDev(None));
+    Ok(None)
+}
+
+fn socket_get_status(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let status = match socketdev(args, 0)?.and_then(|socket| host.sockets.get(socket)?.as_ref()) {
+        Some(Socket::Created) => "SOCKET_CREATED",
+        Some(Socket::Connected(_)) => "SOCKET_CONNECTED",
+        None => "SOCKET_CLOSED",
+    };
+    Ok(socket_data(status))
+}
+
+// ------------------ Modules -----------------------/
+
+// Modules are read from the module search path of the host and linked to
+// the program when the instruction returns. Routines of a loaded module are
+// called late bound, like `%"rPart" + sNo%;`.
+
+/// Index in the load sessions of the host of a loadsession argument
+fn load_session(args: &[Option<Variable>], idx: usize) -> Result<Option<usize>, RuntimeError> {
+    match &args[idx] {
+        Some(Variable::LoadSession(session)) => Ok(*session),
+        Some(var) => Err(RuntimeError::type_mismatch(format!("Expected loadsession, found {}", var.type_name()))),
+        None => Err(RuntimeError::type_mismatch(format!("Missing argument {}", idx + 1))),
+    }
+}
+
+fn load(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let module = loader::read_module(host, &file_name(args, 1, 2)?)?;
+    host.module_changes.push(ModuleChange::Load(Box::new(module)));
+    Ok(None)
+}
+
+/// Read a module, WaitLoad links it to the program
+fn start_load(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    if load_session(args, 3)?.is_some_and(|session| host.load_sessions.get(session).is_some_and(Option::is_some)) {
+        return Err(loader::load_error(LoadError::SessionInUse, String::from("Load session is loading a module already")));
+    }
+    let module = loader::read_module(host, &file_name(args, 1, 2)?)?;
+    let idx = match host.load_sessions.iter().position(Option::is_none) {
+        Some(idx) => idx,
+        None => {
+            host.load_sessions.push(None);
+            host.load_sessions.len() - 1
+        },
+    };
+    host.load_sessions[idx] = Some(module);
+    args[3] = Some(Variable::LoadSession(Some(idx)));
+    Ok(None)
+}
+
+/// Link the module of a load session, after unloading the module of
+/// `\UnloadPath` when one is given
+fn wait_load(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let module = load_session(args, 2)?.and_then(|session| host.load_sessions.get_mut(session)?.take());
+    let module = module.ok_or_else(|| loader::load_error(LoadError::SessionUnused, String::from("Load session isn't loading a module")))?;
+    args[2] = Some(Variable::LoadSession(None));
+    if args[0].is_some() {
+        host.module_changes.push(ModuleChange::Unload(file_name(args, 0, 1)?));
+    }
+    host.module_changes.push(ModuleChange::Load(Box::new(module)));
+    Ok(None)
+}
+
+fn cancel_load(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    if let Some(session) = load_session(args, 0)?.and_then(|session| host.load_sessions.get_mut(session)) {
+        *session = None;
+    }
+    args[0] = Some(Variable::LoadSession(None));
+    Ok(None)
+}
+
+fn unload(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    host.module_changes.push(ModuleChange::Unload(file_name(args, 2, 3)?));
+    Ok(None)
+}
+
+// ------------------ Event log -----------------------/
+
+/// Event numbers of the messages ErrWrite logs, by severity
+const ERR_WRITE_ERROR: i32 = 80001;
+const ERR_WRITE_WARNING: i32 = 80002;
+const ERR_WRITE_INFORMATION: i32 = 80003;
+
+/// Severity of the `\W` and `\I` switches at `idx` and the one after it
+fn severity(args: &[Option<Variable>], idx: usize) -> Severity {
+    match (&args[idx], &args[idx + 1]) {
+        (Some(_), _) => Severity::Warning,
+        (_, Some(_)) => Severity::Information,
+        _ => Severity::Error,
+    }
+}
+
+fn log_event(host: &mut Host, number: i32, severity: Severity, title: String, reason: Vec<String>) {
+    let time = host.clock.now();
+    host.event_log.push(Event { number, severity, time, title, reason });
+}
+
+/// Log a message of the program, the reason has up to four lines
+fn err_write(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let severity = severity(args, 0);
+    let number = match severity {
+        Severity::Error => ERR_WRITE_ERROR,
+        Severity::Warning => ERR_WRITE_WARNING,
+        Severity::Information => ERR_WRITE_INFORMATION,
+    };
+    let mut reason = vec![str_arg(args, 3)?];
+    for idx in 4..7 {
+        if args[idx].is_some() {
+            reason.push(str_arg(args, idx)?);
+        }
+    }
+    log_event(host, number, severity, str_arg(args, 2)?, reason);
+    Ok(None)
+}
+
+/// Log an event of the numbers the controller keeps for programs, with
+/// its arguments as the reason
+fn err_log(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let id = num(args, 0)?;
+    if id.fract() != 0.0 || !(4800.0..=4814.0).contains(&id) && !(5000.0..=9999.0).contains(&id) {
+        return Err(RuntimeError::type_mismatch(format!("Invalid ErrorID {}", host::format_num(id))));
+    }
+    let reason = args[3..8].iter().flatten()
+        .map(|value| match value {
+            Variable::Str(text) => text.clone(),
+            value => value.to_string(),
+        })
+        .collect();
+    log_event(host, id as i32, severity(args, 1), format!("Event {}", id), reason);
+    Ok(None)
+}
+
+// ------------------ Operator dialogs -----------------------/
+
+/// Error for a dialog the operator didn't answer
+fn unanswered(max_time: Option<f64>) -> RuntimeError {
+    match max_time {
+        Some(max_time) => RuntimeError::OperatorTimeout { max_time, span: Span::default() },
+        None => RuntimeError::NoOperator { span: Span::default() },
+    }
+}
+
+fn tp_read_num(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let prompt = str_arg(args, 1)?;
+    let max_time = num_arg(args, 2)?;
+    let input = host.input.as_mut().ok_or_else(|| unanswered(None))?;
+    let answer = input.read_num(&prompt).ok_or_else(|| unanswered(max_time))?;
+    args[0] = Some(Variable::Num(answer));
+    Ok(None)
+}
+
+fn tp_read_fk(host: &mut Host, args: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    let prompt = str_arg(args, 1)?;
+    let keys = (2..7).map(|idx| str_arg(args, idx)).collect::<Result<Vec<_>, _>>()?;
+    let max_time = num_arg(args, 7)?;
+    let input = host.input.as_mut().ok_or_else(|| unanswered(None))?;
+    let key = input.read_fk(&prompt, &keys).ok_or_else(|| unanswered(max_time))?;
+
+    // Keys with an empty label aren't shown and can't be pressed
+    if !(1..=keys.len()).contains(&key) || keys[key - 1].is_empty() {
+        return Err(RuntimeError::type_mismatch(format!("Function key {} is not available", key)));
+    }
+    args[0] = Some(Variable::Num(key as f64));
+    Ok(None)
+}
+
+/// Stop the program, the run ends without an error
+fn exit(_: &mut Host, _: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+    Err(RuntimeError::Exit { span: Span::default() })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler;
+    use crate::interpreter::{self, InterpreterOptions};
+    use crate::json::ToJson;
+    use crate::lexer;
+    use crate::parser;
+    use crate::resolver::{self, ResolveOptions};
+    use crate::variable::SignalKind;
+    use crate::vm;
+
+    /// Value of `expr` assigned to data of `data_type`, computed by both the
+    /// interpreter and the VM
+    fn eval(data_type: &str, expr: &str) -> Result<Variable, RuntimeError> {
+        let source = format!("
+MODULE Builtins
+    VAR {} result;
+    PROC main()
+        result := {};
+    ENDPROC
+ENDMODULE", data_type, expr);
+        let mut program = parser::parse_tokens(lexer::parse(&source)).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let bytecode = compiler::compile(&program).unwrap();
+        let options = InterpreterOptions::default();
+
+        let mut globals = Vec::new();
+        let mut compiled = Vec::new();
+        let interpreted = interpreter::run(&mut program, "main", &options, &mut Host::new(&mut Vec::new())).into_result()
+            .map(|_| globals = program.variables.clone());
+        let executed = vm::run(&mut program, &bytecode, "main", &options, &mut Host::new(&mut Vec::new()))
+            .map(|_| compiled = program.variables.clone());
+        assert_eq!(interpreted, executed);
+        assert_eq!(format!("{:?}", globals), format!("{:?}", compiled));
+        interpreted.map(|_| globals.remove(0))
+    }
+
+    fn eval_num(expr: &str) -> f64 {
+        match eval("num", expr) {
+            Ok(Variable::Num(value)) => value,
+            result => panic!("{} evaluated to {:?}", expr, result),
+        }
+    }
+
+    /// Run main of a resolved program on the interpreter and on the VM, each
+    /// from the data it starts with and on a manual clock from zero, and what
+    /// `outcome` makes of the program and the host after each run, which has
+    /// to be the same. The host gets the I/O board, `setup` prepares a run.
+    fn run_both<T: PartialEq + std::fmt::Debug>(program: &mut parser::Program, io: Option<&IoBoard>, setup: impl Fn(&mut Host),
+        outcome: impl Fn(&parser::Program, &Host) -> T) -> T {
+        let bytecode = compiler::compile(program).unwrap();
+        let initial = program.variables.clone();
+        let mut outcomes = Vec::new();
+        for compiled in [false, true] {
+            program.variables = initial.clone();
+            let clock = host::ManualClock::new(Duration::ZERO);
+            let mut output = Vec::new();
+            let mut host = Host::new(&mut output).with_clock(&clock);
+            host.io = io;
+            setup(&mut host);
+            if compiled {
+                vm::run(program, &bytecode, "main", &InterpreterOptions::default(), &mut host).unwrap();
+            } else {
+                interpreter::run(program, "main", &InterpreterOptions::default(), &mut host).into_result().unwrap();
+            }
+            outcomes.push(outcome(program, &host));
+        }
+        let executed = outcomes.pop().unwrap();
+        let interpreted = outcomes.pop().unwrap();
+        assert_eq!(interpreted, executed);
+        interpreted
+    }
+
+    #[test]
+    fn math_functions() {
+        assert_eq!(eval_num("Abs(-2.5)"), 2.5);
+        assert_eq!(eval_num("Sqrt(16) + Pow(2, 10) + Exp(0)"), 1029.0);
+        assert_eq!(eval_num("Min(3, -1) * Max(3, -1)"), -3.0);
+        assert!((eval_num("Sin(30)") - 0.5).abs() < 1e-12);
+        assert!((eval_num("Cos(60) + Tan(45)") - 1.5).abs() < 1e-12);
+        assert!((eval_num("ASin(0.5) + ACos(0.5) + ATan(1)") - 135.0).abs() < 1e-12);
+        assert!((eval_num("ATan2(1, -1)") - 135.0).abs() < 1e-12);
+        assert_eq!(eval_num("Round(2.5) + Round(-2.5)"), 0.0);
+        assert_eq!(eval_num("Round(1.2345 \\Dec:=2)"), 1.23);
+        assert_eq!(eval_num("Trunc(-1.789) + Trunc(1.789 \\Dec:=1)"), 0.7);
+
+        let err = eval("num", "Sqrt(-1)").unwrap_err();
+        assert_eq!(err.to_string(), "5:19: ERR_ARGVALERR: Argument out of range for Sqrt");
+        assert_eq!(eval("num", "ACos(2)").unwrap_err().name(), "ERR_ARGVALERR");
+        assert_eq!(eval("num", "Round(1 \\Dec:=0.5)").unwrap_err().name(), "ERR_ARGVALERR");
+    }
+
+    #[test]
+    fn math_constants() {
+        assert!((eval_num("Cos(180 * pi / PI)") + 1.0).abs() < 1e-12);
+        assert!((eval_num("Exp(1) - E")).abs() < 1e-12);
+        assert_eq!(eval_num("NUM_INT_MAX - NUM_INT_MIN"), 16777215.0);
+        assert!(matches!(eval("dnum", "DNUM_INT_MAX + DNUM_INT_MIN"), Ok(Variable::Dnum(value)) if value == 0.0));
+        assert!(matches!(eval("bool", "NUM_MAX < DNUM_MAX"), Ok(Variable::Bool(true))));
+        assert_eq!(eval_num("DnumToNum(NumToDnum(NUM_INT_MAX) \\Integer)"), 8388608.0);
+    }
+
+    #[test]
+    fn string_constants() {
+        let upper = eval("string", "StrMap(\"Größe 42\", STR_LOWER, STR_UPPER)");
+        assert!(matches!(upper, Ok(Variable::Str(text)) if text == "GRÖßE 42"));
+        assert_eq!(eval_num("StrFind(\"Part 7\", 1, str_digit)"), 6.0);
+        assert_eq!(eval_num("StrFind(\"Part 7\", 1, STR_WHITE)"), 5.0);
+        assert!(matches!(eval("bool", "StrMemb(\"é\", 1, STR_LOWER)"), Ok(Variable::Bool(true))));
+        assert_eq!(eval_num("StrLen(STR_UPPER) + 1 - StrLen(STR_LOWER)"), 0.0);
+    }
+
+    #[test]
+    fn dnum_arithmetic() {
+        assert!(matches!(eval("dnum", "NumToDnum(4294967295) + 1"), Ok(Variable::Dnum(value)) if value == 4294967296.0));
+        assert!(matches!(eval("dnum", "0xFFFFFFFF - 0b11 * 0o10"), Ok(Variable::Dnum(value)) if value == 4294967271.0));
+        assert!(matches!(eval("dnum", "NumToDnum(7) DIV 2"), Ok(Variable::Dnum(value)) if value == 3.0));
+        assert!(matches!(eval("bool", "NumToDnum(3) > 2.5"), Ok(Variable::Bool(true))));
+        assert!(matches!(eval("string", "ValToStr(NumToDnum(4294967295))"), Ok(Variable::Str(text)) if text == "4294967295"));
+        assert_eq!(eval_num("DnumToNum(NumToDnum(2.5) * 2 \\Integer)"), 5.0);
+
+        assert_eq!(eval("num", "NumToDnum(1)").unwrap_err().to_string(), "5:9: ERR_ARGVALERR: Cannot assign dnum to num");
+        let err = eval("num", "DnumToNum(NumToDnum(16777216) \\Integer)").unwrap_err();
+        assert_eq!(err.to_string(), "5:19: ERR_ARGVALERR: Value 16777216 out of range for num");
+        assert_eq!(eval("dnum", "NumToDnum(1) / 0").unwrap_err().name(), "ERR_DIVZERO");
+    }
+
+    #[test]
+    fn bit_functions() {
+        let byte = |expr: &str| match eval("byte", expr) {
+            Ok(Variable::Byte(value)) => value,
+            result => panic!("{} evaluated to {:?}", expr, result),
+        };
+        assert_eq!(byte("BitAnd(38, 34)"), 34);
+        assert_eq!(byte("BitOr(38, 34)"), 38);
+        assert_eq!(byte("BitXOr(38, 34)"), 4);
+        assert_eq!(byte("BitNeg(38)"), 217);
+        assert_eq!(byte("BitLSh(38, 3)"), 48);
+        assert_eq!(byte("BitRSh(38, 3)"), 4);
+        assert_eq!(byte("BitRSh(38, 8)"), 0);
+        assert!(matches!(eval("bool", "BitCheck(38, 2) AND NOT BitCheck(38, 1)"), Ok(Variable::Bool(true))));
+        assert_eq!(eval_num("BitAnd(38, 34) + 300"), 334.0);
+
+        let err = eval("byte", "255 + 1").unwrap_err();
+        assert_eq!(err.to_string(), "5:9: ERR_ARGVALERR: Value 256 out of range for byte");
+        assert_eq!(eval("byte", "BitAnd(1.5, 1)").unwrap_err().name(), "ERR_ARGVALERR");
+        assert_eq!(eval("bool", "BitCheck(1, 9)").unwrap_err().name(), "ERR_ARGVALERR");
+
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Bits
+    VAR byte data := 130;
+    PROC main()
+        BitSet data, 1;
+        BitClear data, 8;
+        TPWrite \"Data \" \\Num:=data;
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let mut output = Vec::new();
+        interpreter::run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut output)).into_result().unwrap();
+        assert_eq!(output, vec!["Data 3"]);
+        assert!(parser::parse_tokens(lexer::parse("MODULE Bits VAR byte data := 256; ENDMODULE")).is_err());
+    }
+
+    #[test]
+    fn clocks_measure_host_time() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Clocks
+    VAR num nFirst := 0;
+    VAR num nSecond := 0;
+    VAR num nReset := 0;
+    PROC main()
+        VAR clock clkCycle;
+        ClkStart clkCycle;
+        ClkStop clkCycle;
+        nFirst := ClkRead(clkCycle);
+        ClkStart clkCycle;
+        nSecond := ClkRead(clkCycle \\HighRes);
+        ClkReset clkCycle;
+        nReset := ClkRead(clkCycle);
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let bytecode = compiler::compile(&program).unwrap();
+
+        // Every reading of the clock advances it by 1.2345 s
+        let step = Duration::from_micros(1_234_500);
+        let clock = host::ManualClock::new(step);
+        interpreter::run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new()).with_clock(&clock)).into_result().unwrap();
+        assert_eq!(format!("{:?}", &program.variables), "[Num(1.234), Num(2.469), Num(0.0)]");
+
+        let clock = host::ManualClock::new(step);
+        vm::run(&mut program, &bytecode, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new()).with_clock(&clock)).unwrap();
+        assert_eq!(format!("{:?}", &program.variables), "[Num(1.234), Num(2.469), Num(0.0)]");
+    }
+
+    fn eval_str(expr: &str) -> String {
+        match eval("string", expr) {
+            Ok(Variable::Str(text)) => text,
+            result => panic!("{} evaluated to {:?}", expr, result),
+        }
+    }
+
+    #[test]
+    fn string_functions() {
+        assert_eq!(eval_num("StrLen(\"Robot\")"), 5.0);
+        assert_eq!(eval_str("StrPart(\"Robotics\", 3, 3)"), "bot");
+        assert_eq!(eval_str("StrPart(\"Robot\", 6, 0)"), "");
+        assert_eq!(eval_num("StrFind(\"Robot\", 1, \"aeiou\")"), 2.0);
+        assert_eq!(eval_num("StrFind(\"Robot\", 3, \"o\" \\NotInSet)"), 3.0);
+        assert_eq!(eval_num("StrFind(\"Robot\", 1, \"xyz\")"), 6.0);
+        assert_eq!(eval_num("StrMatch(\"Robot arm\", 1, \"arm\")"), 7.0);
+        assert_eq!(eval_num("StrMatch(\"Robot\", 3, \"Ro\")"), 6.0);
+        assert_eq!(eval_str("StrMap(\"Robot\", \"obt\", \"OBT\")"), "ROBOT");
+        assert!(matches!(eval("bool", "StrMemb(\"Robot\", 2, \"aeiou\")"), Ok(Variable::Bool(true))));
+        assert!(matches!(eval("bool", "StrOrder(\"FIRST\", \"SECOND\", \"SFIR\")"), Ok(Variable::Bool(false))));
+        assert!(matches!(eval("bool", "StrOrder(\"AB\", \"ABC\", \"\")"), Ok(Variable::Bool(true))));
+        assert_eq!(eval_str("NumToStr(0.38521, 3)"), "0.385");
+        assert_eq!(eval_str("NumToStr(0.38521, 3 \\Exp)"), "3.852E-01");
+        // Halves round away from zero like Round, and zero has no sign
+        assert_eq!(eval_str("NumToStr(2.5, 0) + \" \" + NumToStr(-2.5, 0) + \" \" + NumToStr(-0.5, 0)"), "3 -3 -1");
+        assert_eq!(eval_str("NumToStr(-0.004, 2) + \" \" + NumToStr(9.9996, 3 \\Exp)"), "0.00 1.000E+01");
+        assert_eq!(eval_str("ValToStr(1.5) + ValToStr(TRUE) + ValToStr(\"a\")"), "1.5TRUE\"a\"");
+
+        let err = eval("string", "StrPart(\"Robot\", 4, 3)").unwrap_err();
+        assert_eq!(err.to_string(), "5:19: ERR_ARGVALERR: StrPart length 3 out of range");
+        assert_eq!(eval("bool", "StrMemb(\"Robot\", 0, \"R\")").unwrap_err().name(), "ERR_ARGVALERR");
+        assert_eq!(eval("string", "NumToStr(1, 1E15)").unwrap_err().name(), "ERR_ARGVALERR");
+        assert_eq!(eval("string", "NumToStr(1, 16)").unwrap_err().name(), "ERR_ARGVALERR");
+    }
+
+    #[test]
+    fn str_to_val_parses_by_type() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Parse
+    VAR num nValue := 0;
+    VAR pos pValue := [0, 0, 0];
+    VAR bool bOk1 := FALSE;
+    VAR bool bOk2 := FALSE;
+    VAR bool bOk3 := TRUE;
+    PROC main()
+        bOk1 := StrToVal(\"12.5\", nValue);
+        bOk2 := StrToVal(\" [1, 2.5, -3] \", pValue);
+        bOk3 := StrToVal(\"twelve\", nValue);
+        TPWrite ValToStr(pValue);
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let bytecode = compiler::compile(&program).unwrap();
+        let initial = program.variables.clone();
+
+        let mut output = Vec::new();
+        interpreter::run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut output)).into_result().unwrap();
+        program.variables = initial;
+        vm::run(&mut program, &bytecode, "main", &InterpreterOptions::default(), &mut Host::new(&mut output)).unwrap();
+        assert_eq!(output, vec!["[1,2.5,-3]", "[1,2.5,-3]"]);
+        assert_eq!(format!("{:?}", &program.variables[2..]), "[Bool(true), Bool(true), Bool(false)]");
+        assert!(matches!(program.variables[0], Variable::Num(n) if n == 12.5));
+    }
+
+    #[test]
+    fn host_routines_are_called() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Gripper
+    VAR num nForce := 0;
+    VAR string sName := \"\";
+    VAR num nErrno := 0;
+    PROC main()
+        SetGripper TRUE;
+        SetGripper FALSE \\Force:=20;
+        nForce := Scale(2 \\Factor:=3) + Scale(4);
+        GripperName sName;
+        Fail;
+    ERROR
+        nErrno := ERRNO;
+        TRYNEXT;
+    ENDPROC
+ENDMODULE")).unwrap();
+        let log = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let calls = log.clone();
+        program.register_proc("SetGripper", &["bool Close", "\\num Force"], move |args| {
+            let force = if args.present("Force") { args.num("Force")? } else { 10.0 };
+            calls.borrow_mut().push(format!("{} {}", args.bool("Close")?, force));
+            Ok(())
+        });
+        program.register_func("Scale", "num", &["num Value", "\\num Factor"], |args| {
+            let factor = if args.present("Factor") { args.num("Factor")? } else { 10.0 };
+            Ok(Variable::Num(args.num("Value")? * factor))
+        });
+        program.register_proc("GripperName", &["VAR string Name"], |args| args.set("Name", Variable::Str(String::from("Schunk"))));
+        program.register_proc("Fail", &[], |_| Err(RuntimeError::raise(5.0)));
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let bytecode = compiler::compile(&program).unwrap();
+        let initial = program.variables.clone();
+
+        interpreter::run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).into_result().unwrap();
+        let interpreted = std::mem::replace(&mut program.variables, initial);
+        vm::run(&mut program, &bytecode, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).unwrap();
+        assert_eq!(format!("{:?}", program.variables), r#"[Num(46.0), Str("Schunk"), Num(5.0)]"#);
+        assert_eq!(format!("{:?}", interpreted), format!("{:?}", program.variables));
+        assert_eq!(*log.borrow(), vec!["true 10", "false 20", "true 10", "false 20"]);
+    }
+
+    #[test]
+    fn signals_on_io_board() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Cell
+    VAR num nGripper := 0;
+    VAR num nWaits := 0;
+    VAR num nErrno := 0;
+    PROC main()
+        SetDO doGripper, 1;
+        SetGO goProgram, 5;
+        SetAO aoSpeed, 12.5;
+        nGripper := DOutput(doGripper);
+        WaitDI diPartReady, 1;
+        nWaits := nWaits + 1;
+        WaitDI diPartReady, 0 \\MaxTime:=2;
+        nWaits := nWaits + 1;
+    ERROR
+        nErrno := ERRNO;
+        SetDO doGripper, 0;
+    ENDPROC
+ENDMODULE")).unwrap();
+        let mut board = host::IoBoard::new();
+        board.define("diPartReady", SignalKind::DigitalInput);
+        board.define("doGripper", SignalKind::DigitalOutput);
+        board.define("aoSpeed", SignalKind::AnalogOutput);
+        board.define_group("goProgram", SignalKind::GroupOutput, 4);
+        board.declare(&mut program);
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let (time, outputs) = run_both(&mut program, Some(&board), |_| {
+            board.set_input("diPartReady", 0.0).unwrap();
+            board.set_input_at("diPartReady", 1.0, Duration::from_secs(3)).unwrap();
+        }, |program, host| {
+            let signals = ["doGripper", "goProgram", "aoSpeed"].map(|name| board.read(name).unwrap());
+            (host.clock.now(), format!("{:?} {:?}", &program.variables[4..], signals))
+        });
+        // The input changed after 3 s, the second wait timed out 2 s later
+        assert_eq!(time, Duration::from_secs(5));
+        assert_eq!(outputs, "[Num(1.0), Num(1.0), Num(1009.0)] [0.0, 5.0, 12.5]");
+
+        assert!(board.set_input("doGripper", 1.0).is_err());
+        assert!(board.write("doGripper", 2.0).is_err());
+        assert!(board.write("goProgram", 16.0).is_err());
+        let err = board.read("diMissing").unwrap_err();
+        assert_eq!(err.to_string(), "0:0: ERR_NO_ALIASIO_DEF: Signal diMissing is not defined on the I/O board");
+    }
+
+    #[test]
+    fn interrupts_run_traps() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Cell
+    VAR intnum irPart;
+    VAR intnum irTick;
+    VAR num nParts := 0;
+    VAR num nTicks := 0;
+    VAR num nErrno := 0;
+    TRAP tPart
+        nParts := nParts + 1;
+    ENDTRAP
+    TRAP tTick
+        nTicks := nTicks + 1;
+    ENDTRAP
+    PROC main()
+        CONNECT irPart WITH tPart;
+        ISignalDI diPart, 1, irPart;
+        CONNECT irTick WITH tTick;
+        ITimer \\Single, 2.5, irTick;
+        FOR i FROM 1 TO 10 DO
+            WaitTime 1;
+        ENDFOR
+        CONNECT irPart WITH tPart;
+    ERROR
+        nErrno := ERRNO;
+    ENDPROC
+ENDMODULE")).unwrap();
+        let mut board = host::IoBoard::new();
+        board.define("diPart", SignalKind::DigitalInput);
+        board.declare(&mut program);
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let variables = run_both(&mut program, Some(&board), |_| {
+            board.set_input("diPart", 0.0).unwrap();
+            for (value, at) in [(1.0, 3), (0.0, 5), (1.0, 7)] {
+                board.set_input_at("diPart", value, Duration::from_secs(at)).unwrap();
+            }
+        }, |program, _| format!("{:?}", &program.variables[1..]));
+        // Two rising edges of the input, the single timer once, and the
+        // second CONNECT of the same interrupt data raised an error
+        assert_eq!(variables, "[Num(1.0), Num(2.0), Num(2.0), Num(1.0), Num(1026.0)]");
+    }
+
+    #[test]
+    fn interrupts_can_be_masked() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Cell
+    VAR intnum irTick;
+    VAR intnum irNone;
+    VAR num nTicks := 0;
+    VAR num nHeld := 0;
+    VAR num nErrno := 0;
+    TRAP tTick
+        nTicks := nTicks + 1;
+    ENDTRAP
+    PROC main()
+        IDelete irTick;
+        CONNECT irTick WITH tTick;
+        ITimer 1, irTick;
+        FOR i FROM 1 TO 3 DO
+            WaitTime 1;
+        ENDFOR
+        IDisable;
+        WaitTime 1;
+        WaitTime 1;
+        nHeld := nTicks;
+        IEnable;
+        ISleep irTick;
+        WaitTime 2;
+        IWatch irTick;
+        WaitTime 1;
+        IDelete irTick;
+        WaitTime 2;
+        CONNECT irTick WITH tTick;
+        ISleep irNone;
+    ERROR
+        nErrno := ERRNO;
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let (time, variables) = run_both(&mut program, None, |_| (), |program, host| (host.clock.now(), format!("{:?}", program.variables)));
+        assert_eq!(time, Duration::from_secs(10));
+        // Three ticks, two held back until IEnable, one lost while asleep,
+        // one more before IDelete
+        assert_eq!(variables, "[Num(2.0), Num(0.0), Num(6.0), Num(3.0), Num(1027.0)]");
+    }
+
+    #[test]
+    fn moves_are_logged() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Path
+    CONST robtarget p10 := [[500,0,400],[0,0,1,0],[0,0,0,0],[9E+09,9E+09,9E+09,9E+09,9E+09,9E+09]];
+    CONST robtarget p20 := [[500,300,400],[0,0,1,0],[0,0,0,0],[9E+09,9E+09,9E+09,9E+09,9E+09,9E+09]];
+    CONST robtarget p30 := [[500,300,800],[0,0,1,0],[0,0,0,0],[9E+09,9E+09,9E+09,9E+09,9E+09,9E+09]];
+    CONST robtarget p40 := [[500,600,400],[0,0,1,0],[0,0,0,0],[9E+09,9E+09,9E+09,9E+09,9E+09,9E+09]];
+    CONST jointtarget jHome := [[0,0,0,0,30,0],[9E9,9E9,9E9,9E9,9E9,9E9]];
+    CONST jointtarget jPark := [[-250,0,0,0,30,0],[9E9,9E9,9E9,9E9,9E9,9E9]];
+    PROC main()
+        MoveJ p10, v1000, z10, tool0;
+        MoveL p20, v100, fine, tool0 \\WObj:=wobj0;
+        MoveL p10, v100 \\V:=150, z10, tool0;
+        MoveC p30, p40, v500, z20 \\Z:=25, tool0;
+        MoveAbsJ jHome, v1000 \\T:=4, fine, tool0;
+        MoveAbsJ jPark, v1000, fine, tool0;
+        WaitTime \\InPos, 0.5;
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let (time, trajectory) = run_both(&mut program, None, |_| (), |_, host| (host.clock.now(), host.trajectory.clone()));
+        // WaitTime \InPos waits for the robot to reach the last target
+        assert_eq!(time, Duration::from_secs(12));
+
+        let moves: Vec<String> = trajectory.iter()
+            .map(|waypoint| format!("{} {} {:?} {}", waypoint.instruction, waypoint.speed, waypoint.zone, waypoint.time.as_secs_f64()))
+            .collect();
+        assert_eq!(moves, vec![
+            "MoveJ 1000 Some(10.0) 0",
+            "MoveL 100 None 3",
+            "MoveL 150 Some(10.0) 5",
+            "MoveC 500 Some(25.0) 7",
+            "MoveAbsJ 1000 None 11",
+            "MoveAbsJ 1000 None 11.5",
+        ]);
+        assert_eq!(trajectory[3].via, Some([500.0, 300.0, 800.0]));
+        assert_eq!(trajectory[3].target, Target::Cartesian { pos: [500.0, 600.0, 400.0], rot: [0.0, 0.0, 1.0, 0.0] });
+        let targets: Vec<String> = trajectory.iter()
+            .map(|waypoint| format!("{} {} {}", waypoint.name.as_deref().unwrap_or("-"), waypoint.frame.as_deref().unwrap_or("-"), waypoint.programmed_speed))
+            .collect();
+        assert_eq!(targets, vec!["p10 wobj0 1000", "p20 wobj0 100", "p10 wobj0 150", "p40 wobj0 500", "jHome wobj0 1000", "jPark wobj0 1000"]);
+
+        let csv = host::trajectory_csv(&trajectory);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "instruction,target,frame,programmed_speed,speed,zone,time,x,y,z,q1,q2,q3,q4,j1,j2,j3,j4,j5,j6");
+        assert_eq!(lines[2], "MoveL,p20,wobj0,100,100,,3,500,300,400,0,0,1,0,,,,,,");
+        assert_eq!(lines[5], "MoveAbsJ,jHome,wobj0,1000,1000,,11,,,,,,,,0,0,0,0,30,0");
+        assert_eq!(trajectory[0].to_json().to_string(), "{\"instruction\":\"MoveJ\",\"target\":\"p10\",\"frame\":\"wobj0\",\"programmed_speed\":1000,\
+            \"speed\":1000,\"zone\":10,\"time\":0,\"pos\":[500,0,400],\"rot\":[0,0,1,0]}");
+    }
+
+    /// Position and orientation of a frame-like value, rounded to 9 decimals
+    fn frame_values(var: &Variable) -> Vec<f64> {
+        fn flatten(var: &Variable, values: &mut Vec<f64>) {
+            match var {
+                Variable::Num(value) => values.push((value * 1e9).round() / 1e9),
+                Variable::Record(_, fields) => fields.iter().for_each(|field| flatten(field, values)),
+                _ => (),
+            }
+        }
+        let mut values = Vec::new();
+        flatten(var, &mut values);
+        values.truncate(7);
+        values
+    }
+
+    fn eval_frame(data_type: &str, expr: &str) -> Vec<f64> {
+        match eval(data_type, expr) {
+            Ok(var) => frame_values(&var),
+            result => panic!("{} evaluated to {:?}", expr, result),
+        }
+    }
+
+    #[test]
+    fn target_offsets() {
+        let point = "[[500,0,400],[0,0,1,0],[0,0,0,0],[9E+09,9E+09,9E+09,9E+09,9E+09,9E+09]]";
+        assert_eq!(eval_frame("robtarget", &format!("Offs({}, 10, -20, 30)", point)), vec![510.0, -20.0, 430.0, 0.0, 0.0, 1.0, 0.0]);
+        // The tool points down, its z axis is the -z axis of the work object
+        assert_eq!(eval_frame("robtarget", &format!("RelTool({}, 0, 0, 100)", point)), vec![500.0, 0.0, 300.0, 0.0, 0.0, 1.0, 0.0]);
+        assert_eq!(eval_frame("robtarget", &format!("RelTool({}, 10, 0, 0)", point)), vec![490.0, 0.0, 400.0, 0.0, 0.0, 1.0, 0.0]);
+        let half = (0.5f64.sqrt() * 1e9).round() / 1e9;
+        assert_eq!(eval_frame("robtarget", &format!("RelTool({}, 0, 0, 0 \\Rz:=90)", point)), vec![500.0, 0.0, 400.0, 0.0, half, half, 0.0]);
+        assert_eq!(eval_frame("robtarget", &format!("RelTool({}, 0, 0, 0 \\Rx:=180 \\Ry:=180)", point)), vec![500.0, 0.0, 400.0, 0.0, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn pose_math() {
+        // Rotated 90 degrees around z and displaced along x
+        let pose = "[[100,0,0],[0.7071067811865476,0,0,0.7071067811865476]]";
+        let half = (0.5f64.sqrt() * 1e9).round() / 1e9;
+        assert_eq!(eval_frame("pos", &format!("PoseVect({}, [10,0,5])", pose)), vec![100.0, 10.0, 5.0]);
+        assert_eq!(eval_frame("pose", &format!("PoseInv({})", pose)), vec![0.0, 100.0, 0.0, half, 0.0, 0.0, -half]);
+        assert_eq!(eval_frame("pose", &format!("PoseMult({0}, {0})", pose)), vec![100.0, 100.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(eval_frame("pose", &format!("PoseMult({0}, PoseInv({0}))", pose)), vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(eval_frame("pos", &format!("PoseVect(PoseInv({0}), PoseVect({0}, [1,2,3]))", pose)), vec![1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn euler_angles() {
+        let half = (0.5f64.sqrt() * 1e9).round() / 1e9;
+        assert_eq!(eval_frame("orient", "OrientZYX(90, 0, 0)"), vec![half, 0.0, 0.0, half]);
+        assert_eq!(eval_frame("orient", "OrientZYX(0, 180, 0)"), vec![0.0, 0.0, 1.0, 0.0]);
+        assert_eq!(eval_frame("orient", "NOrient([2, 0, 0, 2])"), vec![half, 0.0, 0.0, half]);
+        let angles = "[EulerZYX(\\Z, OrientZYX(30, -45, 120)), EulerZYX(\\Y, OrientZYX(30, -45, 120)), EulerZYX(\\X, OrientZYX(30, -45, 120))]";
+        assert_eq!(eval_frame("pos", angles), vec![30.0, -45.0, 120.0]);
+        assert_eq!(eval_num("EulerZYX(\\Z, [0.5, 0.5, 0.5, 0.5])").round(), 90.0);
+        assert_eq!(eval("orient", "NOrient([0, 0, 0, 0])").unwrap_err().name(), "ERR_ARGVALERR");
+    }
+
+    #[test]
+    fn current_position_follows_moves() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Cell
+    CONST robtarget p10 := [[500,0,400],[0,0,1,0],[0,0,0,0],[9E+09,9E+09,9E+09,9E+09,9E+09,9E+09]];
+    CONST jointtarget jHome := [[0,0,0,0,30,0],[9E+09,9E+09,9E+09,9E+09,9E+09,9E+09]];
+    PERS tooldata tGripper := [TRUE,[[0,0,100],[1,0,0,0]],[1,[0,0,1],[1,0,0,0],0,0,0]];
+    PERS wobjdata wTable := [FALSE,TRUE,\"\",[[100,200,0],[1,0,0,0]],[[0,0,0],[1,0,0,0]]];
+    VAR robtarget pStart;
+    VAR jointtarget jStart;
+    VAR robtarget pHere;
+    VAR robtarget pWorld;
+    VAR robtarget pFlange;
+    VAR jointtarget jHere;
+    VAR robtarget pRetreat;
+    PROC main()
+        pStart := CRobT();
+        jStart := CJointT();
+        MoveL p10, v1000, fine, tGripper \\WObj:=wTable;
+        pHere := CRobT();
+        pWorld := CRobT(\\WObj:=wobj0);
+        pFlange := CRobT(\\Tool:=tool0 \\WObj:=wobj0);
+        MoveAbsJ jHome, v1000, fine, tool0;
+        jHere := CJointT();
+        MoveL Offs(CRobT(), 0, 0, 100), v1000, fine, tGripper \\WObj:=wTable;
+        pRetreat := CRobT();
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let positions = run_both(&mut program, None, |_| (), |program, _| program.variables[4..].iter().map(frame_values).collect::<Vec<_>>());
+        assert_eq!(positions, vec![
+            vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0],
+            vec![0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 9E9],
+            vec![500.0, 0.0, 400.0, 0.0, 0.0, 1.0, 0.0],
+            // In the base frame, and of the flange above the TCP of the gripper
+            vec![600.0, 200.0, 400.0, 0.0, 0.0, 1.0, 0.0],
+            vec![600.0, 200.0, 500.0, 0.0, 0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 0.0, 0.0, 30.0, 0.0, 9E9],
+            vec![500.0, 0.0, 500.0, 0.0, 0.0, 1.0, 0.0],
+        ]);
+    }
+
+    #[test]
+    fn motion_settings_scale_moves() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Settings
+    CONST robtarget p10 := [[500,0,400],[0,0,1,0],[0,0,0,0],[9E+09,9E+09,9E+09,9E+09,9E+09,9E+09]];
+    CONST robtarget p20 := [[500,300,400],[0,0,1,0],[0,0,0,0],[9E+09,9E+09,9E+09,9E+09,9E+09,9E+09]];
+    PROC main()
+        MoveJ p10, v1000, fine, tool0;
+        VelSet 50, 200;
+        AccSet 10, 50;
+        ConfL \\Off;
+        MoveL p20, v1000, fine, tool0;
+        VelSet 100, 5000;
+        MoveL p10, v100 \\T:=1, fine, tool0;
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let trajectory = run_both(&mut program, None, |host| host.speed_override = 50.0, |_, host| host.trajectory.clone());
+
+        // v1000 at 50 % is limited to 200 mm/s, of which the operator allows half
+        let moves: Vec<String> = trajectory.iter()
+            .map(|waypoint| format!("{} {} {}", waypoint.instruction, waypoint.speed, waypoint.time.as_secs_f64()))
+            .collect();
+        assert_eq!(moves, vec!["MoveJ 500 0", "MoveL 100 3", "MoveL 50 5"]);
+        let settings = trajectory[1].settings;
+        assert_eq!((settings.acceleration, settings.ramp, settings.conf_j, settings.conf_l), (20.0, 50.0, true, false));
+        assert!(trajectory[2].settings.velocity == 100.0);
+
+        let mut program = parser::parse_tokens(lexer::parse("MODULE Conf PROC main() ConfJ \\On \\Off; ENDPROC ENDMODULE")).unwrap();
+        assert!(resolver::resolve(&mut program, &ResolveOptions::default()).is_err());
+    }
+
+    #[test]
+    fn files_in_the_file_root() {
+        let root = std::env::temp_dir().join(format!("rapid_rust_files_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let source = |body: &str| format!("
+MODULE Recipes
+    VAR iodev file;
+    VAR string sLines := \"\";
+    VAR num nValue := 0;
+    PROC main()
+        VAR string sLine;
+{}
+    ENDPROC
+ENDMODULE", body);
+        let program = |body: &str| {
+            let mut program = parser::parse_tokens(lexer::parse(&source(body))).unwrap();
+            resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+            program
+        };
+        let run = |body: &str| {
+            let options = InterpreterOptions::default();
+            let mut interpreted = program(body);
+            let result = interpreter::run(&mut interpreted, "main", &options, &mut Host::new(&mut Vec::new()).with_file_root(&root)).into_result().map(|_| ());
+            let mut executed = program(body);
+            let bytecode = compiler::compile(&executed).unwrap();
+            assert_eq!(result, vm::run(&mut executed, &bytecode, "main", &options, &mut Host::new(&mut Vec::new()).with_file_root(&root)));
+            let globals = format!("{:?}", interpreted.variables);
+            assert_eq!(globals, format!("{:?}", executed.variables));
+            result.map(|_| globals)
+        };
+
+        let globals = run("
+        Open \"HOME:\" \\File:=\"recipe.txt\", file \\Write;
+        Write file, \"Recipe A\";
+        Write file, \"Speed \" \\Num:=2.5;
+        Write file, \" 3.5 \";
+        Write file, \"\" \\Pos:=[1, 2, 3];
+        Close file;
+        Open \"HOME:/recipe.txt\", file \\Append;
+        Write file, \"last\" \\NoNewLine;
+        Close file;
+        Open \"HOME:/recipe.txt\", file \\Read;
+        sLine := ReadStr(file);
+        WHILE sLine <> EOF DO
+            sLines := sLines + sLine + \";\";
+            sLine := ReadStr(file);
+        ENDWHILE
+        Rewind file;
+        sLine := ReadStr(file \\Delim:=\" \");
+        sLine := ReadStr(file);
+        sLine := ReadStr(file);
+        nValue := ReadNum(file);
+        Close file;").unwrap();
+        assert_eq!(globals, "[IoDev(None), Str(\"Recipe A;Speed 2.5; 3.5 ;[1,2,3];last;\"), Num(3.5)]");
+        assert_eq!(fs::read_to_string(root.join("recipe.txt")).unwrap(), "Recipe A\nSpeed 2.5\n 3.5 \n[1,2,3]\nlast");
+
+        let globals = run("
+        Open \"HOME:/recipe.txt\", file \\Read;
+        FOR i FROM 1 TO 5 DO
+            sLine := ReadStr(file);
+        ENDFOR
+        nValue := ReadNum(file);
+        IF nValue = EOF_NUM sLines := ReadStr(file);").unwrap();
+        assert_eq!(globals, "[IoDev(Some(0)), Str(\"EOF\"), Num(9.998e36)]");
+
+        assert_eq!(run("nValue := ReadNum(file);").unwrap_err().name(), "ERR_FILEACC");
+        assert_eq!(run("Open \"HOME:/../recipe.txt\", file;").unwrap_err().name(), "ERR_FILEOPEN");
+        assert_eq!(run("Open \"HOME:/missing.txt\", file \\Read;").unwrap_err().name(), "ERR_FILEOPEN");
+        assert_eq!(run("Open \"HOME:/recipe.txt\", file \\Read;\n        Open \"HOME:/recipe.txt\", file \\Read;").unwrap_err().name(), "ERR_FILEOPEN");
+        let err = run("Open \"HOME:/recipe.txt\", file \\Read;\n        nValue := ReadNum(file);").unwrap_err();
+        assert_eq!(err.to_string(), "9:19: ERR_RCVDATA: ReadNum read \"Recipe A\", not a number");
+
+        // Without a file root the program can't open files at all
+        let err = interpreter::run(&mut program("Open \"HOME:/recipe.txt\", file \\Read;"), "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).into_result().unwrap_err();
+        assert_eq!(err.name(), "ERR_FILEOPEN");
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn sockets_talk_tcp() {
+        // Answers every message with ACK: and the message
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                std::thread::spawn(move || {
+                    let mut buffer = [0; 256];
+                    while let Ok(read) = stream.read(&mut buffer) {
+                        if read == 0 || stream.write_all(&[b"ACK:", &buffer[..read]].concat()).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        let source = |body: &str| format!("
+MODULE Client
+    VAR socketdev socket;
+    VAR string sReply := \"\";
+    VAR num nBytes := 0;
+    VAR byte bData{{6}} := [80, 76, 67, 0, 0, 0];
+    VAR num nStatus := 0;
+    PROC main()
+        SocketCreate socket;
+{}
+    ENDPROC
+ENDMODULE", body);
+        let program = |body: &str| {
+            let mut program = parser::parse_tokens(lexer::parse(&source(body))).unwrap();
+            resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+            program
+        };
+        let run = |body: &str| {
+            let options = InterpreterOptions::default();
+            let allowed = format!("127.0.0.1:{}", port);
+            let mut interpreted = program(body);
+            let result = interpreter::run(&mut interpreted, "main", &options, &mut Host::new(&mut Vec::new()).with_socket_host(&allowed)).into_result().map(|_| ());
+            let mut executed = program(body);
+            let bytecode = compiler::compile(&executed).unwrap();
+            assert_eq!(result, vm::run(&mut executed, &bytecode, "main", &options, &mut Host::new(&mut Vec::new()).with_socket_host(&allowed)));
+            let globals = format!("{:?}", interpreted.variables);
+            assert_eq!(globals, format!("{:?}", executed.variables));
+            result.map(|_| globals)
+        };
+
+        let globals = run(&format!("
+        SocketConnect socket, \"127.0.0.1\", {};
+        nStatus := SocketGetStatus(socket);
+        SocketSend socket \\Str:=\"PING\";
+        SocketReceive socket \\Str:=sReply \\NoRecBytes:=nBytes;
+        SocketSend socket \\Data:=bData \\NoOfBytes:=2;
+        SocketReceive socket \\Data:=bData \\ReadNoOfBytes:=6;
+        SocketClose socket;
+        IF SocketGetStatus(socket) <> SOCKET_CLOSED nStatus := -1;", port)).unwrap();
+        assert_eq!(globals, "[SocketDev(None), Str(\"ACK:PING\"), Num(8.0), Array([Byte(65), Byte(67), Byte(75), Byte(58), Byte(80), Byte(76)]), Num(2.0)]");
+
+        assert_eq!(run("SocketConnect socket, \"127.0.0.1\", 1;").unwrap_err().name(), "ERR_SOCK_NET_UNREACH");
+        assert_eq!(run("SocketSend socket \\Str:=\"PING\";").unwrap_err().name(), "ERR_SOCK_CLOSED");
+        let err = run(&format!("SocketConnect socket, \"127.0.0.1\", {};\n        SocketReceive socket \\Str:=sReply \\Time:=0.05;", port)).unwrap_err();
+        assert_eq!(err.name(), "ERR_SOCK_TIMEOUT");
+        assert_eq!(err.errno(), error_number("ERR_SOCK_TIMEOUT"));
+    }
+
+    #[test]
+    fn sandbox_paths_stay_in_the_root() {
+        let root = std::path::Path::new("/sim");
+        assert_eq!(host::sandbox_path(root, "HOME:/logs/cycle.txt").unwrap(), root.join("logs/cycle.txt"));
+        assert_eq!(host::sandbox_path(root, "home:log.txt").unwrap(), root.join("log.txt"));
+        assert_eq!(host::sandbox_path(root, "TEMP:/./a.txt").unwrap(), root.join("TEMP/a.txt"));
+        assert!(host::sandbox_path(root, "HOME:/logs/../../etc/passwd").is_err());
+        assert!(host::sandbox_path(root, "HOME:/C:/x").is_err());
+        assert!(host::sandbox_path(root, "/etc:/passwd").is_err());
+    }
+
+    #[test]
+    fn events_are_logged() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Cell
+    VAR num nErrno := 0;
+    PROC main()
+        ErrWrite \\I, \"Cycle started\", \"Part 12\";
+        WaitTime 1.5;
+        ErrWrite \\W, \"Gripper slow\", \"Closing took\" \\RL2:=\"2.5 s\" \\RL4:=\"Check the air\";
+        ErrWrite \"Part lost\", \"Vacuum dropped\";
+        ErrLog 4800, \"Station\", 3, TRUE, [1, 2], \"\";
+        ErrLog 4799, \"\", \"\", \"\", \"\", \"\";
+    ERROR
+        nErrno := ERRNO;
+        TRYNEXT;
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let (variables, log) = run_both(&mut program, None, |_| (), |program, host| (format!("{:?}", program.variables), host.event_log.clone()));
+        assert_eq!(variables, "[Num(1001.0)]");
+        let events: Vec<String> = log.iter()
+            .map(|event| format!("{} {:?} {:?} {}: {}", event.number, event.severity, event.time, event.title, event.reason.join("|")))
+            .collect();
+        assert_eq!(events, vec![
+            "80003 Information 0ns Cycle started: Part 12",
+            "80002 Warning 1.5s Gripper slow: Closing took|2.5 s|Check the air",
+            "80001 Error 1.5s Part lost: Vacuum dropped",
+            "4800 Error 1.5s Event 4800: Station|3|TRUE|[1,2]|",
+        ]);
+    }
+
+    #[test]
+    fn data_by_name() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Cell
+    VAR num nCount := 3;
+    PERS string sName := \"Cell\";
+    CONST num nMax := 10;
+    LOCAL VAR num nHidden := 1;
+    VAR num nFound := 0;
+    VAR string sObject := \"\";
+    VAR string sFound := \"\";
+    VAR string sErrors := \"\";
+    PROC main()
+        GetDataVal \"ncount\", nFound;
+        SetDataVal \"nCount\", nFound + 1;
+        SetDataVal \"nHidden\" \\Block:=\"Cell\", 5;
+        GetDataVal \"nShared\", nFound;
+        WHILE SearchObject(\"n*\", sObject \\Type:=\"num\") DO
+            sFound := sFound + sObject + \" \";
+        ENDWHILE
+        GetDataVal \"nSecret\", nFound;
+        GetDataVal \"nCount\" \\Block:=\"Other\", nFound;
+        SetDataVal \"nMax\", 1;
+        GetDataVal \"sName\", nFound;
+    ERROR
+        sErrors := sErrors + ValToStr(ERRNO) + \" \";
+        TRYNEXT;
+    ENDPROC
+ENDMODULE
+MODULE Other
+    LOCAL VAR num nSecret := 7;
+    VAR num nShared := 2;
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let variables = run_both(&mut program, None, |_| (), |program, _| format!("{:?}", program.variables));
+        assert_eq!(variables, concat!(r#"[Num(4.0), Str("Cell"), Num(10.0), Num(5.0), Num(2.0), Str("nShared"), "#,
+            r#"Str("nCount nMax nHidden nFound nShared "), Str("1022 1022 1022 1001 "), Num(7.0), Num(2.0)]"#));
+    }
+
+    #[test]
+    fn data_reflection() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Cell
+    VAR num nCount := 1;
+    PERS num nTotal := 0;
+    CONST num nMax := 5;
+    VAR pos pHome := [0, 0, 0];
+    VAR byte byFlags{2} := [1, 2];
+    VAR string sLog := \"\";
+    FUNC string sBit(bool bValue)
+        IF bValue RETURN \"1\";
+        RETURN \"0\";
+    ENDFUNC
+    PROC rCheck(INOUT num nData, PERS num nPers)
+        sLog := sLog + \" \" + sBit(IsPers(nData)) + sBit(IsVar(nData)) + sBit(IsPers(nPers));
+    ENDPROC
+    PROC rForward(INOUT num nData)
+        rCheck nData, nTotal;
+    ENDPROC
+    PROC main()
+        VAR num nLocal := 0;
+        sLog := Type(nCount) + \" \" + Type(pHome) + \" \" + Type(byFlags) + \" \" + Type(sLog \\BaseName);
+        sLog := sLog + \" \" + sBit(IsPers(nMax)) + sBit(IsVar(nMax)) + sBit(IsVar(nLocal));
+        rCheck nCount, nTotal;
+        rCheck nTotal, nTotal;
+        rForward nTotal;
+        rForward nLocal;
+        %\"rCheck\"% nTotal, nTotal;
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let log = run_both(&mut program, None, |_| (), |program, _| format!("{:?}", program.variables[5]));
+        assert_eq!(log, r#"Str("num pos byte string 001 011 101 101 011 101")"#);
+    }
+}
@@ -0,0 +1,95 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::parser::{Module, Program};
+
+// ------------------ Parse cache -----------------------/
+
+// Format of the entries, those of another format are parsed again. Bump it
+// whenever `Program`, `Module` or the node types they hold change.
+const FORMAT: u32 = 2;
+
+/// Modules parsed from source text, stored in a directory by the hash of the
+/// source together with the source itself, so a file whose hash collides
+/// with another is parsed again. Tools run again over a big program or
+/// backup only parse the files that changed since.
+pub struct ParseCache {
+    dir: PathBuf,
+}
+
+impl ParseCache {
+    /// Cache in a directory, which is created when the first entry is stored
+    pub fn new(dir: impl Into<PathBuf>) -> ParseCache {
+        ParseCache { dir: dir.into() }
+    }
+
+    /// Modules of source text, from the cache when it has those of the same
+    /// source, parsed and stored otherwise. An entry that can't be stored
+    /// leaves the cache as it was.
+    pub fn modules(&self, source: &str) -> Result<Vec<Module>, Error> {
+        let path = self.entry(source);
+        let cached = fs::read(&path).ok()
+            .and_then(|bytes| bincode::deserialize::<(u32, String, Vec<Module>)>(&bytes).ok())
+            .filter(|(format, cached, _)| *format == FORMAT && cached == source);
+        if let Some((_, _, modules)) = cached {
+            return Ok(modules);
+        }
+        let modules = Program::from_source(source)?.modules;
+        if let Ok(bytes) = bincode::serialize(&(FORMAT, source, &modules)) {
+            let _ = fs::create_dir_all(&self.dir).and_then(|_| fs::write(&path, bytes));
+        }
+        Ok(modules)
+    }
+
+    /// Program of a path like `Program::from_path`, with the modules of its
+    /// files from the cache
+    pub fn program(&self, path: &Path) -> Result<Program, Error> {
+        Program::from_path_with(path, &|source| self.modules(source))
+    }
+
+    fn entry(&self, source: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}.bin", hash(source.as_bytes())))
+    }
+}
+
+/// FNV-1a hash of the bytes, the same in every build unlike the hashers of
+/// the standard library
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_changed_sources_only() {
+        let dir = std::env::temp_dir().join(format!("rapid_rust_cache_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = ParseCache::new(&dir);
+        let source = "MODULE Cell\n    VAR pos pHome := [1, 2, 3];\n    PROC main()\n        pHome := [4, 5, 6];\n    ENDPROC\nENDMODULE";
+
+        let parsed = cache.modules(source).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        let cached = cache.modules(source).unwrap();
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", cached));
+
+        // An entry that doesn't read back is parsed again
+        fs::write(cache.entry(source), b"stale").unwrap();
+        assert_eq!(format!("{:?}", cache.modules(source).unwrap()), format!("{:?}", parsed));
+
+        // So is an entry of another source with the same hash
+        let other = source.replace("[4, 5, 6]", "[7, 8, 9]");
+        let modules = Program::from_source(&other).unwrap().modules;
+        fs::write(cache.entry(source), bincode::serialize(&(FORMAT, &other, &modules)).unwrap()).unwrap();
+        assert_eq!(format!("{:?}", cache.modules(source).unwrap()), format!("{:?}", parsed));
+        assert!(cache.modules("MODULE Cell\n    PROC main(\nENDMODULE").is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let path = dir.join("Cell.sys");
+        fs::write(&path, source).unwrap();
+        assert!(cache.program(&path).unwrap().modules[0].system);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,352 @@
+use std::fs;
+use std::path::Path;
+
+use crate::json::Json;
+use crate::lexer::Span;
+use crate::parser::{DataDecl, Program, Storage};
+use crate::variable::Variable;
+
+// ------------------ Calibration -----------------------/
+
+/// Values of the data of a cell, like the tooldata of its tools and the
+/// wobjdata of its fixtures, that replace the values the modules declare.
+/// The same program can so be checked and run against the calibration of
+/// each cell without editing it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Calibration {
+    // Data names with their values, in the order of the file
+    pub values: Vec<(String, Json)>,
+}
+
+impl Calibration {
+    /// Read a calibration as JSON or as YAML, an object of data names with
+    /// their values:
+    ///
+    /// ```text
+    /// # Cell 2
+    /// tGripper:
+    ///   tframe: [[0, 0, 120], [1, 0, 0, 0]]
+    /// wobjTable: [FALSE, TRUE, "", [[1200, -300, 750], [1, 0, 0, 0]], [[0, 0, 0], [1, 0, 0, 0]]]
+    /// tooldata tWelder: [TRUE, [[0, 0, 300], [1, 0, 0, 0]], [2, [0, 0, 100], [1, 0, 0, 0], 0, 0, 0]]
+    /// ```
+    ///
+    /// An array sets every component or element, an object the components it
+    /// names. A name with a data type before it is declared as PERS data of
+    /// the system when the program doesn't declare it.
+    pub fn parse(text: &str) -> Result<Calibration, String> {
+        let json = if text.trim_start().starts_with(['{', '[']) { Json::parse(text)? } else { yaml(text)? };
+        match json {
+            Json::Object(values) => Ok(Calibration { values }),
+            _ => Err(String::from("Expected data names with their values")),
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Result<Calibration, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Calibration::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// Set the data of the modules of a parsed program to the values of the
+    /// calibration, before it's resolved. Constants and variables are set
+    /// like persistent data. Gives the names the program doesn't declare.
+    pub fn apply(&self, program: &mut Program) -> Result<Vec<String>, String> {
+        let mut missing = Vec::new();
+        for (key, value) in self.values.iter() {
+            let (data_type, name) = match key.trim().split_once(char::is_whitespace) {
+                Some((data_type, name)) => (Some(data_type), name.trim()),
+                None => (None, key.trim()),
+            };
+            let mut found = false;
+            let decls = program.modules.iter_mut()
+                .flat_map(|module| module.variables.iter_mut())
+                .filter(|decl| decl.name.eq_ignore_ascii_case(name));
+            for decl in decls {
+                if let Some(data_type) = data_type.filter(|data_type| !data_type.eq_ignore_ascii_case(&decl.data_type)) {
+                    return Err(format!("{} is {}, not {}", decl.name, decl.data_type, data_type));
+                }
+                assign(&mut decl.value, value).map_err(|err| format!("{}: {}", name, err))?;
+                found = true;
+            }
+            match data_type {
+                _ if found => (),
+                Some(data_type) => {
+                    let mut decl = DataDecl {
+                        name: String::from(name),
+                        data_type: String::from(data_type),
+                        storage: Storage::Pers,
+                        local: false,
+                        value: Variable::from(data_type).map_err(|err| format!("{}: {}", name, err))?,
+                        init: None,
+                        span: Span::default(),
+                    };
+                    assign(&mut decl.value, value).map_err(|err| format!("{}: {}", name, err))?;
+                    program.system.push(decl);
+                },
+                None => missing.push(String::from(name)),
+            }
+        }
+        Ok(missing)
+    }
+}
+
+/// Set data to a value, an object sets the components it names
+fn assign(var: &mut Variable, value: &Json) -> Result<(), String> {
+    match value {
+        Json::Object(members) => {
+            for (name, value) in members {
+                let component = var.component_mut(name).map_err(|err| err.to_string())?;
+                assign(component, value).map_err(|err| format!("{}: {}", name, err))?;
+            }
+            Ok(())
+        },
+        value => var.set(to_variable(value)?).map_err(|err| err.to_string()),
+    }
+}
+
+/// Value of JSON, arrays become aggregates that take the shape of the data
+/// they're assigned to
+fn to_variable(value: &Json) -> Result<Variable, String> {
+    match value {
+        Json::Null => Err(String::from("Missing value")),
+        Json::Bool(value) => Ok(Variable::Bool(*value)),
+        Json::Num(value) => Ok(Variable::Num(*value)),
+        Json::Str(text) => Ok(Variable::Str(text.clone())),
+        Json::Array(items) => Ok(Variable::Record("", items.iter().map(to_variable).collect::<Result<_, _>>()?)),
+        Json::Object(_) => Err(String::from("Unexpected object in an array")),
+    }
+}
+
+// ------------------ YAML -----------------------/
+
+/// Read the mappings of YAML, nested by indentation, with their values in
+/// flow style. Booleans may be written as in RAPID, like TRUE.
+fn yaml(text: &str) -> Result<Json, String> {
+    let mut lines = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = strip_comment(line);
+        if line.trim().is_empty() || line.trim() == "---" {
+            continue;
+        }
+        if line.starts_with('\t') {
+            return Err(format!("{}: Tabs can't indent YAML", idx + 1));
+        }
+        let indent = line.len() - line.trim_start().len();
+        lines.push((idx + 1, indent, line.trim()));
+    }
+    let mut pos = 0;
+    let indent = lines.first().map(|(_, indent, _)| *indent).unwrap_or(0);
+    let value = mapping(&lines, &mut pos, indent)?;
+    match lines.get(pos) {
+        Some((line, _, _)) => Err(format!("{}: Unexpected indentation", line)),
+        None => Ok(value),
+    }
+}
+
+/// Members of a mapping at the indentation, and the mappings nested in them
+fn mapping(lines: &[(usize, usize, &str)], pos: &mut usize, indent: usize) -> Result<Json, String> {
+    let mut members = Vec::new();
+    while let Some(&(line, line_indent, text)) = lines.get(*pos) {
+        if line_indent < indent {
+            break;
+        }
+        if line_indent > indent {
+            return Err(format!("{}: Unexpected indentation", line));
+        }
+        let (key, value) = match text.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => return Err(format!("{}: Expected name: value", line)),
+        };
+        *pos += 1;
+        let value = match lines.get(*pos) {
+            Some(&(_, next, _)) if value.is_empty() && next > indent => mapping(lines, pos, next)?,
+            _ if value.is_empty() => Json::Null,
+            _ => Flow { chars: value.chars().collect(), pos: 0 }.read().map_err(|err| format!("{}: {}", line, err))?,
+        };
+        members.push((String::from(key.trim_matches(|c| c == '"' || c == '\'')), value));
+    }
+    Ok(Json::Object(members))
+}
+
+/// Line without a comment, which starts with # outside of quotes
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (idx, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => return &line[..idx],
+            None => (),
+        }
+        previous = c;
+    }
+    line
+}
+
+/// Reads a YAML value in flow style: a sequence in brackets, a quoted
+/// string or a plain scalar
+struct Flow {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Flow {
+    fn read(mut self) -> Result<Json, String> {
+        let value = self.value()?;
+        self.skip_space();
+        match self.chars.get(self.pos) {
+            None => Ok(value),
+            Some(c) => Err(format!("Unexpected '{}' after value", c)),
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_space();
+        match self.chars.get(self.pos) {
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_space();
+                    if self.chars.get(self.pos) == Some(&']') {
+                        self.pos += 1;
+                        return Ok(Json::Array(items));
+                    }
+                    items.push(self.value()?);
+                    self.skip_space();
+                    match self.chars.get(self.pos) {
+                        Some(',') => self.pos += 1,
+                        Some(']') => (),
+                        _ => return Err(String::from("Expected ',' or ']' in sequence")),
+                    }
+                }
+            },
+            Some(&quote @ ('"' | '\'')) => {
+                self.pos += 1;
+                let mut text = String::new();
+                loop {
+                    match self.chars.get(self.pos) {
+                        // A quote is doubled in a single-quoted string
+                        Some('\'') if quote == '\'' && self.chars.get(self.pos + 1) == Some(&'\'') => {
+                            text.push('\'');
+                            self.pos += 1;
+                        },
+                        Some(c) if *c == quote => break,
+                        Some('\\') if quote == '"' => {
+                            self.pos += 1;
+                            match self.chars.get(self.pos) {
+                                Some(c @ ('"' | '\\')) => text.push(*c),
+                                _ => return Err(String::from("Unsupported escape in string")),
+                            }
+                        },
+                        Some(c) => text.push(*c),
+                        None => return Err(String::from("Unterminated string")),
+                    }
+                    self.pos += 1;
+                }
+                self.pos += 1;
+                Ok(Json::Str(text))
+            },
+            Some('{') => Err(String::from("Nest a mapping on the lines below its name")),
+            Some(_) => {
+                let start = self.pos;
+                while self.chars.get(self.pos).is_some_and(|c| !matches!(c, ',' | ']')) {
+                    self.pos += 1;
+                }
+                let word: String = self.chars[start..self.pos].iter().collect();
+                let word = word.trim();
+                Ok(match word.to_ascii_lowercase().as_str() {
+                    "true" => Json::Bool(true),
+                    "false" => Json::Bool(false),
+                    "null" | "~" => Json::Null,
+                    _ => match word.trim_start_matches('+').parse() {
+                        Ok(value) => Json::Num(value),
+                        Err(_) => Json::str(word),
+                    },
+                })
+            },
+            None => Err(String::from("Expected value")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer;
+    use crate::parser;
+    use crate::resolver::{self, ResolveOptions};
+
+    const SOURCE: &str = "
+MODULE Cell
+    PERS tooldata tGripper := [TRUE, [[0, 0, 100], [1, 0, 0, 0]], [1, [0, 0, 50], [1, 0, 0, 0], 0, 0, 0]];
+    PERS wobjdata wobjTable := [FALSE, TRUE, \"\", [[0, 0, 0], [1, 0, 0, 0]], [[0, 0, 0], [1, 0, 0, 0]]];
+    CONST num nParts := 4;
+    VAR tooldata tCurrent;
+    PROC main()
+        tCurrent := tWelder;
+    ENDPROC
+ENDMODULE";
+
+    fn calibrated(calibration: &str) -> Program {
+        let mut program = parser::parse_tokens(lexer::parse(SOURCE)).unwrap();
+        assert!(Calibration::parse(calibration).unwrap().apply(&mut program).unwrap().is_empty());
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        program
+    }
+
+    fn value(program: &Program, name: &str, components: &[&str]) -> String {
+        let decl = program.module_data().into_iter().map(|(_, decl, _)| decl)
+            .chain(program.system.iter())
+            .find(|decl| decl.name == name)
+            .unwrap();
+        let var = components.iter().try_fold(&decl.value, |var, component| var.component(component)).unwrap();
+        format!("{:?}", var)
+    }
+
+    #[test]
+    fn overrides_the_data_of_the_cell() {
+        let program = calibrated("# Cell 2
+tGripper:
+  tframe:
+    trans: [0, 0, 120.5]   # measured
+wobjTable: [FALSE, TRUE, '', [[1200, -300, 750], [1, 0, 0, 0]], [[0, 0, 0], [1, 0, 0, 0]]]
+nParts: 6
+\"tooldata tWelder\": [TRUE, [[0, 0, 300], [1, 0, 0, 0]], [2.5, [0, 0, 100], [1, 0, 0, 0], 0, 0, 0]]
+");
+        assert_eq!(value(&program, "tGripper", &["tframe", "trans"]), "Record(\"pos\", [Num(0.0), Num(0.0), Num(120.5)])");
+        assert_eq!(value(&program, "tGripper", &["tload", "mass"]), "Num(1.0)");
+        assert_eq!(value(&program, "wobjTable", &["uframe", "trans"]), "Record(\"pos\", [Num(1200.0), Num(-300.0), Num(750.0)])");
+        assert_eq!(value(&program, "nParts", &[]), "Num(6.0)");
+        assert_eq!(value(&program, "tWelder", &["tload", "mass"]), "Num(2.5)");
+
+        let program = calibrated(r#"{"tGripper": {"tload": {"mass": 3}}, "NPARTS": 6, "tooldata tWelder": {}}"#);
+        assert_eq!(value(&program, "tGripper", &["tload", "mass"]), "Num(3.0)");
+        assert_eq!(value(&program, "nParts", &[]), "Num(6.0)");
+        assert_eq!(value(&program, "tWelder", &["robhold"]), "Bool(false)");
+    }
+
+    #[test]
+    fn rejects_values_of_the_wrong_shape() {
+        let mut program = parser::parse_tokens(lexer::parse(SOURCE)).unwrap();
+        let calibration = Calibration::parse("nParts: 6\nnMissing: 1\nwobjdata tWelder:\n  robhold: TRUE").unwrap();
+        assert_eq!(calibration.apply(&mut program).unwrap(), vec![String::from("nMissing")]);
+        assert_eq!(program.system[0].data_type, "wobjdata");
+
+        let mut error = |text: &str| Calibration::parse(text).and_then(|calibration| calibration.apply(&mut program)).unwrap_err();
+        assert_eq!(error("nParts: \"six\""), "nParts: 0:0: ERR_ARGVALERR: Cannot assign string to num");
+        assert_eq!(error("tGripper: [TRUE]"), "tGripper: 0:0: ERR_ARGVALERR: Cannot assign aggregate to tooldata");
+        assert_eq!(error("tGripper:\n  tfram: [[0, 0, 0], [1, 0, 0, 0]]"), "tGripper: 0:0: ERR_ARGVALERR: tooldata has no component tfram");
+        assert_eq!(error("wobjdata tGripper: []"), "tGripper is tooldata, not wobjdata");
+        assert_eq!(error("nParts: [1, 2"), "1: Expected ',' or ']' in sequence");
+        assert_eq!(error("nParts: 1\n    nOther: 2"), "2: Unexpected indentation");
+        assert_eq!(error("[1, 2]"), "Expected data names with their values");
+    }
+}
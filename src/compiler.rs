@@ -0,0 +1,677 @@
+use std::rc::Rc;
+
+use crate::builtins::{self, HostRoutine};
+use crate::interpreter::{self, LateArg, Passed, RuntimeError};
+use crate::lexer::Span;
+use crate::parser::{Argument, Callee, DataDecl, Module, Node, NodeId, Operator, Param, ParamMode, Program, Routine, Statement, Storage, WriteArg};
+use crate::variable::Variable;
+
+// ------------------ Instructions -----------------------/
+
+/// Instruction of the stack machine. Operands are popped from the value
+/// stack and results pushed back onto it, slots index the locals of the
+/// current frame or the global data.
+#[derive(Debug, Clone)]
+pub enum Instr {
+    Push(Variable),
+    Pop,
+    Dup,
+    Load(usize),
+    Store(usize),
+    LoadGlobal(usize),
+    StoreGlobal(usize),
+    // Element of the array in a local or global slot, the indices are on
+    // the stack, above the value for a store
+    LoadElement { slot: usize, global: bool, dims: usize },
+    StoreElement { slot: usize, global: bool, dims: usize },
+    // Whether an argument was passed for the parameter slot
+    Present(usize),
+    // Whether the data passed for the INOUT parameter slot has the storage
+    IsStorage(usize, Storage),
+    // Number of the error last handled
+    Errno,
+    BinOp(Operator),
+    Neg,
+    Not,
+    // Record from the given number of values
+    Aggregate(usize),
+    // TPWrite of the text, followed by the value of the optional argument
+    Print(Option<WriteArg>),
+    Jump(usize),
+    JumpIfFalse(usize),
+    JumpIfTrue(usize),
+    // Step of a FOR loop without STEP: -1 when counting down, 1 otherwise
+    DefaultStep { counter: usize, end: usize, step: usize },
+    // Jump to `exit` once the counter of a FOR loop passed its end
+    ForCheck { counter: usize, end: usize, step: usize, exit: usize },
+    // Call a routine with the values on the stack for the parameter slots in
+    // `args`, switches are passed as a void value. On return the values of
+    // the `outputs` slots are pushed, last first, after the return value of
+    // a FUNC. `passed` tells what each argument passes.
+    Call { routine: usize, args: Vec<usize>, outputs: Vec<usize>, passed: Vec<Passed> },
+    // Call a built-in routine or a routine of the host, like `Call`. A
+    // motion instruction gets the `names` of the data passed, by slot.
+    CallBuiltin { builtin: usize, args: Vec<usize>, outputs: Vec<usize>, names: Vec<Option<String>> },
+    CallHost { routine: usize, args: Vec<usize>, outputs: Vec<usize> },
+    // Call the procedure named by the string below the arguments on the
+    // stack. On return each data argument is pushed, last first, with
+    // whether it's an output on top of it.
+    LateCall { args: Vec<LateArg> },
+    // Replace the intnum value on the stack, which can't be connected yet
+    // or has to be deleted, with a new interrupt number for the TRAP routine
+    // of the module
+    Connect { module: String, trap: String },
+    Return,
+    ReturnValue,
+    // End of a FUNC that didn't execute RETURN
+    MissingReturn,
+    // Leave the ERROR handler for the start or the end of the statement
+    // that raised the error
+    Retry,
+    TryNext,
+    // Raise the error number on the stack
+    Raise,
+    // Pass the error being handled on to the caller
+    Reraise,
+}
+
+/// Compiled routine
+#[derive(Debug, Clone)]
+pub struct Code {
+    pub name: String,
+    pub module: String,
+    pub func: bool,
+    // TRAP routine, only called for interrupts
+    pub trap: bool,
+    pub local: bool,
+    // Routine of a system module
+    pub system: bool,
+    // Parameters, to check the arguments of late bound calls
+    pub params: Vec<Param>,
+    // Initial values of the argument, data and temporary slots of a frame
+    pub locals: Vec<Variable>,
+    // Names of the argument and data slots
+    pub data: Vec<DataName>,
+    pub instrs: Vec<Instr>,
+    // Source location of each instruction, for runtime errors
+    pub spans: Vec<Span>,
+    // Start of the ERROR handler
+    pub handler: Option<usize>,
+    // Instructions of each statement, inner statements before the ones
+    // containing them
+    pub statements: Vec<(usize, usize)>,
+}
+
+/// Data declared for a slot, to find data by name while debugging or
+/// while the program runs
+#[derive(Debug, Clone)]
+pub struct DataName {
+    pub name: String,
+    pub data_type: String,
+    // Module of global data, empty for the data of a routine or the system
+    pub module: String,
+    // Data of a system module
+    pub system: bool,
+    pub storage: Storage,
+    pub local: bool,
+}
+
+impl DataName {
+    fn new(module: &str, decl: &DataDecl) -> DataName {
+        DataName {
+            name: decl.name.clone(),
+            data_type: decl.data_type.clone(),
+            module: String::from(module),
+            system: false,
+            storage: decl.storage,
+            local: decl.local,
+        }
+    }
+
+    /// How near global data is to the routines of `module`: the data of the
+    /// module first, then global data of other modules and then the data of
+    /// the system. `None` when they can't see it.
+    pub fn rank(&self, module: &str) -> Option<u8> {
+        match self {
+            data if data.module.eq_ignore_ascii_case(module) => Some(0),
+            data if data.module.is_empty() || (data.system && !data.local) => Some(2),
+            data if !data.local => Some(1),
+            _ => None,
+        }
+    }
+}
+
+/// Names of the global data slots of the modules, after the system data
+pub fn global_names(modules: &[Module], system: &[DataDecl]) -> Rc<Vec<DataName>> {
+    let system = system.iter().map(|decl| DataName::new("", decl));
+    let module_data = modules.iter().flat_map(|module| module.variables.iter()
+        .map(move |decl| DataName { system: module.system, ..DataName::new(&module.name, decl) }));
+    Rc::new(system.chain(module_data).collect())
+}
+
+/// Compiled program, routines are numbered in module order
+#[derive(Debug, Clone)]
+pub struct Bytecode {
+    // Shared with the frames running them, so the modules of a running
+    // program can be compiled again
+    pub routines: Vec<Rc<Code>>,
+    // Names of the global data slots, lent to the instructions that access
+    // data by name
+    pub globals: Rc<Vec<DataName>>,
+}
+
+impl Bytecode {
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.routines.iter().position(|code| code.name.eq_ignore_ascii_case(name))
+    }
+}
+
+impl Code {
+    /// First instruction of the outermost statement that starts on a line
+    pub fn statement_at(&self, line: usize) -> Option<usize> {
+        self.statements.iter()
+            .filter(|(start, end)| start < end && self.spans.get(*start).is_some_and(|span| span.line == line))
+            .map(|(start, _)| *start)
+            .min()
+    }
+
+    pub fn starts_statement(&self, pc: usize) -> bool {
+        self.statements.iter().any(|(start, end)| *start == pc && start < end)
+    }
+}
+
+// ------------------ Compiler -----------------------/
+
+/// Compile a resolved program to bytecode for the VM
+pub fn compile(program: &Program) -> Result<Bytecode, RuntimeError> {
+    compile_modules(&program.modules, &program.system, &program.host_routines)
+}
+
+/// Compile resolved modules, like the modules of a program after modules
+/// were loaded while it runs
+pub fn compile_modules(modules: &[Module], system: &[DataDecl], host_routines: &[HostRoutine]) -> Result<Bytecode, RuntimeError> {
+    // Index of the first routine of each module
+    let mut offsets = Vec::new();
+    let mut count = 0;
+    for module in modules.iter() {
+        offsets.push(count);
+        count += module.routines.len();
+    }
+
+    let globals = global_names(modules, system);
+    let mut routines = Vec::new();
+    for (module, routine) in modules.iter().flat_map(|module| module.routines.iter().map(move |routine| (module, routine))) {
+        let mut compiler = Compiler {
+            modules,
+            routine,
+            globals: &globals,
+            host_routines,
+            offsets: &offsets,
+            code: Code {
+                name: routine.name.clone(),
+                module: module.name.clone(),
+                func: routine.return_type.is_some(),
+                trap: routine.trap,
+                local: routine.local,
+                system: module.system,
+                params: routine.arguments.clone(),
+                locals: initial_locals(routine),
+                data: routine_data(routine).map(|decl| DataName::new("", decl)).collect(),
+                instrs: Vec::new(),
+                spans: Vec::new(),
+                handler: None,
+                statements: Vec::new(),
+            },
+            span: routine.span,
+        };
+        let end = if routine.return_type.is_some() { Instr::MissingReturn } else { Instr::Return };
+        compiler.body(&routine.statements)?;
+        compiler.emit(end.clone());
+        if let Some(handler) = &routine.handler {
+            compiler.code.handler = Some(compiler.code.instrs.len());
+            compiler.body(handler)?;
+            compiler.emit(end);
+        }
+        routines.push(Rc::new(compiler.code));
+    }
+
+    Ok(Bytecode { routines, globals })
+}
+
+/// Argument a conditional argument like `\Speed?nSpeed` passes once the
+/// optional parameter of the calling routine it names is present: the
+/// value of that parameter, or nothing for a switch
+fn pass_conditional(arg: &Argument, caller: &Routine) -> Argument {
+    let switch = match &arg.value {
+        Some(Node::Var(slot)) => caller.arguments.get(*slot).is_some_and(|param| param.decl.data_type.eq_ignore_ascii_case("switch")),
+        _ => false,
+    };
+    Argument {
+        name: arg.name.clone(),
+        value: if switch { None } else { arg.value.clone() },
+        span: arg.span,
+        conditional: false,
+    }
+}
+
+/// Declarations of the argument and data slots of a routine
+fn routine_data(routine: &Routine) -> impl Iterator<Item = &DataDecl> {
+    routine.arguments.iter().map(|param| &param.decl).chain(routine.variables.iter())
+}
+
+fn initial_locals(routine: &Routine) -> Vec<Variable> {
+    routine_data(routine).map(|decl| decl.value.clone()).collect()
+}
+
+struct Compiler<'a> {
+    modules: &'a [Module],
+    routine: &'a Routine,
+    globals: &'a [DataName],
+    host_routines: &'a [HostRoutine],
+    offsets: &'a [usize],
+    code: Code,
+    // Location of the statement being compiled
+    span: Span,
+}
+
+impl<'a> Compiler<'a> {
+    fn emit(&mut self, instr: Instr) -> usize {
+        self.code.instrs.push(instr);
+        self.code.spans.push(self.span);
+        self.code.instrs.len() - 1
+    }
+
+    /// Point the jump at `at` to the next instruction
+    fn patch(&mut self, at: usize) {
+        let here = self.code.instrs.len();
+        match &mut self.code.instrs[at] {
+            Instr::Jump(target)
+            | Instr::JumpIfFalse(target)
+            | Instr::JumpIfTrue(target)
+            | Instr::ForCheck { exit: target, .. } => *target = here,
+            _ => (),
+        }
+    }
+
+    /// Operand node of the routine
+    fn node(&self, id: NodeId) -> &'a Node {
+        &self.routine.nodes[id]
+    }
+
+    /// Frame slot for a value that isn't visible in the source
+    fn temporary(&mut self, value: Variable) -> usize {
+        self.code.locals.push(value);
+        self.code.locals.len() - 1
+    }
+
+    fn body(&mut self, body: &[Statement]) -> Result<(), RuntimeError> {
+        let outer = self.span;
+        for statement in body {
+            self.span = statement.span;
+            let start = self.code.instrs.len();
+            self.statement(&statement.node).map_err(|err| err.at(statement.span))?;
+            self.code.statements.push((start, self.code.instrs.len()));
+        }
+        self.span = outer;
+        Ok(())
+    }
+
+    fn statement(&mut self, node: &Node) -> Result<(), RuntimeError> {
+        match node {
+            Node::Assign { lhs, rhs } => {
+                self.expr(self.node(*rhs))?;
+                self.store(self.node(*lhs))?;
+            },
+            Node::Print { text, arg } => {
+                self.expr(self.node(*text))?;
+                if let Some((_, node)) = arg {
+                    self.expr(self.node(*node))?;
+                }
+                self.emit(Instr::Print(arg.as_ref().map(|(kind, _)| *kind)));
+            },
+            Node::If { branches, otherwise } => {
+                let mut ends = Vec::new();
+                for (condition, body) in branches {
+                    self.expr(condition)?;
+                    let next = self.emit(Instr::JumpIfFalse(0));
+                    self.body(body)?;
+                    ends.push(self.emit(Instr::Jump(0)));
+                    self.patch(next);
+                }
+                self.body(otherwise)?;
+                for end in ends {
+                    self.patch(end);
+                }
+            },
+            Node::Test { value, cases, default } => {
+                // The value stays on the stack until a case matches
+                self.expr(self.node(*value))?;
+                let mut ends = Vec::new();
+                for (values, body) in cases {
+                    let mut matches = Vec::new();
+                    for case in values {
+                        self.emit(Instr::Dup);
+                        self.expr(case)?;
+                        self.emit(Instr::BinOp(Operator::Equal));
+                        matches.push(self.emit(Instr::JumpIfTrue(0)));
+                    }
+                    let next = self.emit(Instr::Jump(0));
+                    for at in matches {
+                        self.patch(at);
+                    }
+                    self.emit(Instr::Pop);
+                    self.body(body)?;
+                    ends.push(self.emit(Instr::Jump(0)));
+                    self.patch(next);
+                }
+                self.emit(Instr::Pop);
+                self.body(default)?;
+                for end in ends {
+                    self.patch(end);
+                }
+            },
+            Node::While { condition, body } => {
+                let start = self.code.instrs.len();
+                self.expr(self.node(*condition))?;
+                let exit = self.emit(Instr::JumpIfFalse(0));
+                self.body(body)?;
+                self.emit(Instr::Jump(start));
+                self.patch(exit);
+            },
+            Node::For { var, from, to, step, body } => {
+                let counter = match self.node(*var) {
+                    Node::Var(slot) => *slot,
+                    node => return Err(unsupported(format!("Invalid FOR loop variable {:?}", node))),
+                };
+                // The bounds are evaluated once, before the first iteration
+                let end = self.temporary(Variable::Num(0.0));
+                let step_slot = self.temporary(Variable::Num(0.0));
+                self.expr(self.node(*from))?;
+                self.emit(Instr::Store(counter));
+                self.expr(self.node(*to))?;
+                self.emit(Instr::Store(end));
+                match step {
+                    Some(step) => {
+                        self.expr(self.node(*step))?;
+                        self.emit(Instr::Store(step_slot));
+                    },
+                    None => {
+                        self.emit(Instr::DefaultStep { counter, end, step: step_slot });
+                    },
+                }
+
+                let start = self.emit(Instr::ForCheck { counter, end, step: step_slot, exit: 0 });
+                self.body(body)?;
+                self.emit(Instr::Load(counter));
+                self.emit(Instr::Load(step_slot));
+                self.emit(Instr::BinOp(Operator::Add));
+                self.emit(Instr::Store(counter));
+                self.emit(Instr::Jump(start));
+                self.patch(start);
+            },
+            Node::ProcCall { name, args, span, target } => self.call(*target, name, args, *span)?,
+            Node::LateCall { name, args, span } => self.late_call(self.node(*name), args, *span)?,
+            Node::Connect { interrupt, trap, span, target } => {
+                let module = match target {
+                    Some(Callee::Routine(module, _)) => self.modules.get(*module).map(|module| module.name.clone()),
+                    _ => None,
+                };
+                let module = module.ok_or_else(|| RuntimeError::UnknownRoutine { name: trap.clone(), span: *span })?;
+                let interrupt = self.node(*interrupt);
+                self.expr(interrupt)?;
+                self.emit(Instr::Connect { module, trap: trap.clone() });
+                self.store(interrupt)?;
+            },
+            Node::Return(value) => {
+                match value {
+                    Some(node) => {
+                        self.expr(self.node(*node))?;
+                        self.emit(Instr::ReturnValue);
+                    },
+                    None => {
+                        self.emit(Instr::Return);
+                    },
+                }
+            },
+            Node::Retry => {
+                self.emit(Instr::Retry);
+            },
+            Node::TryNext => {
+                self.emit(Instr::TryNext);
+            },
+            Node::Raise(errno) => {
+                match errno {
+                    Some(node) => {
+                        self.expr(self.node(*node))?;
+                        self.emit(Instr::Raise);
+                    },
+                    None => {
+                        self.emit(Instr::Reraise);
+                    },
+                }
+            },
+            node => {
+                self.expr(node)?;
+                self.emit(Instr::Pop);
+            },
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, node: &Node) -> Result<(), RuntimeError> {
+        match node {
+            Node::BinOp { op, lhs, rhs } => {
+                self.expr(self.node(*lhs))?;
+                self.expr(self.node(*rhs))?;
+                self.emit(Instr::BinOp(*op));
+            },
+            Node::OpNeg(node) => {
+                self.expr(self.node(*node))?;
+                self.emit(Instr::Neg);
+            },
+            Node::OpNot(node) => {
+                self.expr(self.node(*node))?;
+                self.emit(Instr::Not);
+            },
+            Node::Value(var) => {
+                self.emit(Instr::Push(var.clone()));
+            },
+            Node::Aggregate(values) => {
+                for value in values {
+                    self.expr(value)?;
+                }
+                self.emit(Instr::Aggregate(values.len()));
+            },
+            Node::Var(slot) => {
+                self.emit(Instr::Load(*slot));
+            },
+            Node::Global(slot) => {
+                self.emit(Instr::LoadGlobal(*slot));
+            },
+            Node::Index { base, indices } => {
+                let (slot, global) = array_slot(self.node(*base))?;
+                for index in indices {
+                    self.expr(index)?;
+                }
+                self.emit(Instr::LoadElement { slot, global, dims: indices.len() });
+            },
+            Node::Present(slot) => {
+                self.emit(Instr::Present(*slot));
+            },
+            Node::IsStorage(slot, storage) => {
+                self.emit(Instr::IsStorage(*slot, *storage));
+            },
+            Node::Errno => {
+                self.emit(Instr::Errno);
+            },
+            Node::Id(name, span) => {
+                return Err(RuntimeError::Unsupported { message: format!("Unresolved id {}", name), span: *span });
+            },
+            Node::FuncCall { name, args, span, target } => self.call(*target, name, args, *span)?,
+            node => return Err(unsupported(format!("Statement {:?} used as expression", node))),
+        }
+        Ok(())
+    }
+
+    /// Pop the top of the stack into the data object `node`
+    fn store(&mut self, node: &Node) -> Result<(), RuntimeError> {
+        match node {
+            Node::Var(slot) => self.emit(Instr::Store(*slot)),
+            Node::Global(slot) => self.emit(Instr::StoreGlobal(*slot)),
+            Node::Index { base, indices } => {
+                let (slot, global) = array_slot(self.node(*base))?;
+                for index in indices {
+                    self.expr(index)?;
+                }
+                self.emit(Instr::StoreElement { slot, global, dims: indices.len() })
+            },
+            _ => return Err(RuntimeError::type_mismatch(String::from("Can only assign to variable"))),
+        };
+        Ok(())
+    }
+
+    /// Push the arguments, call the routine and copy VAR, PERS and INOUT
+    /// arguments back to the caller's data
+    fn call(&mut self, target: Option<Callee>, name: &str, args: &[Argument], span: Span) -> Result<(), RuntimeError> {
+        if let Some(idx) = args.iter().position(|arg| arg.conditional) {
+            return self.conditional(args, idx, |compiler, args| compiler.call(target, name, args, span));
+        }
+        let (modules, host_routines) = (self.modules, self.host_routines);
+        let callee = target.and_then(|callee| {
+            let (params, routine_name): (&[Param], &str) = match callee {
+                Callee::Routine(module, idx) => {
+                    let routine = modules.get(module)?.routines.get(idx)?;
+                    (&routine.arguments, &routine.name)
+                },
+                Callee::Builtin(idx) => {
+                    let builtin = builtins::builtins().get(idx)?;
+                    (&builtin.params, builtin.name)
+                },
+                Callee::Host(idx) => {
+                    let routine = host_routines.get(idx)?;
+                    (&routine.params, &routine.name)
+                },
+            };
+            Some((callee, params, routine_name))
+        });
+        let (callee, params, routine_name) = match callee {
+            Some(found) => found,
+            None => return Err(RuntimeError::UnknownRoutine { name: String::from(name), span }),
+        };
+
+        let mut slots = Vec::new();
+        let mut outputs = Vec::new();
+        let mut stores = Vec::new();
+        let mut passed = Vec::new();
+        let mut names = vec![None; params.len()];
+        for (slot, arg) in interpreter::param_slots(params, routine_name, args)?.into_iter().zip(args) {
+            slots.push(slot);
+            let node = match &arg.value {
+                Some(node) => node,
+                // Switches are passed without a value
+                None => {
+                    self.emit(Instr::Push(Variable::Void));
+                    passed.push(Passed::Data(Storage::Var));
+                    continue;
+                },
+            };
+            self.expr(node)?;
+            passed.push(Passed::from(node, self.routine, self.globals));
+            names[slot] = interpreter::data_name(node, self.routine, self.globals);
+            if params[slot].mode != ParamMode::In {
+                outputs.push(slot);
+                stores.push(node);
+            }
+        }
+
+        let statement = self.span;
+        self.span = span;
+        match callee {
+            Callee::Routine(module, idx) => self.emit(Instr::Call { routine: self.offsets[module] + idx, args: slots, outputs, passed }),
+            Callee::Builtin(builtin) => {
+                if !builtins::builtins().get(builtin).is_some_and(builtins::logs_move) {
+                    names.clear();
+                }
+                self.emit(Instr::CallBuiltin { builtin, args: slots, outputs, names })
+            },
+            Callee::Host(routine) => self.emit(Instr::CallHost { routine, args: slots, outputs }),
+        };
+        self.span = statement;
+
+        for node in stores {
+            self.store(node)?;
+        }
+        Ok(())
+    }
+
+    /// Call with the conditional argument `idx`, compiled once with and once
+    /// without it, the one run chosen by whether the optional parameter it
+    /// names is present. Each further conditional argument branches again.
+    fn conditional(&mut self, args: &[Argument], idx: usize,
+        mut compile: impl FnMut(&mut Self, &[Argument]) -> Result<(), RuntimeError>) -> Result<(), RuntimeError> {
+        let slot = match args[idx].value {
+            Some(Node::Var(slot)) => slot,
+            _ => return Err(RuntimeError::Unsupported { message: String::from("Conditional argument of an unknown parameter"), span: args[idx].span }),
+        };
+        let mut passed = args.to_vec();
+        passed[idx] = pass_conditional(&args[idx], self.routine);
+        self.emit(Instr::Present(slot));
+        let skip = self.emit(Instr::JumpIfFalse(0));
+        compile(self, &passed)?;
+        let end = self.emit(Instr::Jump(0));
+        self.patch(skip);
+        passed.remove(idx);
+        compile(self, &passed)?;
+        self.patch(end);
+        Ok(())
+    }
+
+    /// Push the name and the arguments of a late bound call. Which data
+    /// arguments are outputs is known once the procedure is, so each one
+    /// is stored only when the call says so.
+    fn late_call(&mut self, name: &Node, args: &[Argument], span: Span) -> Result<(), RuntimeError> {
+        if let Some(idx) = args.iter().position(|arg| arg.conditional) {
+            return self.conditional(args, idx, |compiler, args| compiler.late_call(name, args, span));
+        }
+        self.expr(name)?;
+        for arg in args {
+            match &arg.value {
+                Some(node) => self.expr(node)?,
+                // Switches are passed without a value
+                None => {
+                    self.emit(Instr::Push(Variable::Void));
+                },
+            }
+        }
+
+        let statement = self.span;
+        self.span = span;
+        let late: Vec<LateArg> = args.iter().map(|arg| LateArg::from(arg, self.routine, self.globals)).collect();
+        let data: Vec<&Node> = args.iter().zip(late.iter()).filter(|(_, late)| late.data).filter_map(|(arg, _)| arg.value.as_ref()).collect();
+        self.emit(Instr::LateCall { args: late });
+        self.span = statement;
+
+        for node in data {
+            let skip = self.emit(Instr::JumpIfFalse(0));
+            self.store(node)?;
+            let end = self.emit(Instr::Jump(0));
+            self.patch(skip);
+            self.emit(Instr::Pop);
+            self.patch(end);
+        }
+        Ok(())
+    }
+}
+
+/// Slot of the array of an element and whether it's global data
+fn array_slot(base: &Node) -> Result<(usize, bool), RuntimeError> {
+    match base {
+        Node::Var(slot) => Ok((*slot, false)),
+        Node::Global(slot) => Ok((*slot, true)),
+        _ => Err(RuntimeError::type_mismatch(String::from("Can only index data"))),
+    }
+}
+
+fn unsupported(message: String) -> RuntimeError {
+    RuntimeError::Unsupported { message, span: Span::default() }
+}
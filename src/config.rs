@@ -0,0 +1,263 @@
+use std::path::Path;
+
+use crate::host::IoBoard;
+use crate::lexer;
+use crate::variable::SignalKind;
+
+// ------------------ Configuration file -----------------------/
+
+/// Instance of a configuration type, like a signal of EIO_SIGNAL
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instance {
+    // Attributes in the order of the file, a flag without a value has an
+    // empty one
+    pub attributes: Vec<(String, String)>,
+    // Line the instance starts on
+    pub line: usize,
+}
+
+impl Instance {
+    /// Value of an attribute, by its name without the dash
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|(attribute, _)| attribute.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Configuration file of the controller, like EIO.cfg or SYS.cfg, with the
+/// instances of each type of its domain
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    // Domain of the header, like EIO of `EIO:CFG_1.0:6:1::`
+    pub domain: String,
+    pub types: Vec<(String, Vec<Instance>)>,
+}
+
+impl Config {
+    /// Read a configuration as RobotStudio and the controller save it:
+    ///
+    /// ```text
+    /// EIO:CFG_1.0:6:1::
+    /// #
+    /// EIO_SIGNAL:
+    ///
+    ///       -Name "diPartReady" -SignalType "DI" -Device "d652"\
+    ///       -DeviceMap "0"
+    /// ```
+    ///
+    /// An instance is a line of attributes, a backslash at the end continues
+    /// it on the next line.
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let mut lines = text.lines().enumerate();
+        let header = lines.find(|(_, line)| !line.trim().is_empty()).map_or("", |(_, line)| line.trim());
+        let domain = match header.split_once(':') {
+            Some((domain, version)) if version.starts_with("CFG_") => domain,
+            _ => return Err(String::from("1: Expected a header like EIO:CFG_1.0:6:1::")),
+        };
+        let mut config = Config { domain: String::from(domain), types: Vec::new() };
+
+        let mut instance: Option<(usize, String)> = None;
+        for (idx, line) in lines {
+            let line = line.trim();
+            if let Some((first, mut text)) = instance.take() {
+                text.push(' ');
+                text.push_str(line);
+                instance = Some((first, text));
+            } else if line.is_empty() || line.starts_with('#') {
+                continue;
+            } else if line.starts_with('-') {
+                instance = Some((idx + 1, String::from(line)));
+            } else if let Some(name) = line.strip_suffix(':') {
+                config.types.push((String::from(name.trim()), Vec::new()));
+                continue;
+            } else {
+                return Err(format!("{}: Expected a type or an instance", idx + 1));
+            }
+
+            let (first, text) = instance.take().unwrap_or_default();
+            if let Some(text) = text.strip_suffix('\\') {
+                instance = Some((first, String::from(text)));
+                continue;
+            }
+            let attributes = attributes(&text).map_err(|err| format!("{}: {}", first, err))?;
+            match config.types.last_mut() {
+                Some((_, instances)) => instances.push(Instance { attributes, line: first }),
+                None => return Err(format!("{}: Instance outside of a type", first)),
+            }
+        }
+        if let Some((first, _)) = instance {
+            return Err(format!("{}: Instance continues past the end", first));
+        }
+        Ok(config)
+    }
+
+    /// Read a configuration file, Latin-1 unless it's UTF-8
+    pub fn from_path(path: &Path) -> Result<Config, String> {
+        let text = lexer::read_source(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Config::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// Instances of a type, none when the file doesn't have it
+    pub fn instances(&self, type_name: &str) -> &[Instance] {
+        self.types.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(type_name))
+            .map_or(&[], |(_, instances)| instances)
+    }
+}
+
+/// Attributes of an instance, like `-Name "doGripper" -Access "All"`
+fn attributes(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut attributes = Vec::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.next() {
+            None => return Ok(attributes),
+            Some('-') => (),
+            Some(c) => return Err(format!("Expected an attribute at '{}'", c)),
+        }
+        let name: String = std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace())).collect();
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut value = String::new();
+        match chars.peek().copied() {
+            Some('"') => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => value.push(c),
+                        None => return Err(format!("Unterminated value of -{}", name)),
+                    }
+                }
+            },
+            // The next attribute, unless it's a negative number
+            Some('-') if !chars.clone().nth(1).is_some_and(|c| c.is_ascii_digit() || c == '.') => (),
+            Some(_) => value = std::iter::from_fn(|| chars.next_if(|c| !c.is_whitespace())).collect(),
+            None => (),
+        }
+        attributes.push((name, value));
+    }
+}
+
+// ------------------ Signals -----------------------/
+
+/// Signal of the I/O configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignalConfig {
+    pub name: String,
+    pub kind: SignalKind,
+    // Width of a group signal, 1 for the others
+    pub bits: u32,
+}
+
+/// Signals of an EIO.cfg, of EIO_SIGNAL or EIO_USER_SIGNAL of older
+/// controllers. A group is as wide as the bits of its device map, like
+/// `"0-7"`, or 32 bits without one.
+pub fn signals(config: &Config) -> Result<Vec<SignalConfig>, String> {
+    let mut signals = Vec::new();
+    for instance in config.instances("EIO_SIGNAL").iter().chain(config.instances("EIO_USER_SIGNAL")) {
+        let error = |message: String| format!("{}: {}", instance.line, message);
+        let name = instance.get("Name").filter(|name| !name.is_empty()).ok_or_else(|| error(String::from("Signal without a name")))?;
+        let kind = match instance.get("SignalType").map(str::to_ascii_uppercase).as_deref() {
+            Some("DI") => SignalKind::DigitalInput,
+            Some("DO") => SignalKind::DigitalOutput,
+            Some("AI") => SignalKind::AnalogInput,
+            Some("AO") => SignalKind::AnalogOutput,
+            Some("GI") => SignalKind::GroupInput,
+            Some("GO") => SignalKind::GroupOutput,
+            Some(kind) => return Err(error(format!("Unknown signal type {} of {}", kind, name))),
+            None => return Err(error(format!("Signal {} without a type", name))),
+        };
+        let bits = match kind {
+            SignalKind::GroupInput | SignalKind::GroupOutput => match instance.get("DeviceMap").or_else(|| instance.get("UnitMap")) {
+                Some(map) => map_bits(map).ok_or_else(|| error(format!("Invalid device map {} of {}", map, name)))?,
+                None => 32,
+            },
+            _ => 1,
+        };
+        signals.push(SignalConfig { name: String::from(name), kind, bits });
+    }
+    Ok(signals)
+}
+
+/// Number of bits of a device map, like `"0-7, 16"`
+fn map_bits(map: &str) -> Option<u32> {
+    let mut bits = 0;
+    for range in map.split(',') {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        let (first, last): (u32, u32) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+        bits += first.max(last) - first.min(last) + 1;
+    }
+    Some(bits).filter(|bits| (1..=32).contains(bits))
+}
+
+/// I/O board with the signals, all at 0
+pub fn io_board(signals: &[SignalConfig]) -> IoBoard {
+    let mut io = IoBoard::new();
+    for signal in signals {
+        io.define_group(&signal.name, signal.kind, signal.bits);
+    }
+    io
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parser, resolver};
+
+    const EIO: &str = "EIO:CFG_1.0:6:1::
+#
+EIO_DEVICE:
+
+      -Name \"d652\" -VendorName \"ABB Robotics\" -Simulated
+
+#
+EIO_SIGNAL:
+
+      -Name \"diPartReady\" -SignalType \"DI\" -Device \"d652\" -DeviceMap \"0\"
+
+      -Name \"goProgram\" -SignalType \"GO\" -Device \"d652\"\\
+      -DeviceMap \"0-3, 8-11\"
+
+      -Name \"aoSpeed\" -SignalType \"AO\" -MinLog -10 -MaxLog 10
+";
+
+    #[test]
+    fn reads_signals() {
+        let config = Config::parse(EIO).unwrap();
+        assert_eq!(config.domain, "EIO");
+        assert_eq!(config.instances("EIO_DEVICE")[0].attributes, vec![
+            (String::from("Name"), String::from("d652")),
+            (String::from("VendorName"), String::from("ABB Robotics")),
+            (String::from("Simulated"), String::new()),
+        ]);
+        let speed = &config.instances("eio_signal")[2];
+        assert_eq!((speed.get("minlog"), speed.get("MaxLog"), speed.line), (Some("-10"), Some("10"), 15));
+        assert_eq!(signals(&config).unwrap(), vec![
+            SignalConfig { name: String::from("diPartReady"), kind: SignalKind::DigitalInput, bits: 1 },
+            SignalConfig { name: String::from("goProgram"), kind: SignalKind::GroupOutput, bits: 8 },
+            SignalConfig { name: String::from("aoSpeed"), kind: SignalKind::AnalogOutput, bits: 1 },
+        ]);
+
+        let io = io_board(&signals(&config).unwrap());
+        assert!(io.write("goProgram", 255.0).is_ok());
+        assert!(io.write("goProgram", 256.0).is_err());
+        let mut program = parser::parse_tokens(lexer::parse("MODULE Cell
+    PROC main()
+        WaitDI diPartReady, 1;
+        SetGO goProgram, 3;
+        SetDO doGripper, 1;
+    ENDPROC
+ENDMODULE")).unwrap();
+        io.declare(&mut program);
+        let diagnostics = resolver::resolve(&mut program, &resolver::ResolveOptions::default()).unwrap_err();
+        assert_eq!(diagnostics.iter().map(|diagnostic| diagnostic.message.as_str()).collect::<Vec<_>>(), vec!["Unknown id 'doGripper'"]);
+
+        assert_eq!(Config::parse("EIO_SIGNAL:").unwrap_err(), "1: Expected a header like EIO:CFG_1.0:6:1::");
+        assert_eq!(Config::parse("EIO:CFG_1.0:6:1::\n      -Name \"di1\"").unwrap_err(), "2: Instance outside of a type");
+        assert_eq!(Config::parse("EIO:CFG_1.0:6:1::\nEIO_SIGNAL:\n      -Name \"di1\"\\").unwrap_err(), "3: Instance continues past the end");
+        let config = Config::parse("EIO:CFG_1.0:6:1::\nEIO_SIGNAL:\n      -Name \"di1\" -SignalType \"XI\"").unwrap();
+        assert_eq!(signals(&config).unwrap_err(), "3: Unknown signal type XI of di1");
+    }
+}
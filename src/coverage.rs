@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+
+use crate::host::Observer;
+use crate::lexer::Span;
+use crate::parser::{Program, Statement};
+
+// ------------------ Coverage -----------------------/
+
+/// Counts how often each statement runs. Observe one or more runs with it,
+/// then report on the statements of the program.
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    // Executions by module and the start of the statement in its source,
+    // modules of separate files have statements at the same offsets
+    counts: HashMap<(String, usize), u64>,
+    // Calls by module and routine name
+    calls: HashMap<(String, String), u64>,
+    // Modules of the routines being executed, the innermost last
+    modules: Vec<String>,
+}
+
+impl Observer for Coverage {
+    fn statement(&mut self, span: Span) {
+        let module = self.modules.last().cloned().unwrap_or_default();
+        *self.counts.entry((module, span.start)).or_default() += 1;
+    }
+
+    fn enter_routine(&mut self, module: &str, routine: &str) {
+        *self.calls.entry((String::from(module), String::from(routine))).or_default() += 1;
+        self.modules.push(String::from(module));
+    }
+
+    fn exit_routine(&mut self, _module: &str, _routine: &str) {
+        self.modules.pop();
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    /// Executions of every statement of the program, ERROR handlers included
+    pub fn report(&self, program: &Program) -> CoverageReport {
+        let mut routines = Vec::new();
+        for module in program.modules.iter() {
+            for routine in module.routines.iter() {
+                let mut statements = Vec::new();
+                self.count(&module.name, &routine.statements, &mut statements);
+                if let Some(handler) = &routine.handler {
+                    self.count(&module.name, handler, &mut statements);
+                }
+                let key = (module.name.clone(), routine.name.clone());
+                let calls = self.calls.get(&key).copied().unwrap_or(0);
+                routines.push(RoutineCoverage { module: key.0, routine: key.1, calls, statements });
+            }
+        }
+        CoverageReport { routines }
+    }
+
+    fn count(&self, module: &str, body: &[Statement], statements: &mut Vec<(Span, u64)>) {
+        for statement in body {
+            let count = self.counts.get(&(String::from(module), statement.span.start)).copied().unwrap_or(0);
+            statements.push((statement.span, count));
+            for inner in statement.node.bodies() {
+                self.count(module, inner, statements);
+            }
+        }
+    }
+}
+
+/// Statements of a routine, with the number of times each one ran
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutineCoverage {
+    pub module: String,
+    pub routine: String,
+    pub calls: u64,
+    pub statements: Vec<(Span, u64)>,
+}
+
+impl RoutineCoverage {
+    pub fn executed(&self) -> usize {
+        self.statements.iter().filter(|(_, count)| *count > 0).count()
+    }
+
+    /// Lines of the statements that never ran
+    pub fn missed(&self) -> Vec<usize> {
+        let mut lines: Vec<usize> = self.statements.iter().filter(|(_, count)| *count == 0).map(|(span, _)| span.line).collect();
+        lines.dedup();
+        lines
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageReport {
+    pub routines: Vec<RoutineCoverage>,
+}
+
+impl CoverageReport {
+    /// Statements executed per routine and in total, with the lines missed
+    pub fn summary(&self) -> String {
+        let mut text = String::new();
+        for routine in self.routines.iter() {
+            text.push_str(&format!("{}.{}: {}\n", routine.module, routine.routine, ratio(routine.executed(), routine.statements.len())));
+            let missed: Vec<String> = routine.missed().iter().map(usize::to_string).collect();
+            if !missed.is_empty() {
+                text.push_str(&format!("    missed lines {}\n", missed.join(", ")));
+            }
+        }
+        let executed = self.routines.iter().map(RoutineCoverage::executed).sum();
+        let total = self.routines.iter().map(|routine| routine.statements.len()).sum();
+        text.push_str(&format!("Total: {}\n", ratio(executed, total)));
+        text
+    }
+
+    /// Line coverage in the LCOV tracefile format, a source file per module
+    pub fn lcov(&self) -> String {
+        let mut text = String::from("TN:\n");
+        let mut modules: Vec<&str> = self.routines.iter().map(|routine| routine.module.as_str()).collect();
+        modules.dedup();
+        for module in modules {
+            text.push_str(&format!("SF:{}\n", module));
+            let routines: Vec<&RoutineCoverage> = self.routines.iter().filter(|routine| routine.module == module).collect();
+            for routine in routines.iter() {
+                let line = routine.statements.first().map_or(0, |(span, _)| span.line);
+                text.push_str(&format!("FN:{},{}\n", line, routine.routine));
+            }
+            for routine in routines.iter() {
+                text.push_str(&format!("FNDA:{},{}\n", routine.calls, routine.routine));
+            }
+            let called = routines.iter().filter(|routine| routine.calls > 0).count();
+            text.push_str(&format!("FNF:{}\nFNH:{}\n", routines.len(), called));
+
+            // A line runs as often as its first statement
+            let mut lines: Vec<(usize, u64)> = Vec::new();
+            for (span, count) in routines.iter().flat_map(|routine| routine.statements.iter()) {
+                if !lines.iter().any(|(line, _)| *line == span.line) {
+                    lines.push((span.line, *count));
+                }
+            }
+            lines.sort();
+            for (line, count) in lines.iter() {
+                text.push_str(&format!("DA:{},{}\n", line, count));
+            }
+            let hit = lines.iter().filter(|(_, count)| *count > 0).count();
+            text.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", lines.len(), hit));
+        }
+        text
+    }
+
+    /// Statements per routine as JSON
+    pub fn json(&self) -> String {
+        let routines: Vec<String> = self.routines.iter()
+            .map(|routine| {
+                let statements: Vec<String> = routine.statements.iter()
+                    .map(|(span, count)| format!("{{\"line\":{},\"column\":{},\"count\":{}}}", span.line, span.column, count))
+                    .collect();
+                format!("{{\"module\":\"{}\",\"routine\":\"{}\",\"executed\":{},\"total\":{},\"statements\":[{}]}}",
+                    routine.module, routine.routine, routine.executed(), routine.statements.len(), statements.join(","))
+            })
+            .collect();
+        format!("{{\"routines\":[{}]}}", routines.join(","))
+    }
+}
+
+fn ratio(executed: usize, total: usize) -> String {
+    let percent = if total == 0 { 100.0 } else { executed as f64 * 100.0 / total as f64 };
+    format!("{}/{} statements ({:.1}%)", executed, total, percent)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler;
+    use crate::host::Host;
+    use crate::interpreter::{self, InterpreterOptions};
+    use crate::lexer;
+    use crate::parser;
+    use crate::resolver::{self, ResolveOptions};
+    use crate::vm;
+
+    #[test]
+    fn reports_statements_never_run() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Cell
+    VAR num nParts := 0;
+    PROC rReject()
+        TPWrite \"Rejected\";
+    ENDPROC
+    PROC main()
+        FOR i FROM 1 TO 3 DO
+            IF i > 5 THEN
+                rReject;
+            ELSE
+                nParts := nParts + 1;
+            ENDIF
+        ENDFOR
+    ERROR
+        TRYNEXT;
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let bytecode = compiler::compile(&program).unwrap();
+
+        // Runs on both backends add up
+        let mut coverage = Coverage::new();
+        let mut output = Vec::new();
+        let mut host = Host::new(&mut output).with_observer(&mut coverage);
+        interpreter::run(&mut program, "main", &InterpreterOptions::default(), &mut host).into_result().unwrap();
+        let mut host = Host::new(&mut output).with_observer(&mut coverage);
+        vm::run(&mut program, &bytecode, "main", &InterpreterOptions::default(), &mut host).unwrap();
+
+        let report = coverage.report(&program);
+        assert_eq!(report.summary(), "\
+Cell.rReject: 0/1 statements (0.0%)
+    missed lines 5
+Cell.main: 3/5 statements (60.0%)
+    missed lines 10, 16
+Total: 3/6 statements (50.0%)
+");
+        assert_eq!(report.lcov(), "\
+TN:
+SF:Cell
+FN:5,rReject
+FN:8,main
+FNDA:0,rReject
+FNDA:2,main
+FNF:2
+FNH:1
+DA:5,0
+DA:8,2
+DA:9,6
+DA:10,0
+DA:12,6
+DA:16,0
+LF:6
+LH:3
+end_of_record
+");
+        assert_eq!(report.json(), concat!(
+            r#"{"routines":[{"module":"Cell","routine":"rReject","executed":0,"total":1,"statements":[{"line":5,"column":9,"count":0}]},"#,
+            r#"{"module":"Cell","routine":"main","executed":3,"total":5,"statements":[{"line":8,"column":9,"count":2},{"line":9,"column":13,"count":6},"#,
+            r#"{"line":10,"column":17,"count":0},{"line":12,"column":17,"count":6},{"line":16,"column":9,"count":0}]}]}"#));
+    }
+    #[test]
+    fn counts_statements_by_module() {
+        // Modules of separate files, with statements at the same offsets
+        let mut program = parser::Program::from_source("MODULE A\n    PROC rA()\n        rB;\n    ENDPROC\nENDMODULE").unwrap();
+        let other = parser::Program::from_source("MODULE B\n    PROC rB()\n        TPWrite \"B\";\n    ENDPROC\nENDMODULE").unwrap();
+        program.modules.extend(other.modules);
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let bytecode = compiler::compile(&program).unwrap();
+
+        let mut coverage = Coverage::new();
+        let mut output = Vec::new();
+        let mut host = Host::new(&mut output).with_observer(&mut coverage);
+        interpreter::run(&mut program, "rA", &InterpreterOptions::default(), &mut host).into_result().unwrap();
+        let mut host = Host::new(&mut output).with_observer(&mut coverage);
+        vm::run(&mut program, &bytecode, "rB", &InterpreterOptions::default(), &mut host).unwrap();
+
+        let report = coverage.report(&program);
+        let counts: Vec<_> = report.routines.iter().map(|routine| (routine.routine.as_str(), routine.statements[0].1)).collect();
+        assert_eq!(counts, vec![("rA", 1), ("rB", 2)]);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::compiler::Code;
+use crate::host::Host;
+use crate::interpreter::RuntimeError;
+use crate::lexer::Span;
+use crate::parser::Storage;
+use crate::variable::Variable;
+use crate::vm::{ProgramPointer, Status, Vm};
+
+// ------------------ Debugger -----------------------/
+
+/// Place execution stops before running the statement
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+    // Statements starting on a line of a module
+    Line { module: String, line: usize },
+    // First statement of a routine, each time it's called
+    Routine(String),
+}
+
+impl Breakpoint {
+    fn hit(&self, code: &Code, span: Span) -> bool {
+        match self {
+            Breakpoint::Line { module, line } => code.module.eq_ignore_ascii_case(module) && span.line == *line,
+            Breakpoint::Routine(name) => code.name.eq_ignore_ascii_case(name) && code.spans.first() == Some(&span),
+        }
+    }
+}
+
+/// Part of a data object: a component of a record, or an element of an
+/// array at its RAPID indices
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access<'p> {
+    Component(&'p str),
+    Element(&'p [usize]),
+}
+
+/// Steps through a program on the VM statement by statement. The host is
+/// lent to each step, like to `Vm::execute`, and the program pointer is
+/// moved on the VM. Every step stops at the breakpoints it passes, the
+/// program pointer tells where.
+pub struct Debugger<'a> {
+    pub vm: Vm<'a>,
+    breakpoints: Vec<Breakpoint>,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(vm: Vm<'a>) -> Debugger<'a> {
+        Debugger { vm, breakpoints: Vec::new() }
+    }
+
+    /// Add a breakpoint, on a line with a statement or a routine of the program
+    pub fn set_breakpoint(&mut self, breakpoint: Breakpoint) -> Result<(), RuntimeError> {
+        let routines = &self.vm.bytecode().routines;
+        match &breakpoint {
+            Breakpoint::Line { module, line } => {
+                let found = routines.iter().any(|code| code.module.eq_ignore_ascii_case(module) && code.statement_at(*line).is_some());
+                if !found {
+                    return Err(RuntimeError::Unsupported { message: format!("No statement of {} on line {}", module, line), span: Span::default() });
+                }
+            },
+            Breakpoint::Routine(name) => {
+                if !routines.iter().any(|code| code.name.eq_ignore_ascii_case(name)) {
+                    return Err(RuntimeError::UnknownRoutine { name: name.clone(), span: Span::default() });
+                }
+            },
+        }
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+        Ok(())
+    }
+
+    /// Remove a breakpoint, false when it wasn't set
+    pub fn clear_breakpoint(&mut self, breakpoint: &Breakpoint) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints.retain(|set| set != breakpoint);
+        self.breakpoints.len() != count
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Statement executed by the next step, none once the program finished
+    pub fn pp(&self) -> Option<ProgramPointer> {
+        self.vm.pp()
+    }
+
+    /// Value of data in scope of the routine the program is paused in, or
+    /// of a part of it
+    pub fn read(&self, name: &str, path: &[Access]) -> Result<Variable, RuntimeError> {
+        let (_, mut var) = self.vm.lookup(name).ok_or_else(|| unknown_name(name))?;
+        for access in path {
+            var = match access {
+                Access::Component(component) => var.component(component)?,
+                Access::Element(indices) => var.element(&to_indices(indices))?,
+            };
+        }
+        Ok(var.clone())
+    }
+
+    /// Assign to data in scope, or to a part of it, like an assignment in
+    /// the program would
+    pub fn write(&mut self, name: &str, path: &[Access], value: Variable) -> Result<(), RuntimeError> {
+        let (data, mut var) = self.vm.lookup_mut(name).ok_or_else(|| unknown_name(name))?;
+        if data.storage == Storage::Const {
+            return Err(RuntimeError::type_mismatch(format!("Cannot assign to CONST {}", data.name)));
+        }
+        for access in path {
+            var = match access {
+                Access::Component(component) => var.component_mut(component)?,
+                Access::Element(indices) => var.element_mut(&to_indices(indices))?,
+            };
+        }
+        var.set(value)
+    }
+
+    /// Execute the statement and stop at the next one, which is the first
+    /// statement of the routine it calls
+    pub fn step_into(&mut self, host: &mut Host) -> Result<Status, RuntimeError> {
+        self.vm.execute_until(host, Some(&mut |_, _, _| true))
+    }
+
+    /// Execute the statement and the routines it calls, then stop
+    pub fn step_over(&mut self, host: &mut Host) -> Result<Status, RuntimeError> {
+        let depth = self.vm.depth();
+        let breakpoints = &self.breakpoints;
+        self.vm.execute_until(host, Some(&mut |code, span, at| at <= depth || hit(breakpoints, code, span)))
+    }
+
+    /// Execute the rest of the routine and stop in the caller
+    pub fn step_out(&mut self, host: &mut Host) -> Result<Status, RuntimeError> {
+        let depth = self.vm.depth();
+        let breakpoints = &self.breakpoints;
+        self.vm.execute_until(host, Some(&mut |code, span, at| at < depth || hit(breakpoints, code, span)))
+    }
+
+    /// Run until the program finishes, waits or reaches a breakpoint
+    pub fn resume(&mut self, host: &mut Host) -> Result<Status, RuntimeError> {
+        let breakpoints = &self.breakpoints;
+        self.vm.execute_until(host, Some(&mut |code, span, _| hit(breakpoints, code, span)))
+    }
+}
+
+fn unknown_name(name: &str) -> RuntimeError {
+    RuntimeError::UnknownName { name: String::from(name), span: Span::default() }
+}
+
+fn to_indices(indices: &[usize]) -> Vec<f64> {
+    indices.iter().map(|index| *index as f64).collect()
+}
+
+fn hit(breakpoints: &[Breakpoint], code: &Code, span: Span) -> bool {
+    breakpoints.iter().any(|breakpoint| breakpoint.hit(code, span))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler::{self, Bytecode};
+    use crate::interpreter::InterpreterOptions;
+    use crate::lexer;
+    use crate::parser::{self, Program};
+    use crate::resolver::{self, ResolveOptions};
+
+    const SOURCE: &str = "
+MODULE Steps
+    VAR num nSum := 0;
+    PROC rAdd(num nValue)
+        nSum := nSum + nValue;
+        nSum := nSum * 2;
+    ENDPROC
+    PROC main()
+        rAdd 1;
+        FOR i FROM 1 TO 2 DO
+            rAdd i;
+        ENDFOR
+        nSum := -nSum;
+    ENDPROC
+ENDMODULE";
+
+    fn compile_source() -> (Program, Bytecode) {
+        let mut program = parser::parse_tokens(lexer::parse(SOURCE)).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let bytecode = compiler::compile(&program).unwrap();
+        (program, bytecode)
+    }
+
+    fn line(debugger: &Debugger) -> Option<(String, usize)> {
+        debugger.pp().map(|pp| (pp.routine, pp.span.line))
+    }
+
+    #[test]
+    fn steps_through_statements() {
+        let (mut program, bytecode) = compile_source();
+        let globals = std::mem::take(&mut program.variables);
+        let vm = Vm::new(&bytecode, &program, globals, "main", &InterpreterOptions::default()).unwrap();
+        let mut debugger = Debugger::new(vm);
+        let mut output = Vec::new();
+        let mut host = Host::new(&mut output);
+
+        assert_eq!(line(&debugger), Some((String::from("main"), 9)));
+        assert!(matches!(debugger.step_into(&mut host).unwrap(), Status::Paused { .. }));
+        assert_eq!(line(&debugger), Some((String::from("rAdd"), 5)));
+        debugger.step_over(&mut host).unwrap();
+        assert_eq!(line(&debugger), Some((String::from("rAdd"), 6)));
+        debugger.step_out(&mut host).unwrap();
+        assert_eq!(line(&debugger), Some((String::from("main"), 10)));
+        assert_eq!(format!("{:?}", debugger.vm.globals), "[Num(2.0)]");
+
+        // Over the FOR loop into its body, and over the calls in it
+        debugger.step_over(&mut host).unwrap();
+        assert_eq!(line(&debugger), Some((String::from("main"), 11)));
+        debugger.step_over(&mut host).unwrap();
+        assert_eq!(line(&debugger), Some((String::from("main"), 11)));
+        debugger.step_over(&mut host).unwrap();
+        assert_eq!(line(&debugger), Some((String::from("main"), 13)));
+        assert_eq!(format!("{:?}", debugger.vm.globals), "[Num(16.0)]");
+
+        assert_eq!(debugger.step_over(&mut host).unwrap(), Status::Finished);
+        assert_eq!(debugger.pp(), None);
+        assert_eq!(format!("{:?}", debugger.vm.globals), "[Num(-16.0)]");
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        let (mut program, bytecode) = compile_source();
+        let globals = std::mem::take(&mut program.variables);
+        let vm = Vm::new(&bytecode, &program, globals, "main", &InterpreterOptions::default()).unwrap();
+        let mut debugger = Debugger::new(vm);
+        let mut output = Vec::new();
+        let mut host = Host::new(&mut output);
+
+        debugger.set_breakpoint(Breakpoint::Routine(String::from("radd"))).unwrap();
+        debugger.set_breakpoint(Breakpoint::Line { module: String::from("Steps"), line: 13 }).unwrap();
+        assert_eq!(debugger.set_breakpoint(Breakpoint::Line { module: String::from("Steps"), line: 7 }).unwrap_err().to_string(),
+            "0:0: ERR_UNSUPPORTED: No statement of Steps on line 7");
+        assert_eq!(debugger.set_breakpoint(Breakpoint::Routine(String::from("rNone"))).unwrap_err().name(), "ERR_REFUNKPRC");
+
+        assert!(matches!(debugger.resume(&mut host).unwrap(), Status::Paused { .. }));
+        assert_eq!(line(&debugger), Some((String::from("rAdd"), 5)));
+        // Stepping over a call stops in it at a breakpoint
+        debugger.step_out(&mut host).unwrap();
+        debugger.step_over(&mut host).unwrap();
+        debugger.step_over(&mut host).unwrap();
+        assert_eq!((line(&debugger), debugger.vm.depth()), (Some((String::from("rAdd"), 5)), 2));
+
+        assert!(debugger.clear_breakpoint(&Breakpoint::Routine(String::from("radd"))));
+        assert!(!debugger.clear_breakpoint(&Breakpoint::Routine(String::from("radd"))));
+        debugger.resume(&mut host).unwrap();
+        assert_eq!(line(&debugger), Some((String::from("main"), 13)));
+        assert_eq!(format!("{:?}", debugger.vm.globals), "[Num(16.0)]");
+        debugger.clear_breakpoints();
+        assert_eq!(debugger.resume(&mut host).unwrap(), Status::Finished);
+    }
+
+    #[test]
+    fn inspects_and_changes_data() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Cell
+    CONST num nMax := 3;
+    VAR pos pHome := [1, 2, 3];
+    VAR num nCounts{2, 2} := [[1, 2], [3, 4]];
+    PROC main()
+        VAR num nLocal := 5;
+        nLocal := nLocal + nCounts{2, 1};
+        pHome := [nLocal, 0, 0];
+    ENDPROC
+ENDMODULE
+MODULE Other
+    LOCAL VAR num nHidden := 1;
+    VAR num nShared := 2;
+    VAR num nLocal := 9;
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let bytecode = compiler::compile(&program).unwrap();
+        let globals = std::mem::take(&mut program.variables);
+        let vm = Vm::new(&bytecode, &program, globals, "main", &InterpreterOptions::default()).unwrap();
+        let mut debugger = Debugger::new(vm);
+        let mut output = Vec::new();
+        let mut host = Host::new(&mut output);
+
+        // The data of the routine shadows the global data
+        assert_eq!(format!("{:?}", debugger.read("NLOCAL", &[]).unwrap()), "Num(5.0)");
+        assert_eq!(format!("{:?}", debugger.read("nShared", &[]).unwrap()), "Num(2.0)");
+        assert_eq!(debugger.read("nHidden", &[]).unwrap_err().to_string(), "0:0: ERR_REFUNKDAT: Unknown data nHidden");
+
+        debugger.write("nCounts", &[Access::Element(&[2, 1])], Variable::Num(10.0)).unwrap();
+        debugger.step_over(&mut host).unwrap();
+        assert_eq!(format!("{:?}", debugger.read("nLocal", &[]).unwrap()), "Num(15.0)");
+        assert_eq!(format!("{:?}", debugger.read("nCounts", &[Access::Element(&[2])]).unwrap()), "Array([Num(10.0), Num(4.0)])");
+
+        debugger.step_over(&mut host).unwrap();
+        debugger.write("pHome", &[Access::Component("Y")], Variable::Num(7.0)).unwrap();
+        assert_eq!(format!("{:?}", debugger.read("pHome", &[]).unwrap()), r#"Record("pos", [Num(15.0), Num(7.0), Num(0.0)])"#);
+        assert_eq!(format!("{:?}", debugger.read("pHome", &[Access::Component("z")]).unwrap()), "Num(0.0)");
+
+        let error = |result: Result<(), RuntimeError>| result.unwrap_err().to_string();
+        assert_eq!(error(debugger.write("nMax", &[], Variable::Num(4.0))), "0:0: ERR_ARGVALERR: Cannot assign to CONST nMax");
+        assert_eq!(error(debugger.write("pHome", &[Access::Component("w")], Variable::Num(4.0))), "0:0: ERR_ARGVALERR: pos has no component w");
+        assert_eq!(error(debugger.write("nLocal", &[], Variable::Str(String::from("x")))), "0:0: ERR_ARGVALERR: Cannot assign string to num");
+        assert_eq!(error(debugger.write("nCounts", &[Access::Element(&[3, 1])], Variable::Num(1.0))), "0:0: ERR_INDEX: Index 3 out of bounds 1 to 2");
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::host::{Clock, IoBoard, Location, ManualClock, Observer, Waypoint};
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::json::{Json, ToJson};
+use crate::lexer::{self, Span};
+use crate::linter::Linter;
+use crate::parser::{self, Program};
+use crate::resolver::{self, ResolveOptions};
+use crate::sarif::Finding;
+use crate::variable::{SignalKind, Variable};
+
+// The lexer, parser and interpreter for hosts that aren't a command line:
+// source text in, JSON text out. Nothing here reads files, waits or looks at
+// the process, so it also runs in a browser or behind bindings of other
+// languages.
+
+// ------------------ Source -----------------------/
+
+/// Tokens of a module, each with its Debug name and span
+pub fn tokenize(source: &str) -> Result<Json, String> {
+    let tokens = lexer::tokenize(source).map_err(|err| err.to_string())?;
+    Ok(Json::Array(tokens.iter()
+        .map(|token| Json::object(vec![("token", Json::str(&format!("{:?}", token.token_type))), ("span", token.span.to_json())]))
+        .collect()))
+}
+
+/// Modules with spans, like `parse --format json` prints them
+pub fn parse(source: &str) -> Result<Json, String> {
+    let tokens = lexer::tokenize(source).map_err(|err| err.to_string())?;
+    let program = parser::parse_tokens(tokens).map_err(|err| err.to_string())?;
+    Ok(Json::object(vec![("modules", Json::Array(program.modules.iter().map(ToJson::to_json).collect()))]))
+}
+
+/// Errors of the lexer, the parser and the resolver, or the warnings and
+/// lints of a module that resolves, like `check --message-format json`
+pub fn check(source: &str, options: &ResolveOptions) -> Json {
+    let findings = match resolve(source, &IoBoard::new(), options) {
+        Ok((program, warnings)) => {
+            let mut findings: Vec<Finding> = warnings.iter().map(|diagnostic| Finding::diagnostic("", diagnostic)).collect();
+            findings.extend(Linter::new().check(&program).iter().map(|lint| Finding::lint("", lint)));
+            findings
+        },
+        Err(findings) => findings,
+    };
+    Json::Array(findings.iter().map(ToJson::to_json).collect())
+}
+
+/// Program of a module with the signals of the I/O board, and the warnings
+/// of the resolver. A module that doesn't load gives its errors.
+pub fn resolve(source: &str, io: &IoBoard, options: &ResolveOptions) -> Result<(Program, Vec<resolver::Diagnostic>), Vec<Finding>> {
+    let tokens = lexer::tokenize(source).map_err(|err| vec![Finding::syntax("", &err.to_string(), Some(err.span))])?;
+    let mut program = parser::parse_tokens(tokens).map_err(|err| vec![Finding::syntax("", &err.message, Some(err.span))])?;
+    io.declare(&mut program);
+    match resolver::resolve(&mut program, options) {
+        Ok(warnings) => Ok((program, warnings)),
+        Err(diagnostics) => Err(diagnostics.iter().map(|diagnostic| Finding::diagnostic("", diagnostic)).collect()),
+    }
+}
+
+/// Errors of a module that doesn't load, on a line each. The messages of
+/// the lexer and the parser have the position already.
+pub fn error_text(findings: &[Finding]) -> String {
+    let errors: Vec<String> = findings.iter()
+        .map(|finding| match finding.span {
+            Some(span) if finding.rule != "syntax" => format!("{}:{}: {}", span.line, span.column, finding.message),
+            _ => finding.message.clone(),
+        })
+        .collect();
+    errors.join("\n")
+}
+
+// ------------------ Run -----------------------/
+
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// Routine the run starts in
+    pub entry: String,
+    /// Abort after executing this many nodes, a loop that doesn't end
+    /// would otherwise never return
+    pub max_instructions: u64,
+    /// Answers of the operator dialogs, in order
+    pub answers: Vec<f64>,
+    /// Add the statements, calls, assignments and errors of the run to the
+    /// result as its `trace`
+    pub trace: bool,
+}
+
+impl Default for RunOptions {
+    fn default() -> RunOptions {
+        RunOptions { entry: String::from("main"), max_instructions: 1_000_000, answers: Vec::new(), trace: false }
+    }
+}
+
+/// Run a module on simulated time, with what it wrote, the moves of the
+/// robot and the error it stopped with:
+///
+/// ```text
+/// {"output":["Hello"],"moves":[{"instruction":"MoveL","target":"p10","frame":"wobj0","programmed_speed":1000,
+///     "speed":1000,"zone":null,"time":1.5,"pos":[500,0,400],"rot":[1,0,0,0]}],"error":null,"time":1.5}
+/// ```
+///
+/// Source that doesn't resolve gives its diagnostics as the error. A traced
+/// run adds its events in order, like
+/// `{"event":"assign","name":"nCount","value":1}`.
+pub fn run(source: &str, options: &RunOptions) -> Json {
+    match Session::new(source, &[]) {
+        Ok(mut session) => session.run(options),
+        Err(findings) => run_result(Vec::new(), &[], Json::Array(findings.iter().map(ToJson::to_json).collect()), Duration::ZERO, None),
+    }
+}
+
+// ------------------ Session -----------------------/
+
+/// Program of a module that runs on simulated time. Its data, signals and
+/// clock keep their values from one run to the next, so the host can set
+/// inputs, run a routine and look at the data in turns.
+pub struct Session {
+    program: Program,
+    io: IoBoard,
+    clock: ManualClock,
+}
+
+impl Session {
+    /// Load a module with the signals of the I/O board, a group signal is
+    /// 32 bits wide
+    pub fn new(source: &str, signals: &[(String, SignalKind)]) -> Result<Session, Vec<Finding>> {
+        let mut io = IoBoard::new();
+        for (name, kind) in signals {
+            match kind {
+                SignalKind::GroupInput | SignalKind::GroupOutput => io.define_group(name, *kind, 32),
+                _ => io.define(name, *kind),
+            }
+        }
+        let (program, _) = resolve(source, &io, &ResolveOptions::default())?;
+        Ok(Session { program, io, clock: ManualClock::new(Duration::ZERO) })
+    }
+
+    /// Run a routine, like `run`
+    pub fn run(&mut self, options: &RunOptions) -> Json {
+        let mut output = Vec::new();
+        let mut answers: VecDeque<f64> = options.answers.iter().copied().collect();
+        let mut trace = Trace::default();
+        let mut builder = Interpreter::builder(&mut output)
+            .clock(&self.clock)
+            .input(&mut answers)
+            .io(&self.io)
+            .max_instructions(options.max_instructions);
+        if options.trace {
+            builder = builder.host(|host| host.with_observer(&mut trace));
+        }
+        let mut interpreter = builder.build();
+        let result = interpreter.run(&mut self.program, &options.entry);
+        let error = match result.error() {
+            None => Json::Null,
+            Some(err) => error_json(&err, result.location.as_ref()),
+        };
+        let trajectory = std::mem::take(&mut interpreter.host.trajectory);
+        drop(interpreter);
+        run_result(output, &trajectory, error, self.clock.now(), options.trace.then_some(trace.0))
+    }
+
+    fn slot(&self, name: &str) -> Result<usize, RuntimeError> {
+        self.program.module_data().into_iter()
+            .find(|(_, decl, _)| decl.name.eq_ignore_ascii_case(name))
+            .map(|(_, _, slot)| slot)
+            .ok_or_else(|| RuntimeError::UnknownName { name: String::from(name), span: Span::default() })
+    }
+
+    /// Value of the data of a module
+    pub fn get(&self, name: &str) -> Result<&Variable, RuntimeError> {
+        Ok(&self.program.variables[self.slot(name)?])
+    }
+
+    /// Assign to the data of a module, like an assignment in the program would
+    pub fn set(&mut self, name: &str, value: Variable) -> Result<(), RuntimeError> {
+        let slot = self.slot(name)?;
+        self.program.variables[slot].set(value)
+    }
+
+    pub fn set_input(&self, name: &str, value: f64) -> Result<(), RuntimeError> {
+        self.io.set_input(name, value)
+    }
+
+    /// Value of a signal
+    pub fn signal(&self, name: &str) -> Result<f64, RuntimeError> {
+        self.io.read(name)
+    }
+
+    /// Simulated time the runs took
+    pub fn time(&self) -> Duration {
+        self.clock.now()
+    }
+}
+
+fn run_result(output: Vec<String>, trajectory: &[Waypoint], error: Json, time: Duration, trace: Option<Vec<Json>>) -> Json {
+    let moves = trajectory.iter().map(ToJson::to_json).collect();
+    let mut members = vec![
+        ("output", Json::Array(output.iter().map(|line| Json::str(line)).collect())),
+        ("moves", Json::Array(moves)),
+        ("error", error),
+        ("time", Json::Num(time.as_secs_f64())),
+    ];
+    if let Some(trace) = trace {
+        members.push(("trace", Json::Array(trace)));
+    }
+    Json::object(members)
+}
+
+/// Error as JSON, with the module and routine it was raised in when known
+fn error_json(err: &RuntimeError, location: Option<&Location>) -> Json {
+    let mut members = vec![("name", Json::str(err.name())), ("message", Json::str(&err.to_string())), ("span", err.span().to_json())];
+    if let Some(location) = location {
+        members.push(("module", Json::str(&location.module)));
+        members.push(("routine", Json::str(&location.routine)));
+    }
+    Json::object(members)
+}
+
+/// Events of a traced run
+#[derive(Default)]
+struct Trace(Vec<Json>);
+
+impl Observer for Trace {
+    fn statement(&mut self, span: Span) {
+        self.0.push(Json::object(vec![("event", Json::str("statement")), ("span", span.to_json())]));
+    }
+
+    fn enter_routine(&mut self, module: &str, routine: &str) {
+        self.0.push(Json::object(vec![("event", Json::str("enter")), ("module", Json::str(module)), ("routine", Json::str(routine))]));
+    }
+
+    fn exit_routine(&mut self, module: &str, routine: &str) {
+        self.0.push(Json::object(vec![("event", Json::str("exit")), ("module", Json::str(module)), ("routine", Json::str(routine))]));
+    }
+
+    fn assign(&mut self, name: &str, value: &Variable) {
+        self.0.push(Json::object(vec![("event", Json::str("assign")), ("name", Json::str(name)), ("value", value.to_json())]));
+    }
+
+    fn error(&mut self, error: &RuntimeError) {
+        self.0.push(Json::object(vec![("event", Json::str("error")), ("error", error_json(error, None))]));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn runs_source() {
+        assert_eq!(tokenize("MODULE Cell").unwrap().to_string(),
+            r#"[{"token":"Mod","span":{"line":1,"column":1,"start":0,"end":6}},{"token":"Id(\"Cell\")","span":{"line":1,"column":8,"start":7,"end":11}}]"#);
+        assert_eq!(tokenize("MODULE $").unwrap_err(), "Undefined symbol $ at 1:8");
+        assert_eq!(parse("MODULE Cell\nENDMODULE").unwrap().path(&["modules"]).and_then(Json::as_array).map(<[Json]>::len), Some(1));
+
+        let findings = check("MODULE Cell\n    PROC main()\n        nCount := 1;\n    ENDPROC\nENDMODULE", &ResolveOptions::default());
+        assert_eq!(findings.as_array().unwrap()[0].get("message").and_then(Json::as_str), Some("Unknown id 'nCount'"));
+        let findings = check("MODULE Cell\n    PROC main()\n        nCount := ;\n    ENDPROC\nENDMODULE", &ResolveOptions::default());
+        assert_eq!(findings.as_array().unwrap()[0].path(&["span", "line"]).and_then(Json::as_f64), Some(3.0));
+        let shadowing = "MODULE Cell\n    PERS num nCount := 0;\n    PROC main()\n        VAR num nCount := 1;\n        nCount := nCount + 1;\n    ENDPROC\nENDMODULE";
+        assert_eq!(check(shadowing, &ResolveOptions::default()).as_array().map(<[Json]>::len), Some(0));
+        let findings = check(shadowing, &ResolveOptions { warn_shadowing: true, ..ResolveOptions::default() });
+        assert_eq!(findings.as_array().unwrap()[0].path(&["span", "line"]).and_then(Json::as_f64), Some(4.0));
+
+        let source = "MODULE Cell
+    PROC main()
+        VAR num nAnswer;
+        TPReadNum nAnswer, \"Parts?\";
+        TPWrite \"Parts: \" \\Num:=nAnswer;
+        MoveAbsJ [[0, 0, 0, 0, 90, 0], [9E9, 9E9, 9E9, 9E9, 9E9, 9E9]], v100, fine, tool0;
+        WHILE TRUE DO
+        ENDWHILE
+    ENDPROC
+ENDMODULE";
+        let result = run(source, &RunOptions { answers: vec![4.0], max_instructions: 1000, ..RunOptions::default() });
+        assert_eq!(result.get("output").unwrap().to_string(), r#"["Parts: 4"]"#);
+        assert_eq!(result.get("moves").and_then(Json::as_array).unwrap()[0].get("joints").unwrap().to_string(), "[0,0,0,0,90,0]");
+        assert_eq!(result.path(&["error", "name"]).and_then(Json::as_str), Some("ERR_EXECLIMIT"));
+        assert_eq!(result.path(&["error", "routine"]).and_then(Json::as_str), Some("main"));
+        assert_eq!(result.get("trace"), None);
+
+        let source = "MODULE Cell\n    PROC main()\n        VAR num nCount := 1;\n        nCount := nCount + 1;\n    ENDPROC\nENDMODULE";
+        let result = run(source, &RunOptions { trace: true, ..RunOptions::default() });
+        let events: Vec<String> = result.get("trace").and_then(Json::as_array).unwrap().iter().map(Json::to_string).collect();
+        assert_eq!(events, vec![
+            r#"{"event":"enter","module":"Cell","routine":"main"}"#,
+            r#"{"event":"statement","span":{"line":4,"column":9,"start":65,"end":71}}"#,
+            r#"{"event":"assign","name":"nCount","value":2}"#,
+            r#"{"event":"exit","module":"Cell","routine":"main"}"#,
+        ]);
+    }
+
+    #[test]
+    fn drives_a_session() {
+        let source = "MODULE Cell
+    VAR num nCount := 0;
+    PROC main()
+        WaitDI diPartReady, 1 \\MaxTime:=2;
+        SetDO doGripper, 1;
+        nCount := nCount + 1;
+    ENDPROC
+ENDMODULE";
+        let signals = [(String::from("diPartReady"), SignalKind::DigitalInput), (String::from("doGripper"), SignalKind::DigitalOutput)];
+        let mut session = Session::new(source, &signals).unwrap();
+        let result = session.run(&RunOptions::default());
+        assert_eq!(result.path(&["error", "name"]).and_then(Json::as_str), Some("ERR_WAIT_MAXTIME"));
+        assert_eq!(session.time(), Duration::from_secs(2));
+
+        session.set_input("diPartReady", 1.0).unwrap();
+        assert_eq!(session.run(&RunOptions::default()).get("error"), Some(&Json::Null));
+        assert_eq!(session.signal("doGripper").ok(), Some(1.0));
+        assert_eq!(format!("{:?}", session.get("nCount").unwrap()), "Num(1.0)");
+        session.set("nCount", Variable::Num(5.0)).unwrap();
+        session.run(&RunOptions::default());
+        assert_eq!(format!("{:?}", session.get("nCount").unwrap()), "Num(6.0)");
+        assert_eq!(session.set("nCount", Variable::Str(String::from("six"))).unwrap_err().name(), "ERR_ARGVALERR");
+        assert_eq!(session.get("nParts").unwrap_err().name(), "ERR_REFUNKDAT");
+        assert!(Session::new("MODULE Cell\n    PROC main()\n        WaitDI diPartReady, 1;\n    ENDPROC\nENDMODULE", &[]).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use std::path::{Path, PathBuf};
+
+use crate::interpreter::RuntimeError;
+use crate::lexer::{LexError, Span};
+use crate::parser::ParseError;
+use crate::resolver::{Diagnostic, Severity};
+
+// ------------------ Errors -----------------------/
+
+/// Error of loading, checking or running a program, by the stage it failed
+/// in. Modules read from files name the file.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Text that isn't a token
+    #[error("{}{error}", in_file(.file))]
+    Lex { error: LexError, file: Option<PathBuf> },
+    /// Tokens that don't make a module
+    #[error("{}{error}", in_file(.file))]
+    Parse { error: ParseError, file: Option<PathBuf> },
+    /// Diagnostics of the resolver, with at least one error
+    #[error("{}", .diagnostics.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"))]
+    Semantic { diagnostics: Vec<Diagnostic> },
+    #[error(transparent)]
+    Runtime(#[from] RuntimeError),
+    /// File or directory that can't be read, or a program directory without
+    /// a single .pgf file
+    #[error("{}: {message}", .path.display())]
+    Io { path: PathBuf, message: String },
+}
+
+impl Error {
+    pub fn io(path: &Path, message: impl ToString) -> Error {
+        Error::Io { path: path.to_path_buf(), message: message.to_string() }
+    }
+
+    /// The error of a module read from a file
+    pub fn in_file(mut self, path: &Path) -> Error {
+        match &mut self {
+            Error::Lex { file, .. } | Error::Parse { file, .. } => *file = Some(path.to_path_buf()),
+            _ => (),
+        }
+        self
+    }
+
+    /// Position of the error: of the first error of the resolver, and none
+    /// for files that can't be read
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::Lex { error, .. } => Some(error.span),
+            Error::Parse { error, .. } => Some(error.span),
+            Error::Semantic { diagnostics } => diagnostics.iter().find(|diagnostic| diagnostic.severity == Severity::Error).map(|diagnostic| diagnostic.span),
+            Error::Runtime(err) => Some(err.span()),
+            Error::Io { .. } => None,
+        }
+    }
+
+    /// Code of the error: the rule of the findings of `check` for the
+    /// errors of loading, the name of ERRNO for runtime errors
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Lex { .. } | Error::Parse { .. } => "syntax",
+            Error::Semantic { .. } => "semantic",
+            Error::Runtime(err) => err.name(),
+            Error::Io { .. } => "io",
+        }
+    }
+}
+
+impl From<LexError> for Error {
+    fn from(error: LexError) -> Error {
+        Error::Lex { error, file: None }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(error: ParseError) -> Error {
+        Error::Parse { error, file: None }
+    }
+}
+
+impl From<Vec<Diagnostic>> for Error {
+    fn from(diagnostics: Vec<Diagnostic>) -> Error {
+        Error::Semantic { diagnostics }
+    }
+}
+
+fn in_file(file: &Option<PathBuf>) -> String {
+    file.as_ref().map(|file| format!("{}: ", file.display())).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer;
+    use crate::parser::Program;
+    use crate::resolver::{self, ResolveOptions};
+
+    #[test]
+    fn errors_have_codes_and_spans() {
+        let err = Program::from_source("MODULE Cell\n    VAR num n := 1 $ 2;\nENDMODULE").err().unwrap();
+        assert!(matches!(err, Error::Lex { .. }));
+        assert_eq!((err.code(), err.span().map(|span| (span.line, span.column))), ("syntax", Some((2, 20))));
+
+        let err = Program::from_source("MODULE Cell\n    PROC main(\nENDMODULE").err().unwrap();
+        assert!(matches!(err, Error::Parse { .. }));
+        assert_eq!(err.span().map(|span| (span.line, span.column)), Some((3, 1)));
+
+        let mut program = Program::from_source("MODULE Cell\n    PROC main()\n        nMissing := 1;\n    ENDPROC\nENDMODULE").unwrap();
+        let err = Error::from(resolver::resolve(&mut program, &ResolveOptions::default()).unwrap_err());
+        assert_eq!((err.code(), err.span().map(|span| span.line)), ("semantic", Some(3)));
+
+        let err = Error::from(RuntimeError::DivZero { span: lexer::Span::default() });
+        assert_eq!(err.code(), "ERR_DIVZERO");
+        assert!(std::error::Error::source(&err).is_none());
+
+        let err = Program::from_path(Path::new("missing.mod")).err().unwrap();
+        assert_eq!((err.code(), err.span()), ("io", None));
+        assert!(err.to_string().starts_with("missing.mod: "));
+    }
+}
@@ -0,0 +1,330 @@
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+
+use crate::embed::{self, RunOptions, Session};
+use crate::interpreter::RuntimeError;
+use crate::json::{Json, ToJson};
+use crate::resolver::ResolveOptions;
+use crate::sarif::Finding;
+use crate::variable::Variable;
+
+// C interface of the library, declared in include/rapid_rust.h. Text goes in
+// and out as NUL terminated UTF-8, structured results are JSON text like the
+// command line prints. Every function returns one of the codes below. The
+// text a function gives back through `out` is the result, or the error
+// message when it didn't return RAPID_OK; free it with rapid_string_free.
+
+// ------------------ Codes -----------------------/
+
+pub const RAPID_OK: c_int = 0;
+/// A pointer is NULL, or text isn't UTF-8 or the JSON expected
+pub const RAPID_ERR_ARGUMENT: c_int = 1;
+/// The lexer or the parser rejected the source
+pub const RAPID_ERR_SYNTAX: c_int = 2;
+/// The source parsed but doesn't resolve
+pub const RAPID_ERR_SEMANTIC: c_int = 3;
+/// The program stopped with an error of RAPID
+pub const RAPID_ERR_RUNTIME: c_int = 4;
+/// No data or signal has the name
+pub const RAPID_ERR_UNKNOWN: c_int = 5;
+/// A bug of the library, the handle shouldn't be used anymore
+pub const RAPID_ERR_PANIC: c_int = 6;
+
+type Failure = (c_int, String);
+
+/// Run the body of an exported function, a panic must not unwind into C
+fn call(out: *mut *mut c_char, body: impl FnOnce() -> Result<String, Failure>) -> c_int {
+    let (code, text) = match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(text)) => (RAPID_OK, text),
+        Ok(Err(failure)) => failure,
+        Err(_) => (RAPID_ERR_PANIC, String::from("The library panicked")),
+    };
+    if !out.is_null() {
+        // JSON escapes NUL, messages don't have one
+        let text = CString::new(text).unwrap_or_default();
+        unsafe { *out = text.into_raw() };
+    }
+    code
+}
+
+unsafe fn text<'a>(ptr: *const c_char, what: &str) -> Result<&'a str, Failure> {
+    if ptr.is_null() {
+        return Err((RAPID_ERR_ARGUMENT, format!("The {} is NULL", what)));
+    }
+    CStr::from_ptr(ptr).to_str().map_err(|_| (RAPID_ERR_ARGUMENT, format!("The {} isn't UTF-8", what)))
+}
+
+unsafe fn session<'a>(program: *mut RapidProgram) -> Result<&'a mut Session, Failure> {
+    program.as_mut().map(|program| &mut program.session).ok_or_else(|| (RAPID_ERR_ARGUMENT, String::from("The program is NULL")))
+}
+
+fn runtime_error(err: RuntimeError) -> Failure {
+    let code = match err {
+        RuntimeError::UnknownName { .. } | RuntimeError::UnknownSignal { .. } => RAPID_ERR_UNKNOWN,
+        _ => RAPID_ERR_RUNTIME,
+    };
+    (code, err.to_string())
+}
+
+/// Free text the library gave back, NULL is ignored
+///
+/// # Safety
+///
+/// The text must come from this library and not be freed before.
+#[no_mangle]
+pub unsafe extern "C" fn rapid_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
+
+// ------------------ Source -----------------------/
+
+/// Modules of a source with their spans as JSON
+///
+/// # Safety
+///
+/// `source` is NULL or NUL terminated, `out` is NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn rapid_parse(source: *const c_char, out: *mut *mut c_char) -> c_int {
+    call(out, || {
+        let source = text(source, "source")?;
+        embed::parse(source).map(|json| json.to_string()).map_err(|err| (RAPID_ERR_SYNTAX, err))
+    })
+}
+
+/// Errors, warnings and lints of a module as a JSON array
+///
+/// # Safety
+///
+/// `source` is NULL or NUL terminated, `out` is NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn rapid_check(source: *const c_char, out: *mut *mut c_char) -> c_int {
+    call(out, || Ok(embed::check(text(source, "source")?, &ResolveOptions::default()).to_string()))
+}
+
+// ------------------ Program -----------------------/
+
+/// Opaque handle of a program, see `embed::Session`
+pub struct RapidProgram {
+    session: Session,
+}
+
+/// Load a module with the signals of a JSON object by name and data type,
+/// like `{"doGripper": "signaldo"}`, or none for NULL. On an error `out`
+/// gets the errors of the module, on a line each.
+///
+/// # Safety
+///
+/// `source` and `signals` are NULL or NUL terminated, `program` and `out`
+/// are NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn rapid_program_new(source: *const c_char, signals: *const c_char, program: *mut *mut RapidProgram, out: *mut *mut c_char) -> c_int {
+    call(out, || {
+        if program.is_null() {
+            return Err((RAPID_ERR_ARGUMENT, String::from("The program is NULL")));
+        }
+        let source = text(source, "source")?;
+        let mut kinds = Vec::new();
+        if !signals.is_null() {
+            let signals = Json::parse(text(signals, "signals")?).map_err(|err| (RAPID_ERR_ARGUMENT, err))?;
+            let members = match signals {
+                Json::Object(members) => members,
+                _ => return Err((RAPID_ERR_ARGUMENT, String::from("The signals aren't an object"))),
+            };
+            for (name, data_type) in members {
+                match data_type.as_str().map(Variable::from) {
+                    Some(Ok(Variable::Signal(kind, _))) => kinds.push((name, kind)),
+                    _ => return Err((RAPID_ERR_ARGUMENT, format!("{} is not a signal type", data_type))),
+                }
+            }
+        }
+        let session = Session::new(source, &kinds).map_err(|findings| load_error(&findings))?;
+        *program = Box::into_raw(Box::new(RapidProgram { session }));
+        Ok(String::new())
+    })
+}
+
+fn load_error(findings: &[Finding]) -> Failure {
+    let code = if findings.first().is_some_and(|finding| finding.rule == "syntax") { RAPID_ERR_SYNTAX } else { RAPID_ERR_SEMANTIC };
+    (code, embed::error_text(findings))
+}
+
+/// Free a program, NULL is ignored
+///
+/// # Safety
+///
+/// The program must come from rapid_program_new and not be freed before.
+#[no_mangle]
+pub unsafe extern "C" fn rapid_program_free(program: *mut RapidProgram) {
+    if !program.is_null() {
+        drop(Box::from_raw(program));
+    }
+}
+
+/// Run a routine, "main" for a NULL entry, with the answers of the operator
+/// dialogs. `out` gets the output, the moves, the time and the error as
+/// JSON, also for RAPID_ERR_RUNTIME.
+///
+/// # Safety
+///
+/// `program` comes from rapid_program_new, `entry` is NULL or NUL
+/// terminated, `answers` points to `answer_count` numbers and `out` is NULL
+/// or writable.
+#[no_mangle]
+pub unsafe extern "C" fn rapid_program_run(program: *mut RapidProgram, entry: *const c_char, max_instructions: u64,
+    answers: *const f64, answer_count: usize, out: *mut *mut c_char) -> c_int {
+    call(out, || {
+        let session = session(program)?;
+        let entry = if entry.is_null() { "main" } else { text(entry, "entry")? };
+        let answers = if answers.is_null() { &[] } else { slice::from_raw_parts(answers, answer_count) };
+        let options = RunOptions { entry: entry.to_string(), max_instructions, answers: answers.to_vec(), ..RunOptions::default() };
+        let result = session.run(&options);
+        match result.get("error") {
+            Some(Json::Null) => Ok(result.to_string()),
+            _ => Err((RAPID_ERR_RUNTIME, result.to_string())),
+        }
+    })
+}
+
+/// Value of the data of a module as JSON
+///
+/// # Safety
+///
+/// `program` comes from rapid_program_new, `name` is NULL or NUL
+/// terminated and `out` is NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn rapid_program_get(program: *mut RapidProgram, name: *const c_char, out: *mut *mut c_char) -> c_int {
+    call(out, || {
+        let session = session(program)?;
+        session.get(text(name, "name")?).map(|value| value.to_json().to_string()).map_err(runtime_error)
+    })
+}
+
+/// Assign JSON to the data of a module: a number, bool or string, or an
+/// array for records and arrays
+///
+/// # Safety
+///
+/// `program` comes from rapid_program_new, `name` and `value` are NULL or
+/// NUL terminated and `out` is NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn rapid_program_set(program: *mut RapidProgram, name: *const c_char, value: *const c_char, out: *mut *mut c_char) -> c_int {
+    call(out, || {
+        let session = session(program)?;
+        let name = text(name, "name")?;
+        let json = Json::parse(text(value, "value")?).map_err(|err| (RAPID_ERR_ARGUMENT, err))?;
+        let value = from_json(&json).ok_or_else(|| (RAPID_ERR_ARGUMENT, format!("Cannot assign {} to RAPID data", json)))?;
+        session.set(name, value).map(|()| String::new()).map_err(runtime_error)
+    })
+}
+
+fn from_json(json: &Json) -> Option<Variable> {
+    match json {
+        Json::Bool(value) => Some(Variable::Bool(*value)),
+        Json::Num(value) => Some(Variable::Num(*value)),
+        Json::Str(text) => Some(Variable::Str(text.clone())),
+        Json::Array(items) => items.iter().map(from_json).collect::<Option<Vec<Variable>>>().map(|items| Variable::Record("", items)),
+        Json::Null | Json::Object(_) => None,
+    }
+}
+
+/// Set an input signal
+///
+/// # Safety
+///
+/// `program` comes from rapid_program_new, `name` is NULL or NUL
+/// terminated and `out` is NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn rapid_program_set_input(program: *mut RapidProgram, name: *const c_char, value: f64, out: *mut *mut c_char) -> c_int {
+    call(out, || {
+        let session = session(program)?;
+        session.set_input(text(name, "name")?, value).map(|()| String::new()).map_err(runtime_error)
+    })
+}
+
+/// Value of a signal
+///
+/// # Safety
+///
+/// `program` comes from rapid_program_new, `name` is NULL or NUL
+/// terminated, `value` and `out` are NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn rapid_program_signal(program: *mut RapidProgram, name: *const c_char, value: *mut f64, out: *mut *mut c_char) -> c_int {
+    call(out, || {
+        let session = session(program)?;
+        let signal = session.signal(text(name, "name")?).map_err(runtime_error)?;
+        if !value.is_null() {
+            *value = signal;
+        }
+        Ok(String::new())
+    })
+}
+
+/// Seconds of simulated time the runs took, 0 for NULL
+///
+/// # Safety
+///
+/// `program` is NULL or comes from rapid_program_new.
+#[no_mangle]
+pub unsafe extern "C" fn rapid_program_time(program: *const RapidProgram) -> f64 {
+    program.as_ref().map_or(0.0, |program| program.session.time().as_secs_f64())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ptr;
+
+    unsafe fn take(text: *mut c_char) -> String {
+        let copy = CStr::from_ptr(text).to_str().unwrap().to_string();
+        rapid_string_free(text);
+        copy
+    }
+
+    #[test]
+    fn drives_a_program() {
+        unsafe {
+            let mut out = ptr::null_mut();
+            assert_eq!(rapid_check(ptr::null(), &mut out), RAPID_ERR_ARGUMENT);
+            assert_eq!(take(out), "The source is NULL");
+
+            let source = CString::new("MODULE Cell
+    PERS pos pHome := [0, 0, 0];
+    PROC main()
+        SetDO doGripper, 1;
+        TPWrite \"Home \" \\Pos:=pHome;
+    ENDPROC
+ENDMODULE").unwrap();
+            let signals = CString::new(r#"{"doGripper": "signaldo"}"#).unwrap();
+            let mut program = ptr::null_mut();
+            assert_eq!(rapid_program_new(source.as_ptr(), signals.as_ptr(), &mut program, ptr::null_mut()), RAPID_OK);
+
+            let name = CString::new("pHome").unwrap();
+            let value = CString::new("[1, 2, 3]").unwrap();
+            assert_eq!(rapid_program_set(program, name.as_ptr(), value.as_ptr(), ptr::null_mut()), RAPID_OK);
+            assert_eq!(rapid_program_run(program, ptr::null(), 1000, ptr::null(), 0, &mut out), RAPID_OK);
+            assert!(take(out).starts_with(r#"{"output":["Home [1,2,3]"]"#));
+            let mut signal = 0.0;
+            let gripper = CString::new("doGripper").unwrap();
+            assert_eq!(rapid_program_signal(program, gripper.as_ptr(), &mut signal, ptr::null_mut()), RAPID_OK);
+            assert_eq!(signal, 1.0);
+            assert_eq!(rapid_program_get(program, name.as_ptr(), &mut out), RAPID_OK);
+            assert_eq!(take(out), r#"{"record":"pos","components":[1,2,3]}"#);
+
+            let unknown = CString::new("pWork").unwrap();
+            assert_eq!(rapid_program_get(program, unknown.as_ptr(), &mut out), RAPID_ERR_UNKNOWN);
+            assert_eq!(take(out), "0:0: ERR_REFUNKDAT: Unknown data pWork");
+            let entry = CString::new("Home").unwrap();
+            assert_eq!(rapid_program_run(program, entry.as_ptr(), 1000, ptr::null(), 0, &mut out), RAPID_ERR_RUNTIME);
+            assert!(take(out).contains(r#""name":"ERR_REFUNKPRC""#));
+            rapid_program_free(program);
+
+            let source = CString::new("MODULE Cell\n    PROC main()\n        nCount := 1;\n    ENDPROC\nENDMODULE").unwrap();
+            assert_eq!(rapid_program_new(source.as_ptr(), ptr::null(), &mut program, &mut out), RAPID_ERR_SEMANTIC);
+            assert_eq!(take(out), "3:9: Unknown id 'nCount'");
+        }
+    }
+}
@@ -0,0 +1,426 @@
+use crate::lexer::{self, Comment, Token, TokenType};
+use crate::parser;
+
+// ------------------ Options -----------------------/
+
+/// Spelling of the keywords in formatted source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeywordCase {
+    /// Like the manual writes them: keywords upper case, data types lower case
+    Upper,
+    Lower,
+    /// As they are written
+    Keep,
+}
+
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    /// Spaces per level of indentation
+    pub indent: usize,
+    pub keyword_case: KeywordCase,
+    /// Line up the types, names and values of consecutive data declarations
+    pub align_declarations: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions { indent: 4, keyword_case: KeywordCase::Upper, align_declarations: true }
+    }
+}
+
+// ------------------ Formatter -----------------------/
+
+/// Source line with tokens, as it will be written
+struct Line<'t> {
+    tokens: &'t [Token],
+    // Comment at the end of the line, or alone on it without tokens
+    comment: Option<&'t Comment>,
+    // Line number in the source
+    number: usize,
+    depth: usize,
+    // Blank lines before it
+    blank: bool,
+}
+
+/// Reformat the modules of a source file. Line breaks and the spacing
+/// within a line are kept, the indentation follows the blocks and the
+/// keywords are spelled alike. Source that doesn't parse is not changed.
+pub fn format(source: &str, options: &FormatOptions) -> Result<String, String> {
+    let (tokens, comments) = lexer::tokenize_with_comments(source).map_err(|err| err.to_string())?;
+    parser::parse_tokens(tokens.clone()).map_err(|err| err.to_string())?;
+
+    let mut text = String::new();
+    if tokens.is_empty() {
+        return Ok(text);
+    }
+    // The header of older controllers, a field on each line
+    if let Some(header) = lexer::header(source) {
+        text.push_str("%%%\n");
+        for (name, value) in header.iter() {
+            match value.as_str() {
+                "" => text.push_str(&format!("  {}\n", name)),
+                value => text.push_str(&format!("  {}:{}\n", name, value)),
+            }
+        }
+        text.push_str("%%%\n");
+    }
+
+    let lines = comment_lines(indent_lines(&tokens), &comments);
+    let mut idx = 0;
+    while idx < lines.len() {
+        // A run of declarations is aligned as a whole
+        let run = if options.align_declarations {
+            lines[idx..].iter().enumerate()
+                .take_while(|(offset, line)| declaration(line.tokens).is_some()
+                    && (*offset == 0 || (!line.blank && line.depth == lines[idx].depth)))
+                .count()
+        } else {
+            0
+        };
+        if run > 1 {
+            let declarations: Vec<Declaration> = lines[idx..idx + run].iter()
+                .filter_map(|line| declaration(line.tokens))
+                .collect();
+            for (line, declaration) in lines[idx..idx + run].iter().zip(declarations.iter()) {
+                start_line(&mut text, line, options);
+                text.push_str(&declaration.render(source, &declarations, options));
+                end_line(&mut text, source, line);
+            }
+            idx += run;
+            continue;
+        }
+
+        let line = &lines[idx];
+        start_line(&mut text, line, options);
+        text.push_str(&spell(source, line.tokens, options));
+        end_line(&mut text, source, line);
+        idx += 1;
+    }
+    Ok(text)
+}
+
+fn start_line(text: &mut String, line: &Line, options: &FormatOptions) {
+    if line.blank {
+        text.push('\n');
+    }
+    text.push_str(&" ".repeat(line.depth * options.indent));
+}
+
+/// Comment of a line as written, after the spacing before it, and the end
+/// of the line
+fn end_line(text: &mut String, source: &str, line: &Line) {
+    if let Some(comment) = line.comment {
+        let start = line.tokens.last().map_or(comment.span.start, |token| token.span.end);
+        text.push_str(source[start..comment.span.end].trim_end());
+    }
+    text.push('\n');
+}
+
+/// Lines with the comments added: those after code to its line, the others
+/// as lines of their own, indented like the line they precede. Blank lines
+/// are kept as one.
+fn comment_lines<'t>(lines: Vec<Line<'t>>, comments: &'t [Comment]) -> Vec<Line<'t>> {
+    let mut all: Vec<Line> = Vec::new();
+    let mut comments = comments.iter().peekable();
+    for mut line in lines {
+        while let Some(comment) = comments.next_if(|comment| comment.span.line < line.number) {
+            all.push(Line { tokens: &[], comment: Some(comment), number: comment.span.line, depth: line.depth, blank: false });
+        }
+        line.comment = comments.next_if(|comment| comment.span.line == line.number);
+        all.push(line);
+    }
+    let depth = all.last().map_or(0, |line| line.depth);
+    all.extend(comments.map(|comment| Line { tokens: &[], comment: Some(comment), number: comment.span.line, depth, blank: false }));
+    for idx in 1..all.len() {
+        all[idx].blank = all[idx].number > all[idx - 1].number + 1;
+    }
+    all
+}
+
+/// Tokens by source line, with the depth of the blocks they're in
+fn indent_lines(tokens: &[Token]) -> Vec<Line<'_>> {
+    let mut lines: Vec<Line> = Vec::new();
+    let mut depth: usize = 0;
+    // First keyword of the statement a continuation line is part of
+    let mut statement: Option<&TokenType> = None;
+    let mut nesting: usize = 0;
+
+    let mut start = 0;
+    while start < tokens.len() {
+        let line = tokens[start].span.line;
+        let end = start + tokens[start..].iter().take_while(|token| token.span.line == line).count();
+        let line_tokens = &tokens[start..end];
+        start = end;
+
+        let first = leading(line_tokens);
+        let last = &line_tokens[line_tokens.len() - 1].token_type;
+        let line_depth = match statement {
+            // The rest of a statement goes one deeper
+            Some(_) => depth + 1,
+            None => match first {
+                TokenType::EndMod | TokenType::EndProc | TokenType::EndFunc | TokenType::EndTrap
+                | TokenType::EndIf | TokenType::EndWhile | TokenType::EndFor => {
+                    depth = depth.saturating_sub(1);
+                    depth
+                },
+                TokenType::EndTest => {
+                    depth = depth.saturating_sub(2);
+                    depth
+                },
+                TokenType::ElseIf | TokenType::Else | TokenType::Error
+                | TokenType::Case | TokenType::Default => depth.saturating_sub(1),
+                _ => depth,
+            },
+        };
+        lines.push(Line { tokens: line_tokens, comment: None, number: line, depth: line_depth, blank: false });
+
+        for token in line_tokens {
+            match token.token_type {
+                TokenType::LeftPar | TokenType::LeftBrack | TokenType::LeftBrace => nesting += 1,
+                TokenType::RightPar | TokenType::RightBrack | TokenType::RightBrace => nesting = nesting.saturating_sub(1),
+                _ => (),
+            }
+        }
+        let first = statement.unwrap_or(first);
+        let complete = nesting == 0 && (matches!(last,
+            TokenType::Semicolon | TokenType::Then | TokenType::Do | TokenType::Else | TokenType::Error
+            | TokenType::EndMod | TokenType::EndProc | TokenType::EndFunc | TokenType::EndTrap | TokenType::EndIf
+            | TokenType::EndWhile | TokenType::EndFor | TokenType::EndTest)
+            || matches!(first, TokenType::Mod | TokenType::Proc | TokenType::Func | TokenType::Trap | TokenType::Test
+                | TokenType::Case | TokenType::Default));
+        if !complete {
+            statement = Some(first);
+            continue;
+        }
+        statement = None;
+        depth += match (first, last) {
+            (TokenType::Mod | TokenType::Proc | TokenType::Func | TokenType::Trap, _) => 1,
+            (TokenType::If, TokenType::Then) => 1,
+            (TokenType::While | TokenType::For, TokenType::Do) => 1,
+            // The cases are a level deeper than TEST, their statements two
+            (TokenType::Test, _) => 2,
+            _ => 0,
+        };
+    }
+    lines
+}
+
+/// First token of a line, after LOCAL
+fn leading(tokens: &[Token]) -> &TokenType {
+    match tokens {
+        [local, next, ..] if local.token_type == TokenType::Local => &next.token_type,
+        [first, ..] => &first.token_type,
+        [] => &TokenType::Newline,
+    }
+}
+
+/// Text of the tokens with the spacing of the source between them. The
+/// name after a `\` is kept as written, like `\Num` of an optional
+/// argument.
+fn spell(source: &str, tokens: &[Token], options: &FormatOptions) -> String {
+    let mut text = String::new();
+    for (idx, token) in tokens.iter().enumerate() {
+        if idx > 0 {
+            text.push_str(&source[tokens[idx - 1].span.end..token.span.start]);
+        }
+        if idx > 0 && tokens[idx - 1].token_type == TokenType::Backslash {
+            text.push_str(&source[token.span.start..token.span.end]);
+        } else {
+            text.push_str(&spell_token(source, token, options));
+        }
+    }
+    text
+}
+
+fn spell_token(source: &str, token: &Token, options: &FormatOptions) -> String {
+    let text = &source[token.span.start..token.span.end];
+    let keyword = !matches!(token.token_type, TokenType::Id(_) | TokenType::NumValue(_) | TokenType::StringValue(_) | TokenType::TpWrite)
+        && lexer::keyword(&token.token_type).starts_with(|c: char| c.is_ascii_alphabetic());
+    match options.keyword_case {
+        KeywordCase::Upper if keyword && matches!(token.token_type, TokenType::NumType | TokenType::StringType | TokenType::BoolType) => text.to_ascii_lowercase(),
+        KeywordCase::Upper if keyword => text.to_ascii_uppercase(),
+        KeywordCase::Lower if keyword => text.to_ascii_lowercase(),
+        _ => String::from(text),
+    }
+}
+
+// ------------------ Declarations -----------------------/
+
+/// Data declaration on a line of its own, split in the columns it's aligned by
+struct Declaration<'t> {
+    // LOCAL and the storage keyword
+    storage: &'t [Token],
+    data_type: &'t Token,
+    // Name and dimensions
+    name: &'t [Token],
+    // From := to the end, or the semicolon
+    rest: &'t [Token],
+}
+
+fn declaration(tokens: &[Token]) -> Option<Declaration<'_>> {
+    let storage = match tokens.first()?.token_type {
+        TokenType::Local => 2,
+        _ => 1,
+    };
+    if !matches!(tokens.get(storage - 1)?.token_type, TokenType::Var | TokenType::Pers | TokenType::Const) {
+        return None;
+    }
+    // One statement on the line
+    if tokens.iter().filter(|token| token.token_type == TokenType::Semicolon).count() != 1
+        || tokens[tokens.len() - 1].token_type != TokenType::Semicolon {
+        return None;
+    }
+    let name_end = tokens.iter().position(|token| matches!(token.token_type, TokenType::Assign | TokenType::Semicolon))?;
+    if name_end < storage + 2 {
+        return None;
+    }
+    Some(Declaration {
+        storage: &tokens[..storage],
+        data_type: &tokens[storage],
+        name: &tokens[storage + 1..name_end],
+        rest: &tokens[name_end..],
+    })
+}
+
+impl<'t> Declaration<'t> {
+    fn render(&self, source: &str, run: &[Declaration], options: &FormatOptions) -> String {
+        let storage = |declaration: &Declaration| declaration.storage.iter()
+            .map(|token| spell_token(source, token, options))
+            .collect::<Vec<_>>().join(" ");
+        let width = |column: &dyn Fn(&Declaration) -> String| run.iter().map(|declaration| column(declaration).chars().count()).max().unwrap_or(0);
+        let data_type = |declaration: &Declaration| spell_token(source, declaration.data_type, options);
+        let name = |declaration: &Declaration| spell(source, declaration.name, options);
+
+        let mut text = format!("{:<storage_width$} {:<type_width$} ", storage(self), data_type(self),
+            storage_width = width(&storage), type_width = width(&data_type));
+        if self.rest[0].token_type == TokenType::Assign {
+            // Declarations without a value don't widen the names
+            let name_width = run.iter()
+                .filter(|declaration| declaration.rest[0].token_type == TokenType::Assign)
+                .map(|declaration| name(declaration).chars().count())
+                .max().unwrap_or(0);
+            text.push_str(&format!("{:<name_width$} := {}", name(self), spell(source, &self.rest[1..], options), name_width = name_width));
+        } else {
+            text.push_str(&name(self));
+            text.push(';');
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MESSY: &str = "%%%
+  VERSION: 1
+
+LANGUAGE:ENGLISH
+%%%
+module Cell(sysmodule)
+  var num nCount:=0;
+    local PERS string sPart := \"A\";
+const num nMax{2} := [1, 2];
+ VAR bool bDone;
+
+
+proc main()
+VAR num i;
+! Count up to the first
+if nCount < nMax{1} then
+nCount := nCount +
+1;   ! one more  
+elseif nCount > 3 THEN
+  TPWrite \"Big\";
+else
+   while not bDone do
+bDone := true;
+   endwhile
+endif
+test nCount
+case 1, 2:
+  sPart := \"B\";
+default:
+  sPart := \"C\";
+endtest
+IF bDone nCount := 0;
+error
+  TRYNEXT;
+endproc
+endmodule
+";
+
+    #[test]
+    fn indents_blocks() {
+        let formatted = format(MESSY, &FormatOptions::default()).unwrap();
+        assert_eq!(formatted, "%%%
+  VERSION:1
+  LANGUAGE:ENGLISH
+%%%
+MODULE Cell(sysmodule)
+    VAR        num    nCount  := 0;
+    LOCAL PERS string sPart   := \"A\";
+    CONST      num    nMax{2} := [1, 2];
+    VAR        bool   bDone;
+
+    PROC main()
+        VAR num i;
+        ! Count up to the first
+        IF nCount < nMax{1} THEN
+            nCount := nCount +
+                1;   ! one more
+        ELSEIF nCount > 3 THEN
+            TPWrite \"Big\";
+        ELSE
+            WHILE NOT bDone DO
+                bDone := TRUE;
+            ENDWHILE
+        ENDIF
+        TEST nCount
+            CASE 1, 2:
+                sPart := \"B\";
+            DEFAULT:
+                sPart := \"C\";
+        ENDTEST
+        IF bDone nCount := 0;
+    ERROR
+        TRYNEXT;
+    ENDPROC
+ENDMODULE
+");
+        // Formatting again changes nothing
+        assert_eq!(format(&formatted, &FormatOptions::default()).unwrap(), formatted);
+
+        let options = FormatOptions { indent: 2, keyword_case: KeywordCase::Keep, align_declarations: false };
+        let kept = format(MESSY, &options).unwrap();
+        assert!(kept.contains("\n  var num nCount:=0;\n  local PERS string sPart := \"A\";\n"));
+        assert!(kept.contains("\n  proc main()\n    VAR num i;\n    ! Count up to the first\n    if nCount < nMax{1} then\n"));
+        assert!(format("MODULE Cell\n PROC main(\nENDMODULE", &options).is_err());
+    }
+
+    #[test]
+    fn keeps_optional_argument_names() {
+        let source = "MODULE Cell
+proc rMove(\\num Speed | switch Fast)
+endproc
+proc main()
+rMove \\Speed:=100;
+rMove \\Fast;
+TPWrite \"Count\" \\Num:=1;
+MoveL p10, v100, z10, tool0 \\WObj:=wobj0;
+endproc
+ENDMODULE
+";
+        assert_eq!(format(source, &FormatOptions::default()).unwrap(), "MODULE Cell
+    PROC rMove(\\num Speed | switch Fast)
+    ENDPROC
+    PROC main()
+        rMove \\Speed:=100;
+        rMove \\Fast;
+        TPWrite \"Count\" \\Num:=1;
+        MoveL p10, v100, z10, tool0 \\WObj:=wobj0;
+    ENDPROC
+ENDMODULE
+");
+    }
+}
@@ -0,0 +1,274 @@
+use crate::builtins;
+use crate::json::Json;
+use crate::parser::{Arena, Callee, Node, Program, Routine, Statement};
+
+// ------------------ Options -----------------------/
+
+#[derive(Debug, Clone, Default)]
+pub struct GraphOptions {
+    /// Add the built-in routines and those of the host that are called
+    pub builtins: bool,
+}
+
+// ------------------ Calls -----------------------/
+
+/// Routine a call goes to, as the resolver bound it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Call(Callee),
+    // Call of a procedure a string names, like `%sName%`
+    Late,
+}
+
+/// Calls of the routines of a resolved program by caller, module and
+/// routine index, each callee once in the order of the first call
+pub fn calls(program: &Program) -> Vec<((usize, usize), Vec<Target>)> {
+    let mut calls = Vec::new();
+    for (module_idx, module) in program.modules.iter().enumerate() {
+        for (routine_idx, routine) in module.routines.iter().enumerate() {
+            let mut targets = Vec::new();
+            for node in routine_nodes(routine) {
+                let target = match node {
+                    Node::ProcCall { target: Some(callee), .. } | Node::FuncCall { target: Some(callee), .. }
+                    | Node::Connect { target: Some(callee), .. } => Target::Call(*callee),
+                    Node::LateCall { .. } => Target::Late,
+                    _ => continue,
+                };
+                if !targets.contains(&target) {
+                    targets.push(target);
+                }
+            }
+            calls.push(((module_idx, routine_idx), targets));
+        }
+    }
+    calls
+}
+
+/// Nodes of the statements of a routine and its error handler
+pub fn routine_nodes(routine: &Routine) -> Vec<&Node> {
+    fn add<'a>(body: &'a [Statement], arena: &'a Arena, all: &mut Vec<&'a Node>) {
+        for statement in body {
+            let mut nodes = vec![&statement.node];
+            while let Some(node) = nodes.pop() {
+                all.push(node);
+                nodes.extend(node.children(arena));
+            }
+            for inner in statement.node.bodies() {
+                add(inner, arena, all);
+            }
+        }
+    }
+    let mut all = Vec::new();
+    add(&routine.statements, &routine.nodes, &mut all);
+    if let Some(handler) = &routine.handler {
+        add(handler, &routine.nodes, &mut all);
+    }
+    all
+}
+
+/// Module and data index of a global data slot, the system data comes
+/// before the data of the modules
+fn slot_data(program: &Program, slot: usize) -> Option<(usize, usize)> {
+    let mut first = program.system.len();
+    for (idx, module) in program.modules.iter().enumerate() {
+        if slot < first + module.variables.len() {
+            return slot.checked_sub(first).map(|data| (idx, data));
+        }
+        first += module.variables.len();
+    }
+    None
+}
+
+// ------------------ Dependencies -----------------------/
+
+/// Routines and data of a module that the routines of another module use
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dependency {
+    pub from: usize,
+    pub to: usize,
+    // Names of what is used, each once in the order of the first use
+    pub routines: Vec<String>,
+    pub data: Vec<String>,
+}
+
+/// Modules of a resolved program that use other modules, in the order of
+/// the modules and the first use
+pub fn dependencies(program: &Program) -> Vec<Dependency> {
+    let mut dependencies: Vec<Dependency> = Vec::new();
+    for (module_idx, module) in program.modules.iter().enumerate() {
+        for routine in module.routines.iter() {
+            for node in routine_nodes(routine) {
+                let (to, routine, data) = match node {
+                    Node::ProcCall { target: Some(Callee::Routine(callee, routine)), .. }
+                    | Node::FuncCall { target: Some(Callee::Routine(callee, routine)), .. } => (*callee, Some(*routine), None),
+                    Node::Global(slot) => match slot_data(program, *slot) {
+                        Some((to, data)) => (to, None, Some(data)),
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+                if to == module_idx {
+                    continue;
+                }
+                let idx = match dependencies.iter().position(|dependency| (dependency.from, dependency.to) == (module_idx, to)) {
+                    Some(idx) => idx,
+                    None => {
+                        dependencies.push(Dependency { from: module_idx, to, routines: Vec::new(), data: Vec::new() });
+                        dependencies.len() - 1
+                    },
+                };
+                let dependency = &mut dependencies[idx];
+                let used = &program.modules[to];
+                if let Some(name) = routine.map(|routine| &used.routines[routine].name).filter(|name| !dependency.routines.contains(name)) {
+                    dependency.routines.push(name.clone());
+                }
+                if let Some(name) = data.map(|data| &used.variables[data].name).filter(|name| !dependency.data.contains(name)) {
+                    dependency.data.push(name.clone());
+                }
+            }
+        }
+    }
+    dependencies
+}
+
+/// Line for each dependency, like `Cell -> Pick: PickPart, nCount`
+pub fn dependency_list(program: &Program) -> String {
+    let mut text = String::new();
+    for dependency in dependencies(program) {
+        let used: Vec<&str> = dependency.routines.iter().chain(dependency.data.iter()).map(String::as_str).collect();
+        text.push_str(&format!("{} -> {}: {}\n", program.modules[dependency.from].name, program.modules[dependency.to].name, used.join(", ")));
+    }
+    text
+}
+
+impl Dependency {
+    pub fn to_json(&self, program: &Program) -> Json {
+        let names = |names: &[String]| Json::Array(names.iter().map(|name| Json::str(name)).collect());
+        Json::object(vec![
+            ("from", Json::str(&program.modules[self.from].name)),
+            ("to", Json::str(&program.modules[self.to].name)),
+            ("routines", names(&self.routines)),
+            ("data", names(&self.data)),
+        ])
+    }
+}
+
+// ------------------ DOT -----------------------/
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Call graph of a resolved program in the DOT language of Graphviz. The
+/// routines of each module are drawn in a box, calls by a late bound name go
+/// to a node of their own.
+pub fn call_graph(program: &Program, options: &GraphOptions) -> String {
+    let routine_id = |module: usize, routine: usize| quote(&format!("{}.{}", program.modules[module].name, program.modules[module].routines[routine].name));
+    let mut dot = String::from("digraph calls {\n    rankdir=LR;\n    node [shape=box];\n");
+    for (idx, module) in program.modules.iter().enumerate() {
+        dot.push_str(&format!("    subgraph {} {{\n        label={};\n", quote(&format!("cluster_{}", idx)), quote(&module.name)));
+        if module.system {
+            dot.push_str("        style=dashed;\n");
+        }
+        for (routine_idx, routine) in module.routines.iter().enumerate() {
+            dot.push_str(&format!("        {} [label={}];\n", routine_id(idx, routine_idx), quote(&routine.name)));
+        }
+        dot.push_str("    }\n");
+    }
+
+    let mut natives: Vec<String> = Vec::new();
+    let mut late = false;
+    for ((module, routine), targets) in calls(program) {
+        for target in targets {
+            let callee = match target {
+                Target::Call(Callee::Routine(callee_module, callee_routine)) => routine_id(callee_module, callee_routine),
+                Target::Call(Callee::Builtin(idx)) if options.builtins => quote(builtins::builtins()[idx].name),
+                Target::Call(Callee::Host(idx)) if options.builtins => quote(&program.host_routines[idx].name),
+                Target::Call(_) => continue,
+                Target::Late => {
+                    late = true;
+                    quote("%late%")
+                },
+            };
+            dot.push_str(&format!("    {} -> {};\n", routine_id(module, routine), callee));
+            if matches!(target, Target::Call(Callee::Builtin(_)) | Target::Call(Callee::Host(_))) && !natives.contains(&callee) {
+                natives.push(callee);
+            }
+        }
+    }
+    for native in natives {
+        dot.push_str(&format!("    {} [shape=ellipse];\n", native));
+    }
+    if late {
+        dot.push_str("    \"%late%\" [label=\"late bound\", shape=plaintext];\n");
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Modules of a resolved program in the DOT language of Graphviz, with an
+/// edge to each module whose routines or data a module uses
+pub fn module_graph(program: &Program) -> String {
+    let mut dot = String::from("digraph modules {\n    node [shape=box];\n");
+    for module in program.modules.iter() {
+        let style = if module.system { " [style=dashed]" } else { "" };
+        dot.push_str(&format!("    {}{};\n", quote(&module.name), style));
+    }
+    for dependency in dependencies(program) {
+        dot.push_str(&format!("    {} -> {};\n", quote(&program.modules[dependency.from].name), quote(&program.modules[dependency.to].name)));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lexer, parser, resolver};
+
+    fn program() -> Program {
+        let mut program = parser::parse_tokens(lexer::parse("MODULE Cell
+    PERS num nCount := 0;
+    PROC Main()
+        Pick;
+        Pick;
+        WaitTime 1;
+        %\"rPart\" + \"A\"%;
+    ENDPROC
+ENDMODULE
+MODULE Pick
+    PROC Pick()
+        nCount := nCount + Twice(1);
+    ENDPROC
+    FUNC num Twice(num x)
+        RETURN 2 * x;
+    ENDFUNC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &resolver::ResolveOptions::default()).unwrap();
+        program
+    }
+
+    #[test]
+    fn draws_calls() {
+        let program = program();
+        let wait_time = builtins::builtins().iter().position(|builtin| builtin.name == "WaitTime").unwrap();
+        assert_eq!(calls(&program)[0].1, vec![Target::Call(Callee::Routine(1, 0)), Target::Call(Callee::Builtin(wait_time)), Target::Late]);
+
+        let dot = call_graph(&program, &GraphOptions::default());
+        assert!(dot.starts_with("digraph calls {\n"));
+        assert!(dot.contains("    subgraph \"cluster_1\" {\n        label=\"Pick\";\n        \"Pick.Pick\" [label=\"Pick\"];\n"));
+        assert!(dot.contains("    \"Cell.Main\" -> \"Pick.Pick\";\n    \"Cell.Main\" -> \"%late%\";\n    \"Pick.Pick\" -> \"Pick.Twice\";\n"));
+        assert!(!dot.contains("WaitTime"));
+        let dot = call_graph(&program, &GraphOptions { builtins: true });
+        assert!(dot.contains("    \"Cell.Main\" -> \"WaitTime\";\n"));
+        assert!(dot.contains("    \"WaitTime\" [shape=ellipse];\n"));
+
+        assert_eq!(dependencies(&program), vec![
+            Dependency { from: 0, to: 1, routines: vec![String::from("Pick")], data: Vec::new() },
+            Dependency { from: 1, to: 0, routines: Vec::new(), data: vec![String::from("nCount")] },
+        ]);
+        assert_eq!(dependency_list(&program), "Cell -> Pick: Pick\nPick -> Cell: nCount\n");
+        assert_eq!(dependencies(&program)[1].to_json(&program).to_string(), r#"{"from":"Pick","to":"Cell","routines":[],"data":["nCount"]}"#);
+        assert_eq!(module_graph(&program), "digraph modules {\n    node [shape=box];\n    \"Cell\";\n    \"Pick\";\n    \"Cell\" -> \"Pick\";\n    \"Pick\" -> \"Cell\";\n}\n");
+    }
+}
@@ -0,0 +1,553 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+use tonic::{Request, Response, Status};
+
+use crate::compiler;
+use crate::debugger::{self, Access, Debugger};
+use crate::embed::{self, RunOptions};
+use crate::host::{Clock, Host, IoBoard, ManualClock, OutputSink, TaskWait};
+use crate::interpreter::{InterpreterOptions, RuntimeError};
+use crate::json::ToJson;
+use crate::resolver::{ResolveOptions, Severity};
+use crate::variable::{SignalKind, Variable};
+use crate::vm::{self, Vm};
+
+// The interpreter control service of proto/rapid.proto, for debuggers and
+// digital twins that drive a program from another process. Each session
+// runs on a thread of its own, which owns the program and takes the
+// requests of the session in turn.
+
+include!(concat!(env!("OUT_DIR"), "/rapid.Interpreter.rs"));
+
+use interpreter_server::{Interpreter, InterpreterServer};
+
+// ------------------ Messages -----------------------/
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LoadRequest {
+    #[prost(string, tag = "1")]
+    pub source: String,
+    #[prost(map = "string, string", tag = "2")]
+    pub signals: HashMap<String, String>,
+    #[prost(string, tag = "3")]
+    pub entry: String,
+    #[prost(uint64, tag = "4")]
+    pub max_instructions: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Diagnostic {
+    #[prost(string, tag = "1")]
+    pub severity: String,
+    #[prost(string, tag = "2")]
+    pub message: String,
+    #[prost(uint32, tag = "3")]
+    pub line: u32,
+    #[prost(uint32, tag = "4")]
+    pub column: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LoadReply {
+    #[prost(uint64, tag = "1")]
+    pub session: u64,
+    #[prost(message, repeated, tag = "2")]
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SessionRequest {
+    #[prost(uint64, tag = "1")]
+    pub session: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct UnloadReply {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum StepKind {
+    StepInto = 0,
+    StepOver = 1,
+    StepOut = 2,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StepRequest {
+    #[prost(uint64, tag = "1")]
+    pub session: u64,
+    #[prost(enumeration = "StepKind", tag = "2")]
+    pub kind: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProgramPointer {
+    #[prost(string, tag = "1")]
+    pub module: String,
+    #[prost(string, tag = "2")]
+    pub routine: String,
+    #[prost(uint32, tag = "3")]
+    pub line: u32,
+    #[prost(uint32, tag = "4")]
+    pub column: u32,
+    #[prost(uint32, tag = "5")]
+    pub depth: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum State {
+    Paused = 0,
+    Waiting = 1,
+    Finished = 2,
+    Failed = 3,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExecutionReply {
+    #[prost(enumeration = "State", tag = "1")]
+    pub state: i32,
+    #[prost(message, optional, tag = "2")]
+    pub pp: Option<ProgramPointer>,
+    #[prost(string, repeated, tag = "3")]
+    pub output: Vec<String>,
+    #[prost(string, tag = "4")]
+    pub error: String,
+    #[prost(double, tag = "5")]
+    pub time: f64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Breakpoint {
+    #[prost(string, tag = "1")]
+    pub module: String,
+    #[prost(uint32, tag = "2")]
+    pub line: u32,
+    #[prost(string, tag = "3")]
+    pub routine: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BreakpointRequest {
+    #[prost(uint64, tag = "1")]
+    pub session: u64,
+    #[prost(message, optional, tag = "2")]
+    pub breakpoint: Option<Breakpoint>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct BreakpointsReply {
+    #[prost(message, repeated, tag = "1")]
+    pub breakpoints: Vec<Breakpoint>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct InspectRequest {
+    #[prost(uint64, tag = "1")]
+    pub session: u64,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(string, repeated, tag = "3")]
+    pub path: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct InspectReply {
+    #[prost(string, tag = "1")]
+    pub data_type: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+    #[prost(string, tag = "3")]
+    pub json: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SignalRequest {
+    #[prost(uint64, tag = "1")]
+    pub session: u64,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(double, optional, tag = "3")]
+    pub value: Option<f64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SignalReply {
+    #[prost(double, tag = "1")]
+    pub value: f64,
+}
+
+// ------------------ Service -----------------------/
+
+/// Serve the interpreter control service on an address until it fails
+pub async fn serve(address: SocketAddr) -> Result<(), String> {
+    tonic::transport::Server::builder()
+        .add_service(InterpreterServer::new(InterpreterService::default()))
+        .serve(address)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+/// Work for the thread of a session
+type Job = Box<dyn for<'a> FnOnce(&mut Session<'a>) + Send>;
+
+/// Sessions by their number, with the queue of their thread
+#[derive(Default)]
+pub struct InterpreterService {
+    sessions: Mutex<HashMap<u64, mpsc::Sender<Job>>>,
+    last: AtomicU64,
+}
+
+impl InterpreterService {
+    /// Do work on the thread of a session and answer with its result
+    async fn call<T: Send + 'static>(&self, session: u64, work: impl for<'a> FnOnce(&mut Session<'a>) -> Result<T, Status> + Send + 'static) -> Result<Response<T>, Status> {
+        let (reply, answer) = oneshot::channel();
+        let job: Job = Box::new(move |session| {
+            let _ = reply.send(work(session));
+        });
+        let queue = self.sessions.lock().unwrap().get(&session).cloned();
+        queue.and_then(|queue| queue.send(job).ok()).ok_or_else(|| Status::not_found(format!("No session {}", session)))?;
+        match answer.await {
+            Ok(result) => result.map(Response::new),
+            Err(_) => Err(Status::internal(format!("Session {} stopped", session))),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Interpreter for InterpreterService {
+    async fn load(&self, request: Request<LoadRequest>) -> Result<Response<LoadReply>, Status> {
+        let request = request.into_inner();
+        let number = self.last.fetch_add(1, Ordering::Relaxed) + 1;
+        let (queue, jobs) = mpsc::channel();
+        let (reply, loaded) = oneshot::channel();
+        thread::spawn(move || work(request, number, reply, jobs));
+        let reply = loaded.await.map_err(|_| Status::internal("Session stopped while loading"))??;
+        if reply.session != 0 {
+            self.sessions.lock().unwrap().insert(reply.session, queue);
+        }
+        Ok(Response::new(reply))
+    }
+
+    async fn unload(&self, request: Request<SessionRequest>) -> Result<Response<UnloadReply>, Status> {
+        let session = request.into_inner().session;
+        // The thread ends once its queue is gone
+        match self.sessions.lock().unwrap().remove(&session) {
+            Some(_) => Ok(Response::new(UnloadReply {})),
+            None => Err(Status::not_found(format!("No session {}", session))),
+        }
+    }
+
+    async fn run(&self, request: Request<SessionRequest>) -> Result<Response<ExecutionReply>, Status> {
+        self.call(request.into_inner().session, |session| Ok(session.execute(|debugger, host| debugger.resume(host)))).await
+    }
+
+    async fn step(&self, request: Request<StepRequest>) -> Result<Response<ExecutionReply>, Status> {
+        let request = request.into_inner();
+        let kind = StepKind::try_from(request.kind).map_err(|_| Status::invalid_argument(format!("Unknown step kind {}", request.kind)))?;
+        self.call(request.session, move |session| Ok(session.execute(|debugger, host| match kind {
+            StepKind::StepInto => debugger.step_into(host),
+            StepKind::StepOver => debugger.step_over(host),
+            StepKind::StepOut => debugger.step_out(host),
+        }))).await
+    }
+
+    async fn set_breakpoint(&self, request: Request<BreakpointRequest>) -> Result<Response<BreakpointsReply>, Status> {
+        let request = request.into_inner();
+        let breakpoint = to_breakpoint(request.breakpoint)?;
+        self.call(request.session, move |session| {
+            session.debugger.set_breakpoint(breakpoint).map_err(invalid)?;
+            Ok(session.breakpoints())
+        }).await
+    }
+
+    async fn clear_breakpoint(&self, request: Request<BreakpointRequest>) -> Result<Response<BreakpointsReply>, Status> {
+        let request = request.into_inner();
+        let breakpoint = to_breakpoint(request.breakpoint)?;
+        self.call(request.session, move |session| {
+            session.debugger.clear_breakpoint(&breakpoint);
+            Ok(session.breakpoints())
+        }).await
+    }
+
+    async fn inspect(&self, request: Request<InspectRequest>) -> Result<Response<InspectReply>, Status> {
+        let request = request.into_inner();
+        self.call(request.session, move |session| {
+            let indices: Vec<Option<Vec<usize>>> = request.path.iter()
+                .map(|part| part.split(',').map(|index| index.trim().parse().ok()).collect())
+                .collect();
+            let path: Vec<Access> = request.path.iter().zip(indices.iter())
+                .map(|(part, indices)| match indices {
+                    Some(indices) => Access::Element(indices),
+                    None => Access::Component(part),
+                })
+                .collect();
+            let value = session.debugger.read(&request.name, &path).map_err(invalid)?;
+            Ok(InspectReply {
+                data_type: String::from(value.type_name()),
+                value: value.to_string(),
+                json: value.to_json().to_string(),
+            })
+        }).await
+    }
+
+    async fn signal(&self, request: Request<SignalRequest>) -> Result<Response<SignalReply>, Status> {
+        let request = request.into_inner();
+        self.call(request.session, move |session| {
+            if let Some(value) = request.value {
+                session.io.set_input(&request.name, value).map_err(invalid)?;
+            }
+            Ok(SignalReply { value: session.io.read(&request.name).map_err(invalid)? })
+        }).await
+    }
+}
+
+fn invalid(err: RuntimeError) -> Status {
+    Status::invalid_argument(err.to_string())
+}
+
+/// Breakpoint on the routine when it's named, otherwise on the line
+fn to_breakpoint(breakpoint: Option<Breakpoint>) -> Result<debugger::Breakpoint, Status> {
+    match breakpoint {
+        Some(breakpoint) if !breakpoint.routine.is_empty() => Ok(debugger::Breakpoint::Routine(breakpoint.routine)),
+        Some(breakpoint) if !breakpoint.module.is_empty() => Ok(debugger::Breakpoint::Line { module: breakpoint.module, line: breakpoint.line as usize }),
+        _ => Err(Status::invalid_argument("Breakpoint without a module or routine")),
+    }
+}
+
+// ------------------ Session -----------------------/
+
+/// Program of a session, paused between the requests
+struct Session<'a> {
+    debugger: Debugger<'a>,
+    host: Host<'a>,
+    io: &'a IoBoard,
+    clock: &'a ManualClock,
+    written: Rc<RefCell<Vec<String>>>,
+    // Error the program stopped with, it can't go on after it
+    error: Option<String>,
+}
+
+/// Lines the program writes, until the next reply takes them
+struct Written(Rc<RefCell<Vec<String>>>);
+
+impl OutputSink for Written {
+    fn write(&mut self, line: &str) {
+        self.0.borrow_mut().push(String::from(line));
+    }
+}
+
+impl<'a> Session<'a> {
+    /// Take a step, again after each wait that ends at a known time. A wait
+    /// for an input that doesn't come leaves the session waiting.
+    fn execute(&mut self, mut step: impl FnMut(&mut Debugger<'a>, &mut Host<'a>) -> Result<vm::Status, RuntimeError>) -> ExecutionReply {
+        let state = match &self.error {
+            Some(_) => State::Failed,
+            None => loop {
+                match step(&mut self.debugger, &mut self.host) {
+                    Ok(vm::Status::Waiting { wake: Some(wake), .. }) => {
+                        let now = self.clock.now();
+                        self.clock.advance(wake.saturating_sub(now));
+                    },
+                    Ok(vm::Status::Waiting { wake: None, .. }) => break State::Waiting,
+                    Ok(vm::Status::Paused { .. }) => break State::Paused,
+                    Ok(vm::Status::Finished) => break State::Finished,
+                    Err(err) => {
+                        self.error = Some(err.to_string());
+                        break State::Failed;
+                    },
+                }
+            },
+        };
+        let pp = self.debugger.pp().filter(|_| self.error.is_none()).map(|pp| ProgramPointer {
+            module: pp.module,
+            routine: pp.routine,
+            line: pp.span.line as u32,
+            column: pp.span.column as u32,
+            depth: pp.depth as u32,
+        });
+        ExecutionReply {
+            state: state as i32,
+            pp,
+            output: self.written.borrow_mut().drain(..).collect(),
+            error: self.error.clone().unwrap_or_default(),
+            time: self.clock.now().as_secs_f64(),
+        }
+    }
+
+    fn breakpoints(&self) -> BreakpointsReply {
+        let breakpoints = self.debugger.breakpoints().iter()
+            .map(|breakpoint| match breakpoint {
+                debugger::Breakpoint::Line { module, line } => Breakpoint { module: module.clone(), line: *line as u32, routine: String::new() },
+                debugger::Breakpoint::Routine(routine) => Breakpoint { module: String::new(), line: 0, routine: routine.clone() },
+            })
+            .collect();
+        BreakpointsReply { breakpoints }
+    }
+}
+
+/// Load the module of a session and take its requests until the session is
+/// unloaded. A module that doesn't load is answered with its diagnostics.
+fn work(request: LoadRequest, number: u64, loaded: oneshot::Sender<Result<LoadReply, Status>>, jobs: mpsc::Receiver<Job>) {
+    let mut io = IoBoard::new();
+    for (name, data_type) in request.signals.iter() {
+        match Variable::from(data_type) {
+            Ok(Variable::Signal(kind @ (SignalKind::GroupInput | SignalKind::GroupOutput), _)) => io.define_group(name, kind, 32),
+            Ok(Variable::Signal(kind, _)) => io.define(name, kind),
+            _ => {
+                let _ = loaded.send(Err(Status::invalid_argument(format!("{} is not a signal type", data_type))));
+                return;
+            },
+        }
+    }
+    let diagnostic = |severity: Severity, message: &str, line: usize, column: usize| Diagnostic {
+        severity: String::from(if severity == Severity::Error { "error" } else { "warning" }),
+        message: String::from(message),
+        line: line as u32,
+        column: column as u32,
+    };
+    let (mut program, warnings) = match embed::resolve(&request.source, &io, &ResolveOptions::default()) {
+        Ok(resolved) => resolved,
+        Err(findings) => {
+            let diagnostics = findings.iter()
+                .map(|finding| {
+                    let span = finding.span.unwrap_or_default();
+                    diagnostic(finding.severity, &finding.message, span.line, span.column)
+                })
+                .collect();
+            let _ = loaded.send(Ok(LoadReply { session: 0, diagnostics }));
+            return;
+        },
+    };
+    let bytecode = match compiler::compile(&program) {
+        Ok(bytecode) => bytecode,
+        Err(err) => {
+            let _ = loaded.send(Err(invalid(err)));
+            return;
+        },
+    };
+
+    let entry = if request.entry.is_empty() { "main" } else { &request.entry };
+    let max_instructions = match request.max_instructions {
+        0 => RunOptions::default().max_instructions,
+        max => max,
+    };
+    let options = InterpreterOptions { max_instructions: Some(max_instructions), ..InterpreterOptions::default() };
+    let globals = std::mem::take(&mut program.variables);
+    let vm = match Vm::new(&bytecode, &program, globals, entry, &options) {
+        Ok(vm) => vm,
+        Err((err, _)) => {
+            let _ = loaded.send(Err(invalid(err)));
+            return;
+        },
+    };
+    let clock = ManualClock::new(Duration::ZERO);
+    let written = Rc::new(RefCell::new(Vec::new()));
+    let mut output = Written(written.clone());
+    let mut host = Host::new(&mut output).with_clock(&clock).with_io(&io);
+    // Waits give the client a turn, like the scheduler gives other tasks one
+    host.task = Some(TaskWait { name: String::from("T_ROB1"), ..TaskWait::default() });
+    let mut session = Session { debugger: Debugger::new(vm), host, io: &io, clock: &clock, written, error: None };
+
+    let diagnostics = warnings.iter()
+        .map(|warning| diagnostic(warning.severity, &warning.message, warning.span.line, warning.span.column))
+        .collect();
+    if loaded.send(Ok(LoadReply { session: number, diagnostics })).is_err() {
+        return;
+    }
+    for job in jobs {
+        job(&mut session);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SOURCE: &str = "
+MODULE Cell
+    VAR num nParts := 0;
+    VAR pos pPick := [100, 0, 50];
+    PROC rPick()
+        nParts := nParts + 1;
+        TPWrite \"Picked \" \\Num:=nParts;
+    ENDPROC
+    PROC main()
+        WaitDI diPartReady, 1;
+        rPick;
+        SetDO doGripper, 1;
+        WaitTime 0.5;
+        rPick;
+    ENDPROC
+ENDMODULE";
+
+    async fn load(service: &InterpreterService) -> u64 {
+        let signals = [("diPartReady", "signaldi"), ("doGripper", "signaldo")].iter()
+            .map(|(name, data_type)| (String::from(*name), String::from(*data_type)))
+            .collect();
+        let request = LoadRequest { source: String::from(SOURCE), signals, entry: String::new(), max_instructions: 0 };
+        service.load(Request::new(request)).await.unwrap().into_inner().session
+    }
+
+    fn state(reply: &ExecutionReply) -> (State, Option<(String, u32)>) {
+        (State::try_from(reply.state).unwrap(), reply.pp.as_ref().map(|pp| (pp.routine.clone(), pp.line)))
+    }
+
+    #[tokio::test]
+    async fn drives_a_session() {
+        let service = InterpreterService::default();
+        let session = load(&service).await;
+        let run = || service.run(Request::new(SessionRequest { session }));
+
+        let breakpoint = BreakpointRequest { session, breakpoint: Some(Breakpoint { module: String::new(), line: 0, routine: String::from("rPick") }) };
+        let breakpoints = service.set_breakpoint(Request::new(breakpoint.clone())).await.unwrap().into_inner();
+        assert_eq!(breakpoints.breakpoints.len(), 1);
+        let reply = run().await.unwrap().into_inner();
+        assert_eq!(state(&reply), (State::Waiting, Some((String::from("main"), 10))));
+
+        let input = SignalRequest { session, name: String::from("diPartReady"), value: Some(1.0) };
+        assert_eq!(service.signal(Request::new(input)).await.unwrap().into_inner().value, 1.0);
+        let reply = run().await.unwrap().into_inner();
+        assert_eq!(state(&reply), (State::Paused, Some((String::from("rPick"), 6))));
+        let step = StepRequest { session, kind: StepKind::StepOver as i32 };
+        let reply = service.step(Request::new(step)).await.unwrap().into_inner();
+        assert_eq!(state(&reply), (State::Paused, Some((String::from("rPick"), 7))));
+
+        let inspect = |name: &str, path: &[&str]| InspectRequest { session, name: String::from(name), path: path.iter().map(|part| String::from(*part)).collect() };
+        let value = service.inspect(Request::new(inspect("nParts", &[]))).await.unwrap().into_inner();
+        assert_eq!((value.data_type.as_str(), value.value.as_str(), value.json.as_str()), ("num", "1", "1"));
+        let value = service.inspect(Request::new(inspect("pPick", &["z"]))).await.unwrap().into_inner();
+        assert_eq!(value.value, "50");
+        assert_eq!(service.inspect(Request::new(inspect("nNone", &[]))).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+
+        service.clear_breakpoint(Request::new(breakpoint)).await.unwrap();
+        let reply = run().await.unwrap().into_inner();
+        assert_eq!((state(&reply), reply.time), ((State::Finished, None), 0.5));
+        assert_eq!(reply.output, vec![String::from("Picked 1"), String::from("Picked 2")]);
+        let output = SignalRequest { session, name: String::from("doGripper"), value: None };
+        assert_eq!(service.signal(Request::new(output)).await.unwrap().into_inner().value, 1.0);
+
+        service.unload(Request::new(SessionRequest { session })).await.unwrap();
+        assert_eq!(run().await.unwrap_err().code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn reports_modules_that_dont_load() {
+        let service = InterpreterService::default();
+        let request = LoadRequest { source: String::from("MODULE Cell\n    PROC main()\n        nCount := 1;\n    ENDPROC\nENDMODULE"), ..LoadRequest::default() };
+        let reply = service.load(Request::new(request)).await.unwrap().into_inner();
+        assert_eq!(reply.session, 0);
+        assert_eq!(reply.diagnostics, vec![Diagnostic { severity: String::from("error"), message: String::from("Unknown id 'nCount'"), line: 3, column: 9 }]);
+        let breakpoint = BreakpointRequest { session: 1, breakpoint: None };
+        assert_eq!(service.set_breakpoint(Request::new(breakpoint)).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::lexer::{self, TokenType};
+use crate::variable::Variable;
+
+// ------------------ Classes -----------------------/
+
+/// Kind of source text, for coloring
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Class {
+    Keyword,
+    // Data types, built in or records
+    Type,
+    // Numbers, strings, TRUE and FALSE
+    Literal,
+    // Text the lexer skips, like the header of older controllers
+    Comment,
+    Identifier,
+    Operator,
+    Whitespace,
+}
+
+impl Class {
+    /// Name of the class, the class of its HTML span
+    pub fn name(self) -> &'static str {
+        match self {
+            Class::Keyword => "keyword",
+            Class::Type => "type",
+            Class::Literal => "literal",
+            Class::Comment => "comment",
+            Class::Identifier => "identifier",
+            Class::Operator => "operator",
+            Class::Whitespace => "whitespace",
+        }
+    }
+
+    fn color(self) -> Option<&'static str> {
+        match self {
+            Class::Keyword => Some("\x1b[1;34m"),
+            Class::Type => Some("\x1b[36m"),
+            Class::Literal => Some("\x1b[35m"),
+            Class::Comment => Some("\x1b[90m"),
+            Class::Identifier | Class::Operator | Class::Whitespace => None,
+        }
+    }
+}
+
+/// Source split in parts of a class, as byte ranges. Together they are
+/// the whole source.
+pub fn classify(source: &str) -> Result<Vec<(Class, usize, usize)>, String> {
+    let tokens = lexer::tokenize(source).map_err(|err| err.to_string())?;
+    let mut parts = Vec::new();
+    let mut end = 0;
+    for token in tokens.iter() {
+        gap(source, end, token.span.start, &mut parts);
+        let class = match &token.token_type {
+            TokenType::NumValue(_) | TokenType::StringValue(_) | TokenType::True | TokenType::False => Class::Literal,
+            TokenType::NumType | TokenType::StringType | TokenType::BoolType => Class::Type,
+            TokenType::Id(name) if Variable::from(name).is_ok() => Class::Type,
+            TokenType::Id(_) | TokenType::TpWrite => Class::Identifier,
+            token_type if lexer::keyword(token_type).starts_with(|c: char| c.is_ascii_alphabetic()) => Class::Keyword,
+            _ => Class::Operator,
+        };
+        parts.push((class, token.span.start, token.span.end));
+        end = token.span.end;
+    }
+    gap(source, end, source.len(), &mut parts);
+    Ok(parts)
+}
+
+/// Whitespace and the text the lexer skipped between tokens
+fn gap(source: &str, start: usize, end: usize, parts: &mut Vec<(Class, usize, usize)>) {
+    let mut idx = start;
+    while idx < end {
+        let whitespace = source[idx..end].starts_with(char::is_whitespace);
+        let length = source[idx..end].find(|c: char| c.is_whitespace() != whitespace).unwrap_or(end - idx);
+        parts.push((if whitespace { Class::Whitespace } else { Class::Comment }, idx, idx + length));
+        idx += length;
+    }
+}
+
+// ------------------ Output -----------------------/
+
+/// Source with the escape codes of terminal colors
+pub fn ansi(source: &str) -> Result<String, String> {
+    let mut text = String::new();
+    for (class, start, end) in classify(source)? {
+        match class.color() {
+            Some(color) => text.push_str(&format!("{}{}\x1b[0m", color, &source[start..end])),
+            None => text.push_str(&source[start..end]),
+        }
+    }
+    Ok(text)
+}
+
+/// Source as a `pre` element of HTML, with a span for each part with the
+/// name of its class
+pub fn html(source: &str) -> Result<String, String> {
+    let mut text = String::from("<pre class=\"rapid\">");
+    for (class, start, end) in classify(source)? {
+        let part = escape(&source[start..end]);
+        match class {
+            Class::Whitespace => text.push_str(&part),
+            _ => text.push_str(&format!("<span class=\"{}\">{}</span>", class.name(), part)),
+        }
+    }
+    text.push_str("</pre>\n");
+    Ok(text)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn classifies_tokens() {
+        let source = "%%%\n  VERSION:1\n%%%\nMODULE Cell\n    PERS robtarget pHome;\n    VAR num n := 1;\n    PROC Main()\n        IF n <> 2 TPWrite \"<b>\";\n    ENDPROC\nENDMODULE";
+        let parts = classify(source).unwrap();
+        assert_eq!(parts.iter().map(|(_, start, end)| end - start).sum::<usize>(), source.len());
+        let class = |text: &str| parts.iter().find(|(_, start, end)| &source[*start..*end] == text).map(|(class, _, _)| *class);
+        assert_eq!(class("%%%"), Some(Class::Comment));
+        assert_eq!(class("VERSION:1"), Some(Class::Comment));
+        assert_eq!(class("MODULE"), Some(Class::Keyword));
+        assert_eq!(class("Cell"), Some(Class::Identifier));
+        assert_eq!(class("robtarget"), Some(Class::Type));
+        assert_eq!(class("num"), Some(Class::Type));
+        assert_eq!(class("1"), Some(Class::Literal));
+        assert_eq!(class("\"<b>\""), Some(Class::Literal));
+        assert_eq!(class(":="), Some(Class::Operator));
+        assert_eq!(class("TPWrite"), Some(Class::Identifier));
+
+        let html = html(source).unwrap();
+        assert!(html.starts_with("<pre class=\"rapid\"><span class=\"comment\">%%%</span>\n  <span class=\"comment\">VERSION:1</span>"));
+        assert!(html.contains("<span class=\"operator\">&lt;&gt;</span> <span class=\"literal\">2</span>"));
+        assert!(html.contains("<span class=\"literal\">&quot;&lt;b&gt;&quot;</span>"));
+        let ansi = ansi(source).unwrap();
+        assert!(ansi.contains("\x1b[1;34mPROC\x1b[0m Main()"));
+        assert!(classify("MODULE Cell\n    n := 1 $;\nENDMODULE").is_err());
+    }
+}
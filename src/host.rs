@@ -0,0 +1,877 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::net::TcpStream;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::compiler::DataName;
+use crate::interpreter::RuntimeError;
+use crate::lexer::Span;
+use crate::loader::{LoadedModule, ModuleChange};
+use crate::parser::{DataDecl, Program, Storage, WriteArg};
+use crate::variable::{SignalKind, Variable};
+
+// ------------------ Output -----------------------/
+
+/// Receives the lines a program writes to the FlexPendant with TPWrite
+pub trait OutputSink {
+    fn write(&mut self, line: &str);
+}
+
+/// Writes FlexPendant output to stdout
+pub struct Stdout;
+
+impl OutputSink for Stdout {
+    fn write(&mut self, line: &str) {
+        println!("{}", line);
+    }
+}
+
+/// Collects the written lines, for hosts that assert on the output
+impl OutputSink for Vec<String> {
+    fn write(&mut self, line: &str) {
+        self.push(String::from(line));
+    }
+}
+
+// ------------------ Input -----------------------/
+
+/// Answers the operator dialogs of TPReadNum and TPReadFK
+pub trait InputProvider {
+    /// Number entered at the prompt, `None` when the operator doesn't answer
+    fn read_num(&mut self, prompt: &str) -> Option<f64>;
+    /// Function key pressed at the prompt, numbered from 1 like the labels in `keys`
+    fn read_fk(&mut self, prompt: &str, keys: &[String]) -> Option<usize>;
+}
+
+/// Asks the operator on stdin, end of input means no answer
+pub struct Stdin;
+
+impl Stdin {
+    fn read_number(prompt: &str) -> Option<f64> {
+        let stdin = io::stdin();
+        let mut line = String::new();
+        loop {
+            print!("{} ", prompt);
+            io::stdout().flush().ok()?;
+            line.clear();
+            if stdin.lock().read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            match line.trim().parse() {
+                Ok(value) => return Some(value),
+                Err(_) => println!("Not a number: {}", line.trim()),
+            }
+        }
+    }
+}
+
+impl InputProvider for Stdin {
+    fn read_num(&mut self, prompt: &str) -> Option<f64> {
+        Stdin::read_number(prompt)
+    }
+
+    fn read_fk(&mut self, prompt: &str, keys: &[String]) -> Option<usize> {
+        let labels: Vec<String> = keys.iter().enumerate()
+            .filter(|(_, key)| !key.is_empty())
+            .map(|(idx, key)| format!("[{}] {}", idx + 1, key))
+            .collect();
+        Stdin::read_number(&format!("{} {}", prompt, labels.join(" "))).map(|key| key as usize)
+    }
+}
+
+/// Scripted answers, taken in order by both dialogs. TPReadFK reads the
+/// number of the function key.
+impl InputProvider for VecDeque<f64> {
+    fn read_num(&mut self, _prompt: &str) -> Option<f64> {
+        self.pop_front()
+    }
+
+    fn read_fk(&mut self, _prompt: &str, _keys: &[String]) -> Option<usize> {
+        self.pop_front().map(|key| key as usize)
+    }
+}
+
+// ------------------ Clock -----------------------/
+
+/// Time source of the clock instructions, as time since an arbitrary start
+pub trait Clock {
+    fn now(&self) -> Duration;
+    /// Let time pass, for instructions that wait
+    fn sleep(&self, duration: Duration);
+}
+
+/// Wall clock time since the first reading in this process
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        static START: OnceLock<Instant> = OnceLock::new();
+        START.get_or_init(Instant::now).elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Simulated time, moved by the host or by a fixed step on every reading
+pub struct ManualClock {
+    now: Cell<Duration>,
+    step: Duration,
+}
+
+impl ManualClock {
+    pub fn new(step: Duration) -> ManualClock {
+        ManualClock { now: Cell::new(Duration::ZERO), step }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        let now = self.now.get();
+        self.advance(self.step);
+        now
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+// ------------------ I/O signals -----------------------/
+
+/// Signal of the I/O board with its current value
+struct Signal {
+    name: String,
+    kind: SignalKind,
+    // Width of a group signal
+    bits: u32,
+    value: Cell<f64>,
+    // Whether an interrupt is ordered for its changes
+    watched: Cell<bool>,
+}
+
+/// Digital, analog and group signals of the simulated I/O system. The host
+/// defines the signals, sets inputs while the program runs and reads the
+/// outputs it set.
+#[derive(Default)]
+pub struct IoBoard {
+    signals: Vec<Signal>,
+    // Input changes the host scheduled, in the order they were scheduled
+    scheduled: RefCell<Vec<(Duration, String, f64)>>,
+    // Changes of the watched signals that the interrupts didn't see yet
+    changes: RefCell<Vec<(String, f64)>>,
+}
+
+impl IoBoard {
+    pub fn new() -> IoBoard {
+        IoBoard::default()
+    }
+
+    /// Define a digital or analog signal, its value starts at 0
+    pub fn define(&mut self, name: &str, kind: SignalKind) {
+        self.define_group(name, kind, 1);
+    }
+
+    /// Define a group signal that combines `bits` digital signals
+    pub fn define_group(&mut self, name: &str, kind: SignalKind, bits: u32) {
+        self.signals.push(Signal { name: String::from(name), kind, bits, value: Cell::new(0.0), watched: Cell::new(false) });
+    }
+
+    /// Declare the signals as system data of the program, before it's resolved
+    pub fn declare(&self, program: &mut Program) {
+        for signal in self.signals.iter() {
+            program.system.push(DataDecl {
+                name: signal.name.clone(),
+                data_type: String::from(signal.kind.type_name()),
+                storage: Storage::Var,
+                local: false,
+                value: Variable::Signal(signal.kind, signal.name.clone()),
+                init: None,
+                span: Span::default(),
+            });
+        }
+    }
+
+    fn signal(&self, name: &str) -> Result<&Signal, RuntimeError> {
+        match self.signals.iter().find(|signal| !name.is_empty() && signal.name.eq_ignore_ascii_case(name)) {
+            Some(signal) => Ok(signal),
+            None => Err(RuntimeError::UnknownSignal { name: String::from(name), span: Span::default() }),
+        }
+    }
+
+    /// Current value of a signal
+    pub fn read(&self, name: &str) -> Result<f64, RuntimeError> {
+        Ok(self.signal(name)?.value.get())
+    }
+
+    /// Set a signal to a value in its range: 0 or 1 for a digital signal,
+    /// an unsigned integer of its width for a group
+    pub fn write(&self, name: &str, value: f64) -> Result<(), RuntimeError> {
+        let signal = self.signal(name)?;
+        let valid = match signal.kind {
+            SignalKind::AnalogInput | SignalKind::AnalogOutput => value.is_finite(),
+            _ => value == value.trunc() && value >= 0.0 && value < 2f64.powi(signal.bits as i32),
+        };
+        if !valid {
+            return Err(RuntimeError::type_mismatch(format!("Value {} out of range for {} {}", value, signal.kind.type_name(), signal.name)));
+        }
+        if signal.watched.get() && signal.value.get() != value {
+            self.changes.borrow_mut().push((signal.name.clone(), value));
+        }
+        signal.value.set(value);
+        Ok(())
+    }
+
+    /// Set an input signal, like the process connected to the controller does
+    pub fn set_input(&self, name: &str, value: f64) -> Result<(), RuntimeError> {
+        let signal = self.signal(name)?;
+        if !signal.kind.input() {
+            return Err(RuntimeError::type_mismatch(format!("{} is not an input signal", signal.name)));
+        }
+        self.write(name, value)
+    }
+
+    /// Set an input signal once the clock of the run reaches `at`
+    pub fn set_input_at(&self, name: &str, value: f64, at: Duration) -> Result<(), RuntimeError> {
+        if !self.signal(name)?.kind.input() {
+            return Err(RuntimeError::type_mismatch(format!("{} is not an input signal", name)));
+        }
+        self.scheduled.borrow_mut().push((at, String::from(name), value));
+        Ok(())
+    }
+
+    /// Apply the scheduled input changes that are due at `now`, in the order
+    /// of their time
+    pub fn update(&self, now: Duration) -> Result<(), RuntimeError> {
+        let mut due: Vec<(Duration, String, f64)> = {
+            let mut scheduled = self.scheduled.borrow_mut();
+            let (due, pending) = scheduled.drain(..).partition(|(at, _, _)| *at <= now);
+            *scheduled = pending;
+            due
+        };
+        due.sort_by_key(|(at, _, _)| *at);
+        for (_, name, value) in due {
+            self.set_input(&name, value)?;
+        }
+        Ok(())
+    }
+
+    /// Keep the changes of a signal for `changes`, from now on
+    pub fn watch(&self, name: &str) -> Result<(), RuntimeError> {
+        self.signal(name)?.watched.set(true);
+        Ok(())
+    }
+
+    /// Changes of the watched signals since the last call, in the order
+    /// they were made
+    pub fn changes(&self) -> Vec<(String, f64)> {
+        std::mem::take(&mut *self.changes.borrow_mut())
+    }
+
+    /// Time of the next scheduled change of a signal
+    pub fn next_change(&self, name: &str) -> Option<Duration> {
+        self.scheduled.borrow().iter()
+            .filter(|(_, signal, _)| signal.eq_ignore_ascii_case(name))
+            .map(|(at, _, _)| *at)
+            .min()
+    }
+}
+
+// ------------------ Interrupts -----------------------/
+
+/// Event an interrupt is ordered for
+#[derive(Debug, Clone, PartialEq)]
+enum Trigger {
+    // Change of a signal to the value, any change for 2
+    Signal { name: String, value: f64 },
+    // Every `period` of host time, the next one at `due`
+    Timer { period: Duration, due: Duration },
+}
+
+/// Interrupt number of CONNECT, with its TRAP routine
+#[derive(Debug, Clone)]
+struct Interrupt {
+    module: String,
+    trap: String,
+    trigger: Option<Trigger>,
+    // Ordered with \Single, the interrupt occurs once
+    single: bool,
+    // Deactivated by ISleep, its events are lost until IWatch
+    asleep: bool,
+}
+
+/// Interrupts of the program. CONNECT gives intnum data a number, an
+/// interrupt instruction orders it for an event, and the program runs the
+/// TRAP routine of an interrupt that occurred before its next statement.
+/// Interrupts that occur during a wait instruction are taken after it.
+#[derive(Debug, Clone, Default)]
+pub struct Interrupts {
+    // Numbered from 1, intnum data of 0 isn't connected. IDelete leaves
+    // None behind, the numbers of the others don't change.
+    connected: Vec<Option<Interrupt>>,
+    // Interrupts that occurred, their TRAP routines run in this order
+    pending: VecDeque<usize>,
+    // Set by IDisable, the interrupts that occur wait until IEnable
+    disabled: bool,
+}
+
+impl Interrupts {
+    /// Number of a new interrupt that runs the TRAP routine of the module
+    pub fn connect(&mut self, module: &str, trap: &str) -> f64 {
+        let interrupt = Interrupt { module: String::from(module), trap: String::from(trap), trigger: None, single: false, asleep: false };
+        self.connected.push(Some(interrupt));
+        self.connected.len() as f64
+    }
+
+    /// Whether intnum data with this number is connected to a TRAP routine
+    pub fn is_connected(&self, number: f64) -> bool {
+        self.index(number).is_some()
+    }
+
+    fn index(&self, number: f64) -> Option<usize> {
+        let idx = (number as usize).wrapping_sub(1);
+        match self.connected.get(idx) {
+            Some(Some(_)) if (idx + 1) as f64 == number => Some(idx),
+            _ => None,
+        }
+    }
+
+    fn interrupt(&mut self, number: f64) -> Result<&mut Interrupt, RuntimeError> {
+        match self.index(number) {
+            Some(idx) => Ok(self.connected[idx].as_mut().unwrap()),
+            None => Err(RuntimeError::UnknownInterrupt { number, span: Span::default() }),
+        }
+    }
+
+    /// Order an interrupt for the changes of a signal to `value`, or for any
+    /// change when it's 2. The signal has to be watched on the I/O board.
+    pub fn order_signal(&mut self, number: f64, name: &str, value: f64, single: bool) -> Result<(), RuntimeError> {
+        let interrupt = self.interrupt(number)?;
+        interrupt.trigger = Some(Trigger::Signal { name: String::from(name), value });
+        interrupt.single = single;
+        Ok(())
+    }
+
+    /// Order an interrupt for every `period` from `now` on
+    pub fn order_timer(&mut self, number: f64, period: Duration, now: Duration, single: bool) -> Result<(), RuntimeError> {
+        let interrupt = self.interrupt(number)?;
+        interrupt.trigger = Some(Trigger::Timer { period, due: now + period });
+        interrupt.single = single;
+        Ok(())
+    }
+
+    /// Cancel an interrupt, the intnum data can be connected again. Its
+    /// occurrences that didn't run their TRAP routine yet are dropped. Data
+    /// that isn't connected is left as it is.
+    pub fn delete(&mut self, number: f64) {
+        if let Some(idx) = self.index(number) {
+            self.connected[idx] = None;
+            self.pending.retain(|pending| *pending != idx);
+        }
+    }
+
+    /// Deactivate an interrupt with ISleep, or activate it again with
+    /// IWatch. Its events while it sleeps are lost.
+    pub fn sleep(&mut self, number: f64, asleep: bool) -> Result<(), RuntimeError> {
+        self.interrupt(number)?.asleep = asleep;
+        Ok(())
+    }
+
+    /// Hold back the TRAP routines of all interrupts with IDisable, until
+    /// IEnable. The interrupts that occur meanwhile are kept.
+    pub fn disable(&mut self, disabled: bool) {
+        self.disabled = disabled;
+    }
+
+    /// Whether an interrupt can occur or did, so the program has to look
+    /// for them between its statements
+    pub fn armed(&self) -> bool {
+        !self.pending.is_empty() || self.connected.iter().flatten().any(|interrupt| interrupt.trigger.is_some())
+    }
+
+    /// Module and name of the TRAP routine of the next interrupt that
+    /// occurred by `now`, none while they are disabled. A timer that was due
+    /// several times while the program waited occurs once.
+    pub fn next(&mut self, io: Option<&IoBoard>, now: Duration) -> Result<Option<(String, String)>, RuntimeError> {
+        if let Some(io) = io {
+            io.update(now)?;
+            for (name, value) in io.changes() {
+                for idx in 0..self.connected.len() {
+                    let occurs = matches!(self.connected[idx].as_ref().and_then(|interrupt| interrupt.trigger.as_ref()),
+                        Some(Trigger::Signal { name: signal, value: trigger }) if signal.eq_ignore_ascii_case(&name) && (*trigger == value || *trigger == 2.0));
+                    if occurs {
+                        self.occur(idx);
+                    }
+                }
+            }
+        }
+        for idx in 0..self.connected.len() {
+            if let Some(Trigger::Timer { period, due }) = self.connected[idx].as_mut().and_then(|interrupt| interrupt.trigger.as_mut()) {
+                if *due <= now {
+                    let missed = (now - *due).as_secs_f64() / period.as_secs_f64();
+                    *due += period.mul_f64(missed.floor() + 1.0);
+                    self.occur(idx);
+                }
+            }
+        }
+        if self.disabled {
+            return Ok(None);
+        }
+        Ok(self.pending.pop_front()
+            .and_then(|idx| self.connected[idx].as_ref())
+            .map(|interrupt| (interrupt.module.clone(), interrupt.trap.clone())))
+    }
+
+    fn occur(&mut self, idx: usize) {
+        if let Some(interrupt) = self.connected[idx].as_mut() {
+            if interrupt.asleep {
+                return;
+            }
+            if interrupt.single {
+                interrupt.trigger = None;
+            }
+            self.pending.push_back(idx);
+        }
+    }
+}
+
+// ------------------ Motion -----------------------/
+
+/// Position the robot moves to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    // TCP position in mm and orientation as a quaternion, of a robtarget
+    Cartesian { pos: [f64; 3], rot: [f64; 4] },
+    // Axis angles in degrees, of a jointtarget
+    Joints([f64; 6]),
+}
+
+/// Motion settings of the program, changed by VelSet, AccSet, ConfJ and ConfL
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionSettings {
+    // Percentage of the programmed speeds
+    pub velocity: f64,
+    // Maximum TCP speed in mm/s
+    pub max_speed: f64,
+    // Percentages of the normal acceleration and of its rate of change
+    pub acceleration: f64,
+    pub ramp: f64,
+    // Configuration monitoring of joint and linear moves
+    pub conf_j: bool,
+    pub conf_l: bool,
+}
+
+impl Default for MotionSettings {
+    fn default() -> MotionSettings {
+        MotionSettings { velocity: 100.0, max_speed: 5000.0, acceleration: 100.0, ramp: 100.0, conf_j: true, conf_l: true }
+    }
+}
+
+/// Where the robot is when its last move ends, for CRobT and CJointT.
+/// Without a robot model a robtarget and a jointtarget don't give each
+/// other, each is the one of the last move to a target of its kind.
+#[derive(Debug, Clone, Default)]
+pub struct RobotPosition {
+    // robtarget of the last MoveJ, MoveL or MoveC with the tooldata and
+    // the wobjdata of the move, `None` for the start position
+    pub robtarget: Option<(Variable, Variable, Variable)>,
+    // jointtarget of the last MoveAbsJ, `None` for the calibration position
+    pub jointtarget: Option<Variable>,
+}
+
+/// Move of the robot, instead of moving it the motion instructions log where
+/// it goes and when it gets there
+#[derive(Debug, Clone, PartialEq)]
+pub struct Waypoint {
+    pub instruction: &'static str,
+    pub target: Target,
+    // Data of the target, `None` when it's an expression like Offs(p10, 0, 0, 10)
+    pub name: Option<String>,
+    // Work object of the target, wobj0 unless the move gives one
+    pub frame: Option<String>,
+    // Circle point of a MoveC
+    pub via: Option<[f64; 3]>,
+    // TCP speed in mm/s as programmed, and after the overrides
+    pub programmed_speed: f64,
+    pub speed: f64,
+    // Zone radius of the TCP in mm, `None` for a stop point
+    pub zone: Option<f64>,
+    // Host time the robot reaches the target
+    pub time: Duration,
+    pub settings: MotionSettings,
+}
+
+/// Trajectory as CSV, for plotting and path review tools. A line per move
+/// with the target and its work object, the programmed and the actual TCP
+/// speed in mm/s, the zone radius in mm, empty for a stop point, the time in
+/// seconds the robot gets there and the position: the TCP and orientation
+/// of a robtarget or the axes of a jointtarget.
+pub fn trajectory_csv(trajectory: &[Waypoint]) -> String {
+    let mut csv = String::from("instruction,target,frame,programmed_speed,speed,zone,time,x,y,z,q1,q2,q3,q4,j1,j2,j3,j4,j5,j6\n");
+    for waypoint in trajectory {
+        let nums = |values: &[f64]| values.iter().map(|value| value.to_string()).collect::<Vec<_>>().join(",");
+        let position = match &waypoint.target {
+            Target::Cartesian { pos, rot } => format!("{},{},,,,,,", nums(pos), nums(rot)),
+            Target::Joints(joints) => format!(",,,,,,,{}", nums(joints)),
+        };
+        csv.push_str(&format!("{},{},{},{},{},{},{},{}\n",
+            waypoint.instruction,
+            waypoint.name.as_deref().unwrap_or_default(),
+            waypoint.frame.as_deref().unwrap_or_default(),
+            waypoint.programmed_speed,
+            waypoint.speed,
+            waypoint.zone.map(|zone| zone.to_string()).unwrap_or_default(),
+            waypoint.time.as_secs_f64(),
+            position));
+    }
+    csv
+}
+
+// ------------------ Observer -----------------------/
+
+/// Place in the source of a statement or instruction being executed: its
+/// module, routine and the span of the statement or call
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Location {
+    pub module: String,
+    pub routine: String,
+    pub span: Span,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{} in {}", self.module, self.span, self.routine)
+    }
+}
+
+/// Follows the execution of a run, for tracing, coverage and monitoring.
+/// Events the observer doesn't implement are ignored.
+pub trait Observer {
+    /// A statement starts
+    fn statement(&mut self, _span: Span) {}
+    /// A routine of the program is called, or the run starts in it
+    fn enter_routine(&mut self, _module: &str, _routine: &str) {}
+    /// A routine returns, or is left for an error it doesn't handle
+    fn exit_routine(&mut self, _module: &str, _routine: &str) {}
+    /// Data got a new value, by an assignment, a FOR loop or an output
+    /// argument. The value is the whole data object.
+    fn assign(&mut self, _name: &str, _value: &Variable) {}
+    /// A statement raised an error, before an ERROR handler runs
+    fn error(&mut self, _error: &RuntimeError) {}
+}
+
+// ------------------ Files -----------------------/
+
+/// Path in the file root of the host of a file name of the program, like
+/// "HOME:/log.txt". The HOME: device is the root itself, other devices are
+/// directories in it. Names that would leave the root are refused.
+pub fn sandbox_path(root: &Path, name: &str) -> Result<PathBuf, String> {
+    let (device, path) = name.split_once(':').unwrap_or(("HOME", name));
+    let mut sandboxed = root.to_path_buf();
+    if !device.eq_ignore_ascii_case("HOME") {
+        if device.is_empty() || !device.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("{}: unknown device {}", name, device));
+        }
+        sandboxed.push(device);
+    }
+    for part in path.split(['/', '\\']) {
+        match Path::new(part).components().next() {
+            None | Some(Component::CurDir) => (),
+            Some(Component::Normal(_)) if !part.contains(':') => sandboxed.push(part),
+            Some(_) => return Err(format!("{}: outside the file root", name)),
+        }
+    }
+    Ok(sandboxed)
+}
+
+// ------------------ Sockets -----------------------/
+
+/// Socket of the program, from SocketCreate until SocketClose
+pub enum Socket {
+    Created,
+    Connected(TcpStream),
+}
+
+// ------------------ Event log -----------------------/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Information,
+    Warning,
+    Error,
+}
+
+/// Message of the event log of the controller, written by ErrWrite or ErrLog
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub number: i32,
+    pub severity: Severity,
+    // Host time the event was logged at
+    pub time: Duration,
+    pub title: String,
+    // Lines of the reason
+    pub reason: Vec<String>,
+}
+
+// ------------------ Program data -----------------------/
+
+/// Global data of the program, lent to the host while an instruction that
+/// accesses data by name runs, like GetDataVal
+#[derive(Debug, Clone, Default)]
+pub struct ProgramData {
+    pub names: Rc<Vec<DataName>>,
+    pub values: Vec<Variable>,
+    // Module of the routine running the instruction
+    pub module: String,
+}
+
+// ------------------ Host -----------------------/
+
+/// Wait instruction of the task the scheduler runs. Instead of letting host
+/// time pass, a wait that can't finish yet gives the other tasks a turn.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TaskWait {
+    // Name of the task
+    pub name: String,
+    // Host time the wait started, until it finishes
+    pub start: Option<Duration>,
+    // Number of waits the task finished
+    pub finished: usize,
+}
+
+/// Synchronization point of WaitSyncTask, from the first task that arrives
+/// until every task of the list went on
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncPoint {
+    // Name of the syncident data, the same in every task
+    pub ident: String,
+    pub tasks: Vec<String>,
+    pub arrived: Vec<String>,
+    pub left: Vec<String>,
+}
+
+/// Devices of the simulated controller that the host provides to a run
+pub struct Host<'a> {
+    pub output: &'a mut dyn OutputSink,
+    // Without input the operator dialogs fail with ERR_TP_NO_CLIENT
+    pub input: Option<&'a mut dyn InputProvider>,
+    pub clock: &'a dyn Clock,
+    // Without an I/O board no signal is defined
+    pub io: Option<&'a IoBoard>,
+    // Moves of the run, in the order the robot makes them
+    pub trajectory: Vec<Waypoint>,
+    pub position: RobotPosition,
+    // Names of the data passed to the motion instruction being executed, by
+    // parameter slot, for its waypoint
+    pub arg_names: Vec<Option<String>>,
+    pub motion: MotionSettings,
+    // Speed override of the operator, in percent
+    pub speed_override: f64,
+    // Wait of the task being run, `None` when a single program runs
+    pub task: Option<TaskWait>,
+    // WaitSyncTask points some task arrived at
+    pub sync_points: Vec<SyncPoint>,
+    pub observer: Option<&'a mut dyn Observer>,
+    // Directory the file instructions work in, without one every Open fails
+    pub file_root: Option<PathBuf>,
+    // Files the program opened, iodev data index into it
+    pub files: Vec<Option<fs::File>>,
+    // Addresses SocketConnect may connect to, like "127.0.0.1:502" or a
+    // host name for any port. Without them every connection is refused.
+    pub socket_hosts: Vec<String>,
+    // Sockets the program created, socketdev data index into it
+    pub sockets: Vec<Option<Socket>>,
+    // Directories Load and StartLoad search for modules, in order
+    pub module_path: Vec<PathBuf>,
+    // Modules loaded or unloaded by the instruction being executed, linked
+    // to the program when it returns
+    pub module_changes: Vec<ModuleChange>,
+    // Modules StartLoad read, loadsession data index into it
+    pub load_sessions: Vec<Option<LoadedModule>>,
+    // Events the program logged, the oldest first
+    pub event_log: Vec<Event>,
+    // Empty unless the instruction being executed accesses data by name
+    pub data: ProgramData,
+    // Where the last error was raised, by either backend
+    pub error_location: Option<Location>,
+    pub interrupts: Interrupts,
+}
+
+impl<'a> Host<'a> {
+    pub fn new(output: &'a mut dyn OutputSink) -> Host<'a> {
+        Host {
+            output,
+            input: None,
+            clock: &SystemClock,
+            io: None,
+            trajectory: Vec::new(),
+            position: RobotPosition::default(),
+            arg_names: Vec::new(),
+            motion: MotionSettings::default(),
+            speed_override: 100.0,
+            task: None,
+            sync_points: Vec::new(),
+            observer: None,
+            file_root: None,
+            files: Vec::new(),
+            socket_hosts: Vec::new(),
+            sockets: Vec::new(),
+            module_path: Vec::new(),
+            module_changes: Vec::new(),
+            load_sessions: Vec::new(),
+            event_log: Vec::new(),
+            data: ProgramData::default(),
+            error_location: None,
+            interrupts: Interrupts::default(),
+        }
+    }
+
+    pub fn with_input(mut self, input: &'a mut dyn InputProvider) -> Host<'a> {
+        self.input = Some(input);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: &'a dyn Clock) -> Host<'a> {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_io(mut self, io: &'a IoBoard) -> Host<'a> {
+        self.io = Some(io);
+        self
+    }
+
+    pub fn with_speed_override(mut self, percent: f64) -> Host<'a> {
+        self.speed_override = percent;
+        self
+    }
+
+    pub fn with_observer(mut self, observer: &'a mut dyn Observer) -> Host<'a> {
+        self.observer = Some(observer);
+        self
+    }
+
+    pub fn with_file_root(mut self, root: impl Into<PathBuf>) -> Host<'a> {
+        self.file_root = Some(root.into());
+        self
+    }
+
+    /// Let SocketConnect connect to an address, like "127.0.0.1:502", or to
+    /// any port of a host
+    pub fn with_socket_host(mut self, address: &str) -> Host<'a> {
+        self.socket_hosts.push(String::from(address));
+        self
+    }
+
+    /// Whether the program may connect to a port of a host
+    pub fn allows_socket(&self, address: &str, port: u16) -> bool {
+        let with_port = format!("{}:{}", address, port);
+        self.socket_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(address) || allowed.eq_ignore_ascii_case(&with_port))
+    }
+
+    /// Add a directory to the module search path
+    pub fn with_module_dir(mut self, dir: impl Into<PathBuf>) -> Host<'a> {
+        self.module_path.push(dir.into());
+        self
+    }
+
+    /// Tell the observer about an event of the run
+    pub fn observe(&mut self, event: impl FnOnce(&mut dyn Observer)) {
+        if let Some(observer) = self.observer.as_mut() {
+            event(&mut **observer);
+        }
+    }
+}
+
+/// Text of a TPWrite, the optional argument is appended to the string
+pub fn write_line(text: Variable, arg: Option<(WriteArg, Variable)>) -> Result<String, RuntimeError> {
+    let mut line = match text {
+        Variable::Str(text) => text,
+        var => return Err(RuntimeError::type_mismatch(format!("TPWrite expects a string, found {}", var.type_name()))),
+    };
+
+    match arg {
+        None => (),
+        Some((WriteArg::Num, Variable::Num(value))) => line.push_str(&format_num(value)),
+        Some((WriteArg::Num, Variable::Byte(value))) => line.push_str(&value.to_string()),
+        Some((WriteArg::Dnum, Variable::Dnum(value) | Variable::Num(value))) => line.push_str(&format_dnum(value)),
+        Some((WriteArg::Bool, Variable::Bool(value))) => line.push_str(if value { "TRUE" } else { "FALSE" }),
+        Some((WriteArg::Pos, Variable::Record(name, fields))) if name == "pos" || name.is_empty() => {
+            let mut values = Vec::new();
+            for field in fields {
+                match field {
+                    Variable::Num(value) => values.push(format_num(value)),
+                    var => return Err(RuntimeError::type_mismatch(format!("\\Pos expects num components, found {}", var.type_name()))),
+                }
+            }
+            line.push_str(&format!("[{}]", values.join(",")));
+        },
+        Some((kind, var)) => return Err(RuntimeError::type_mismatch(format!("\\{:?} cannot write {}", kind, var.type_name()))),
+    }
+    Ok(line)
+}
+
+/// Numbers are written with at most 6 significant digits, like the controller
+/// does, and with an exponent from a million on or below 0.0001, like
+/// `9E+09` or `1.5E-07`
+pub fn format_num(value: f64) -> String {
+    format_digits(value, 6)
+}
+
+/// Dnum values are written like num values, with at most 15 significant
+/// digits, so integers up to 4294967295 are written in full
+pub fn format_dnum(value: f64) -> String {
+    format_digits(value, 15)
+}
+
+fn format_digits(value: f64, digits: i32) -> String {
+    if value == 0.0 {
+        return String::from("0");
+    }
+    let trim = |text: String| if text.contains('.') { String::from(text.trim_end_matches('0').trim_end_matches('.')) } else { text };
+
+    // Exponent after rounding, 999999.5 is written as 1E+06
+    let text = format!("{:.*E}", (digits - 1) as usize, value);
+    let (mantissa, exponent) = text.split_once('E').unwrap_or((&text, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    if !(-4..digits).contains(&exponent) {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        return format!("{}E{}{:02}", trim(String::from(mantissa)), sign, exponent.abs());
+    }
+    trim(format!("{:.*}", (digits - 1 - exponent) as usize, value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formats_write_arguments() {
+        let text = || Variable::Str(String::from("Value: "));
+        assert_eq!(write_line(text(), Some((WriteArg::Num, Variable::Num(42.0)))).unwrap(), "Value: 42");
+        assert_eq!(write_line(text(), Some((WriteArg::Num, Variable::Num(1.0 / 3.0)))).unwrap(), "Value: 0.333333");
+        assert_eq!(write_line(text(), Some((WriteArg::Num, Variable::Num(-1234.56789)))).unwrap(), "Value: -1234.57");
+        assert_eq!(write_line(text(), Some((WriteArg::Dnum, Variable::Dnum(4294967295.5)))).unwrap(), "Value: 4294967295.5");
+        assert_eq!(write_line(text(), Some((WriteArg::Bool, Variable::Bool(false)))).unwrap(), "Value: FALSE");
+        assert!(write_line(text(), Some((WriteArg::Bool, Variable::Num(1.0)))).is_err());
+        assert!(write_line(Variable::Num(1.0), None).is_err());
+    }
+
+    #[test]
+    fn formats_numbers_like_the_controller() {
+        let formatted: Vec<String> = [0.0, -0.0, 42.0, 100000.0, 999999.5, 9E+09, 1234567.0, 0.0001, 0.000015, 2.0 / 3.0, -0.5]
+            .iter().map(|value| format_num(*value)).collect();
+        assert_eq!(formatted, vec!["0", "0", "42", "100000", "1E+06", "9E+09", "1.23457E+06", "0.0001", "1.5E-05", "0.666667", "-0.5"]);
+    }
+}
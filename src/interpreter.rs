@@ -0,0 +1,2037 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::builtins::{self, Builtin, HostRoutine};
+use crate::compiler::{self, DataName};
+use crate::host::{self, Clock, Host, InputProvider, IoBoard, Location, OutputSink, ProgramData};
+use crate::lexer::Span;
+use crate::loader::{self, LoadedModule};
+use crate::parser::{Argument, Arena, Callee, DataDecl, Module, Node, Param, ParamMode, Program, Routine, Statement, Storage};
+use crate::persistence;
+use crate::variable::{self, Variable};
+
+// ------------------ Errors -----------------------/
+
+/// Error raised while executing a program. The span points at the statement
+/// that raised it, or at a more precise location when one is known.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    /// Operand or assigned value has the wrong data type
+    TypeMismatch { message: String, span: Span },
+    /// Division by zero in `/`, `DIV` or `MOD`
+    DivZero { span: Span },
+    /// Reference to a data slot that doesn't exist
+    UnknownData { slot: usize, span: Span },
+    /// Name of data that isn't in scope where the program is paused
+    UnknownName { name: String, span: Span },
+    /// Reference to a routine that doesn't exist
+    UnknownRoutine { name: String, span: Span },
+    /// FUNC ended without returning a value
+    MissingReturn { name: String, span: Span },
+    /// Array index below 1 or past the end of the array
+    IndexOutOfBounds { index: f64, len: usize, span: Span },
+    /// String longer than `variable::MAX_STRING` characters
+    StringTooLong { len: usize, span: Span },
+    /// Error number raised by RAISE
+    Raised { errno: i32, span: Span },
+    /// Routine calls nested deeper than `InterpreterOptions::max_depth`
+    StackOverflow { depth: usize, span: Span },
+    /// Instruction budget or timeout of `InterpreterOptions` exhausted
+    LimitExceeded { limit: Limit, span: Span },
+    /// Operator dialog without an `InputProvider`, or one that ran out of answers
+    NoOperator { span: Span },
+    /// Operator didn't answer a dialog within its `\MaxTime`
+    OperatorTimeout { max_time: f64, span: Span },
+    /// Signal data that isn't connected to a signal of the I/O board
+    UnknownSignal { name: String, span: Span },
+    /// Signal didn't reach the value waited for within the `\MaxTime`, or
+    /// never will when `max_time` is infinite
+    WaitTimeout { signal: String, max_time: f64, span: Span },
+    /// Not every task of the list reached the WaitSyncTask within its `\TimeOut`
+    SyncTimeout { ident: String, span: Span },
+    /// Wait the task scheduler has to resume later, after the other tasks
+    /// had their turn or at the host time `wake`
+    Blocked { wake: Option<Duration>, span: Span },
+    /// Every task of the scheduler waits and no wait can end
+    Deadlock { span: Span },
+    /// PERS file of `InterpreterOptions::pers_file` or a file of the file
+    /// instructions can't be read or written
+    FileAccess { message: String, span: Span },
+    /// Open of a file failed, or the I/O device is open already
+    FileOpen { message: String, span: Span },
+    /// Read data isn't of the type asked for, like text for ReadNum
+    ReceiveData { message: String, span: Span },
+    /// Data named by GetDataVal or SetDataVal doesn't exist where the
+    /// program runs, or can't be written
+    SymbolAccess { message: String, span: Span },
+    /// Socket isn't connected, or the other side closed the connection
+    SocketClosed { message: String, span: Span },
+    /// Socket didn't connect or receive within the `\Time`
+    SocketTimeout { message: String, span: Span },
+    /// Address the host doesn't allow, or that refused the connection
+    SocketUnreachable { message: String, span: Span },
+    /// CONNECT of intnum data with an interrupt that wasn't deleted
+    AlreadyConnected { span: Span },
+    /// Interrupt number that isn't connected to a TRAP routine, or was deleted
+    UnknownInterrupt { number: f64, span: Span },
+    /// Module can't be loaded, linked or unloaded while the program runs.
+    /// The message is boxed to keep the errors small.
+    ModuleLoad { error: LoadError, message: Box<str>, span: Span },
+    /// Construct the interpreter can't execute (yet)
+    Unsupported { message: String, span: Span },
+    /// EXIT stopped the program. It isn't an error of the program, but ends
+    /// the run the same way: no ERROR handler gets it.
+    Exit { span: Span },
+}
+
+impl RuntimeError {
+    pub fn type_mismatch(message: String) -> RuntimeError {
+        RuntimeError::TypeMismatch { message, span: Span::default() }
+    }
+
+    pub fn div_zero() -> RuntimeError {
+        RuntimeError::DivZero { span: Span::default() }
+    }
+
+    /// Error of `RAISE errno`, which takes the number of an error of the
+    /// program or of an error constant
+    pub fn raise(errno: f64) -> RuntimeError {
+        let number = errno as i32;
+        if errno == number as f64 && (builtins::USER_ERRORS.contains(&number) || builtins::error_name(number).is_some()) {
+            RuntimeError::Raised { errno: number, span: Span::default() }
+        } else {
+            RuntimeError::type_mismatch(format!("Invalid error number {}", errno))
+        }
+    }
+
+    /// Name of the matching RAPID error constant
+    pub fn name(&self) -> &'static str {
+        match self {
+            RuntimeError::TypeMismatch { .. } => "ERR_ARGVALERR",
+            RuntimeError::DivZero { .. } => "ERR_DIVZERO",
+            RuntimeError::UnknownData { .. } | RuntimeError::UnknownName { .. } => "ERR_REFUNKDAT",
+            RuntimeError::UnknownRoutine { .. } => "ERR_REFUNKPRC",
+            RuntimeError::MissingReturn { .. } => "ERR_FNCNORET",
+            RuntimeError::IndexOutOfBounds { .. } => "ERR_INDEX",
+            RuntimeError::StringTooLong { .. } => "ERR_STRTOOLONG",
+            RuntimeError::Raised { errno, .. } => builtins::error_name(*errno).unwrap_or("ERR_USER"),
+            RuntimeError::StackOverflow { .. } => "ERR_STACKOVERFLOW",
+            RuntimeError::LimitExceeded { .. } => "ERR_EXECLIMIT",
+            RuntimeError::NoOperator { .. } => "ERR_TP_NO_CLIENT",
+            RuntimeError::OperatorTimeout { .. } => "ERR_TP_MAXTIME",
+            RuntimeError::UnknownSignal { .. } => "ERR_NO_ALIASIO_DEF",
+            RuntimeError::WaitTimeout { .. } => "ERR_WAIT_MAXTIME",
+            RuntimeError::SyncTimeout { .. } => "ERR_WAITSYNCTASK",
+            // Simulator specific, the controller refuses to load these programs
+            RuntimeError::Blocked { .. } | RuntimeError::Unsupported { .. } => "ERR_UNSUPPORTED",
+            RuntimeError::Deadlock { .. } => "ERR_DEADLOCK",
+            RuntimeError::FileAccess { .. } => "ERR_FILEACC",
+            RuntimeError::FileOpen { .. } => "ERR_FILEOPEN",
+            RuntimeError::ReceiveData { .. } => "ERR_RCVDATA",
+            RuntimeError::SymbolAccess { .. } => "ERR_SYM_ACCESS",
+            RuntimeError::SocketClosed { .. } => "ERR_SOCK_CLOSED",
+            RuntimeError::SocketTimeout { .. } => "ERR_SOCK_TIMEOUT",
+            RuntimeError::SocketUnreachable { .. } => "ERR_SOCK_NET_UNREACH",
+            RuntimeError::AlreadyConnected { .. } => "ERR_ALRDYCNT",
+            RuntimeError::UnknownInterrupt { .. } => "ERR_UNKINO",
+            RuntimeError::ModuleLoad { error, .. } => error.name(),
+            RuntimeError::Exit { .. } => "EXIT",
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            RuntimeError::TypeMismatch { span, .. }
+            | RuntimeError::DivZero { span }
+            | RuntimeError::UnknownData { span, .. }
+            | RuntimeError::UnknownName { span, .. }
+            | RuntimeError::UnknownRoutine { span, .. }
+            | RuntimeError::MissingReturn { span, .. }
+            | RuntimeError::IndexOutOfBounds { span, .. }
+            | RuntimeError::StringTooLong { span, .. }
+            | RuntimeError::Raised { span, .. }
+            | RuntimeError::StackOverflow { span, .. }
+            | RuntimeError::LimitExceeded { span, .. }
+            | RuntimeError::NoOperator { span }
+            | RuntimeError::OperatorTimeout { span, .. }
+            | RuntimeError::UnknownSignal { span, .. }
+            | RuntimeError::WaitTimeout { span, .. }
+            | RuntimeError::SyncTimeout { span, .. }
+            | RuntimeError::Blocked { span, .. }
+            | RuntimeError::Deadlock { span }
+            | RuntimeError::FileAccess { span, .. }
+            | RuntimeError::FileOpen { span, .. }
+            | RuntimeError::ReceiveData { span, .. }
+            | RuntimeError::SymbolAccess { span, .. }
+            | RuntimeError::SocketClosed { span, .. }
+            | RuntimeError::SocketTimeout { span, .. }
+            | RuntimeError::SocketUnreachable { span, .. }
+            | RuntimeError::AlreadyConnected { span }
+            | RuntimeError::UnknownInterrupt { span, .. }
+            | RuntimeError::ModuleLoad { span, .. }
+            | RuntimeError::Unsupported { span, .. }
+            | RuntimeError::Exit { span } => *span,
+        }
+    }
+
+    /// Value of ERRNO for the error, errors without a number can't be
+    /// handled by the ERROR handler of a routine
+    pub fn errno(&self) -> Option<i32> {
+        match self {
+            RuntimeError::Raised { errno, .. } => Some(*errno),
+            err => builtins::error_number(err.name()),
+        }
+    }
+
+    /// Attach the span of the statement that raised the error, unless a more precise one is known
+    pub fn at(mut self, location: Span) -> RuntimeError {
+        match &mut self {
+            RuntimeError::TypeMismatch { span, .. }
+            | RuntimeError::DivZero { span }
+            | RuntimeError::UnknownData { span, .. }
+            | RuntimeError::UnknownName { span, .. }
+            | RuntimeError::UnknownRoutine { span, .. }
+            | RuntimeError::MissingReturn { span, .. }
+            | RuntimeError::IndexOutOfBounds { span, .. }
+            | RuntimeError::StringTooLong { span, .. }
+            | RuntimeError::Raised { span, .. }
+            | RuntimeError::StackOverflow { span, .. }
+            | RuntimeError::LimitExceeded { span, .. }
+            | RuntimeError::NoOperator { span }
+            | RuntimeError::OperatorTimeout { span, .. }
+            | RuntimeError::UnknownSignal { span, .. }
+            | RuntimeError::WaitTimeout { span, .. }
+            | RuntimeError::SyncTimeout { span, .. }
+            | RuntimeError::Blocked { span, .. }
+            | RuntimeError::Deadlock { span }
+            | RuntimeError::FileAccess { span, .. }
+            | RuntimeError::FileOpen { span, .. }
+            | RuntimeError::ReceiveData { span, .. }
+            | RuntimeError::SymbolAccess { span, .. }
+            | RuntimeError::SocketClosed { span, .. }
+            | RuntimeError::SocketTimeout { span, .. }
+            | RuntimeError::SocketUnreachable { span, .. }
+            | RuntimeError::AlreadyConnected { span }
+            | RuntimeError::UnknownInterrupt { span, .. }
+            | RuntimeError::ModuleLoad { span, .. }
+            | RuntimeError::Unsupported { span, .. }
+            | RuntimeError::Exit { span } => {
+                if *span == Span::default() {
+                    *span = location;
+                }
+            },
+        }
+        self
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}: ", self.span(), self.name())?;
+        match self {
+            RuntimeError::TypeMismatch { message, .. } => write!(f, "{}", message),
+            RuntimeError::DivZero { .. } => write!(f, "Division by zero"),
+            RuntimeError::UnknownData { slot, .. } => write!(f, "Unknown data slot {}", slot),
+            RuntimeError::UnknownName { name, .. } => write!(f, "Unknown data {}", name),
+            RuntimeError::UnknownRoutine { name, .. } => write!(f, "Unknown routine {}", name),
+            RuntimeError::MissingReturn { name, .. } => write!(f, "FUNC {} ended without RETURN", name),
+            RuntimeError::IndexOutOfBounds { index, len, .. } => write!(f, "Index {} out of bounds 1 to {}", index, len),
+            RuntimeError::StringTooLong { len, .. } => write!(f, "String of {} characters is longer than {}", len, variable::MAX_STRING),
+            RuntimeError::Raised { errno, .. } => write!(f, "Error {} raised and not handled", errno),
+            RuntimeError::StackOverflow { depth, .. } => write!(f, "Routine calls nested deeper than {} levels", depth),
+            RuntimeError::LimitExceeded { limit: Limit::Instructions(max), .. } => write!(f, "Execution limit exceeded: more than {} instructions", max),
+            RuntimeError::LimitExceeded { limit: Limit::Timeout(timeout), .. } => write!(f, "Execution limit exceeded: running longer than {:?}", timeout),
+            RuntimeError::NoOperator { .. } => write!(f, "No operator to answer the dialog"),
+            RuntimeError::OperatorTimeout { max_time, .. } => write!(f, "No answer from the operator within {} s", max_time),
+            RuntimeError::UnknownSignal { name, .. } if name.is_empty() => write!(f, "Signal data is not connected to a signal"),
+            RuntimeError::UnknownSignal { name, .. } => write!(f, "Signal {} is not defined on the I/O board", name),
+            RuntimeError::WaitTimeout { signal, max_time, .. } if max_time.is_finite() => write!(f, "Signal {} did not change within {} s", signal, max_time),
+            RuntimeError::WaitTimeout { signal, .. } => write!(f, "Signal {} never changes to the value waited for", signal),
+            RuntimeError::SyncTimeout { ident, .. } => write!(f, "Not every task reached {} within the time-out", ident),
+            RuntimeError::Blocked { .. } => write!(f, "Waiting for another task, only the task scheduler runs tasks side by side"),
+            RuntimeError::Deadlock { .. } => write!(f, "Every task waits for something no task will do"),
+            RuntimeError::FileAccess { message, .. } => write!(f, "File access failed: {}", message),
+            RuntimeError::FileOpen { message, .. } => write!(f, "Cannot open file {}", message),
+            RuntimeError::ReceiveData { message, .. } => write!(f, "{}", message),
+            RuntimeError::SymbolAccess { message, .. } => write!(f, "{}", message),
+            RuntimeError::SocketClosed { message, .. } => write!(f, "Socket closed: {}", message),
+            RuntimeError::SocketTimeout { message, .. } => write!(f, "Socket timed out: {}", message),
+            RuntimeError::SocketUnreachable { message, .. } => write!(f, "Cannot connect to {}", message),
+            RuntimeError::AlreadyConnected { .. } => write!(f, "Interrupt data is connected to a TRAP routine already"),
+            RuntimeError::UnknownInterrupt { number, .. } => write!(f, "Interrupt {} is not connected to a TRAP routine", number),
+            RuntimeError::ModuleLoad { message, .. } => write!(f, "{}", message),
+            RuntimeError::Unsupported { message, .. } => write!(f, "{}", message),
+            RuntimeError::Exit { .. } => write!(f, "Program stopped by EXIT"),
+        }
+    }
+}
+
+// ------------------ Evaluation -----------------------/
+
+#[derive(Debug, Clone)]
+pub struct InterpreterOptions {
+    /// Maximum nesting of routine calls, deeper calls raise a
+    /// `StackOverflow`. The stack of the host grows as deep as they need.
+    pub max_depth: usize,
+    /// Abort after executing this many nodes or VM instructions
+    pub max_instructions: Option<u64>,
+    /// Abort when a run takes longer than this
+    pub timeout: Option<Duration>,
+    /// File the PERS data are loaded from before a run and stored to after
+    /// it, like a controller keeps them when the program restarts
+    pub pers_file: Option<PathBuf>,
+    /// Fail on values in the PERS file of data the program doesn't declare,
+    /// rather than ignoring them, to catch the file of another program
+    pub strict_pers: bool,
+}
+
+impl Default for InterpreterOptions {
+    fn default() -> InterpreterOptions {
+        InterpreterOptions {
+            max_depth: 250,
+            max_instructions: None,
+            timeout: None,
+            pers_file: None,
+            strict_pers: false,
+        }
+    }
+}
+
+/// Execution limit that aborted a run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Instructions(u64),
+    Timeout(Duration),
+}
+
+/// Why loading or unloading a module failed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadError {
+    // No module file with the path in the module search path
+    FileNotFound,
+    // A module with the same name is loaded already
+    Loaded,
+    // The file doesn't hold one module that parses
+    Syntax,
+    // The module refers to data or routines the program doesn't have
+    LinkRef,
+    // The module wasn't loaded, or a routine of it is running
+    Unload,
+    // StartLoad with a load session that's loading already
+    SessionInUse,
+    // WaitLoad with a load session that isn't loading
+    SessionUnused,
+}
+
+impl LoadError {
+    pub fn name(self) -> &'static str {
+        match self {
+            LoadError::FileNotFound => "ERR_FILNOTFND",
+            LoadError::Loaded => "ERR_LOADED",
+            LoadError::Syntax => "ERR_SYNTAX",
+            LoadError::LinkRef => "ERR_LINKREF",
+            LoadError::Unload => "ERR_UNLOAD",
+            LoadError::SessionInUse => "ERR_LOADNO_INUSE",
+            LoadError::SessionUnused => "ERR_LOADNO_NOUSE",
+        }
+    }
+}
+
+/// Counts the instructions of a run against the limits of `InterpreterOptions`
+pub struct Budget {
+    executed: u64,
+    max_instructions: Option<u64>,
+    timeout: Option<(Duration, Instant)>,
+}
+
+impl Budget {
+    // Reading the clock is slow compared to an instruction, so the
+    // timeout is only checked once per this many instructions
+    const CLOCK_INTERVAL: u64 = 1024;
+
+    pub fn new(options: &InterpreterOptions) -> Budget {
+        Budget {
+            executed: 0,
+            max_instructions: options.max_instructions,
+            timeout: options.timeout.map(|timeout| (timeout, Instant::now() + timeout)),
+        }
+    }
+
+    /// Count one instruction, fails once a limit is exceeded
+    pub fn tick(&mut self) -> Result<(), RuntimeError> {
+        self.executed += 1;
+        if let Some(max) = self.max_instructions {
+            if self.executed > max {
+                return Err(RuntimeError::LimitExceeded { limit: Limit::Instructions(max), span: Span::default() });
+            }
+        }
+        if let Some((timeout, deadline)) = self.timeout {
+            if self.executed.is_multiple_of(Self::CLOCK_INTERVAL) && Instant::now() >= deadline {
+                return Err(RuntimeError::LimitExceeded { limit: Limit::Timeout(timeout), span: Span::default() });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How execution continues after the ERROR handler of a routine
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Resume {
+    // Execute the statement that raised the error again
+    Retry,
+    // Continue with the statement after it
+    TryNext,
+    // Return from the routine, by RETURN or at the end of the handler
+    Return,
+}
+
+/// Activation record of a routine call
+struct Frame {
+    // Modules as they were when the routine was called, modules loaded
+    // meanwhile don't change the routine
+    modules: Rc<Vec<Module>>,
+    module: usize,
+    routine: usize,
+    // Argument slots followed by the data declared in the routine
+    locals: Vec<Variable>,
+    // Storage of the data passed for each parameter, `None` when no
+    // argument was passed
+    passed: Vec<Option<Storage>>,
+    // Set by RETURN, ends the execution of the routine
+    result: Option<Variable>,
+    // Error being handled, errors raised meanwhile go to the caller
+    error: Option<RuntimeError>,
+    // Set when the handler is done, ends its execution
+    resume: Option<Resume>,
+}
+
+impl Frame {
+    fn new(modules: Rc<Vec<Module>>, module: usize, idx: usize) -> Frame {
+        let routine = &modules[module].routines[idx];
+        let arguments = routine.arguments.iter().map(|param| &param.decl);
+        let locals = arguments.chain(routine.variables.iter()).map(|decl| decl.value.clone()).collect();
+        let passed = vec![None; routine.arguments.len()];
+        Frame {
+            modules,
+            module,
+            routine: idx,
+            locals,
+            passed,
+            result: None,
+            error: None,
+            resume: None,
+        }
+    }
+
+    fn routine(&self) -> &Routine {
+        &self.modules[self.module].routines[self.routine]
+    }
+}
+
+pub struct Stack<'a, 'h> {
+    host: &'a mut Host<'h>,
+    frames: Vec<Frame>,
+    globals: Vec<Variable>,
+    // Names of the global data slots
+    names: Rc<Vec<DataName>>,
+    modules: Rc<Vec<Module>>,
+    // Modules linked to the program while it runs, as they were read
+    loaded: Vec<LoadedModule>,
+    system: &'a [DataDecl],
+    host_routines: &'a [HostRoutine],
+    // Number of the last error handled, read by ERRNO
+    errno: i32,
+    // Whether the observer knows the error passed on to the callers
+    reported: bool,
+    // Number of frames with the TRAP routine that runs, interrupts that
+    // occur wait until it returns. An error it doesn't handle ends the run.
+    trap: Option<usize>,
+    // Maximum number of frames
+    max_depth: usize,
+    budget: Budget,
+    // Lines written to the FlexPendant, for the result of the run
+    output: Vec<String>,
+}
+
+impl<'a, 'h> Stack<'a, 'h> {
+    fn local(&self, idx: usize) -> Option<&Variable> {
+        self.frames.last().and_then(|frame| frame.locals.get(idx))
+    }
+
+    fn local_mut(&mut self, idx: usize) -> Option<&mut Variable> {
+        self.frames.last_mut().and_then(|frame| frame.locals.get_mut(idx))
+    }
+
+    /// Enter a statement or expression, every node counts as an
+    /// instruction of the budget
+    fn enter(&mut self) -> Result<(), RuntimeError> {
+        self.budget.tick()
+    }
+
+    /// Enter a statement, after the TRAP routines of the interrupts that
+    /// occurred
+    #[inline(never)]
+    fn enter_statement(&mut self) -> Result<(), RuntimeError> {
+        if self.host.interrupts.armed() {
+            self.interrupt()?;
+        }
+        self.enter()
+    }
+
+    /// A RETURN was executed in the current routine, or its ERROR handler is done
+    fn returning(&self) -> bool {
+        self.frames.last().is_some_and(|frame| frame.result.is_some() || frame.resume.is_some())
+    }
+
+    /// Run the ERROR handler of the current routine for an error raised by
+    /// one of its statements. Errors without a number, and errors raised
+    /// while the handler runs, are passed on to the caller. Errors that left
+    /// a TRAP routine end the run.
+    fn handle(&mut self, err: RuntimeError) -> Result<Resume, RuntimeError> {
+        let errno = match err.errno() {
+            Some(errno) if self.trap.is_none_or(|depth| self.frames.len() >= depth) => errno,
+            _ => return Err(err),
+        };
+        let (modules, module, routine) = match self.frames.last_mut() {
+            Some(frame) if frame.error.is_none() && frame.routine().handler.is_some() => {
+                frame.error = Some(err);
+                (Rc::clone(&frame.modules), frame.module, frame.routine)
+            },
+            _ => return Err(err),
+        };
+        self.errno = errno;
+        self.reported = false;
+
+        let routine = &modules[module].routines[routine];
+        execute_body(routine.handler.as_deref().unwrap_or_default(), &routine.nodes, self)?;
+        match self.frames.last_mut() {
+            Some(frame) => match frame.resume.take() {
+                Some(resume @ (Resume::Retry | Resume::TryNext)) => {
+                    frame.error = None;
+                    Ok(resume)
+                },
+                _ => {
+                    frame.resume = Some(Resume::Return);
+                    Ok(Resume::Return)
+                },
+            },
+            None => Err(RuntimeError::Unsupported { message: String::from("Call stack is empty"), span: Span::default() }),
+        }
+    }
+
+    /// Link the modules the instruction that returned loaded or unloaded.
+    /// The routines being executed keep running the modules they started in.
+    #[inline(never)]
+    fn link(&mut self) -> Result<(), RuntimeError> {
+        let busy = self.frames.iter().map(|frame| frame.module + 1).max().unwrap_or(0);
+        let modules = Rc::make_mut(&mut self.modules);
+        for change in std::mem::take(&mut self.host.module_changes) {
+            loader::link(change, modules, &mut self.loaded, &mut self.globals, self.system, self.host_routines, busy)?;
+            self.names = compiler::global_names(modules, self.system);
+        }
+        Ok(())
+    }
+
+    /// Storage of the data an argument of a call in the current routine passes
+    #[inline(never)]
+    fn passed(&self, node: &Node) -> Storage {
+        let frame = match self.frames.last() {
+            Some(frame) => frame,
+            None => return Storage::Var,
+        };
+        match Passed::from(node, frame.routine(), &self.names) {
+            Passed::Data(storage) => storage,
+            Passed::InOut(slot) => frame.passed.get(slot).copied().flatten().unwrap_or(Storage::Var),
+        }
+    }
+
+    /// Value an argument of a call in the current routine passes, `None`
+    /// for a switch. Conditional arguments of optional parameters that
+    /// weren't passed to the routine pass nothing.
+    #[inline(never)]
+    fn passes<'n>(&self, arg: &'n Argument) -> Option<Option<&'n Node>> {
+        let (slot, frame) = match (arg.conditional, &arg.value, self.frames.last()) {
+            (true, Some(Node::Var(slot)), Some(frame)) => (*slot, frame),
+            _ => return Some(arg.value.as_ref()),
+        };
+        frame.passed.get(slot).copied().flatten()?;
+        match frame.routine().arguments.get(slot) {
+            Some(param) if param.decl.data_type.eq_ignore_ascii_case("switch") => Some(None),
+            _ => Some(arg.value.as_ref()),
+        }
+    }
+
+    /// Arguments a call in the current routine passes, without the
+    /// conditional ones that pass nothing
+    #[inline(never)]
+    fn present_args(&self, args: &[Argument]) -> Vec<Argument> {
+        args.iter()
+            .filter_map(|arg| {
+                let value = self.passes(arg)?.cloned();
+                Some(Argument { name: arg.name.clone(), value, span: arg.span, conditional: false })
+            })
+            .collect()
+    }
+
+    /// Run a built-in routine, lending the global data to the ones that
+    /// access data by name and naming the data passed to the motion
+    /// instructions
+    #[inline(never)]
+    fn call_builtin(&mut self, builtin: &Builtin, args: &[Argument], values: &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError> {
+        if builtins::logs_move(builtin) {
+            self.name_arguments(builtin, args)?;
+        }
+        if !builtins::accesses_data(builtin) {
+            return (builtin.call)(self.host, values);
+        }
+        let module = self.frames.last().map(|frame| frame.modules[frame.module].name.clone()).unwrap_or_default();
+        self.host.data = ProgramData { names: Rc::clone(&self.names), values: std::mem::take(&mut self.globals), module };
+        let result = (builtin.call)(self.host, values);
+        self.globals = std::mem::take(&mut self.host.data).values;
+        result
+    }
+
+    /// Tell the host the names of the data passed to a motion instruction
+    fn name_arguments(&mut self, builtin: &Builtin, args: &[Argument]) -> Result<(), RuntimeError> {
+        let mut names = vec![None; builtin.params.len()];
+        if let Some(frame) = self.frames.last() {
+            for (slot, arg) in param_slots(&builtin.params, builtin.name, args)?.into_iter().zip(args) {
+                names[slot] = arg.value.as_ref().and_then(|node| data_name(node, frame.routine(), &self.names));
+            }
+        }
+        self.host.arg_names = names;
+        Ok(())
+    }
+
+    /// Run the TRAP routine of each interrupt that occurred, before the
+    /// next statement
+    #[inline(never)]
+    fn interrupt(&mut self) -> Result<(), RuntimeError> {
+        if self.trap.is_some() {
+            return Ok(());
+        }
+        let now = self.host.clock.now();
+        while let Some((module, trap)) = self.host.interrupts.next(self.host.io, now)? {
+            let found = self.modules.iter().enumerate()
+                .filter(|(_, candidate)| candidate.name == module)
+                .find_map(|(idx, candidate)| Some((idx, candidate.routines.iter().position(|routine| routine.trap && routine.name.eq_ignore_ascii_case(&trap))?)));
+            let target = match found {
+                Some((module, idx)) => Callee::Routine(module, idx),
+                None => return Err(RuntimeError::UnknownRoutine { name: trap, span: Span::default() }),
+            };
+            self.trap = Some(self.frames.len() + 1);
+            call(Some(target), &trap, &[], Span::default(), &Arena::default(), self)?;
+            self.trap = None;
+        }
+        Ok(())
+    }
+
+    /// Procedure a late bound call names: one of the module of the calling
+    /// routine, a global one of another module, those of system modules last,
+    /// or one of the host or the built-in ones. Functions can't be called late
+    /// bound.
+    fn late_target(&self, name: &str) -> Option<Callee> {
+        let caller = self.frames.last().map(|frame| frame.module);
+        let routines = self.modules.iter().enumerate()
+            .flat_map(|(module, routines)| routines.routines.iter().enumerate().map(move |(idx, routine)| (module, idx, routine)));
+        let found = routines
+            .filter(|(module, _, routine)| routine.name.eq_ignore_ascii_case(name) && (!routine.local || Some(*module) == caller))
+            .min_by_key(|(module, ..)| (Some(*module) != caller, self.modules[*module].system));
+        match found {
+            Some((module, idx, routine)) => routine.return_type.is_none().then_some(Callee::Routine(module, idx)),
+            None => builtins::native_procedure(name, self.host_routines),
+        }
+    }
+
+    /// Write a line to the FlexPendant of the host, kept for the result of
+    /// the run
+    #[inline(never)]
+    fn write_line(&mut self, line: String) {
+        self.host.output.write(&line);
+        self.output.push(line);
+    }
+
+    // The observer is told about events apart from executing them, so that
+    // the frames of deeply nested statements stay small
+
+    #[inline(never)]
+    fn observe_statement(&mut self, span: Span) {
+        self.host.observe(|observer| observer.statement(span));
+    }
+
+    /// Tell the observer the routine of the current frame starts or ends
+    #[inline(never)]
+    fn observe_routine(&mut self, enter: bool) {
+        if let Some(frame) = self.frames.last() {
+            let (module, routine) = (frame.modules[frame.module].name.as_str(), frame.routine().name.as_str());
+            self.host.observe(|observer| if enter { observer.enter_routine(module, routine) } else { observer.exit_routine(module, routine) });
+        }
+    }
+
+    /// Tell the observer about an error, once on its way to the handler, and
+    /// keep the routine that raised it
+    #[inline(never)]
+    fn observe_error(&mut self, err: &RuntimeError) {
+        if !self.reported {
+            self.reported = true;
+            self.host.error_location = self.frames.last().map(|frame| Location {
+                module: frame.modules[frame.module].name.clone(),
+                routine: frame.routine().name.clone(),
+                span: err.span(),
+            });
+            self.host.observe(|observer| observer.error(err));
+        }
+    }
+
+    /// Tell the observer about the new value of the data of a Var or Global node
+    #[inline(never)]
+    fn observe_assign(&mut self, node: &Node) {
+        if self.host.observer.is_none() {
+            return;
+        }
+        let name = match node {
+            Node::Var(slot) => self.frames.last().and_then(|frame| {
+                let routine = frame.routine();
+                let arguments = routine.arguments.iter().map(|param| &param.decl);
+                arguments.chain(routine.variables.iter()).nth(*slot)
+            }),
+            Node::Global(slot) => self.system.iter().chain(self.modules.iter().flat_map(|module| module.variables.iter())).nth(*slot),
+            _ => None,
+        };
+        if let (Some(decl), Ok(var)) = (name, node.data(self)) {
+            let var = var.clone();
+            self.host.observe(|observer| observer.assign(&decl.name, &var));
+        }
+    }
+}
+
+impl Node {
+    fn eval(&self, nodes: &Arena, stack: &mut Stack) -> Result<Variable, RuntimeError> {
+        stack.enter()?;
+        grow(|| self.eval_expr(nodes, stack))
+    }
+
+    fn eval_expr(&self, nodes: &Arena, stack: &mut Stack) -> Result<Variable, RuntimeError> {
+        let var = match self {
+            Node::BinOp { op, lhs, rhs } => {
+                let lhs = nodes[*lhs].eval(nodes, stack)?;
+                let rhs = nodes[*rhs].eval(nodes, stack)?;
+                Variable::operate(*op, lhs, rhs)?
+            },
+            Node::OpNeg(node) => (Variable::Num(0.0) - nodes[*node].eval(nodes, stack)?)?,
+            Node::OpNot(node) => Variable::Bool(!nodes[*node].eval(nodes, stack)?.condition()?),
+            Node::Value(var) => var.clone(),
+            Node::Aggregate(values) => {
+                let values = values.iter().map(|value| value.eval(nodes, stack)).collect::<Result<_, _>>()?;
+                Variable::Record("", values)
+            },
+            Node::Var(idx) => {
+                match stack.local(*idx) {
+                    Some(var) => var.clone(),
+                    None => return Err(RuntimeError::UnknownData { slot: *idx, span: Span::default() }),
+                }
+            },
+            Node::Global(idx) => {
+                match stack.globals.get(*idx) {
+                    Some(var) => var.clone(),
+                    None => return Err(RuntimeError::UnknownData { slot: *idx, span: Span::default() }),
+                }
+            },
+            Node::Index { base, indices } => {
+                let indices = eval_indices(indices, nodes, stack)?;
+                nodes[*base].data(stack)?.element(&indices)?.clone()
+            },
+            Node::Present(slot) => {
+                let passed = stack.frames.last().and_then(|frame| frame.passed.get(*slot));
+                Variable::Bool(passed.is_some_and(Option::is_some))
+            },
+            Node::IsStorage(slot, storage) => {
+                let passed = stack.frames.last().and_then(|frame| frame.passed.get(*slot));
+                Variable::Bool(passed == Some(&Some(*storage)))
+            },
+            Node::Errno => Variable::Num(stack.errno as f64),
+            Node::Id(name, span) => {
+                return Err(RuntimeError::Unsupported { message: format!("Unresolved id {}", name), span: *span });
+            },
+            Node::FuncCall { name, args, span, target } => {
+                match call(*target, name, args, *span, nodes, stack)? {
+                    Some(var) => var,
+                    None => return Err(RuntimeError::MissingReturn { name: name.clone(), span: *span }),
+                }
+            },
+            // Statements evaluate to nothing
+            node => {
+                node.execute(nodes, stack)?;
+                Variable::Void
+            },
+        };
+        Ok(var)
+    }
+
+    /// Execute a statement node. Kept apart from `eval_expr` so that the
+    /// frames of deeply nested expressions stay small.
+    fn execute(&self, nodes: &Arena, stack: &mut Stack) -> Result<(), RuntimeError> {
+        match self {
+            Node::Assign { lhs, rhs }=> {
+                let var_rhs = nodes[*rhs].eval(nodes, stack)?;
+                nodes[*lhs].assign(nodes, stack, var_rhs)?;
+            },
+            Node::Print { text, arg } => {
+                let text = nodes[*text].eval(nodes, stack)?;
+                let arg = match arg {
+                    Some((kind, node)) => Some((*kind, nodes[*node].eval(nodes, stack)?)),
+                    None => None,
+                };
+                stack.write_line(host::write_line(text, arg)?);
+            },
+            Node::If { branches, otherwise } => execute_if(branches, otherwise, nodes, stack)?,
+            Node::Test { value, cases, default } => execute_test(&nodes[*value], cases, default, nodes, stack)?,
+            Node::While { condition, body } => {
+                while !stack.returning() && nodes[*condition].eval(nodes, stack)?.condition()? {
+                    execute_body(body, nodes, stack)?;
+                }
+            },
+            Node::For { var, from, to, step, body } => {
+                let step = step.map(|step| &nodes[step]);
+                execute_for(&nodes[*var], &nodes[*from], &nodes[*to], step, body, nodes, stack)?;
+            },
+            Node::ProcCall { name, args, span, target } => {
+                call(*target, name, args, *span, nodes, stack)?;
+            },
+            Node::LateCall { name, args, span } => return late_call(&nodes[*name], args, *span, nodes, stack),
+            Node::Connect { .. } => return connect(self, nodes, stack),
+            Node::Return(value) => {
+                let result = match value {
+                    Some(node) => nodes[*node].eval(nodes, stack)?,
+                    None => Variable::Void,
+                };
+                if let Some(frame) = stack.frames.last_mut() {
+                    frame.result = Some(result);
+                }
+            },
+            Node::Retry | Node::TryNext | Node::Raise(_) => self.execute_recovery(nodes, stack)?,
+            node => {
+                node.eval(nodes, stack)?;
+            },
+        }
+        Ok(())
+    }
+
+    /// Execute RETRY, TRYNEXT or RAISE, kept apart from `execute` like `recover`
+    #[inline(never)]
+    fn execute_recovery(&self, nodes: &Arena, stack: &mut Stack) -> Result<(), RuntimeError> {
+        let err = match self {
+            Node::Retry | Node::TryNext => {
+                if let Some(frame) = stack.frames.last_mut() {
+                    frame.resume = Some(if let Node::Retry = self { Resume::Retry } else { Resume::TryNext });
+                }
+                return Ok(());
+            },
+            Node::Raise(Some(errno)) => RuntimeError::raise(nodes[*errno].eval(nodes, stack)?.number()?),
+            _ => match stack.frames.last().and_then(|frame| frame.error.clone()) {
+                Some(err) => err,
+                None => RuntimeError::Unsupported { message: String::from("RAISE without an error to pass on"), span: Span::default() },
+            },
+        };
+        Err(err)
+    }
+
+    fn assign(&self, nodes: &Arena, stack: &mut Stack, other: Variable) -> Result<(), RuntimeError> {
+        let data = match self {
+            Node::Index { base, indices } => {
+                let indices = eval_indices(indices, nodes, stack)?;
+                let base = &nodes[*base];
+                base.data_mut(stack)?.element_mut(&indices)?.set(other)?;
+                base
+            },
+            node => {
+                node.data_mut(stack)?.set(other)?;
+                node
+            },
+        };
+        if stack.host.observer.is_some() {
+            stack.observe_assign(data);
+        }
+        Ok(())
+    }
+
+    /// Data object of a Var or Global node
+    fn data<'s>(&self, stack: &'s Stack) -> Result<&'s Variable, RuntimeError> {
+        let (var, idx) = match self {
+            Node::Var(idx) => (stack.local(*idx), *idx),
+            Node::Global(idx) => (stack.globals.get(*idx), *idx),
+            _ => return Err(RuntimeError::type_mismatch(String::from("Can only index data"))),
+        };
+        var.ok_or(RuntimeError::UnknownData { slot: idx, span: Span::default() })
+    }
+
+    fn data_mut<'s>(&self, stack: &'s mut Stack) -> Result<&'s mut Variable, RuntimeError> {
+        let (var, idx) = match self {
+            Node::Var(idx) => (stack.local_mut(*idx), *idx),
+            Node::Global(idx) => (stack.globals.get_mut(*idx), *idx),
+            _ => return Err(RuntimeError::type_mismatch(String::from("Can only assign to variable"))),
+        };
+        var.ok_or(RuntimeError::UnknownData { slot: idx, span: Span::default() })
+    }
+}
+
+fn eval_indices(indices: &[Node], nodes: &Arena, stack: &mut Stack) -> Result<Vec<f64>, RuntimeError> {
+    indices.iter().map(|index| index.eval(nodes, stack)?.number()).collect()
+}
+
+impl Variable {
+    /// Value of a condition in IF, WHILE, NOT, ...
+    pub fn condition(&self) -> Result<bool, RuntimeError> {
+        match self {
+            Variable::Bool(value) => Ok(*value),
+            var => Err(RuntimeError::type_mismatch(format!("Expected bool condition, found {}", var.type_name()))),
+        }
+    }
+
+    /// Value of a FOR loop bound or step
+    pub fn number(&self) -> Result<f64, RuntimeError> {
+        match self {
+            Variable::Num(value) | Variable::Dnum(value) => Ok(*value),
+            Variable::Byte(value) => Ok(*value as f64),
+            var => Err(RuntimeError::type_mismatch(format!("Expected num, found {}", var.type_name()))),
+        }
+    }
+}
+
+impl Statement {
+    fn execute(&self, nodes: &Arena, stack: &mut Stack) -> Result<(), RuntimeError> {
+        let span = self.span;
+        stack.enter_statement().map_err(|err| err.at(span))?;
+        if stack.host.observer.is_some() {
+            stack.observe_statement(span);
+        }
+        grow(|| match self.node.execute(nodes, stack) {
+            Err(err) => self.recover(err.at(span), nodes, stack),
+            result => result,
+        })
+    }
+
+    /// Run the ERROR handler of the routine for an error of the statement,
+    /// until it leaves the statement behind. Kept apart from `execute` so
+    /// that the frames of deeply nested statements stay small.
+    #[inline(never)]
+    fn recover(&self, mut err: RuntimeError, nodes: &Arena, stack: &mut Stack) -> Result<(), RuntimeError> {
+        stack.observe_error(&err);
+        while stack.handle(err)? == Resume::Retry {
+            match self.node.execute(nodes, stack) {
+                Ok(()) => break,
+                Err(next) => {
+                    err = next.at(self.span);
+                    stack.observe_error(&err);
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+fn execute_body(body: &[Statement], nodes: &Arena, stack: &mut Stack) -> Result<(), RuntimeError> {
+    for statement in body {
+        statement.execute(nodes, stack)?;
+        if stack.returning() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn execute_if(branches: &[(Node, Vec<Statement>)], otherwise: &[Statement], nodes: &Arena, stack: &mut Stack) -> Result<(), RuntimeError> {
+    for (condition, body) in branches {
+        if condition.eval(nodes, stack)?.condition()? {
+            return execute_body(body, nodes, stack);
+        }
+    }
+    execute_body(otherwise, nodes, stack)
+}
+
+fn execute_test(value: &Node, cases: &[(Vec<Node>, Vec<Statement>)], default: &[Statement], nodes: &Arena, stack: &mut Stack) -> Result<(), RuntimeError> {
+    let value = value.eval(nodes, stack)?;
+    for (values, body) in cases {
+        for case in values {
+            if value.equals(&case.eval(nodes, stack)?)? {
+                return execute_body(body, nodes, stack);
+            }
+        }
+    }
+    execute_body(default, nodes, stack)
+}
+
+fn execute_for(var: &Node, from: &Node, to: &Node, step: Option<&Node>, body: &[Statement], nodes: &Arena, stack: &mut Stack) -> Result<(), RuntimeError> {
+    // The bounds are evaluated once, before the first iteration
+    let from = from.eval(nodes, stack)?.number()?;
+    let to = to.eval(nodes, stack)?.number()?;
+    let step = match step {
+        Some(step) => step.eval(nodes, stack)?.number()?,
+        None if from > to => -1.0,
+        None => 1.0,
+    };
+
+    let mut counter = from;
+    while !stack.returning() && (if step < 0.0 { counter >= to } else { counter <= to }) {
+        var.assign(nodes, stack, Variable::Num(counter))?;
+        execute_body(body, nodes, stack)?;
+        counter += step;
+    }
+    Ok(())
+}
+
+/// Parameter slot of each argument of a call: named arguments bind by name,
+/// the others to the required parameters in order
+pub fn param_slots(params: &[Param], routine: &str, args: &[Argument]) -> Result<Vec<usize>, RuntimeError> {
+    named_slots(params, routine, args.iter().map(|arg| (arg.name.as_deref(), arg.span)))
+}
+
+fn named_slots<'n>(params: &[Param], routine: &str, args: impl Iterator<Item = (Option<&'n str>, Span)>) -> Result<Vec<usize>, RuntimeError> {
+    let mut required = params.iter().enumerate().filter(|(_, param)| !param.optional);
+    let mut slots = Vec::new();
+    for (name, span) in args {
+        let slot = match name {
+            None => required.next().map(|(slot, _)| slot),
+            Some(arg_name) => params.iter().position(|param| param.decl.name.eq_ignore_ascii_case(arg_name)),
+        };
+        match slot {
+            Some(slot) => slots.push(slot),
+            None => return Err(RuntimeError::Unsupported { message: format!("Invalid argument in call to {}", routine), span }),
+        }
+    }
+    Ok(slots)
+}
+
+/// Data an argument passes, for IsPers and IsVar of an INOUT parameter:
+/// data declared with a storage, or an INOUT parameter of the calling
+/// routine passed on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Passed {
+    Data(Storage),
+    InOut(usize),
+}
+
+impl Passed {
+    /// What the argument `node` of a call in `routine` passes
+    pub fn from(node: &Node, routine: &Routine, globals: &[DataName]) -> Passed {
+        match node {
+            Node::Index { base, .. } => Passed::from(&routine.nodes[*base], routine, globals),
+            Node::Global(slot) => Passed::Data(globals.get(*slot).map_or(Storage::Var, |data| data.storage)),
+            Node::Var(slot) => match routine.arguments.get(*slot).map(|param| param.mode) {
+                Some(ParamMode::InOut) => Passed::InOut(*slot),
+                Some(ParamMode::Pers) => Passed::Data(Storage::Pers),
+                Some(ParamMode::Var | ParamMode::In) => Passed::Data(Storage::Var),
+                None => {
+                    let decl = routine.variables.get(slot - routine.arguments.len());
+                    Passed::Data(decl.map_or(Storage::Var, |decl| decl.storage))
+                },
+            },
+            // Values are passed like the data of a VAR
+            _ => Passed::Data(Storage::Var),
+        }
+    }
+}
+
+/// Name of the data the argument `node` of a call in `routine` passes,
+/// `None` for a value or an array element
+pub fn data_name(node: &Node, routine: &Routine, globals: &[DataName]) -> Option<String> {
+    match node {
+        Node::Global(slot) => globals.get(*slot).map(|data| data.name.clone()),
+        Node::Var(slot) => match routine.arguments.get(*slot) {
+            Some(param) => Some(param.decl.name.clone()),
+            None => routine.variables.get(slot - routine.arguments.len()).map(|decl| decl.name.clone()),
+        },
+        _ => None,
+    }
+}
+
+/// Argument of a late bound call, as far as it's known before the procedure is
+#[derive(Debug, Clone)]
+pub struct LateArg {
+    pub name: Option<String>,
+    // Switches are passed without a value
+    pub value: bool,
+    // Whether the value is a data object, which can take a VAR, PERS or
+    // INOUT parameter
+    pub data: bool,
+    pub passed: Passed,
+}
+
+impl LateArg {
+    pub fn from(arg: &Argument, routine: &Routine, globals: &[DataName]) -> LateArg {
+        LateArg {
+            name: arg.name.clone(),
+            value: arg.value.is_some(),
+            data: matches!(arg.value, Some(Node::Var(_) | Node::Global(_) | Node::Index { .. })),
+            passed: arg.value.as_ref().map_or(Passed::Data(Storage::Var), |node| Passed::from(node, routine, globals)),
+        }
+    }
+}
+
+/// Parameter slot of each argument of a late bound call. The resolver
+/// can't check these calls, so the arguments are checked once the
+/// procedure is known, a mistake is an error the program can handle.
+pub fn late_slots(params: &[Param], routine: &str, args: &[LateArg], span: Span) -> Result<Vec<usize>, RuntimeError> {
+    let invalid = |message: String| Err(RuntimeError::TypeMismatch { message, span });
+    let slots = match named_slots(params, routine, args.iter().map(|arg| (arg.name.as_deref(), span))) {
+        Ok(slots) => slots,
+        Err(_) => return invalid(format!("Invalid argument in call to {}", routine)),
+    };
+    for (slot, arg) in slots.iter().zip(args) {
+        let param = &params[*slot];
+        if param.decl.data_type.eq_ignore_ascii_case("switch") == arg.value {
+            return invalid(format!("Invalid argument \\{} in call to {}", param.decl.name, routine));
+        }
+        if param.mode != ParamMode::In && !arg.data {
+            return invalid(format!("Argument {} of {} must be data", param.decl.name, routine));
+        }
+    }
+    match params.iter().enumerate().find(|(slot, param)| !param.optional && !slots.contains(slot)) {
+        Some((_, param)) => invalid(format!("Missing argument {} in call to {}", param.decl.name, routine)),
+        None => Ok(slots),
+    }
+}
+
+/// Give the intnum data of a CONNECT a new interrupt number for the TRAP
+/// routine. Takes the whole node so that the frame of `execute` stays small.
+#[inline(never)]
+fn connect(node: &Node, nodes: &Arena, stack: &mut Stack) -> Result<(), RuntimeError> {
+    let (interrupt, trap, target) = match node {
+        Node::Connect { interrupt, trap, target, .. } => (&nodes[*interrupt], trap, *target),
+        _ => return Ok(()),
+    };
+    let number = interrupt.eval(nodes, stack)?.number()?;
+    if stack.host.interrupts.is_connected(number) {
+        return Err(RuntimeError::AlreadyConnected { span: Span::default() });
+    }
+    let module = match target {
+        Some(Callee::Routine(module, _)) => stack.modules.get(module).map(|module| module.name.clone()),
+        _ => None,
+    };
+    let module = module.ok_or_else(|| RuntimeError::UnknownRoutine { name: String::from(trap), span: Span::default() })?;
+    let number = stack.host.interrupts.connect(&module, trap);
+    interrupt.assign(nodes, stack, Variable::Num(number))
+}
+
+/// Call the procedure a string names, with the arguments checked then
+#[inline(never)]
+fn late_call(name: &Node, args: &[Argument], span: Span, nodes: &Arena, stack: &mut Stack) -> Result<(), RuntimeError> {
+    let name = match name.eval(nodes, stack)? {
+        Variable::Str(name) => name,
+        var => return Err(RuntimeError::type_mismatch(format!("Late binding expects a string, found {}", var.type_name()))),
+    };
+    let target = stack.late_target(&name);
+    let params = match target {
+        Some(Callee::Routine(module, idx)) => &stack.modules[module].routines[idx].arguments,
+        Some(Callee::Builtin(idx)) => &builtins::builtins()[idx].params,
+        Some(Callee::Host(idx)) => &stack.host_routines[idx].params,
+        None => return Err(RuntimeError::UnknownRoutine { name, span }),
+    };
+    let args = stack.present_args(args);
+    let late: Vec<LateArg> = match stack.frames.last() {
+        Some(frame) => args.iter().map(|arg| LateArg::from(arg, frame.routine(), &stack.names)).collect(),
+        None => return Err(RuntimeError::Unsupported { message: String::from("Call stack is empty"), span }),
+    };
+    late_slots(params, &name, &late, span)?;
+    call(target, &name, &args, span, nodes, stack)?;
+    Ok(())
+}
+
+/// Run a step of the recursive evaluation, on a new stack segment when
+/// little of the current one is left. Deep recursion of the program can't
+/// overflow the stack of the host, only `InterpreterOptions::max_depth`.
+fn grow<T>(step: impl FnOnce() -> T) -> T {
+    stacker::maybe_grow(128 * 1024, 1024 * 1024, step)
+}
+
+/// Call a routine in a new frame, returns the value of its RETURN.
+/// Arguments for VAR, PERS and INOUT parameters are copied back to the
+/// caller's data when the routine returns. The arguments are nodes of the
+/// calling routine, in `nodes`.
+fn call(target: Option<Callee>, name: &str, args: &[Argument], span: Span, nodes: &Arena, stack: &mut Stack) -> Result<Option<Variable>, RuntimeError> {
+    let modules = Rc::clone(&stack.modules);
+    let routine = match target {
+        Some(Callee::Routine(module, idx)) => modules.get(module).and_then(|module| module.routines.get(idx)),
+        Some(Callee::Builtin(idx)) => match builtins::builtins().get(idx) {
+            Some(builtin) => return call_native(&builtin.params, builtin.name, args, span, nodes, stack, |stack, values| stack.call_builtin(builtin, args, values)),
+            None => None,
+        },
+        Some(Callee::Host(idx)) => match stack.host_routines.get(idx) {
+            Some(routine) => return call_native(&routine.params, &routine.name, args, span, nodes, stack, |_, values| routine.call(values)),
+            None => None,
+        },
+        None => None,
+    };
+    let (routine, module, idx) = match (routine, target) {
+        (Some(routine), Some(Callee::Routine(module, idx))) => (routine, module, idx),
+        _ => return Err(RuntimeError::UnknownRoutine { name: String::from(name), span }),
+    };
+
+    let mut frame = Frame::new(Rc::clone(&modules), module, idx);
+    let mut outputs = Vec::new();
+    for (slot, arg) in param_slots(&routine.arguments, &routine.name, args)?.into_iter().zip(args) {
+        // Switches are passed without a value
+        let node = match stack.passes(arg) {
+            Some(Some(node)) => node,
+            None => continue,
+            Some(None) => {
+                frame.passed[slot] = Some(Storage::Var);
+                continue;
+            },
+        };
+        frame.passed[slot] = Some(stack.passed(node));
+        if routine.arguments[slot].mode != ParamMode::In {
+            outputs.push((slot, node));
+        }
+        let value = node.eval(nodes, stack)?;
+        routine.arguments[slot].bind(&mut frame.locals[slot], value).map_err(|err| err.at(arg.span))?;
+    }
+
+    if stack.frames.len() >= stack.max_depth {
+        return Err(RuntimeError::StackOverflow { depth: stack.max_depth, span });
+    }
+    stack.frames.push(frame);
+    stack.observe_routine(true);
+    let result = execute_body(&routine.statements, &routine.nodes, stack);
+    stack.observe_routine(false);
+    let frame = match stack.frames.pop() {
+        Some(frame) => frame,
+        None => return Err(RuntimeError::Unsupported { message: String::from("Call stack is empty"), span }),
+    };
+    result?;
+
+    for (slot, node) in outputs {
+        node.assign(nodes, stack, frame.locals[slot].clone())?;
+    }
+    Ok(frame.result)
+}
+
+/// Call a built-in or host routine with the evaluated arguments
+fn call_native<'a>(params: &[Param], name: &str, args: &[Argument], span: Span, nodes: &Arena, stack: &mut Stack<'a, '_>,
+    invoke: impl FnOnce(&mut Stack<'a, '_>, &mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError>) -> Result<Option<Variable>, RuntimeError> {
+    let mut values = vec![None; params.len()];
+    let mut outputs = Vec::new();
+    for (slot, arg) in param_slots(params, name, args)?.into_iter().zip(args) {
+        let value = match stack.passes(arg) {
+            None => continue,
+            Some(Some(node)) => {
+                if params[slot].mode != ParamMode::In {
+                    outputs.push((slot, node));
+                }
+                node.eval(nodes, stack)?
+            },
+            Some(None) => Variable::Void,
+        };
+        // Like on the VM, conversion errors point at the call
+        values[slot] = Some(builtins::argument(&params[slot], value).map_err(|err| err.at(span))?);
+    }
+
+    let result = invoke(stack, &mut values).map_err(|err| err.at(span))?;
+    for (slot, node) in outputs {
+        if let Some(value) = values[slot].take() {
+            node.assign(nodes, stack, value)?;
+        }
+    }
+    if !stack.host.module_changes.is_empty() {
+        stack.link().map_err(|err| err.at(span))?;
+    }
+    Ok(result)
+}
+
+// ------------------ Runs -----------------------/
+
+/// How a run ended
+#[derive(Debug, Clone)]
+pub enum Termination {
+    /// The routine returned
+    Completed,
+    /// EXIT stopped the program
+    Exit,
+    /// The instruction budget or the timeout of `InterpreterOptions` ran out
+    Limit { limit: Limit, span: Span },
+    /// Error that no ERROR handler handled
+    Error(RuntimeError),
+}
+
+/// What a run did and the data it left behind
+#[derive(Debug, Clone)]
+pub struct ExecutionResult {
+    pub termination: Termination,
+    /// Lines the program wrote to the FlexPendant, the output of the host
+    /// got them as well
+    pub output: Vec<String>,
+    /// Values of the PERS data of the modules afterwards, by name
+    pub pers: Vec<(String, Variable)>,
+    /// Statements and expressions executed, counted like
+    /// `InterpreterOptions::max_instructions`
+    pub executed: u64,
+    /// Time of the clock of the host that passed, simulated time with a
+    /// `ManualClock`
+    pub elapsed: Duration,
+    /// Module, routine and position of the error or limit that ended the run
+    pub location: Option<Location>,
+}
+
+impl ExecutionResult {
+    /// The result of a run that completed or was stopped by EXIT, or the
+    /// error or limit that ended it
+    pub fn into_result(self) -> Result<ExecutionResult, RuntimeError> {
+        match self.termination {
+            Termination::Completed | Termination::Exit => Ok(self),
+            Termination::Limit { limit, span } => Err(RuntimeError::LimitExceeded { limit, span }),
+            Termination::Error(err) => Err(err),
+        }
+    }
+
+    /// Error that ended the run, with the limit that ran out as one
+    pub fn error(&self) -> Option<RuntimeError> {
+        self.clone().into_result().err()
+    }
+}
+
+/// Run a resolved routine without arguments
+pub fn run(program: &mut Program, entry: &str, options: &InterpreterOptions, host: &mut Host) -> ExecutionResult {
+    let target = program.modules.iter().enumerate()
+        .find_map(|(module_idx, module)| {
+            module.routines.iter()
+                .position(|routine| routine.name.eq_ignore_ascii_case(entry))
+                .map(|idx| Callee::Routine(module_idx, idx))
+        });
+    let start = host.clock.now();
+    let mut output = Vec::new();
+    let mut executed = 0;
+    host.error_location = None;
+    let result = with_stack(program, options, host, |stack| {
+        let result = call(target, entry, &[], Span::default(), &Arena::default(), stack);
+        output = std::mem::take(&mut stack.output);
+        executed = stack.budget.executed;
+        result
+    });
+
+    let termination = match result {
+        Ok(_) => Termination::Completed,
+        Err(RuntimeError::Exit { .. }) => Termination::Exit,
+        Err(RuntimeError::LimitExceeded { limit, span }) => Termination::Limit { limit, span },
+        Err(err) => Termination::Error(err),
+    };
+    let pers = program.module_data().into_iter()
+        .filter(|(_, decl, _)| decl.storage == Storage::Pers)
+        .filter_map(|(_, decl, slot)| Some((decl.name.clone(), program.variables.get(slot)?.clone())))
+        .collect();
+    let location = match termination {
+        Termination::Completed | Termination::Exit => None,
+        Termination::Limit { .. } | Termination::Error(_) => host.error_location.take(),
+    };
+    ExecutionResult { termination, output, pers, executed, elapsed: host.clock.now().saturating_sub(start), location }
+}
+
+/// Call a routine of a resolved program with the values of its parameters,
+/// in the order they are declared. Optional parameters after the values
+/// are left out. Returns the value of a FUNC, or Void for a PROC. Values
+/// the routine assigns to its VAR, PERS and INOUT parameters are dropped.
+pub fn call_routine(program: &mut Program, name: &str, args: &[Variable], options: &InterpreterOptions, host: &mut Host) -> Result<Variable, RuntimeError> {
+    let found = program.modules.iter().enumerate()
+        .find_map(|(module_idx, module)| {
+            module.routines.iter()
+                .position(|routine| routine.name.eq_ignore_ascii_case(name))
+                .map(|idx| (module_idx, idx))
+        });
+    let (module, idx) = match found {
+        Some(found) => found,
+        None => return Err(RuntimeError::UnknownRoutine { name: String::from(name), span: Span::default() }),
+    };
+    let routine = &program.modules[module].routines[idx];
+    if args.len() > routine.arguments.len() || routine.arguments[args.len()..].iter().any(|param| !param.optional) {
+        return Err(RuntimeError::Unsupported { message: format!("Invalid argument in call to {}", routine.name), span: routine.span });
+    }
+
+    with_stack(program, options, host, |stack| {
+        let modules = Rc::clone(&stack.modules);
+        let routine = &modules[module].routines[idx];
+        let mut frame = Frame::new(Rc::clone(&modules), module, idx);
+        for (slot, value) in args.iter().enumerate() {
+            frame.passed[slot] = Some(Storage::Var);
+            routine.arguments[slot].bind(&mut frame.locals[slot], value.clone())?;
+        }
+
+        stack.frames.push(frame);
+        stack.observe_routine(true);
+        let result = execute_body(&routine.statements, &routine.nodes, stack);
+        stack.observe_routine(false);
+        let frame = stack.frames.pop();
+        result?;
+        match frame.and_then(|frame| frame.result) {
+            Some(value) => Ok(value),
+            None if routine.return_type.is_some() => Err(RuntimeError::MissingReturn { name: routine.name.clone(), span: routine.end }),
+            None => Ok(Variable::Void),
+        }
+    })
+}
+
+// ------------------ Builder -----------------------/
+
+/// Host and options of the runs of an embedding program, put together by
+/// `Interpreter::builder`
+pub struct Interpreter<'a> {
+    pub options: InterpreterOptions,
+    pub host: Host<'a>,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Builder of an interpreter that writes to `output`, with the settings
+    /// of `Host::new` and `InterpreterOptions::default` otherwise
+    pub fn builder(output: &'a mut dyn OutputSink) -> InterpreterBuilder<'a> {
+        InterpreterBuilder { options: InterpreterOptions::default(), host: Host::new(output) }
+    }
+
+    /// Run a resolved routine without arguments, like `run`
+    pub fn run(&mut self, program: &mut Program, entry: &str) -> ExecutionResult {
+        run(program, entry, &self.options, &mut self.host)
+    }
+
+    /// Call a routine with the values of its parameters, like `call_routine`
+    pub fn call(&mut self, program: &mut Program, name: &str, args: &[Variable]) -> Result<Variable, RuntimeError> {
+        call_routine(program, name, args, &self.options, &mut self.host)
+    }
+}
+
+pub struct InterpreterBuilder<'a> {
+    options: InterpreterOptions,
+    host: Host<'a>,
+}
+
+impl<'a> InterpreterBuilder<'a> {
+    pub fn output(mut self, output: &'a mut dyn OutputSink) -> InterpreterBuilder<'a> {
+        self.host.output = output;
+        self
+    }
+
+    /// Answers of the operator dialogs
+    pub fn input(mut self, input: &'a mut dyn InputProvider) -> InterpreterBuilder<'a> {
+        self.host = self.host.with_input(input);
+        self
+    }
+
+    pub fn clock(mut self, clock: &'a dyn Clock) -> InterpreterBuilder<'a> {
+        self.host = self.host.with_clock(clock);
+        self
+    }
+
+    pub fn io(mut self, io: &'a IoBoard) -> InterpreterBuilder<'a> {
+        self.host = self.host.with_io(io);
+        self
+    }
+
+    /// Settings of the host without a method here, like
+    /// `.host(|host| host.with_file_root("HOME"))`
+    pub fn host(mut self, configure: impl FnOnce(Host<'a>) -> Host<'a>) -> InterpreterBuilder<'a> {
+        self.host = configure(self.host);
+        self
+    }
+
+    pub fn max_depth(mut self, depth: usize) -> InterpreterBuilder<'a> {
+        self.options.max_depth = depth;
+        self
+    }
+
+    pub fn max_instructions(mut self, count: u64) -> InterpreterBuilder<'a> {
+        self.options.max_instructions = Some(count);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> InterpreterBuilder<'a> {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    pub fn pers_file(mut self, path: impl Into<PathBuf>) -> InterpreterBuilder<'a> {
+        self.options.pers_file = Some(path.into());
+        self
+    }
+
+    /// Fail on values in the PERS file of data the program doesn't declare
+    pub fn strict_pers(mut self, strict: bool) -> InterpreterBuilder<'a> {
+        self.options.strict_pers = strict;
+        self
+    }
+
+    pub fn build(self) -> Interpreter<'a> {
+        Interpreter { options: self.options, host: self.host }
+    }
+}
+
+/// Run `execute` on a stack of the program's data and modules, which go back
+/// to the program afterwards. With a PERS file, its values are loaded before
+/// and stored after.
+fn with_stack<T>(program: &mut Program, options: &InterpreterOptions, host: &mut Host,
+    execute: impl FnOnce(&mut Stack) -> Result<T, RuntimeError>) -> Result<T, RuntimeError> {
+    if let Some(path) = &options.pers_file {
+        persistence::load_file(program, path, options.strict_pers)?;
+    }
+
+    let mut stack = Stack {
+        host,
+        frames: Vec::new(),
+        globals: std::mem::take(&mut program.variables),
+        names: compiler::global_names(&program.modules, &program.system),
+        modules: Rc::new(std::mem::take(&mut program.modules)),
+        loaded: std::mem::take(&mut program.loaded),
+        system: &program.system,
+        host_routines: &program.host_routines,
+        errno: 0,
+        reported: false,
+        trap: None,
+        max_depth: options.max_depth,
+        budget: Budget::new(options),
+        output: Vec::new(),
+    };
+
+    let result = execute(&mut stack);
+
+    program.variables = stack.globals;
+    program.modules = Rc::try_unwrap(stack.modules).unwrap_or_else(|modules| (*modules).clone());
+    program.loaded = stack.loaded;
+    // Stored after errors too, the controller keeps the values a stopped
+    // program left behind
+    let stored = match &options.pers_file {
+        Some(path) => persistence::store_file(program, path),
+        None => Ok(()),
+    };
+    let value = result?;
+    stored.map(|_| value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::VecDeque;
+    use crate::lexer;
+    use crate::parser;
+    use crate::resolver::{self, ResolveOptions};
+
+    /// Run `main` and return the global data afterwards
+    fn run_source(source: &str) -> Result<Vec<Variable>, RuntimeError> {
+        let mut program = parser::parse_tokens(lexer::parse(source)).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).into_result()?;
+        Ok(program.variables)
+    }
+
+    #[test]
+    fn evaluates_statements() {
+        let globals = run_source("
+MODULE Eval
+    VAR num nResult := 0;
+    VAR bool bFlag := FALSE;
+    PROC main()
+        nResult := 10 - 4 - 3 + 7 DIV 2 * 2;
+        IF nResult = 9 AND NOT bFlag THEN
+            bFlag := TRUE;
+        ENDIF
+        TEST nResult MOD 4
+        CASE 1:
+            nResult := -nResult;
+        ENDTEST
+    ENDPROC
+ENDMODULE").unwrap();
+        assert!(matches!(globals[0], Variable::Num(n) if n == -9.0));
+        assert!(matches!(globals[1], Variable::Bool(true)));
+    }
+
+    #[test]
+    fn reports_errors_with_span() {
+        let err = run_source("
+MODULE Eval
+    VAR num nResult := 0;
+    PROC main()
+        nResult := 1;
+        nResult := nResult / 0;
+    ENDPROC
+ENDMODULE").unwrap_err();
+        assert_eq!(err.name(), "ERR_DIVZERO");
+        assert_eq!(err.to_string(), "6:9: ERR_DIVZERO: Division by zero");
+
+        let err = run_source("
+MODULE Eval
+    VAR num nResult := 0;
+    PROC main()
+        nResult := \"text\";
+    ENDPROC
+ENDMODULE").unwrap_err();
+        assert!(matches!(err, RuntimeError::TypeMismatch { span, .. } if span.line == 5));
+    }
+
+    #[test]
+    fn calls_push_frames() {
+        let globals = run_source("
+MODULE Calls
+    VAR num nResult := 0;
+    VAR num nCount := 0;
+    FUNC num Fact(num n)
+        VAR num nLocal := 0;
+        nLocal := n;
+        IF n <= 1 RETURN 1;
+        RETURN nLocal * Fact(n - 1);
+    ENDFUNC
+    PROC rIncr(INOUT num nValue, \\num nStep)
+        nValue := nValue + 1;
+        RETURN;
+        nValue := 0;
+    ENDPROC
+    PROC main()
+        nResult := Fact(5);
+        rIncr nCount;
+        rIncr nCount \\nStep:=2;
+    ENDPROC
+ENDMODULE").unwrap();
+        assert!(matches!(globals[0], Variable::Num(n) if n == 120.0));
+        assert!(matches!(globals[1], Variable::Num(n) if n == 2.0));
+
+        let err = run_source("
+MODULE Calls
+    VAR num nResult := 0;
+    FUNC num Missing()
+    ENDFUNC
+    PROC main()
+        nResult := Missing();
+    ENDPROC
+ENDMODULE").unwrap_err();
+        assert_eq!(err.to_string(), "7:20: ERR_FNCNORET: FUNC Missing ended without RETURN");
+    }
+
+    #[test]
+    fn programs_run_repeatedly() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Loops
+    VAR num nSum := 0;
+    FUNC num SumTo(num nMax)
+        VAR num nTotal := 0;
+        FOR i FROM nMax TO 1 DO
+            nTotal := nTotal + i;
+        ENDFOR
+        WHILE TRUE DO
+            RETURN nTotal;
+        ENDWHILE
+    ENDFUNC
+    PROC main()
+        nSum := nSum + SumTo(4);
+        FOR i FROM 0 TO 10 STEP 5 DO
+            nSum := nSum + 1;
+        ENDFOR
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).into_result().unwrap();
+        run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).into_result().unwrap();
+        assert!(matches!(program.variables[0], Variable::Num(n) if n == 26.0));
+    }
+
+    #[test]
+    fn programs_reset_to_their_image() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Cycle
+    CONST num nParts := 3;
+    VAR num nDone := 1;
+    PERS string sLast := \"none\";
+    PROC main()
+        nDone := nDone + nParts;
+        sLast := \"part\";
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let image = program.image();
+        for _ in 0..3 {
+            program.reset(&image);
+            run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).into_result().unwrap();
+            assert!(matches!(program.variables[1], Variable::Num(n) if n == 4.0));
+            assert!(matches!(&program.variables[2], Variable::Str(text) if text == "part"));
+        }
+        program.reset(&image);
+        assert!(matches!(&program.variables[2], Variable::Str(text) if text == "none"));
+    }
+
+    #[test]
+    fn reports_how_runs_ended() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Cell
+    PERS num nCycles := 0;
+    VAR bool bStop := FALSE;
+    PROC main()
+        nCycles := nCycles + 1;
+        TPWrite \"Cycle \" \\Num:=nCycles;
+        IF bStop EXIT;
+        TPWrite \"Done\";
+    ENDPROC
+    PROC spin()
+        WHILE TRUE DO
+        ENDWHILE
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+
+        let mut output = Vec::new();
+        let result = run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut output));
+        assert!(matches!(result.termination, Termination::Completed));
+        assert_eq!(result.output, vec!["Cycle 1", "Done"]);
+        assert_eq!(result.output, output);
+        assert!(matches!(result.pers.as_slice(), [(name, Variable::Num(n))] if name == "nCycles" && *n == 1.0));
+        assert!(result.executed > 0);
+
+        program.variables[1] = Variable::Bool(true);
+        let result = run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new()));
+        assert!(matches!(result.termination, Termination::Exit));
+        assert_eq!(result.output, vec!["Cycle 2"]);
+        assert!(result.into_result().is_ok());
+
+        let options = InterpreterOptions { max_instructions: Some(100), ..InterpreterOptions::default() };
+        let result = run(&mut program, "spin", &options, &mut Host::new(&mut Vec::new()));
+        assert!(matches!(result.termination, Termination::Limit { limit: Limit::Instructions(100), .. }));
+        assert_eq!(result.executed, 101);
+        assert_eq!(result.error().map(|err| err.name()), Some("ERR_EXECLIMIT"));
+        assert_eq!(result.location.map(|location| (location.module, location.routine)), Some((String::from("Cell"), String::from("spin"))));
+    }
+
+    #[test]
+    fn locates_errors_in_their_module() {
+        let mut program = Program::from_source("MODULE Cell\n    PROC main()\n        rDivide;\n    ENDPROC\nENDMODULE").unwrap();
+        let other = Program::from_source("MODULE Tools\n    PROC rDivide()\n        VAR num n := 0;\n        n := 1 / n;\n    ENDPROC\nENDMODULE").unwrap();
+        program.modules.extend(other.modules);
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let bytecode = crate::compiler::compile(&program).unwrap();
+
+        let mut output = Vec::new();
+        let mut host = Host::new(&mut output);
+        let result = run(&mut program, "main", &InterpreterOptions::default(), &mut host);
+        let location = result.location.unwrap();
+        assert_eq!((location.to_string(), location.span.column), (String::from("Tools:4:9 in rDivide"), 9));
+        assert!(host.error_location.is_none());
+
+        crate::vm::run(&mut program, &bytecode, "main", &InterpreterOptions::default(), &mut host).unwrap_err();
+        let location = host.error_location.unwrap();
+        assert_eq!((location.module.as_str(), location.routine.as_str(), location.span.line), ("Tools", "rDivide", 4));
+    }
+
+    #[test]
+    fn calls_routines_from_rust() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Calc
+    VAR num nCalls := 0;
+    FUNC num Area(num nWidth, num nHeight, \\num nScale)
+        nCalls := nCalls + 1;
+        IF Present(nScale) RETURN nWidth * nHeight * nScale;
+        RETURN nWidth * nHeight;
+    ENDFUNC
+    FUNC string Missing()
+    ENDFUNC
+    PROC rCount(num nStep)
+        nCalls := nCalls + nStep;
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let area = program.call("area", &[Variable::Num(2.0), Variable::Num(3.0)]).unwrap();
+        assert!(matches!(area, Variable::Num(n) if n == 6.0));
+        let area = program.call("Area", &[Variable::Num(2.0), Variable::Num(3.0), Variable::Num(10.0)]).unwrap();
+        assert!(matches!(area, Variable::Num(n) if n == 60.0));
+        assert!(matches!(program.call("rCount", &[Variable::Num(5.0)]).unwrap(), Variable::Void));
+        assert!(matches!(program.variables[0], Variable::Num(n) if n == 7.0));
+
+        assert_eq!(program.call("Area", &[Variable::Num(2.0)]).unwrap_err().to_string(), "4:14: ERR_UNSUPPORTED: Invalid argument in call to Area");
+        assert_eq!(program.call("rCount", &[Variable::Str(String::from("5"))]).unwrap_err().name(), "ERR_ARGVALERR");
+        assert_eq!(program.call("Missing", &[]).unwrap_err().to_string(), "10:5: ERR_FNCNORET: FUNC Missing ended without RETURN");
+        assert_eq!(program.call("rMissing", &[]).unwrap_err().name(), "ERR_REFUNKPRC");
+    }
+
+    #[test]
+    fn deep_recursion_is_an_error() {
+        let err = run_source("
+MODULE Recursion
+    VAR num nResult := 0;
+    FUNC num Forever(num n)
+        RETURN Forever(n + 1);
+    ENDFUNC
+    PROC main()
+        nResult := Forever(0);
+    ENDPROC
+ENDMODULE").unwrap_err();
+        assert_eq!(err.name(), "ERR_STACKOVERFLOW");
+        assert_eq!(err.span().line, 5);
+
+        // Anything the parser accepts evaluates, however deep the stack of the host
+        let globals = run_source(&format!("
+MODULE Recursion
+    VAR num nResult := 0;
+    PROC main()
+        nResult := {}1{};
+        nResult := nResult{};
+    ENDPROC
+ENDMODULE", "-(1 + ".repeat(44), ")".repeat(44), " + 1".repeat(150))).unwrap();
+        assert!(matches!(globals[0], Variable::Num(n) if n == 151.0));
+
+        // The limit counts routine calls
+        let source = "
+MODULE Recursion
+    VAR num nResult := 0;
+    FUNC num Depth(num n)
+        IF n <= 1 RETURN 1;
+        RETURN 1 + Depth(n - 1);
+    ENDFUNC
+    PROC main()
+        nResult := Depth(249);
+    ENDPROC
+ENDMODULE";
+        let globals = run_source(source).unwrap();
+        assert!(matches!(globals[0], Variable::Num(n) if n == 249.0));
+        let mut program = parser::parse_tokens(lexer::parse(source)).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let options = InterpreterOptions { max_depth: 100, ..InterpreterOptions::default() };
+        let err = run(&mut program, "main", &options, &mut Host::new(&mut Vec::new())).into_result().unwrap_err();
+        assert_eq!(err.to_string(), "6:20: ERR_STACKOVERFLOW: Routine calls nested deeper than 100 levels");
+    }
+
+    #[test]
+    fn runaway_loops_hit_limits() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Limits
+    VAR num nCount := 0;
+    PROC main()
+        WHILE TRUE DO
+            nCount := nCount + 1;
+        ENDWHILE
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+
+        let options = InterpreterOptions { max_instructions: Some(1000), ..InterpreterOptions::default() };
+        let err = run(&mut program, "main", &options, &mut Host::new(&mut Vec::new())).into_result().unwrap_err();
+        assert!(matches!(err, RuntimeError::LimitExceeded { limit: Limit::Instructions(1000), .. }));
+        assert_eq!(err.span().line, 6);
+
+        let options = InterpreterOptions { timeout: Some(Duration::from_millis(10)), ..InterpreterOptions::default() };
+        let err = run(&mut program, "main", &options, &mut Host::new(&mut Vec::new())).into_result().unwrap_err();
+        assert_eq!(err.name(), "ERR_EXECLIMIT");
+        assert!(err.to_string().ends_with("Execution limit exceeded: running longer than 10ms"));
+    }
+
+    #[test]
+    fn writes_to_output_sink() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Output
+    VAR pos pHome := [100, 0, -50.5];
+    PROC main()
+        VAR num nCount := 3;
+        TPWrite \"Count: \" \\Num:=nCount / 4;
+        TPWrite \"Done: \" \\Bool:=nCount > 2;
+        pHome := [1, 2, 3];
+        TPWrite \"\" \\Pos:=pHome;
+        TPWrite \"Plain\";
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+
+        let mut output = Vec::new();
+        run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut output)).into_result().unwrap();
+        assert_eq!(output, vec!["Count: 0.75", "Done: TRUE", "[1,2,3]", "Plain"]);
+    }
+
+    #[test]
+    fn builds_interpreters() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Cell
+    VAR num nAnswer := 0;
+    PERS num nRuns := 0;
+    PROC main()
+        TPReadNum nAnswer, \"How many?\";
+        WaitTime 1.5;
+        SetDO doReady, 1;
+        nRuns := nRuns + 1;
+        TPWrite \"Runs \" \\Num:=nRuns;
+    ENDPROC
+ENDMODULE")).unwrap();
+        let mut io = host::IoBoard::new();
+        io.define("doReady", variable::SignalKind::DigitalOutput);
+        io.declare(&mut program);
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+
+        let path = std::env::temp_dir().join(format!("rapid_rust_builder_{}.toml", std::process::id()));
+        std::fs::write(&path, "[Cell]\nnRuns = 4\nnOther = 1\n").unwrap();
+        let clock = host::ManualClock::new(Duration::ZERO);
+        let mut answers = VecDeque::from(vec![7.0]);
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::builder(&mut output)
+            .input(&mut answers)
+            .clock(&clock)
+            .io(&io)
+            .max_instructions(1000)
+            .pers_file(&path)
+            .build();
+        let result = interpreter.run(&mut program, "main").into_result().unwrap();
+        assert_eq!(result.output, vec!["Runs 5"]);
+        assert_eq!(result.elapsed, Duration::from_millis(1500));
+        let answer = program.module_data().into_iter().find(|(_, decl, _)| decl.name == "nAnswer").map(|(_, _, slot)| slot).unwrap();
+        assert!(matches!(program.variables[answer], Variable::Num(n) if n == 7.0));
+        assert_eq!(io.read("doReady").unwrap(), 1.0);
+
+        // Stored without the data the program doesn't have
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "# PERS data, by module\n\n[Cell]\nnRuns = 5.0\n");
+        std::fs::write(&path, "[Cell]\nnOther = 1\n").unwrap();
+        let mut interpreter = Interpreter::builder(&mut output).pers_file(&path).strict_pers(true).build();
+        let err = interpreter.run(&mut program, "main").into_result().unwrap_err();
+        assert!(err.to_string().contains("No PERS data nOther in module Cell"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn reads_operator_input() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Input
+    VAR num nCount := 0;
+    VAR num nKey := 0;
+    PROC main()
+        TPReadNum nCount, \"How many?\";
+        TPReadFK nKey, \"Continue?\", \"Yes\", \"\", \"\", \"\", \"No\" \\MaxTime:=30;
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+
+        let mut answers = VecDeque::from(vec![3.0, 5.0]);
+        run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new()).with_input(&mut answers)).into_result().unwrap();
+        assert!(matches!(program.variables[0], Variable::Num(n) if n == 3.0));
+        assert!(matches!(program.variables[1], Variable::Num(n) if n == 5.0));
+
+        let err = run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).into_result().unwrap_err();
+        assert_eq!(err.to_string(), "6:9: ERR_TP_NO_CLIENT: No operator to answer the dialog");
+
+        let mut answers = VecDeque::from(vec![1.0]);
+        let err = run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new()).with_input(&mut answers)).into_result().unwrap_err();
+        assert_eq!(err.to_string(), "7:9: ERR_TP_MAXTIME: No answer from the operator within 30 s");
+
+        let mut answers = VecDeque::from(vec![1.0, 2.0]);
+        let err = run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new()).with_input(&mut answers)).into_result().unwrap_err();
+        assert_eq!(err.to_string(), "7:9: ERR_ARGVALERR: Function key 2 is not available");
+    }
+
+    #[test]
+    fn arrays_and_optional_arguments() {
+        let globals = run_source("
+MODULE Arrays
+    VAR num nGrid{2, 3} := [[1, 2, 3], [4, 5, 6]];
+    VAR num nSum := 0;
+    VAR num nFlags := 0;
+    PROC rCount(\\num nStep, \\switch Fast)
+        IF Present(nStep) nFlags := nFlags + 1;
+        IF Present(Fast) nFlags := nFlags + 10;
+    ENDPROC
+    PROC rIncr(INOUT num nValue)
+        nValue := nValue + 1;
+    ENDPROC
+    PROC main()
+        VAR num nList{4};
+        FOR i FROM 1 TO Dim(nList, 1) DO
+            nList{i} := i * i;
+        ENDFOR
+        FOR i FROM 1 TO Dim(nGrid, 1) DO
+            FOR j FROM 1 TO Dim(nGrid, 2) DO
+                nSum := nSum + nGrid{i, j};
+            ENDFOR
+        ENDFOR
+        nSum := nSum + nList{4};
+        rIncr nList{1};
+        nGrid{2, 3} := nList{1};
+        rCount;
+        rCount \\nStep:=1;
+        rCount \\Fast;
+    ENDPROC
+ENDMODULE").unwrap();
+        assert_eq!(format!("{:?}", globals), "[Array([Array([Num(1.0), Num(2.0), Num(3.0)]), Array([Num(4.0), Num(5.0), Num(2.0)])]), Num(37.0), Num(11.0)]");
+
+        let err = run_source("
+MODULE Arrays
+    VAR num nList{4};
+    PROC main()
+        nList{5} := 1;
+    ENDPROC
+ENDMODULE").unwrap_err();
+        assert_eq!(err.to_string(), "5:9: ERR_INDEX: Index 5 out of bounds 1 to 4");
+    }
+
+    #[test]
+    fn error_handlers_recover() {
+        let globals = run_source("
+MODULE Errors
+    VAR num nDivisor := 0;
+    VAR num nResult := 0;
+    VAR num nErrno := 0;
+    VAR num nSkipped := 0;
+    VAR string sText := \"\";
+    FUNC num Divide(num nValue)
+        RETURN nValue / nDivisor;
+    ENDFUNC
+    PROC rSkip()
+        VAR num nList{2};
+        nList{3} := 1;
+        nSkipped := nSkipped + 1;
+    ERROR
+        nErrno := ERRNO;
+        TRYNEXT;
+    ENDPROC
+    PROC rRaise()
+        RAISE 10;
+    ENDPROC
+    PROC main()
+        nResult := Divide(10);
+        rSkip;
+        FOR i FROM 1 TO 10 DO
+            sText := sText + \"0123456789\";
+        ENDFOR
+        rRaise;
+        nResult := 0;
+    ERROR
+        TEST ERRNO
+        CASE ERR_DIVZERO:
+            nDivisor := 2;
+            RETRY;
+        CASE ERR_STRTOOLONG:
+            TRYNEXT;
+        CASE 10:
+            nResult := nResult + 100;
+        ENDTEST
+    ENDPROC
+ENDMODULE").unwrap();
+        assert_eq!(format!("{:?}", &globals[..4]), "[Num(2.0), Num(105.0), Num(1004.0), Num(1.0)]");
+        assert!(matches!(&globals[4], Variable::Str(text) if text.len() == 80));
+
+        let err = run_source("
+MODULE Errors
+    VAR num nResult := 0;
+    PROC rFail()
+        nResult := 1 / 0;
+    ERROR
+        RAISE;
+    ENDPROC
+    PROC main()
+        rFail;
+    ENDPROC
+ENDMODULE").unwrap_err();
+        assert_eq!(err.to_string(), "5:9: ERR_DIVZERO: Division by zero");
+
+        let err = run_source("
+MODULE Errors
+    VAR num nResult := 0;
+    FUNC num Fail()
+        RAISE 10;
+    ERROR
+        nResult := ERRNO;
+    ENDFUNC
+    PROC main()
+        nResult := Fail();
+    ERROR
+        RAISE 90 + 1;
+    ENDPROC
+ENDMODULE").unwrap_err();
+        assert_eq!(err.to_string(), "12:9: ERR_ARGVALERR: Invalid error number 91");
+    }
+}
@@ -0,0 +1,603 @@
+use std::fmt;
+
+use crate::host::{Target, Waypoint};
+use crate::lexer::Span;
+use crate::parser::{Argument, Arena, Callee, DataDecl, Module, Node, NodeId, Operator, Param, Routine, Statement, Storage, WriteArg};
+use crate::variable::Variable;
+
+// ------------------ JSON -----------------------/
+
+/// JSON value, for the messages of the language server. Object members
+/// keep their order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut reader = Reader { chars: text.chars().collect(), pos: 0 };
+        let value = reader.value()?;
+        reader.skip_space();
+        match reader.chars.get(reader.pos) {
+            None => Ok(value),
+            Some(c) => Err(format!("Unexpected '{}' after the value at {}", c, reader.pos)),
+        }
+    }
+
+    pub fn object(members: Vec<(&str, Json)>) -> Json {
+        Json::Object(members.into_iter().map(|(name, value)| (String::from(name), value)).collect())
+    }
+
+    pub fn str(text: &str) -> Json {
+        Json::Str(String::from(text))
+    }
+
+    /// Member of an object
+    pub fn get(&self, name: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(member, _)| member == name).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Member of nested objects, like `["params", "textDocument", "uri"]`
+    pub fn path(&self, names: &[&str]) -> Option<&Json> {
+        names.iter().try_fold(self, |value, name| value.get(name))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Num(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Num(value) if value.is_finite() => write!(f, "{}", value),
+            Json::Num(_) => write!(f, "null"),
+            Json::Str(text) => write_str(f, text),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (idx, (name, value)) in members.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_str(f, name)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+fn write_str(f: &mut fmt::Formatter, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Reader {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Reader {
+    fn skip_space(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        Err(format!("{} at {}", message, self.pos))
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_space();
+        if self.chars.get(self.pos) == Some(&c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_space();
+        match self.chars.get(self.pos) {
+            Some('{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                if self.eat('}') {
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.skip_space();
+                    let name = match self.value()? {
+                        Json::Str(name) => name,
+                        _ => return self.error("Expected a member name"),
+                    };
+                    if !self.eat(':') {
+                        return self.error("Expected ':'");
+                    }
+                    members.push((name, self.value()?));
+                    if self.eat('}') {
+                        return Ok(Json::Object(members));
+                    }
+                    if !self.eat(',') {
+                        return self.error("Expected ',' or '}'");
+                    }
+                }
+            },
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if self.eat(']') {
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    if self.eat(']') {
+                        return Ok(Json::Array(items));
+                    }
+                    if !self.eat(',') {
+                        return self.error("Expected ',' or ']'");
+                    }
+                }
+            },
+            Some('"') => {
+                self.pos += 1;
+                self.string().map(Json::Str)
+            },
+            Some(c) if *c == '-' || c.is_ascii_digit() => {
+                let start = self.pos;
+                while self.chars.get(self.pos).is_some_and(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E')) {
+                    self.pos += 1;
+                }
+                let number: String = self.chars[start..self.pos].iter().collect();
+                number.parse().map(Json::Num).or_else(|_| self.error("Invalid number"))
+            },
+            Some(_) => {
+                for (word, value) in [("true", Json::Bool(true)), ("false", Json::Bool(false)), ("null", Json::Null)].iter() {
+                    if self.chars[self.pos..].iter().take(word.len()).eq(word.chars().collect::<Vec<_>>().iter()) {
+                        self.pos += word.len();
+                        return Ok(value.clone());
+                    }
+                }
+                self.error("Expected a value")
+            },
+            None => self.error("Expected a value"),
+        }
+    }
+
+    /// Rest of a string after its opening quote
+    fn string(&mut self) -> Result<String, String> {
+        let mut text = String::new();
+        loop {
+            let c = match self.chars.get(self.pos) {
+                Some(c) => *c,
+                None => return self.error("Unterminated string"),
+            };
+            self.pos += 1;
+            match c {
+                '"' => return Ok(text),
+                '\\' => {
+                    let escape = self.chars.get(self.pos).copied();
+                    self.pos += 1;
+                    match escape {
+                        Some('"') => text.push('"'),
+                        Some('\\') => text.push('\\'),
+                        Some('/') => text.push('/'),
+                        Some('b') => text.push('\u{8}'),
+                        Some('f') => text.push('\u{c}'),
+                        Some('n') => text.push('\n'),
+                        Some('r') => text.push('\r'),
+                        Some('t') => text.push('\t'),
+                        Some('u') => {
+                            let mut code = self.hex()?;
+                            // Characters outside the basic plane are a surrogate pair
+                            if (0xd800..0xdc00).contains(&code) && self.chars.get(self.pos) == Some(&'\\') && self.chars.get(self.pos + 1) == Some(&'u') {
+                                self.pos += 2;
+                                let low = self.hex()?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            text.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                        },
+                        _ => return self.error("Invalid escape"),
+                    }
+                },
+                c => text.push(c),
+            }
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        let digits: String = self.chars.iter().skip(self.pos).take(4).collect();
+        self.pos += 4;
+        match u32::from_str_radix(&digits, 16) {
+            Ok(code) if digits.len() == 4 => Ok(code),
+            _ => self.error("Invalid \\u escape"),
+        }
+    }
+}
+
+// ------------------ AST -----------------------/
+
+/// Value that has a JSON form, for tools that don't read RAPID themselves
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+fn num(value: usize) -> Json {
+    Json::Num(value as f64)
+}
+
+fn array<T: ToJson>(items: &[T]) -> Json {
+    Json::Array(items.iter().map(ToJson::to_json).collect())
+}
+
+fn optional<T: ToJson>(item: Option<&T>) -> Json {
+    item.map_or(Json::Null, ToJson::to_json)
+}
+
+impl ToJson for Span {
+    fn to_json(&self) -> Json {
+        Json::object(vec![("line", num(self.line)), ("column", num(self.column)), ("start", num(self.start)), ("end", num(self.end))])
+    }
+}
+
+impl ToJson for Module {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("name", Json::str(&self.name)),
+            ("attributes", Json::Array(self.attributes.iter().map(|attribute| Json::str(attribute)).collect())),
+            ("system", Json::Bool(self.system)),
+            ("header", self.header.as_ref().map_or(Json::Null, |fields| {
+                Json::Object(fields.iter().map(|(name, value)| (name.clone(), Json::str(value))).collect())
+            })),
+            ("data", array(&self.variables)),
+            ("routines", array(&self.routines)),
+            ("span", self.span.to_json()),
+            ("end", self.end.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Routine {
+    fn to_json(&self) -> Json {
+        let ast = Ast { nodes: &self.nodes };
+        Json::object(vec![
+            ("name", Json::str(&self.name)),
+            ("local", Json::Bool(self.local)),
+            ("return_type", self.return_type.as_deref().map_or(Json::Null, Json::str)),
+            ("trap", Json::Bool(self.trap)),
+            ("parameters", array(&self.arguments)),
+            ("data", array(&self.variables)),
+            ("statements", ast.body(&self.statements)),
+            ("handler", self.handler.as_deref().map_or(Json::Null, |handler| ast.body(handler))),
+            ("span", self.span.to_json()),
+            ("end", self.end.to_json()),
+        ])
+    }
+}
+
+fn storage(storage: Storage) -> Json {
+    Json::str(storage.keyword())
+}
+
+impl ToJson for DataDecl {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("name", Json::str(&self.name)),
+            ("data_type", Json::str(&self.data_type)),
+            ("storage", storage(self.storage)),
+            ("local", Json::Bool(self.local)),
+            ("value", self.value.to_json()),
+            ("span", self.span.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Param {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("name", Json::str(&self.decl.name)),
+            ("data_type", Json::str(&self.decl.data_type)),
+            ("mode", Json::str(self.mode.keyword())),
+            ("optional", Json::Bool(self.optional)),
+            ("group", num(self.group)),
+            ("span", self.decl.span.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Variable {
+    fn to_json(&self) -> Json {
+        match self {
+            Variable::Void => Json::Null,
+            Variable::Bool(value) => Json::Bool(*value),
+            Variable::Num(value) | Variable::Dnum(value) => Json::Num(*value),
+            Variable::Byte(value) => Json::Num(f64::from(*value)),
+            Variable::Str(text) => Json::str(text),
+            Variable::Array(items) => array(items),
+            Variable::Record(name, components) => Json::object(vec![("record", Json::str(name)), ("components", array(components))]),
+            // Data of the system has no value in the source
+            _ => Json::object(vec![("type", Json::str(self.type_name()))]),
+        }
+    }
+}
+
+/// Move of the trajectory log, with the position as `pos` and `rot` of a
+/// robtarget or as `joints` of a jointtarget
+impl ToJson for Waypoint {
+    fn to_json(&self) -> Json {
+        let nums = |values: &[f64]| Json::Array(values.iter().map(|value| Json::Num(*value)).collect());
+        let name = |name: &Option<String>| name.as_deref().map_or(Json::Null, Json::str);
+        let mut members = vec![
+            ("instruction", Json::str(self.instruction)),
+            ("target", name(&self.name)),
+            ("frame", name(&self.frame)),
+            ("programmed_speed", Json::Num(self.programmed_speed)),
+            ("speed", Json::Num(self.speed)),
+            ("zone", self.zone.map_or(Json::Null, Json::Num)),
+            ("time", Json::Num(self.time.as_secs_f64())),
+        ];
+        match &self.target {
+            Target::Cartesian { pos, rot } => members.extend([("pos", nums(pos)), ("rot", nums(rot))]),
+            Target::Joints(joints) => members.push(("joints", nums(joints))),
+        }
+        if let Some(via) = &self.via {
+            members.push(("via", nums(via)));
+        }
+        Json::object(members)
+    }
+}
+
+impl ToJson for Callee {
+    fn to_json(&self) -> Json {
+        match self {
+            Callee::Routine(module, routine) => Json::object(vec![("kind", Json::str("routine")), ("module", num(*module)), ("routine", num(*routine))]),
+            Callee::Builtin(idx) => Json::object(vec![("kind", Json::str("builtin")), ("index", num(*idx))]),
+            Callee::Host(idx) => Json::object(vec![("kind", Json::str("host")), ("index", num(*idx))]),
+        }
+    }
+}
+
+/// Writes the statements and expressions of a routine, with the operands
+/// looked up in its arena and written in place
+struct Ast<'a> {
+    nodes: &'a Arena,
+}
+
+impl<'a> Ast<'a> {
+    fn body(&self, statements: &[Statement]) -> Json {
+        Json::Array(statements.iter().map(|statement| self.statement(statement)).collect())
+    }
+
+    /// Statement as its node with the span added
+    fn statement(&self, statement: &Statement) -> Json {
+        match self.node(&statement.node) {
+            Json::Object(mut members) => {
+                members.push((String::from("span"), statement.span.to_json()));
+                if !statement.comments.is_empty() {
+                    let comments = statement.comments.iter().map(|comment| Json::Str(comment.clone())).collect();
+                    members.push((String::from("comments"), Json::Array(comments)));
+                }
+                Json::Object(members)
+            },
+            node => node,
+        }
+    }
+
+    fn argument(&self, arg: &Argument) -> Json {
+        Json::object(vec![
+            ("name", arg.name.as_deref().map_or(Json::Null, Json::str)),
+            ("value", arg.value.as_ref().map_or(Json::Null, |value| self.node(value))),
+            ("conditional", Json::Bool(arg.conditional)),
+            ("span", arg.span.to_json()),
+        ])
+    }
+
+    fn arguments(&self, args: &[Argument]) -> Json {
+        Json::Array(args.iter().map(|arg| self.argument(arg)).collect())
+    }
+
+    fn list(&self, nodes: &[Node]) -> Json {
+        Json::Array(nodes.iter().map(|node| self.node(node)).collect())
+    }
+
+    fn operand(&self, id: NodeId) -> Json {
+        self.node(&self.nodes[id])
+    }
+
+    fn optional(&self, id: Option<NodeId>) -> Json {
+        id.map_or(Json::Null, |id| self.operand(id))
+    }
+
+    /// Nodes are objects with the name of their kind, like `{"kind":"BinOp",
+    /// "op":"+","lhs":...}`
+    fn node(&self, node: &Node) -> Json {
+        let (kind, mut members): (&str, Vec<(&str, Json)>) = match node {
+            Node::Assign { lhs, rhs } => ("Assign", vec![("lhs", self.operand(*lhs)), ("rhs", self.operand(*rhs))]),
+            Node::BinOp { op, lhs, rhs } => ("BinOp", vec![("op", Json::str(operator(*op))), ("lhs", self.operand(*lhs)), ("rhs", self.operand(*rhs))]),
+            Node::OpNeg(operand) => ("OpNeg", vec![("operand", self.operand(*operand))]),
+            Node::OpNot(operand) => ("OpNot", vec![("operand", self.operand(*operand))]),
+            Node::Print { text, arg } => ("Print", vec![
+                ("text", self.operand(*text)),
+                ("arg", arg.as_ref().map_or(Json::Null, |(arg, value)| Json::object(vec![
+                    ("type", Json::str(match arg {
+                        WriteArg::Num => "num",
+                        WriteArg::Dnum => "dnum",
+                        WriteArg::Bool => "bool",
+                        WriteArg::Pos => "pos",
+                    })),
+                    ("value", self.operand(*value)),
+                ]))),
+            ]),
+            Node::Value(value) => ("Value", vec![("value", value.to_json())]),
+            Node::Aggregate(items) => ("Aggregate", vec![("items", self.list(items))]),
+            Node::Id(name, span) => ("Id", vec![("name", Json::str(name)), ("span", span.to_json())]),
+            Node::Var(slot) => ("Var", vec![("slot", num(*slot))]),
+            Node::Global(slot) => ("Global", vec![("slot", num(*slot))]),
+            Node::Index { base, indices } => ("Index", vec![("base", self.operand(*base)), ("indices", self.list(indices))]),
+            Node::Present(slot) => ("Present", vec![("slot", num(*slot))]),
+            Node::IsStorage(slot, kind) => ("IsStorage", vec![("slot", num(*slot)), ("storage", storage(*kind))]),
+            Node::Errno => ("Errno", Vec::new()),
+            Node::ProcCall { name, args, span, target } | Node::FuncCall { name, args, span, target } => (
+                if matches!(node, Node::ProcCall { .. }) { "ProcCall" } else { "FuncCall" },
+                vec![("name", Json::str(name)), ("args", self.arguments(args)), ("span", span.to_json()), ("target", optional(target.as_ref()))],
+            ),
+            Node::LateCall { name, args, span } => ("LateCall", vec![("name", self.operand(*name)), ("args", self.arguments(args)), ("span", span.to_json())]),
+            Node::Return(value) => ("Return", vec![("value", self.optional(*value))]),
+            Node::Connect { interrupt, trap, span, target } => ("Connect", vec![
+                ("interrupt", self.operand(*interrupt)),
+                ("trap", Json::str(trap)),
+                ("span", span.to_json()),
+                ("target", optional(target.as_ref())),
+            ]),
+            Node::Retry => ("Retry", Vec::new()),
+            Node::TryNext => ("TryNext", Vec::new()),
+            Node::Raise(value) => ("Raise", vec![("value", self.optional(*value))]),
+            Node::If { branches, otherwise } => ("If", vec![
+                ("branches", Json::Array(branches.iter()
+                    .map(|(condition, body)| Json::object(vec![("condition", self.node(condition)), ("body", self.body(body))]))
+                    .collect())),
+                ("otherwise", self.body(otherwise)),
+            ]),
+            Node::While { condition, body } => ("While", vec![("condition", self.operand(*condition)), ("body", self.body(body))]),
+            Node::For { var, from, to, step, body } => ("For", vec![
+                ("var", self.operand(*var)),
+                ("from", self.operand(*from)),
+                ("to", self.operand(*to)),
+                ("step", self.optional(*step)),
+                ("body", self.body(body)),
+            ]),
+            Node::Test { value, cases, default } => ("Test", vec![
+                ("value", self.operand(*value)),
+                ("cases", Json::Array(cases.iter()
+                    .map(|(values, body)| Json::object(vec![("values", self.list(values)), ("body", self.body(body))]))
+                    .collect())),
+                ("default", self.body(default)),
+            ]),
+        };
+        members.insert(0, ("kind", Json::str(kind)));
+        Json::object(members)
+    }
+}
+
+fn operator(op: Operator) -> &'static str {
+    match op {
+        Operator::Add => "+",
+        Operator::Sub => "-",
+        Operator::Mul => "*",
+        Operator::Div => "/",
+        Operator::IntDiv => "DIV",
+        Operator::Mod => "MOD",
+        Operator::Equal => "=",
+        Operator::NotEqual => "<>",
+        Operator::Less => "<",
+        Operator::LessEqual => "<=",
+        Operator::Greater => ">",
+        Operator::GreaterEqual => ">=",
+        Operator::And => "AND",
+        Operator::Or => "OR",
+        Operator::Xor => "XOR",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_and_writes() {
+        let text = r#" {"id": 1, "params": {"text": "A \"quote\"\n\u00e9\ud83d\ude00", "list": [true, false, null, -1.5e2]}, "empty": {}} "#;
+        let value = Json::parse(text).unwrap();
+        assert_eq!(value.path(&["params", "text"]).and_then(Json::as_str), Some("A \"quote\"\né😀"));
+        assert_eq!(value.get("id").and_then(Json::as_f64), Some(1.0));
+        assert_eq!(value.to_string(), r#"{"id":1,"params":{"text":"A \"quote\"\né😀","list":[true,false,null,-150]},"empty":{}}"#);
+        assert_eq!(Json::parse(&value.to_string()).unwrap(), value);
+
+        assert_eq!(Json::parse("[1, 2").unwrap_err(), "Expected ',' or ']' at 5");
+        assert_eq!(Json::parse("{\"a\" 1}").unwrap_err(), "Expected ':' at 5");
+        assert_eq!(Json::parse("1 2").unwrap_err(), "Unexpected '2' after the value at 2");
+        assert!(Json::parse("\"\\x\"").is_err());
+    }
+
+    #[test]
+    fn writes_the_ast() {
+        let program = crate::parser::parse_tokens(crate::lexer::parse("MODULE Cell(NOSTEPIN)
+    CONST num nMax{2} := [1, 2];
+    PROC Main(\\switch Fast)
+        IF nMax{1} > 0 Pick \\Fast;
+    ENDPROC
+ENDMODULE")).unwrap();
+        let module = program.modules[0].to_json();
+        assert_eq!(module.get("attributes").unwrap().to_string(), r#"["NOSTEPIN"]"#);
+        assert_eq!(module.path(&["data"]).and_then(Json::as_array).unwrap()[0].to_string(), concat!(
+            r#"{"name":"nMax","data_type":"num","storage":"CONST","local":false,"value":[1,2],"#,
+            r#""span":{"line":2,"column":15,"start":36,"end":40}}"#));
+        let routine = &module.get("routines").and_then(Json::as_array).unwrap()[0];
+        assert_eq!(routine.get("parameters").unwrap().to_string(), concat!(
+            r#"[{"name":"Fast","data_type":"switch","mode":"IN","optional":true,"group":0,"#,
+            r#""span":{"line":3,"column":23,"start":77,"end":81}}]"#));
+        let statement = &routine.get("statements").and_then(Json::as_array).unwrap()[0];
+        assert_eq!(statement.get("kind").and_then(Json::as_str), Some("If"));
+        assert_eq!(statement.path(&["span", "line"]).and_then(Json::as_f64), Some(4.0));
+        assert_eq!(routine.path(&["end", "line"]).and_then(Json::as_f64), Some(5.0));
+        assert_eq!(module.path(&["end", "line"]).and_then(Json::as_f64), Some(6.0));
+        let branch = &statement.get("branches").and_then(Json::as_array).unwrap()[0];
+        assert_eq!(branch.path(&["condition", "op"]).and_then(Json::as_str), Some(">"));
+        assert_eq!(branch.path(&["condition", "lhs", "kind"]).and_then(Json::as_str), Some("Index"));
+        let call = &branch.get("body").and_then(Json::as_array).unwrap()[0];
+        assert_eq!(call.get("kind").and_then(Json::as_str), Some("ProcCall"));
+        assert_eq!(call.path(&["args"]).and_then(Json::as_array).unwrap()[0].get("name").and_then(Json::as_str), Some("Fast"));
+        assert_eq!(call.get("target"), Some(&Json::Null));
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::graph;
+use crate::lexer;
+use crate::parser::{self, DataDecl, Module, Node, Program, Routine};
+
+// ------------------ Lazy modules -----------------------/
+
+/// Module file registered with a lazy program, parsed the first time one of
+/// the names it declares is needed
+struct ModuleFile {
+    path: String,
+    source: String,
+    system: bool,
+    // Names of the routines and data it declares, in lower case
+    names: Vec<String>,
+    modules: Option<Vec<Module>>,
+}
+
+/// Module files of a large program or backup, each only lexed and parsed
+/// once a routine or data it declares is needed. Tools that touch a few
+/// routines skip the rest of the program.
+#[derive(Default)]
+pub struct LazyProgram {
+    files: Vec<ModuleFile>,
+}
+
+impl LazyProgram {
+    pub fn new() -> LazyProgram {
+        LazyProgram { files: Vec::new() }
+    }
+
+    /// Register the module files of a path like `Program::from_path` reads
+    /// them, without parsing them: a module file, the .pgf file of a program
+    /// or its directory with the system modules in it
+    pub fn from_path(path: &Path) -> Result<LazyProgram, Error> {
+        let files = if path.is_dir() {
+            let mut files = parser::system_files(path)?;
+            files.append(&mut parser::pgf_files(&parser::program_file(path)?)?);
+            files
+        } else if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("pgf")) {
+            parser::pgf_files(path)?
+        } else {
+            vec![path.to_path_buf()]
+        };
+        let mut program = LazyProgram::new();
+        for file in files {
+            let source = lexer::read_source(&file).map_err(|err| Error::io(&file, err))?;
+            let system = file.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("sys"));
+            program.register(&file.display().to_string(), source, system);
+        }
+        Ok(program)
+    }
+
+    /// Register the source of a module file, the modules of system files are
+    /// system modules. Errors of the file name its path.
+    pub fn register(&mut self, path: &str, source: String, system: bool) {
+        let names = declared_names(&source);
+        self.files.push(ModuleFile { path: String::from(path), source, system, names, modules: None });
+    }
+
+    /// Number of module files registered
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Number of module files parsed so far
+    pub fn parsed(&self) -> usize {
+        self.files.iter().filter(|file| file.modules.is_some()).count()
+    }
+
+    /// Routine of a name, parsing the module files that declare it
+    pub fn routine(&mut self, name: &str) -> Result<Option<&Routine>, Error> {
+        let declaring = self.parse_declaring(name)?;
+        let files = &self.files;
+        Ok(declaring.into_iter()
+            .flat_map(|idx| files[idx].modules.iter().flatten())
+            .flat_map(|module| module.routines.iter())
+            .find(|routine| routine.name.eq_ignore_ascii_case(name)))
+    }
+
+    /// Program of the modules the entry routine needs: the module declaring
+    /// it and, in turn, those declaring the routines and data its modules
+    /// use, in the order they were registered. It isn't resolved yet.
+    /// Procedures called by a string, like `%sName%`, need their modules to
+    /// be needed by name elsewhere.
+    pub fn program(&mut self, entry: &str) -> Result<Program, Error> {
+        let mut needed = vec![entry.to_ascii_lowercase()];
+        let mut seen = HashSet::new();
+        while let Some(name) = needed.pop() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            for idx in self.declaring(&name).filter(|idx| self.files[*idx].modules.is_none()).collect::<Vec<_>>() {
+                self.parse(idx)?;
+                for module in self.files[idx].modules.iter().flatten() {
+                    for routine in module.routines.iter() {
+                        needed.extend(graph::routine_nodes(routine).into_iter().filter_map(used_name));
+                    }
+                    // Aliases of the data types, declared in library modules
+                    needed.extend(module_data(module).map(|decl| decl.data_type.to_ascii_lowercase()));
+                    needed.extend(module.aliases.iter().map(|alias| alias.base.to_ascii_lowercase()));
+                }
+            }
+        }
+        let modules = self.files.iter().filter_map(|file| file.modules.as_ref()).flatten().cloned().collect();
+        Ok(Program { modules, ..Program::default() })
+    }
+
+    /// Indices of the files that declare a name, by the names found in them
+    fn declaring<'a>(&'a self, name: &'a str) -> impl Iterator<Item = usize> + 'a {
+        self.files.iter().enumerate()
+            .filter(move |(_, file)| file.names.iter().any(|declared| declared.eq_ignore_ascii_case(name)))
+            .map(|(idx, _)| idx)
+    }
+
+    fn parse_declaring(&mut self, name: &str) -> Result<Vec<usize>, Error> {
+        let files: Vec<usize> = self.declaring(name).collect();
+        for idx in files.iter() {
+            self.parse(*idx)?;
+        }
+        Ok(files)
+    }
+
+    /// Lex and parse a module file, unless it was already
+    fn parse(&mut self, idx: usize) -> Result<(), Error> {
+        let file = &mut self.files[idx];
+        if file.modules.is_some() {
+            return Ok(());
+        }
+        let mut modules = Program::from_source(&file.source).map_err(|err| err.in_file(Path::new(&file.path)))?.modules;
+        for module in modules.iter_mut() {
+            module.system |= file.system;
+        }
+        file.modules = Some(modules);
+        Ok(())
+    }
+}
+
+/// Name of the routine or data a node uses, in lower case
+fn used_name(node: &Node) -> Option<String> {
+    match node {
+        Node::Id(name, _) | Node::ProcCall { name, .. } | Node::FuncCall { name, .. } => Some(name.to_ascii_lowercase()),
+        _ => None,
+    }
+}
+
+/// Data of a module and its routines, parameters included
+fn module_data(module: &Module) -> impl Iterator<Item = &DataDecl> {
+    let routine_data = module.routines.iter()
+        .flat_map(|routine| routine.arguments.iter().map(|param| &param.decl).chain(routine.variables.iter()));
+    module.variables.iter().chain(routine_data)
+}
+
+/// Names a module file declares, found by its words without lexing it: the
+/// word after PROC or TRAP, or after the type following FUNC, VAR, PERS,
+/// CONST or ALIAS.
+/// The data of routines is found too, which at most parses a module that
+/// isn't needed.
+fn declared_names(source: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut words = source.split(|c: char| !c.is_alphanumeric() && c != '_').filter(|word| !word.is_empty());
+    while let Some(word) = words.next() {
+        let name = match word.to_ascii_uppercase().as_str() {
+            "PROC" | "TRAP" => words.next(),
+            "FUNC" | "VAR" | "PERS" | "CONST" | "ALIAS" => words.nth(1),
+            _ => continue,
+        };
+        names.extend(name.map(str::to_ascii_lowercase));
+    }
+    names
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::host::Host;
+    use crate::interpreter::{self, InterpreterOptions};
+    use crate::resolver::{self, ResolveOptions};
+    use crate::variable::Variable;
+
+    #[test]
+    fn parses_the_modules_needed() {
+        let mut program = LazyProgram::new();
+        program.register("Main.mod", String::from("MODULE Main\n    PROC main()\n        rPick;\n        nCount := nCount + 1;\n    ENDPROC\nENDMODULE"), false);
+        program.register("Data.mod", String::from("MODULE Data\n    VAR counter nCount := 0;\nENDMODULE"), false);
+        program.register("Broken.mod", String::from("MODULE Broken\n    PROC rOther(\nENDMODULE"), false);
+        program.register("Pick.mod", String::from("MODULE Pick\n    PROC rPick()\n        TPWrite \"Pick!\";\n    ENDPROC\nENDMODULE"), false);
+        program.register("Types.sys", String::from("MODULE Types\n    ALIAS num counter;\nENDMODULE"), true);
+        assert_eq!((program.len(), program.parsed()), (5, 0));
+
+        assert_eq!(program.routine("RPICK").unwrap().map(|routine| routine.name.as_str()), Some("rPick"));
+        assert_eq!(program.parsed(), 1);
+        let mut program = program.program("main").unwrap();
+        let modules: Vec<&str> = program.modules.iter().map(|module| module.name.as_str()).collect();
+        assert_eq!(modules, vec!["Main", "Data", "Pick", "Types"]);
+
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        interpreter::run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).into_result().unwrap();
+        assert!(matches!(program.variables[0], Variable::Num(n) if n == 1.0));
+    }
+
+    #[test]
+    fn reports_errors_of_needed_files() {
+        let mut program = LazyProgram::new();
+        program.register("Broken.mod", String::from("MODULE Broken\n    PROC rOther(\nENDMODULE"), false);
+        assert!(program.routine("rOther").is_err_and(|err| err.to_string().starts_with("Broken.mod: ")));
+        assert!(program.routine("rMissing").unwrap().is_none());
+    }
+}
@@ -1,6 +1,24 @@
-use std::vec;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-#[derive(Debug, Clone)]
+use phf::phf_ordered_map;
+use serde::{Deserialize, Serialize};
+
+// Whether the lexer and the parser print what they find, for debugging
+static TRACE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_trace(on: bool) {
+    TRACE.store(on, Ordering::Relaxed);
+}
+
+pub fn trace() -> bool {
+    TRACE.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     // Brackets
     LeftPar, RightPar, LeftBrace, RightBrace, LeftBrack, RightBrack,
@@ -8,11 +26,17 @@ pub enum TokenType {
     // Terminators
     Semicolon, Comma, Whitespace, Newline,
 
+    // Argument markers, `?` of a conditional argument
+    Backslash, Pipe, Question,
+
+    // Late binding, around the expression naming the procedure
+    Percent,
+
     // Operators
     Add, Minus, Multiply, Divide,
 
     // Assign
-    Assign,
+    Assign, Colon,
 
     // Multi-char token
     Equal, NotEqual, 
@@ -26,11 +50,18 @@ pub enum TokenType {
     Mod, EndMod,
     Proc, EndProc,
     Func, EndFunc,
-    Local, Var, Pers, Inout,
-    If, Then, ElseIf, EndIf,
-    While, EndWhile, 
-    For, EndFor,
+    Trap, EndTrap,
+    Local, Var, Pers, Const, Inout, Alias,
+    If, Then, ElseIf, Else, EndIf,
+    While, Do, EndWhile,
+    For, From, To, Step, EndFor,
+    Test, Case, Default, EndTest,
     Return,
+    Error, Retry, TryNext, Raise,
+    Connect, With,
+
+    // Operator keywords
+    Div, And, Or, Xor, Not,
 
     // Data types
     NumType, StringType, BoolType,
@@ -39,12 +70,39 @@ pub enum TokenType {
     TpWrite,
 }
 
-static DEFAULT_TOKENS : &'static [(&str, TokenType)] = &[
+/// Location of a token in the source, line and column are 1-based
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub token_type: TokenType,
+    pub span: Span,
+}
+
+/// Symbols, longer ones before their prefixes
+static SYMBOLS: &[(&str, TokenType)] = &[
     (";",TokenType::Semicolon),
     (",",TokenType::Comma),
     ("\n",TokenType::Newline),
     (" ",TokenType::Whitespace),
     ("\t",TokenType::Whitespace),
+    ("\r",TokenType::Whitespace),
+    ("\\",TokenType::Backslash),
+    ("|",TokenType::Pipe),
+    ("?",TokenType::Question),
+    ("%",TokenType::Percent),
     ("(",TokenType::LeftPar),
     (")",TokenType::RightPar),
     ("{",TokenType::LeftBrace),
@@ -60,107 +118,346 @@ static DEFAULT_TOKENS : &'static [(&str, TokenType)] = &[
     ("<=",TokenType::LessEqual),
     ("<",TokenType::Less),
     (">=",TokenType::GreaterEqual),
-    (">",TokenType::Greater),    
+    (">",TokenType::Greater),
     (":=",TokenType::Assign),
-    ("MOD",TokenType::Mod),
-    ("ENDMOD",TokenType::EndMod),
-    ("PROC",TokenType::Proc),
-    ("ENDPROC",TokenType::EndProc),
-    ("FUNC",TokenType::Func),
-    ("ENDFUNC",TokenType::EndFunc),
-    ("LOCAL",TokenType::Local),
-    ("VAR",TokenType::Var),
-    ("PERS",TokenType::Pers),
-    ("INOUT",TokenType::Inout),
-    ("IF",TokenType::If),
-    ("THEN",TokenType::Then),
-    ("ELSEIF",TokenType::ElseIf),
-    ("ENDIF",TokenType::EndIf),
-    ("WHILE",TokenType::While),
-    ("ENDWHILE",TokenType::EndWhile),
-    ("FOR",TokenType::For),
-    ("ENDFOR",TokenType::EndFor),
-    ("RETURN",TokenType::Return),
-    ("TPWRITE",TokenType::TpWrite),
-    ("TRUE",TokenType::True),
-    ("FALSE",TokenType::False),
-    ("num",TokenType::NumType),
-    ("string",TokenType::StringType),
-    ("bool",TokenType::BoolType),
+    (":",TokenType::Colon),
 ];
 
-pub fn parse(contents: &str) -> Vec<TokenType> {
+/// Keywords by their spelling in upper case, as RAPID ignores the case of
+/// keywords. The first spelling of a token is the one it is written as.
+static KEYWORDS: phf::OrderedMap<&'static str, TokenType> = phf_ordered_map! {
+    "MODULE" => TokenType::Mod,
+    "ENDMODULE" => TokenType::EndMod,
+    "MOD" => TokenType::Mod,
+    "ENDMOD" => TokenType::EndMod,
+    "PROC" => TokenType::Proc,
+    "ENDPROC" => TokenType::EndProc,
+    "FUNC" => TokenType::Func,
+    "ENDFUNC" => TokenType::EndFunc,
+    "TRAP" => TokenType::Trap,
+    "ENDTRAP" => TokenType::EndTrap,
+    "LOCAL" => TokenType::Local,
+    "VAR" => TokenType::Var,
+    "PERS" => TokenType::Pers,
+    "CONST" => TokenType::Const,
+    "INOUT" => TokenType::Inout,
+    "ALIAS" => TokenType::Alias,
+    "IF" => TokenType::If,
+    "THEN" => TokenType::Then,
+    "ELSEIF" => TokenType::ElseIf,
+    "ELSE" => TokenType::Else,
+    "ENDIF" => TokenType::EndIf,
+    "WHILE" => TokenType::While,
+    "DO" => TokenType::Do,
+    "ENDWHILE" => TokenType::EndWhile,
+    "FOR" => TokenType::For,
+    "FROM" => TokenType::From,
+    "TO" => TokenType::To,
+    "STEP" => TokenType::Step,
+    "ENDFOR" => TokenType::EndFor,
+    "TEST" => TokenType::Test,
+    "CASE" => TokenType::Case,
+    "DEFAULT" => TokenType::Default,
+    "ENDTEST" => TokenType::EndTest,
+    "DIV" => TokenType::Div,
+    "AND" => TokenType::And,
+    "OR" => TokenType::Or,
+    "XOR" => TokenType::Xor,
+    "NOT" => TokenType::Not,
+    "RETURN" => TokenType::Return,
+    "ERROR" => TokenType::Error,
+    "RETRY" => TokenType::Retry,
+    "TRYNEXT" => TokenType::TryNext,
+    "RAISE" => TokenType::Raise,
+    "CONNECT" => TokenType::Connect,
+    "WITH" => TokenType::With,
+    "TPWRITE" => TokenType::TpWrite,
+    "TRUE" => TokenType::True,
+    "FALSE" => TokenType::False,
+    "NUM" => TokenType::NumType,
+    "STRING" => TokenType::StringType,
+    "BOOL" => TokenType::BoolType,
+};
+
+// Length of the longest keyword, longer words are identifiers
+const KEYWORD_LEN: usize = 9;
+
+/// Source spelling of a keyword or symbol token
+pub fn keyword(token_type: &TokenType) -> String {
+    if let Some((symbol, _)) = SYMBOLS.iter().find(|token| token.1 == *token_type) {
+        return String::from(*symbol);
+    }
+    match KEYWORDS.entries().find(|(_, keyword)| *keyword == token_type) {
+        // Data types are written in lower case
+        Some((word, TokenType::NumType | TokenType::StringType | TokenType::BoolType)) => word.to_ascii_lowercase(),
+        Some((word, _)) => String::from(*word),
+        None => format!("{:?}", token_type),
+    }
+}
+
+/// Keyword a word of the source is, in any case
+fn keyword_of(word: &str) -> Option<&'static TokenType> {
+    if word.len() > KEYWORD_LEN {
+        return None;
+    }
+    let mut upper = [0u8; KEYWORD_LEN];
+    upper[..word.len()].copy_from_slice(word.as_bytes());
+    upper[..word.len()].make_ascii_uppercase();
+    KEYWORDS.get(std::str::from_utf8(&upper[..word.len()]).ok()?)
+}
+
+/// Comment from `!` to the end of the line, after the code on the line
+/// when it's `trailing`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    // Text after the `!`
+    pub text: String,
+    pub span: Span,
+    pub trailing: bool,
+}
+
+/// Text the lexer has no token for
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.span)
+    }
+}
+
+/// Tokens of the source, panics on text that isn't a token
+pub fn parse(contents: &str) -> Vec<Token> {
+    tokenize(contents).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Tokens of the source, or where it has text that isn't a token, for
+/// source being edited
+pub fn tokenize(contents: &str) -> Result<Vec<Token>, LexError> {
+    tokenize_with_comments(contents).map(|(tokens, _)| tokens)
+}
+
+/// Tokens of the source and the comments between them
+pub fn tokenize_with_comments(contents: &str) -> Result<(Vec<Token>, Vec<Comment>), LexError> {
     // Create new list with tokens
-    let mut tokens: Vec<TokenType> = Vec::new();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut comments: Vec<Comment> = Vec::new();
     // Get reference to byte array
     let bytes = contents.as_bytes();
     // Current index
     let mut idx = 0;
+    // Position of the current line, for spans
+    let mut line = 1;
+    let mut line_start = 0;
 
     'outer: while idx < contents.len() {
         let slice = &contents[idx..];
+        let span = |len: usize| Span { line, column: idx - line_start + 1, start: idx, end: idx + len };
 
-        // Check terminators
-        for token in DEFAULT_TOKENS {
-            if token.0.len() > slice.len() {
-                continue;
+        // Header of the files of older controllers, the parser reads it
+        // from the source
+        if let Some(header) = header_block(slice) {
+            if let Some(last) = header.rfind('\n') {
+                line += header.matches('\n').count();
+                line_start = idx + last + 1;
             }
+            idx += header.len();
+            continue 'outer;
+        }
 
-            if slice[0..token.0.len()].eq_ignore_ascii_case(token.0) {
+        if bytes[idx] == b'!' {
+            let len = slice.find(['\r', '\n']).unwrap_or(slice.len());
+            let trailing = tokens.last().is_some_and(|token| token.span.line == line);
+            comments.push(Comment { text: String::from(&slice[1..len]), span: span(len), trailing });
+            idx += len;
+            continue 'outer;
+        }
+
+        // Check terminators
+        for token in SYMBOLS {
+            if slice.starts_with(token.0) {
                 match token.1 {
                     // Ignore whitespace and newlines
                     TokenType::Whitespace => (),
-                    TokenType::Newline => (),
+                    TokenType::Newline => {
+                        line += 1;
+                        line_start = idx + 1;
+                    },
                     // Put other tokens into the vec
-                    _ => tokens.push(token.1.clone())
+                    _ => tokens.push(Token { token_type: token.1.clone(), span: span(token.0.len()) }),
                 }
                 idx += token.0.len();
                 continue 'outer;
-            }            
-        }    
+            }
+        }
 
         // Check if string value
         if bytes[idx] == b'\"' {
             if let Some(idx2) = slice[1..].find('\"') {
-                let token = TokenType::StringValue(String::from(&slice[1..idx2]));
-                tokens.push(token);
-                idx += idx2 + 1;
+                let token_type = TokenType::StringValue(String::from(&slice[1..idx2 + 1]));
+                tokens.push(Token { token_type, span: span(idx2 + 2) });
+                idx += idx2 + 2;
                 continue 'outer;
             } else {
-                panic!("Expected \" at {}", slice);
-            }            
+                return Err(LexError { message: String::from("Missing \" at the end of the string"), span: span(slice.find('\n').unwrap_or(slice.len())) });
+            }
         }
 
         // check for num value
-        if bytes[idx] >= b'0' && bytes[idx] <= b'9' {
-            if let Some(idx2) = slice.find(|c: char| !c.is_numeric() && c != '.') {
-                let token = TokenType::NumValue(String::from(&slice[0..idx2]));
-                tokens.push(token);
-                idx += idx2;
-                continue 'outer;
-            } else {
-                panic!("Expected terminator at {}", slice);
+        if let Some((radix, digits)) = radix_digits(slice) {
+            let idx2 = slice.len() - digits.len() + digits.find(|c: char| !c.is_digit(radix)).unwrap_or(digits.len());
+            let token_type = TokenType::NumValue(String::from(&slice[0..idx2]));
+            tokens.push(Token { token_type, span: span(idx2) });
+            idx += idx2;
+            continue 'outer;
+        }
+        if bytes[idx].is_ascii_digit() {
+            let mut idx2 = slice.find(|c: char| !c.is_numeric() && c != '.').unwrap_or(slice.len());
+            // Exponent like in 9E+09
+            let exponent = slice[idx2..].strip_prefix(['e', 'E'])
+                .map(|rest| rest.strip_prefix(['+', '-']).unwrap_or(rest))
+                .filter(|rest| rest.starts_with(|c: char| c.is_ascii_digit()));
+            if let Some(rest) = exponent {
+                idx2 = slice.len() - rest.len() + rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
             }
+            let token_type = TokenType::NumValue(String::from(&slice[0..idx2]));
+            tokens.push(Token { token_type, span: span(idx2) });
+            idx += idx2;
+            continue 'outer;
         }
 
-        // Check if identifier
-        if (bytes[idx] >= b'A' && bytes[idx] <= b'Z') || (bytes[idx] >= b'a' && bytes[idx] <= b'z') {
-            if let Some(idx2) = slice.find(|c: char| !c.is_alphanumeric() && c != '_') {
-                let token = TokenType::Id(String::from(&slice[0..idx2]));
-                tokens.push(token);
-                idx += idx2;
-                continue 'outer;
-            } else {
-                panic!("Expected terminator at {}", slice);
-            }
+        // Check if keyword or identifier, keywords match a whole word
+        if bytes[idx].is_ascii_alphabetic() {
+            let idx2 = slice.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(slice.len());
+            let token_type = match keyword_of(&slice[0..idx2]) {
+                Some(token_type) => token_type.clone(),
+                None => TokenType::Id(String::from(&slice[0..idx2])),
+            };
+            tokens.push(Token { token_type, span: span(idx2) });
+            idx += idx2;
+            continue 'outer;
         }
 
-        panic!("Undefined symbol {}", slice);
+        let symbol = slice.chars().next().unwrap_or_default();
+        return Err(LexError { message: format!("Undefined symbol {}", symbol), span: span(symbol.len_utf8()) });
     }
 
-    for token in tokens.iter() {
-        println!("Token found {:?}", token);
+    if trace() {
+        for token in tokens.iter() {
+            println!("Token found {:?}", token.token_type);
+        }
     }
 
-    return tokens;
-}
\ No newline at end of file
+    Ok((tokens, comments))
+}
+
+/// `%%%` block at the start of text, the markers included
+fn header_block(text: &str) -> Option<&str> {
+    let end = text.strip_prefix("%%%")?.find("%%%")?;
+    Some(&text[..end + 6])
+}
+
+/// Fields of the header of the files of older controllers, like
+/// `%%%\n  VERSION:1\n  LANGUAGE:ENGLISH\n%%%`, by name. A line without a
+/// colon is a field without a value.
+pub fn header(source: &str) -> Option<Vec<(String, String)>> {
+    let block = header_block(source.trim_start())?;
+    let fields = block[3..block.len() - 3].lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split_once(':') {
+            Some((name, value)) => (String::from(name.trim()), String::from(value.trim())),
+            None => (String::from(line), String::new()),
+        })
+        .collect();
+    Some(fields)
+}
+
+/// Radix and digits of a hexadecimal, octal or binary integer at the start
+/// of the text, like `0xFF`, `0o17` or `0b101`
+fn radix_digits(text: &str) -> Option<(u32, &str)> {
+    let radix = match text.get(..2)? {
+        "0x" | "0X" => 16,
+        "0o" | "0O" => 8,
+        "0b" | "0B" => 2,
+        _ => return None,
+    };
+    let digits = &text[2..];
+    digits.starts_with(|c: char| c.is_digit(radix)).then_some((radix, digits))
+}
+
+/// Value of a number token, decimal or an integer of another radix up to
+/// 0xFFFFFFFF, the largest a dnum literal can be
+pub fn num_value(text: &str) -> Option<f64> {
+    match radix_digits(text) {
+        Some((radix, digits)) => u32::from_str_radix(digits, radix).ok().map(f64::from),
+        None => text.parse().ok(),
+    }
+}
+
+/// Text of a source file. Files that aren't UTF-8 are read as Latin-1, the
+/// encoding older controllers write.
+pub fn decode(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => String::from(text.strip_prefix('\u{feff}').unwrap_or(text)),
+        Err(_) => bytes.iter().map(|byte| char::from(*byte)).collect(),
+    }
+}
+
+/// Read a source file, or standard input for the path "-"
+pub fn read_source(path: &Path) -> io::Result<String> {
+    let mut bytes = Vec::new();
+    if path == Path::new("-") {
+        io::stdin().read_to_end(&mut bytes)?;
+    } else {
+        bytes = fs::read(path)?;
+    }
+    Ok(decode(&bytes))
+}
+
+/// Tokens of a source file, or of standard input for the path "-". Text
+/// that isn't a token is an error of kind `InvalidData`.
+pub fn parse_file(path: &Path) -> io::Result<Vec<Token>> {
+    let source = read_source(path)?;
+    tokenize(&source).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn looks_up_keywords() {
+        let types: Vec<TokenType> = parse("endproc Proc ENDIFs STRING procedure_1 Xor").into_iter().map(|token| token.token_type).collect();
+        assert_eq!(types, vec![TokenType::EndProc, TokenType::Proc, TokenType::Id(String::from("ENDIFs")), TokenType::StringType,
+            TokenType::Id(String::from("procedure_1")), TokenType::Xor]);
+        assert_eq!(KEYWORDS.keys().map(|word| word.len()).max(), Some(KEYWORD_LEN));
+        assert_eq!(keyword(&TokenType::Mod), "MODULE");
+        assert_eq!(keyword(&TokenType::BoolType), "bool");
+        assert_eq!(keyword(&TokenType::LessEqual), "<=");
+    }
+
+    #[test]
+    fn reads_numbers_of_any_radix() {
+        let values: Vec<Option<f64>> = parse("0xFFFFFFFF 0o17 0B101 2.5E+3 0xg").into_iter()
+            .filter_map(|token| match token.token_type {
+                TokenType::NumValue(text) => Some(num_value(&text)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(values, vec![Some(4294967295.0), Some(15.0), Some(5.0), Some(2500.0), Some(0.0)]);
+        assert_eq!(num_value("0x100000000"), None);
+    }
+
+    #[test]
+    fn keeps_comments_apart_from_tokens() {
+        let (tokens, comments) = tokenize_with_comments("! Counter\nn := 1; ! one\r\nn := 2;").unwrap();
+        assert_eq!(tokens.len(), 8);
+        assert_eq!(comments.iter().map(|comment| (comment.text.as_str(), comment.span.line, comment.trailing)).collect::<Vec<_>>(),
+            vec![(" Counter", 1, false), (" one", 2, true)]);
+        assert_eq!(comments[1].span.column, 9);
+    }
+}
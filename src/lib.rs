@@ -1,3 +1,85 @@
-pub fn dummy() {
-    
-}
\ No newline at end of file
+//! Lexer, parser, resolver and interpreter of ABB RAPID, the language of
+//! the robot controllers, with the checks and tools built on them. The
+//! `rapid-rust` binary is a command line over this library.
+//!
+//! A program is parsed from source or files into a [`Program`], resolved,
+//! and then run with a [`Host`] that stands in for the controller:
+//!
+//! ```
+//! use rapid_rust::{resolver, run, Error, Host, InterpreterOptions, Program, Variable};
+//!
+//! let source = "
+//! MODULE Cell
+//!     VAR num nParts := 0;
+//!     PROC main()
+//!         nParts := nParts + 2;
+//!         TPWrite \"Parts: \" \\Num:=nParts;
+//!     ENDPROC
+//! ENDMODULE";
+//! let mut program = Program::from_source(source)?;
+//! resolver::resolve(&mut program, &resolver::ResolveOptions::default())?;
+//!
+//! let mut output = Vec::new();
+//! run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut output)).into_result()?;
+//! assert_eq!(output, vec!["Parts: 2"]);
+//! assert!(matches!(program.variables[0], Variable::Num(n) if n == 2.0));
+//! # Ok::<(), Error>(())
+//! ```
+//!
+//! [`Program::from_path`] reads module files, the .pgf file of a program or
+//! its directory, and [`backup::load`] the tasks of a controller backup.
+//! Routines of the host are added with [`Program::register_proc`] and
+//! [`Program::register_func`] before resolving.
+//!
+//! Loading and running fail with an [`Error`] of the stage that failed, with
+//! the position and code of the error.
+
+
+pub mod backup;
+pub mod builtins;
+pub mod cache;
+pub mod calibration;
+pub mod compiler;
+pub mod config;
+pub mod coverage;
+pub mod debugger;
+pub mod embed;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod formatter;
+pub mod graph;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod highlight;
+pub mod host;
+pub mod interpreter;
+pub mod json;
+pub mod lazy;
+pub mod lexer;
+pub mod linter;
+pub mod loader;
+pub mod lsp;
+pub mod metrics;
+pub mod parser;
+pub mod persistence;
+pub mod profiler;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod resolver;
+pub mod sarif;
+pub mod scheduler;
+pub mod server;
+pub mod symbols;
+pub mod transpile;
+pub mod variable;
+pub mod vm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod watch;
+
+pub use error::Error;
+pub use host::Host;
+pub use interpreter::{run, Interpreter, InterpreterOptions, RuntimeError};
+pub use parser::Program;
+pub use variable::Variable;
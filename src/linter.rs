@@ -0,0 +1,703 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::lexer::Span;
+use crate::parser::{Arena, DataDecl, Node, Operator, Program, Routine, Statement};
+use crate::resolver::Severity;
+
+// ------------------ Lints -----------------------/
+
+/// Finding of a lint rule in a resolved program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}[{}]: {}", self.span, severity, self.rule, self.message)
+    }
+}
+
+/// Check of a resolved program. Rules report what they find with a message
+/// and a span, the linter adds the severity the project configured.
+pub trait Rule {
+    /// Name the configuration and the lints use, like "unused-data"
+    fn id(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    /// Severity unless the configuration changes it
+    fn severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    /// Set an option of the rule from the configuration
+    fn configure(&mut self, option: &str, value: &str) -> Result<(), String> {
+        let _ = value;
+        Err(format!("Unknown option {} of rule {}", option, self.id()))
+    }
+
+    fn check(&self, program: &Program, found: &mut Vec<(String, Span)>);
+}
+
+// ------------------ Configuration -----------------------/
+
+/// What a project does with the lints of a rule
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Off,
+    Warning,
+    Error,
+}
+
+/// Levels and options of rules, by rule ID
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LintConfig {
+    pub levels: HashMap<String, Level>,
+    pub options: HashMap<String, Vec<(String, String)>>,
+}
+
+impl LintConfig {
+    /// Read a configuration with a table per rule, like
+    ///
+    /// ```text
+    /// [long-routine]
+    /// level = "error"
+    /// max_statements = 40
+    /// ```
+    pub fn parse(text: &str) -> Result<LintConfig, String> {
+        let mut config = LintConfig::default();
+        let mut rule: Option<String> = None;
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| format!("{}: {}", idx + 1, message);
+
+            if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                rule = Some(String::from(name.trim()));
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(error(String::from("Expected name = value"))),
+            };
+            let value = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).unwrap_or(value);
+            let rule = match &rule {
+                Some(rule) => rule.clone(),
+                None => return Err(error(format!("{} outside of a rule table", key))),
+            };
+            if key == "level" {
+                let level = match value {
+                    "off" => Level::Off,
+                    "warning" => Level::Warning,
+                    "error" => Level::Error,
+                    _ => return Err(error(format!("Unknown level {}", value))),
+                };
+                config.levels.insert(rule, level);
+            } else {
+                config.options.entry(rule).or_default().push((String::from(key), String::from(value)));
+            }
+        }
+        Ok(config)
+    }
+}
+
+// ------------------ Linter -----------------------/
+
+/// Rules a program is checked by, with the level the project gave them
+pub struct Linter {
+    rules: Vec<(Box<dyn Rule>, Level)>,
+}
+
+impl Default for Linter {
+    fn default() -> Linter {
+        let mut linter = Linter { rules: Vec::new() };
+        linter.register(Box::new(UnusedData));
+        linter.register(Box::new(LongRoutine { max_statements: 50 }));
+        linter.register(Box::new(DeepNesting { max_depth: 4 }));
+        linter.register(Box::new(Complexity { max_complexity: 10 }));
+        linter.register(Box::new(EmptyBlock));
+        linter.register(Box::new(Naming::default()));
+        linter
+    }
+}
+
+impl Linter {
+    /// Linter with the built-in rules
+    pub fn new() -> Linter {
+        Linter::default()
+    }
+
+    /// Add a rule, it reports at its own severity until configured
+    pub fn register(&mut self, rule: Box<dyn Rule>) {
+        let level = match rule.severity() {
+            Severity::Error => Level::Error,
+            Severity::Warning => Level::Warning,
+        };
+        self.rules.push((rule, level));
+    }
+
+    /// IDs and descriptions of the rules
+    pub fn rules(&self) -> Vec<(&'static str, &'static str)> {
+        self.rules.iter().map(|(rule, _)| (rule.id(), rule.description())).collect()
+    }
+
+    /// Apply the configuration of a project, rules it names must exist
+    pub fn configure(&mut self, config: &LintConfig) -> Result<(), String> {
+        let names = config.levels.keys().chain(config.options.keys());
+        if let Some(unknown) = names.into_iter().find(|name| !self.rules.iter().any(|(rule, _)| rule.id() == name.as_str())) {
+            return Err(format!("Unknown rule {}", unknown));
+        }
+        for (rule, level) in self.rules.iter_mut() {
+            if let Some(configured) = config.levels.get(rule.id()) {
+                *level = *configured;
+            }
+            for (option, value) in config.options.get(rule.id()).into_iter().flatten() {
+                rule.configure(option, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lints of the rules that are on, in source order
+    pub fn check(&self, program: &Program) -> Vec<Lint> {
+        let mut lints = Vec::new();
+        for (rule, level) in self.rules.iter() {
+            let severity = match level {
+                Level::Off => continue,
+                Level::Warning => Severity::Warning,
+                Level::Error => Severity::Error,
+            };
+            let mut found = Vec::new();
+            rule.check(program, &mut found);
+            lints.extend(found.into_iter().map(|(message, span)| Lint { rule: rule.id(), severity, message, span }));
+        }
+        lints.sort_by_key(|lint| (lint.span.line, lint.span.column));
+        lints
+    }
+}
+
+/// Statements of a routine with the statements nested in them, ERROR
+/// handler included
+fn statements(routine: &Routine) -> Vec<&Statement> {
+    fn add<'a>(body: &'a [Statement], all: &mut Vec<&'a Statement>) {
+        for statement in body {
+            all.push(statement);
+            for inner in statement.node.bodies() {
+                add(inner, all);
+            }
+        }
+    }
+    let mut all = Vec::new();
+    add(&routine.statements, &mut all);
+    if let Some(handler) = &routine.handler {
+        add(handler, &mut all);
+    }
+    all
+}
+
+/// Nodes of an expression of a routine with its operands in `arena`, the
+/// node itself first
+fn nodes<'a>(node: &'a Node, arena: &'a Arena) -> Vec<&'a Node> {
+    let mut all = vec![node];
+    let mut idx = 0;
+    while idx < all.len() {
+        let children = all[idx].children(arena);
+        all.extend(children);
+        idx += 1;
+    }
+    all
+}
+
+// ------------------ Rules -----------------------/
+
+/// Routine data and LOCAL module data that nothing uses
+struct UnusedData;
+
+impl Rule for UnusedData {
+    fn id(&self) -> &'static str {
+        "unused-data"
+    }
+
+    fn description(&self) -> &'static str {
+        "Data declared in a routine, or LOCAL in a module, that is never used"
+    }
+
+    fn check(&self, program: &Program, found: &mut Vec<(String, Span)>) {
+        let mut globals = vec![false; program.variables.len()];
+        for module in program.modules.iter() {
+            for routine in module.routines.iter() {
+                let mut locals = vec![false; routine.arguments.len() + routine.variables.len()];
+                for node in statements(routine).iter().flat_map(|statement| nodes(&statement.node, &routine.nodes)) {
+                    match node {
+                        Node::Var(slot) => locals[*slot] = true,
+                        Node::Global(slot) => globals[*slot] = true,
+                        _ => (),
+                    }
+                }
+                // FOR adds its loop variable to the data, the loop uses it
+                for (decl, used) in routine.variables.iter().zip(locals[routine.arguments.len()..].iter()) {
+                    if !used {
+                        found.push((format!("'{}' in routine {} is never used", decl.name, routine.name), decl.span));
+                    }
+                }
+            }
+        }
+        for (module, decl, slot) in program.module_data() {
+            if decl.local && !globals.get(slot).copied().unwrap_or(true) {
+                found.push((format!("LOCAL '{}' in module {} is never used", decl.name, module.name), decl.span));
+            }
+        }
+    }
+}
+
+/// Routines with more statements than a maximum
+struct LongRoutine {
+    max_statements: usize,
+}
+
+impl Rule for LongRoutine {
+    fn id(&self) -> &'static str {
+        "long-routine"
+    }
+
+    fn description(&self) -> &'static str {
+        "Routine with more statements than max_statements"
+    }
+
+    fn configure(&mut self, option: &str, value: &str) -> Result<(), String> {
+        match option {
+            "max_statements" => {
+                self.max_statements = value.parse().map_err(|_| format!("Invalid max_statements {}", value))?;
+                Ok(())
+            },
+            _ => Err(format!("Unknown option {} of rule {}", option, self.id())),
+        }
+    }
+
+    fn check(&self, program: &Program, found: &mut Vec<(String, Span)>) {
+        for routine in program.modules.iter().flat_map(|module| module.routines.iter()) {
+            let count = statements(routine).len();
+            if count > self.max_statements {
+                found.push((format!("Routine {} has {} statements, more than {}", routine.name, count, self.max_statements), routine.span));
+            }
+        }
+    }
+}
+
+/// Routines with statements nested deeper than a maximum
+struct DeepNesting {
+    max_depth: usize,
+}
+
+impl DeepNesting {
+    /// First statement nested deeper than the maximum
+    fn too_deep<'a>(&self, body: &'a [Statement], depth: usize) -> Option<&'a Statement> {
+        for statement in body {
+            if depth > self.max_depth {
+                return Some(statement);
+            }
+            if let Some(deep) = statement.node.bodies().into_iter().find_map(|inner| self.too_deep(inner, depth + 1)) {
+                return Some(deep);
+            }
+        }
+        None
+    }
+}
+
+impl Rule for DeepNesting {
+    fn id(&self) -> &'static str {
+        "deep-nesting"
+    }
+
+    fn description(&self) -> &'static str {
+        "Statements nested in more than max_depth IF, WHILE, FOR or TEST"
+    }
+
+    fn configure(&mut self, option: &str, value: &str) -> Result<(), String> {
+        match option {
+            "max_depth" => {
+                self.max_depth = value.parse().map_err(|_| format!("Invalid max_depth {}", value))?;
+                Ok(())
+            },
+            _ => Err(format!("Unknown option {} of rule {}", option, self.id())),
+        }
+    }
+
+    fn check(&self, program: &Program, found: &mut Vec<(String, Span)>) {
+        for routine in program.modules.iter().flat_map(|module| module.routines.iter()) {
+            let bodies = std::iter::once(&routine.statements).chain(routine.handler.iter());
+            if let Some(statement) = bodies.into_iter().find_map(|body| self.too_deep(body, 0)) {
+                found.push((format!("Routine {} nests statements more than {} deep", routine.name, self.max_depth), statement.span));
+            }
+        }
+    }
+}
+
+/// Routines with a cyclomatic complexity above a maximum: one plus the
+/// branches, loops, cases and AND or OR in them
+struct Complexity {
+    max_complexity: usize,
+}
+
+impl Complexity {
+    fn complexity(routine: &Routine) -> usize {
+        let mut complexity = 1;
+        for statement in statements(routine) {
+            complexity += match &statement.node {
+                Node::If { branches, .. } => branches.len(),
+                Node::Test { cases, .. } => cases.len(),
+                Node::While { .. } | Node::For { .. } => 1,
+                _ => 0,
+            };
+            complexity += nodes(&statement.node, &routine.nodes).iter()
+                .filter(|node| matches!(node, Node::BinOp { op: Operator::And | Operator::Or, .. }))
+                .count();
+        }
+        complexity
+    }
+}
+
+impl Rule for Complexity {
+    fn id(&self) -> &'static str {
+        "complexity"
+    }
+
+    fn description(&self) -> &'static str {
+        "Routine with a cyclomatic complexity above max_complexity"
+    }
+
+    fn configure(&mut self, option: &str, value: &str) -> Result<(), String> {
+        match option {
+            "max_complexity" => {
+                self.max_complexity = value.parse().map_err(|_| format!("Invalid max_complexity {}", value))?;
+                Ok(())
+            },
+            _ => Err(format!("Unknown option {} of rule {}", option, self.id())),
+        }
+    }
+
+    fn check(&self, program: &Program, found: &mut Vec<(String, Span)>) {
+        for routine in program.modules.iter().flat_map(|module| module.routines.iter()) {
+            let complexity = Complexity::complexity(routine);
+            if complexity > self.max_complexity {
+                found.push((format!("Routine {} has complexity {}, more than {}", routine.name, complexity, self.max_complexity), routine.span));
+            }
+        }
+    }
+}
+
+/// IF branches and loops without statements
+struct EmptyBlock;
+
+impl Rule for EmptyBlock {
+    fn id(&self) -> &'static str {
+        "empty-block"
+    }
+
+    fn description(&self) -> &'static str {
+        "IF, ELSEIF, WHILE or FOR without statements"
+    }
+
+    fn check(&self, program: &Program, found: &mut Vec<(String, Span)>) {
+        for routine in program.modules.iter().flat_map(|module| module.routines.iter()) {
+            for statement in statements(routine) {
+                let empty = match &statement.node {
+                    Node::If { branches, .. } => branches.iter().any(|(_, body)| body.is_empty()),
+                    Node::While { body, .. } | Node::For { body, .. } => body.is_empty(),
+                    _ => false,
+                };
+                if empty {
+                    found.push((format!("Empty block in routine {}", routine.name), statement.span));
+                }
+            }
+        }
+    }
+}
+
+/// Casing of routine names
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Casing {
+    Pascal,
+    Camel,
+    Any,
+}
+
+/// Type prefixes of data names, like nCount for a num, and the casing of
+/// routine names. The entry routine main is left alone.
+struct Naming {
+    // Prefix by data type
+    prefixes: Vec<(String, String)>,
+    routines: Casing,
+}
+
+impl Default for Naming {
+    fn default() -> Naming {
+        let prefixes = [("num", "n"), ("bool", "b"), ("string", "s"), ("byte", "by"), ("robtarget", "p"), ("jointtarget", "j"),
+            ("tooldata", "t"), ("wobjdata", "wobj"), ("speeddata", "v"), ("zonedata", "z")];
+        Naming {
+            prefixes: prefixes.iter().map(|(data_type, prefix)| (String::from(*data_type), String::from(*prefix))).collect(),
+            routines: Casing::Pascal,
+        }
+    }
+}
+
+impl Naming {
+    /// Whether the name is the prefix followed by an upper case letter or a
+    /// digit, like nCount or p10
+    fn prefixed(name: &str, prefix: &str) -> bool {
+        name.strip_prefix(prefix).is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_uppercase() || c.is_ascii_digit()))
+    }
+
+    fn check_data(&self, decl: &DataDecl, found: &mut Vec<(String, Span)>) {
+        let prefix = self.prefixes.iter().find(|(data_type, _)| data_type.eq_ignore_ascii_case(&decl.data_type));
+        if let Some((_, prefix)) = prefix {
+            if !Naming::prefixed(&decl.name, prefix) {
+                found.push((format!("{} '{}' doesn't start with {}", decl.data_type, decl.name, prefix), decl.span));
+            }
+        }
+    }
+}
+
+impl Rule for Naming {
+    fn id(&self) -> &'static str {
+        "naming"
+    }
+
+    fn description(&self) -> &'static str {
+        "Data names with the prefix of their type, routine names in PascalCase"
+    }
+
+    fn configure(&mut self, option: &str, value: &str) -> Result<(), String> {
+        if let Some(data_type) = option.strip_prefix("prefix.") {
+            self.prefixes.retain(|(known, _)| !known.eq_ignore_ascii_case(data_type));
+            // An empty prefix leaves the names of the type alone
+            if !value.is_empty() {
+                self.prefixes.push((String::from(data_type), String::from(value)));
+            }
+            return Ok(());
+        }
+        match option {
+            "routines" => {
+                self.routines = match value {
+                    "pascal" => Casing::Pascal,
+                    "camel" => Casing::Camel,
+                    "any" => Casing::Any,
+                    _ => return Err(format!("Invalid routines {}, expected pascal, camel or any", value)),
+                };
+                Ok(())
+            },
+            _ => Err(format!("Unknown option {} of rule {}", option, self.id())),
+        }
+    }
+
+    fn check(&self, program: &Program, found: &mut Vec<(String, Span)>) {
+        for module in program.modules.iter() {
+            for decl in module.variables.iter() {
+                self.check_data(decl, found);
+            }
+            for routine in module.routines.iter() {
+                let casing = match self.routines {
+                    Casing::Pascal => Some(("PascalCase", true)),
+                    Casing::Camel => Some(("camelCase", false)),
+                    Casing::Any => None,
+                };
+                if let Some((casing, upper)) = casing {
+                    let first_upper = routine.name.starts_with(|c: char| c.is_ascii_uppercase());
+                    if !routine.name.eq_ignore_ascii_case("main") && (first_upper != upper || routine.name.contains('_')) {
+                        found.push((format!("Routine {} isn't {}", routine.name, casing), routine.span));
+                    }
+                }
+
+                // Loop variables of FOR, like i, have no prefix
+                let loops: Vec<usize> = statements(routine).iter()
+                    .filter_map(|statement| match &statement.node {
+                        Node::For { var, .. } => match routine.nodes[*var] {
+                            Node::Var(slot) => Some(slot),
+                            _ => None,
+                        },
+                        _ => None,
+                    })
+                    .collect();
+                let decls = routine.arguments.iter().map(|param| &param.decl).chain(routine.variables.iter());
+                for (_, decl) in decls.enumerate().filter(|(slot, _)| !loops.contains(slot)) {
+                    self.check_data(decl, found);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer;
+    use crate::parser;
+    use crate::resolver::{self, ResolveOptions};
+
+    const SOURCE: &str = "
+MODULE Cell
+    LOCAL VAR num nUnused := 0;
+    LOCAL VAR num nCount := 0;
+    PROC main()
+        VAR num nTemp;
+        VAR string sText;
+        FOR i FROM 1 TO 3 DO
+            nCount := nCount + i;
+        ENDFOR
+        WHILE nCount > 10 DO
+        ENDWHILE
+        TPWrite sText;
+    ENDPROC
+ENDMODULE";
+
+    /// Rule of the site that data has a type prefix, like nCount
+    struct Prefix;
+
+    impl Rule for Prefix {
+        fn id(&self) -> &'static str {
+            "prefix"
+        }
+
+        fn description(&self) -> &'static str {
+            "Num data starts with n"
+        }
+
+        fn severity(&self) -> Severity {
+            Severity::Error
+        }
+
+        fn check(&self, program: &Program, found: &mut Vec<(String, Span)>) {
+            for (_, decl, _) in program.module_data() {
+                if decl.data_type == "num" && !decl.name.starts_with('n') {
+                    found.push((format!("{} has no prefix", decl.name), decl.span));
+                }
+            }
+        }
+    }
+
+    fn lint(linter: &Linter, source: &str) -> Vec<String> {
+        let mut program = parser::parse_tokens(lexer::parse(source)).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        linter.check(&program).iter().map(Lint::to_string).collect()
+    }
+
+    #[test]
+    fn rules_are_configured() {
+        let mut linter = Linter::new();
+        assert_eq!(lint(&linter, SOURCE), vec![
+            "3:19: warning[unused-data]: LOCAL 'nUnused' in module Cell is never used",
+            "6:17: warning[unused-data]: 'nTemp' in routine main is never used",
+            "11:9: warning[empty-block]: Empty block in routine main",
+        ]);
+
+        let config = LintConfig::parse("
+# Site rules
+[unused-data]
+level = \"off\"
+[naming]
+level = \"off\"
+[long-routine]
+level = \"error\"
+max_statements = 3").unwrap();
+        linter.configure(&config).unwrap();
+        linter.register(Box::new(Prefix));
+        assert_eq!(lint(&linter, &SOURCE.replace("nUnused", "xUnused")), vec![
+            "3:19: error[prefix]: xUnused has no prefix",
+            "5:10: error[long-routine]: Routine main has 4 statements, more than 3",
+            "11:9: warning[empty-block]: Empty block in routine main",
+        ]);
+
+        let configure = |text: &str| Linter::new().configure(&LintConfig::parse(text)?);
+        assert_eq!(configure("[naming]\nroutines = \"snake\"").unwrap_err(), "Invalid routines snake, expected pascal, camel or any");
+        assert_eq!(configure("[missing]\nlevel = \"off\"").unwrap_err(), "Unknown rule missing");
+        assert_eq!(configure("[empty-block]\nlength = 3").unwrap_err(), "Unknown option length of rule empty-block");
+        assert_eq!(configure("[long-routine]\nmax_statements = many").unwrap_err(), "Invalid max_statements many");
+        assert_eq!(configure("level = \"off\"").unwrap_err(), "1: level outside of a rule table");
+        assert_eq!(configure("[empty-block]\nlevel = \"info\"").unwrap_err(), "2: Unknown level info");
+    }
+
+    #[test]
+    fn names_follow_conventions() {
+        let source = "
+MODULE Cell
+    VAR num count := 0;
+    PERS robtarget p10 := [[0, 0, 0], [1, 0, 0, 0], [0, 0, 0, 0], [9E9, 9E9, 9E9, 9E9, 9E9, 9E9]];
+    PERS tooldata gripper := [TRUE, [[0, 0, 100], [1, 0, 0, 0]], [1, [0, 0, 1], [1, 0, 0, 0], 0, 0, 0]];
+    PROC pick_part(bool bFast, string name)
+        VAR bool bnext;
+        bnext := bFast;
+        FOR i FROM 1 TO 3 DO
+            count := count + i;
+        ENDFOR
+    ENDPROC
+    PROC main()
+        pick_part TRUE, \"A\";
+    ENDPROC
+ENDMODULE";
+        let mut linter = Linter::new();
+        assert_eq!(lint(&linter, source), vec![
+            "3:13: warning[naming]: num 'count' doesn't start with n",
+            "5:19: warning[naming]: tooldata 'gripper' doesn't start with t",
+            "6:10: warning[naming]: Routine pick_part isn't PascalCase",
+            "6:39: warning[naming]: string 'name' doesn't start with s",
+            "7:18: warning[naming]: bool 'bnext' doesn't start with b",
+        ]);
+
+        let config = LintConfig::parse("[naming]\nroutines = \"any\"\nprefix.num = \"\"\nprefix.tooldata = \"to\"\nprefix.bool = \"is\"").unwrap();
+        linter.configure(&config).unwrap();
+        assert_eq!(lint(&linter, source), vec![
+            "5:19: warning[naming]: tooldata 'gripper' doesn't start with to",
+            "6:25: warning[naming]: bool 'bFast' doesn't start with is",
+            "6:39: warning[naming]: string 'name' doesn't start with s",
+            "7:18: warning[naming]: bool 'bnext' doesn't start with is",
+        ]);
+    }
+
+    #[test]
+    fn complex_routines() {
+        let source = "
+MODULE Cell
+    VAR num nCount := 0;
+    PROC main()
+        IF nCount > 0 AND nCount < 10 THEN
+            WHILE nCount > 0 DO
+                TEST nCount
+                CASE 1, 2:
+                    FOR i FROM 1 TO 2 DO
+                        IF nCount = 1 OR nCount = 2 nCount := nCount - 1;
+                    ENDFOR
+                CASE 3:
+                    nCount := 0;
+                ENDTEST
+                nCount := nCount - 1;
+            ENDWHILE
+        ELSEIF nCount < 0 THEN
+            nCount := 0;
+        ENDIF
+    ENDPROC
+ENDMODULE";
+        let mut linter = Linter::new();
+        // The statement of the compact IF is five deep
+        assert_eq!(lint(&linter, source), vec!["10:53: warning[deep-nesting]: Routine main nests statements more than 4 deep"]);
+
+        let config = LintConfig::parse("[deep-nesting]\nmax_depth = 3\n[complexity]\nmax_complexity = 8").unwrap();
+        linter.configure(&config).unwrap();
+        assert_eq!(lint(&linter, source), vec![
+            "4:10: warning[complexity]: Routine main has complexity 10, more than 8",
+            "10:25: warning[deep-nesting]: Routine main nests statements more than 3 deep",
+        ]);
+        let configure = |text: &str| Linter::new().configure(&LintConfig::parse(text)?);
+        assert_eq!(configure("[deep-nesting]\nmax_depth = -1").unwrap_err(), "Invalid max_depth -1");
+    }
+}
@@ -0,0 +1,259 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::builtins::HostRoutine;
+use crate::host::{self, Host};
+use crate::interpreter::{LoadError, RuntimeError};
+use crate::lexer::{self, Span};
+use crate::parser::{self, DataDecl, Module};
+use crate::resolver;
+use crate::variable::Variable;
+
+// ------------------ Modules -----------------------/
+
+/// Module read from a file while the program runs, as it was parsed
+#[derive(Debug, Clone)]
+pub struct LoadedModule {
+    // Path in the module search path, without the device of the file name
+    pub path: PathBuf,
+    pub module: Module,
+}
+
+/// Change to the modules of a running program, made by the instruction
+/// being executed
+#[derive(Debug, Clone)]
+pub enum ModuleChange {
+    Load(Box<LoadedModule>),
+    // Path of a loaded module, like the program named it
+    Unload(String),
+}
+
+pub fn load_error(error: LoadError, message: String) -> RuntimeError {
+    RuntimeError::ModuleLoad { error, message: message.into_boxed_str(), span: Span::default() }
+}
+
+/// Path of a module file, relative to the directories of the search path
+fn module_file(name: &str) -> Result<PathBuf, RuntimeError> {
+    host::sandbox_path(Path::new(""), name).map_err(|message| load_error(LoadError::FileNotFound, message))
+}
+
+/// Read and parse the module file `name`, like "HOME:/PART_A.MOD", from the
+/// first directory of the module search path that has it
+pub fn read_module(host: &Host, name: &str) -> Result<LoadedModule, RuntimeError> {
+    let path = module_file(name)?;
+    let source = host.module_path.iter()
+        .map(|dir| dir.join(&path))
+        .find(|file| file.is_file())
+        .ok_or_else(|| load_error(LoadError::FileNotFound, format!("Module file {} not found", name)))?;
+    let source = fs::read(&source)
+        .map(|bytes| lexer::decode(&bytes))
+        .map_err(|err| load_error(LoadError::FileNotFound, format!("{}: {}", name, err)))?;
+
+    let program = parser::parse_tokens(lexer::parse(&source))
+        .map_err(|err| load_error(LoadError::Syntax, format!("{}: {}", name, err)))?;
+    let mut modules = program.modules;
+    match modules.pop() {
+        Some(module) if modules.is_empty() => Ok(LoadedModule { path, module }),
+        _ => Err(load_error(LoadError::Syntax, format!("{}: a module file holds one module", name))),
+    }
+}
+
+/// Link a change to the modules of a program. Routines of the modules before
+/// `busy` are running, those modules can't be unloaded. Global data of the
+/// modules that stay keeps its value. On failure nothing changes.
+pub fn link(change: ModuleChange, modules: &mut Vec<Module>, loaded: &mut Vec<LoadedModule>, globals: &mut Vec<Variable>,
+    system: &[DataDecl], host_routines: &[HostRoutine], busy: usize) -> Result<(), RuntimeError> {
+    // Loaded modules follow the modules of the program
+    let first_loaded = modules.len() - loaded.len();
+    let data_start = |modules: &[Module], idx: usize| system.len() + modules[..idx].iter().map(|module| module.variables.len()).sum::<usize>();
+
+    match change {
+        ModuleChange::Load(new) => {
+            if modules.iter().any(|module| module.name.eq_ignore_ascii_case(&new.module.name)) {
+                return Err(load_error(LoadError::Loaded, format!("Module {} is loaded already", new.module.name)));
+            }
+            let idx = modules.len();
+            modules.push(new.module.clone());
+            match resolver::link(modules, system, host_routines, idx) {
+                Ok(mut initial) => {
+                    globals.extend(initial.drain(data_start(modules, idx)..));
+                    loaded.push(*new);
+                    Ok(())
+                },
+                Err(diagnostics) => {
+                    modules.pop();
+                    Err(link_error(&new.module.name, &diagnostics))
+                },
+            }
+        },
+        ModuleChange::Unload(name) => {
+            let path = module_file(&name).map_err(|err| load_error(LoadError::Unload, err.to_string()))?;
+            let position = loaded.iter().position(|module| module.path == path);
+            let (position, idx) = match position {
+                Some(position) if first_loaded + position >= busy => (position, first_loaded + position),
+                Some(_) => return Err(load_error(LoadError::Unload, format!("Module {} is running", name))),
+                None => return Err(load_error(LoadError::Unload, format!("Module {} is not loaded", name))),
+            };
+
+            // The modules after it are linked again from how they were read
+            let mut relinked: Vec<Module> = modules[..idx].to_vec();
+            relinked.extend(loaded[position + 1..].iter().map(|later| later.module.clone()));
+            if let Err(diagnostics) = resolver::link(&mut relinked, system, host_routines, idx) {
+                return Err(link_error(&modules[idx].name, &diagnostics));
+            }
+            let start = data_start(modules, idx);
+            globals.drain(start..start + modules[idx].variables.len());
+            *modules = relinked;
+            loaded.remove(position);
+            Ok(())
+        },
+    }
+}
+
+fn link_error(module: &str, diagnostics: &[resolver::Diagnostic]) -> RuntimeError {
+    let messages: Vec<String> = diagnostics.iter()
+        .filter(|diagnostic| diagnostic.severity == resolver::Severity::Error)
+        .map(|diagnostic| diagnostic.to_string())
+        .collect();
+    load_error(LoadError::LinkRef, format!("Module {} doesn't link: {}", module, messages.join("; ")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler;
+    use crate::interpreter;
+    use crate::interpreter::InterpreterOptions;
+    use crate::parser::Program;
+    use crate::resolver::ResolveOptions;
+    use crate::vm;
+
+    const PART_A: &str = "
+MODULE PartA
+    VAR num nCalls := 0;
+    PROC rPartA(INOUT num nCount)
+        nCalls := nCalls + 1;
+        nCount := nCount + 10;
+        rLog \"A\";
+    ENDPROC
+ENDMODULE";
+
+    const PART_B: &str = "
+MODULE PartB
+    PROC rPartB(INOUT num nCount, \\switch Twice)
+        nCount := nCount * 2;
+        IF Present(Twice) nCount := nCount * 2;
+        rLog \"B\";
+    ENDPROC
+ENDMODULE";
+
+    fn cell(body: &str) -> Program {
+        let source = format!("
+MODULE Cell
+    VAR num nCount := 0;
+    VAR string sPart := \"A\";
+    VAR string sLog := \"\";
+    VAR loadsession load1;
+    PROC rLog(string sText)
+        sLog := sLog + sText;
+    ENDPROC
+    PROC main()
+{}
+    ENDPROC
+ENDMODULE", body);
+        let mut program = parser::parse_tokens(lexer::parse(&source)).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        program
+    }
+
+    /// Run `main` of the program on both backends, which have to agree
+    fn run_both(body: &str, dir: &Path) -> (Result<(), RuntimeError>, String) {
+        let options = InterpreterOptions::default();
+        let mut program = cell(body);
+        let result = interpreter::run(&mut program, "main", &options, &mut Host::new(&mut Vec::new()).with_module_dir(dir)).into_result().map(|_| ());
+        let globals = format!("{:?} {:?}", program.variables, program.modules.iter().map(|module| &module.name).collect::<Vec<_>>());
+
+        let mut program = cell(body);
+        let bytecode = compiler::compile(&program).unwrap();
+        assert_eq!(result, vm::run(&mut program, &bytecode, "main", &options, &mut Host::new(&mut Vec::new()).with_module_dir(dir)));
+        assert_eq!(globals, format!("{:?} {:?}", program.variables, program.modules.iter().map(|module| &module.name).collect::<Vec<_>>()));
+        (result, globals)
+    }
+
+    #[test]
+    fn loads_and_unloads_modules() {
+        let dir = std::env::temp_dir().join(format!("rapid_rust_modules_{}", std::process::id()));
+        fs::create_dir_all(dir.join("TEMP")).unwrap();
+        fs::write(dir.join("PART_A.MOD"), PART_A).unwrap();
+        fs::write(dir.join("TEMP").join("PART_B.MOD"), PART_B).unwrap();
+        fs::write(dir.join("PART_C.MOD"), "MODULE PartC\n PROC rPartC()\n rMissing;\n ENDPROC\nENDMODULE").unwrap();
+
+        let (result, globals) = run_both("
+        Load \"HOME:\" \\File:=\"PART_A.MOD\";
+        %\"rPart\" + sPart% nCount;
+        %\"rPart\" + sPart% nCount;
+        UnLoad \"HOME:/PART_A.MOD\";
+        StartLoad \\Dynamic, \"TEMP:/PART_B.MOD\", load1;
+        sPart := \"B\";
+        WaitLoad load1;
+        %\"rPart\" + sPart% nCount \\Twice;
+        %\"rLog\"% \"!\";", &dir);
+        result.unwrap();
+        assert_eq!(globals, r#"[Num(80.0), Str("B"), Str("AAB!"), LoadSession(None)] ["Cell", "PartB"]"#);
+
+        // Loading again after the unload starts with fresh data
+        let (result, globals) = run_both("
+        Load \"HOME:/PART_A.MOD\";
+        %\"rPartA\"% nCount;
+        UnLoad \"PART_A.MOD\";
+        Load \"PART_A.MOD\";
+        %\"rPartA\"% nCount;", &dir);
+        result.unwrap();
+        assert_eq!(globals, r#"[Num(20.0), Str("A"), Str("AA"), LoadSession(None), Num(1.0)] ["Cell", "PartA"]"#);
+
+        let error = |body: &str| run_both(body, &dir).0.unwrap_err().name();
+        assert_eq!(error("Load \"HOME:/PART_X.MOD\";"), "ERR_FILNOTFND");
+        assert_eq!(error("Load \"HOME:/../PART_A.MOD\";"), "ERR_FILNOTFND");
+        assert_eq!(error("Load \"PART_A.MOD\"; Load \"PART_A.MOD\";"), "ERR_LOADED");
+        assert_eq!(error("Load \"PART_C.MOD\";"), "ERR_LINKREF");
+        assert_eq!(error("UnLoad \"PART_A.MOD\";"), "ERR_UNLOAD");
+        assert_eq!(error("WaitLoad load1;"), "ERR_LOADNO_NOUSE");
+        assert_eq!(error("StartLoad \"PART_A.MOD\", load1; StartLoad \"PART_A.MOD\", load1;"), "ERR_LOADNO_INUSE");
+        // Late bound calls are checked when the procedure is known
+        assert_eq!(error("%\"rPartA\"% nCount;"), "ERR_REFUNKPRC");
+        assert_eq!(error("Load \"PART_A.MOD\"; %\"rPartA\"% 1;"), "ERR_ARGVALERR");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn images_keep_the_loaded_modules() {
+        let dir = std::env::temp_dir().join(format!("rapid_rust_image_{}", std::process::id()));
+        fs::create_dir_all(dir.join("TEMP")).unwrap();
+        fs::write(dir.join("PART_A.MOD"), PART_A).unwrap();
+        fs::write(dir.join("TEMP").join("PART_B.MOD"), PART_B).unwrap();
+
+        let mut program = cell("
+        IF nCount = 0 THEN
+            Load \"PART_A.MOD\";
+        ELSE
+            UnLoad \"PART_A.MOD\";
+            Load \"TEMP:/PART_B.MOD\";
+        ENDIF
+        nCount := nCount + 1;");
+        let options = InterpreterOptions::default();
+        let names = |program: &Program| program.modules.iter().map(|module| module.name.clone()).collect::<Vec<_>>();
+        interpreter::run(&mut program, "main", &options, &mut Host::new(&mut Vec::new()).with_module_dir(&dir)).into_result().unwrap();
+        let image = program.image();
+
+        // Runs after the image unload PartA and load PartB in its place
+        for _ in 0..2 {
+            interpreter::run(&mut program, "main", &options, &mut Host::new(&mut Vec::new()).with_module_dir(&dir)).into_result().unwrap();
+            assert_eq!(names(&program), ["Cell", "PartB"]);
+            program.reset(&image);
+            assert_eq!(names(&program), ["Cell", "PartA"]);
+            assert_eq!(program.loaded.len(), 1);
+            assert!(matches!(program.variables[0], Variable::Num(n) if n == 1.0));
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,688 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::builtins::{self, Builtin};
+use crate::json::Json;
+use crate::lexer::{self, Span};
+use crate::linter::Linter;
+use crate::parser::{self, Storage};
+use crate::resolver::{self, ResolveOptions, Severity};
+use crate::symbols::{self, OutlineItem, OutlineKind, Symbol, SymbolIndex};
+
+// ------------------ Transport -----------------------/
+
+/// Read a message with its Content-Length header, None at the end of the input
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Message without Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Json::parse(&body).map(Some).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// Serve the messages of a client until it exits. True when it asked for a
+/// shutdown first, like the protocol wants.
+pub fn run(input: &mut impl BufRead, output: &mut impl Write) -> io::Result<bool> {
+    let mut server = Server::default();
+    while let Some(message) = read_message(input)? {
+        if message.get("method").and_then(Json::as_str) == Some("exit") {
+            return Ok(server.shutdown);
+        }
+        for reply in server.handle(&message) {
+            write_message(output, &reply)?;
+        }
+    }
+    Ok(false)
+}
+
+// ------------------ Server -----------------------/
+
+/// Documents open in the editor. Each change sends the full text, which is
+/// analyzed again as a whole. Names are looked up in the module files of the
+/// workspace folder, the open documents replace the files on disk.
+#[derive(Debug, Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+    root: Option<PathBuf>,
+    // Options of the client, `warnShadowing` in its initializationOptions
+    options: ResolveOptions,
+    shutdown: bool,
+}
+
+impl Server {
+    /// Replies and notifications for a message of the client
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let uri = params.path(&["textDocument", "uri"]).and_then(Json::as_str).map(String::from);
+
+        let result = match (method, uri) {
+            ("initialize", _) => {
+                let folder = params.get("workspaceFolders").and_then(Json::as_array)
+                    .and_then(|folders| folders.first())
+                    .and_then(|folder| folder.get("uri"))
+                    .or_else(|| params.get("rootUri"));
+                self.root = folder.and_then(Json::as_str).and_then(uri_to_path);
+                self.options.warn_shadowing = matches!(params.path(&["initializationOptions", "warnShadowing"]), Some(Json::Bool(true)));
+                Json::object(vec![
+                    ("capabilities", Json::object(vec![
+                        // Full text on every change
+                        ("textDocumentSync", Json::Num(1.0)),
+                        ("definitionProvider", Json::Bool(true)),
+                        ("referencesProvider", Json::Bool(true)),
+                        ("completionProvider", Json::object(Vec::new())),
+                        ("hoverProvider", Json::Bool(true)),
+                        ("documentSymbolProvider", Json::Bool(true)),
+                        ("renameProvider", Json::Bool(true)),
+                    ])),
+                    ("serverInfo", Json::object(vec![("name", Json::str("rapid-lsp"))])),
+                ])
+            },
+            ("shutdown", _) => {
+                self.shutdown = true;
+                Json::Null
+            },
+            ("textDocument/didOpen", Some(uri)) => {
+                let text = params.path(&["textDocument", "text"]).and_then(Json::as_str).unwrap_or("");
+                self.documents.insert(uri.clone(), String::from(text));
+                return vec![self.publish(&uri)];
+            },
+            ("textDocument/didChange", Some(uri)) => {
+                let changes = params.get("contentChanges").and_then(Json::as_array).unwrap_or(&[]);
+                if let Some(text) = changes.last().and_then(|change| change.get("text")).and_then(Json::as_str) {
+                    self.documents.insert(uri.clone(), String::from(text));
+                }
+                return vec![self.publish(&uri)];
+            },
+            ("textDocument/didClose", Some(uri)) => {
+                self.documents.remove(&uri);
+                return vec![notification("textDocument/publishDiagnostics", Json::object(vec![
+                    ("uri", Json::Str(uri)),
+                    ("diagnostics", Json::Array(Vec::new())),
+                ]))];
+            },
+            ("textDocument/definition", Some(uri)) => {
+                let index = self.index();
+                let symbol = index.file(&uri)
+                    .and_then(|file| Some((file, offset(&index.files[file].source, params.get("position")?))))
+                    .and_then(|(file, offset)| index.symbol_at(file, offset));
+                match symbol {
+                    Some(symbol) => {
+                        let (file, span) = index.definition(symbol);
+                        location(&index, file, span)
+                    },
+                    None => Json::Null,
+                }
+            },
+            ("textDocument/references", Some(uri)) => {
+                let index = self.index();
+                let symbol = index.file(&uri)
+                    .and_then(|file| Some((file, offset(&index.files[file].source, params.get("position")?))))
+                    .and_then(|(file, offset)| index.symbol_at(file, offset));
+                let declaration = params.path(&["context", "includeDeclaration"]) != Some(&Json::Bool(false));
+                let locations = symbol.map(|symbol| {
+                    let definition = index.definition(symbol);
+                    index.references(symbol).into_iter()
+                        .filter(|reference| declaration || *reference != definition)
+                        .map(|(file, span)| location(&index, file, span))
+                        .collect()
+                });
+                Json::Array(locations.unwrap_or_default())
+            },
+            ("textDocument/hover", Some(uri)) => {
+                let index = self.index();
+                let symbol = index.file(&uri)
+                    .and_then(|file| Some((file, offset(&index.files[file].source, params.get("position")?))))
+                    .and_then(|(file, offset)| index.symbol_at(file, offset));
+                symbol.map_or(Json::Null, |symbol| hover(&index, symbol))
+            },
+            ("textDocument/rename", Some(uri)) => {
+                let index = self.index();
+                let symbol = index.file(&uri)
+                    .and_then(|file| Some((file, offset(&index.files[file].source, params.get("position")?))))
+                    .and_then(|(file, offset)| index.symbol_at(file, offset));
+                let new_name = params.get("newName").and_then(Json::as_str).unwrap_or("");
+                let edits = match symbol.map(|symbol| index.rename(symbol, new_name)) {
+                    Some(Ok(edits)) => edits,
+                    // Request failed
+                    Some(Err(err)) => return vec![error(message, -32803.0, &err)],
+                    None => return vec![error(message, -32803.0, "No symbol to rename")],
+                };
+                let mut changes: Vec<(String, Json)> = Vec::new();
+                for edit in edits {
+                    let file = &index.files[edit.file];
+                    let text_edit = Json::object(vec![
+                        ("range", range(&file.source, edit.start, edit.end)),
+                        ("newText", Json::Str(edit.text)),
+                    ]);
+                    match changes.iter_mut().find(|(uri, _)| *uri == file.uri) {
+                        Some((_, Json::Array(file_edits))) => file_edits.push(text_edit),
+                        _ => changes.push((file.uri.clone(), Json::Array(vec![text_edit]))),
+                    }
+                }
+                Json::object(vec![("changes", Json::Object(changes))])
+            },
+            ("textDocument/documentSymbol", Some(uri)) => {
+                let source = self.documents.get(&uri).map(String::as_str).unwrap_or("");
+                let items = symbols::outline(source).unwrap_or_default();
+                Json::Array(items.iter().map(|item| document_symbol(source, item)).collect())
+            },
+            ("textDocument/completion", Some(uri)) => {
+                let source = self.documents.get(&uri).cloned().unwrap_or_default();
+                let offset = offset(&source, params.get("position").unwrap_or(&Json::Null));
+                Json::Array(self.complete(&uri, &source, offset))
+            },
+            // Other notifications need no reply
+            _ if message.get("id").is_none() => return Vec::new(),
+            _ => return vec![error(message, -32601.0, &format!("Method not found: {}", method))],
+        };
+        vec![Json::object(vec![
+            ("jsonrpc", Json::str("2.0")),
+            ("id", message.get("id").cloned().unwrap_or(Json::Null)),
+            ("result", result),
+        ])]
+    }
+
+    fn publish(&self, uri: &str) -> Json {
+        let source = self.documents.get(uri).map(String::as_str).unwrap_or("");
+        let diagnostics = check(source, &self.options).iter()
+            .map(|finding| Json::object(vec![
+                ("range", range(source, finding.start, finding.end)),
+                ("severity", Json::Num(match finding.severity {
+                    Severity::Error => 1.0,
+                    Severity::Warning => 2.0,
+                })),
+                ("source", Json::str("rapid")),
+                ("message", Json::Str(finding.message.clone())),
+            ]))
+            .collect();
+        notification("textDocument/publishDiagnostics", Json::object(vec![
+            ("uri", Json::str(uri)),
+            ("diagnostics", Json::Array(diagnostics)),
+        ]))
+    }
+}
+
+impl Server {
+    /// Symbols of the open documents and the module files of the workspace
+    fn index(&self) -> SymbolIndex {
+        self.index_with(None)
+    }
+
+    /// Index with the text of one document replaced
+    fn index_with(&self, edited: Option<(&str, String)>) -> SymbolIndex {
+        let mut files: Vec<(String, String)> = self.documents.iter()
+            .map(|(uri, text)| match &edited {
+                Some((edited, edited_text)) if edited == uri => (uri.clone(), edited_text.clone()),
+                _ => (uri.clone(), text.clone()),
+            })
+            .collect();
+        let open: Vec<PathBuf> = self.documents.keys().filter_map(|uri| uri_to_path(uri)).collect();
+        let mut paths = Vec::new();
+        if let Some(root) = &self.root {
+            module_files(root, &mut paths);
+        }
+        for path in paths.into_iter().filter(|path| !open.contains(path)) {
+            if let Ok(source) = lexer::read_source(&path) {
+                files.push((path_to_uri(&path), source));
+            }
+        }
+        SymbolIndex::new(files)
+    }
+}
+
+// ------------------ Completion -----------------------/
+
+impl Server {
+    /// Data and routines in scope at an offset of a document, then the
+    /// built-in routines with their parameters to fill in
+    fn complete(&self, uri: &str, source: &str, offset: usize) -> Vec<Json> {
+        let prefix_start = source[..offset].rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).map_or(0, |idx| idx + 1);
+        let prefix = source[prefix_start..offset].to_ascii_lowercase();
+        let matches = |name: &str| name.to_ascii_lowercase().starts_with(&prefix);
+
+        // The line being typed seldom parses, the rest of the document should
+        let line_start = source[..offset].rfind('\n').map_or(0, |idx| idx + 1);
+        let line_end = source[offset..].find('\n').map_or(source.len(), |idx| offset + idx);
+        let blanked = format!("{}{}{}", &source[..line_start], " ".repeat(line_end - line_start), &source[line_end..]);
+        let index = self.index_with(Some((uri, blanked)));
+
+        let symbols = index.file(uri).map(|file| index.in_scope(file, offset)).unwrap_or_default();
+        let mut items: Vec<Json> = symbols.iter()
+            .filter(|symbol| matches(index.name(**symbol)))
+            .map(|symbol| {
+                let kind = match index.data(*symbol) {
+                    Some(decl) if decl.storage == Storage::Const => 21.0,
+                    Some(_) => 6.0,
+                    None => 3.0,
+                };
+                Json::object(vec![
+                    ("label", Json::str(index.name(*symbol))),
+                    ("kind", Json::Num(kind)),
+                    ("detail", Json::Str(index.detail(*symbol))),
+                ])
+            })
+            .collect();
+
+        // Routines of the program hide the built-in ones
+        let hidden = |name: &str| symbols.iter().any(|symbol| index.name(*symbol).eq_ignore_ascii_case(name));
+        for builtin in builtins::builtins().iter().filter(|builtin| matches(builtin.name) && !hidden(builtin.name)) {
+            items.push(Json::object(vec![
+                ("label", Json::str(builtin.name)),
+                ("kind", Json::Num(3.0)),
+                ("detail", Json::Str(builtin.declaration())),
+                ("insertText", Json::Str(snippet(builtin))),
+                // Snippet
+                ("insertTextFormat", Json::Num(2.0)),
+            ]));
+        }
+        items
+    }
+}
+
+/// Call of a built-in routine with placeholders for its required
+/// arguments, like `MoveL ${1:ToPoint}, ${2:Speed}, ${3:Zone}, ${4:Tool}`
+fn snippet(builtin: &Builtin) -> String {
+    let arguments: Vec<String> = builtin.params.iter()
+        .filter(|param| !param.optional)
+        .enumerate()
+        .map(|(idx, param)| format!("${{{}:{}}}", idx + 1, param.decl.name))
+        .collect();
+    match builtin.return_type {
+        Some(_) => format!("{}({})", builtin.name, arguments.join(", ")),
+        None if arguments.is_empty() => String::from(builtin.name),
+        None => format!("{} {}", builtin.name, arguments.join(", ")),
+    }
+}
+
+/// Module files in a directory and the directories in it
+fn module_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect(),
+        Err(_) => return,
+    };
+    entries.sort();
+    for path in entries {
+        let hidden = path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with('.'));
+        let extension = path.extension().and_then(|extension| extension.to_str()).map(str::to_ascii_lowercase);
+        if path.is_dir() && !hidden {
+            module_files(&path, files);
+        } else if matches!(extension.as_deref(), Some("mod" | "modx" | "sys" | "sysx")) {
+            files.push(path);
+        }
+    }
+}
+
+/// Path of a file: URI, with its percent escapes decoded
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = Vec::new();
+    let mut rest = path.as_bytes();
+    while let Some((byte, tail)) = rest.split_first() {
+        let escaped = tail.get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(decoded) if *byte == b'%' => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            },
+            _ => {
+                bytes.push(*byte);
+                rest = tail;
+            },
+        }
+    }
+    Some(PathBuf::from(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            uri.push(char::from(byte));
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+fn location(index: &SymbolIndex, file: usize, span: Span) -> Json {
+    let file = &index.files[file];
+    Json::object(vec![("uri", Json::str(&file.uri)), ("range", range(&file.source, span.start, span.end))])
+}
+
+/// Declaration of a symbol with its value, and where it is declared
+fn hover(index: &SymbolIndex, symbol: Symbol) -> Json {
+    let mut declaration = index.detail(symbol);
+    if let Some(value) = index.initial_value(symbol) {
+        declaration.push_str(&format!(" := {}", value));
+    }
+    let line = index.definition(symbol).1.line;
+    let mut place = match index.owner(symbol) {
+        Some(routine) => format!("In `{}` of module `{}`, line {}", routine.name, index.module(symbol).name, line),
+        None => format!("Module `{}`, line {}", index.module(symbol).name, line),
+    };
+    if let Some(alias) = index.data(symbol).and_then(|decl| index.alias(&decl.data_type)) {
+        place.push_str(&format!("\n\n`{}` is an alias of `{}`", alias.name, alias.base));
+    }
+    Json::object(vec![("contents", Json::object(vec![
+        ("kind", Json::str("markdown")),
+        ("value", Json::Str(format!("```rapid\n{}\n```\n{}", declaration, place))),
+    ]))])
+}
+
+fn document_symbol(source: &str, item: &OutlineItem) -> Json {
+    // Kinds of the protocol
+    let kind = match item.kind {
+        OutlineKind::Module => 2.0,
+        OutlineKind::Procedure | OutlineKind::Function => 12.0,
+        OutlineKind::Variable | OutlineKind::Persistent => 13.0,
+        OutlineKind::Constant => 14.0,
+    };
+    Json::object(vec![
+        ("name", Json::str(&item.name)),
+        ("detail", Json::str(&item.detail)),
+        ("kind", Json::Num(kind)),
+        ("range", range(source, item.start, item.end)),
+        ("selectionRange", range(source, item.span.start, item.span.end)),
+        ("children", Json::Array(item.children.iter().map(|child| document_symbol(source, child)).collect())),
+    ])
+}
+
+/// Reply with an error to a request
+fn error(request: &Json, code: f64, message: &str) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::str("2.0")),
+        ("id", request.get("id").cloned().unwrap_or(Json::Null)),
+        ("error", Json::object(vec![
+            ("code", Json::Num(code)),
+            ("message", Json::str(message)),
+        ])),
+    ])
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    Json::object(vec![("jsonrpc", Json::str("2.0")), ("method", Json::str(method)), ("params", params)])
+}
+
+// ------------------ Diagnostics -----------------------/
+
+/// Problem in a document, between byte offsets
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Finding {
+    fn new(severity: Severity, message: String, span: Span) -> Finding {
+        Finding { severity, message, start: span.start, end: span.end.max(span.start) }
+    }
+}
+
+/// Errors of the lexer, the parser and the resolver, or the warnings and
+/// lints of a module that resolves
+pub fn check(source: &str, options: &ResolveOptions) -> Vec<Finding> {
+    let tokens = match lexer::tokenize(source) {
+        Ok(tokens) => tokens,
+        Err(err) => return vec![Finding::new(Severity::Error, err.message, err.span)],
+    };
+    let mut program = match parser::parse_tokens(tokens) {
+        Ok(program) => program,
+        Err(err) => return vec![Finding::new(Severity::Error, err.message, err.span)],
+    };
+    match resolver::resolve(&mut program, options) {
+        Ok(warnings) => {
+            let mut findings: Vec<Finding> = warnings.into_iter()
+                .map(|diagnostic| Finding::new(diagnostic.severity, diagnostic.message, diagnostic.span))
+                .collect();
+            findings.extend(Linter::new().check(&program).into_iter()
+                .map(|lint| Finding::new(lint.severity, format!("{} [{}]", lint.message, lint.rule), lint.span)));
+            findings
+        },
+        Err(diagnostics) => diagnostics.into_iter()
+            .map(|diagnostic| Finding::new(diagnostic.severity, diagnostic.message, diagnostic.span))
+            .collect(),
+    }
+}
+
+/// Range between byte offsets, in lines and UTF-16 characters from 0
+fn range(source: &str, start: usize, end: usize) -> Json {
+    Json::object(vec![("start", position(source, start)), ("end", position(source, end))])
+}
+
+/// Byte offset of a position in lines and UTF-16 characters from 0
+fn offset(source: &str, position: &Json) -> usize {
+    let line = position.get("line").and_then(Json::as_f64).unwrap_or(0.0) as usize;
+    let character = position.get("character").and_then(Json::as_f64).unwrap_or(0.0) as usize;
+    let line_start: usize = source.split_inclusive('\n').take(line).map(str::len).sum();
+    let mut units = 0;
+    for (idx, c) in source[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + idx;
+        }
+        units += c.len_utf16();
+    }
+    source.len()
+}
+
+fn position(source: &str, offset: usize) -> Json {
+    let offset = offset.min(source.len());
+    let before = source.get(..offset).unwrap_or(source);
+    let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    Json::object(vec![
+        ("line", Json::Num(before.matches('\n').count() as f64)),
+        ("character", Json::Num(character as f64)),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message(json: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", json.len(), json)
+    }
+
+    fn session(messages: &[&str]) -> (bool, Vec<Json>) {
+        let input: String = messages.iter().map(|json| message(json)).collect();
+        let mut output = Vec::new();
+        let clean = run(&mut input.as_bytes(), &mut output).unwrap();
+        let mut replies = Vec::new();
+        let mut output = output.as_slice();
+        while let Some(reply) = read_message(&mut output).unwrap() {
+            replies.push(reply);
+        }
+        (clean, replies)
+    }
+
+    #[test]
+    fn publishes_diagnostics() {
+        let (clean, replies) = session(&[
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+            r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+            r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///Cell.mod","languageId":"rapid","version":1,
+                "text":"MODULE Cell\n    PROC Main()\n        nMissing := 1;\n    ENDPROC\nENDMODULE"}}}"#,
+            r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///Cell.mod","version":2},
+                "contentChanges":[{"text":"MODULE Cell\n    PROC Main()\n        TPWrite \"é\" $;\n    ENDPROC\nENDMODULE"}]}}"#,
+            r#"{"jsonrpc":"2.0","method":"textDocument/didChange","params":{"textDocument":{"uri":"file:///Cell.mod","version":3},
+                "contentChanges":[{"text":"MODULE Cell\n    PROC Main(\nENDMODULE"}]}}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"workspace/symbol","params":{"query":""}}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#,
+        ]);
+        assert!(clean);
+        assert_eq!(replies.len(), 6);
+        assert_eq!(replies[0].to_string(), r#"{"jsonrpc":"2.0","id":1,"result":{"capabilities":{"textDocumentSync":1,"definitionProvider":true,"referencesProvider":true,"completionProvider":{},"hoverProvider":true,"documentSymbolProvider":true,"renameProvider":true},"serverInfo":{"name":"rapid-lsp"}}}"#);
+        assert_eq!(replies[1].get("params").unwrap().to_string(), concat!(r#"{"uri":"file:///Cell.mod","diagnostics":[{"range":{"start":{"line":2,"character":8},"#,
+            r#""end":{"line":2,"character":16}},"severity":1,"source":"rapid","message":"Unknown id 'nMissing'"}]}"#));
+        // Columns count UTF-16 characters, é is two bytes
+        let diagnostic = replies[2].path(&["params", "diagnostics"]).and_then(Json::as_array).unwrap()[0].clone();
+        assert_eq!(diagnostic.to_string(), concat!(r#"{"range":{"start":{"line":2,"character":20},"end":{"line":2,"character":21}},"#,
+            r#""severity":1,"source":"rapid","message":"Undefined symbol $"}"#));
+        let diagnostic = replies[3].path(&["params", "diagnostics"]).and_then(Json::as_array).unwrap()[0].clone();
+        assert_eq!(diagnostic.path(&["range", "start"]).unwrap().to_string(), r#"{"line":2,"character":0}"#);
+        assert_eq!(replies[4].path(&["error", "code"]).and_then(Json::as_f64), Some(-32601.0));
+        assert_eq!(replies[5].to_string(), r#"{"jsonrpc":"2.0","id":3,"result":null}"#);
+
+        // Exit without shutdown
+        assert!(!session(&[r#"{"jsonrpc":"2.0","method":"exit"}"#]).0);
+    }
+
+    #[test]
+    fn warns_about_shadowing_when_asked() {
+        let text = r#""text":"MODULE Cell\n    PERS num nCount := 0;\n    PROC Main()\n        VAR num nCount := 1;\n        nCount := 2;\n    ENDPROC\nENDMODULE""#;
+        let open = format!(r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"file:///Cell.mod",{}}}}}}}"#, text);
+        let messages = |params: &str| {
+            let initialize = format!(r#"{{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}}"#, params);
+            let (_, replies) = session(&[&initialize, &open, r#"{"jsonrpc":"2.0","method":"exit"}"#]);
+            replies[1].path(&["params", "diagnostics"]).and_then(Json::as_array).unwrap()
+                .iter().filter_map(|diagnostic| diagnostic.get("message").and_then(Json::as_str).map(String::from)).collect::<Vec<_>>()
+        };
+        assert!(messages("{}").is_empty());
+        assert_eq!(messages(r#"{"initializationOptions":{"warnShadowing":true}}"#), vec!["'nCount' in routine Main shadows module PERS 'nCount'"]);
+    }
+
+    #[test]
+    fn lints_are_warnings() {
+        let findings = check("MODULE Cell\n    PROC Main()\n        VAR num nTemp;\n    ENDPROC\nENDMODULE", &ResolveOptions::default());
+        assert_eq!(findings, vec![Finding {
+            severity: Severity::Warning,
+            message: String::from("'nTemp' in routine Main is never used [unused-data]"),
+            start: 44,
+            end: 49,
+        }]);
+    }
+
+    #[test]
+    fn finds_names_in_the_workspace() {
+        let dir = std::env::temp_dir().join(format!("rapid_rust_lsp {}", std::process::id()));
+        fs::create_dir_all(dir.join("SYSMOD")).unwrap();
+        fs::write(dir.join("SYSMOD").join("user.sys"), "MODULE user(SYSMODULE)\n    PERS num nSpeed := 100;\nENDMODULE").unwrap();
+        fs::write(dir.join("Cell.mod"), "MODULE Cell\n    PERS num nCount := 0;\nENDMODULE").unwrap();
+        // The editor has the newer text
+        fs::write(dir.join("Main.mod"), "MODULE Main\nENDMODULE").unwrap();
+        let main = path_to_uri(&dir.join("Main.mod"));
+        assert!(main.contains("/rapid_rust_lsp%20"));
+        assert_eq!(uri_to_path(&main), Some(dir.join("Main.mod")));
+
+        let open = format!(r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"{}","languageId":"rapid","version":1,
+            "text":"MODULE Main\n    PROC Main()\n        nCount := nSpeed;\n    ENDPROC\nENDMODULE"}}}}}}"#, main);
+        let request = |id: usize, method: &str, line: usize, character: usize| format!(
+            r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}},"context":{{"includeDeclaration":false}}}}}}"#,
+            id, method, main, line, character);
+        let (_, replies) = session(&[
+            &format!(r#"{{"jsonrpc":"2.0","id":1,"method":"initialize","params":{{"rootUri":"{}"}}}}"#, path_to_uri(&dir)),
+            &open,
+            &request(2, "textDocument/definition", 2, 10),
+            &request(3, "textDocument/references", 2, 20),
+            &request(4, "textDocument/definition", 2, 19),
+            &request(5, "textDocument/definition", 2, 15),
+            &format!(r#"{{"jsonrpc":"2.0","id":6,"method":"textDocument/rename","params":{{"textDocument":{{"uri":"{}"}},"position":{{"line":2,"character":10}},"newName":"nParts"}}}}"#, main),
+            &format!(r#"{{"jsonrpc":"2.0","id":7,"method":"textDocument/rename","params":{{"textDocument":{{"uri":"{}"}},"position":{{"line":2,"character":10}},"newName":"nSpeed"}}}}"#, main),
+        ]);
+        let result = |idx: usize| replies[idx].get("result").unwrap().to_string();
+        assert_eq!(result(2), format!(r#"{{"uri":"{}","range":{{"start":{{"line":1,"character":13}},"end":{{"line":1,"character":19}}}}}}"#,
+            path_to_uri(&dir.join("Cell.mod"))));
+        assert_eq!(result(3), format!(r#"[{{"uri":"{}","range":{{"start":{{"line":2,"character":18}},"end":{{"line":2,"character":24}}}}}}]"#, main));
+        // System data in a directory of the workspace
+        assert!(result(4).starts_with(&format!(r#"{{"uri":"{}","#, path_to_uri(&dir.join("SYSMOD").join("user.sys")))));
+        assert_eq!(result(5), "null");
+        // Renamed in the file on disk and the open document
+        let changes = replies[6].path(&["result", "changes"]).unwrap();
+        assert_eq!(changes.get(&path_to_uri(&dir.join("Cell.mod"))).unwrap().to_string(),
+            r#"[{"range":{"start":{"line":1,"character":13},"end":{"line":1,"character":19}},"newText":"nParts"}]"#);
+        assert_eq!(changes.get(&main).and_then(Json::as_array).map(<[Json]>::len), Some(1));
+        assert_eq!(replies[7].path(&["error", "code"]).and_then(Json::as_f64), Some(-32803.0));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn completes_names_and_builtins() {
+        let open = r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///Cell.mod","languageId":"rapid","version":1,
+            "text":"MODULE Cell\n    CONST num nMax := 3;\n    PERS num nCount := 0;\n    PROC Main()\n        VAR num nMove;\n        Mo\n    ENDPROC\nENDMODULE"}}}"#;
+        let request = |id: usize, line: usize, character: usize| format!(
+            r#"{{"jsonrpc":"2.0","id":{},"method":"textDocument/completion","params":{{"textDocument":{{"uri":"file:///Cell.mod"}},"position":{{"line":{},"character":{}}}}}}}"#,
+            id, line, character);
+        let (_, replies) = session(&[open, &request(1, 5, 10), &request(2, 5, 9), &request(3, 5, 8)]);
+        let items = |idx: usize| replies[idx].get("result").and_then(Json::as_array).unwrap().to_vec();
+        let labels = |idx: usize| items(idx).iter().map(|item| item.get("label").and_then(Json::as_str).unwrap().to_string()).collect::<Vec<_>>();
+
+        assert_eq!(labels(1), vec!["MoveAbsJ", "MoveC", "MoveJ", "MoveL"]);
+        let move_l = &items(1)[3];
+        assert_eq!(move_l.get("insertText").and_then(Json::as_str), Some("MoveL ${1:ToPoint}, ${2:Speed}, ${3:Zone}, ${4:Tool}"));
+        assert_eq!(move_l.get("detail").and_then(Json::as_str),
+            Some("PROC MoveL(robtarget ToPoint, speeddata Speed, \\num V | \\num T, zonedata Zone, \\num Z, tooldata Tool, \\wobjdata WObj)"));
+
+        let names = labels(3);
+        assert_eq!(&names[..4], &["nMove", "nMax", "nCount", "Main"]);
+        assert_eq!(items(3)[0].get("detail").and_then(Json::as_str), Some("VAR num nMove"));
+        assert_eq!(items(3)[1].get("kind").and_then(Json::as_f64), Some(21.0));
+        let abs = items(3).into_iter().find(|item| item.get("label") == Some(&Json::str("Abs"))).unwrap();
+        assert_eq!(abs.get("insertText").and_then(Json::as_str), Some("Abs(${1:Input})"));
+        assert!(labels(2).contains(&String::from("Min")));
+    }
+
+    #[test]
+    fn hovers_declarations() {
+        let open = r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///Cell.mod","languageId":"rapid","version":1,
+            "text":"MODULE Cell\n    LOCAL CONST num nMax{2} := [1, 2];\n    PROC Pick(INOUT num nCount, \\switch Fast)\n        VAR string sPart := \"A;B\";\n        nCount := nMax{1};\n        Pick nCount;\n    ENDPROC\n    ALIAS num level;\n    VAR level nLevel := 2;\nENDMODULE"}}}"#;
+        let request = |id: usize, line: usize, character: usize| format!(
+            r#"{{"jsonrpc":"2.0","id":{},"method":"textDocument/hover","params":{{"textDocument":{{"uri":"file:///Cell.mod"}},"position":{{"line":{},"character":{}}}}}}}"#,
+            id, line, character);
+        let (_, replies) = session(&[open, &request(1, 4, 20), &request(2, 4, 9), &request(3, 3, 20), &request(4, 5, 9), &request(5, 6, 5), &request(6, 8, 16)]);
+        let hover = |idx: usize| replies[idx].path(&["result", "contents", "value"]).and_then(Json::as_str).map(String::from);
+        assert_eq!(hover(1).unwrap(), "```rapid\nLOCAL CONST num nMax := [1, 2]\n```\nModule `Cell`, line 2");
+        assert_eq!(hover(2).unwrap(), "```rapid\nINOUT num nCount\n```\nIn `Pick` of module `Cell`, line 3");
+        assert_eq!(hover(3).unwrap(), "```rapid\nVAR string sPart := \"A;B\"\n```\nIn `Pick` of module `Cell`, line 4");
+        assert_eq!(hover(4).unwrap(), "```rapid\nPROC Pick(INOUT num nCount, \\switch Fast)\n```\nModule `Cell`, line 3");
+        assert_eq!(replies[5].get("result"), Some(&Json::Null));
+        assert_eq!(hover(6).unwrap(), "```rapid\nVAR level nLevel := 2\n```\nModule `Cell`, line 9\n\n`level` is an alias of `num`");
+    }
+
+    #[test]
+    fn outlines_documents() {
+        let (_, replies) = session(&[
+            r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///Cell.mod","languageId":"rapid","version":1,
+                "text":"MODULE Cell\n    PROC Main()\n        VAR num i;\n    ENDPROC\nENDMODULE"}}}"#,
+            r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/documentSymbol","params":{"textDocument":{"uri":"file:///Cell.mod"}}}"#,
+        ]);
+        let result = replies[1].get("result").unwrap();
+        assert_eq!(result.to_string(), concat!(
+            r#"[{"name":"Cell","detail":"","kind":2,"range":{"start":{"line":0,"character":0},"end":{"line":4,"character":9}},"#,
+            r#""selectionRange":{"start":{"line":0,"character":7},"end":{"line":0,"character":11}},"children":["#,
+            r#"{"name":"Main","detail":"PROC Main()","kind":12,"range":{"start":{"line":1,"character":4},"end":{"line":3,"character":11}},"#,
+            r#""selectionRange":{"start":{"line":1,"character":9},"end":{"line":1,"character":13}},"children":["#,
+            r#"{"name":"i","detail":"num","kind":13,"range":{"start":{"line":2,"character":8},"end":{"line":2,"character":18}},"#,
+            r#""selectionRange":{"start":{"line":2,"character":16},"end":{"line":2,"character":17}},"children":[]}]}]}]"#));
+    }
+}
@@ -1,30 +1,896 @@
-use rapid_rust;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::Duration;
 
-mod lexer;
-mod parser;
+use rapid_rust::error::Error;
+use rapid_rust::json::{Json, ToJson};
+use rapid_rust::lexer::Span;
+use rapid_rust::{backup, cache, calibration, compiler, config, formatter, graph, highlight, host, interpreter, lexer, linter, metrics, parser, resolver, sarif, scheduler, server, transpile, watch};
 
-fn main() {
-    println!("Hello, world!");
-    let tokens = lexer::parse("\
-MOD Testmodule 
-    PROC rTest() 
-        VAR num nTest1:=0; 
-        nTest1:= 2 + 2 * 3 *4 + 1;
-        TpWrite nTest1;
-    ENDPROC 
-ENDMOD");
-    match parser::parse_tokens(tokens) {
-        Err(err) => println!("Error: {}", err),
-        _ => ()
+const USAGE: &str = "\
+Usage: rapid-rust <command> [options] <file>
+
+The file is a module file, the .pgf file of a program or the directory of
+the program, which adds the system modules (.sys) in it. The file - reads
+the module from standard input. Files that aren't UTF-8 are read as Latin-1.
+check, lint and run also take a controller backup, the directory with RAPID
+and SYSPAR in it or a Pack&Go or zip archive of one, with every task and
+the signals of its EIO.cfg.
+
+Commands:
+    lex <file>                      Print the tokens of a module file
+    parse [--ast] [--format json] <file>
+                                    Check the syntax, --ast prints the modules,
+                                    --format json prints them with spans as JSON
+    check [--watch] [--jobs <n>] [--format sarif] [--message-format json] <file>...
+                                    Print the diagnostics, fails on errors.
+                                    Of many files or a pattern with * and ?
+                                    it adds a report of the errors of each,
+                                    checking n files at a time. --format
+                                    sarif prints them as a SARIF log,
+                                    --message-format json as a line of JSON
+                                    each
+    run [--entry <routine>] [--trajectory <file>] [--max-depth <n>] [--watch] <file>
+                                    Run a routine, main unless given.
+                                    --trajectory writes the moves of the
+                                    robot to a .csv or .json file,
+                                    --max-depth stops calls nested deeper
+                                    than n routines, 250 unless given
+    lint [--config <file>] [--format sarif] [--message-format json] <file>
+                                    Print the lints, fails on errors. The
+                                    config sets the level and options of rules
+    fmt [--check] <file>            Print the module file formatted, --check
+                                    fails if formatting would change it
+    highlight [--html] <file>       Print the module file in colors, or as
+                                    HTML with a span for each token
+    graph [--modules] [--builtins] <file>
+                                    Print the call graph in the DOT language
+                                    of Graphviz, --modules the modules that
+                                    use each other, --builtins adds the
+                                    built-in routines called
+    metrics [--format json] <file>  Print the lines, statements, declarations,
+                                    Move instructions and deepest nesting of
+                                    the modules and routines
+    deps [--format json] <file>     Print the modules that use the routines or
+                                    data of other modules, with what they use
+    transpile [--target st] <file>  Print the routines as Python functions,
+                                    with stubs of the motion and I/O
+                                    instructions for a simulation, or
+                                    their logic as Structured Text of
+                                    IEC 61131-3 for a PLC
+    serve [--address <host:port>]   Answer POST requests to /check, /parse
+                                    and /run with JSON, on 127.0.0.1:8080
+                                    unless given
+
+Options:
+    --eio <file>  Define the signals of an EIO.cfg for the commands that
+                  resolve the program, others are unknown
+    --cache <dir> Keep the modules parsed from the files in a directory,
+                  a file that didn't change isn't parsed again
+    --calibration <file>
+                  Set the tooldata, wobjdata and other data of the program
+                  to the values of a YAML or JSON file before checking or
+                  running it
+    --strict      Report what the controller doesn't load as errors: names
+                  over 32 and strings over 80 characters, reserved words as
+                  names and routines with too many parameters
+    --warn-shadowing
+                  Warn when routine data shadows a PERS or CONST of its
+                  module
+    --watch       Check or run again whenever the files of the program change
+    --quiet       Print only errors and what the program writes
+    --verbose     Print the tokens and routines found while parsing";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Lex,
+    Parse,
+    Check,
+    Run,
+    Lint,
+    Fmt,
+    Highlight,
+    Graph,
+    Metrics,
+    Deps,
+    Transpile,
+    Serve,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+/// Command line of the binary
+#[derive(Debug, Clone, PartialEq)]
+struct Cli {
+    command: Command,
+    // Files to check, or the one file of the other commands
+    files: Vec<String>,
+    // Print the parsed modules
+    ast: bool,
+    // Print the parsed modules, metrics or dependencies as JSON
+    json: bool,
+    // Print the diagnostics and lints as a SARIF log
+    sarif: bool,
+    // Print each diagnostic and lint as a line of JSON
+    message_json: bool,
+    // Only check that the file is formatted
+    check: bool,
+    // Highlight as HTML instead of in terminal colors
+    html: bool,
+    // Graph of the modules instead of the routines
+    modules: bool,
+    // Built-in routines in the call graph
+    builtins: bool,
+    // Transpile to Structured Text instead of Python
+    structured_text: bool,
+    // Lint configuration of the project
+    config: Option<String>,
+    // I/O configuration of the cell with the signals
+    eio: Option<String>,
+    // Directory of the modules parsed before
+    cache: Option<String>,
+    // Values of the data of the cell
+    calibration: Option<String>,
+    // Hold the program to the limits of the controller
+    strict: bool,
+    // Warn about routine data that shadows module data
+    warn_shadowing: bool,
+    entry: String,
+    // File to write the moves of a run to
+    trajectory: Option<String>,
+    // Nesting of routine calls a run stops at
+    max_depth: usize,
+    // Address the service listens on
+    address: String,
+    // Run the command again when the files change
+    watch: bool,
+    // Files checked at a time
+    jobs: usize,
+    verbosity: Verbosity,
+}
+
+impl Cli {
+    /// Read the arguments after the name of the binary
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, String> {
+        let mut args = args.into_iter();
+        let command = match args.next().as_deref() {
+            Some("lex") => Command::Lex,
+            Some("parse") => Command::Parse,
+            Some("check") => Command::Check,
+            Some("run") => Command::Run,
+            Some("lint") => Command::Lint,
+            Some("fmt") => Command::Fmt,
+            Some("highlight") => Command::Highlight,
+            Some("graph") => Command::Graph,
+            Some("metrics") => Command::Metrics,
+            Some("deps") => Command::Deps,
+            Some("transpile") => Command::Transpile,
+            Some("serve") => Command::Serve,
+            Some(command) => return Err(format!("Unknown command {}", command)),
+            None => return Err(String::from("Missing command")),
+        };
+        let mut cli = Cli { command, files: Vec::new(), ast: false, json: false, sarif: false, message_json: false, check: false, html: false, modules: false,
+            builtins: false, structured_text: false, config: None, eio: None, cache: None, calibration: None, strict: false, warn_shadowing: false,
+            entry: String::from("main"), trajectory: None, max_depth: interpreter::InterpreterOptions::default().max_depth, address: String::from("127.0.0.1:8080"), watch: false, jobs: 1,
+            verbosity: Verbosity::Normal };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--ast" if command == Command::Parse => cli.ast = true,
+                "--format" if matches!(command, Command::Parse | Command::Metrics | Command::Deps | Command::Check | Command::Lint) => match args.next().as_deref() {
+                    Some("json") if matches!(command, Command::Parse | Command::Metrics | Command::Deps) => cli.json = true,
+                    Some("sarif") if matches!(command, Command::Check | Command::Lint) => cli.sarif = true,
+                    Some(format) => return Err(format!("Unknown format {}", format)),
+                    None => return Err(String::from("Missing format after --format")),
+                },
+                "--message-format" if matches!(command, Command::Check | Command::Lint) => match args.next().as_deref() {
+                    Some("json") => cli.message_json = true,
+                    Some("human") => cli.message_json = false,
+                    Some(format) => return Err(format!("Unknown message format {}", format)),
+                    None => return Err(String::from("Missing format after --message-format")),
+                },
+                "--check" if command == Command::Fmt => cli.check = true,
+                "--html" if command == Command::Highlight => cli.html = true,
+                "--modules" if command == Command::Graph => cli.modules = true,
+                "--builtins" if command == Command::Graph => cli.builtins = true,
+                "--target" if command == Command::Transpile => match args.next().as_deref() {
+                    Some("python") => cli.structured_text = false,
+                    Some("st") => cli.structured_text = true,
+                    Some(target) => return Err(format!("Unknown target {}", target)),
+                    None => return Err(String::from("Missing target after --target")),
+                },
+                "--config" if command == Command::Lint => match args.next() {
+                    Some(config) => cli.config = Some(config),
+                    None => return Err(String::from("Missing file after --config")),
+                },
+                "--eio" if !matches!(command, Command::Lex | Command::Parse | Command::Fmt | Command::Highlight | Command::Transpile | Command::Serve) => match args.next() {
+                    Some(eio) => cli.eio = Some(eio),
+                    None => return Err(String::from("Missing file after --eio")),
+                },
+                "--strict" if !matches!(command, Command::Lex | Command::Parse | Command::Fmt | Command::Highlight | Command::Transpile | Command::Serve) => cli.strict = true,
+                "--warn-shadowing" if !matches!(command, Command::Lex | Command::Parse | Command::Fmt | Command::Highlight | Command::Transpile | Command::Serve) => cli.warn_shadowing = true,
+                "--cache" if !matches!(command, Command::Lex | Command::Fmt | Command::Highlight | Command::Serve) => match args.next() {
+                    Some(cache) => cli.cache = Some(cache),
+                    None => return Err(String::from("Missing directory after --cache")),
+                },
+                "--calibration" if matches!(command, Command::Check | Command::Run) => match args.next() {
+                    Some(calibration) => cli.calibration = Some(calibration),
+                    None => return Err(String::from("Missing file after --calibration")),
+                },
+                "--entry" if command == Command::Run => match args.next() {
+                    Some(entry) => cli.entry = entry,
+                    None => return Err(String::from("Missing routine after --entry")),
+                },
+                "--trajectory" if command == Command::Run => match args.next() {
+                    Some(trajectory) => cli.trajectory = Some(trajectory),
+                    None => return Err(String::from("Missing file after --trajectory")),
+                },
+                "--max-depth" if command == Command::Run => match args.next().map(|depth| depth.parse::<usize>()) {
+                    Some(Ok(depth)) if depth > 0 => cli.max_depth = depth,
+                    Some(_) => return Err(String::from("Invalid maximum depth")),
+                    None => return Err(String::from("Missing number after --max-depth")),
+                },
+                "--address" if command == Command::Serve => match args.next() {
+                    Some(address) => cli.address = address,
+                    None => return Err(String::from("Missing address after --address")),
+                },
+                "--watch" if matches!(command, Command::Check | Command::Run) => cli.watch = true,
+                "--jobs" if command == Command::Check => match args.next().map(|jobs| jobs.parse::<usize>()) {
+                    Some(Ok(jobs)) if jobs > 0 => cli.jobs = jobs,
+                    Some(_) => return Err(String::from("Invalid number of jobs")),
+                    None => return Err(String::from("Missing number after --jobs")),
+                },
+                "--quiet" => cli.verbosity = Verbosity::Quiet,
+                "--verbose" => cli.verbosity = Verbosity::Verbose,
+                option if option.starts_with("--") => return Err(format!("Unknown option {}", option)),
+                _ if command == Command::Serve || (!cli.files.is_empty() && (command != Command::Check || cli.watch)) => return Err(format!("Unexpected argument {}", arg)),
+                _ => cli.files.push(arg),
+            }
+        }
+        if cli.files.is_empty() && command != Command::Serve {
+            return Err(String::from("Missing file"));
+        }
+        if cli.watch && cli.files.len() > 1 {
+            return Err(String::from("Only one file can be watched"));
+        }
+        if cli.watch && cli.sarif {
+            return Err(String::from("A SARIF log can't be watched"));
+        }
+        if cli.watch && cli.message_json {
+            return Err(String::from("JSON messages can't be watched"));
+        }
+        if cli.watch && cli.file() == "-" {
+            return Err(String::from("Standard input can't be watched"));
+        }
+        Ok(cli)
+    }
+
+    /// File of the commands that take one
+    fn file(&self) -> &str {
+        &self.files[0]
+    }
+
+    fn run(&self) -> Result<(), String> {
+        lexer::set_trace(self.verbosity == Verbosity::Verbose);
+        if self.command == Command::Serve {
+            let server = server::Server::bind(&self.address, server::ServerOptions::default())?;
+            if self.verbosity != Verbosity::Quiet {
+                println!("Listening on http://{}", server.local_addr()?);
+            }
+            return server.run();
+        }
+        if self.command == Command::Check && !self.watch {
+            let mut files = Vec::new();
+            for pattern in self.files.iter() {
+                files.extend(expand(pattern)?);
+            }
+            if files.len() > 1 || self.sarif || self.message_json {
+                return self.check_all(&files);
+            }
+        }
+        if self.command == Command::Lint && (self.sarif || self.message_json) {
+            let linter = self.linter()?;
+            let report = self.check_file(self.file(), Some(&linter), &self.signals()?);
+            return self.print_findings(&[report], &linter.rules());
+        }
+        let path = Path::new(self.file());
+        if backup::is_backup(path) {
+            return self.run_backup(path);
+        }
+        if self.command == Command::Lex {
+            let tokens = lexer::parse_file(path).map_err(|err| format!("{}: {}", self.file(), err))?;
+            for token in tokens.iter() {
+                println!("{}: {:?}", token.span, token.token_type);
+            }
+            return Ok(());
+        }
+        if self.command == Command::Fmt {
+            let source = lexer::read_source(path).map_err(|err| format!("{}: {}", self.file(), err))?;
+            let formatted = formatter::format(&source, &formatter::FormatOptions::default())
+                .map_err(|err| format!("{}: {}", self.file(), err))?;
+            if !self.check {
+                print!("{}", formatted);
+            } else if formatted != source {
+                return Err(format!("{}: not formatted", self.file()));
+            }
+            return Ok(());
+        }
+        if self.command == Command::Highlight {
+            let source = lexer::read_source(path).map_err(|err| format!("{}: {}", self.file(), err))?;
+            let highlighted = if self.html { highlight::html(&source) } else { highlight::ansi(&source) };
+            print!("{}", highlighted.map_err(|err| format!("{}: {}", self.file(), err))?);
+            return Ok(());
+        }
+
+        let mut program = self.load_program(path)?;
+        if self.command == Command::Parse {
+            if self.json {
+                let modules = program.modules.iter().map(ToJson::to_json).collect();
+                println!("{}", Json::object(vec![("modules", Json::Array(modules))]));
+            } else if self.ast {
+                println!("{:#?}", program.modules);
+            } else if self.verbosity != Verbosity::Quiet {
+                println!("{}: {} modules", self.file(), program.modules.len());
+            }
+            return Ok(());
+        }
+        if self.command == Command::Transpile {
+            if self.structured_text {
+                print!("{}", transpile::structured_text(&program));
+            } else {
+                print!("{}", transpile::python(&program));
+            }
+            return Ok(());
+        }
+
+        self.calibrate(vec![&mut program])?;
+        let io = config::io_board(&self.signals()?);
+        io.declare(&mut program);
+        let options = self.resolve_options();
+        let diagnostics = resolver::resolve(&mut program, &options);
+        let failed = diagnostics.is_err();
+        let diagnostics = diagnostics.unwrap_or_else(|errors| errors);
+        for diagnostic in diagnostics.iter() {
+            // Graphs, metrics and dependencies alone go to standard output, for other tools
+            if failed || (self.verbosity != Verbosity::Quiet && !matches!(self.command, Command::Graph | Command::Metrics | Command::Deps)) {
+                println!("{}: {}", self.file(), diagnostic);
+            }
+        }
+        if failed {
+            let errors = diagnostics.iter().filter(|diagnostic| diagnostic.severity == resolver::Severity::Error).count();
+            return Err(format!("{}: {} errors", self.file(), errors));
+        }
+        if self.command == Command::Check {
+            return Ok(());
+        }
+        if self.command == Command::Lint {
+            return self.lint(&program);
+        }
+        if self.command == Command::Graph {
+            if self.modules {
+                print!("{}", graph::module_graph(&program));
+            } else {
+                print!("{}", graph::call_graph(&program, &graph::GraphOptions { builtins: self.builtins }));
+            }
+            return Ok(());
+        }
+        if self.command == Command::Metrics {
+            let modules = metrics::measure(&program);
+            if self.json {
+                println!("{}", Json::object(vec![("modules", Json::Array(modules.iter().map(ToJson::to_json).collect()))]));
+            } else {
+                print!("{}", metrics::table(&modules));
+            }
+            return Ok(());
+        }
+
+        if self.command == Command::Deps {
+            if self.json {
+                let dependencies = graph::dependencies(&program).iter().map(|dependency| dependency.to_json(&program)).collect();
+                println!("{}", Json::object(vec![("dependencies", Json::Array(dependencies))]));
+            } else {
+                print!("{}", graph::dependency_list(&program));
+            }
+            return Ok(());
+        }
+
+        let mut output = host::Stdout;
+        let mut input = host::Stdin;
+        let mut host = host::Host::new(&mut output).with_input(&mut input).with_io(&io);
+        let mut result = interpreter::run(&mut program, &self.entry, &self.interpreter_options(), &mut host);
+        let location = result.location.take();
+        let result = result.into_result()
+            .map(|_| ())
+            .map_err(|err| match location {
+                Some(location) => format!("{}: {} (in {}.{})", self.file(), err, location.module, location.routine),
+                None => format!("{}: {}", self.file(), err),
+            });
+        let written = self.write_trajectory(&host.trajectory);
+        result.and(written)
+    }
+}
+
+impl Cli {
+    fn lint(&self, program: &parser::Program) -> Result<(), String> {
+        let lints = self.linter()?.check(program);
+        for lint in lints.iter() {
+            println!("{}: {}", self.file(), lint);
+        }
+        let errors = lints.iter().filter(|lint| lint.severity == resolver::Severity::Error).count();
+        if errors > 0 {
+            return Err(format!("{}: {} errors", self.file(), errors));
+        }
+        Ok(())
+    }
+
+    /// Program of a path, with the modules of the --cache directory
+    fn load_program(&self, path: &Path) -> Result<parser::Program, String> {
+        self.parse_program(path).map_err(|err| err.to_string())
+    }
+
+    fn parse_program(&self, path: &Path) -> Result<parser::Program, Error> {
+        match &self.cache {
+            Some(dir) => cache::ParseCache::new(dir).program(path),
+            None => parser::Program::from_path(path),
+        }
+    }
+
+    fn load_backup(&self, path: &Path) -> Result<backup::Backup, String> {
+        match &self.cache {
+            Some(dir) => backup::load_cached(path, &cache::ParseCache::new(dir)),
+            None => backup::load(path),
+        }
+    }
+
+    /// Signals of the EIO.cfg, none without one
+    fn signals(&self) -> Result<Vec<config::SignalConfig>, String> {
+        match &self.eio {
+            Some(eio) => config::Config::from_path(Path::new(eio))
+                .and_then(|config| config::signals(&config).map_err(|err| format!("{}: {}", eio, err))),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Set the data of the programs to the values of the --calibration file.
+    /// Each name has to be declared by one of the programs, or be given a
+    /// type to be declared with.
+    fn calibrate(&self, programs: Vec<&mut parser::Program>) -> Result<(), String> {
+        let path = match &self.calibration {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let calibration = calibration::Calibration::from_path(Path::new(path))?;
+        let mut missing: Option<Vec<String>> = None;
+        for program in programs {
+            let names = calibration.apply(program).map_err(|err| format!("{}: {}", path, err))?;
+            missing = Some(match missing {
+                Some(missing) => missing.into_iter().filter(|name| names.contains(name)).collect(),
+                None => names,
+            });
+        }
+        match missing.filter(|names| !names.is_empty()) {
+            Some(names) => Err(format!("{}: Unknown data {}", path, names.join(", "))),
+            None => Ok(()),
+        }
+    }
+
+    fn resolve_options(&self) -> resolver::ResolveOptions {
+        resolver::ResolveOptions { warn_shadowing: self.warn_shadowing, controller_limits: self.strict }
+    }
+
+    fn interpreter_options(&self) -> interpreter::InterpreterOptions {
+        interpreter::InterpreterOptions { max_depth: self.max_depth, ..interpreter::InterpreterOptions::default() }
+    }
+
+    /// Linter with the rules of the configuration
+    fn linter(&self) -> Result<linter::Linter, String> {
+        let mut linter = linter::Linter::new();
+        if let Some(config) = &self.config {
+            let text = std::fs::read_to_string(config).map_err(|err| format!("{}: {}", config, err))?;
+            linter::LintConfig::parse(&text)
+                .and_then(|config| linter.configure(&config))
+                .map_err(|err| format!("{}: {}", config, err))?;
+        }
+        Ok(linter)
+    }
+}
+
+// ------------------ Batch check -----------------------/
+
+/// Diagnostics of one file of a batch check
+struct Report {
+    file: String,
+    // Lines to print, in the order found
+    output: Vec<String>,
+    findings: Vec<sarif::Finding>,
+    errors: usize,
+    warnings: usize,
+}
+
+impl Cli {
+    /// Check the files, printing the diagnostics of each and then a table of
+    /// the errors and warnings per file, or the findings of all for tools.
+    /// Fails if any file has errors.
+    fn check_all(&self, files: &[String]) -> Result<(), String> {
+        let signals = &self.signals()?;
+        let reports: Vec<Report> = if self.jobs > 1 && files.len() > 1 {
+            let chunk = files.len().div_ceil(self.jobs);
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = files.chunks(chunk)
+                    .map(|files| scope.spawn(move || files.iter().map(|file| self.check_file(file, None, signals)).collect::<Vec<_>>()))
+                    .collect();
+                handles.into_iter().flat_map(|handle| handle.join().expect("check panicked")).collect()
+            })
+        } else {
+            files.iter().map(|file| self.check_file(file, None, signals)).collect()
+        };
+        if self.sarif || self.message_json {
+            return self.print_findings(&reports, &[]);
+        }
+
+        for report in reports.iter() {
+            for line in report.output.iter() {
+                println!("{}", line);
+            }
+        }
+        let width = reports.iter().map(|report| report.file.chars().count()).chain(std::iter::once(4)).max().unwrap_or(0);
+        println!("\n{:<width$}  {:>6}  {:>8}", "File", "Errors", "Warnings", width = width);
+        for report in reports.iter() {
+            println!("{:<width$}  {:>6}  {:>8}", report.file, report.errors, report.warnings, width = width);
+        }
+        let errors: usize = reports.iter().map(|report| report.errors).sum();
+        let warnings: usize = reports.iter().map(|report| report.warnings).sum();
+        let failed = reports.iter().filter(|report| report.errors > 0).count();
+        println!("{} files, {} errors, {} warnings", reports.len(), errors, warnings);
+        if failed > 0 {
+            return Err(format!("{} errors in {} of {} files", errors, failed, reports.len()));
+        }
+        Ok(())
+    }
+
+    /// Parse and resolve a file with the signals, as check does, and lint it
+    /// if it resolves
+    fn check_file(&self, file: &str, linter: Option<&linter::Linter>, signals: &[config::SignalConfig]) -> Report {
+        let mut report = Report { file: file.to_string(), output: Vec::new(), findings: Vec::new(), errors: 0, warnings: 0 };
+        if backup::is_backup(Path::new(file)) {
+            let loaded = self.load_backup(Path::new(file))
+                .and_then(|mut backup| {
+                    self.calibrate(backup.tasks.iter_mut().map(|task| &mut task.program).collect())?;
+                    Ok((self.backup_signals(&backup, signals)?, backup))
+                });
+            match loaded {
+                Ok((signals, backup)) => for task in backup.tasks {
+                    self.check_program(task.program, &format!("{}: {}", file, task.name), linter, &signals, &mut report);
+                },
+                Err(err) => report.load_error(err, None),
+            }
+            return report;
+        }
+        let loaded = self.parse_program(Path::new(file))
+            .map_err(|err| (err.to_string(), err.span()))
+            .and_then(|mut program| self.calibrate(vec![&mut program]).map(|_| program).map_err(|err| (err, None)));
+        match loaded {
+            Ok(program) => self.check_program(program, file, linter, signals, &mut report),
+            Err((err, span)) => report.load_error(err, span),
+        }
+        report
+    }
+
+    /// Resolve a program and lint it if it resolves, the lines of the
+    /// report start with the label
+    fn check_program(&self, mut program: parser::Program, label: &str, linter: Option<&linter::Linter>, signals: &[config::SignalConfig], report: &mut Report) {
+        config::io_board(signals).declare(&mut program);
+        let options = self.resolve_options();
+        let diagnostics = resolver::resolve(&mut program, &options);
+        let failed = diagnostics.is_err();
+        let diagnostics = diagnostics.unwrap_or_else(|errors| errors);
+        for diagnostic in diagnostics.iter() {
+            report.add(diagnostic.severity, format!("{}: {}", label, diagnostic), self.verbosity);
+            report.findings.push(sarif::Finding::diagnostic(&report.file, diagnostic));
+        }
+        if let Some(linter) = linter.filter(|_| !failed) {
+            for lint in linter.check(&program).iter() {
+                report.add(lint.severity, format!("{}: {}", label, lint), self.verbosity);
+                report.findings.push(sarif::Finding::lint(&report.file, lint));
+            }
+        }
+    }
+
+    /// Signals of --eio, or of the EIO.cfg of the backup without it
+    fn backup_signals(&self, backup: &backup::Backup, signals: &[config::SignalConfig]) -> Result<Vec<config::SignalConfig>, String> {
+        match &self.eio {
+            Some(_) => Ok(signals.to_vec()),
+            None => backup.signals().map_err(|err| format!("{}: {}", self.file(), err)),
+        }
+    }
+
+    /// Check, lint or run every task of a backup. The tasks run together,
+    /// taking turns as the controller does.
+    fn run_backup(&self, path: &Path) -> Result<(), String> {
+        if matches!(self.command, Command::Check | Command::Lint) {
+            let linter = if self.command == Command::Lint { Some(self.linter()?) } else { None };
+            let report = self.check_file(self.file(), linter.as_ref(), &self.signals()?);
+            for line in report.output.iter() {
+                println!("{}", line);
+            }
+            if report.errors > 0 {
+                return Err(format!("{}: {} errors", self.file(), report.errors));
+            }
+            return Ok(());
+        }
+        if self.command != Command::Run {
+            return Err(format!("{}: a backup can only be checked, linted or run", self.file()));
+        }
+
+        let mut backup = self.load_backup(path)?;
+        self.calibrate(backup.tasks.iter_mut().map(|task| &mut task.program).collect())?;
+        let io = config::io_board(&self.backup_signals(&backup, &self.signals()?)?);
+        let mut tasks = Vec::new();
+        for task in backup.tasks {
+            let label = format!("{}: {}", self.file(), task.name);
+            let mut program = task.program;
+            io.declare(&mut program);
+            let options = self.resolve_options();
+            if let Err(diagnostics) = resolver::resolve(&mut program, &options) {
+                for diagnostic in diagnostics.iter() {
+                    println!("{}: {}", label, diagnostic);
+                }
+                let errors = diagnostics.iter().filter(|diagnostic| diagnostic.severity == resolver::Severity::Error).count();
+                return Err(format!("{}: {} errors", label, errors));
+            }
+            let bytecode = compiler::compile(&program).map_err(|err| format!("{}: {}", label, err))?;
+            tasks.push(scheduler::Task::new(&task.name, program, bytecode));
+        }
+        let mut output = host::Stdout;
+        let mut input = host::Stdin;
+        let mut host = host::Host::new(&mut output).with_input(&mut input).with_io(&io);
+        let result = scheduler::run_tasks(&mut tasks, &self.entry, &self.interpreter_options(), &mut host)
+            .map_err(|err| format!("{}: {}", self.file(), err));
+        let written = self.write_trajectory(&host.trajectory);
+        result.and(written)
+    }
+
+    /// Write the moves of a run to the --trajectory file, also when the run
+    /// stopped with an error. A .json file gets them as JSON, others as CSV.
+    fn write_trajectory(&self, trajectory: &[host::Waypoint]) -> Result<(), String> {
+        let path = match &self.trajectory {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let text = if path.to_ascii_lowercase().ends_with(".json") {
+            format!("{}\n", Json::object(vec![("moves", Json::Array(trajectory.iter().map(ToJson::to_json).collect()))]))
+        } else {
+            host::trajectory_csv(trajectory)
+        };
+        fs::write(path, text).map_err(|err| format!("{}: {}", path, err))
+    }
+}
+
+impl Report {
+    /// Count a file that can't be read or parsed as an error, at the span
+    /// of the lexer or the parser error
+    fn load_error(&mut self, err: String, span: Option<Span>) {
+        self.findings.push(sarif::Finding::load_error(&err, span));
+        self.output.push(err);
+        self.errors += 1;
+    }
+
+    /// Count a diagnostic, and print it unless quiet and not an error
+    fn add(&mut self, severity: resolver::Severity, line: String, verbosity: Verbosity) {
+        match severity {
+            resolver::Severity::Error => self.errors += 1,
+            resolver::Severity::Warning => self.warnings += 1,
+        }
+        if severity == resolver::Severity::Error || verbosity != Verbosity::Quiet {
+            self.output.push(line);
+        }
+    }
+}
+
+impl Cli {
+    /// Print the findings of the files as a SARIF log or a line of JSON
+    /// each, fails if any are errors
+    fn print_findings(&self, reports: &[Report], lint_rules: &[(&str, &str)]) -> Result<(), String> {
+        let findings: Vec<sarif::Finding> = reports.iter().flat_map(|report| report.findings.iter().cloned()).collect();
+        if self.sarif {
+            println!("{}", sarif::log(&findings, lint_rules));
+        } else {
+            for finding in findings.iter() {
+                println!("{}", finding.to_json());
+            }
+        }
+        let errors: usize = reports.iter().map(|report| report.errors).sum();
+        if errors > 0 {
+            return Err(format!("{} errors", errors));
+        }
+        Ok(())
+    }
+}
+
+/// Files a pattern with `*` or `?` in its last part matches, sorted, or the
+/// file itself
+fn expand(pattern: &str) -> Result<Vec<String>, String> {
+    let path = Path::new(pattern);
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) if name.contains(['*', '?']) => name,
+        _ => return Ok(vec![pattern.to_string()]),
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let entries = std::fs::read_dir(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+    let mut files: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_str().is_some_and(|file| matches(name, file)))
+        .map(|entry| match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.join(entry.file_name()).display().to_string(),
+            _ => entry.file_name().to_string_lossy().into_owned(),
+        })
+        .collect();
+    if files.is_empty() {
+        return Err(format!("{}: no files match", pattern));
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Whether a name matches a pattern, where `*` is any text and `?` any
+/// character
+fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Where to go on after the last star, to let it match one more character
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            star = Some((star_p, star_n + 1));
+            p = star_p + 1;
+            n = star_n + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn main() -> ExitCode {
+    let cli = match Cli::parse(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return ExitCode::from(2);
+        },
+    };
+    if cli.watch {
+        watch(&cli);
+    }
+    match cli.run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        },
+    }
+}
+
+/// Run the command on every change of the files, until interrupted
+fn watch(cli: &Cli) -> ! {
+    let mut watcher = watch::Watcher::new(Path::new(cli.file()));
+    loop {
+        match cli.run() {
+            Ok(()) if cli.command == Command::Check => println!("{}: no errors", cli.file()),
+            Ok(()) => (),
+            Err(err) => eprintln!("{}", err),
+        }
+        let changed = watcher.wait(Duration::from_millis(300));
+        let names: Vec<String> = changed.iter().map(|path| path.display().to_string()).collect();
+        println!("\n{} changed", names.join(", "));
     }
 }
 
 #[cfg(test)]
-mod test{
+mod test {
     use super::*;
 
+    fn cli(args: &str) -> Result<Cli, String> {
+        Cli::parse(args.split_whitespace().map(String::from))
+    }
+
     #[test]
     fn my_test() {
-        
+
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn reads_the_command_line() {
+        let run = cli("run --entry rCycle --quiet CELL.MOD").unwrap();
+        assert_eq!(run, Cli { command: Command::Run, files: vec![String::from("CELL.MOD")], ast: false,
+            json: false, sarif: false, message_json: false, check: false, html: false, modules: false, builtins: false, structured_text: false, config: None,
+            eio: None, cache: None, calibration: None, strict: false, warn_shadowing: false, entry: String::from("rCycle"), trajectory: None, max_depth: 250, address: String::from("127.0.0.1:8080"),
+            watch: false, jobs: 1, verbosity: Verbosity::Quiet });
+        assert!(cli("parse --ast --verbose CELL.MOD").unwrap().ast);
+        assert!(cli("parse --format json CELL.MOD").unwrap().json);
+        assert_eq!(cli("parse --format xml CELL.MOD").unwrap_err(), "Unknown format xml");
+        assert!(cli("metrics --format json CELL.MOD").unwrap().json);
+        assert_eq!(cli("deps --format json CELL.MOD").unwrap().command, Command::Deps);
+        assert_eq!(cli("transpile CELL.MOD").unwrap().command, Command::Transpile);
+        assert!(cli("transpile --target st CELL.MOD").unwrap().structured_text);
+        assert_eq!(cli("transpile --target c CELL.MOD").unwrap_err(), "Unknown target c");
+        assert_eq!(cli("check CELL.MOD").unwrap().entry, "main");
+        assert!(cli("fmt --check CELL.MOD").unwrap().check);
+        assert!(cli("highlight --html CELL.MOD").unwrap().html);
+        assert!(cli("check --watch CELL.MOD").unwrap().watch);
+        assert!(cli("lint --strict CELL.MOD").unwrap().strict);
+        assert!(cli("check --warn-shadowing CELL.MOD").unwrap().warn_shadowing);
+        assert_eq!(cli("fmt --strict CELL.MOD").unwrap_err(), "Unknown option --strict");
+        assert_eq!(cli("fmt --watch CELL.MOD").unwrap_err(), "Unknown option --watch");
+        assert_eq!(cli("run --watch -").unwrap_err(), "Standard input can't be watched");
+        let graph = cli("graph --modules --builtins CELL.MOD").unwrap();
+        assert!(graph.modules && graph.builtins);
+        assert_eq!(cli("lint --config lint.toml CELL.MOD").unwrap().config.as_deref(), Some("lint.toml"));
+
+        assert_eq!(cli("").unwrap_err(), "Missing command");
+        assert_eq!(cli("build CELL.MOD").unwrap_err(), "Unknown command build");
+        assert_eq!(cli("check --ast CELL.MOD").unwrap_err(), "Unknown option --ast");
+        assert_eq!(cli("run --check CELL.MOD").unwrap_err(), "Unknown option --check");
+        assert_eq!(cli("run --entry").unwrap_err(), "Missing routine after --entry");
+        assert_eq!(cli("run --trajectory moves.csv A.MOD").unwrap().trajectory.as_deref(), Some("moves.csv"));
+        assert_eq!(cli("check --trajectory moves.csv A.MOD").unwrap_err(), "Unknown option --trajectory");
+        assert_eq!(cli("run --max-depth 1000 A.MOD").unwrap().max_depth, 1000);
+        assert_eq!(cli("run --max-depth 0 A.MOD").unwrap_err(), "Invalid maximum depth");
+        assert_eq!(cli("check --max-depth 1000 A.MOD").unwrap_err(), "Unknown option --max-depth");
+        assert_eq!(cli("run A.MOD B.MOD").unwrap_err(), "Unexpected argument B.MOD");
+        assert_eq!(cli("lex --quiet").unwrap_err(), "Missing file");
+
+        let check = cli("check --jobs 4 A.MOD B.MOD").unwrap();
+        assert_eq!((check.files.len(), check.jobs), (2, 4));
+        assert_eq!(cli("check --jobs 0 A.MOD").unwrap_err(), "Invalid number of jobs");
+        assert_eq!(cli("check --watch A.MOD B.MOD").unwrap_err(), "Unexpected argument B.MOD");
+        assert_eq!(cli("run --jobs 2 A.MOD").unwrap_err(), "Unknown option --jobs");
+        assert!(cli("check --format sarif A.MOD B.MOD").unwrap().sarif);
+        assert!(cli("lint --format sarif A.MOD").unwrap().sarif);
+        assert_eq!(cli("check --format json A.MOD").unwrap_err(), "Unknown format json");
+        assert_eq!(cli("metrics --format sarif A.MOD").unwrap_err(), "Unknown format sarif");
+        assert!(cli("lint --message-format json A.MOD").unwrap().message_json);
+        assert_eq!(cli("check --message-format xml A.MOD").unwrap_err(), "Unknown message format xml");
+        assert_eq!(cli("run --message-format json A.MOD").unwrap_err(), "Unknown option --message-format");
+        assert_eq!(cli("run --eio EIO.cfg A.MOD").unwrap().eio.as_deref(), Some("EIO.cfg"));
+        assert_eq!(cli("check --eio").unwrap_err(), "Missing file after --eio");
+        assert_eq!(cli("fmt --eio EIO.cfg A.MOD").unwrap_err(), "Unknown option --eio");
+        assert_eq!(cli("check --calibration cell2.yaml A.MOD").unwrap().calibration.as_deref(), Some("cell2.yaml"));
+        assert_eq!(cli("run --calibration").unwrap_err(), "Missing file after --calibration");
+        assert_eq!(cli("lint --calibration cell2.yaml A.MOD").unwrap_err(), "Unknown option --calibration");
+        assert_eq!(cli("lint --cache target/rapid A.MOD").unwrap().cache.as_deref(), Some("target/rapid"));
+        assert_eq!(cli("check --cache").unwrap_err(), "Missing directory after --cache");
+        assert_eq!(cli("fmt --cache target/rapid A.MOD").unwrap_err(), "Unknown option --cache");
+        assert_eq!(cli("serve").unwrap().address, "127.0.0.1:8080");
+        assert_eq!(cli("serve --address 0.0.0.0:9000").unwrap().address, "0.0.0.0:9000");
+        assert_eq!(cli("serve A.MOD").unwrap_err(), "Unexpected argument A.MOD");
+        assert_eq!(cli("run --address 0.0.0.0:9000 A.MOD").unwrap_err(), "Unknown option --address");
+    }
+
+    #[test]
+    fn matches_patterns() {
+        assert!(matches("*.mod", "Cell.mod"));
+        assert!(matches("C?ll*", "Cell.mod"));
+        assert!(matches("*e*.m*d", "Cell.mod"));
+        assert!(!matches("*.mod", "Cell.sys"));
+        assert!(!matches("C?ll", "Cel"));
+        assert_eq!(expand("CELL.MOD").unwrap(), vec![String::from("CELL.MOD")]);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::builtins;
+use crate::json::{Json, ToJson};
+use crate::parser::{Callee, Node, Program, Routine, Statement};
+
+// ------------------ Metrics -----------------------/
+
+/// Size and shape of a routine, or of the routines and data of a module
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    /// Source lines from the header to the end
+    pub lines: usize,
+    /// Statements, those nested in others and of the error handler included
+    pub statements: usize,
+    /// Data declarations, not the parameters
+    pub declarations: usize,
+    /// Calls of the Move instructions
+    pub moves: usize,
+    /// Most compound statements one statement is nested in
+    pub nesting: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleMetrics {
+    pub name: String,
+    pub metrics: Metrics,
+    pub routines: Vec<(String, Metrics)>,
+}
+
+/// Metrics of the modules of a program and their routines. Move
+/// instructions are recognized by the routine a call is bound to, so the
+/// program should be resolved.
+pub fn measure(program: &Program) -> Vec<ModuleMetrics> {
+    program.modules.iter().map(|module| {
+        let routines: Vec<(String, Metrics)> = module.routines.iter()
+            .map(|routine| (routine.name.clone(), routine_metrics(routine)))
+            .collect();
+        let metrics = Metrics {
+            lines: module.end.line + 1 - module.span.line.min(module.end.line),
+            statements: routines.iter().map(|(_, metrics)| metrics.statements).sum(),
+            declarations: module.variables.len() + routines.iter().map(|(_, metrics)| metrics.declarations).sum::<usize>(),
+            moves: routines.iter().map(|(_, metrics)| metrics.moves).sum(),
+            nesting: routines.iter().map(|(_, metrics)| metrics.nesting).max().unwrap_or(0),
+        };
+        ModuleMetrics { name: module.name.clone(), metrics, routines }
+    }).collect()
+}
+
+fn routine_metrics(routine: &Routine) -> Metrics {
+    // The resolver adds the loop variables of FOR after the declared data
+    let body = routine.statements.first()
+        .or_else(|| routine.handler.as_ref().and_then(|handler| handler.first()))
+        .map_or(routine.end.start, |statement| statement.span.start);
+    let mut metrics = Metrics {
+        lines: routine.end.line + 1 - routine.span.line.min(routine.end.line),
+        declarations: routine.variables.iter().filter(|decl| decl.span.start < body).count(),
+        ..Metrics::default()
+    };
+    count(&routine.statements, 0, &mut metrics);
+    if let Some(handler) = &routine.handler {
+        count(handler, 0, &mut metrics);
+    }
+    metrics
+}
+
+fn count(body: &[Statement], depth: usize, metrics: &mut Metrics) {
+    for statement in body {
+        metrics.statements += 1;
+        metrics.nesting = metrics.nesting.max(depth);
+        if let Node::ProcCall { target: Some(Callee::Builtin(idx)), .. } = statement.node {
+            if builtins::builtins()[idx].name.starts_with("Move") {
+                metrics.moves += 1;
+            }
+        }
+        for inner in statement.node.bodies() {
+            count(inner, depth + 1, metrics);
+        }
+    }
+}
+
+// ------------------ Output -----------------------/
+
+/// Table with a row for each module and, indented below it, its routines
+pub fn table(modules: &[ModuleMetrics]) -> String {
+    let mut rows: Vec<(String, &Metrics)> = Vec::new();
+    for module in modules {
+        rows.push((module.name.clone(), &module.metrics));
+        rows.extend(module.routines.iter().map(|(name, metrics)| (format!("  {}", name), metrics)));
+    }
+    let width = rows.iter().map(|(name, _)| name.chars().count()).chain(std::iter::once(6)).max().unwrap_or(0);
+    let mut text = format!("{:<width$}  {:>5}  {:>10}  {:>12}  {:>5}  {:>7}\n", "Module", "Lines", "Statements", "Declarations", "Moves", "Nesting", width = width);
+    for (name, metrics) in rows {
+        text.push_str(&format!("{:<width$}  {:>5}  {:>10}  {:>12}  {:>5}  {:>7}\n", name,
+            metrics.lines, metrics.statements, metrics.declarations, metrics.moves, metrics.nesting, width = width));
+    }
+    text
+}
+
+impl ToJson for Metrics {
+    fn to_json(&self) -> Json {
+        let num = |value: usize| Json::Num(value as f64);
+        Json::object(vec![
+            ("lines", num(self.lines)),
+            ("statements", num(self.statements)),
+            ("declarations", num(self.declarations)),
+            ("moves", num(self.moves)),
+            ("nesting", num(self.nesting)),
+        ])
+    }
+}
+
+impl ToJson for ModuleMetrics {
+    fn to_json(&self) -> Json {
+        let routines = self.routines.iter()
+            .map(|(name, metrics)| Json::object(vec![("name", Json::str(name)), ("metrics", metrics.to_json())]))
+            .collect();
+        Json::object(vec![
+            ("name", Json::str(&self.name)),
+            ("metrics", self.metrics.to_json()),
+            ("routines", Json::Array(routines)),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lexer, parser, resolver};
+
+    #[test]
+    fn measures_routines() {
+        let mut program = parser::parse_tokens(lexer::parse("MODULE Cell
+    PERS robtarget pHome := [[0, 0, 0], [1, 0, 0, 0], [0, 0, 0, 0], [9E9, 9E9, 9E9, 9E9, 9E9, 9E9]];
+    PROC Main()
+        VAR num i;
+        MoveJ pHome, v1000, z50, tool0;
+        FOR i FROM 1 TO 3 DO
+            IF i > 1 THEN
+                MoveL pHome, v100, fine, tool0;
+            ENDIF
+        ENDFOR
+    ERROR
+        RETRY;
+    ENDPROC
+
+    PROC MoveHome()
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &resolver::ResolveOptions::default()).unwrap();
+        let modules = measure(&program);
+        assert_eq!(modules[0].routines[0].1, Metrics { lines: 11, statements: 5, declarations: 1, moves: 2, nesting: 2 });
+        assert_eq!(modules[0].routines[1].1, Metrics { lines: 2, ..Metrics::default() });
+        assert_eq!(modules[0].metrics, Metrics { lines: 17, statements: 5, declarations: 2, moves: 2, nesting: 2 });
+
+        assert_eq!(table(&modules), "\
+Module      Lines  Statements  Declarations  Moves  Nesting
+Cell           17           5             2      2        2
+  Main         11           5             1      2        2
+  MoveHome      2           0             0      0        0
+");
+        assert_eq!(modules[0].to_json().path(&["routines"]).and_then(Json::as_array).unwrap()[1].to_string(),
+            r#"{"name":"MoveHome","metrics":{"lines":2,"statements":0,"declarations":0,"moves":0,"nesting":0}}"#);
+    }
+}
@@ -1,554 +1,2126 @@
-use std::vec;
-use std::collections::HashMap;
-use std::ops;
-use std::rc::Rc;
-use std::cell::RefCell;
-
-use crate::lexer::TokenType;
+use std::fs;
+use std::fmt;
+use std::ops::{Index, IndexMut};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::builtins::{Args, HostRoutine};
+use crate::error::Error;
+use crate::host::Host;
+use crate::interpreter::{self, InterpreterOptions, RuntimeError};
+use crate::lexer::{self, keyword, Comment, Span, Token, TokenType};
+use crate::loader::LoadedModule;
+use crate::symbols::OutlineKind;
+use crate::variable::{self, Variable};
 
 // ------------------ Nodes -----------------------/
 
-#[derive(Debug)]
-enum Node {
-    Assign{ 
-        lhs: Box<Node>, 
-        rhs: Box<Node>,    
-    },
-    OpAdd { 
-        lhs: Box<Node>, 
-        rhs: Box<Node>,    
-    },
-    OpSub{ 
-        lhs: Box<Node>, 
-        rhs: Box<Node>,    
+/// Index of a node in the arena of its routine
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NodeId(usize);
+
+/// Nodes of the expressions of a routine, side by side in one vector rather
+/// than boxed one by one. Operands refer to their nodes by `NodeId`, the
+/// nodes of statements and of lists like arguments are kept in place.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Arena {
+    nodes: Vec<Node>,
+}
+
+impl Arena {
+    pub const fn new() -> Arena {
+        Arena { nodes: Vec::new() }
+    }
+
+    pub fn alloc(&mut self, node: Node) -> NodeId {
+        self.nodes.push(node);
+        NodeId(self.nodes.len() - 1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Take a node out to change it while the arena is borrowed, `put` it
+    /// back when done
+    pub fn take(&mut self, id: NodeId) -> Node {
+        std::mem::replace(&mut self.nodes[id.0], Node::Retry)
+    }
+
+    pub fn put(&mut self, id: NodeId, node: Node) {
+        self.nodes[id.0] = node;
+    }
+}
+
+impl Index<NodeId> for Arena {
+    type Output = Node;
+
+    fn index(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+}
+
+impl IndexMut<NodeId> for Arena {
+    fn index_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+}
+
+/// Binary operators, in RAPID precedence groups from high to low:
+/// `* / DIV MOD`, `+ -`, comparisons, `AND`, `OR XOR`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Operator {
+    Add, Sub, Mul, Div, IntDiv, Mod,
+    Equal, NotEqual, Less, LessEqual, Greater, GreaterEqual,
+    And, Or, Xor,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Node {
+    Assign{
+        lhs: NodeId,
+        rhs: NodeId,
     },
-    OpMul{ 
-        lhs: Box<Node>, 
-        rhs: Box<Node>,    
+    BinOp {
+        op: Operator,
+        lhs: NodeId,
+        rhs: NodeId,
     },
-    OpDiv{ 
-        lhs: Box<Node>, 
-        rhs: Box<Node>,    
+    OpNeg(NodeId),
+    OpNot(NodeId),
+    // TPWrite with its text and optional value argument
+    Print {
+        text: NodeId,
+        arg: Option<(WriteArg, NodeId)>,
     },
-    Print(usize),
     Value(Variable),
+    // Aggregate like `[1, 2, 3]`, typed by the record it's assigned to
+    Aggregate(Vec<Node>),
+    // Identifier as written in the source, bound to a slot by the resolver
+    Id(String, Span),
+    // Routine argument or local variable
     Var(usize),
-    ProcCall(Box<Routine>),
-    FuncCall,
+    // Module or system data
+    Global(usize),
+    // Array element like `arr{i, j}`, the indices count from 1
+    Index {
+        base: NodeId,
+        indices: Vec<Node>,
+    },
+    // Present() of the optional parameter in the given argument slot
+    Present(usize),
+    // IsPers() or IsVar() of the INOUT parameter in the given argument slot,
+    // whether the data passed for it has the storage
+    IsStorage(usize, Storage),
+    // Read-only ERRNO system data, the number of the error being handled
+    Errno,
+    ProcCall {
+        name: String,
+        args: Vec<Argument>,
+        span: Span,
+        // Callee, bound by the resolver
+        target: Option<Callee>,
+    },
+    FuncCall {
+        name: String,
+        args: Vec<Argument>,
+        span: Span,
+        target: Option<Callee>,
+    },
+    // Call of the procedure a string expression names, like `%"rPart" + sNo%;`
+    LateCall {
+        name: NodeId,
+        args: Vec<Argument>,
+        span: Span,
+    },
+    Return(Option<NodeId>),
+    // Connect the intnum data to the TRAP routine, which runs whenever the
+    // interrupt it's ordered for occurs
+    Connect {
+        interrupt: NodeId,
+        trap: String,
+        span: Span,
+        // TRAP routine, bound by the resolver
+        target: Option<Callee>,
+    },
+    // Resume at the statement that raised the error, or at the next one
+    Retry,
+    TryNext,
+    // Raise an error number, or pass the error being handled to the caller
+    Raise(Option<NodeId>),
+    If {
+        // Condition and body of the IF and each ELSEIF
+        branches: Vec<(Node, Vec<Statement>)>,
+        otherwise: Vec<Statement>,
+    },
+    While {
+        condition: NodeId,
+        body: Vec<Statement>,
+    },
+    For {
+        // Loop variable, implicitly declared for the body
+        var: NodeId,
+        from: NodeId,
+        to: NodeId,
+        step: Option<NodeId>,
+        body: Vec<Statement>,
+    },
+    Test {
+        value: NodeId,
+        cases: Vec<(Vec<Node>, Vec<Statement>)>,
+        default: Vec<Statement>,
+    },
 }
 
 impl Node {
-    fn eval(self, stack: &mut Stack) -> Variable {
-        let var = match self {
-            Node::Assign { lhs, rhs }=> { 
-                let var_rhs = rhs.eval(stack);
-                lhs.assign(stack, var_rhs)
-            },
-            Node::OpAdd { lhs, rhs } => ( lhs.eval(stack) + rhs.eval(stack)),
-            Node::OpSub { lhs, rhs } => ( lhs.eval(stack) - rhs.eval(stack)),
-            Node::OpMul { lhs, rhs } => ( lhs.eval(stack) * rhs.eval(stack)),
-            Node::OpDiv { lhs, rhs } => ( lhs.eval(stack) / rhs.eval(stack)),
-            Node::Value(var) => {
-                var.clone()
+    /// Statement bodies nested in a compound statement, in source order
+    pub fn bodies(&self) -> Vec<&[Statement]> {
+        match self {
+            Node::If { branches, otherwise } => {
+                let mut bodies: Vec<&[Statement]> = branches.iter().map(|(_, body)| body.as_slice()).collect();
+                bodies.push(otherwise);
+                bodies
             },
-            Node::Var(idx) => {
-                if let Some(var) = stack.variables.get(stack.offset + idx) {
-                    var.clone()
-                } else {
-                    panic!("");
-                }
+            Node::Test { cases, default, .. } => {
+                let mut bodies: Vec<&[Statement]> = cases.iter().map(|(_, body)| body.as_slice()).collect();
+                bodies.push(default);
+                bodies
             },
-            Node::Print(idx) => {
-                if let Some(var) = stack.variables.get(stack.offset + idx) {
-                    println!("[Out] {:?}", var);
-                    Variable::Void
-                } else {
-                    panic!("");
-                }
-            }
-            _ => panic!(""),
-        };
-        var
+            Node::While { body, .. } | Node::For { body, .. } => vec![body],
+            _ => Vec::new(),
+        }
     }
 
-    fn assign(self, stack: &mut Stack, other: Variable) -> Variable {
+    /// Expressions of a node, not the statements in its bodies, with the
+    /// operands looked up in the arena of its routine
+    pub fn children<'a>(&'a self, nodes: &'a Arena) -> Vec<&'a Node> {
         match self {
-            Node::Var(idx) => {
-                if let Some(var) = stack.variables.get_mut(stack.offset + idx) {
-                    
-                    var.set(other);
-                }
-            },
-            _ => panic!("Can only assign to variable"),
-        };
-        Variable::Void
+            Node::Assign { lhs, rhs } | Node::BinOp { lhs, rhs, .. } => vec![&nodes[*lhs], &nodes[*rhs]],
+            Node::OpNeg(node) | Node::OpNot(node) => vec![&nodes[*node]],
+            Node::Print { text, arg } => std::iter::once(&nodes[*text]).chain(arg.iter().map(|(_, node)| &nodes[*node])).collect(),
+            Node::Aggregate(items) => items.iter().collect(),
+            Node::Index { base, indices } => std::iter::once(&nodes[*base]).chain(indices.iter()).collect(),
+            Node::ProcCall { args, .. } | Node::FuncCall { args, .. } => args.iter().filter_map(|arg| arg.value.as_ref()).collect(),
+            Node::LateCall { name, args, .. } => std::iter::once(&nodes[*name]).chain(args.iter().filter_map(|arg| arg.value.as_ref())).collect(),
+            Node::Return(value) | Node::Raise(value) => value.iter().map(|node| &nodes[*node]).collect(),
+            Node::Connect { interrupt, .. } => vec![&nodes[*interrupt]],
+            Node::If { branches, .. } => branches.iter().map(|(condition, _)| condition).collect(),
+            Node::While { condition, .. } => vec![&nodes[*condition]],
+            Node::For { var, from, to, step, .. } => vec![&nodes[*var], &nodes[*from], &nodes[*to]].into_iter().chain(step.iter().map(|node| &nodes[*node])).collect(),
+            Node::Test { value, cases, .. } => std::iter::once(&nodes[*value]).chain(cases.iter().flat_map(|(values, _)| values.iter())).collect(),
+            Node::Value(_) | Node::Id(..) | Node::Var(_) | Node::Global(_) | Node::Present(_)
+            | Node::IsStorage(..) | Node::Errno | Node::Retry | Node::TryNext => Vec::new(),
+        }
     }
 }
 
+/// Routine a call is bound to
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Callee {
+    // Module and routine index
+    Routine(usize, usize),
+    // Index in the built-in routine table
+    Builtin(usize),
+    // Index in the routines registered by the host
+    Host(usize),
+}
 
-// ------------------ Variables -----------------------/
+/// Optional value argument of TPWrite, written after the text
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum WriteArg {
+    Num,
+    Dnum,
+    Bool,
+    Pos,
+}
 
-#[derive(Debug,Clone)]
-enum Variable {
-    Void,
-    Bool(bool),
-    Num(f64),
-    Str(String),
-    //Proc(Box<Node>),
+/// Node executed as a statement, with the span of its first token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Statement {
+    pub node: Node,
+    pub span: Span,
+    // Comments after the code on its lines, those on the lines of nested
+    // statements are theirs
+    pub comments: Vec<String>,
 }
 
-impl Variable {
-    fn set(&mut self, other: Variable) {
-        match (self, other) {
-            (Variable::Bool(ref mut value), Variable::Bool(value2)) => *value = value2,
-            (Variable::Num(ref mut value), Variable::Num(value2)) => *value = value2,
-            (Variable::Str(ref mut value), Variable::Str(ref value2)) => *value = value2.clone(),
-            _ => panic!("")
-        }
-    }
+/// Argument of a routine call, optional arguments are passed by name
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Argument {
+    pub name: Option<String>,
+    pub value: Option<Node>,
+    pub span: Span,
+    // Conditional argument like `\Speed?nSpeed`, passed only when the
+    // optional parameter of the caller in `value` is present
+    pub conditional: bool,
+}
 
-    fn from(data_type: &TokenType) -> Result<Variable,String> {
-        let var = match data_type {
-            TokenType::NumType => Variable::Num(0.0),
-            TokenType::BoolType => Variable::Bool(false),
-            TokenType::StringType => Variable::Str(String::default()),
-            _ => return Err(String::from("Unknown data type")),
-        };
-        
-        Ok(var)
-    }
+// ------------------ Program structure -----------------------/
 
-    fn from_value(data_type: &TokenType, value: &TokenType) -> Result<Variable,String> {
-        let var = match (data_type, value) {
-            (TokenType::BoolType, TokenType::True) => Variable::Bool(true),
-            (TokenType::BoolType, TokenType::False) => Variable::Bool(false),
-            (TokenType::NumType, TokenType::NumValue(val)) => Variable::Num(val.parse().unwrap()),
-            (TokenType::StringType, TokenType::StringValue(val)) => Variable::Str(val.clone()),
-            _ => return Err(String::from("Unknown data type")),
-        };
-        
-        Ok(var)
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Storage {
+    Var,
+    Pers,
+    Const,
 }
 
-impl ops::Add for Variable {
-    type Output = Variable;
-
-    fn add(self, other: Variable) -> Variable {
-        match (self, other) {
-            (Variable::Bool(b1), Variable::Bool(b2)) => Variable::Bool(b1 || b2),
-            (Variable::Num(n1), Variable::Num(n2)) => Variable::Num(n1 + n2),
-            (Variable::Str(s1), Variable::Str(s2)) => Variable::Str(s1.clone() + &s2.clone()),
-            _ => panic!("Unknown combo")
+impl Storage {
+    /// Keyword of the declaration, like `PERS`
+    pub fn keyword(self) -> &'static str {
+        match self {
+            Storage::Var => "VAR",
+            Storage::Pers => "PERS",
+            Storage::Const => "CONST",
         }
     }
 }
 
-impl ops::Sub for Variable {
-    type Output = Variable;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataDecl {
+    pub name: String,
+    pub data_type: String,
+    pub storage: Storage,
+    pub local: bool,
+    pub value: Variable,
+    // Expression the initial value is written as, the resolver evaluates it into `value`
+    pub init: Option<Initializer>,
+    pub span: Span,
+}
 
-    fn sub(self, other: Variable) -> Variable {
-        match (self, other) {
-            (Variable::Num(n1), Variable::Num(n2)) => Variable::Num(n1 - n2),
-            _ => panic!("Unknown combo")
-        }
-    }
+/// Initial value of data written as an expression of constants rather than
+/// a literal, like `nMax * 2`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Initializer {
+    pub expr: Node,
+    // Operands of the expression
+    pub nodes: Arena,
 }
 
-impl ops::Mul for Variable {
-    type Output = Variable;
+/// Other name of a data type, like `ALIAS num level;`. Data of the alias
+/// type keeps the alias as its data type, the resolver gives it the value of
+/// the base type.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alias {
+    pub name: String,
+    pub base: String,
+    pub local: bool,
+    pub span: Span,
+}
+
+/// Access mode of a routine parameter
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ParamMode {
+    In,
+    Var,
+    Pers,
+    InOut,
+}
 
-    fn mul(self, other: Variable) -> Variable {
-        match (self, other) {
-            (Variable::Num(n1), Variable::Num(n2)) => Variable::Num(n1 * n2),
-            _ => panic!("Unknown combo")
+impl ParamMode {
+    /// Keyword of the mode, IN for a parameter declared without one
+    pub fn keyword(self) -> &'static str {
+        match self {
+            ParamMode::In => "IN",
+            ParamMode::Var => "VAR",
+            ParamMode::Pers => "PERS",
+            ParamMode::InOut => "INOUT",
         }
     }
 }
 
-impl ops::Div for Variable {
-    type Output = Variable;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Param {
+    pub decl: DataDecl,
+    pub mode: ParamMode,
+    pub optional: bool,
+    // Optional parameters separated by '|' share a group and exclude each other
+    pub group: usize,
+    // Dimensions of an array parameter like `num values{*}`, which takes
+    // arrays of any length. The value of its declaration is that of an
+    // element.
+    pub dims: usize,
+}
 
-    fn div(self, other: Variable) -> Variable {
-        match (self, other) {
-            (Variable::Num(n1), Variable::Num(n2)) => Variable::Num(n1 / n2),
-            _ => panic!("Unknown combo")
+impl Param {
+    /// Parameter as declared, like `\\switch Fast` or `INOUT num nCount`
+    pub fn declaration(&self) -> String {
+        let mode = match self.mode {
+            ParamMode::In => "",
+            ParamMode::Var => "VAR ",
+            ParamMode::Pers => "PERS ",
+            ParamMode::InOut => "INOUT ",
+        };
+        let dims = if self.dims > 0 { format!("{{{}}}", vec!["*"; self.dims].join(",")) } else { String::new() };
+        format!("{}{}{} {}{}", if self.optional { "\\" } else { "" }, mode, self.decl.data_type, self.decl.name, dims)
+    }
+
+    /// Set the slot of the parameter to the value of an argument. An array
+    /// parameter takes an array of any length with as many dimensions, its
+    /// elements converted to the data type.
+    pub fn bind(&self, slot: &mut Variable, value: Variable) -> Result<(), RuntimeError> {
+        if self.dims == 0 {
+            return slot.set(value);
         }
+        *slot = conform(&self.decl.value, value, self.dims)?;
+        Ok(())
     }
 }
 
-struct RapidIter<I> {
-    iter: I
+#[derive(Default)]
+pub struct Program {
+    pub modules: Vec<Module>,
+    // Data of the system tier, visible to every module unless shadowed
+    pub system: Vec<DataDecl>,
+    // Global data slots, laid out by the resolver
+    pub variables: Vec<Variable>,
+    // Routines of the host, they shadow the built-in routines
+    pub host_routines: Vec<HostRoutine>,
+    // Modules loaded while the program ran, after the modules it started with
+    pub loaded: Vec<LoadedModule>,
 }
 
-impl<I> Iterator for RapidIter<I> where I: Iterator<Item = TokenType>
-{
-    type Item = Node;
+/// Routine or data declared in a program, see `Program::symbols`
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolInfo<'a> {
+    pub name: &'a str,
+    pub kind: OutlineKind,
+    // Data type, or the return type of a FUNC. None for a PROC.
+    pub data_type: Option<&'a str>,
+    pub module: &'a str,
+    // Routine declaring the data, None for routines and the data of modules
+    pub routine: Option<&'a str>,
+    // Name of the declaration
+    pub span: Span,
+}
 
-    fn next(&mut self) -> Option<Node> {
-        None
+impl<'a> SymbolInfo<'a> {
+    fn data(decl: &'a DataDecl, module: &'a Module, routine: Option<&'a Routine>) -> SymbolInfo<'a> {
+        SymbolInfo {
+            name: &decl.name,
+            kind: match decl.storage {
+                Storage::Var => OutlineKind::Variable,
+                Storage::Pers => OutlineKind::Persistent,
+                Storage::Const => OutlineKind::Constant,
+            },
+            data_type: Some(&decl.data_type),
+            module: &module.name,
+            routine: routine.map(|routine| routine.name.as_str()),
+            span: decl.span,
+        }
     }
 }
 
-pub struct Program {
-    modules: Vec<Module>,
+/// Frozen initial state of a program, see `Program::image`
+#[derive(Debug, Clone)]
+pub struct Image {
+    // Values of the global data slots
     variables: Vec<Variable>,
+    // Number of modules the program started with
+    started: usize,
+    // Modules loaded while the program ran, linked and as they were read
+    modules: Vec<Module>,
+    loaded: Vec<LoadedModule>,
 }
 
 impl Program {
     fn new() -> Program {
         Program {
             modules: Vec::new(),
+            system: Vec::new(),
             variables: Vec::new(),
+            host_routines: Vec::new(),
+            loaded: Vec::new(),
         }
     }
+
+    /// Parse a program: the modules of a file, or of standard input for the
+    /// path "-". The modules of a .sys file are system modules. The .pgf
+    /// file of a program saved by RobotStudio, or the directory it's in, gives
+    /// the module files that make up the program. The .sys files next to the
+    /// .pgf file are added to a program read from its directory.
+    pub fn from_path(path: &Path) -> Result<Program, Error> {
+        Program::from_path_with(path, &|source| Program::from_source(source).map(|program| program.modules))
+    }
+
+    /// Read a program like `from_path`, with the modules of each file the
+    /// source of which `parse` parses
+    pub fn from_path_with(path: &Path, parse: &(dyn Fn(&str) -> Result<Vec<Module>, Error> + Sync)) -> Result<Program, Error> {
+        let modules = if path.is_dir() {
+            // The controller loads the system modules before the program
+            let mut files = system_files(path)?;
+            files.append(&mut pgf_files(&program_file(path)?)?);
+            parse_all(&files, |file| read_modules(file, parse))?
+        } else if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("pgf")) {
+            parse_all(&pgf_files(path)?, |file| read_modules(file, parse))?
+        } else {
+            read_modules(path, parse)?
+        };
+        let mut program = Program::new();
+        program.modules = modules;
+        Ok(program)
+    }
+
+    /// Parse the modules of source text
+    pub fn from_source(source: &str) -> Result<Program, Error> {
+        let (tokens, comments) = lexer::tokenize_with_comments(source)?;
+        let mut program = parse_commented(tokens, comments)?;
+        if let Some(header) = lexer::header(source) {
+            for module in program.modules.iter_mut() {
+                module.header = Some(header.clone());
+            }
+        }
+        Ok(program)
+    }
+
+    /// Call a FUNC or PROC of a resolved program with the values of its
+    /// parameters in order, like `interpreter::call_routine` with the default
+    /// options. What the routine writes to the FlexPendant is dropped.
+    pub fn call(&mut self, routine: &str, args: &[Variable]) -> Result<Variable, RuntimeError> {
+        interpreter::call_routine(self, routine, args, &InterpreterOptions::default(), &mut Host::new(&mut Vec::new()))
+    }
+
+    /// Initial state of a resolved program, the values the resolver gave its
+    /// global data. Take it before the first run to reset the program to.
+    pub fn image(&self) -> Image {
+        let started = self.modules.len() - self.loaded.len();
+        Image {
+            variables: self.variables.clone(),
+            started,
+            modules: self.modules[started..].to_vec(),
+            loaded: self.loaded.clone(),
+        }
+    }
+
+    /// Reset the program to an image of it between runs: the global data gets
+    /// its values back and the loaded modules are those of the image again.
+    /// Nothing is parsed or resolved again.
+    pub fn reset(&mut self, image: &Image) {
+        self.variables.clone_from(&image.variables);
+        self.modules.truncate(image.started);
+        self.modules.extend(image.modules.iter().cloned());
+        self.loaded.clone_from(&image.loaded);
+    }
+
+    /// Data declared in the modules with its module and global slot, as the
+    /// resolver lays them out after the system data
+    pub fn module_data(&self) -> Vec<(&Module, &DataDecl, usize)> {
+        let mut slot = self.system.len();
+        let mut data = Vec::new();
+        for module in self.modules.iter() {
+            for decl in module.variables.iter() {
+                data.push((module, decl, slot));
+                slot += 1;
+            }
+        }
+        data
+    }
+
+    /// Routines and data declared in the modules, by module: its data, then
+    /// each routine followed by the data of the routine
+    pub fn symbols(&self) -> impl Iterator<Item = SymbolInfo<'_>> + '_ {
+        self.modules.iter().flat_map(|module| {
+            let routines = module.routines.iter().flat_map(move |routine| {
+                let symbol = SymbolInfo {
+                    name: &routine.name,
+                    kind: if routine.return_type.is_some() { OutlineKind::Function } else { OutlineKind::Procedure },
+                    data_type: routine.return_type.as_deref(),
+                    module: &module.name,
+                    routine: None,
+                    span: routine.span,
+                };
+                std::iter::once(symbol).chain(routine.variables.iter().map(move |decl| SymbolInfo::data(decl, module, Some(routine))))
+            });
+            module.variables.iter().map(move |decl| SymbolInfo::data(decl, module, None)).chain(routines)
+        })
+    }
+
+    /// Make a Rust closure callable as a RAPID procedure. Parameters are
+    /// declared like in RAPID: `[\][VAR|PERS|INOUT] type name`. Register
+    /// routines before the program is resolved.
+    pub fn register_proc(&mut self, name: &str, params: &[&str], mut call: impl FnMut(&mut Args) -> Result<(), RuntimeError> + 'static) {
+        let call = Box::new(move |args: &mut Args| call(args).map(|_| None));
+        self.host_routines.push(HostRoutine::new(name, None, params, call));
+    }
+
+    /// Make a Rust closure callable as a RAPID function, the value it returns
+    /// is converted to `return_type`
+    pub fn register_func(&mut self, name: &str, return_type: &str, params: &[&str], mut call: impl FnMut(&mut Args) -> Result<Variable, RuntimeError> + 'static) {
+        let call = Box::new(move |args: &mut Args| call(args).map(Some));
+        self.host_routines.push(HostRoutine::new(name, Some(return_type), params, call));
+    }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Module {
-    name: String,
-    routines: Vec<Routine>,
-    variables: Vec<Variable>,
+    pub name: String,
+    // Attributes of the module header as written, like NOSTEPIN
+    pub attributes: Vec<String>,
+    // A system module, by its SYSMODULE attribute or .sys file. Its global
+    // data and routines are in the system tier, below those of the task.
+    pub system: bool,
+    pub routines: Vec<Routine>,
+    pub variables: Vec<DataDecl>,
+    pub aliases: Vec<Alias>,
+    // Fields of the `%%%` header of the file, like VERSION and LANGUAGE, of
+    // the exports of older controllers
+    pub header: Option<Vec<(String, String)>>,
+    // Name of the module and its ENDMODULE
+    pub span: Span,
+    pub end: Span,
 }
 
 impl Module {
-    fn new(name: String) -> Module {
+    fn new(name: String, span: Span) -> Module {
         Module {
-            name: name, 
+            name,
+            attributes: Vec::new(),
+            system: false,
             routines: Vec::new(),
             variables: Vec::new(),
+            aliases: Vec::new(),
+            header: None,
+            span,
+            end: span,
         }
     }
 }
 
-pub struct Scope {
-    v: Vec<Variable>,
-    indices: HashMap<String, i32>,
-    variables: HashMap<String, (i32, Variable)>,
-}
-
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Routine {
-    name: String,
-    arguments: Vec<Variable>,
-    variables: HashMap<String,(usize, Variable)>,
-    nodes: Vec<Node>,
+    pub name: String,
+    pub local: bool,
+    // Return type of a FUNC, None for a PROC or a TRAP
+    pub return_type: Option<String>,
+    // TRAP routine, which only runs for the interrupts connected to it
+    pub trap: bool,
+    pub arguments: Vec<Param>,
+    pub variables: Vec<DataDecl>,
+    pub statements: Vec<Statement>,
+    // ERROR handler, runs when a statement of the routine raises an error
+    pub handler: Option<Vec<Statement>>,
+    // Operands of the expressions in the statements and the handler
+    pub nodes: Arena,
+    pub span: Span,
+    // ENDPROC, ENDFUNC or ENDTRAP
+    pub end: Span,
 }
 
 impl Routine {
-    fn new(name: String) -> Routine {
+    fn new(name: String, span: Span) -> Routine {
         Routine {
-            name: name, 
+            name,
+            local: false,
+            return_type: None,
+            trap: false,
             arguments: Vec::new(),
-            variables: HashMap::new(),
-            nodes: Vec::new(),
+            variables: Vec::new(),
+            statements: Vec::new(),
+            handler: None,
+            nodes: Arena::default(),
+            span,
+            end: span,
+        }
+    }
+
+    /// Header of the routine, like `PROC rPick(num nPart, \switch Fast)`
+    pub fn declaration(&self) -> String {
+        if self.trap {
+            return format!("TRAP {}", self.name);
         }
+        declaration(&self.name, self.return_type.as_deref(), &self.arguments)
     }
 }
 
-pub struct Stack {
-    offset: usize,
-    variables: Vec<Variable>,    
+/// Header of a routine with its parameters, alternatives of an optional
+/// parameter separated by '|'
+pub fn declaration(name: &str, return_type: Option<&str>, params: &[Param]) -> String {
+    let mut text = match return_type {
+        Some(return_type) => format!("FUNC {} {}(", return_type, name),
+        None => format!("PROC {}(", name),
+    };
+    for (idx, param) in params.iter().enumerate() {
+        if idx > 0 {
+            text.push_str(if params[idx - 1].group == param.group { " | " } else { ", " });
+        }
+        text.push_str(&param.declaration());
+    }
+    text.push(')');
+    text
 }
 
+/// Tokens that don't make a module, with the span of the token the message
+/// names
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+}
 
-pub fn parse_tokens(tokens: Vec<TokenType>) -> Result<Program, String> {
+impl ParseError {
+    /// Error at a token, which the message ends with
+    fn at(message: String, span: Span) -> ParseError {
+        ParseError { message: format!("{} at {}", message, span), span }
+    }
+}
 
-    let mut iter = tokens.iter();
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
 
-    let mut program = Program::new();
+/// Construct waiting for its terminator, used to explain unmatched blocks
+struct Block {
+    keyword: &'static str,
+    name: Option<String>,
+    span: Span,
+    terminator: TokenType,
+}
 
-    let var1 = Variable::Str(String::from("test"));
-    let var2 = Variable::Str(String::from("the banana"));
-    let var3 = var1 + var2;
+impl Block {
+    fn open(keyword: &'static str, span: Span, terminator: TokenType) -> Block {
+        Block { keyword, name: None, span, terminator }
+    }
 
-    println!("var3 = {:?}", var3);
+    fn named(mut self, name: &str) -> Block {
+        self.name = Some(String::from(name));
+        self
+    }
 
-    while let Some(token) = iter.next() {
+    fn describe(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{} {} opened at {}", self.keyword, name, self.span),
+            None => format!("{} opened at {}", self.keyword, self.span),
+        }
+    }
 
-        match token {
-            // Valid tokens
-            TokenType::Mod => { program.modules.push(read_mod(&mut iter)?); },
-            // Invalid tokens
-            _ => return Err(format!("Invalid token for program: {:?}", token)),
-        };
+    fn missing(&self) -> ParseError {
+        ParseError { message: format!("Missing {} for {}", keyword(&self.terminator), self.describe()), span: self.span }
     }
 
-    Ok(program)
+    fn mismatch(&self, found: &TokenType, span: Span) -> ParseError {
+        ParseError { message: format!("Found {} at {} but {} expects {}", keyword(found), span, self.describe(), keyword(&self.terminator)), span }
+    }
 }
 
-fn read_mod<'a,I>(iter: &mut I) -> Result<Module, String> where I: Iterator<Item = &'a TokenType> {
-    // Create new scope that inherits parent scope
-    // add routines and global variables to scope
-    // exit at END_MOD
+/// Whether a data type isn't built in, so it's an alias the resolver knows
+/// the base type of, or unknown
+fn alias(data_type: &str) -> bool {
+    Variable::from(data_type).is_err()
+}
 
-    let name = match iter.next() {
-        Some(TokenType::Id(name)) => name,
-        _ => return Err(String::from("Expected module name")),
-    };
+/// Value of data of a type without an initial value, void for an alias
+fn typed_value(data_type: &str) -> Variable {
+    Variable::from(data_type).unwrap_or(Variable::Void)
+}
 
-    let mut module = Module::new(name.clone());
-        
-    while let Some(token) = iter.next() {
-        match token {
-            // Valid tokens
-            TokenType::Proc => { 
-                let routine = read_proc(iter)?; 
-                println!("Routine: {:?}", routine);
-                test_proc(routine);
-            },
-            TokenType::Func => (),
-            TokenType::Var => (),
-            TokenType::Pers => (),
-            TokenType::Local => (),
-            // Closing token
-            TokenType::EndMod => return Ok(module),
-            // Invalid tokens
-            _ => return Err(format!("Invalid token for module: {:?}", token)),
-        };
+/// Whether an expression is written as a literal, an aggregate of literals
+/// or a negated literal
+fn is_literal(node: &Node, nodes: &Arena) -> bool {
+    match node {
+        Node::Value(_) => true,
+        Node::OpNeg(id) => is_literal(&nodes[*id], nodes),
+        Node::Aggregate(items) => items.iter().all(|item| is_literal(item, nodes)),
+        _ => false,
+    }
+}
+
+/// Value of an argument for an array parameter with `dims` dimensions, its
+/// elements set to copies of `element`
+fn conform(element: &Variable, value: Variable, dims: usize) -> Result<Variable, RuntimeError> {
+    match (dims, value) {
+        (0, value @ Variable::Array(_)) => Err(RuntimeError::type_mismatch(format!("Array of {} has too many dimensions", value.type_name()))),
+        (0, value) => {
+            let mut var = element.clone();
+            var.set(value)?;
+            Ok(var)
+        },
+        (_, Variable::Array(items)) => items.into_iter().map(|item| conform(element, item, dims - 1)).collect::<Result<_, _>>().map(Variable::Array),
+        (_, value) => Err(RuntimeError::type_mismatch(format!("Expected array of {}, found {}", element.type_name(), value.type_name()))),
     }
+}
 
-    return Err(String::from("Unexpected end of module"));
+/// Type of the value of a literal token
+fn literal_type(value: &TokenType) -> Option<&'static str> {
+    match value {
+        TokenType::NumValue(_) => Some("num"),
+        TokenType::StringValue(_) => Some("string"),
+        TokenType::True | TokenType::False => Some("bool"),
+        _ => None,
+    }
 }
 
-fn test_proc(routine : Routine) {
-    let mut stack = Stack {
-        offset: 0,
-        variables: Vec::new(),
-    };
+/// Tokens that end (part of) a block construct
+fn is_terminator(token_type: &TokenType) -> bool {
+    matches!(token_type,
+        TokenType::EndMod | TokenType::EndProc | TokenType::EndFunc | TokenType::EndTrap |
+        TokenType::EndIf | TokenType::ElseIf | TokenType::Else |
+        TokenType::EndWhile | TokenType::EndFor |
+        TokenType::EndTest | TokenType::Case | TokenType::Default |
+        TokenType::Error)
+}
 
-    for var in routine.variables {
-        let pair = var.1;
-        stack.variables.push(pair.1.clone());
+/// Maximum nesting of statements and expressions in blocks, parentheses and
+/// calls. Deeper input is rejected so the recursive parser, resolver and
+/// evaluator can't overflow the stack.
+const MAX_NESTING: usize = 100;
+
+/// Maximum depth of the tree of an expression, where every operator of a
+/// chain like `a + b + c` also nests its left operand a level deeper
+const MAX_TREE_DEPTH: usize = 200;
+
+/// Attributes a module header can have
+const MODULE_ATTRIBUTES: [&str; 5] = ["SYSMODULE", "NOVIEW", "NOSTEPIN", "VIEWONLY", "READONLY"];
+
+/// The .pgf file of the program saved in a directory
+pub fn program_file(dir: &Path) -> Result<PathBuf, Error> {
+    let entries = fs::read_dir(dir).map_err(|err| Error::io(dir, err))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("pgf")))
+        .collect();
+    match files.len() {
+        1 => Ok(files.remove(0)),
+        0 => Err(Error::io(dir, "no program file (.pgf)")),
+        _ => Err(Error::io(dir, "more than one program file (.pgf)")),
     }
+}
 
-    for node in routine.nodes {
-        let var = node.eval(&mut stack);
+/// The system module files (.sys) in a directory, by name
+pub fn system_files(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let entries = fs::read_dir(dir).map_err(|err| Error::io(dir, err))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("sys")))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Modules of a module file, those of a .sys file are system modules
+fn read_modules(path: &Path, parse: &(dyn Fn(&str) -> Result<Vec<Module>, Error> + Sync)) -> Result<Vec<Module>, Error> {
+    let source = lexer::read_source(path).map_err(|err| Error::io(path, err))?;
+    let mut modules = parse(&source).map_err(|err| err.in_file(path))?;
+    if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("sys")) {
+        for module in modules.iter_mut() {
+            module.system = true;
+        }
     }
+    Ok(modules)
 }
 
-fn read_proc<'a,I>(iter: &mut I) -> Result<Routine, String> where I: Iterator<Item = &'a TokenType> {
-    // Create new local scope that inherits parent scope
-    // Add variables to scope
-    // exit at END_PROC
+/// Paths of the module files a .pgf file lists, next to it
+pub fn pgf_files(path: &Path) -> Result<Vec<PathBuf>, Error> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let pgf = lexer::read_source(path).map_err(|err| Error::io(path, err))?;
+    Ok(module_files(&pgf).into_iter().map(|file| dir.join(file)).collect())
+}
 
-    // Routine name
-    let name = match iter.next() {
-        Some(TokenType::Id(name)) => name,
-        _ => return Err(String::from("Expected routine name")),
+/// Modules of the files, parsed on a thread per core and in the order of
+/// the files. The error is that of the first file failing. The resolver
+/// merges the symbols of the modules afterwards.
+pub fn parse_all<T: Sync, E: Send>(files: &[T], parse: impl Fn(&T) -> Result<Vec<Module>, E> + Sync) -> Result<Vec<Module>, E> {
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    let parsed: Vec<Result<Vec<Module>, E>> = if threads > 1 && files.len() > 1 {
+        let chunk = files.len().div_ceil(threads);
+        let parse = &parse;
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = files.chunks(chunk)
+                .map(|files| scope.spawn(move || files.iter().map(parse).collect::<Vec<_>>()))
+                .collect();
+            handles.into_iter().flat_map(|handle| handle.join().expect("parse panicked")).collect()
+        })
+    } else {
+        files.iter().map(parse).collect()
     };
+    let mut modules = Vec::new();
+    for result in parsed {
+        modules.append(&mut result?);
+    }
+    Ok(modules)
+}
 
-    match iter.next() {
-        Some(TokenType::LeftPar) => (),
-        _ => return Err(String::from("Expected '('")),
-    };
+/// Module files a .pgf file lists, like `<Module>MainModule.mod</Module>`
+fn module_files(pgf: &str) -> Vec<&str> {
+    pgf.split("<Module>")
+        .skip(1)
+        .filter_map(|part| part.split_once("</Module>"))
+        .map(|(file, _)| file.trim())
+        .collect()
+}
+
+pub fn parse_tokens(tokens: Vec<Token>) -> Result<Program, ParseError> {
+    parse_commented(tokens, Vec::new())
+}
 
-    let mut routine = Routine::new(name.clone());   
+/// Program of the tokens, with the trailing comments attached to the
+/// statements they follow
+pub fn parse_commented(tokens: Vec<Token>, comments: Vec<Comment>) -> Result<Program, ParseError> {
 
-    let mut var_idx = 0;
-    let mut variables : HashMap<String,(usize, Variable)> = HashMap::new();
+    let comments = comments.into_iter().filter(|comment| comment.trailing).collect();
+    let mut parser = Parser { tokens: &tokens, pos: 0, depth: 0, tree_depth: 0, handler: false, nodes: Arena::default(), comments };
 
-    // Parse arguments
-    while let Some(token) = iter.next() {
-        let arg = match token {
+    let mut program = Program::new();
+
+    while let Some(token) = parser.next() {
+
+        match token.token_type {
             // Valid tokens
-            TokenType::NumType => parse_arg(iter, token)?,
-            TokenType::StringType => parse_arg(iter, token)?,
-            TokenType::BoolType => parse_arg(iter, token)?,
-            // Closing token
-            TokenType::RightPar => break,
+            TokenType::Mod => { program.modules.push(parser.read_mod()?); },
             // Invalid tokens
-            _ => return Err(format!("Expected ')' {:?}", token)),
+            _ => return parser.error(format!("Invalid token for program: {:?}", token.token_type)),
         };
+    }
+
+    Ok(program)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    // Nesting of the statement or expression being parsed
+    depth: usize,
+    // Depth of the tree being parsed, with the operators of chains
+    tree_depth: usize,
+    // Parsing an ERROR handler, where RETRY, TRYNEXT and a bare RAISE are allowed
+    handler: bool,
+    // Nodes of the routine being parsed
+    nodes: Arena,
+    // Trailing comments not attached to a statement yet
+    comments: Vec<Comment>,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos);
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
 
-        variables.insert(arg.0, (var_idx, arg.1));
-        var_idx += 1;
+    fn peek(&self) -> Option<&'a TokenType> {
+        self.tokens.get(self.pos).map(|token| &token.token_type)
     }
 
-    // Parse variable declarations
+    /// Span of the next token, or of the last one at the end of the input
+    fn span(&self) -> Span {
+        self.tokens.get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map(|token| token.span)
+            .unwrap_or_default()
+    }
 
+    /// Span of the token that was consumed last
+    fn last_span(&self) -> Span {
+        self.pos.checked_sub(1)
+            .and_then(|pos| self.tokens.get(pos))
+            .map(|token| token.span)
+            .unwrap_or_default()
+    }
 
-    // Parse body
-    while let Some(token) = iter.next() {
-        match token {
-            // Valid tokens
-            TokenType::Var => {
-                    let var = parse_var(iter)?;
-                    variables.insert(var.0, (var_idx, var.1));
-                    var_idx += 1;
-                },
-            TokenType::Id(name) => { 
-                    if let Some((idx, var)) = variables.get(name) {
-                        let node = Node::Var(*idx);
-                        routine.nodes.push(parse_statement(iter, &variables, node)?); 
+    fn error<T>(&self, message: String) -> Result<T, ParseError> {
+        Err(ParseError::at(message, self.last_span()))
+    }
+
+    fn expect(&mut self, token_type: TokenType, what: &str) -> Result<(), ParseError> {
+        match self.next() {
+            Some(token) if token.token_type == token_type => Ok(()),
+            _ => self.error(format!("Expected {}", what)),
+        }
+    }
+
+    fn eat(&mut self, token_type: TokenType) -> bool {
+        if self.peek() == Some(&token_type) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn alloc(&mut self, node: Node) -> NodeId {
+        self.nodes.alloc(node)
+    }
+
+    /// Run a nested parse step, rejecting input nested deeper than `MAX_NESTING`
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        let (depth, tree_depth) = (self.depth, self.tree_depth);
+        if self.depth >= MAX_NESTING {
+            return Err(ParseError::at(format!("Nesting deeper than {} levels", MAX_NESTING), self.span()));
+        }
+        self.depth += 1;
+        self.deepen()?;
+        let result = parse(self);
+        self.depth = depth;
+        self.tree_depth = tree_depth;
+        result
+    }
+
+    /// Go a level deeper in the tree, rejecting trees deeper than `MAX_TREE_DEPTH`
+    fn deepen(&mut self) -> Result<(), ParseError> {
+        if self.tree_depth >= MAX_TREE_DEPTH {
+            return Err(ParseError::at(format!("Expression nested deeper than {} levels", MAX_TREE_DEPTH), self.span()));
+        }
+        self.tree_depth += 1;
+        Ok(())
+    }
+
+    /// Parse a chain of left-associative operators like `a + b + c`. Every
+    /// operator nests the left operand one level deeper in the tree, which
+    /// counts toward `MAX_TREE_DEPTH` but not `MAX_NESTING`.
+    fn chain(&mut self, parse: fn(&mut Self) -> Result<Node, ParseError>) -> Result<Node, ParseError> {
+        let tree_depth = self.tree_depth;
+        let result = parse(self);
+        self.tree_depth = tree_depth;
+        result
+    }
+
+    fn read_name(&mut self, what: &str) -> Result<String, ParseError> {
+        match self.next().map(|token| &token.token_type) {
+            Some(TokenType::Id(name)) => Ok(name.clone()),
+            _ => self.error(format!("Expected {}", what)),
+        }
+    }
+
+    fn read_type(&mut self) -> Result<String, ParseError> {
+        let name = match self.next().map(|token| &token.token_type) {
+            Some(TokenType::NumType) => "num",
+            Some(TokenType::StringType) => "string",
+            Some(TokenType::BoolType) => "bool",
+            Some(TokenType::Id(name)) => name,
+            _ => return self.error(String::from("Expected data type")),
+        };
+        Ok(String::from(name))
+    }
+
+    fn read_mod(&mut self) -> Result<Module, ParseError> {
+        let block = Block::open("MODULE", self.last_span(), TokenType::EndMod);
+        let span = self.span();
+        let name = self.read_name("module name")?;
+        let block = block.named(&name);
+
+        let mut module = Module::new(name, span);
+        if self.eat(TokenType::LeftPar) {
+            loop {
+                let attribute = self.read_name("module attribute")?;
+                if !MODULE_ATTRIBUTES.iter().any(|known| known.eq_ignore_ascii_case(&attribute)) {
+                    return self.error(format!("Unknown module attribute {}", attribute));
+                }
+                module.system |= attribute.eq_ignore_ascii_case("SYSMODULE");
+                module.attributes.push(attribute);
+                match self.next().map(|token| &token.token_type) {
+                    Some(TokenType::Comma) => (),
+                    Some(TokenType::RightPar) => break,
+                    _ => return self.error(String::from("Expected ')'")),
+                }
+            }
+        }
+        let mut local = false;
+
+        while let Some(token) = self.next() {
+            match token.token_type {
+                // Valid tokens
+                TokenType::Proc | TokenType::Func | TokenType::Trap => {
+                    let routine = self.read_routine(&token.token_type, local)?;
+                    if lexer::trace() {
+                        println!("Routine: {:?}", routine);
                     }
+                    module.routines.push(routine);
+                },
+                TokenType::Var => module.variables.push(self.parse_var(Storage::Var, local)?),
+                TokenType::Pers => module.variables.push(self.parse_var(Storage::Pers, local)?),
+                TokenType::Const => module.variables.push(self.parse_var(Storage::Const, local)?),
+                TokenType::Alias => module.aliases.push(self.parse_alias(local)?),
+                TokenType::Local => {
+                    local = true;
+                    continue;
+                },
+                // Closing token
+                TokenType::EndMod => {
+                    module.end = token.span;
+                    return Ok(module);
+                },
+                // Terminator of another construct
+                ref token_type if is_terminator(token_type) => return Err(block.mismatch(token_type, token.span)),
+                // Invalid tokens
+                _ => return self.error(format!("Invalid token for module: {:?}", token.token_type)),
+            };
+            local = false;
+        }
+
+        Err(block.missing())
+    }
+
+    fn read_routine(&mut self, keyword: &TokenType, local: bool) -> Result<Routine, ParseError> {
+        let keyword_span = self.last_span();
+        let return_type = if *keyword == TokenType::Func { Some(self.read_type()?) } else { None };
+
+        // Routine name
+        let span = self.span();
+        let name = self.read_name("routine name")?;
+        let block = match keyword {
+            TokenType::Func => Block::open("FUNC", keyword_span, TokenType::EndFunc),
+            TokenType::Trap => Block::open("TRAP", keyword_span, TokenType::EndTrap),
+            _ => Block::open("PROC", keyword_span, TokenType::EndProc),
+        }.named(&name);
+
+        let mut routine = Routine::new(name, span);
+        routine.local = local;
+        routine.return_type = return_type;
+        routine.trap = *keyword == TokenType::Trap;
+
+        // A TRAP routine has no parameter list
+        if !routine.trap {
+            self.expect(TokenType::LeftPar, "'('")?;
+        }
+
+        // Parse arguments
+        if !routine.trap && !self.eat(TokenType::RightPar) {
+            loop {
+                let group = routine.arguments.len();
+                routine.arguments.push(self.parse_param(group)?);
+                while self.eat(TokenType::Pipe) {
+                    // Alternatives are always optional
+                    let mut param = self.parse_param(group)?;
+                    param.optional = true;
+                    routine.arguments[group].optional = true;
+                    routine.arguments.push(param);
+                }
+
+                match self.next().map(|token| &token.token_type) {
+                    Some(TokenType::Comma) => (),
+                    Some(TokenType::RightPar) => break,
+                    _ => return self.error(String::from("Expected ')'")),
+                }
+            }
+        }
+
+        // Data declarations precede the statements
+        loop {
+            match self.peek() {
+                Some(TokenType::Var) => {
+                    self.pos += 1;
+                    routine.variables.push(self.parse_var(Storage::Var, false)?);
+                },
+                Some(TokenType::Const) => {
+                    self.pos += 1;
+                    routine.variables.push(self.parse_var(Storage::Const, false)?);
+                },
+                _ => break,
+            }
+        }
+
+        let end = block.terminator.clone();
+        let (statements, closed) = self.parse_block(&block, &[end.clone(), TokenType::Error])?;
+        routine.statements = statements;
+        if closed == TokenType::Error {
+            let handler = Block::open("ERROR", self.last_span(), end.clone());
+            self.handler = true;
+            let statements = self.parse_block(&handler, &[end]);
+            self.handler = false;
+            routine.handler = Some(statements?.0);
+        }
+        routine.nodes = std::mem::take(&mut self.nodes);
+        routine.end = self.last_span();
+        Ok(routine)
+    }
+
+    /// Parse statements until one of `ends` closes the block, returns the statements and the closing token
+    fn parse_block(&mut self, block: &Block, ends: &[TokenType]) -> Result<(Vec<Statement>, TokenType), ParseError> {
+        let mut statements = Vec::new();
+
+        loop {
+            match self.peek() {
+                None => return Err(block.missing()),
+                Some(token_type) if ends.contains(token_type) => {
+                    self.pos += 1;
+                    return Ok((statements, token_type.clone()));
                 },
-            // Future
-            TokenType::If => (),
-            TokenType::While => (),
-            TokenType::For => (),
-            TokenType::Return => (),
-            TokenType::TpWrite => {
-                if let Some(TokenType::Id(name)) = iter.next() {
-                    if let Some((idx, var)) = variables.get(name) {
-                        let node = Node::Print(*idx);
-                        routine.nodes.push(node); 
-                    }                    
+                Some(token_type) if is_terminator(token_type) => return Err(block.mismatch(token_type, self.span())),
+                _ => statements.push(self.parse_statement()?),
+            }
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement, ParseError> {
+        self.nested(Self::read_statement)
+    }
+
+    fn read_statement(&mut self) -> Result<Statement, ParseError> {
+        let token = match self.next() {
+            Some(token) => token,
+            None => return self.error(String::from("Expected statement")),
+        };
+
+        let node = match &token.token_type {
+            TokenType::Id(name) => self.parse_assign_or_call(name, token.span)?,
+            TokenType::Percent => {
+                let name = self.parse_expr()?;
+                self.expect(TokenType::Percent, "'%'")?;
+                let mut args = Vec::new();
+                if !self.eat(TokenType::Semicolon) {
+                    args = self.parse_args(TokenType::Semicolon)?;
                 }
-                iter.next();
+                Node::LateCall { name: self.alloc(name), args, span: token.span }
+            },
+            TokenType::If => self.parse_if(token.span)?,
+            TokenType::While => {
+                let condition = self.parse_expr()?;
+                self.expect(TokenType::Do, "DO")?;
+                let block = Block::open("WHILE", token.span, TokenType::EndWhile);
+                let body = self.parse_block(&block, &[TokenType::EndWhile])?.0;
+                Node::While { condition: self.alloc(condition), body }
+            },
+            TokenType::For => {
+                let span = self.span();
+                let var = Node::Id(self.read_name("loop variable")?, span);
+                self.expect(TokenType::From, "FROM")?;
+                let from = self.parse_expr()?;
+                self.expect(TokenType::To, "TO")?;
+                let to = self.parse_expr()?;
+                let step = if self.eat(TokenType::Step) { Some(self.parse_expr()?) } else { None };
+                self.expect(TokenType::Do, "DO")?;
+                let block = Block::open("FOR", token.span, TokenType::EndFor);
+                let body = self.parse_block(&block, &[TokenType::EndFor])?.0;
+                let step = step.map(|step| self.alloc(step));
+                Node::For { var: self.alloc(var), from: self.alloc(from), to: self.alloc(to), step, body }
+            },
+            TokenType::Test => self.parse_test(token.span)?,
+            TokenType::Return => {
+                let value = if self.peek() == Some(&TokenType::Semicolon) {
+                    None
+                } else {
+                    let value = self.parse_expr()?;
+                    Some(self.alloc(value))
+                };
+                self.expect(TokenType::Semicolon, "';'")?;
+                Node::Return(value)
+            },
+            TokenType::Retry | TokenType::TryNext => {
+                if !self.handler {
+                    return self.error(format!("{} is only allowed in an ERROR handler", keyword(&token.token_type)));
+                }
+                self.expect(TokenType::Semicolon, "';'")?;
+                if token.token_type == TokenType::Retry { Node::Retry } else { Node::TryNext }
+            },
+            TokenType::Raise => {
+                let value = if self.peek() == Some(&TokenType::Semicolon) {
+                    if !self.handler {
+                        return self.error(String::from("RAISE without an error number is only allowed in an ERROR handler"));
+                    }
+                    None
+                } else {
+                    let value = self.parse_expr()?;
+                    Some(self.alloc(value))
+                };
+                self.expect(TokenType::Semicolon, "';'")?;
+                Node::Raise(value)
+            },
+            TokenType::Connect => {
+                let interrupt = self.parse_expr()?;
+                self.expect(TokenType::With, "WITH")?;
+                let span = self.span();
+                let trap = self.read_name("TRAP routine")?;
+                self.expect(TokenType::Semicolon, "';'")?;
+                Node::Connect { interrupt: self.alloc(interrupt), trap, span, target: None }
+            },
+            TokenType::TpWrite => self.parse_write()?,
+            TokenType::Var | TokenType::Pers | TokenType::Const => {
+                return self.error(String::from("Data declarations must precede the statements of a routine"));
             },
-            // Closing tokene
-            TokenType::EndProc => {
-                routine.variables = variables;
-                return Ok(routine);
-            }
             // Invalid tokens
-            _ => return Err(format!("Invalid token for routine: {:?}", token)),
+            _ => return self.error(format!("Invalid token for routine: {:?}", token.token_type)),
         };
+
+        let end = self.tokens.get(self.pos - 1).map_or(token.span.line, |last| last.span.line);
+        let lines = token.span.line..=end;
+        let (comments, rest) = std::mem::take(&mut self.comments).into_iter().partition(|comment: &Comment| lines.contains(&comment.span.line));
+        self.comments = rest;
+        Ok(Statement { node, span: token.span, comments: comments.into_iter().map(|comment| comment.text).collect() })
     }
 
-    return Err(String::from("Unexpected end of routine"));
-}
+    fn parse_if(&mut self, span: Span) -> Result<Node, ParseError> {
+        let condition = self.parse_expr()?;
 
-fn parse_statement<'a,I>(iter: &mut I, vars_map: &HashMap<String,(usize, Variable)>, lhs_node: Node) -> Result<Node, String> where I: Iterator<Item = &'a TokenType> {
+        // Compact IF without THEN guards a single statement
+        if !self.eat(TokenType::Then) {
+            let body = vec![self.parse_statement()?];
+            return Ok(Node::If { branches: vec![(condition, body)], otherwise: Vec::new() });
+        }
 
-    let op = iter.next();
+        let mut block = Block::open("IF", span, TokenType::EndIf);
+        let ends = [TokenType::ElseIf, TokenType::Else, TokenType::EndIf];
+        let mut branches = Vec::new();
+        let mut condition = condition;
 
-    // Var name
-    match op {
-        Some(TokenType::Assign) => (),
-        _ => return Err(String::from("Expected variable assignment")),
-    };
-   
-    while let Some(token) = iter.next() {
-        let rhs_node = match token {
-            TokenType::NumValue(val) => Node::Value(Variable::Num(val.parse().unwrap())),
-            TokenType::StringValue(val) => Node::Value(Variable::Str(val.clone())),
-            TokenType::True=> Node::Value(Variable::Bool(true)),
-            TokenType::False => Node::Value(Variable::Bool(false)),
-            TokenType::Id(name) => {
-                if let Some(var_idx) = vars_map.get(name) {
-                    Node::Var(var_idx.0)
+        loop {
+            let (body, end) = self.parse_block(&block, &ends)?;
+            branches.push((condition, body));
+
+            match end {
+                TokenType::ElseIf => {
+                    block = Block::open("ELSEIF", self.last_span(), TokenType::EndIf);
+                    condition = self.parse_expr()?;
+                    self.expect(TokenType::Then, "THEN")?;
+                },
+                TokenType::Else => {
+                    let block = Block::open("ELSE", self.last_span(), TokenType::EndIf);
+                    let otherwise = self.parse_block(&block, &[TokenType::EndIf])?.0;
+                    return Ok(Node::If { branches, otherwise });
+                },
+                _ => return Ok(Node::If { branches, otherwise: Vec::new() }),
+            }
+        }
+    }
+
+    fn parse_test(&mut self, span: Span) -> Result<Node, ParseError> {
+        let value = self.parse_expr()?;
+        let block = Block::open("TEST", span, TokenType::EndTest);
+        let ends = [TokenType::Case, TokenType::Default, TokenType::EndTest];
+
+        let (body, mut end) = self.parse_block(&block, &ends)?;
+        if !body.is_empty() {
+            return Err(ParseError::at(String::from("Expected CASE after TEST opened"), span));
+        }
+
+        let mut cases = Vec::new();
+        loop {
+            match end {
+                TokenType::Case => {
+                    let mut values = vec![self.parse_expr()?];
+                    while self.eat(TokenType::Comma) {
+                        values.push(self.parse_expr()?);
+                    }
+                    self.expect(TokenType::Colon, "':'")?;
+                    let (body, next) = self.parse_block(&block, &ends)?;
+                    cases.push((values, body));
+                    end = next;
+                },
+                TokenType::Default => {
+                    self.expect(TokenType::Colon, "':'")?;
+                    let default = self.parse_block(&block, &[TokenType::EndTest])?.0;
+                    return Ok(Node::Test { value: self.alloc(value), cases, default });
+                },
+                _ => return Ok(Node::Test { value: self.alloc(value), cases, default: Vec::new() }),
+            }
+        }
+    }
+
+    /// Parse the arguments of `TPWrite String [\Num] | [\Dnum] | [\Bool] | [\Pos];`
+    fn parse_write(&mut self) -> Result<Node, ParseError> {
+        let text = self.parse_expr()?;
+        let text = self.alloc(text);
+
+        let mut arg = None;
+        if self.eat(TokenType::Backslash) {
+            // The names of the arguments double as data type keywords
+            let kind = match self.next().map(|token| &token.token_type) {
+                Some(TokenType::NumType) => WriteArg::Num,
+                Some(TokenType::BoolType) => WriteArg::Bool,
+                Some(TokenType::Id(name)) if name.eq_ignore_ascii_case("dnum") => WriteArg::Dnum,
+                Some(TokenType::Id(name)) if name.eq_ignore_ascii_case("pos") => WriteArg::Pos,
+                Some(TokenType::Id(name)) => return self.error(format!("'TPWrite' has no parameter \\{}", name)),
+                _ => return self.error(String::from("Expected argument name")),
+            };
+            self.expect(TokenType::Assign, "':='")?;
+            let value = self.parse_expr()?;
+            arg = Some((kind, self.alloc(value)));
+        }
+
+        self.expect(TokenType::Semicolon, "';'")?;
+        Ok(Node::Print { text, arg })
+    }
+
+    fn parse_param(&mut self, group: usize) -> Result<Param, ParseError> {
+        let optional = self.eat(TokenType::Backslash);
+
+        let mode = match self.peek() {
+            Some(TokenType::Var) => ParamMode::Var,
+            Some(TokenType::Pers) => ParamMode::Pers,
+            Some(TokenType::Inout) => ParamMode::InOut,
+            _ => ParamMode::In,
+        };
+        if mode != ParamMode::In {
+            self.pos += 1;
+        }
+
+        let data_type = self.read_type()?;
+        let span = self.span();
+        let name = self.read_name("parameter name")?;
+        let dims = if self.eat(TokenType::LeftBrace) { self.read_open_dims()? } else { 0 };
+        let storage = if mode == ParamMode::Pers { Storage::Pers } else { Storage::Var };
+        let value = typed_value(&data_type);
+
+        Ok(Param {
+            decl: DataDecl { name, data_type, storage, local: false, value, init: None, span },
+            mode,
+            optional,
+            group,
+            dims,
+        })
+    }
+
+    /// Parse an assignment or procedure call starting with the identifier `name`
+    fn parse_assign_or_call(&mut self, name: &str, span: Span) -> Result<Node, ParseError> {
+        let mut lhs_node = Node::Id(String::from(name), span);
+        if self.eat(TokenType::LeftBrace) {
+            lhs_node = self.parse_index(lhs_node)?;
+            if self.peek() != Some(&TokenType::Assign) {
+                return self.error(String::from("Expected ':='"));
+            }
+        }
+
+        if self.eat(TokenType::Assign) {
+            let rhs_node = self.parse_expr()?;
+            self.expect(TokenType::Semicolon, "';'")?;
+
+            return Ok(Node::Assign {
+                lhs: self.alloc(lhs_node),
+                rhs: self.alloc(rhs_node),
+            });
+        }
+
+        // Procedure call, arguments follow the name without parentheses
+        let mut args = Vec::new();
+        if !self.eat(TokenType::Semicolon) {
+            args = self.parse_args(TokenType::Semicolon)?;
+        }
+
+        Ok(Node::ProcCall { name: String::from(name), args, span, target: None })
+    }
+
+    /// Parse a comma separated argument list up to and including `end`
+    fn parse_args(&mut self, end: TokenType) -> Result<Vec<Argument>, ParseError> {
+        let mut args = Vec::new();
+
+        loop {
+            let span = self.span();
+            if self.eat(TokenType::Backslash) {
+                // Arguments like `\Num` are named after a data type keyword
+                let keyword = match self.peek() {
+                    Some(TokenType::NumType) => Some("Num"),
+                    Some(TokenType::BoolType) => Some("Bool"),
+                    Some(TokenType::StringType) => Some("String"),
+                    _ => None,
+                };
+                let name = match keyword {
+                    Some(name) => {
+                        self.pos += 1;
+                        String::from(name)
+                    },
+                    None => self.read_name("argument name")?,
+                };
+                let conditional = self.eat(TokenType::Question);
+                let value = if conditional {
+                    let span = self.span();
+                    Some(Node::Id(self.read_name("parameter name")?, span))
+                } else if self.eat(TokenType::Assign) {
+                    Some(self.parse_expr()?)
                 } else {
-                    return Err(String::from("Unknown id"));
-                }
+                    None
+                };
+                args.push(Argument { name: Some(name), value, span, conditional });
+            } else {
+                args.push(Argument { name: None, value: Some(self.parse_expr()?), span, conditional: false });
             }
-            // Invalid tokens
-            _ => return Err(format!("Invalid token for statement: {:?}", token)),
+
+            match self.peek() {
+                Some(TokenType::Comma) => self.pos += 1,
+                // Optional arguments may directly follow the previous one
+                Some(TokenType::Backslash) => (),
+                Some(token_type) if *token_type == end => {
+                    self.pos += 1;
+                    return Ok(args);
+                },
+                _ => {
+                    self.pos += 1;
+                    return self.error(String::from("Expected ',' or end of arguments"));
+                },
+            }
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Node, ParseError> {
+        self.nested(Self::parse_or)
+    }
+
+    fn parse_or(&mut self) -> Result<Node, ParseError> {
+        self.chain(Self::read_or)
+    }
+
+    fn read_or(&mut self) -> Result<Node, ParseError> {
+        let mut node = self.parse_and()?;
+
+        loop {
+            let op = match self.peek() {
+                Some(TokenType::Or) => Operator::Or,
+                Some(TokenType::Xor) => Operator::Xor,
+                _ => return Ok(node),
+            };
+            self.pos += 1;
+            self.deepen()?;
+            let rhs = self.parse_and()?;
+            node = Node::BinOp { op, lhs: self.alloc(node), rhs: self.alloc(rhs) };
+        }
+    }
+
+    fn parse_and(&mut self) -> Result<Node, ParseError> {
+        self.chain(Self::read_and)
+    }
+
+    fn read_and(&mut self) -> Result<Node, ParseError> {
+        let mut node = self.parse_not()?;
+
+        while self.eat(TokenType::And) {
+            self.deepen()?;
+            let rhs = self.parse_not()?;
+            node = Node::BinOp { op: Operator::And, lhs: self.alloc(node), rhs: self.alloc(rhs) };
+        }
+        Ok(node)
+    }
+
+    fn parse_not(&mut self) -> Result<Node, ParseError> {
+        if self.eat(TokenType::Not) {
+            let node = self.nested(Self::parse_not)?;
+            return Ok(Node::OpNot(self.alloc(node)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Node, ParseError> {
+        let node = self.parse_sum()?;
+
+        let op = match self.peek() {
+            Some(TokenType::Equal) => Operator::Equal,
+            Some(TokenType::NotEqual) => Operator::NotEqual,
+            Some(TokenType::Less) => Operator::Less,
+            Some(TokenType::LessEqual) => Operator::LessEqual,
+            Some(TokenType::Greater) => Operator::Greater,
+            Some(TokenType::GreaterEqual) => Operator::GreaterEqual,
+            _ => return Ok(node),
         };
+        self.pos += 1;
+        let rhs = self.parse_sum()?;
+        Ok(Node::BinOp { op, lhs: self.alloc(node), rhs: self.alloc(rhs) })
+    }
 
-        let rhs_node = parse_sub(iter, vars_map, rhs_node)?;
-        
-        return Ok(Node::Assign {
-            lhs: Box::from(lhs_node),
-            rhs: Box::from(rhs_node),  
-        });
+    fn parse_sum(&mut self) -> Result<Node, ParseError> {
+        self.chain(Self::read_sum)
     }
 
-    Err(String::from("Unexpected token"))
-}
+    fn read_sum(&mut self) -> Result<Node, ParseError> {
+        let mut node = self.parse_term()?;
+
+        loop {
+            let op = match self.peek() {
+                Some(TokenType::Add) => Operator::Add,
+                Some(TokenType::Minus) => Operator::Sub,
+                _ => return Ok(node),
+            };
+            self.pos += 1;
+            self.deepen()?;
+            let rhs = self.parse_term()?;
+            node = Node::BinOp { op, lhs: self.alloc(node), rhs: self.alloc(rhs) };
+        }
+    }
 
-fn parse_sub<'a,I>(iter: &mut I, vars_map: &HashMap<String,(usize, Variable)>, lhs_node: Node) -> Result<Node, String> where I: Iterator<Item = &'a TokenType> {
+    fn parse_term(&mut self) -> Result<Node, ParseError> {
+        self.chain(Self::read_term)
+    }
+
+    fn read_term(&mut self) -> Result<Node, ParseError> {
+        let mut node = self.parse_unary()?;
+
+        loop {
+            let op = match self.peek() {
+                Some(TokenType::Multiply) => Operator::Mul,
+                Some(TokenType::Divide) => Operator::Div,
+                Some(TokenType::Div) => Operator::IntDiv,
+                // MOD doubles as the old module keyword
+                Some(TokenType::Mod) => Operator::Mod,
+                _ => return Ok(node),
+            };
+            self.pos += 1;
+            self.deepen()?;
+            let rhs = self.parse_unary()?;
+            node = Node::BinOp { op, lhs: self.alloc(node), rhs: self.alloc(rhs) };
+        }
+    }
 
-    let op = iter.next();
+    fn parse_unary(&mut self) -> Result<Node, ParseError> {
+        if self.eat(TokenType::Minus) {
+            let node = self.nested(Self::parse_unary)?;
+            return Ok(Node::OpNeg(self.alloc(node)));
+        }
+        self.eat(TokenType::Add);
+        self.parse_operand()
+    }
 
-    let operator = match op {
-        Some(TokenType::Semicolon) => return Ok(lhs_node),
-        None => return Err(String::from("Expected operator")),
-        Some(o) => o,
-    };
+    fn parse_operand(&mut self) -> Result<Node, ParseError> {
+        let token = match self.next() {
+            Some(token) => token,
+            None => return self.error(String::from("Expected expression")),
+        };
 
-    if let Some(rhs_var) = iter.next() {
-        let rhs_node = match rhs_var {
-            TokenType::NumValue(val) => Node::Value(Variable::Num(val.parse().unwrap())),
+        let node = match &token.token_type {
+            TokenType::NumValue(val) => match lexer::num_value(val) {
+                Some(val) => Node::Value(Variable::Num(val)),
+                None => return self.error(format!("Invalid number {}", val)),
+            },
             TokenType::StringValue(val) => Node::Value(Variable::Str(val.clone())),
             TokenType::True=> Node::Value(Variable::Bool(true)),
             TokenType::False => Node::Value(Variable::Bool(false)),
-            TokenType::Id(name) => {
-                if let Some(var_idx) = vars_map.get(name) {
-                    Node::Var(var_idx.0)
-                } else {
-                    return Err(String::from("Unknown id"));
+            TokenType::Id(name) if self.peek() == Some(&TokenType::LeftPar) => {
+                self.pos += 1;
+                let args = if self.eat(TokenType::RightPar) { Vec::new() } else { self.parse_args(TokenType::RightPar)? };
+                Node::FuncCall { name: name.clone(), args, span: token.span, target: None }
+            },
+            TokenType::Id(name) if self.eat(TokenType::LeftBrace) => self.parse_index(Node::Id(name.clone(), token.span))?,
+            TokenType::Id(name) => Node::Id(name.clone(), token.span),
+            TokenType::LeftPar => {
+                let node = self.parse_expr()?;
+                self.expect(TokenType::RightPar, "')'")?;
+                node
+            },
+            TokenType::LeftBrack => {
+                let mut values = vec![self.parse_expr()?];
+                while self.eat(TokenType::Comma) {
+                    values.push(self.parse_expr()?);
                 }
-            }
+                self.expect(TokenType::RightBrack, "']'")?;
+                Node::Aggregate(values)
+            },
             // Invalid tokens
-            _ => return Err(format!("Invalid token for statement: {:?}", rhs_var)),
+            _ => return self.error(format!("Invalid token for statement: {:?}", token.token_type)),
         };
 
-        let node = match operator {
-            TokenType::Add => {
-                let rhs_node = parse_sub(iter, vars_map, rhs_node)?;
-                Node::OpAdd {
-                    lhs: Box::from(lhs_node),
-                    rhs: Box::from(rhs_node)
-                }
-            },
-            TokenType::Minus => { 
-                let rhs_node = parse_sub(iter, vars_map, rhs_node)?;
-                Node::OpSub {
-                    lhs: Box::from(lhs_node),
-                    rhs: Box::from(rhs_node)
-                }
+        Ok(node)
+    }
+
+    /// Parse the indices of an array element after the opening `{`
+    fn parse_index(&mut self, base: Node) -> Result<Node, ParseError> {
+        let mut indices = vec![self.parse_expr()?];
+        while self.eat(TokenType::Comma) {
+            indices.push(self.parse_expr()?);
+        }
+        self.expect(TokenType::RightBrace, "'}'")?;
+        Ok(Node::Index { base: self.alloc(base), indices })
+    }
+
+    fn parse_var(&mut self, storage: Storage, local: bool) -> Result<DataDecl, ParseError> {
+        let data_type = self.read_type()?;
+
+        // Var name
+        let span = self.span();
+        let name = self.read_name("var name")?;
+        let dims = if self.eat(TokenType::LeftBrace) { self.read_dims()? } else { Vec::new() };
+
+        match self.next().map(|token| &token.token_type) {
+            Some(TokenType::Assign) => (),
+            Some(TokenType::Semicolon) if storage != Storage::Const => {
+                let value = match typed_value(&data_type) {
+                    Variable::SyncIdent(_) => Variable::SyncIdent(name.to_ascii_lowercase()),
+                    value => value,
+                };
+                let value = Variable::array(&dims, value);
+                return Ok(DataDecl { name, data_type, storage, local, value, init: None, span });
             },
-            TokenType::Multiply => {
-                let node = Node::OpMul {
-                    lhs: Box::from(lhs_node),
-                    rhs: Box::from(rhs_node)
-                };                
-                parse_sub(iter, vars_map, node)?
+            Some(TokenType::Semicolon) => return self.error(format!("Expected value for CONST {}", name)),
+            _ => return self.error(String::from("Expected assign or semicolon")),
+        };
+
+        // A literal is read as it is, anything else as an expression
+        let start = self.pos;
+        let literal = match self.read_array(&data_type, &dims) {
+            Ok(value) if self.peek() == Some(&TokenType::Semicolon) => Ok(value),
+            Ok(_) => self.error(String::from("Expected ';'")),
+            Err(err) => Err(err),
+        };
+        let (value, init) = match literal {
+            Ok(value) => (value, None),
+            Err(err) => {
+                // A literal with an invalid value stays an error
+                self.pos = start;
+                let init = self.parse_initializer().ok().filter(|init| !is_literal(&init.expr, &init.nodes)).ok_or(err)?;
+                (Variable::array(&dims, typed_value(&data_type)), Some(init))
             },
-            TokenType::Divide => {
-                let node = Node::OpDiv {
-                    lhs: Box::from(lhs_node),
-                    rhs: Box::from(rhs_node)
-                };                 
-                parse_sub(iter, vars_map, node)?
-            } ,
-            _ => return Err(format!("Invalid token for statement: {:?}", operator))
         };
+        self.expect(TokenType::Semicolon, "';'")?;
+        Ok(DataDecl { name, data_type, storage, local, value, init, span })
+    }
 
-        return Ok(node);
+    /// Parse the initial value of a declaration as an expression, with its
+    /// own nodes apart from those of the routine
+    fn parse_initializer(&mut self) -> Result<Initializer, ParseError> {
+        let routine_nodes = std::mem::take(&mut self.nodes);
+        let expr = self.parse_expr();
+        let nodes = std::mem::replace(&mut self.nodes, routine_nodes);
+        Ok(Initializer { expr: expr?, nodes })
     }
 
-    Err(String::from("Unexpected token"))
-}
+    /// Parse `ALIAS num level;` after the keyword
+    fn parse_alias(&mut self, local: bool) -> Result<Alias, ParseError> {
+        let base = self.read_type()?;
+        let span = self.span();
+        let name = self.read_name("alias name")?;
+        self.expect(TokenType::Semicolon, "';'")?;
+        Ok(Alias { name, base, local, span })
+    }
+
+    /// Read the dimensions of an array parameter like `{*, *}` after the
+    /// opening `{`, returns their number
+    fn read_open_dims(&mut self) -> Result<usize, ParseError> {
+        let mut dims = 0;
+        loop {
+            self.expect(TokenType::Multiply, "'*'")?;
+            dims += 1;
+            if !self.eat(TokenType::Comma) {
+                break;
+            }
+        }
+        if dims > 3 {
+            return self.error(String::from("Arrays have at most 3 dimensions"));
+        }
+        self.expect(TokenType::RightBrace, "'}'")?;
+        Ok(dims)
+    }
 
-fn parse_arg<'a,I>(iter: &mut I, data_type: &TokenType) -> Result<(String, Variable), String> where I: Iterator<Item = &'a TokenType> {
+    /// Read the dimensions of an array declaration after the opening `{`
+    fn read_dims(&mut self) -> Result<Vec<usize>, ParseError> {
+        let mut dims = Vec::new();
+        loop {
+            let len = match self.next().map(|token| &token.token_type) {
+                Some(TokenType::NumValue(val)) => val.parse::<usize>().ok().filter(|len| *len > 0),
+                _ => None,
+            };
+            match len {
+                Some(len) => dims.push(len),
+                None => return self.error(String::from("Expected array dimension")),
+            }
+            if !self.eat(TokenType::Comma) {
+                break;
+            }
+        }
+        if dims.len() > 3 {
+            return self.error(String::from("Arrays have at most 3 dimensions"));
+        }
+        self.expect(TokenType::RightBrace, "'}'")?;
+        Ok(dims)
+    }
 
-    // Var name
-    let name = match iter.next() {
-        Some(TokenType::Id(name)) => name,
-        _ => return Err(String::from("Expected var name")),
-    };
-  
-    Ok((name.clone(), Variable::from(data_type)?))
+    /// Read the initial value of an array as nested aggregates, one level per dimension
+    fn read_array(&mut self, data_type: &str, dims: &[usize]) -> Result<Variable, ParseError> {
+        let (len, inner) = match dims.split_first() {
+            Some(dim) => dim,
+            None => return self.read_value(data_type),
+        };
+        self.expect(TokenType::LeftBrack, "'['")?;
+        let mut items = Vec::new();
+        for idx in 0..*len {
+            if idx > 0 {
+                self.expect(TokenType::Comma, "','")?;
+            }
+            items.push(self.read_array(data_type, inner)?);
+        }
+        self.expect(TokenType::RightBrack, "']'")?;
+        Ok(Variable::Array(items))
+    }
+
+    /// Read the literal initial value of a declaration, records are written as aggregates
+    fn read_value(&mut self, data_type: &str) -> Result<Variable, ParseError> {
+        if self.eat(TokenType::LeftBrack) {
+            let (name, fields) = match variable::record_type(data_type) {
+                Some(record) => record,
+                None if alias(data_type) => return self.read_aggregate(),
+                None => return self.error(format!("Unexpected aggregate for {}", data_type)),
+            };
+            let mut values = Vec::new();
+            for (idx, field) in fields.iter().enumerate() {
+                if idx > 0 {
+                    self.expect(TokenType::Comma, "','")?;
+                }
+                values.push(self.read_value(field.1)?);
+            }
+            self.expect(TokenType::RightBrack, "']'")?;
+            return Ok(Variable::Record(name, values));
+        }
+
+        let negative = self.eat(TokenType::Minus);
+        let value = match self.next() {
+            Some(token) => &token.token_type,
+            None => return self.error(String::from("Expected value")),
+        };
+        if alias(data_type) {
+            return match literal_type(value) {
+                Some(literal) => Variable::from_value(literal, value, negative).or_else(|message| self.error(message)),
+                None => self.error(String::from("Expected value")),
+            };
+        }
+        Variable::from_value(data_type, value, negative).or_else(|message| self.error(message))
+    }
+
+    /// Read the rest of an aggregate of an alias type, with the values typed
+    /// by their literals
+    fn read_aggregate(&mut self) -> Result<Variable, ParseError> {
+        let mut values = Vec::new();
+        loop {
+            values.push(self.read_value("")?);
+            if !self.eat(TokenType::Comma) {
+                break;
+            }
+        }
+        self.expect(TokenType::RightBrack, "']'")?;
+        Ok(Variable::Record("", values))
+    }
 }
 
-fn parse_var<'a,I>(iter: &mut I) -> Result<(String, Variable), String> where I: Iterator<Item = &'a TokenType> {
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer;
 
-    let data_type = match iter.next() {
-        Some(token) => token,
-        None => return Err(String::from("Expected data type")),
-    };
+    fn parse_error(source: &str) -> String {
+        match parse_tokens(lexer::parse(source)) {
+            Ok(_) => panic!("Expected a parse error"),
+            Err(err) => err.to_string(),
+        }
+    }
 
-    // Var name
-    let name = match iter.next() {
-        Some(TokenType::Id(name)) => name,
-        _ => return Err(String::from("Expected var name")),
-    };
+    #[test]
+    fn parses_blocks() {
+        let program = parse_tokens(lexer::parse("
+MODULE Blocks
+    PROC rTest()
+        VAR num nCount := 0;
+        IF nCount > 2 AND NOT TRUE THEN
+            nCount := 1;
+        ELSEIF nCount = 1 THEN
+            nCount := 2;
+        ELSE
+            nCount := 3;
+        ENDIF
+        IF nCount <> 0 nCount := 0;
+        WHILE nCount < 10 DO
+            nCount := nCount + 1;
+        ENDWHILE
+        FOR i FROM 1 TO 10 STEP 2 DO
+            nCount := nCount - i;
+        ENDFOR
+        TEST nCount
+        CASE 1, 2:
+            nCount := 0;
+        DEFAULT:
+            nCount := 1;
+        ENDTEST
+    ENDPROC
+ENDMODULE")).unwrap();
+        let statements = &program.modules[0].routines[0].statements;
+        assert_eq!(statements.len(), 5);
+        assert!(matches!(&statements[0].node, Node::If { branches, otherwise } if branches.len() == 2 && otherwise.len() == 1));
+        assert!(matches!(&statements[4].node, Node::Test { cases, default, .. } if cases.len() == 1 && default.len() == 1));
+        assert_eq!(statements[2].span.line, 13);
+    }
 
-    match iter.next() {
-        Some(TokenType::Assign) => (),
-        Some(TokenType::Semicolon) => return Ok((name.clone(), Variable::from(data_type)?)),
-        _ => return Err(String::from("Expected assign or semicolon")),
-    };
+    #[test]
+    fn keeps_operands_in_the_arena_of_the_routine() {
+        let program = parse_tokens(lexer::parse("
+MODULE Arena
+    PROC rFirst()
+        nCount := nCount + 2 * 3;
+    ENDPROC
+    PROC rSecond()
+        WHILE -nCount < 0 DO
+        ENDWHILE
+    ENDPROC
+ENDMODULE")).unwrap();
+        let routine = &program.modules[0].routines[0];
+        // The target, the sum and its operands, the product and its operands
+        assert_eq!(routine.nodes.len(), 6);
+        let rhs = match &routine.statements[0].node {
+            Node::Assign { rhs, .. } => &routine.nodes[*rhs],
+            node => panic!("Unexpected node {:?}", node),
+        };
+        assert!(matches!(rhs, Node::BinOp { op: Operator::Add, .. }));
+        let children = rhs.children(&routine.nodes);
+        assert!(matches!(children[0], Node::Id(name, _) if name == "nCount"));
+        assert!(matches!(children[1], Node::BinOp { op: Operator::Mul, .. }));
+
+        // Each routine starts an arena of its own: the comparison, the
+        // negation and its operand, and the zero
+        let routine = &program.modules[0].routines[1];
+        assert_eq!(routine.nodes.len(), 4);
+        assert!(matches!(&routine.statements[0].node, Node::While { condition, .. } if matches!(routine.nodes[*condition], Node::BinOp { .. })));
+    }
 
-    let value = match iter.next() {
-        Some(token) => token,
-        None => return Err(String::from("Expected value")),
-    };
-    
-    match iter.next() {
-        Some(TokenType::Semicolon) => return Ok((name.clone(), Variable::from_value(data_type, value)?)),
-        _ => return Err(String::from("Expected value")),
-    };
-}
\ No newline at end of file
+    #[test]
+    fn reports_mismatched_terminator() {
+        assert_eq!(parse_error("
+MODULE Blocks
+    PROC rTest()
+        WHILE TRUE DO
+            rTest;
+    ENDPROC
+ENDMODULE"), "Found ENDPROC at 6:5 but WHILE opened at 4:9 expects ENDWHILE");
+
+        assert_eq!(parse_error("
+MODULE Blocks
+    PROC rTest()
+        IF TRUE THEN
+            rTest;
+        ENDWHILE
+    ENDPROC
+ENDMODULE"), "Found ENDWHILE at 6:9 but IF opened at 4:9 expects ENDIF");
+    }
+
+    #[test]
+    fn reports_missing_terminator() {
+        assert_eq!(parse_error("
+MODULE Blocks
+    PROC rTest()
+        rTest;
+"), "Missing ENDPROC for PROC rTest opened at 3:5");
+
+        assert_eq!(parse_error("
+MODULE Blocks
+    PROC rTest()
+    ENDPROC
+"), "Missing ENDMODULE for MODULE Blocks opened at 2:1");
+
+        assert_eq!(parse_error("
+MODULE Blocks
+    ENDPROC
+ENDMODULE"), "Found ENDPROC at 3:5 but MODULE Blocks opened at 2:1 expects ENDMODULE");
+    }
+
+    #[test]
+    fn parses_error_handlers() {
+        let program = parse_tokens(lexer::parse("
+MODULE Handlers
+    FUNC num Divide(num nValue, num nBy)
+        RETURN nValue / nBy;
+    ERROR
+        IF ERRNO = ERR_DIVZERO THEN
+            nBy := 1;
+            RETRY;
+        ENDIF
+        RAISE;
+    ENDFUNC
+    PROC rTest()
+        RAISE 10;
+    ENDPROC
+ENDMODULE")).unwrap();
+        let routines = &program.modules[0].routines;
+        assert_eq!(routines[0].statements.len(), 1);
+        assert!(matches!(&routines[0].handler, Some(handler) if handler.len() == 2));
+        assert!(matches!(&routines[1].statements[0].node, Node::Raise(Some(_))));
+        assert!(routines[1].handler.is_none());
+
+        assert_eq!(parse_error("
+MODULE Handlers
+    PROC rTest()
+        TRYNEXT;
+    ENDPROC
+ENDMODULE"), "TRYNEXT is only allowed in an ERROR handler at 4:9");
+
+        assert_eq!(parse_error("
+MODULE Handlers
+    PROC rTest()
+        rTest;
+    ERROR
+        RAISE;
+    ERROR
+    ENDPROC
+ENDMODULE"), "Found ERROR at 7:5 but ERROR opened at 5:5 expects ENDPROC");
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let source = format!("
+MODULE Nested
+    PROC rTest()
+        VAR num nValue := 0;
+        nValue := {}1{};
+    ENDPROC
+ENDMODULE", "(".repeat(10_000), ")".repeat(10_000));
+        assert_eq!(parse_error(&source), "Nesting deeper than 100 levels at 5:118");
+
+        let source = format!("
+MODULE Nested
+    PROC rTest()
+        {}rTest;
+    ENDPROC
+ENDMODULE", "IF TRUE ".repeat(10_000));
+        assert!(parse_error(&source).starts_with("Nesting deeper than 100 levels"));
+
+        let source = format!("
+MODULE Nested
+    PROC rTest()
+        VAR num nValue := 0;
+        nValue := 1{};
+    ENDPROC
+ENDMODULE", " + 1".repeat(10_000));
+        assert_eq!(parse_error(&source), "Expression nested deeper than 200 levels at 5:815");
+    }
+
+    #[test]
+    fn reads_long_chains_of_operators() {
+        let source = format!("
+MODULE Chains
+    PROC rTest()
+        VAR num nValue := 0;
+        VAR bool bValue := FALSE;
+        nValue := 1{};
+        nValue := 2{};
+        bValue := TRUE{};
+    ENDPROC
+ENDMODULE", " + 1".repeat(150), " * 2".repeat(150), " AND TRUE".repeat(150));
+        let program = parse_tokens(lexer::parse(&source)).unwrap();
+        assert_eq!(program.modules[0].routines[0].statements.len(), 3);
+    }
+
+    #[test]
+    fn reads_files_in_latin1() {
+        let path = std::env::temp_dir().join(format!("rapid_rust_latin1_{}.mod", std::process::id()));
+        let mut source = b"MODULE Cell\n    VAR string sText := \"Gr\xf6\xdfe\";\nENDMODULE".to_vec();
+        std::fs::write(&path, &source).unwrap();
+        let program = Program::from_path(&path).unwrap();
+        assert_eq!(format!("{:?}", program.modules[0].variables[0].value), r#"Str("Größe")"#);
+
+        // UTF-8 stays UTF-8, also after a byte order mark
+        source = "\u{feff}MODULE Cell\n    VAR string sText := \"Größe\";\nENDMODULE".as_bytes().to_vec();
+        std::fs::write(&path, &source).unwrap();
+        let program = Program::from_path(&path).unwrap();
+        assert_eq!(format!("{:?}", program.modules[0].variables[0].value), r#"Str("Größe")"#);
+        let _ = std::fs::remove_file(&path);
+        assert!(Program::from_path(&path).is_err_and(|err| err.to_string().contains("rapid_rust_latin1_")));
+    }
+
+    #[test]
+    fn attaches_trailing_comments_to_statements() {
+        let program = Program::from_source("MODULE Cell\n    PROC Main()\n        ! Count\n        IF TRUE THEN ! always\n            n := n + 1; ! one more\n        ENDIF\n    ENDPROC\nENDMODULE").unwrap();
+        let statements = &program.modules[0].routines[0].statements;
+        assert_eq!(statements[0].comments, vec![String::from(" always")]);
+        match &statements[0].node {
+            Node::If { branches, .. } => assert_eq!(branches[0].1[0].comments, vec![String::from(" one more")]),
+            node => panic!("Expected IF, got {:?}", node),
+        }
+    }
+
+    #[test]
+    fn reads_traps_and_connect() {
+        let program = Program::from_source("MODULE Cell\n    VAR intnum irPart;\n    TRAP tPart\n        TPWrite \"Part\";\n    ENDTRAP\n    PROC Main()\n        CONNECT irPart WITH tPart;\n    ENDPROC\nENDMODULE").unwrap();
+        let trap = &program.modules[0].routines[0];
+        assert!(trap.trap && trap.arguments.is_empty());
+        assert_eq!(trap.declaration(), "TRAP tPart");
+        let main = &program.modules[0].routines[1];
+        match &main.statements[0].node {
+            Node::Connect { interrupt, trap, span, .. } => {
+                assert!(matches!(&main.nodes[*interrupt], Node::Id(name, _) if name == "irPart"));
+                assert_eq!((trap.as_str(), span.line, span.column), ("tPart", 7, 29));
+            },
+            node => panic!("Expected CONNECT, got {:?}", node),
+        }
+        assert!(Program::from_source("MODULE Cell\n    TRAP tPart()\n    ENDTRAP\nENDMODULE").is_err());
+    }
+
+    #[test]
+    fn keeps_the_header_of_older_controllers() {
+        let program = Program::from_source("%%%\n  VERSION:1\n  LANGUAGE:ENGLISH\n%%%\n\nMODULE Cell\nENDMODULE\nMODULE Tools\nENDMODULE").unwrap();
+        let header = vec![(String::from("VERSION"), String::from("1")), (String::from("LANGUAGE"), String::from("ENGLISH"))];
+        assert!(program.modules.iter().all(|module| module.header.as_ref() == Some(&header)));
+        assert_eq!(program.modules[0].span.line, 6);
+        assert!(Program::from_source("MODULE Cell\nENDMODULE").unwrap().modules[0].header.is_none());
+    }
+
+    #[test]
+    fn reads_program_directories() {
+        let dir = std::env::temp_dir().join(format!("rapid_rust_program_{}", std::process::id()));
+        fs::create_dir_all(dir.join("Modules")).unwrap();
+        fs::write(dir.join("Cell.pgf"), "<?xml version=\"1.0\" encoding=\"ISO-8859-1\" ?>
+<Program>
+\t<Module>MainModule.mod</Module>
+\t<Module>Modules/Gripper.mod</Module>
+</Program>").unwrap();
+        fs::write(dir.join("MainModule.mod"), "MODULE MainModule\n    PROC main()\n        rGrip;\n    ENDPROC\nENDMODULE").unwrap();
+        // Older controllers start the files with a header
+        fs::write(dir.join("Modules").join("Gripper.mod"), "%%%\n  VERSION:1\n  LANGUAGE:ENGLISH\n%%%\n
+MODULE Gripper\n    PROC rGrip()\n        rMissing;\n    ENDPROC\nENDMODULE").unwrap();
+
+        let program = Program::from_path(&dir).unwrap();
+        let modules: Vec<&str> = program.modules.iter().map(|module| module.name.as_str()).collect();
+        assert_eq!(modules, vec!["MainModule", "Gripper"]);
+        assert_eq!(program.modules[1].routines[0].span.line, 7);
+        assert_eq!(format!("{:?}", Program::from_path(&dir.join("Cell.pgf")).map(|program| program.modules.len())), "Ok(2)");
+
+        // System modules next to the program are loaded first
+        fs::write(dir.join("user.sys"), "MODULE user(NOSTEPIN)\n    PERS num nSpeed := 100;\nENDMODULE").unwrap();
+        let program = Program::from_path(&dir).unwrap();
+        let system: Vec<(&str, bool)> = program.modules.iter().map(|module| (module.name.as_str(), module.system)).collect();
+        assert_eq!(system, vec![("user", true), ("MainModule", false), ("Gripper", false)]);
+        fs::write(dir.join("user.sys"), "MODULE user(SYSMODULE, NOSTEP)\nENDMODULE").unwrap();
+        assert!(Program::from_path(&dir).is_err_and(|err| err.to_string().ends_with("user.sys: Unknown module attribute NOSTEP at 1:24")));
+        fs::remove_file(dir.join("user.sys")).unwrap();
+
+        fs::write(dir.join("Modules").join("Gripper.mod"), "MODULE Gripper\n    PROC rGrip(\nENDMODULE").unwrap();
+        assert!(Program::from_path(&dir).is_err_and(|err| err.to_string().contains("Gripper.mod: ")));
+        fs::remove_file(dir.join("Modules").join("Gripper.mod")).unwrap();
+        assert!(Program::from_path(&dir).is_err_and(|err| err.to_string().contains("Gripper.mod: ")));
+        fs::remove_file(dir.join("Cell.pgf")).unwrap();
+        assert!(Program::from_path(&dir).is_err_and(|err| err.to_string().ends_with("no program file (.pgf)")));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn lists_declared_symbols() {
+        let program = Program::from_source("
+MODULE Cell
+    PERS num nCycles := 0;
+    PROC main()
+        VAR num nStep;
+    ENDPROC
+    LOCAL FUNC bool Ready()
+        RETURN TRUE;
+    ENDFUNC
+    CONST string sName := \"Cell\";
+ENDMODULE
+MODULE Tools
+    VAR tooldata tGripper;
+ENDMODULE").unwrap();
+        let symbols: Vec<String> = program.symbols()
+            .map(|symbol| format!("{}.{}{} {:?} {} {}", symbol.module, symbol.routine.map(|routine| format!("{}.", routine)).unwrap_or_default(),
+                symbol.name, symbol.kind, symbol.data_type.unwrap_or("-"), symbol.span))
+            .collect();
+        assert_eq!(symbols, vec![
+            "Cell.nCycles Persistent num 3:14",
+            "Cell.sName Constant string 10:18",
+            "Cell.main Procedure - 4:10",
+            "Cell.main.nStep Variable num 5:17",
+            "Cell.Ready Function bool 7:21",
+            "Tools.tGripper Variable tooldata 13:18",
+        ]);
+    }
+
+    #[test]
+    fn parses_modules_in_order() {
+        let sources: Vec<String> = (0..40).map(|idx| format!("MODULE Module{}\nENDMODULE", idx)).collect();
+        let parse = |source: &String| {
+            let tokens = lexer::tokenize(source).map_err(|err| err.to_string())?;
+            parse_tokens(tokens).map(|program| program.modules).map_err(|err| err.to_string())
+        };
+        let modules = parse_all(&sources, parse).unwrap();
+        let names: Vec<String> = modules.iter().map(|module| module.name.clone()).collect();
+        assert_eq!(names, (0..40).map(|idx| format!("Module{}", idx)).collect::<Vec<_>>());
+
+        // The error is that of the first file failing
+        let mut sources = sources;
+        sources[30] = String::from("MODULE Module30\n    PROC\nENDMODULE");
+        sources[10] = String::from("MODULE Module10 x\nENDMODULE");
+        assert!(parse_all(&sources, parse).is_err_and(|err| err.ends_with("at 1:17")));
+    }
+}
@@ -0,0 +1,322 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::interpreter::RuntimeError;
+use crate::lexer::Span;
+use crate::parser::{Program, Storage};
+use crate::variable::Variable;
+
+// ------------------ PERS file -----------------------/
+
+/// Values of the PERS data of a program as TOML, a table per module. The
+/// controller keeps these when the program restarts.
+pub fn store(program: &Program) -> String {
+    let mut text = String::from("# PERS data, by module\n");
+    let mut module = "";
+    for (data_module, decl, slot) in program.module_data() {
+        if decl.storage != Storage::Pers {
+            continue;
+        }
+        let value = match program.variables.get(slot).and_then(to_toml) {
+            Some(value) => value,
+            None => continue,
+        };
+        if data_module.name != module {
+            module = &data_module.name;
+            text.push_str(&format!("\n[{}]\n", module));
+        }
+        text.push_str(&format!("{} = {}\n", decl.name, value));
+    }
+    text
+}
+
+/// Value in TOML, records and arrays are written as arrays. Data without a
+/// value, like a clock, isn't stored.
+fn to_toml(var: &Variable) -> Option<String> {
+    let text = match var {
+        Variable::Bool(value) => value.to_string(),
+        Variable::Num(value) | Variable::Dnum(value) if value.is_nan() => String::from("nan"),
+        Variable::Num(value) | Variable::Dnum(value) if value.is_infinite() => String::from(if *value > 0.0 { "inf" } else { "-inf" }),
+        // Debug keeps a fraction or exponent, so big numbers stay floats
+        Variable::Num(value) | Variable::Dnum(value) => format!("{:?}", value),
+        Variable::Byte(value) => value.to_string(),
+        Variable::Str(text) => toml_string(text),
+        Variable::Record(_, items) | Variable::Array(items) => {
+            let items = items.iter().map(to_toml).collect::<Option<Vec<String>>>()?;
+            format!("[{}]", items.join(", "))
+        },
+        Variable::Void | Variable::Clock { .. } | Variable::Signal(..) | Variable::SyncIdent(_)
+        | Variable::IoDev(_) | Variable::LoadSession(_) | Variable::SocketDev(_) => return None,
+    };
+    Some(text)
+}
+
+/// Basic TOML string, which can't hold a line break or another control
+/// character as it is
+fn toml_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Set the PERS data of a resolved program to the values stored by `store`.
+/// Values of data the program no longer declares are ignored.
+pub fn load(program: &mut Program, text: &str) -> Result<(), String> {
+    load_values(program, text, false)
+}
+
+fn load_values(program: &mut Program, text: &str, strict: bool) -> Result<(), String> {
+    let mut module = String::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| format!("{}: {}", idx + 1, message);
+
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            module = String::from(name.trim());
+            continue;
+        }
+        let (name, value) = match line.split_once('=') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => return Err(error(String::from("Expected name = value"))),
+        };
+        let value = Value { chars: value.chars().collect(), pos: 0 }.read().map_err(error)?;
+
+        let slot = program.module_data().into_iter()
+            .find(|(data_module, decl, _)| data_module.name.eq_ignore_ascii_case(&module)
+                && decl.storage == Storage::Pers
+                && decl.name.eq_ignore_ascii_case(name))
+            .map(|(_, _, slot)| slot);
+        if strict && slot.is_none() {
+            return Err(error(format!("No PERS data {} in module {}", name, module)));
+        }
+        if let Some(var) = slot.and_then(|slot| program.variables.get_mut(slot)) {
+            var.set(value).map_err(|err| error(format!("{}: {}", name, err)))?;
+        }
+    }
+    Ok(())
+}
+
+/// Load the PERS data from a file, a missing file keeps the initial values.
+/// Strict loading fails on values of data the program doesn't declare.
+pub fn load_file(program: &mut Program, path: &Path, strict: bool) -> Result<(), RuntimeError> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(file_error(path, err.to_string())),
+    };
+    load_values(program, &text, strict).map_err(|err| file_error(path, err))
+}
+
+pub fn store_file(program: &Program, path: &Path) -> Result<(), RuntimeError> {
+    fs::write(path, store(program)).map_err(|err| file_error(path, err.to_string()))
+}
+
+fn file_error(path: &Path, message: String) -> RuntimeError {
+    RuntimeError::FileAccess { message: format!("{}: {}", path.display(), message), span: Span::default() }
+}
+
+/// Reads a TOML value, arrays become aggregates that take the shape of the
+/// data they're assigned to
+struct Value {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Value {
+    fn read(mut self) -> Result<Variable, String> {
+        let value = self.value()?;
+        self.skip_space();
+        match self.chars.get(self.pos) {
+            None | Some('#') => Ok(value),
+            Some(c) => Err(format!("Unexpected '{}' after value", c)),
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn value(&mut self) -> Result<Variable, String> {
+        self.skip_space();
+        match self.chars.get(self.pos) {
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                loop {
+                    self.skip_space();
+                    if self.chars.get(self.pos) == Some(&']') {
+                        self.pos += 1;
+                        return Ok(Variable::Record("", items));
+                    }
+                    items.push(self.value()?);
+                    self.skip_space();
+                    match self.chars.get(self.pos) {
+                        Some(',') => self.pos += 1,
+                        Some(']') => (),
+                        _ => return Err(String::from("Expected ',' or ']' in array")),
+                    }
+                }
+            },
+            Some('"') => {
+                self.pos += 1;
+                let mut text = String::new();
+                loop {
+                    match self.chars.get(self.pos) {
+                        Some('"') => break,
+                        Some('\\') => {
+                            self.pos += 1;
+                            match self.chars.get(self.pos) {
+                                Some(c @ ('"' | '\\')) => text.push(*c),
+                                Some('n') => text.push('\n'),
+                                Some('t') => text.push('\t'),
+                                Some('r') => text.push('\r'),
+                                Some('b') => text.push('\u{8}'),
+                                Some('f') => text.push('\u{c}'),
+                                Some(u @ ('u' | 'U')) => {
+                                    let digits = if *u == 'u' { 4 } else { 8 };
+                                    let code: String = self.chars.iter().skip(self.pos + 1).take(digits).collect();
+                                    let hex = code.len() == digits && code.chars().all(|c| c.is_ascii_hexdigit());
+                                    let c = u32::from_str_radix(&code, 16).ok().filter(|_| hex).and_then(char::from_u32)
+                                        .ok_or_else(|| format!("Invalid escape \\{}{}", u, code))?;
+                                    text.push(c);
+                                    self.pos += digits;
+                                },
+                                _ => return Err(String::from("Unsupported escape in string")),
+                            }
+                        },
+                        Some(c) => text.push(*c),
+                        None => return Err(String::from("Unterminated string")),
+                    }
+                    self.pos += 1;
+                }
+                self.pos += 1;
+                Ok(Variable::Str(text))
+            },
+            Some(_) => {
+                let start = self.pos;
+                while self.chars.get(self.pos).is_some_and(|c| !matches!(c, ',' | ']' | '#') && !c.is_whitespace()) {
+                    self.pos += 1;
+                }
+                let word: String = self.chars[start..self.pos].iter().filter(|c| **c != '_').collect();
+                match word.as_str() {
+                    "true" => Ok(Variable::Bool(true)),
+                    "false" => Ok(Variable::Bool(false)),
+                    word => word.trim_start_matches('+').parse().map(Variable::Num).map_err(|_| format!("Invalid value {}", word)),
+                }
+            },
+            None => Err(String::from("Expected value")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler;
+    use crate::host::Host;
+    use crate::interpreter::{self, InterpreterOptions};
+    use crate::lexer;
+    use crate::parser;
+    use crate::resolver::{self, ResolveOptions};
+    use crate::vm;
+
+    const SOURCE: &str = "
+MODULE Cell
+    PERS num nCycles := 0;
+    PERS string sLast := \"\";
+    PERS pos pOffset := [0, 0, 0];
+    PERS byte bySteps{2} := [1, 2];
+    VAR num nRuns := 0;
+    PROC main()
+        nCycles := nCycles + 1;
+        nRuns := nRuns + 1;
+        sLast := \"Cycle \" + ValToStr(nCycles);
+        pOffset := [nCycles * 0.5, 1E30, -2];
+        bySteps{2} := bySteps{2} + 1;
+    ENDPROC
+ENDMODULE";
+
+    fn cell() -> Program {
+        let mut program = parser::parse_tokens(lexer::parse(SOURCE)).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        program
+    }
+
+    #[test]
+    fn pers_data_survives_restarts() {
+        let mut state = String::new();
+        for _ in 0..2 {
+            let mut program = cell();
+            load(&mut program, &state).unwrap();
+            interpreter::run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).into_result().unwrap();
+            state = store(&program);
+        }
+        assert_eq!(state, "# PERS data, by module
+
+[Cell]
+nCycles = 2.0
+sLast = \"Cycle 2\"
+pOffset = [1.0, 1e30, -2.0]
+bySteps = [1, 4]
+");
+
+        let mut program = cell();
+        load(&mut program, "[cell]\nSLAST = \"a \\\"b\\\" \\\\c\" # quoted").unwrap();
+        assert!(store(&program).contains("sLast = \"a \\\"b\\\" \\\\c\"\n"));
+        // Control characters are escaped, so the value stays on its line
+        let text = "Line 1\nLine 2\tTab\r\u{1b}[0m\u{7f} é";
+        program.variables[1] = Variable::Str(String::from(text));
+        let state = store(&program);
+        assert!(state.contains("sLast = \"Line 1\\nLine 2\\tTab\\r\\u001B[0m\\u007F é\"\n"));
+        program.variables[1] = Variable::Str(String::new());
+        load(&mut program, &state).unwrap();
+        assert!(matches!(&program.variables[1], Variable::Str(loaded) if loaded == text));
+        load(&mut program, "[Cell]\nsLast = \"\\b\\f\\U0001F916\"").unwrap();
+        assert!(matches!(&program.variables[1], Variable::Str(loaded) if loaded == "\u{8}\u{c}\u{1F916}"));
+        assert!(load(&mut program, "[Cell]\nsLast = \"\\u12\"").is_err());
+        assert!(load(&mut program, "[Cell]\nsLast = \"\\uD800\"").is_err());
+        assert_eq!(load(&mut program, "[Cell]\nnCycles = \"two\"").unwrap_err(), "2: nCycles: 0:0: ERR_ARGVALERR: Cannot assign string to num");
+        assert!(load(&mut program, "[Cell]\npOffset = [1, 2").is_err());
+        // Data the program no longer has is dropped
+        load(&mut program, "[Cell]\nnRemoved = 1\n[Other]\nnCycles = 5").unwrap();
+        assert_eq!(format!("{:?}", program.variables[0]), "Num(0.0)");
+    }
+
+    #[test]
+    fn pers_file_between_runs() {
+        let path = std::env::temp_dir().join(format!("rapid_rust_pers_{}.toml", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let options = InterpreterOptions { pers_file: Some(path.clone()), ..InterpreterOptions::default() };
+
+        let mut program = cell();
+        interpreter::run(&mut program, "main", &options, &mut Host::new(&mut Vec::new())).into_result().unwrap();
+        let bytecode = compiler::compile(&program).unwrap();
+        let mut program = cell();
+        vm::run(&mut program, &bytecode, "main", &options, &mut Host::new(&mut Vec::new())).unwrap();
+        assert_eq!(format!("{:?}", program.variables[0]), "Num(2.0)");
+        // VAR data start over
+        assert_eq!(format!("{:?}", program.variables[4]), "Num(1.0)");
+
+        fs::write(&path, "[Cell]\nnCycles = [1, 2]\n").unwrap();
+        let err = interpreter::run(&mut cell(), "main", &options, &mut Host::new(&mut Vec::new())).into_result().unwrap_err();
+        assert_eq!(err.name(), "ERR_FILEACC");
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use crate::host::{Clock, Observer};
+
+// ------------------ Profiler -----------------------/
+
+/// Calls and time of a routine. The total time includes the routines it
+/// calls, the self time doesn't.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutineProfile {
+    pub module: String,
+    pub routine: String,
+    pub calls: u64,
+    pub total: Duration,
+    pub self_time: Duration,
+}
+
+/// Routine call being timed
+struct Call {
+    profile: usize,
+    start: Duration,
+    // Time spent in the routines it called
+    callees: Duration,
+}
+
+/// Times the routines of the runs it observes. Give it the clock of the
+/// host for simulated time, or a `SystemClock` to time the interpreter.
+/// A clock that moves on every reading makes the program take longer.
+pub struct Profiler<'c> {
+    clock: &'c dyn Clock,
+    profiles: Vec<RoutineProfile>,
+    calls: Vec<Call>,
+}
+
+impl<'c> Observer for Profiler<'c> {
+    fn enter_routine(&mut self, module: &str, routine: &str) {
+        let profile = match self.profiles.iter().position(|profile| profile.module == module && profile.routine == routine) {
+            Some(profile) => profile,
+            None => {
+                self.profiles.push(RoutineProfile {
+                    module: String::from(module),
+                    routine: String::from(routine),
+                    calls: 0,
+                    total: Duration::ZERO,
+                    self_time: Duration::ZERO,
+                });
+                self.profiles.len() - 1
+            },
+        };
+        self.profiles[profile].calls += 1;
+        self.calls.push(Call { profile, start: self.clock.now(), callees: Duration::ZERO });
+    }
+
+    fn exit_routine(&mut self, _module: &str, _routine: &str) {
+        let call = match self.calls.pop() {
+            Some(call) => call,
+            None => return,
+        };
+        let elapsed = self.clock.now().saturating_sub(call.start);
+        let profile = &mut self.profiles[call.profile];
+        profile.self_time += elapsed.saturating_sub(call.callees);
+        // A recursive call is part of the total of the outer call already
+        if !self.calls.iter().any(|outer| outer.profile == call.profile) {
+            profile.total += elapsed;
+        }
+        if let Some(caller) = self.calls.last_mut() {
+            caller.callees += elapsed;
+        }
+    }
+}
+
+impl<'c> Profiler<'c> {
+    pub fn new(clock: &'c dyn Clock) -> Profiler<'c> {
+        Profiler { clock, profiles: Vec::new(), calls: Vec::new() }
+    }
+
+    /// Routines that were called, the most self time first
+    pub fn profiles(&self) -> Vec<RoutineProfile> {
+        let mut profiles = self.profiles.clone();
+        profiles.sort_by_key(|profile| std::cmp::Reverse(profile.self_time));
+        profiles
+    }
+
+    /// Table of the profiles, times in milliseconds
+    pub fn report(&self) -> String {
+        let mut text = format!("{:<32} {:>8} {:>12} {:>12}\n", "Routine", "Calls", "Total ms", "Self ms");
+        for profile in self.profiles() {
+            let name = format!("{}.{}", profile.module, profile.routine);
+            text.push_str(&format!("{:<32} {:>8} {:>12.3} {:>12.3}\n", name, profile.calls,
+                profile.total.as_secs_f64() * 1000.0, profile.self_time.as_secs_f64() * 1000.0));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler;
+    use crate::host::{Host, ManualClock};
+    use crate::interpreter::InterpreterOptions;
+    use crate::lexer;
+    use crate::parser;
+    use crate::resolver::{self, ResolveOptions};
+    use crate::vm;
+
+    #[test]
+    fn times_routines() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Cycle
+    PROC rGrip()
+        WaitTime 0.2;
+    ENDPROC
+    PROC rPick()
+        rGrip;
+        WaitTime 0.5;
+    ENDPROC
+    FUNC num Fact(num n)
+        IF n <= 1 RETURN 1;
+        WaitTime 0.1;
+        RETURN n * Fact(n - 1);
+    ENDFUNC
+    PROC main()
+        rPick;
+        rPick;
+        WaitTime Fact(3);
+    ENDPROC
+ENDMODULE")).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let bytecode = compiler::compile(&program).unwrap();
+
+        let clock = ManualClock::new(Duration::ZERO);
+        let mut profiler = Profiler::new(&clock);
+        let mut output = Vec::new();
+        let mut host = Host::new(&mut output).with_clock(&clock).with_observer(&mut profiler);
+        vm::run(&mut program, &bytecode, "main", &InterpreterOptions::default(), &mut host).unwrap();
+
+        assert_eq!(profiler.report(), "\
+Routine                             Calls     Total ms      Self ms
+Cycle.main                              1     7600.000     6000.000
+Cycle.rPick                             2     1400.000     1000.000
+Cycle.rGrip                             2      400.000      400.000
+Cycle.Fact                              3      200.000      200.000
+");
+    }
+}
@@ -0,0 +1,219 @@
+use pyo3::exceptions::{PyKeyError, PySyntaxError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyFloat, PyInt, PyList, PyString};
+
+use crate::embed::{self, RunOptions, Session};
+use crate::interpreter::RuntimeError;
+use crate::json::{Json, ToJson};
+use crate::resolver::ResolveOptions;
+use crate::sarif::Finding;
+use crate::variable::Variable;
+
+// Python module of the extension, for offline programming scripts:
+//
+//     import rapid_rust
+//     ast = rapid_rust.parse(source)
+//     program = rapid_rust.Program(source, signals={"diPartReady": "signaldi"})
+//     program.set_input("diPartReady", 1)
+//     result = program.run("main")
+//     print(result["output"], program.get("nCount"))
+//
+// The AST, findings and results are the JSON objects of the command line as
+// dicts and lists.
+
+#[pymodule]
+fn rapid_rust(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(tokenize, module)?)?;
+    module.add_function(wrap_pyfunction!(parse, module)?)?;
+    module.add_function(wrap_pyfunction!(check, module)?)?;
+    module.add_class::<Program>()?;
+    Ok(())
+}
+
+/// Tokens of a module, raises SyntaxError
+#[pyfunction]
+fn tokenize(py: Python<'_>, source: &str) -> PyResult<PyObject> {
+    to_python(py, &embed::tokenize(source).map_err(PySyntaxError::new_err)?)
+}
+
+/// Modules of a source with their spans, raises SyntaxError
+#[pyfunction]
+fn parse(py: Python<'_>, source: &str) -> PyResult<PyObject> {
+    to_python(py, &embed::parse(source).map_err(PySyntaxError::new_err)?)
+}
+
+/// Errors, warnings and lints of a module, with a warning for routine data
+/// that shadows a PERS or CONST of the module when asked
+#[pyfunction]
+#[pyo3(signature = (source, warn_shadowing=false))]
+fn check(py: Python<'_>, source: &str, warn_shadowing: bool) -> PyResult<PyObject> {
+    to_python(py, &embed::check(source, &ResolveOptions { warn_shadowing, ..ResolveOptions::default() }))
+}
+
+// ------------------ Program -----------------------/
+
+/// Program of a module that runs on simulated time, see `embed::Session`
+#[pyclass(unsendable)]
+struct Program {
+    session: Session,
+}
+
+#[pymethods]
+impl Program {
+    /// Load a module with the signals of the I/O board by name and data
+    /// type, like `{"doGripper": "signaldo"}`. Raises SyntaxError or
+    /// ValueError with the errors of the module.
+    #[new]
+    #[pyo3(signature = (source, signals=None))]
+    fn new(source: &str, signals: Option<&Bound<'_, PyDict>>) -> PyResult<Program> {
+        let mut kinds = Vec::new();
+        for (name, data_type) in signals.into_iter().flat_map(|signals| signals.iter()) {
+            let (name, data_type) = (name.extract::<String>()?, data_type.extract::<String>()?);
+            match Variable::from(&data_type) {
+                Ok(Variable::Signal(kind, _)) => kinds.push((name, kind)),
+                _ => return Err(PyValueError::new_err(format!("{} is not a signal type", data_type))),
+            }
+        }
+        match Session::new(source, &kinds) {
+            Ok(session) => Ok(Program { session }),
+            Err(findings) => Err(load_error(&findings)),
+        }
+    }
+
+    /// Run a routine, with the answers of the operator dialogs. Gives the
+    /// output, the moves, the time and the error it stopped with.
+    #[pyo3(signature = (entry="main", max_instructions=1_000_000, answers=Vec::new()))]
+    fn run(&mut self, py: Python<'_>, entry: &str, max_instructions: u64, answers: Vec<f64>) -> PyResult<PyObject> {
+        let options = RunOptions { entry: entry.to_string(), max_instructions, answers, ..RunOptions::default() };
+        to_python(py, &self.session.run(&options))
+    }
+
+    /// Value of the data of a module
+    fn get(&self, py: Python<'_>, name: &str) -> PyResult<PyObject> {
+        let value = self.session.get(name).map_err(|err| PyKeyError::new_err(err.to_string()))?;
+        to_python(py, &value.to_json())
+    }
+
+    /// Assign to the data of a module. Lists are aggregates, for records
+    /// and arrays.
+    fn set(&mut self, name: &str, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        let value = from_python(value)?;
+        self.session.set(name, value).map_err(|err| match err {
+            RuntimeError::UnknownName { .. } => PyKeyError::new_err(err.to_string()),
+            _ => PyValueError::new_err(err.to_string()),
+        })
+    }
+
+    /// Set an input signal
+    fn set_input(&self, name: &str, value: f64) -> PyResult<()> {
+        self.session.set_input(name, value).map_err(|err| PyValueError::new_err(err.to_string()))
+    }
+
+    /// Value of a signal
+    fn signal(&self, name: &str) -> PyResult<f64> {
+        self.session.signal(name).map_err(|err| PyKeyError::new_err(err.to_string()))
+    }
+
+    /// Seconds of simulated time the runs took
+    #[getter]
+    fn time(&self) -> f64 {
+        self.session.time().as_secs_f64()
+    }
+}
+
+/// SyntaxError of the lexer or parser, or ValueError with the errors of the
+/// resolver on a line each
+fn load_error(findings: &[Finding]) -> PyErr {
+    match findings.first() {
+        Some(finding) if finding.rule == "syntax" => PySyntaxError::new_err(embed::error_text(findings)),
+        _ => PyValueError::new_err(embed::error_text(findings)),
+    }
+}
+
+// ------------------ Conversion -----------------------/
+
+fn to_python(py: Python<'_>, json: &Json) -> PyResult<PyObject> {
+    let object = match json {
+        Json::Null => py.None(),
+        Json::Bool(value) => PyBool::new(py, *value).to_owned().into_any().unbind(),
+        // Lines, indices and counts are ints, as Python reads them from JSON
+        Json::Num(value) if value.fract() == 0.0 && value.abs() < 2f64.powi(53) => PyInt::new(py, *value as i64).into_any().unbind(),
+        Json::Num(value) => PyFloat::new(py, *value).into_any().unbind(),
+        Json::Str(text) => PyString::new(py, text).into_any().unbind(),
+        Json::Array(items) => {
+            let items = items.iter().map(|item| to_python(py, item)).collect::<PyResult<Vec<PyObject>>>()?;
+            PyList::new(py, items)?.into_any().unbind()
+        },
+        Json::Object(members) => {
+            let dict = PyDict::new(py);
+            for (name, value) in members {
+                dict.set_item(name, to_python(py, value)?)?;
+            }
+            dict.into_any().unbind()
+        },
+    };
+    Ok(object)
+}
+
+/// Value of a bool, number, string or a list of them
+fn from_python(value: &Bound<'_, PyAny>) -> PyResult<Variable> {
+    // A bool is an int in Python
+    if let Ok(value) = value.extract::<bool>() {
+        return Ok(Variable::Bool(value));
+    }
+    if let Ok(value) = value.extract::<f64>() {
+        return Ok(Variable::Num(value));
+    }
+    if let Ok(text) = value.extract::<String>() {
+        return Ok(Variable::Str(text));
+    }
+    if let Ok(items) = value.extract::<Vec<Bound<'_, PyAny>>>() {
+        return Ok(Variable::Record("", items.iter().map(from_python).collect::<PyResult<Vec<Variable>>>()?));
+    }
+    Err(PyTypeError::new_err(format!("Cannot assign {} to RAPID data", value.get_type().name()?)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SOURCE: &str = "MODULE Cell
+    VAR num nCount := 0;
+    PROC main()
+        WaitDI diPartReady, 1;
+        SetDO doGripper, 1;
+        nCount := nCount + 1;
+        TPWrite \"Parts: \" \\Num:=nCount;
+    ENDPROC
+ENDMODULE";
+
+    #[test]
+    fn drives_a_program() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let findings = check(py, "MODULE Cell\n    PROC main()\n        nCount := 1;\n    ENDPROC\nENDMODULE", false).unwrap();
+            let message: String = findings.bind(py).get_item(0).unwrap().get_item("message").unwrap().extract().unwrap();
+            assert_eq!(message, "Unknown id 'nCount'");
+            assert!(parse(py, "MODULE Cell").unwrap_err().is_instance_of::<PySyntaxError>(py));
+
+            let signals = PyDict::new(py);
+            signals.set_item("diPartReady", "signaldi").unwrap();
+            signals.set_item("doGripper", "signaldo").unwrap();
+            let mut program = Program::new(SOURCE, Some(&signals)).unwrap();
+            program.set_input("diPartReady", 1.0).unwrap();
+            program.set("nCount", PyInt::new(py, 41).as_any()).unwrap();
+            let result = program.run(py, "main", 1000, Vec::new()).unwrap();
+            let output: Vec<String> = result.bind(py).get_item("output").unwrap().extract().unwrap();
+            assert_eq!(output, vec!["Parts: 42"]);
+            assert_eq!(program.get(py, "nCount").unwrap().extract::<i64>(py).unwrap(), 42);
+            assert_eq!(program.signal("doGripper").unwrap(), 1.0);
+
+            let err = program.set("nCount", PyString::new(py, "many").as_any()).unwrap_err();
+            assert!(err.is_instance_of::<PyValueError>(py));
+            assert!(program.get(py, "nMissing").unwrap_err().is_instance_of::<PyKeyError>(py));
+            let signals = PyDict::new(py);
+            signals.set_item("diPartReady", "num").unwrap();
+            assert!(Program::new(SOURCE, Some(&signals)).err().unwrap().is_instance_of::<PyValueError>(py));
+        });
+    }
+}
@@ -0,0 +1,1500 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::builtins::{self, Builtin, HostRoutine};
+use crate::interpreter::RuntimeError;
+use crate::lexer::Span;
+use crate::parser::{Alias, Argument, Arena, Callee, DataDecl, Module, Node, NodeId, Param, ParamMode, Program, Routine, Statement, Storage};
+use crate::variable::Variable;
+
+// ------------------ Diagnostics -----------------------/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    // Name to write instead, for an unknown name close to a known one
+    pub suggestion: Option<String>,
+}
+
+impl Diagnostic {
+    fn error(message: String, span: Span) -> Diagnostic {
+        Diagnostic { severity: Severity::Error, message, span, suggestion: None }
+    }
+
+    fn warning(message: String, span: Span) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, message, span, suggestion: None }
+    }
+
+    fn suggest(mut self, suggestion: Option<String>) -> Diagnostic {
+        self.suggestion = suggestion;
+        self
+    }
+}
+
+/// Known name that an unknown name is most likely a typo of: the fewest
+/// characters to add, remove or change, at most a third of the name
+fn closest<'n>(name: &str, names: impl IntoIterator<Item = &'n str>) -> Option<String> {
+    let name: Vec<char> = name.to_ascii_lowercase().chars().collect();
+    let limit = (name.len() / 3).max(1);
+    names.into_iter()
+        .filter_map(|candidate| {
+            let other: Vec<char> = candidate.to_ascii_lowercase().chars().collect();
+            // Edit distance, a row of the table at a time
+            let mut row: Vec<usize> = (0..=other.len()).collect();
+            for (i, a) in name.iter().enumerate() {
+                let mut previous = row[0];
+                row[0] = i + 1;
+                for (j, b) in other.iter().enumerate() {
+                    let substituted = previous + usize::from(a != b);
+                    previous = row[j + 1];
+                    row[j + 1] = substituted.min(row[j] + 1).min(previous + 1);
+                }
+            }
+            let distance = row[other.len()];
+            (distance <= limit).then_some((distance, candidate))
+        })
+        .min()
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", self.span, severity, self.message)
+    }
+}
+
+// ------------------ Scopes -----------------------/
+
+/// Tiers of the RAPID name lookup, innermost first: routine data shadows
+/// module data, which shadows task-global data of other modules, which
+/// shadows system data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tier {
+    Routine,
+    Module,
+    Task,
+    System,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Binding {
+    Local(usize),
+    Global(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub storage: Storage,
+    pub binding: Binding,
+}
+
+pub struct Scope<'a> {
+    parent: Option<&'a Scope<'a>>,
+    tier: Tier,
+    symbols: HashMap<String, Symbol>,
+}
+
+impl<'a> Scope<'a> {
+    fn new(tier: Tier, parent: Option<&'a Scope<'a>>) -> Scope<'a> {
+        Scope {
+            parent,
+            tier,
+            symbols: HashMap::new(),
+        }
+    }
+
+    /// Declare a symbol in this scope, RAPID identifiers are case-insensitive
+    fn declare(&mut self, decl: &DataDecl, binding: Binding) -> Result<(), Diagnostic> {
+        let key = decl.name.to_ascii_lowercase();
+        if self.symbols.contains_key(&key) {
+            return Err(Diagnostic::error(format!("Duplicate declaration of '{}'", decl.name), decl.span));
+        }
+
+        self.symbols.insert(key, Symbol {
+            name: decl.name.clone(),
+            storage: decl.storage,
+            binding,
+        });
+        Ok(())
+    }
+
+    /// Look up a name, walking outwards through the enclosing tiers
+    pub fn lookup(&self, name: &str) -> Option<(Tier, &Symbol)> {
+        let key = name.to_ascii_lowercase();
+        let mut scope = Some(self);
+        while let Some(current) = scope {
+            if let Some(symbol) = current.symbols.get(&key) {
+                return Some((current.tier, symbol));
+            }
+            scope = current.parent;
+        }
+        None
+    }
+
+    /// Names of the symbols of this scope and the enclosing tiers
+    fn names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        let mut scope = Some(self);
+        while let Some(current) = scope {
+            names.extend(current.symbols.values().map(|symbol| symbol.name.as_str()));
+            scope = current.parent;
+        }
+        names
+    }
+}
+
+/// Call signature of a declared or built-in routine
+#[derive(Debug, Clone)]
+struct Signature {
+    name: String,
+    id: Callee,
+    func: bool,
+    // TRAP routine, only CONNECT takes it
+    trap: bool,
+    params: Vec<Param>,
+}
+
+impl Signature {
+    fn from(routine: &Routine, id: Callee) -> Signature {
+        Signature {
+            name: routine.name.clone(),
+            id,
+            func: routine.return_type.is_some(),
+            trap: routine.trap,
+            params: routine.arguments.clone(),
+        }
+    }
+
+    fn builtin(builtin: &Builtin, idx: usize) -> Signature {
+        Signature {
+            name: String::from(builtin.name),
+            id: Callee::Builtin(idx),
+            func: builtin.return_type.is_some(),
+            trap: false,
+            params: builtin.params.clone(),
+        }
+    }
+
+    fn host(routine: &HostRoutine, idx: usize) -> Signature {
+        Signature {
+            name: routine.name.clone(),
+            id: Callee::Host(idx),
+            func: routine.return_type.is_some(),
+            trap: false,
+            params: routine.params.clone(),
+        }
+    }
+}
+
+/// Routines visible from one module: its own routines, then global routines
+/// of the task, then the routines of the host and the built-in routines
+struct Routines<'a> {
+    module: HashMap<String, Signature>,
+    task: &'a HashMap<String, Signature>,
+    builtins: &'a HashMap<String, Signature>,
+}
+
+impl<'a> Routines<'a> {
+    fn lookup(&self, name: &str) -> Option<&Signature> {
+        let key = name.to_ascii_lowercase();
+        self.module.get(&key)
+            .or_else(|| self.task.get(&key))
+            .or_else(|| self.builtins.get(&key))
+    }
+
+    fn names(&self) -> impl Iterator<Item = &str> {
+        self.module.values().chain(self.task.values()).chain(self.builtins.values()).map(|signature| signature.name.as_str())
+    }
+}
+
+// ------------------ Resolver -----------------------/
+
+#[derive(Debug, Clone, Default)]
+pub struct ResolveOptions {
+    /// Warn when routine data shadows a module-level PERS or CONST
+    pub warn_shadowing: bool,
+    /// Report what the controller doesn't load as errors: long names and
+    /// strings, reserved words as names and routines with too many
+    /// parameters
+    pub controller_limits: bool,
+}
+
+struct Context<'a> {
+    scope: &'a Scope<'a>,
+    routines: &'a Routines<'a>,
+    // Parameters of the routine, in their argument slots
+    params: &'a [Param],
+    diagnostics: &'a mut Vec<Diagnostic>,
+    // Number of declared argument and data slots of the routine
+    slots: usize,
+    // Locals introduced by statements, like FOR loop variables
+    locals: &'a mut Vec<DataDecl>,
+    // Operands of the expressions of the routine
+    nodes: &'a mut Arena,
+}
+
+/// Bind every identifier in the program to a data slot, lay out the global
+/// data and check all routine calls. Returns the warnings on success, or
+/// all diagnostics when any error was found.
+pub fn resolve(program: &mut Program, options: &ResolveOptions) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+    let (globals, diagnostics) = resolve_modules(&mut program.modules, &program.system, &program.host_routines, 0, options);
+    program.variables = globals;
+    if diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+        Err(diagnostics)
+    } else {
+        Ok(diagnostics)
+    }
+}
+
+/// Resolve the routines of the modules from `first` on, after modules were
+/// loaded or unloaded while the program runs. The modules before it are
+/// resolved already and keep their slots. Returns the initial values of the
+/// global data of all modules.
+pub fn link(modules: &mut [Module], system: &[DataDecl], host_routines: &[HostRoutine], first: usize) -> Result<Vec<Variable>, Vec<Diagnostic>> {
+    let (globals, diagnostics) = resolve_modules(modules, system, host_routines, first, &ResolveOptions::default());
+    if diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+        Err(diagnostics)
+    } else {
+        Ok(globals)
+    }
+}
+
+fn resolve_modules(modules: &mut [Module], system_decls: &[DataDecl], host_routines: &[HostRoutine], first: usize, options: &ResolveOptions) -> (Vec<Variable>, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+    let mut globals = Vec::new();
+    if options.controller_limits {
+        for module in modules[first..].iter() {
+            check_limits(module, &mut diagnostics);
+        }
+    }
+    type_aliases(modules, first, &mut diagnostics);
+
+    let mut system = Scope::new(Tier::System, None);
+    for decl in system_decls.iter() {
+        if let Err(err) = system.declare(decl, Binding::Global(globals.len())) {
+            diagnostics.push(err);
+        }
+        globals.push(decl.value.clone());
+    }
+
+    // Module data gets its slots first so routines in any module can see it
+    let mut module_slots = Vec::new();
+    for module in modules.iter() {
+        let mut slots = Vec::new();
+        for decl in module.variables.iter() {
+            slots.push(globals.len());
+            globals.push(decl.value.clone());
+        }
+        module_slots.push(slots);
+    }
+
+    let mut builtin_routines: HashMap<String, Signature> = builtins::builtins().iter().enumerate()
+        .map(|(idx, builtin)| (builtin.name.to_ascii_lowercase(), Signature::builtin(builtin, idx)))
+        .collect();
+    for (idx, routine) in host_routines.iter().enumerate() {
+        builtin_routines.insert(routine.name.to_ascii_lowercase(), Signature::host(routine, idx));
+    }
+
+    // Global data and routines of system modules are in the system tier, the
+    // task can shadow them
+    let mut system_routines = HashMap::new();
+    for (module_idx, (module, slots)) in modules.iter().zip(module_slots.iter()).enumerate().filter(|(_, (module, _))| module.system) {
+        for (decl, slot) in module.variables.iter().zip(slots.iter()).filter(|(decl, _)| !decl.local) {
+            if system.declare(decl, Binding::Global(*slot)).is_err() {
+                diagnostics.push(Diagnostic::error(
+                    format!("Duplicate system data '{}' in module {}", decl.name, module.name), decl.span));
+            }
+        }
+        for (idx, routine) in module.routines.iter().enumerate().filter(|(_, routine)| !routine.local) {
+            let key = routine.name.to_ascii_lowercase();
+            if system_routines.insert(key, Signature::from(routine, Callee::Routine(module_idx, idx))).is_some() {
+                diagnostics.push(Diagnostic::error(
+                    format!("Duplicate system routine '{}' in module {}", routine.name, module.name), routine.span));
+            }
+        }
+    }
+    // They shadow the built-in routines, like the routines of the host
+    builtin_routines.extend(system_routines);
+
+    let mut task = Scope::new(Tier::Task, Some(&system));
+    let mut task_routines = HashMap::new();
+    for (module_idx, (module, slots)) in modules.iter().zip(module_slots.iter()).enumerate().filter(|(_, (module, _))| !module.system) {
+        for (decl, slot) in module.variables.iter().zip(slots.iter()) {
+            if decl.local {
+                continue;
+            }
+            if task.declare(decl, Binding::Global(*slot)).is_err() {
+                diagnostics.push(Diagnostic::error(
+                    format!("Duplicate global data '{}' in module {}", decl.name, module.name), decl.span));
+            }
+        }
+
+        for (idx, routine) in module.routines.iter().enumerate().filter(|(_, routine)| !routine.local) {
+            let key = routine.name.to_ascii_lowercase();
+            if task_routines.insert(key, Signature::from(routine, Callee::Routine(module_idx, idx))).is_some() {
+                diagnostics.push(Diagnostic::error(
+                    format!("Duplicate global routine '{}' in module {}", routine.name, module.name), routine.span));
+            }
+        }
+    }
+
+    // Module data written as expressions gets its value before any routine
+    // is resolved, in the order it's declared
+    let mut module_scopes = Vec::new();
+    for (module, slots) in modules.iter_mut().zip(module_slots.iter()).skip(first) {
+        let mut module_scope = Scope::new(Tier::Module, Some(&task));
+        for (decl, slot) in module.variables.iter().zip(slots.iter()) {
+            if let Err(err) = module_scope.declare(decl, Binding::Global(*slot)) {
+                diagnostics.push(err);
+            }
+        }
+        for (decl, slot) in module.variables.iter_mut().zip(slots.iter()) {
+            let constant = |name: &str| constant(name, &module_scope, Binding::Global(*slot), &globals, &[]);
+            if let Some(value) = initialize(decl, &constant, &mut diagnostics) {
+                globals[*slot] = value;
+            }
+        }
+        module_scopes.push(module_scope);
+    }
+
+    for ((module_idx, module), module_scope) in modules.iter_mut().enumerate().skip(first).zip(module_scopes.iter()) {
+        let mut routines = Routines { module: HashMap::new(), task: &task_routines, builtins: &builtin_routines };
+        for (idx, routine) in module.routines.iter().enumerate() {
+            let key = routine.name.to_ascii_lowercase();
+            if routines.module.insert(key, Signature::from(routine, Callee::Routine(module_idx, idx))).is_some() && routine.local {
+                diagnostics.push(Diagnostic::error(
+                    format!("Duplicate routine '{}' in module {}", routine.name, module.name), routine.span));
+            }
+        }
+
+        for routine in module.routines.iter_mut() {
+            let mut routine_scope = Scope::new(Tier::Routine, Some(module_scope));
+            let decls = routine.arguments.iter().map(|param| &param.decl).chain(routine.variables.iter());
+            for (idx, decl) in decls.enumerate() {
+                if options.warn_shadowing {
+                    if let Some((tier, symbol)) = module_scope.lookup(&decl.name) {
+                        if tier != Tier::System && symbol.storage != Storage::Var {
+                            diagnostics.push(Diagnostic::warning(
+                                format!("'{}' in routine {} shadows module {} '{}'",
+                                    decl.name, routine.name, symbol.storage.keyword(), symbol.name),
+                                decl.span));
+                        }
+                    }
+                }
+                if let Err(err) = routine_scope.declare(decl, Binding::Local(idx)) {
+                    diagnostics.push(err);
+                }
+            }
+            // Like the data of its module, from the data declared before it
+            let first_variable = routine.arguments.len();
+            for idx in 0..routine.variables.len() {
+                if routine.variables[idx].init.is_none() {
+                    continue;
+                }
+                let locals: Vec<Variable> = routine.arguments.iter().map(|param| &param.decl).chain(routine.variables.iter())
+                    .map(|decl| decl.value.clone())
+                    .collect();
+                let constant = |name: &str| constant(name, &routine_scope, Binding::Local(first_variable + idx), &globals, &locals);
+                initialize(&mut routine.variables[idx], &constant, &mut diagnostics);
+            }
+
+            let mut locals = Vec::new();
+            let mut context = Context {
+                scope: &routine_scope,
+                routines: &routines,
+                params: &routine.arguments,
+                diagnostics: &mut diagnostics,
+                slots: routine.arguments.len() + routine.variables.len(),
+                locals: &mut locals,
+                nodes: &mut routine.nodes,
+            };
+            resolve_body(&mut routine.statements, &mut context);
+            if let Some(handler) = routine.handler.as_mut() {
+                resolve_body(handler, &mut context);
+            }
+            routine.variables.append(&mut locals);
+        }
+    }
+    (globals, diagnostics)
+}
+
+/// Give the data of alias types in the modules from `first` on the value of
+/// their base type. Aliases are known in every module, LOCAL ones only in
+/// their own.
+fn type_aliases(modules: &mut [Module], first: usize, diagnostics: &mut Vec<Diagnostic>) {
+    let aliases: Vec<(usize, Alias)> = modules.iter().enumerate()
+        .flat_map(|(idx, module)| module.aliases.iter().map(move |alias| (idx, alias.clone())))
+        .collect();
+    for (module_idx, module) in modules.iter_mut().enumerate().skip(first) {
+        for alias in module.aliases.iter() {
+            if base_type(&alias.base, module_idx, &aliases).is_none() {
+                diagnostics.push(Diagnostic::error(format!("Unknown data type {} of alias {}", alias.base, alias.name), alias.span));
+            }
+        }
+        let routine_data = module.routines.iter_mut()
+            .flat_map(|routine| routine.arguments.iter_mut().map(|param| &mut param.decl).chain(routine.variables.iter_mut()));
+        for decl in module.variables.iter_mut().chain(routine_data) {
+            if Variable::from(&decl.data_type).is_ok() {
+                continue;
+            }
+            let base = match base_type(&decl.data_type, module_idx, &aliases) {
+                Some(base) => base,
+                None => {
+                    diagnostics.push(Diagnostic::error(format!("Unknown data type {} of '{}'", decl.data_type, decl.name), decl.span));
+                    continue;
+                },
+            };
+            match retype(std::mem::replace(&mut decl.value, Variable::Void), &base) {
+                Ok(value) => decl.value = value,
+                Err(_) => diagnostics.push(Diagnostic::error(
+                    format!("Invalid value for {} '{}' of type {}", decl.data_type, decl.name, base.type_name()), decl.span)),
+            }
+        }
+    }
+}
+
+/// Value of the built-in type an alias stands for, following aliases of
+/// aliases
+fn base_type(data_type: &str, module: usize, aliases: &[(usize, Alias)]) -> Option<Variable> {
+    let mut name = data_type;
+    // An alias of itself, in a loop of aliases, has no base type
+    for _ in 0..=aliases.len() {
+        if let Ok(value) = Variable::from(name) {
+            return Some(value);
+        }
+        let (_, alias) = aliases.iter()
+            .find(|(idx, alias)| alias.name.eq_ignore_ascii_case(name) && (!alias.local || *idx == module))?;
+        name = &alias.base;
+    }
+    None
+}
+
+/// Value parsed for data of an alias type as a value of its base type, the
+/// elements of arrays one by one
+fn retype(value: Variable, base: &Variable) -> Result<Variable, RuntimeError> {
+    match value {
+        Variable::Array(items) => items.into_iter().map(|item| retype(item, base)).collect::<Result<_, _>>().map(Variable::Array),
+        Variable::Void => Ok(base.clone()),
+        value => {
+            let mut var = base.clone();
+            var.set(value)?;
+            Ok(var)
+        },
+    }
+}
+
+/// Evaluate the initial value data is written with as an expression into
+/// its value, with the constants `constant` gives for the ids. Returns the
+/// new value, or None if there's no expression or it's invalid.
+fn initialize(decl: &mut DataDecl, constant: &dyn Fn(&str) -> Result<Variable, String>, diagnostics: &mut Vec<Diagnostic>) -> Option<Variable> {
+    let init = decl.init.as_ref()?;
+    let mut value = decl.value.clone();
+    let result = initial_value(&init.expr, &init.nodes, constant)
+        .and_then(|initial| value.set(initial).map_err(|err| runtime_message(&err)));
+    match result {
+        Ok(()) => {
+            decl.value = value.clone();
+            Some(value)
+        },
+        Err(message) => {
+            diagnostics.push(Diagnostic::error(format!("Invalid initial value of '{}': {}", decl.name, message), decl.span));
+            None
+        },
+    }
+}
+
+/// Value of an expression of constants, literals and operators
+fn initial_value(node: &Node, nodes: &Arena, constant: &dyn Fn(&str) -> Result<Variable, String>) -> Result<Variable, String> {
+    let operand = |id: NodeId| initial_value(&nodes[id], nodes, constant);
+    let result = match node {
+        Node::Value(value) => Ok(value.clone()),
+        Node::Aggregate(items) => {
+            let items = items.iter().map(|item| initial_value(item, nodes, constant)).collect::<Result<_, _>>()?;
+            Ok(Variable::Record("", items))
+        },
+        Node::Id(name, _) => return constant(name),
+        Node::OpNeg(id) => Variable::Num(0.0) - operand(*id)?,
+        Node::OpNot(id) => operand(*id)?.condition().map(|value| Variable::Bool(!value)),
+        Node::BinOp { op, lhs, rhs } => Variable::operate(*op, operand(*lhs)?, operand(*rhs)?),
+        _ => return Err(String::from("only constants, literals and operators are allowed")),
+    };
+    result.map_err(|err| runtime_message(&err))
+}
+
+/// Value of the constant `name` in an initial value. Only CONST data declared
+/// before the data with the binding `data` can be used, `globals` and `locals`
+/// hold the values of the data declared so far.
+fn constant(name: &str, scope: &Scope, data: Binding, globals: &[Variable], locals: &[Variable]) -> Result<Variable, String> {
+    let symbol = match scope.lookup(name) {
+        Some((_, symbol)) => symbol,
+        None => return match system_data(name) {
+            Some(Node::Value(value)) => Ok(value),
+            Some(_) => Err(format!("'{}' is not constant", name)),
+            None => Err(format!("Unknown id '{}'", name)),
+        },
+    };
+    if symbol.storage != Storage::Const {
+        return Err(format!("'{}' is not CONST data", symbol.name));
+    }
+    match (symbol.binding, data) {
+        (Binding::Global(slot), Binding::Global(own)) | (Binding::Local(slot), Binding::Local(own)) if slot >= own =>
+            Err(format!("'{}' is not declared before it", symbol.name)),
+        (Binding::Global(slot), _) => Ok(globals[slot].clone()),
+        (Binding::Local(idx), _) => Ok(locals[idx].clone()),
+    }
+}
+
+/// Message of an error evaluating an initial value, without the span and
+/// error name of the runtime error
+fn runtime_message(err: &RuntimeError) -> String {
+    match err {
+        RuntimeError::TypeMismatch { message, .. } => message.to_string(),
+        RuntimeError::DivZero { .. } => String::from("Division by zero"),
+        err => err.to_string(),
+    }
+}
+
+fn resolve_node(node: &mut Node, context: &mut Context) {
+    let present = match node {
+        Node::FuncCall { name, args, span, .. } if name.eq_ignore_ascii_case("Present") => Some(check_present(args, *span, context)),
+        _ => None,
+    };
+    if let Some(slot) = present {
+        *node = slot.map_or(Node::Value(Variable::Bool(false)), Node::Present);
+        return;
+    }
+    let storage = match node {
+        Node::FuncCall { name, .. } if name.eq_ignore_ascii_case("IsPers") => Some(Storage::Pers),
+        Node::FuncCall { name, .. } if name.eq_ignore_ascii_case("IsVar") => Some(Storage::Var),
+        _ => None,
+    };
+    if let (Some(storage), Node::FuncCall { name, args, span, .. }) = (storage, &*node) {
+        *node = check_storage(name, args, storage, *span, context);
+        return;
+    }
+
+    match node {
+        Node::Assign { lhs, rhs } => {
+            if let Some(Node::Id(name, span)) = data_object(&context.nodes[*lhs], context.nodes) {
+                match context.scope.lookup(name) {
+                    Some((_, symbol)) if symbol.storage == Storage::Const => {
+                        context.diagnostics.push(Diagnostic::error(format!("Cannot assign to CONST '{}'", symbol.name), *span));
+                    },
+                    None if system_data(name).is_some() => {
+                        context.diagnostics.push(Diagnostic::error(format!("Cannot assign to read-only '{}'", name), *span));
+                    },
+                    _ => (),
+                }
+            }
+            resolve_operand(*lhs, context);
+            resolve_operand(*rhs, context);
+        },
+        Node::BinOp { lhs, rhs, .. } => {
+            resolve_operand(*lhs, context);
+            resolve_operand(*rhs, context);
+        },
+        Node::OpNeg(node) | Node::OpNot(node) => resolve_operand(*node, context),
+        Node::Print { text, arg } => {
+            resolve_operand(*text, context);
+            if let Some((_, node)) = arg {
+                resolve_operand(*node, context);
+            }
+        },
+        Node::Aggregate(values) => {
+            for value in values.iter_mut() {
+                resolve_node(value, context);
+            }
+        },
+        Node::If { branches, otherwise } => {
+            for (condition, body) in branches.iter_mut() {
+                resolve_node(condition, context);
+                resolve_body(body, context);
+            }
+            resolve_body(otherwise, context);
+        },
+        Node::While { condition, body } => {
+            resolve_operand(*condition, context);
+            resolve_body(body, context);
+        },
+        Node::For { var, from, to, step, body } => {
+            resolve_operand(*from, context);
+            resolve_operand(*to, context);
+            if let Some(step) = step {
+                resolve_operand(*step, context);
+            }
+
+            // The loop variable is a num local to the loop body
+            let (name, span) = match &context.nodes[*var] {
+                Node::Id(name, span) => (name.clone(), *span),
+                _ => return,
+            };
+            let slot = context.slots + context.locals.len();
+            let decl = DataDecl {
+                name,
+                data_type: String::from("num"),
+                storage: Storage::Var,
+                local: false,
+                value: Variable::Num(0.0),
+                init: None,
+                span,
+            };
+
+            let mut scope = Scope::new(Tier::Routine, Some(context.scope));
+            if let Err(err) = scope.declare(&decl, Binding::Local(slot)) {
+                context.diagnostics.push(err);
+            }
+            context.locals.push(decl);
+            context.nodes[*var] = Node::Var(slot);
+
+            let mut inner = Context {
+                scope: &scope,
+                routines: context.routines,
+                params: context.params,
+                diagnostics: context.diagnostics,
+                slots: context.slots,
+                locals: context.locals,
+                nodes: context.nodes,
+            };
+            resolve_body(body, &mut inner);
+        },
+        Node::Test { value, cases, default } => {
+            resolve_operand(*value, context);
+            for (values, body) in cases.iter_mut() {
+                for value in values.iter_mut() {
+                    resolve_node(value, context);
+                }
+                resolve_body(body, context);
+            }
+            resolve_body(default, context);
+        },
+        Node::Return(value) | Node::Raise(value) => {
+            if let Some(node) = value {
+                resolve_operand(*node, context);
+            }
+        },
+        Node::Connect { interrupt, trap, span, target } => {
+            check_interrupt(*interrupt, *span, context);
+            resolve_operand(*interrupt, context);
+            *target = match context.routines.lookup(trap) {
+                Some(signature) if signature.trap => Some(signature.id),
+                Some(signature) => {
+                    context.diagnostics.push(Diagnostic::error(format!("'{}' is not a TRAP routine", signature.name), *span));
+                    None
+                },
+                None => {
+                    let suggestion = closest(trap, context.routines.names());
+                    context.diagnostics.push(Diagnostic::error(format!("Unknown routine '{}'", trap), *span).suggest(suggestion));
+                    None
+                },
+            };
+        },
+        Node::ProcCall { name, args, span, target } => *target = check_call(name, args, *span, false, context),
+        Node::FuncCall { name, args, span, target } => *target = check_call(name, args, *span, true, context),
+        // The procedure is only known once the name is evaluated, the
+        // arguments are checked then
+        Node::LateCall { name, args, .. } => {
+            resolve_operand(*name, context);
+            for arg in args.iter_mut() {
+                passes_value(arg, context);
+                if let Some(value) = arg.value.as_mut() {
+                    resolve_node(value, context);
+                }
+            }
+        },
+        Node::Id(name, span) => {
+            *node = match context.scope.lookup(name) {
+                Some((_, Symbol { binding: Binding::Local(idx), .. })) => Node::Var(*idx),
+                Some((_, Symbol { binding: Binding::Global(idx), .. })) => Node::Global(*idx),
+                None => match system_data(name) {
+                    Some(node) => node,
+                    None => {
+                        let suggestion = closest(name, context.scope.names());
+                        context.diagnostics.push(Diagnostic::error(format!("Unknown id '{}'", name), *span).suggest(suggestion));
+                        return;
+                    },
+                },
+            };
+        },
+        Node::Index { base, indices } => {
+            resolve_operand(*base, context);
+            for index in indices.iter_mut() {
+                resolve_node(index, context);
+            }
+        },
+        Node::Value(_) | Node::Var(_) | Node::Global(_) | Node::Present(_) | Node::IsStorage(..) | Node::Errno | Node::Retry | Node::TryNext => (),
+    }
+}
+
+/// Resolve a node of the arena, taken out while it's resolved
+fn resolve_operand(id: NodeId, context: &mut Context) {
+    let mut node = context.nodes.take(id);
+    resolve_node(&mut node, context);
+    context.nodes.put(id, node);
+}
+
+/// Read-only data of the system that the program doesn't declare: ERRNO,
+/// the error constants it's compared with, the math constants, the character
+/// sets of the string functions and the predefined motion data
+fn system_data(name: &str) -> Option<Node> {
+    if name.eq_ignore_ascii_case("ERRNO") {
+        return Some(Node::Errno);
+    }
+    builtins::error_number(name).map(|errno| Node::Value(Variable::Num(errno as f64)))
+        .or_else(|| builtins::math_data(name).map(Node::Value))
+        .or_else(|| builtins::string_data(name).map(Node::Value))
+        .or_else(|| builtins::motion_data(name).map(Node::Value))
+        .or_else(|| builtins::file_data(name).map(Node::Value))
+        .or_else(|| builtins::socket_data(name).map(Node::Value))
+}
+
+/// Identifier of the data object an assignment or argument refers to, the
+/// array for an array element
+fn data_object<'a>(node: &'a Node, nodes: &'a Arena) -> Option<&'a Node> {
+    match node {
+        Node::Id(..) => Some(node),
+        Node::Index { base, .. } => data_object(&nodes[*base], nodes),
+        _ => None,
+    }
+}
+
+/// CONNECT stores the interrupt number in VAR data, it can't be PERS or CONST
+fn check_interrupt(interrupt: NodeId, span: Span, context: &mut Context) {
+    let (name, span) = match data_object(&context.nodes[interrupt], context.nodes) {
+        Some(Node::Id(name, span)) => (name, *span),
+        _ => {
+            context.diagnostics.push(Diagnostic::error(String::from("CONNECT takes intnum data"), span));
+            return;
+        },
+    };
+    match context.scope.lookup(name) {
+        Some((_, symbol)) if symbol.storage != Storage::Var => {
+            let message = format!("{} data '{}' cannot be connected, the interrupt number needs VAR data", symbol.storage.keyword(), symbol.name);
+            context.diagnostics.push(Diagnostic::error(message, span));
+        },
+        None if system_data(name).is_some() => {
+            context.diagnostics.push(Diagnostic::error(format!("Cannot connect read-only '{}'", name), span));
+        },
+        _ => (),
+    }
+}
+
+/// `Present(OptPar)` takes an optional parameter of the routine, returns its argument slot
+fn check_present(args: &[Argument], span: Span, context: &mut Context) -> Option<usize> {
+    let name = match args {
+        [Argument { name: None, value: Some(Node::Id(name, _)), .. }] => name,
+        _ => {
+            context.diagnostics.push(Diagnostic::error(String::from("Present takes one optional parameter"), span));
+            return None;
+        },
+    };
+    let slot = context.params.iter().position(|param| param.decl.name.eq_ignore_ascii_case(name));
+    match slot {
+        Some(slot) if context.params[slot].optional => Some(slot),
+        _ => {
+            context.diagnostics.push(Diagnostic::error(format!("'{}' is not an optional parameter", name), span));
+            None
+        },
+    }
+}
+
+/// `IsPers(DatObj)` and `IsVar(DatObj)` take data of the routine or of a
+/// module, whether it's declared with the storage. For an INOUT parameter
+/// that's up to the data passed for it.
+fn check_storage(function: &str, args: &[Argument], storage: Storage, span: Span, context: &mut Context) -> Node {
+    let name = match args {
+        [Argument { name: None, value: Some(Node::Id(name, _)), .. }] => name,
+        _ => {
+            context.diagnostics.push(Diagnostic::error(format!("{} takes one data object", function), span));
+            return Node::Value(Variable::Bool(false));
+        },
+    };
+    let declared = match context.scope.lookup(name) {
+        Some((_, Symbol { binding: Binding::Local(slot), .. })) if *slot < context.params.len() => match context.params[*slot].mode {
+            ParamMode::InOut => return Node::IsStorage(*slot, storage),
+            ParamMode::Pers => Storage::Pers,
+            ParamMode::Var | ParamMode::In => Storage::Var,
+        },
+        Some((_, symbol)) => symbol.storage,
+        None => {
+            let suggestion = closest(name, context.scope.names());
+            context.diagnostics.push(Diagnostic::error(format!("Unknown id '{}'", name), span).suggest(suggestion));
+            return Node::Value(Variable::Bool(false));
+        },
+    };
+    Node::Value(Variable::Bool(declared == storage))
+}
+
+fn resolve_body(body: &mut [Statement], context: &mut Context) {
+    for statement in body.iter_mut() {
+        resolve_node(&mut statement.node, context);
+    }
+}
+
+/// Check the arguments of a call against the signature of the called routine, returns the callee
+fn check_call(name: &str, args: &mut [Argument], span: Span, func: bool, context: &mut Context) -> Option<Callee> {
+    let signature = match context.routines.lookup(name) {
+        Some(signature) => signature,
+        None => {
+            let suggestion = closest(name, context.routines.names());
+            context.diagnostics.push(Diagnostic::error(format!("Unknown routine '{}'", name), span).suggest(suggestion));
+            return None;
+        },
+    };
+
+    if signature.trap {
+        context.diagnostics.push(Diagnostic::error(format!("TRAP '{}' cannot be called, CONNECT it to an interrupt", signature.name), span));
+    } else if signature.func != func {
+        let message = if func {
+            format!("PROC '{}' cannot be used in an expression", signature.name)
+        } else {
+            format!("FUNC '{}' cannot be called as a procedure", signature.name)
+        };
+        context.diagnostics.push(Diagnostic::error(message, span));
+    }
+
+    let required: Vec<&Param> = signature.params.iter().filter(|param| !param.optional).collect();
+    let mut positional = 0;
+    let mut used_groups = Vec::new();
+
+    for arg in args.iter_mut() {
+        let param = match &arg.name {
+            None => {
+                positional += 1;
+                match required.get(positional - 1) {
+                    Some(param) => *param,
+                    None => {
+                        context.diagnostics.push(Diagnostic::error(
+                            format!("Too many arguments in call to '{}'", signature.name), arg.span));
+                        continue;
+                    },
+                }
+            },
+            Some(arg_name) => {
+                let value = passes_value(arg, context);
+                let param = signature.params.iter().find(|param| param.decl.name.eq_ignore_ascii_case(arg_name));
+                let message = match param {
+                    None => format!("'{}' has no parameter \\{}", signature.name, arg_name),
+                    Some(param) if !param.optional => format!("Parameter '{}' of '{}' is not optional", param.decl.name, signature.name),
+                    Some(param) if used_groups.contains(&param.group) => format!("Argument \\{} conflicts with an earlier argument to '{}'", arg_name, signature.name),
+                    Some(param) if param.decl.data_type.eq_ignore_ascii_case("switch") && value => format!("Switch \\{} does not take a value", arg_name),
+                    Some(param) if !param.decl.data_type.eq_ignore_ascii_case("switch") && !value => format!("Argument \\{} requires a value", arg_name),
+                    Some(param) => {
+                        used_groups.push(param.group);
+                        String::new()
+                    },
+                };
+                match param {
+                    Some(param) if message.is_empty() => param,
+                    _ => {
+                        context.diagnostics.push(Diagnostic::error(message, arg.span));
+                        continue;
+                    },
+                }
+            },
+        };
+
+        check_mode(param, arg, &signature.name, context);
+    }
+
+    if let Some(param) = required.get(positional) {
+        context.diagnostics.push(Diagnostic::error(
+            format!("Missing argument '{}' in call to '{}'", param.decl.name, signature.name), span));
+    }
+
+    for arg in args.iter_mut() {
+        if let Some(value) = arg.value.as_mut() {
+            resolve_node(value, context);
+        }
+    }
+
+    Some(signature.id)
+}
+
+/// Whether an argument passes a value. A conditional argument like
+/// `\Speed?nSpeed` names an optional parameter of the routine and passes
+/// its value, nothing when that's a switch.
+fn passes_value(arg: &Argument, context: &mut Context) -> bool {
+    let name = match (&arg.value, arg.conditional) {
+        (Some(Node::Id(name, _)), true) => name,
+        (value, _) => return value.is_some(),
+    };
+    match context.params.iter().find(|param| param.decl.name.eq_ignore_ascii_case(name)) {
+        Some(param) if param.optional => !param.decl.data_type.eq_ignore_ascii_case("switch"),
+        _ => {
+            context.diagnostics.push(Diagnostic::error(format!("'{}' is not an optional parameter", name), arg.span));
+            true
+        },
+    }
+}
+
+/// VAR, PERS and INOUT parameters need a writable data object of the right storage class
+fn check_mode(param: &Param, arg: &Argument, routine: &str, context: &mut Context) {
+    let allowed: &[Storage] = match param.mode {
+        ParamMode::In => return,
+        ParamMode::Var => &[Storage::Var],
+        ParamMode::Pers => &[Storage::Pers],
+        ParamMode::InOut => &[Storage::Var, Storage::Pers],
+    };
+
+    let object = arg.value.as_ref().and_then(|value| data_object(value, context.nodes));
+    let storage = match object {
+        Some(Node::Id(name, _)) => context.scope.lookup(name).map(|(_, symbol)| symbol.storage),
+        _ => None,
+    };
+
+    let message = match (storage, object) {
+        (Some(storage), _) if allowed.contains(&storage) => return,
+        (None, Some(Node::Id(name, _))) if system_data(name).is_some() => format!("Read-only '{}' cannot be passed to {} parameter '{}' of '{}'",
+            name, param.mode.keyword(), param.decl.name, routine),
+        // Unknown ids are reported when the argument is resolved
+        (None, Some(_)) => return,
+        (Some(storage), _) => format!("{} data cannot be passed to {} parameter '{}' of '{}'",
+            storage.keyword(), param.mode.keyword(), param.decl.name, routine),
+        (None, None) => format!("Argument for {} parameter '{}' of '{}' must be a data object",
+            param.mode.keyword(), param.decl.name, routine),
+    };
+    context.diagnostics.push(Diagnostic::error(message, arg.span));
+}
+
+// ------------------ Controller limits -----------------------/
+
+// Longest name and string literal the controller takes
+const MAX_NAME: usize = 32;
+const MAX_STRING: usize = 80;
+// Most parameters of a routine
+const MAX_PARAMS: usize = 32;
+
+/// Reserved words of RAPID that the parser takes as names, because this
+/// implementation doesn't use them
+const RESERVED: &[&str] = &[
+    "BACKWARD", "ENDRECORD", "EXIT", "GOTO", "MOD", "MODULE", "NOSTEPIN", "NOVIEW",
+    "READONLY", "RECORD", "SYSMODULE", "UNDO", "VIEWONLY",
+];
+
+/// Errors for what parses here but doesn't load on the controller
+fn check_limits(module: &Module, diagnostics: &mut Vec<Diagnostic>) {
+    check_name(&module.name, module.span, diagnostics);
+    for alias in module.aliases.iter() {
+        check_name(&alias.name, alias.span, diagnostics);
+    }
+    for decl in module.variables.iter() {
+        check_name(&decl.name, decl.span, diagnostics);
+        check_string(&decl.value, decl.span, diagnostics);
+    }
+    for routine in module.routines.iter() {
+        check_name(&routine.name, routine.span, diagnostics);
+        if routine.arguments.len() > MAX_PARAMS {
+            let message = format!("'{}' has more than {} parameters", routine.name, MAX_PARAMS);
+            diagnostics.push(Diagnostic::error(message, routine.span));
+        }
+        for decl in routine.arguments.iter().map(|param| &param.decl).chain(routine.variables.iter()) {
+            check_name(&decl.name, decl.span, diagnostics);
+            check_string(&decl.value, decl.span, diagnostics);
+        }
+        for (statement, node) in routine_nodes(routine) {
+            match node {
+                Node::For { var, .. } => if let Node::Id(var, span) = &routine.nodes[*var] {
+                    check_name(var, *span, diagnostics);
+                },
+                Node::Value(value) => check_string(value, statement.span, diagnostics),
+                _ => (),
+            }
+        }
+    }
+}
+
+fn check_name(name: &str, span: Span, diagnostics: &mut Vec<Diagnostic>) {
+    if name.chars().count() > MAX_NAME {
+        diagnostics.push(Diagnostic::error(format!("Name '{}' is longer than {} characters", name, MAX_NAME), span));
+    } else if RESERVED.iter().any(|word| word.eq_ignore_ascii_case(name)) {
+        diagnostics.push(Diagnostic::error(format!("'{}' is a reserved word", name), span));
+    }
+}
+
+/// Strings of a value, the elements of arrays and records included
+fn check_string(value: &Variable, span: Span, diagnostics: &mut Vec<Diagnostic>) {
+    match value {
+        Variable::Str(text) if text.chars().count() > MAX_STRING => {
+            diagnostics.push(Diagnostic::error(format!("String is longer than {} characters", MAX_STRING), span));
+        },
+        Variable::Array(items) | Variable::Record(_, items) => {
+            for item in items.iter() {
+                check_string(item, span, diagnostics);
+            }
+        },
+        _ => (),
+    }
+}
+
+/// Every node of the statements of a routine, ERROR handler included,
+/// with the statement it's in
+fn routine_nodes(routine: &Routine) -> Vec<(&Statement, &Node)> {
+    fn add<'a>(body: &'a [Statement], nodes: &'a Arena, all: &mut Vec<(&'a Statement, &'a Node)>) {
+        for statement in body {
+            let first = all.len();
+            all.push((statement, &statement.node));
+            let mut idx = first;
+            while idx < all.len() {
+                let children = all[idx].1.children(nodes);
+                all.extend(children.into_iter().map(|node| (statement, node)));
+                idx += 1;
+            }
+            for inner in statement.node.bodies() {
+                add(inner, nodes, all);
+            }
+        }
+    }
+    let mut all = Vec::new();
+    add(&routine.statements, &routine.nodes, &mut all);
+    if let Some(handler) = &routine.handler {
+        add(handler, &routine.nodes, &mut all);
+    }
+    all
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer;
+    use crate::parser;
+
+    fn resolve_source(source: &str, options: &ResolveOptions) -> Result<(Program, Vec<Diagnostic>), Vec<Diagnostic>> {
+        let mut program = parser::parse_tokens(lexer::parse(source)).unwrap();
+        let warnings = resolve(&mut program, options)?;
+        Ok((program, warnings))
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        match resolve_source(source, &ResolveOptions::default()) {
+            Ok(_) => Vec::new(),
+            Err(diagnostics) => diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn local_shadows_module_data() {
+        let source = "
+MODULE Shadow
+    VAR num nValue := 1;
+    PROC rTest()
+        VAR num nValue := 5;
+        nValue := 7;
+    ENDPROC
+ENDMODULE";
+        let (program, warnings) = resolve_source(source, &ResolveOptions::default()).unwrap();
+        assert!(warnings.is_empty());
+        let routine = &program.modules[0].routines[0];
+        match &routine.statements[0].node {
+            Node::Assign { lhs, .. } => assert!(matches!(routine.nodes[*lhs], Node::Var(0))),
+            node => panic!("Unexpected node {:?}", node),
+        }
+    }
+
+    #[test]
+    fn module_data_shadows_system_data() {
+        let mut program = parser::parse_tokens(lexer::parse("
+MODULE Shadow
+    LOCAL PERS num nSpeed := 100;
+    PROC rTest()
+        nSpeed := 50;
+    ENDPROC
+ENDMODULE")).unwrap();
+        program.system.push(DataDecl {
+            name: String::from("nSpeed"),
+            data_type: String::from("num"),
+            storage: Storage::Pers,
+            local: false,
+            value: Variable::Num(0.0),
+            init: None,
+            span: Span::default(),
+        });
+        resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let routine = &program.modules[0].routines[0];
+        match &routine.statements[0].node {
+            Node::Assign { lhs, .. } => assert!(matches!(routine.nodes[*lhs], Node::Global(1))),
+            node => panic!("Unexpected node {:?}", node),
+        }
+    }
+
+    #[test]
+    fn system_modules_are_below_the_task() {
+        let source = "
+MODULE user(SYSMODULE, NOSTEPIN)
+    PERS tooldata tGripper := [TRUE, [[0, 0, 100], [1, 0, 0, 0]], [1, [0, 0, 1], [1, 0, 0, 0], 0, 0, 0]];
+    PERS num nSpeed := 100;
+    LOCAL VAR num nHidden := 0;
+    PROC rHome()
+        nHidden := nSpeed;
+    ENDPROC
+ENDMODULE
+MODULE Cell
+    PERS num nSpeed := 50;
+    PROC main()
+        VAR tooldata tCurrent;
+        tCurrent := tGripper;
+        nSpeed := 20;
+        rHome;
+    ENDPROC
+ENDMODULE";
+        let (program, _) = resolve_source(source, &ResolveOptions::default()).unwrap();
+        let routine = &program.modules[1].routines[0];
+        match &routine.statements[1].node {
+            // The task data shadows the system data of the same name
+            Node::Assign { lhs, .. } => assert!(matches!(routine.nodes[*lhs], Node::Global(3))),
+            node => panic!("Unexpected node {:?}", node),
+        }
+        assert!(program.modules[0].system);
+        assert_eq!(program.modules[0].attributes, vec!["SYSMODULE", "NOSTEPIN"]);
+
+        assert_eq!(errors(&source.replace("tGripper;", "tGripper; nHidden := 1;")), vec!["14:31: error: Unknown id 'nHidden'"]);
+        assert_eq!(errors(&format!("{}\nMODULE base(SYSMODULE)\n    PERS num nSpeed := 1;\nENDMODULE", source)), vec![
+            "20:14: error: Duplicate system data 'nSpeed' in module base",
+        ]);
+    }
+
+    #[test]
+    fn shadowing_pers_warns_when_enabled() {
+        let source = "
+MODULE Shadow
+    PERS num nCount := 0;
+    CONST num nMax := 10;
+    PROC rTest()
+        VAR num nCount := 1;
+        VAR num nmax := 2;
+    ENDPROC
+ENDMODULE";
+        let (_, warnings) = resolve_source(source, &ResolveOptions::default()).unwrap();
+        assert!(warnings.is_empty());
+
+        let (_, warnings) = resolve_source(source, &ResolveOptions { warn_shadowing: true, ..ResolveOptions::default() }).unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].span.line, 6);
+        assert_eq!(warnings[1].message, "'nmax' in routine rTest shadows module CONST 'nMax'");
+    }
+
+    #[test]
+    fn unknown_id_is_an_error() {
+        let source = "
+MODULE Shadow
+    PROC rTest()
+        nMissing := 1;
+    ENDPROC
+ENDMODULE";
+        assert_eq!(errors(source), vec!["4:9: error: Unknown id 'nMissing'"]);
+    }
+
+    #[test]
+    fn call_arity_is_checked() {
+        let source = "
+MODULE Calls
+    PROC rMove(num nX, num nY, \\num nSpeed | switch Fast, \\switch Slow)
+    ENDPROC
+    FUNC num Twice(num nValue)
+        RETURN nValue * 2;
+    ENDFUNC
+    PROC rTest()
+        rMove 1, 2;
+        rMove 1, 2 \\nSpeed:=3, \\Slow;
+        rMove 1;
+        rMove 1, 2, 3;
+        rMove 1, 2 \\nSpeed:=3 \\Fast;
+        rMove 1, 2 \\Slow:=1;
+        rMove 1, 2 \\Other;
+        rMove 1, Twice(1, 2);
+        Twice 1;
+    ENDPROC
+ENDMODULE";
+        assert_eq!(errors(source), vec![
+            "11:9: error: Missing argument 'nY' in call to 'rMove'",
+            "12:21: error: Too many arguments in call to 'rMove'",
+            "13:31: error: Argument \\Fast conflicts with an earlier argument to 'rMove'",
+            "14:20: error: Switch \\Slow does not take a value",
+            "15:20: error: 'rMove' has no parameter \\Other",
+            "16:27: error: Too many arguments in call to 'Twice'",
+            "17:9: error: FUNC 'Twice' cannot be called as a procedure",
+        ]);
+    }
+
+    #[test]
+    fn argument_modes_are_checked() {
+        let source = "
+MODULE Modes
+    PERS num nPers := 0;
+    CONST num nConst := 1;
+    PROC rIncr(INOUT num nValue)
+    ENDPROC
+    PROC rStore(PERS num nValue)
+    ENDPROC
+    PROC rTest()
+        VAR num nVar := 0;
+        rIncr nVar;
+        rIncr nPers;
+        rIncr nConst;
+        rIncr nVar + 1;
+        rStore nVar;
+        nConst := 2;
+    ENDPROC
+ENDMODULE";
+        assert_eq!(errors(source), vec![
+            "13:15: error: CONST data cannot be passed to INOUT parameter 'nValue' of 'rIncr'",
+            "14:15: error: Argument for INOUT parameter 'nValue' of 'rIncr' must be a data object",
+            "15:16: error: VAR data cannot be passed to PERS parameter 'nValue' of 'rStore'",
+            "16:9: error: Cannot assign to CONST 'nConst'",
+        ]);
+    }
+
+    #[test]
+    fn present_takes_optional_parameters() {
+        let source = "
+MODULE Present
+    PROC rMove(num nX, \\num nSpeed)
+        IF Present(nSpeed) nX := 1;
+        IF Present(nX) nX := 2;
+        IF Present(nSpeed, nX) nX := 3;
+    ENDPROC
+ENDMODULE";
+        assert_eq!(errors(source), vec![
+            "5:12: error: 'nX' is not an optional parameter",
+            "6:12: error: Present takes one optional parameter",
+        ]);
+    }
+
+    #[test]
+    fn conditional_arguments_name_optional_parameters() {
+        let source = "
+MODULE Conditional
+    PROC rMove(num nX, \\num nSpeed, \\switch Fast)
+    ENDPROC
+    PROC rPath(num nX, \\num nSpeed, \\switch Fast)
+        rMove nX \\nSpeed?nSpeed \\Fast?Fast;
+        rMove nX \\nSpeed?nX;
+        rMove nX \\Fast?nSpeed;
+        rMove nX \\nSpeed?Fast;
+    ENDPROC
+ENDMODULE";
+        assert_eq!(errors(source), vec![
+            "7:18: error: 'nX' is not an optional parameter",
+            "8:18: error: Switch \\Fast does not take a value",
+            "9:18: error: Argument \\nSpeed requires a value",
+        ]);
+    }
+
+    #[test]
+    fn strict_mode_holds_to_controller_limits() {
+        let source = format!("
+MODULE Limits
+    VAR string sLong := \"{}\";
+    VAR num nThisNameIsMuchTooLongForTheController := 0;
+    VAR num Record := 0;
+    PROC rTest()
+        TPWrite \"{}\";
+        FOR goto FROM 1 TO 2 DO
+        ENDFOR
+    ENDPROC
+    PROC rMany({})
+    ENDPROC
+ENDMODULE", "x".repeat(81), "x".repeat(80), (0..33).map(|idx| format!("num n{}", idx)).collect::<Vec<_>>().join(", "));
+        assert!(resolve_source(&source, &ResolveOptions::default()).is_ok());
+        let diagnostics = resolve_source(&source, &ResolveOptions { controller_limits: true, ..ResolveOptions::default() }).err().unwrap();
+        let messages: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(messages, vec![
+            "3:16: error: String is longer than 80 characters",
+            "4:13: error: Name 'nThisNameIsMuchTooLongForTheController' is longer than 32 characters",
+            "5:13: error: 'Record' is a reserved word",
+            "8:13: error: 'goto' is a reserved word",
+            "11:10: error: 'rMany' has more than 32 parameters",
+        ]);
+    }
+
+    #[test]
+    fn storage_takes_data() {
+        let source = "
+MODULE Storage
+    VAR num nX := 0;
+    PROC rCheck(INOUT num nData)
+        IF IsPers(nData) nX := 1;
+        IF IsVar(nX + 1) nX := 2;
+        IF IsPers(nMissing) nX := 3;
+    ENDPROC
+ENDMODULE";
+        assert_eq!(errors(source), vec![
+            "6:12: error: IsVar takes one data object",
+            "7:12: error: Unknown id 'nMissing'",
+        ]);
+    }
+
+    #[test]
+    fn errno_is_read_only() {
+        let source = "
+MODULE Errors
+    VAR num ERR_INDEX := 0;
+    PROC rRead(num nErrno, VAR num nOut)
+        nOut := ERRNO;
+    ERROR
+        nErrno := ERR_DIVZERO;
+        ERRNO := 0;
+        ERR_INDEX := 1;
+        rRead ERRNO, ERRNO;
+    ENDPROC
+ENDMODULE";
+        assert_eq!(errors(source), vec![
+            "8:9: error: Cannot assign to read-only 'ERRNO'",
+            "10:22: error: Read-only 'ERRNO' cannot be passed to VAR parameter 'nOut' of 'rRead'",
+        ]);
+    }
+
+    #[test]
+    fn connect_takes_var_data_and_a_trap() {
+        let source = "
+MODULE Traps
+    VAR intnum irPart;
+    CONST num nConst := 0;
+    TRAP tPart
+    ENDTRAP
+    PROC rPart()
+    ENDPROC
+    PROC main()
+        CONNECT irPart WITH tPart;
+        CONNECT nConst WITH tPart;
+        CONNECT irPart WITH rPart;
+        CONNECT irPart WITH tPrat;
+        tPart;
+    ENDPROC
+ENDMODULE";
+        assert_eq!(errors(source), vec![
+            "11:17: error: CONST data 'nConst' cannot be connected, the interrupt number needs VAR data",
+            "12:29: error: 'rPart' is not a TRAP routine",
+            "13:29: error: Unknown routine 'tPrat'",
+            "14:9: error: TRAP 'tPart' cannot be called, CONNECT it to an interrupt",
+        ]);
+    }
+
+    #[test]
+    fn suggests_similar_names() {
+        let source = "
+MODULE Cell
+    PERS num nCount := 0;
+    PROC main()
+        VAR num nIndex;
+        nCuont := nIndx + 1;
+        MoveHom;
+        WaitTme 1;
+        nTotal := 0;
+    ENDPROC
+    PROC MoveHome()
+    ENDPROC
+ENDMODULE";
+        let diagnostics = match resolve_source(source, &ResolveOptions::default()) {
+            Ok(_) => panic!("Unknown names resolved"),
+            Err(diagnostics) => diagnostics,
+        };
+        let suggestions: Vec<Option<&str>> = diagnostics.iter().map(|diagnostic| diagnostic.suggestion.as_deref()).collect();
+        assert_eq!(suggestions, vec![Some("nCount"), Some("nIndex"), Some("MoveHome"), Some("WaitTime"), None]);
+    }
+    #[test]
+    fn aliases_have_the_values_of_their_base_types() {
+        let (program, _) = resolve_source("
+MODULE Types(SYSMODULE)
+    ALIAS num level;
+    ALIAS level depth;
+    ALIAS pos point;
+ENDMODULE
+MODULE Cell
+    LOCAL ALIAS bool flag;
+    VAR depth nDepth := 3;
+    VAR level nLevels{2} := [1, 2];
+    PERS point pHome := [1, 2, 3];
+    PROC rCheck(level nMin)
+        VAR flag bDone;
+    ENDPROC
+ENDMODULE", &ResolveOptions::default()).unwrap();
+        assert_eq!(format!("{:?}", program.variables), "[Num(3.0), Array([Num(1.0), Num(2.0)]), Record(\"pos\", [Num(1.0), Num(2.0), Num(3.0)])]");
+        let routine = &program.modules[1].routines[0];
+        assert!(matches!(routine.arguments[0].decl.value, Variable::Num(_)));
+        assert!(matches!(routine.variables[0].value, Variable::Bool(false)));
+        // Data keeps the type it was declared with
+        assert_eq!(program.modules[1].variables[0].data_type, "depth");
+
+        assert_eq!(errors("
+MODULE Cell
+    ALIAS speed velocity;
+    ALIAS loop loop;
+    VAR loop nLoop;
+    VAR num nMissing;
+    VAR level nLevel := \"high\";
+    ALIAS num level;
+ENDMODULE
+MODULE Other
+    VAR flag bFlag;
+    PROC rTest()
+    ENDPROC
+ENDMODULE
+MODULE Flags
+    LOCAL ALIAS bool flag;
+ENDMODULE"), vec![
+            "3:17: error: Unknown data type speed of alias velocity",
+            "4:16: error: Unknown data type loop of alias loop",
+            "5:14: error: Unknown data type loop of 'nLoop'",
+            "7:15: error: Invalid value for level 'nLevel' of type num",
+            "11:14: error: Unknown data type flag of 'bFlag'",
+        ]);
+    }
+
+    #[test]
+    fn initial_values_are_evaluated_in_declaration_order() {
+        let (program, _) = resolve_source("
+MODULE Limits
+    CONST num nMax := 4;
+ENDMODULE
+MODULE Cell
+    CONST num nTwice := nMax * 2;
+    VAR num nCur := nTwice + 1;
+    PERS pos pStart := [nMax, -nTwice, 0];
+    CONST num nSizes{2} := [nMax, nTwice DIV 3];
+    CONST bool bSmall := NOT (nTwice > 10);
+    PROC rTest()
+        CONST num nHalf := nTwice / 2;
+        VAR num nNext := nHalf + pi - pi;
+    ENDPROC
+ENDMODULE", &ResolveOptions::default()).unwrap();
+        assert_eq!(format!("{:?}", program.variables), "[Num(4.0), Num(8.0), Num(9.0), Record(\"pos\", [Num(4.0), Num(-8.0), Num(0.0)]), \
+            Array([Num(4.0), Num(2.0)]), Bool(true)]");
+        let routine = &program.modules[1].routines[0];
+        assert!(matches!(routine.variables[0].value, Variable::Num(value) if value == 4.0));
+        assert!(matches!(routine.variables[1].value, Variable::Num(value) if value == 4.0));
+
+        assert_eq!(errors("
+MODULE Cell
+    VAR num nCount := 1;
+    CONST num nBefore := nAfter + 1;
+    CONST num nAfter := 2;
+    CONST num nVar := nCount;
+    CONST num nZero := nAfter / 0;
+    CONST string sName := nAfter;
+    CONST num nCall := Abs(nAfter);
+    PROC rTest(num nArg)
+        CONST num nLocal := nArg + nMissing;
+    ENDPROC
+ENDMODULE"), vec![
+            "4:15: error: Invalid initial value of 'nBefore': 'nAfter' is not declared before it",
+            "6:15: error: Invalid initial value of 'nVar': 'nCount' is not CONST data",
+            "7:15: error: Invalid initial value of 'nZero': Division by zero",
+            "8:18: error: Invalid initial value of 'sName': Cannot assign num to string",
+            "9:15: error: Invalid initial value of 'nCall': only constants, literals and operators are allowed",
+            "11:19: error: Invalid initial value of 'nLocal': 'nArg' is not CONST data",
+        ]);
+    }
+}
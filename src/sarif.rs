@@ -0,0 +1,162 @@
+use crate::json::{Json, ToJson};
+use crate::lexer::Span;
+use crate::linter::Lint;
+use crate::resolver::{Diagnostic, Severity};
+
+// ------------------ Findings -----------------------/
+
+/// Rules of the findings that don't come from the linter
+const RULES: &[(&str, &str)] = &[
+    ("syntax", "The module files are read and parsed"),
+    ("semantic", "The names, types and arguments of the program resolve"),
+];
+
+/// Diagnostic of a file, as a result of a SARIF log or a line of JSON
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    // "syntax", "semantic" or the ID of a lint rule
+    pub rule: String,
+    pub severity: Severity,
+    pub message: String,
+    pub file: String,
+    // Unknown for files that can't be read
+    pub span: Option<Span>,
+    pub suggestion: Option<String>,
+}
+
+impl Finding {
+    /// Error of loading a program, with the file it happened in in front,
+    /// like `Cell.mod: Undefined symbol ! at 2:5`, and the span of the lexer
+    /// or the parser error
+    pub fn load_error(message: &str, span: Option<Span>) -> Finding {
+        let (file, text) = message.split_once(": ").unwrap_or(("", message));
+        Finding::syntax(file, text, span)
+    }
+
+    /// Error of the lexer or the parser
+    pub fn syntax(file: &str, message: &str, span: Option<Span>) -> Finding {
+        Finding { rule: String::from("syntax"), severity: Severity::Error, message: message.to_string(), file: file.to_string(), span, suggestion: None }
+    }
+
+    pub fn diagnostic(file: &str, diagnostic: &Diagnostic) -> Finding {
+        Finding {
+            rule: String::from("semantic"),
+            severity: diagnostic.severity,
+            message: diagnostic.message.clone(),
+            file: file.to_string(),
+            span: Some(diagnostic.span),
+            suggestion: diagnostic.suggestion.clone(),
+        }
+    }
+
+    pub fn lint(file: &str, lint: &Lint) -> Finding {
+        Finding {
+            rule: lint.rule.to_string(),
+            severity: lint.severity,
+            message: lint.message.clone(),
+            file: file.to_string(),
+            span: Some(lint.span),
+            suggestion: None,
+        }
+    }
+
+    fn level(&self) -> &'static str {
+        match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn result(&self) -> Json {
+        let num = |value: usize| Json::Num(value as f64);
+        let mut location = vec![("artifactLocation", Json::object(vec![("uri", Json::str(&uri(&self.file)))]))];
+        if let Some(span) = self.span {
+            let mut region = vec![("startLine", num(span.line)), ("startColumn", num(span.column))];
+            if span.end > span.start {
+                region.push(("endColumn", num(span.column + span.end - span.start)));
+            }
+            location.push(("region", Json::object(region)));
+        }
+        Json::object(vec![
+            ("ruleId", Json::str(&self.rule)),
+            ("level", Json::str(self.level())),
+            ("message", Json::object(vec![("text", Json::str(&self.message))])),
+            ("locations", Json::Array(vec![Json::object(vec![("physicalLocation", Json::object(location))])])),
+        ])
+    }
+}
+
+/// Object of `--message-format json`, with the rule as the code
+impl ToJson for Finding {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("code", Json::str(&self.rule)),
+            ("severity", Json::str(self.level())),
+            ("file", Json::str(&self.file)),
+            ("span", self.span.map_or(Json::Null, |span| span.to_json())),
+            ("message", Json::str(&self.message)),
+            ("suggestion", self.suggestion.as_deref().map_or(Json::Null, Json::str)),
+        ])
+    }
+}
+
+/// Relative path with forward slashes, as code hosts match them to the files
+/// of the repository
+fn uri(file: &str) -> String {
+    let file = file.replace('\\', "/");
+    file.strip_prefix("./").unwrap_or(&file).to_string()
+}
+
+// ------------------ Log -----------------------/
+
+/// SARIF 2.1.0 log of one run, with the lint rules by ID and description
+pub fn log(findings: &[Finding], lint_rules: &[(&str, &str)]) -> Json {
+    let rules = RULES.iter().chain(lint_rules.iter())
+        .map(|(id, description)| Json::object(vec![
+            ("id", Json::str(id)),
+            ("shortDescription", Json::object(vec![("text", Json::str(description))])),
+        ]))
+        .collect();
+    let driver = Json::object(vec![
+        ("name", Json::str("rapid-rust")),
+        ("version", Json::str(env!("CARGO_PKG_VERSION"))),
+        ("rules", Json::Array(rules)),
+    ]);
+    let run = Json::object(vec![
+        ("tool", Json::object(vec![("driver", driver)])),
+        ("results", Json::Array(findings.iter().map(Finding::result).collect())),
+    ]);
+    Json::object(vec![
+        ("$schema", Json::str("https://json.schemastore.org/sarif-2.1.0.json")),
+        ("version", Json::str("2.1.0")),
+        ("runs", Json::Array(vec![run])),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lexer, parser, resolver};
+
+    #[test]
+    fn logs_findings() {
+        let mut program = parser::parse_tokens(lexer::parse("MODULE Cell\n    PROC main()\n        nCount := 1;\n    ENDPROC\nENDMODULE")).unwrap();
+        let diagnostics = resolver::resolve(&mut program, &resolver::ResolveOptions::default()).unwrap_err();
+        let finding = Finding::diagnostic("./src/Cell.mod", &diagnostics[0]);
+        let log = log(&[finding], &[("unused-data", "Data is declared but never used")]);
+        assert_eq!(log.get("version").and_then(Json::as_str), Some("2.1.0"));
+        let run = &log.get("runs").and_then(Json::as_array).unwrap()[0];
+        let rules = run.path(&["tool", "driver", "rules"]).and_then(Json::as_array).unwrap();
+        assert_eq!(rules.iter().filter_map(|rule| rule.get("id").and_then(Json::as_str)).collect::<Vec<_>>(), vec!["syntax", "semantic", "unused-data"]);
+        assert_eq!(run.get("results").and_then(Json::as_array).unwrap()[0].to_string(),
+            r#"{"ruleId":"semantic","level":"error","message":{"text":"Unknown id 'nCount'"},"locations":[{"physicalLocation":{"artifactLocation":{"uri":"src/Cell.mod"},"region":{"startLine":3,"startColumn":9,"endColumn":15}}}]}"#);
+
+        assert_eq!(Finding::diagnostic("Cell.mod", &diagnostics[0]).to_json().to_string(),
+            r#"{"code":"semantic","severity":"error","file":"Cell.mod","span":{"line":3,"column":9,"start":36,"end":42},"message":"Unknown id 'nCount'","suggestion":null}"#);
+
+        let err = parser::Program::from_source("MODULE Cell\n    PROC main(\nENDMODULE").err().unwrap().in_file(std::path::Path::new("Cell.mod"));
+        let finding = Finding::load_error(&err.to_string(), err.span());
+        assert_eq!((finding.file.as_str(), finding.message.as_str()), ("Cell.mod", "Expected data type at 3:1"));
+        assert_eq!(finding.span.map(|span| (span.line, span.column)), Some((3, 1)));
+    }
+}
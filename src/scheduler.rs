@@ -0,0 +1,323 @@
+use std::fmt;
+
+use crate::compiler::Bytecode;
+use crate::host::{Host, TaskWait};
+use crate::interpreter::{InterpreterOptions, RuntimeError};
+use crate::parser::{Program, Storage};
+use crate::variable::Variable;
+use crate::vm::{Status, Vm};
+
+// ------------------ Tasks -----------------------/
+
+/// Program of one task of a multitask controller, resolved and compiled.
+/// Every task has its own modules, data and call stack.
+pub struct Task {
+    pub name: String,
+    pub program: Program,
+    pub bytecode: Bytecode,
+}
+
+impl Task {
+    pub fn new(name: &str, program: Program, bytecode: Bytecode) -> Task {
+        Task { name: String::from(name), program, bytecode }
+    }
+
+    /// Global slots of the PERS data the task shares with the others, by
+    /// lowercase name. LOCAL PERS data stays in its module.
+    fn persistents(&self) -> Vec<(String, usize)> {
+        self.program.module_data().into_iter()
+            .filter(|(_, decl, _)| decl.storage == Storage::Pers && !decl.local)
+            .map(|(_, decl, slot)| (decl.name.to_ascii_lowercase(), slot))
+            .collect()
+    }
+}
+
+/// Error that ended a multitask run, with the task that raised it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskError {
+    pub task: String,
+    pub error: RuntimeError,
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.task, self.error)
+    }
+}
+
+/// Task being scheduled
+struct Running<'a> {
+    vm: Vm<'a>,
+    persistents: Vec<(String, usize)>,
+    wait: TaskWait,
+    // How the last turn of the task ended, `None` before its first turn
+    status: Option<Status>,
+}
+
+// ------------------ Scheduler -----------------------/
+
+/// Run the entry routine of every task on the VM, round robin. A task runs
+/// until it finishes or waits for something it can't get yet, then the next
+/// task gets a turn. PERS data with the same name is shared by all tasks.
+/// When every task waits, host time passes until the first wait can end.
+pub fn run_tasks(tasks: &mut [Task], entry: &str, options: &InterpreterOptions, host: &mut Host) -> Result<(), TaskError> {
+    let globals: Vec<Vec<Variable>> = tasks.iter_mut()
+        .map(|task| std::mem::take(&mut task.program.variables))
+        .collect();
+    let outer = host.task.take();
+    let (result, globals) = schedule(tasks, globals, entry, options, host);
+    host.task = outer;
+    for (task, globals) in tasks.iter_mut().zip(globals) {
+        task.program.variables = globals;
+    }
+    result
+}
+
+/// Run the tasks on their global data, which is given back in any case
+fn schedule(tasks: &[Task], globals: Vec<Vec<Variable>>, entry: &str, options: &InterpreterOptions, host: &mut Host) -> (Result<(), TaskError>, Vec<Vec<Variable>>) {
+    let mut running = Vec::new();
+    let mut globals = globals.into_iter();
+    for task in tasks.iter() {
+        match Vm::new(&task.bytecode, &task.program, globals.next().unwrap_or_default(), entry, options) {
+            Ok(vm) => {
+                let wait = TaskWait { name: task.name.clone(), ..TaskWait::default() };
+                running.push(Running { vm, persistents: task.persistents(), wait, status: None });
+            },
+            Err((err, task_globals)) => {
+                let mut all: Vec<Vec<Variable>> = running.into_iter().map(|running| running.vm.globals).collect();
+                all.push(task_globals);
+                all.extend(globals);
+                return (Err(TaskError { task: task.name.clone(), error: err }), all);
+            },
+        }
+    }
+
+    let result = shared_data(tasks, &running).and_then(|mut shared| run_rounds(tasks, &mut running, &mut shared, host));
+    (result, running.into_iter().map(|running| running.vm.globals).collect())
+}
+
+/// Initial values of the shared PERS data, from the first task that declares
+/// it. Every task has to declare it with the same data type.
+fn shared_data(tasks: &[Task], running: &[Running]) -> Result<Vec<(String, Variable)>, TaskError> {
+    let mut shared: Vec<(String, Variable)> = Vec::new();
+    for (task, running) in tasks.iter().zip(running) {
+        for (name, slot) in running.persistents.iter() {
+            let value = &running.vm.globals[*slot];
+            match shared.iter().find(|(shared, _)| shared == name) {
+                Some((_, first)) if first.type_name() != value.type_name() => {
+                    let message = format!("PERS {} is {} here but {} in another task", name, value.type_name(), first.type_name());
+                    return Err(TaskError { task: task.name.clone(), error: RuntimeError::type_mismatch(message) });
+                },
+                Some(_) => (),
+                None => shared.push((name.clone(), value.clone())),
+            }
+        }
+    }
+    Ok(shared)
+}
+
+fn run_rounds(tasks: &[Task], running: &mut [Running], shared: &mut [(String, Variable)], host: &mut Host) -> Result<(), TaskError> {
+    loop {
+        // Whether any task got further than the wait it was stuck in
+        let mut progress = false;
+        for (task, running) in tasks.iter().zip(running.iter_mut()) {
+            if running.status == Some(Status::Finished) {
+                continue;
+            }
+            let waiting = running.status.is_some();
+            let finished = running.wait.finished;
+            running.status = Some(run_slice(running, shared, host).map_err(|error| TaskError { task: task.name.clone(), error })?);
+            progress |= !waiting || running.wait.finished != finished;
+        }
+
+        if running.iter().all(|running| running.status == Some(Status::Finished)) {
+            return Ok(());
+        }
+        if progress {
+            continue;
+        }
+
+        // Every task is stuck, let time pass until the first one can go on
+        let wake = running.iter()
+            .filter_map(|running| match running.status {
+                Some(Status::Waiting { wake, .. }) => wake,
+                _ => None,
+            })
+            .min();
+        match wake {
+            Some(wake) => host.clock.sleep(wake.saturating_sub(host.clock.now())),
+            None => return Err(deadlock(tasks, running)),
+        }
+    }
+}
+
+/// Run a task until it finishes or waits, with the shared data up to date
+fn run_slice(running: &mut Running, shared: &mut [(String, Variable)], host: &mut Host) -> Result<Status, RuntimeError> {
+    for (name, slot) in running.persistents.iter() {
+        if let Some((_, value)) = shared.iter().find(|(shared, _)| shared == name) {
+            running.vm.globals[*slot] = value.clone();
+        }
+    }
+
+    host.task = Some(std::mem::take(&mut running.wait));
+    let status = running.vm.execute(host);
+    running.wait = host.task.take().unwrap_or_default();
+
+    for (name, slot) in running.persistents.iter() {
+        if let Some((_, value)) = shared.iter_mut().find(|(shared, _)| shared == name) {
+            *value = running.vm.globals[*slot].clone();
+        }
+    }
+    status
+}
+
+/// Error for tasks that all wait for something that never happens, raised
+/// in the first one at its wait
+fn deadlock(tasks: &[Task], running: &[Running]) -> TaskError {
+    let (task, span) = tasks.iter().zip(running)
+        .find_map(|(task, running)| match running.status {
+            Some(Status::Waiting { span, .. }) => Some((task.name.clone(), span)),
+            _ => None,
+        })
+        .unwrap_or_default();
+    TaskError { task, error: RuntimeError::Deadlock { span } }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use crate::compiler;
+    use crate::interpreter;
+    use crate::host::{Clock, IoBoard, ManualClock};
+    use crate::lexer;
+    use crate::parser;
+    use crate::resolver::{self, ResolveOptions};
+    use crate::variable::SignalKind;
+
+    fn task(name: &str, source: &str, board: &IoBoard) -> Task {
+        let mut program = parser::parse_tokens(lexer::parse(source)).unwrap();
+        board.declare(&mut program);
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let bytecode = compiler::compile(&program).unwrap();
+        Task::new(name, program, bytecode)
+    }
+
+    #[test]
+    fn tasks_take_turns_at_waits() {
+        let mut board = IoBoard::new();
+        board.define("doReady", SignalKind::DigitalOutput);
+        let mut tasks = vec![
+            task("T_ROB1", "
+MODULE Producer
+    PERS num nCount := 0;
+    PROC main()
+        FOR i FROM 1 TO 3 DO
+            WaitTime 1;
+            nCount := nCount + 1;
+            SetDO doReady, 1;
+            WaitDO doReady, 0;
+        ENDFOR
+    ENDPROC
+ENDMODULE", &board),
+            task("T_ROB2", "
+MODULE Consumer
+    PERS num nCount := 0;
+    VAR num nSeen := 0;
+    PROC main()
+        WHILE nSeen < 3 DO
+            WaitDO doReady, 1;
+            nSeen := nSeen + 1;
+            TPWrite \"Part \" \\Num:=nCount;
+            SetDO doReady, 0;
+        ENDWHILE
+    ENDPROC
+ENDMODULE", &board),
+        ];
+
+        let clock = ManualClock::new(Duration::ZERO);
+        let mut output = Vec::new();
+        let mut host = Host::new(&mut output).with_clock(&clock).with_io(&board);
+        run_tasks(&mut tasks, "main", &InterpreterOptions::default(), &mut host).unwrap();
+        assert_eq!(host.task, None);
+        assert_eq!(clock.now(), Duration::from_secs(3));
+        assert_eq!(output, vec!["Part 1", "Part 2", "Part 3"]);
+        assert_eq!(format!("{:?}", &tasks[1].program.variables[1..]), "[Num(3.0), Num(3.0)]");
+    }
+
+    #[test]
+    fn waiting_tasks_deadlock() {
+        let mut board = IoBoard::new();
+        board.define("doReady", SignalKind::DigitalOutput);
+        let source = "
+MODULE Waiter
+    PROC main()
+        WaitDO doReady, 1;
+    ENDPROC
+ENDMODULE";
+        let mut tasks = vec![task("T_ROB1", source, &board), task("T_ROB2", source, &board)];
+
+        let clock = ManualClock::new(Duration::ZERO);
+        let mut output = Vec::new();
+        let mut host = Host::new(&mut output).with_clock(&clock).with_io(&board);
+        let err = run_tasks(&mut tasks, "main", &InterpreterOptions::default(), &mut host).unwrap_err();
+        assert_eq!(err.to_string(), "T_ROB1: 4:9: ERR_DEADLOCK: Every task waits for something no task will do");
+    }
+
+    fn sync_task(name: &str, wait: f64, board: &IoBoard) -> Task {
+        task(name, &format!("
+MODULE Sync
+    PERS tasks tlAll{{2}} := [[\"T_ROB1\"], [\"T_ROB2\"]];
+    VAR syncident sLoop;
+    VAR syncident sDone;
+    PROC main()
+        FOR i FROM 1 TO 2 DO
+            WaitTime {};
+            WaitSyncTask sLoop, tlAll;
+            TPWrite \"{} synced \" \\Num:=i;
+        ENDFOR
+        WaitSyncTask sDone, tlAll \\TimeOut:=5;
+    ENDPROC
+ENDMODULE", wait, name), board)
+    }
+
+    #[test]
+    fn tasks_synchronize() {
+        let board = IoBoard::new();
+        let mut tasks = vec![sync_task("T_ROB1", 1.0, &board), sync_task("T_ROB2", 3.0, &board)];
+        let clock = ManualClock::new(Duration::ZERO);
+        let mut output = Vec::new();
+        let mut host = Host::new(&mut output).with_clock(&clock);
+        run_tasks(&mut tasks, "main", &InterpreterOptions::default(), &mut host).unwrap();
+        assert!(host.sync_points.is_empty());
+        assert_eq!(clock.now(), Duration::from_secs(6));
+        assert_eq!(output, vec!["T_ROB2 synced 1", "T_ROB1 synced 1", "T_ROB2 synced 2", "T_ROB1 synced 2"]);
+
+        // The other task never comes
+        let mut tasks = vec![
+            task("T_ROB1", "
+MODULE Alone
+    PERS tasks tlAll{2} := [[\"T_ROB1\"], [\"T_ROB2\"]];
+    VAR syncident sStart;
+    PROC main()
+        WaitSyncTask \\InPos, sStart, tlAll \\TimeOut:=2;
+    ERROR
+        IF ERRNO = ERR_WAITSYNCTASK TPWrite \"Timeout\";
+    ENDPROC
+ENDMODULE", &board),
+            task("T_ROB2", "MODULE Idle PROC main() ENDPROC ENDMODULE", &board),
+        ];
+        let clock = ManualClock::new(Duration::ZERO);
+        let mut output = Vec::new();
+        let mut host = Host::new(&mut output).with_clock(&clock);
+        run_tasks(&mut tasks, "main", &InterpreterOptions::default(), &mut host).unwrap();
+        assert_eq!(clock.now(), Duration::from_secs(2));
+        assert!(host.sync_points.is_empty());
+        assert_eq!(output, vec!["Timeout"]);
+
+        // A single program has no other tasks to wait for
+        let mut program = tasks.remove(0).program;
+        let err = interpreter::run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).into_result().unwrap_err();
+        assert_eq!(err.name(), "ERR_UNSUPPORTED");
+    }
+}
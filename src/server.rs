@@ -0,0 +1,376 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::embed::{self, RunOptions, Session};
+use crate::json::{Json, ToJson};
+use crate::resolver::ResolveOptions;
+use crate::variable::Variable;
+
+// A small REST API over HTTP/1.1, to share the checker and the interpreter
+// as a service. Each request is answered with JSON on a connection of its
+// own:
+//
+//     POST /check  {"source": "MODULE ...", "warn_shadowing": true}  diagnostics and lints
+//     POST /parse  {"source": "MODULE ..."}  modules with spans
+//     POST /run    {"source": "MODULE ...", "entry": "main", "signals": {"diPartReady": "signaldi"},
+//                   "inputs": {"diPartReady": 1}, "answers": [4], "max_instructions": 1000, "trace": true}
+//                  output, moves, error, time, trace and the signals after the run
+
+// ------------------ Server -----------------------/
+
+#[derive(Debug, Clone, Copy)]
+pub struct ServerOptions {
+    /// Most nodes a run may execute, a request can ask for fewer
+    pub max_instructions: u64,
+    /// Longest body of a request, in bytes
+    pub max_body: usize,
+    /// Longest request line and headers together, in bytes
+    pub max_head: usize,
+    /// Most connections answered at a time, others are turned away
+    pub max_connections: usize,
+    /// Longest wait for the next bytes of a request
+    pub timeout: Duration,
+}
+
+impl Default for ServerOptions {
+    fn default() -> ServerOptions {
+        ServerOptions {
+            max_instructions: RunOptions::default().max_instructions,
+            max_body: 8 * 1024 * 1024,
+            max_head: 16 * 1024,
+            max_connections: 32,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+pub struct Server {
+    listener: TcpListener,
+    options: ServerOptions,
+}
+
+impl Server {
+    /// Listen on an address like `127.0.0.1:8080`, port 0 picks a free one
+    pub fn bind(address: &str, options: ServerOptions) -> Result<Server, String> {
+        let listener = TcpListener::bind(address).map_err(|err| format!("{}: {}", address, err))?;
+        Ok(Server { listener, options })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, String> {
+        self.listener.local_addr().map_err(|err| err.to_string())
+    }
+
+    /// Answer requests until the listener fails, each connection on a
+    /// thread of its own. Connections over `max_connections` are answered
+    /// with 503 right away.
+    pub fn run(&self) -> Result<(), String> {
+        let open = Arc::new(AtomicUsize::new(0));
+        for stream in self.listener.incoming() {
+            let stream = stream.map_err(|err| err.to_string())?;
+            let options = self.options;
+            // A client that went away can't be told about it
+            if open.fetch_add(1, Ordering::SeqCst) >= options.max_connections {
+                open.fetch_sub(1, Ordering::SeqCst);
+                let _ = stream.set_write_timeout(Some(options.timeout));
+                let _ = write_response(&mut &stream, 503, &error("Too many connections"));
+                continue;
+            }
+            let open = Arc::clone(&open);
+            thread::spawn(move || {
+                let _ = answer(stream, &options);
+                open.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        Ok(())
+    }
+}
+
+fn answer(stream: TcpStream, options: &ServerOptions) -> io::Result<()> {
+    stream.set_read_timeout(Some(options.timeout))?;
+    stream.set_write_timeout(Some(options.timeout))?;
+    let mut input = BufReader::new(stream.try_clone()?);
+    let (status, body) = match read_request(&mut input, options) {
+        Ok(request) => handle(&request, options),
+        Err(response) => response,
+    };
+    write_response(&mut &stream, status, &body)
+}
+
+// ------------------ HTTP -----------------------/
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    // Path without the query
+    pub path: String,
+    pub body: String,
+}
+
+/// Read the request line, the headers and the body of the Content-Length,
+/// within the limits of the options. A request that can't be read gives the
+/// response to it.
+pub fn read_request(input: &mut impl BufRead, options: &ServerOptions) -> Result<Request, (u16, Json)> {
+    let bad_request = |message: &str| (400, error(message));
+    let mut head = input.by_ref().take(options.max_head as u64);
+    let line = read_line(&mut head)?;
+    let mut words = line.split_whitespace();
+    let (method, target) = match (words.next(), words.next(), words.next()) {
+        (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => (method, target),
+        _ => return Err(bad_request("Expected a request line like POST /check HTTP/1.1")),
+    };
+    let path = target.split('?').next().unwrap_or(target);
+
+    let mut length = 0;
+    loop {
+        let header = read_line(&mut head)?;
+        if header.is_empty() {
+            return Err(bad_request("Request ends in the headers"));
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().map_err(|_| bad_request("Invalid Content-Length"))?;
+            } else if name.trim().eq_ignore_ascii_case("Transfer-Encoding") {
+                return Err((411, error("A body needs a Content-Length")));
+            }
+        }
+    }
+    if length > options.max_body {
+        return Err((413, error(&format!("Body longer than {} bytes", options.max_body))));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).map_err(read_error)?;
+    let body = String::from_utf8(body).map_err(|_| bad_request("Body isn't UTF-8"))?;
+    Ok(Request { method: String::from(method), path: String::from(path), body })
+}
+
+/// Line of the request line or the headers, with its line break. A line cut
+/// off by the limit of the head makes it too large.
+fn read_line(head: &mut io::Take<impl BufRead>) -> Result<String, (u16, Json)> {
+    let mut line = String::new();
+    head.read_line(&mut line).map_err(read_error)?;
+    if !line.ends_with('\n') && head.limit() == 0 {
+        return Err((431, error("Request line and headers too large")));
+    }
+    Ok(line)
+}
+
+/// Response to a request that failed to read, 408 when the client stopped
+/// sending it
+fn read_error(err: io::Error) -> (u16, Json) {
+    match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => (408, error("Request timed out")),
+        _ => (400, error(&err.to_string())),
+    }
+}
+
+pub fn write_response(output: &mut impl Write, status: u16, body: &Json) -> io::Result<()> {
+    let body = body.to_string();
+    write!(output, "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, reason(status), body.len(), body)?;
+    output.flush()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+fn error(message: &str) -> Json {
+    Json::object(vec![("error", Json::str(message))])
+}
+
+// ------------------ Routes -----------------------/
+
+/// Status and body of the response to a request
+pub fn handle(request: &Request, options: &ServerOptions) -> (u16, Json) {
+    let route = match request.path.as_str() {
+        "/check" | "/parse" | "/run" => &request.path[1..],
+        _ => return (404, error(&format!("No route {}", request.path))),
+    };
+    if request.method != "POST" {
+        return (405, error(&format!("{} takes POST", request.path)));
+    }
+    let body = match Json::parse(&request.body) {
+        Ok(body @ Json::Object(_)) => body,
+        Ok(_) => return (400, error("Expected a JSON object")),
+        Err(err) => return (400, error(&err)),
+    };
+    let source = match body.get("source").and_then(Json::as_str) {
+        Some(source) => source,
+        None => return (400, error("Missing source")),
+    };
+    match route {
+        "check" => {
+            let warn_shadowing = matches!(body.get("warn_shadowing"), Some(Json::Bool(true)));
+            (200, Json::object(vec![("diagnostics", embed::check(source, &ResolveOptions { warn_shadowing, ..ResolveOptions::default() }))]))
+        },
+        "parse" => match embed::parse(source) {
+            Ok(modules) => (200, modules),
+            Err(err) => (422, error(&err)),
+        },
+        _ => run(source, &body, options).unwrap_or_else(|err| (400, error(&err))),
+    }
+}
+
+/// Run a module with the signals, inputs and answers of the request
+fn run(source: &str, body: &Json, options: &ServerOptions) -> Result<(u16, Json), String> {
+    let mut signals = Vec::new();
+    for (name, data_type) in members(body, "signals")? {
+        match data_type.as_str().map(Variable::from) {
+            Some(Ok(Variable::Signal(kind, _))) => signals.push((name.clone(), kind)),
+            _ => return Err(format!("{} is not a signal type", data_type.as_str().map_or_else(|| data_type.to_string(), String::from))),
+        }
+    }
+    let answers = match body.get("answers") {
+        Some(answers) => answers.as_array()
+            .and_then(|answers| answers.iter().map(Json::as_f64).collect::<Option<Vec<f64>>>())
+            .ok_or_else(|| String::from("Answers have to be numbers"))?,
+        None => Vec::new(),
+    };
+    let max_instructions = match body.get("max_instructions") {
+        Some(max) => max.as_f64().filter(|max| *max >= 0.0).ok_or_else(|| String::from("Invalid max_instructions"))?,
+        None => f64::INFINITY,
+    };
+    let run_options = RunOptions {
+        entry: String::from(body.get("entry").and_then(Json::as_str).unwrap_or("main")),
+        max_instructions: options.max_instructions.min(max_instructions as u64),
+        answers,
+        trace: !matches!(body.get("trace"), Some(Json::Bool(false))),
+    };
+
+    let mut session = match Session::new(source, &signals) {
+        Ok(session) => session,
+        Err(findings) => return Ok((422, Json::object(vec![("diagnostics", Json::Array(findings.iter().map(ToJson::to_json).collect()))]))),
+    };
+    for (name, value) in members(body, "inputs")? {
+        let value = value.as_f64().ok_or_else(|| format!("Input {} has to be a number", name))?;
+        session.set_input(name, value).map_err(|err| err.to_string())?;
+    }
+    let mut result = session.run(&run_options);
+    let values = signals.iter()
+        .map(|(name, _)| (name.clone(), Json::Num(session.signal(name).unwrap_or(0.0))))
+        .collect();
+    if let Json::Object(members) = &mut result {
+        members.push((String::from("signals"), Json::Object(values)));
+    }
+    Ok((200, result))
+}
+
+/// Members of an object of the request, none when it's left out
+fn members<'j>(body: &'j Json, name: &str) -> Result<&'j [(String, Json)], String> {
+    match body.get(name) {
+        Some(Json::Object(members)) => Ok(members),
+        Some(_) => Err(format!("Expected an object of {}", name)),
+        None => Ok(&[]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn post(path: &str, body: &str) -> (u16, Json) {
+        let request = Request { method: String::from("POST"), path: String::from(path), body: String::from(body) };
+        handle(&request, &ServerOptions::default())
+    }
+
+    fn source(body: &str) -> String {
+        Json::object(vec![("source", Json::str(body))]).to_string()
+    }
+
+    #[test]
+    fn answers_requests() {
+        let (status, body) = post("/check", &source("MODULE Cell\n    PROC main()\n        nCount := 1;\n    ENDPROC\nENDMODULE"));
+        assert_eq!(status, 200);
+        assert_eq!(body.path(&["diagnostics"]).and_then(Json::as_array).unwrap()[0].get("message").and_then(Json::as_str), Some("Unknown id 'nCount'"));
+        let (status, body) = post("/parse", &source("MODULE Cell\nENDMODULE"));
+        assert_eq!((status, body.get("modules").and_then(Json::as_array).map(<[Json]>::len)), (200, Some(1)));
+        assert_eq!(post("/parse", &source("MODULE Cell")).0, 422);
+
+        let run = r#"{"source": "MODULE Cell\n    PROC main()\n        WaitDI diPartReady, 1;\n        SetDO doGripper, 1;\n        TPWrite \"Gripped\";\n        WHILE TRUE DO\n        ENDWHILE\n    ENDPROC\nENDMODULE",
+            "signals": {"diPartReady": "signaldi", "doGripper": "signaldo"}, "inputs": {"diPartReady": 1}, "max_instructions": 100, "trace": false}"#;
+        let (status, body) = post("/run", run);
+        assert_eq!(status, 200);
+        assert_eq!(body.get("output").unwrap().to_string(), r#"["Gripped"]"#);
+        assert_eq!(body.get("signals").unwrap().to_string(), r#"{"diPartReady":1,"doGripper":1}"#);
+        assert_eq!(body.path(&["error", "name"]).and_then(Json::as_str), Some("ERR_EXECLIMIT"));
+        assert_eq!(body.get("trace"), None);
+        let (status, body) = post("/run", &source("MODULE Cell\n    PROC main()\n        TPWrite \"Hello\";\n    ENDPROC\nENDMODULE"));
+        assert_eq!((status, body.get("trace").and_then(Json::as_array).map(<[Json]>::len)), (200, Some(3)));
+        assert_eq!(post("/run", &source("MODULE Cell\n    PROC main()\n        SetDO doGripper, 1;\n    ENDPROC\nENDMODULE")).0, 422);
+
+        assert_eq!(post("/run", r#"{"source": "", "signals": {"diPartReady": "num"}}"#), (400, error("num is not a signal type")));
+        assert_eq!(post("/check", "{}"), (400, error("Missing source")));
+        assert_eq!(post("/check", "[").0, 400);
+        assert_eq!(post("/lint", "{}").0, 404);
+        let request = Request { method: String::from("GET"), path: String::from("/check"), body: String::new() };
+        assert_eq!(handle(&request, &ServerOptions::default()).0, 405);
+    }
+
+    #[test]
+    fn serves_http() {
+        let server = Server::bind("127.0.0.1:0", ServerOptions::default()).unwrap();
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let body = source("MODULE Cell\nENDMODULE");
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "POST /check?pretty HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert_eq!(response, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 18\r\nConnection: close\r\n\r\n{\"diagnostics\":[]}");
+
+        let options = ServerOptions { max_body: 10, ..ServerOptions::default() };
+        let mut input = "POST /run HTTP/1.1\r\nContent-Length: 100\r\n\r\n".as_bytes();
+        assert_eq!(read_request(&mut input, &options).unwrap_err(), (413, error("Body longer than 10 bytes")));
+        assert_eq!(read_request(&mut "GET /\r\n".as_bytes(), &options).unwrap_err().0, 400);
+        let mut input = "POST /run HTTP/1.1\r\n\r\n".as_bytes();
+        assert_eq!(read_request(&mut input, &ServerOptions { max_head: 20, ..options }).unwrap_err().0, 431);
+    }
+
+    /// Response of the server to what a client sends, before it closes
+    fn exchange(address: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn limits_requests() {
+        let options = ServerOptions { max_head: 1024, max_connections: 1, timeout: Duration::from_millis(200), ..ServerOptions::default() };
+        let server = Server::bind("127.0.0.1:0", options).unwrap();
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let request = format!("POST /check HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "x".repeat(2000));
+        assert!(exchange(address, &request).starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+        // A client that sends nothing holds the only connection until it times out
+        let idle = TcpStream::connect(address).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(exchange(address, "").starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        let mut response = String::new();
+        (&idle).read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    }
+}
@@ -0,0 +1,638 @@
+use crate::lexer::{self, Span, Token, TokenType};
+use crate::parser::{self, Alias, DataDecl, Module, Routine, Storage};
+
+// ------------------ Symbols -----------------------/
+
+/// Declaration a name in the source refers to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Symbol {
+    // File, module and data of the module
+    Data(usize, usize, usize),
+    // File, module and routine
+    Routine(usize, usize, usize),
+    // File, module, routine and its parameter or data, parameters first
+    Local(usize, usize, usize, usize),
+}
+
+/// Source file of a workspace, with the modules it declares
+pub struct SourceFile {
+    pub uri: String,
+    pub source: String,
+    tokens: Vec<Token>,
+    modules: Vec<Module>,
+    // First token of each module
+    module_starts: Vec<usize>,
+    // Source between the name and the end of each routine, by module
+    routine_ranges: Vec<Vec<(usize, usize)>>,
+}
+
+impl SourceFile {
+    /// Parse a file, the modules of a .sys file are system modules
+    pub fn new(uri: String, source: String) -> Result<SourceFile, String> {
+        let tokens = lexer::tokenize(&source).map_err(|error| error.to_string())?;
+        let mut modules = parser::parse_tokens(tokens.clone()).map_err(|err| err.to_string())?.modules;
+        if uri.to_ascii_lowercase().ends_with(".sys") {
+            modules.iter_mut().for_each(|module| module.system = true);
+        }
+        let module_starts = tokens.iter().enumerate()
+            .filter(|(_, token)| token.token_type == TokenType::Mod)
+            .map(|(idx, _)| idx)
+            .collect();
+        let routine_ranges = modules.iter()
+            .map(|module| module.routines.iter().map(|routine| {
+                let end = tokens.iter()
+                    .find(|token| token.span.start > routine.span.start && matches!(token.token_type, TokenType::EndProc | TokenType::EndFunc | TokenType::EndTrap))
+                    .map_or(source.len(), |token| token.span.end);
+                (routine.span.start, end)
+            }).collect())
+            .collect();
+        Ok(SourceFile { uri, source, tokens, modules, module_starts, routine_ranges })
+    }
+
+    /// Module and routine of the token at `idx`, if it's in one
+    fn place(&self, idx: usize) -> Option<(usize, Option<usize>)> {
+        self.place_at(self.tokens[idx].span.start)
+    }
+
+    /// Module and routine at a byte offset
+    fn place_at(&self, offset: usize) -> Option<(usize, Option<usize>)> {
+        let module = self.module_starts.iter().rposition(|start| self.tokens[*start].span.start <= offset)?;
+        let routine = self.routine_ranges[module].iter().position(|(start, end)| *start <= offset && offset <= *end);
+        Some((module, routine))
+    }
+}
+
+/// Declarations of the modules of several files, to find where a name is
+/// declared and where it's used. Names are looked up like RAPID does: the
+/// data of the routine, the module, the task and the system modules in turn.
+pub struct SymbolIndex {
+    pub files: Vec<SourceFile>,
+}
+
+impl SymbolIndex {
+    /// Index files given by URI and text. Files that don't parse are left
+    /// out. The modules of a .sys file are system modules.
+    pub fn new(files: Vec<(String, String)>) -> SymbolIndex {
+        let files = files.into_iter()
+            .filter_map(|(uri, source)| SourceFile::new(uri, source).ok())
+            .collect();
+        SymbolIndex { files }
+    }
+
+    pub fn file(&self, uri: &str) -> Option<usize> {
+        self.files.iter().position(|file| file.uri == uri)
+    }
+
+    /// Symbol of the name at a byte offset of a file
+    pub fn symbol_at(&self, file: usize, offset: usize) -> Option<Symbol> {
+        let tokens = &self.files.get(file)?.tokens;
+        let idx = tokens.iter().position(|token| token.span.start <= offset && offset <= token.span.end && matches!(token.token_type, TokenType::Id(_)))?;
+        self.resolve(file, idx)
+    }
+
+    /// Where a symbol is declared, file and span of the name
+    pub fn definition(&self, symbol: Symbol) -> (usize, Span) {
+        match symbol {
+            Symbol::Data(file, module, data) => (file, self.files[file].modules[module].variables[data].span),
+            Symbol::Routine(file, module, routine) => (file, self.files[file].modules[module].routines[routine].span),
+            Symbol::Local(file, module, routine, data) => {
+                let routine = &self.files[file].modules[module].routines[routine];
+                (file, routine_data(routine).nth(data).map_or(routine.span, |decl| decl.span))
+            },
+        }
+    }
+
+    /// Names that refer to a symbol, its declaration included
+    pub fn references(&self, symbol: Symbol) -> Vec<(usize, Span)> {
+        let mut found = Vec::new();
+        for (file_idx, file) in self.files.iter().enumerate() {
+            for (idx, token) in file.tokens.iter().enumerate() {
+                if matches!(token.token_type, TokenType::Id(_)) && self.resolve(file_idx, idx) == Some(symbol) {
+                    found.push((file_idx, token.span));
+                }
+            }
+        }
+        found
+    }
+
+    /// Alias declaration of a data type in any of the files
+    pub fn alias(&self, data_type: &str) -> Option<&Alias> {
+        self.files.iter()
+            .flat_map(|file| file.modules.iter())
+            .flat_map(|module| module.aliases.iter())
+            .find(|alias| alias.name.eq_ignore_ascii_case(data_type))
+    }
+
+    /// Declaration of the data, `None` for routines
+    pub fn data(&self, symbol: Symbol) -> Option<&DataDecl> {
+        match symbol {
+            Symbol::Data(file, module, data) => Some(&self.files[file].modules[module].variables[data]),
+            Symbol::Routine(..) => None,
+            Symbol::Local(file, module, routine, data) => routine_data(&self.files[file].modules[module].routines[routine]).nth(data),
+        }
+    }
+
+    fn routine(&self, symbol: Symbol) -> Option<&Routine> {
+        match symbol {
+            Symbol::Routine(file, module, routine) | Symbol::Local(file, module, routine, _) => Some(&self.files[file].modules[module].routines[routine]),
+            Symbol::Data(..) => None,
+        }
+    }
+
+    /// Module a symbol is declared in
+    pub fn module(&self, symbol: Symbol) -> &Module {
+        match symbol {
+            Symbol::Data(file, module, _) | Symbol::Routine(file, module, _) | Symbol::Local(file, module, _, _) => &self.files[file].modules[module],
+        }
+    }
+
+    /// Routine of a local symbol
+    pub fn owner(&self, symbol: Symbol) -> Option<&Routine> {
+        match symbol {
+            Symbol::Local(..) => self.routine(symbol),
+            _ => None,
+        }
+    }
+
+    /// Source of the value a data declaration starts with, like `[1, 2]`
+    pub fn initial_value(&self, symbol: Symbol) -> Option<&str> {
+        match (symbol, self.routine(symbol)) {
+            // Parameters have none
+            (Symbol::Local(_, _, _, data), Some(routine)) if data < routine.arguments.len() => return None,
+            (Symbol::Routine(..), _) => return None,
+            _ => (),
+        }
+        let (file, span) = self.definition(symbol);
+        let file = &self.files[file];
+        let name = file.tokens.binary_search_by_key(&span.start, |token| token.span.start).ok()?;
+        let mut tokens = file.tokens[name..].iter().skip_while(|token| token.token_type != TokenType::Assign && token.token_type != TokenType::Semicolon);
+        if tokens.next()?.token_type != TokenType::Assign {
+            return None;
+        }
+        let first = tokens.next()?.span;
+        let end = tokens.take_while(|token| token.token_type != TokenType::Semicolon).last().map_or(first.end, |token| token.span.end);
+        file.source.get(first.start..end)
+    }
+
+    /// Name of the data or routine
+    pub fn name(&self, symbol: Symbol) -> &str {
+        match (self.data(symbol), self.routine(symbol)) {
+            (Some(decl), _) => &decl.name,
+            (None, Some(routine)) => &routine.name,
+            (None, None) => "",
+        }
+    }
+
+    /// Declaration of the symbol, like `PERS num nCount` or the header of a
+    /// routine
+    pub fn detail(&self, symbol: Symbol) -> String {
+        let local = |local: bool| if local { "LOCAL " } else { "" };
+        match (symbol, self.data(symbol), self.routine(symbol)) {
+            (Symbol::Local(_, _, _, data), _, Some(routine)) if data < routine.arguments.len() => routine.arguments[data].declaration(),
+            (_, Some(decl), _) => format!("{}{} {} {}", local(decl.local), decl.storage.keyword(), decl.data_type, decl.name),
+            (_, None, Some(routine)) => format!("{}{}", local(routine.local), routine.declaration()),
+            (_, None, None) => String::new(),
+        }
+    }
+
+    /// Data and routines visible at a byte offset of a file, the ones that
+    /// shadow others of the same name first
+    pub fn in_scope(&self, file_idx: usize, offset: usize) -> Vec<Symbol> {
+        let file = match self.files.get(file_idx) {
+            Some(file) => file,
+            None => return Vec::new(),
+        };
+        let (module_idx, routine_idx) = match file.place_at(offset) {
+            Some(place) => place,
+            None => return Vec::new(),
+        };
+        let mut symbols = Vec::new();
+        if let Some(routine) = routine_idx {
+            let count = routine_data(&file.modules[module_idx].routines[routine]).count();
+            symbols.extend((0..count).map(|data| Symbol::Local(file_idx, module_idx, routine, data)));
+        }
+        let modules = self.files.iter().enumerate()
+            .flat_map(|(other_file, file)| file.modules.iter().enumerate().map(move |(other_module, module)| (other_file, other_module, module)));
+        let mut modules: Vec<(usize, usize, &Module)> = modules.collect();
+        // The module itself, then the task, then the system
+        modules.sort_by_key(|(other_file, other_module, module)| ((*other_file, *other_module) != (file_idx, module_idx), module.system));
+        for (other_file, other_module, module) in modules {
+            let inside = (other_file, other_module) == (file_idx, module_idx);
+            symbols.extend(module.variables.iter().enumerate()
+                .filter(|(_, decl)| inside || !decl.local)
+                .map(|(data, _)| Symbol::Data(other_file, other_module, data)));
+            symbols.extend(module.routines.iter().enumerate()
+                .filter(|(_, routine)| inside || !routine.local)
+                .map(|(routine, _)| Symbol::Routine(other_file, other_module, routine)));
+        }
+
+        let mut names: Vec<String> = Vec::new();
+        symbols.retain(|symbol| {
+            let name = self.name(*symbol).to_ascii_lowercase();
+            let new = !names.contains(&name);
+            names.push(name);
+            new
+        });
+        symbols
+    }
+
+    /// Declaration the identifier token `idx` of a file refers to
+    fn resolve(&self, file_idx: usize, idx: usize) -> Option<Symbol> {
+        let file = &self.files[file_idx];
+        let name = match &file.tokens[idx].token_type {
+            TokenType::Id(name) => name,
+            _ => return None,
+        };
+        // The name of an optional argument, like \\Tool, is a parameter of the
+        // routine called. Module names aren't looked up.
+        match file.tokens.get(idx.wrapping_sub(1)).map(|token| &token.token_type) {
+            Some(TokenType::Backslash) => return self.argument(file_idx, idx, name),
+            Some(TokenType::Mod) => return None,
+            _ => (),
+        }
+        let (module_idx, routine_idx) = file.place(idx)?;
+        let module = &file.modules[module_idx];
+
+        if let Some(routine) = routine_idx {
+            if let Some(data) = routine_data(&module.routines[routine]).position(|decl| decl.name.eq_ignore_ascii_case(name)) {
+                return Some(Symbol::Local(file_idx, module_idx, routine, data));
+            }
+        }
+        if let Some(symbol) = module_symbol(file_idx, module_idx, module, name, true) {
+            return Some(symbol);
+        }
+
+        // Global symbols of the task, then of the system modules
+        for system in [false, true].iter() {
+            for (other_file, file) in self.files.iter().enumerate() {
+                for (other_module, module) in file.modules.iter().enumerate() {
+                    if module.system != *system || (other_file, other_module) == (file_idx, module_idx) {
+                        continue;
+                    }
+                    if let Some(symbol) = module_symbol(other_file, other_module, module, name, false) {
+                        return Some(symbol);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+impl SymbolIndex {
+    /// Parameter an optional argument of a call names. The routine called is
+    /// the one before the argument list of a function, or else the first
+    /// name before it in the statement that has a parameter like it.
+    fn argument(&self, file_idx: usize, idx: usize, name: &str) -> Option<Symbol> {
+        let tokens = &self.files[file_idx].tokens;
+        let parameter = |callee: usize| match self.resolve(file_idx, callee)? {
+            Symbol::Routine(file, module, routine) => self.files[file].modules[module].routines[routine].arguments.iter()
+                .position(|param| param.optional && param.decl.name.eq_ignore_ascii_case(name))
+                .map(|param| Symbol::Local(file, module, routine, param)),
+            _ => None,
+        };
+        let mut depth = 0;
+        for before in (0..idx - 1).rev() {
+            match tokens[before].token_type {
+                TokenType::RightPar | TokenType::RightBrack | TokenType::RightBrace => depth += 1,
+                TokenType::LeftPar | TokenType::LeftBrack | TokenType::LeftBrace if depth > 0 => depth -= 1,
+                TokenType::LeftPar => return parameter(before.checked_sub(1)?),
+                TokenType::LeftBrack | TokenType::LeftBrace => return None,
+                TokenType::Semicolon | TokenType::Then | TokenType::Do | TokenType::Else | TokenType::Colon
+                | TokenType::Proc | TokenType::Func | TokenType::Trap => return None,
+                TokenType::Id(_) if depth == 0 => {
+                    if let Some(symbol) = parameter(before) {
+                        return Some(symbol);
+                    }
+                },
+                _ => (),
+            }
+        }
+        None
+    }
+}
+
+// ------------------ Rename -----------------------/
+
+/// Replacement of the source between byte offsets of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextEdit {
+    pub file: usize,
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+impl SymbolIndex {
+    /// Edits that rename a symbol everywhere it's used. Names are matched
+    /// without case, like RAPID does. The rename fails when the new name would
+    /// hide another symbol or be hidden by one, in the scopes of the uses.
+    pub fn rename(&self, symbol: Symbol, new_name: &str) -> Result<Vec<TextEdit>, String> {
+        match lexer::tokenize(new_name).as_deref() {
+            Ok([token]) if token.token_type == TokenType::Id(String::from(new_name)) => (),
+            _ => return Err(format!("Invalid name '{}'", new_name)),
+        }
+        let edits: Vec<TextEdit> = self.references(symbol).into_iter()
+            .map(|(file, span)| TextEdit { file, start: span.start, end: span.end, text: String::from(new_name) })
+            .collect();
+
+        // Every name should refer to what it did before
+        let mut sources: Vec<(String, String)> = self.files.iter().map(|file| (file.uri.clone(), file.source.clone())).collect();
+        for (file, source) in self.apply(&edits) {
+            sources[file].1 = source;
+        }
+        let renamed = SymbolIndex::new(sources);
+        if renamed.files.len() != self.files.len() {
+            return Err(format!("Renaming to '{}' makes a file fail to parse", new_name));
+        }
+        for (file_idx, file) in self.files.iter().enumerate() {
+            for (idx, token) in file.tokens.iter().enumerate() {
+                if matches!(token.token_type, TokenType::Id(_)) && self.resolve(file_idx, idx) != renamed.resolve(file_idx, idx) {
+                    return Err(format!("Renaming to '{}' changes what the name at {} of {} refers to", new_name, token.span, file.uri));
+                }
+            }
+        }
+        Ok(edits)
+    }
+
+    /// New source of the files with edits
+    pub fn apply(&self, edits: &[TextEdit]) -> Vec<(usize, String)> {
+        let mut changed: Vec<(usize, String)> = Vec::new();
+        for (file_idx, file) in self.files.iter().enumerate() {
+            let mut file_edits: Vec<&TextEdit> = edits.iter().filter(|edit| edit.file == file_idx).collect();
+            if file_edits.is_empty() {
+                continue;
+            }
+            // From the end, so the offsets of the others stay valid
+            file_edits.sort_by_key(|edit| std::cmp::Reverse(edit.start));
+            let mut source = file.source.clone();
+            for edit in file_edits {
+                source.replace_range(edit.start..edit.end, &edit.text);
+            }
+            changed.push((file_idx, source));
+        }
+        changed
+    }
+}
+
+// ------------------ Outline -----------------------/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutlineKind {
+    Module,
+    Procedure,
+    Function,
+    Variable,
+    Persistent,
+    Constant,
+}
+
+/// Declaration in the outline of a file, with the declarations inside it
+#[derive(Debug, Clone)]
+pub struct OutlineItem {
+    pub name: String,
+    pub kind: OutlineKind,
+    // Data type, or the header of a routine
+    pub detail: String,
+    // Name of the declaration
+    pub span: Span,
+    // Byte offsets of the whole declaration
+    pub start: usize,
+    pub end: usize,
+    pub children: Vec<OutlineItem>,
+}
+
+/// Modules of a source file with their data and routines, and the data of
+/// the routines
+pub fn outline(source: &str) -> Result<Vec<OutlineItem>, String> {
+    Ok(SourceFile::new(String::new(), String::from(source))?.outline())
+}
+
+impl SourceFile {
+    pub fn outline(&self) -> Vec<OutlineItem> {
+        self.modules.iter().enumerate().map(|(module_idx, module)| {
+            let start = self.module_starts[module_idx];
+            let end = self.tokens[start..].iter().find(|token| token.token_type == TokenType::EndMod)
+                .map_or(self.source.len(), |token| token.span.end);
+            let mut children: Vec<OutlineItem> = module.variables.iter().map(|decl| self.data_item(decl)).collect();
+            for (routine, (_, end)) in module.routines.iter().zip(self.routine_ranges[module_idx].iter()) {
+                children.push(OutlineItem {
+                    name: routine.name.clone(),
+                    kind: if routine.return_type.is_some() { OutlineKind::Function } else { OutlineKind::Procedure },
+                    detail: routine.declaration(),
+                    span: routine.span,
+                    start: self.declaration_start(routine.span),
+                    end: *end,
+                    children: routine.variables.iter().map(|decl| self.data_item(decl)).collect(),
+                });
+            }
+            // Declarations in the order of the source
+            children.sort_by_key(|item| item.start);
+            OutlineItem {
+                name: module.name.clone(),
+                kind: OutlineKind::Module,
+                detail: module.attributes.join(", "),
+                span: self.tokens.get(start + 1).map_or(self.tokens[start].span, |token| token.span),
+                start: self.tokens[start].span.start,
+                end,
+                children,
+            }
+        }).collect()
+    }
+
+    fn data_item(&self, decl: &DataDecl) -> OutlineItem {
+        let end = self.tokens.iter().find(|token| token.span.start > decl.span.start && token.token_type == TokenType::Semicolon)
+            .map_or(decl.span.end, |token| token.span.end);
+        OutlineItem {
+            name: decl.name.clone(),
+            kind: match decl.storage {
+                Storage::Var => OutlineKind::Variable,
+                Storage::Pers => OutlineKind::Persistent,
+                Storage::Const => OutlineKind::Constant,
+            },
+            detail: decl.data_type.clone(),
+            span: decl.span,
+            start: self.declaration_start(decl.span),
+            end,
+            children: Vec::new(),
+        }
+    }
+
+    /// Start of LOCAL or the keyword of the declaration with a name
+    fn declaration_start(&self, name: Span) -> usize {
+        let mut idx = match self.tokens.binary_search_by_key(&name.start, |token| token.span.start) {
+            Ok(idx) => idx,
+            Err(_) => return name.start,
+        };
+        let keyword = |idx: usize| matches!(self.tokens[idx].token_type,
+            TokenType::Var | TokenType::Pers | TokenType::Const | TokenType::Proc | TokenType::Func | TokenType::Trap);
+        // The data type or return type
+        if idx > 0 && !keyword(idx - 1) {
+            idx -= 1;
+        }
+        if idx > 0 && keyword(idx - 1) {
+            idx -= 1;
+            if idx > 0 && self.tokens[idx - 1].token_type == TokenType::Local {
+                idx -= 1;
+            }
+        }
+        self.tokens[idx].span.start
+    }
+}
+
+/// Parameters and data of a routine, in slot order
+fn routine_data(routine: &Routine) -> impl Iterator<Item = &DataDecl> {
+    routine.arguments.iter().map(|param| &param.decl).chain(routine.variables.iter())
+}
+
+/// Data or routine of a module with a name, LOCAL ones only from inside
+fn module_symbol(file: usize, module_idx: usize, module: &Module, name: &str, inside: bool) -> Option<Symbol> {
+    let data = module.variables.iter().position(|decl| decl.name.eq_ignore_ascii_case(name) && (inside || !decl.local));
+    if let Some(data) = data {
+        return Some(Symbol::Data(file, module_idx, data));
+    }
+    module.routines.iter()
+        .position(|routine| routine.name.eq_ignore_ascii_case(name) && (inside || !routine.local))
+        .map(|routine| Symbol::Routine(file, module_idx, routine))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TOOLS: &str = "MODULE user(SYSMODULE)
+    PERS num nSpeed := 100;
+    PROC Home()
+    ENDPROC
+ENDMODULE";
+
+    const CELL: &str = "MODULE Cell
+    PERS num nCount := 0;
+    LOCAL VAR num nSpeed := 50;
+    PROC Main()
+        Pick nCount;
+        nSpeed := nSpeed + 1;
+        Home;
+    ENDPROC
+ENDMODULE";
+
+    const PICK: &str = "MODULE Pick
+    PROC Pick(INOUT num nCount)
+        nCount := nCount + 1;
+    ENDPROC
+ENDMODULE";
+
+    fn index() -> SymbolIndex {
+        SymbolIndex::new(vec![
+            (String::from("file:///user.sys"), String::from(TOOLS)),
+            (String::from("file:///Cell.mod"), String::from(CELL)),
+            (String::from("file:///Pick.mod"), String::from(PICK)),
+        ])
+    }
+
+    /// Files and lines of the references to the name at a line and column
+    fn references(index: &SymbolIndex, file: usize, line: usize, column: usize) -> Vec<(usize, usize)> {
+        let offset = index.files[file].tokens.iter().find(|token| token.span.line == line && token.span.column == column).unwrap().span.start;
+        let symbol = index.symbol_at(file, offset).unwrap();
+        index.references(symbol).iter().map(|(file, span)| (*file, span.line)).collect()
+    }
+
+    #[test]
+    fn finds_declarations_and_uses() {
+        let index = index();
+        assert_eq!(index.files.len(), 3);
+        // Module data across modules, not the parameter of the same name
+        assert_eq!(references(&index, 1, 5, 14), vec![(1, 2), (1, 5)]);
+        assert_eq!(references(&index, 2, 3, 9), vec![(2, 2), (2, 3), (2, 3)]);
+        // The module data shadows the system data
+        assert_eq!(references(&index, 1, 6, 9), vec![(1, 3), (1, 6), (1, 6)]);
+        assert_eq!(references(&index, 0, 2, 14), vec![(0, 2)]);
+        // Routines of other modules and of the system
+        assert_eq!(references(&index, 1, 5, 9), vec![(1, 5), (2, 2)]);
+        assert_eq!(references(&index, 0, 3, 10), vec![(0, 3), (1, 7)]);
+
+        let symbol = index.symbol_at(1, CELL.find("Home;").unwrap()).unwrap();
+        assert_eq!(symbol, Symbol::Routine(0, 0, 0));
+        assert_eq!(index.definition(symbol).1.line, 3);
+        assert_eq!(index.symbol_at(1, CELL.find("Main").unwrap()), Some(Symbol::Routine(1, 0, 0)));
+        assert_eq!(index.symbol_at(1, 0), None);
+    }
+
+    #[test]
+    fn names_in_scope() {
+        let index = index();
+        let names = |file: usize, offset: usize| -> Vec<String> {
+            index.in_scope(file, offset).iter().map(|symbol| index.detail(*symbol)).collect()
+        };
+        assert_eq!(names(2, PICK.find("nCount :=").unwrap()), vec![
+            "INOUT num nCount",
+            "PROC Pick(INOUT num nCount)",
+            "PROC Main()",
+            "PERS num nSpeed",
+            "PROC Home()",
+        ]);
+        // Outside of the routines
+        assert_eq!(names(1, CELL.find("PROC").unwrap()), vec![
+            "PERS num nCount",
+            "LOCAL VAR num nSpeed",
+            "PROC Main()",
+            "PROC Pick(INOUT num nCount)",
+            "PROC Home()",
+        ]);
+    }
+
+    #[test]
+    fn outlines_modules() {
+        let items = outline("MODULE Cell(NOSTEPIN)
+    LOCAL CONST num nMax := 3;
+    PROC Main()
+        VAR num i;
+    ENDPROC
+    PERS num nCount := 0;
+    LOCAL FUNC num Twice(num x)
+        RETURN 2 * x;
+    ENDFUNC
+ENDMODULE").unwrap();
+        assert_eq!(items.len(), 1);
+        let cell = &items[0];
+        assert_eq!((cell.name.as_str(), cell.kind, cell.detail.as_str(), cell.span.line), ("Cell", OutlineKind::Module, "NOSTEPIN", 1));
+        let children: Vec<(&str, OutlineKind, &str, usize, usize)> = cell.children.iter()
+            .map(|item| (item.name.as_str(), item.kind, item.detail.as_str(), item.start, item.end))
+            .collect();
+        assert_eq!(children, vec![
+            ("nMax", OutlineKind::Constant, "num", 26, 52),
+            ("Main", OutlineKind::Procedure, "PROC Main()", 57, 99),
+            ("nCount", OutlineKind::Persistent, "num", 104, 125),
+            ("Twice", OutlineKind::Function, "FUNC num Twice(num x)", 130, 191),
+        ]);
+        assert_eq!(cell.children[1].children[0].name, "i");
+        assert_eq!((cell.start, cell.end), (0, 201));
+        assert!(outline("MODULE Cell\n    PROC Main(\nENDMODULE").is_err());
+    }
+
+    #[test]
+    fn renames_across_modules() {
+        let index = index();
+        let symbol = index.symbol_at(1, CELL.find("nCount").unwrap()).unwrap();
+        let edits = index.rename(symbol, "nParts").unwrap();
+        assert_eq!(edits.iter().map(|edit| (edit.file, edit.start)).collect::<Vec<_>>(),
+            vec![(1, CELL.find("nCount").unwrap()), (1, CELL.find("nCount;").unwrap())]);
+        let changed = index.apply(&edits);
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].1, CELL.replace("nCount", "nParts"));
+
+        assert_eq!(index.rename(symbol, "nSpeed").unwrap_err(), "Renaming to 'nSpeed' changes what the name at 3:19 of file:///Cell.mod refers to");
+        let home = index.symbol_at(0, TOOLS.find("Home").unwrap()).unwrap();
+        assert!(index.rename(home, "main").is_err());
+        assert_eq!(index.rename(home, "GoHome").unwrap().len(), 2);
+        assert_eq!(index.rename(home, "IF").unwrap_err(), "Invalid name 'IF'");
+        assert!(index.rename(home, "Go Home").is_err());
+
+        // Optional arguments name the parameter
+        let source = "MODULE Cell\n    PROC Pick(\\switch Fast)\n    ENDPROC\n    PROC Main()\n        Pick \\Fast;\n    ENDPROC\nENDMODULE";
+        let index = SymbolIndex::new(vec![(String::from("file:///Cell.mod"), String::from(source))]);
+        let fast = index.symbol_at(0, source.find("Fast").unwrap()).unwrap();
+        let edits = index.rename(fast, "Quick").unwrap();
+        assert_eq!(index.apply(&edits)[0].1, source.replace("Fast", "Quick"));
+    }
+}
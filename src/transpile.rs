@@ -0,0 +1,1241 @@
+use std::collections::HashMap;
+
+use crate::builtins;
+use crate::parser::{Argument, Arena, DataDecl, Node, NodeId, Operator, ParamMode, Program, Routine, Statement, Storage, WriteArg};
+use crate::variable::{self, Variable};
+
+// ------------------ Python -----------------------/
+
+/// Start of the Python file, before the stubs
+const PRELUDE: &str = "\
+import math
+
+
+class RapidError(Exception):
+    \"\"\"Error of a RAISE or a stub, by its ERRNO\"\"\"
+
+    def __init__(self, errno):
+        super().__init__(errno)
+        self.errno = errno
+
+
+# Calls of the stubs in order, like (\"MoveL\", (p10, v1000, fine, tool0), {})
+calls = []
+# Values of the signals by name, the I/O stubs set them
+signals = {}
+
+
+def signal(name):
+    return signals.get(name, 0)
+";
+
+/// Names of the prelude and the handlers, a routine or data of the program
+/// with one of them gets a `_` after it
+const RESERVED: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif",
+    "else", "except", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda", "nonlocal", "not", "or",
+    "pass", "raise", "return", "try", "while", "with", "yield",
+    "RapidError", "calls", "error", "math", "print", "range", "signal", "signals",
+];
+
+/// I/O instructions and functions that take a signal as their first
+/// argument
+const SIGNAL_ROUTINES: &[&str] = &[
+    "Set", "Reset", "SetDO", "SetAO", "SetGO", "PulseDO", "InvertDO",
+    "WaitDI", "WaitDO", "WaitAI", "WaitAO", "WaitGI", "WaitGO",
+    "DInput", "DOutput", "AInput", "AOutput", "GInput", "GOutput", "TestDI",
+];
+
+/// Python of the modules of a parsed program, before it's resolved, for
+/// prototyping the logic of a cell outside the controller. Each routine is
+/// a function and each module data a global. The instructions and functions
+/// the program doesn't declare, like MoveL and SetDO, are stubs that record
+/// their calls, and the I/O stubs set and read the signals. Data the program
+/// doesn't declare, like v1000 or a signal, is its name as a string.
+///
+/// Arrays are lists indexed from 0, VAR and INOUT parameters are passed by
+/// value and RETRY and TRYNEXT don't resume the routine.
+pub fn python(program: &Program) -> String {
+    let mut writer = Writer::new(program);
+    for module in program.modules.iter() {
+        writer.module(module);
+    }
+
+    let mut text = format!("# Python of the RAPID modules {}, with stubs of the\n", program.modules.iter().map(|module| module.name.as_str()).collect::<Vec<_>>().join(", "));
+    text.push_str("# instructions they call. Generated by rapid-rust.\n\n");
+    text.push_str(PRELUDE);
+    for (name, function) in writer.stubs.iter() {
+        text.push_str(&format!("\n\ndef {}(*args, **options):\n", name));
+        text.push_str(&format!("    calls.append((\"{}\", args, options))\n", name));
+        text.push_str(&stub_body(name, *function));
+    }
+    if !writer.undeclared.is_empty() {
+        text.push_str("\n\n# Data the program doesn't declare\n");
+        for name in writer.undeclared.iter() {
+            match (builtins::error_number(name), builtins::math_data(name).or_else(|| builtins::string_data(name))) {
+                (Some(errno), _) => text.push_str(&format!("{} = {}\n", name, errno)),
+                (None, Some(Variable::Num(value) | Variable::Dnum(value))) => text.push_str(&format!("{} = {:?}\n", name, value)),
+                (None, Some(Variable::Str(set))) => text.push_str(&format!("{} = {:?}\n", name, set)),
+                (None, _) => text.push_str(&format!("{} = \"{}\"\n", name, name)),
+            }
+        }
+    }
+    text.push_str(&writer.text);
+    if let Some(main) = writer.routines.get("main") {
+        text.push_str(&format!("\n\nif __name__ == \"__main__\":\n    {}()\n", main));
+    }
+    text
+}
+
+/// Body of a stub after it records the call
+fn stub_body(name: &str, function: bool) -> String {
+    let body = match name.to_ascii_lowercase().as_str() {
+        "set" => "signals[args[0]] = 1",
+        "reset" => "signals[args[0]] = 0",
+        "setdo" | "setao" | "setgo" => "signals[args[0]] = args[1]",
+        "invertdo" => "signals[args[0]] = 1 - signal(args[0])",
+        "dinput" | "doutput" | "ainput" | "aoutput" | "ginput" | "goutput" => "return signal(args[0])",
+        "testdi" => "return signal(args[0]) == 1",
+        _ if function => "return 0",
+        _ => return String::new(),
+    };
+    format!("    {}\n", body)
+}
+
+/// Python of a data value, a record or array is a list
+fn literal(value: &Variable) -> String {
+    match value {
+        Variable::Bool(true) => String::from("True"),
+        Variable::Bool(false) => String::from("False"),
+        Variable::Num(value) | Variable::Dnum(value) => number(*value),
+        Variable::Byte(value) => value.to_string(),
+        Variable::Str(text) => string(text),
+        Variable::Record(_, items) | Variable::Array(items) => format!("[{}]", items.iter().map(literal).collect::<Vec<_>>().join(", ")),
+        Variable::Signal(_, name) | Variable::SyncIdent(name) if !name.is_empty() => string(name),
+        _ => String::from("None"),
+    }
+}
+
+/// Whole numbers are ints, so that they index lists and print as in RAPID
+fn number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        (value as i64).to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Name of the program in Python, clear of the keywords and the prelude
+fn identifier(name: &str) -> String {
+    if RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else {
+        String::from(name)
+    }
+}
+
+/// Precedence of the Python of an operator, from `or` up
+fn precedence(op: Operator) -> (u8, &'static str) {
+    match op {
+        Operator::Or => (1, "or"),
+        Operator::And => (2, "and"),
+        Operator::Xor => (4, "!="),
+        Operator::Equal => (4, "=="),
+        Operator::NotEqual => (4, "!="),
+        Operator::Less => (4, "<"),
+        Operator::LessEqual => (4, "<="),
+        Operator::Greater => (4, ">"),
+        Operator::GreaterEqual => (4, ">="),
+        Operator::Add => (6, "+"),
+        Operator::Sub => (6, "-"),
+        Operator::Mul => (7, "*"),
+        Operator::Div => (7, "/"),
+        Operator::IntDiv => (7, "//"),
+        Operator::Mod => (7, "%"),
+    }
+}
+
+/// Precedence of `not`, of a unary minus and of a name, literal or call
+const NOT: u8 = 3;
+const NEG: u8 = 8;
+const ATOM: u8 = 9;
+
+// ------------------ Writer -----------------------/
+
+/// Arena of the writers before they write a routine
+const NO_NODES: &Arena = &Arena::new();
+
+/// Python of the modules, with the names it found along the way
+struct Writer<'a> {
+    text: String,
+    depth: usize,
+    // Module data and routines of the program by lower case name, as
+    // declared
+    globals: HashMap<String, String>,
+    routines: HashMap<String, String>,
+    // Parameters and local data of the routine being written
+    locals: HashMap<String, String>,
+    // Names of the signal arguments of the I/O instructions
+    signals: Vec<String>,
+    // Stubs by name as first called, and whether they are functions
+    stubs: Vec<(String, bool)>,
+    undeclared: Vec<String>,
+    // Writing an ERROR handler, where ERRNO is the number of the error
+    handler: bool,
+    // Operands of the routine being written
+    nodes: &'a Arena,
+}
+
+impl<'a> Writer<'a> {
+    fn new(program: &'a Program) -> Writer<'a> {
+        let mut writer = Writer {
+            text: String::new(),
+            depth: 0,
+            globals: HashMap::new(),
+            routines: HashMap::new(),
+            locals: HashMap::new(),
+            signals: Vec::new(),
+            stubs: Vec::new(),
+            undeclared: Vec::new(),
+            handler: false,
+            nodes: NO_NODES,
+        };
+        for module in program.modules.iter() {
+            for decl in module.variables.iter() {
+                writer.globals.insert(decl.name.to_ascii_lowercase(), identifier(&decl.name));
+            }
+            for routine in module.routines.iter() {
+                writer.routines.insert(routine.name.to_ascii_lowercase(), identifier(&routine.name));
+                writer.nodes = &routine.nodes;
+                let bodies = std::iter::once(&routine.statements).chain(routine.handler.iter());
+                for statement in bodies.flatten() {
+                    writer.find_signals(statement);
+                }
+            }
+        }
+        writer
+    }
+
+    /// Collect the names passed as the signal of an I/O instruction
+    fn find_signals(&mut self, statement: &Statement) {
+        let mut nodes = vec![&statement.node];
+        while let Some(node) = nodes.pop() {
+            if let Node::ProcCall { name, args, .. } | Node::FuncCall { name, args, .. } = node {
+                let first = args.iter().find(|arg| arg.name.is_none()).and_then(|arg| arg.value.as_ref());
+                if let (true, Some(Node::Id(signal, _))) = (SIGNAL_ROUTINES.iter().any(|routine| routine.eq_ignore_ascii_case(name)), first) {
+                    self.signals.push(signal.to_ascii_lowercase());
+                }
+            }
+            nodes.extend(node.children(self.nodes));
+        }
+        for body in statement.node.bodies() {
+            for statement in body {
+                self.find_signals(statement);
+            }
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.depth {
+            self.text.push_str("    ");
+        }
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    /// Operand node of the routine being written
+    fn node(&self, id: NodeId) -> &'a Node {
+        &self.nodes[id]
+    }
+
+    fn module(&mut self, module: &'a crate::parser::Module) {
+        self.text.push_str(&format!("\n\n# ------------------ Module {} -----------------------\n", module.name));
+        if !module.variables.is_empty() {
+            self.text.push('\n');
+        }
+        for decl in module.variables.iter() {
+            let line = self.declaration(decl);
+            self.line(&line);
+        }
+        for routine in module.routines.iter() {
+            self.routine(routine);
+        }
+    }
+
+    /// Data with its value, and its storage and type as a comment
+    fn declaration(&self, decl: &DataDecl) -> String {
+        format!("{} = {}  # {} {}", identifier(&decl.name), literal(&decl.value), decl.storage.keyword(), decl.data_type)
+    }
+
+    fn routine(&mut self, routine: &'a Routine) {
+        self.nodes = &routine.nodes;
+        self.locals.clear();
+        for decl in routine.arguments.iter().map(|param| &param.decl).chain(routine.variables.iter()) {
+            self.locals.insert(decl.name.to_ascii_lowercase(), identifier(&decl.name));
+        }
+
+        // Optional parameters are passed by name, as in RAPID
+        let mut params: Vec<String> = routine.arguments.iter().filter(|param| !param.optional).map(|param| identifier(&param.decl.name)).collect();
+        if routine.arguments.iter().any(|param| param.optional) {
+            params.push(String::from("*"));
+            params.extend(routine.arguments.iter().filter(|param| param.optional).map(|param| format!("{}=None", identifier(&param.decl.name))));
+        }
+        self.text.push_str("\n\n");
+        self.line(&format!("# {}", routine.declaration()));
+        self.line(&format!("def {}({}):", identifier(&routine.name), params.join(", ")));
+        self.depth += 1;
+
+        let bodies = std::iter::once(&routine.statements).chain(routine.handler.iter());
+        let mut assigned = Vec::new();
+        for statement in bodies.flatten() {
+            self.assigned_globals(statement, &mut assigned);
+        }
+        if !assigned.is_empty() {
+            self.line(&format!("global {}", assigned.join(", ")));
+        }
+        for decl in routine.variables.iter() {
+            let line = self.declaration(decl);
+            self.line(&line);
+        }
+
+        match &routine.handler {
+            Some(handler) => {
+                self.line("try:");
+                self.body(&routine.statements);
+                self.line("except RapidError as error:");
+                self.handler = true;
+                self.body(handler);
+                self.handler = false;
+            },
+            None if routine.statements.is_empty() && routine.variables.is_empty() => self.line("pass"),
+            None => self.statements(&routine.statements),
+        }
+        self.depth -= 1;
+    }
+
+    /// Module data a statement assigns to, which the function declares
+    /// global
+    fn assigned_globals(&mut self, statement: &Statement, assigned: &mut Vec<String>) {
+        let mut target = match &statement.node {
+            Node::Assign { lhs, .. } => Some(self.node(*lhs)),
+            Node::For { var, .. } => Some(self.node(*var)),
+            _ => None,
+        };
+        while let Some(Node::Index { base, .. }) = target {
+            target = Some(self.node(*base));
+        }
+        if let Some(Node::Id(name, _)) = target {
+            let name = name.to_ascii_lowercase();
+            if let (false, Some(global)) = (self.locals.contains_key(&name), self.globals.get(&name)) {
+                if !assigned.contains(global) {
+                    assigned.push(global.clone());
+                }
+            }
+        }
+        for body in statement.node.bodies() {
+            for statement in body {
+                self.assigned_globals(statement, assigned);
+            }
+        }
+    }
+
+    /// Statements of a block one level in, `pass` for none
+    fn body(&mut self, statements: &[Statement]) {
+        self.depth += 1;
+        if statements.is_empty() {
+            self.line("pass");
+        }
+        self.statements(statements);
+        self.depth -= 1;
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.statement(&statement.node);
+        }
+    }
+
+    fn statement(&mut self, node: &Node) {
+        match node {
+            Node::Assign { lhs, rhs } => {
+                let line = format!("{} = {}", self.expr(self.node(*lhs), 0), self.expr(self.node(*rhs), 0));
+                self.line(&line);
+            },
+            Node::Print { text, arg } => {
+                let line = match arg {
+                    Some((WriteArg::Num | WriteArg::Dnum | WriteArg::Bool | WriteArg::Pos, value)) => {
+                        format!("print({} + str({}))", self.expr(self.node(*text), 7), self.expr(self.node(*value), 0))
+                    },
+                    None => format!("print({})", self.expr(self.node(*text), 0)),
+                };
+                self.line(&line);
+            },
+            Node::ProcCall { name, args, .. } => {
+                let line = self.call(name, args, false);
+                self.line(&line);
+            },
+            Node::LateCall { name, args, .. } => {
+                let line = format!("globals()[{}]({})", self.expr(self.node(*name), 0), self.arguments(args));
+                self.line(&line);
+            },
+            Node::Return(Some(value)) => {
+                let line = format!("return {}", self.expr(self.node(*value), 0));
+                self.line(&line);
+            },
+            Node::Return(None) => self.line("return"),
+            Node::Retry => self.line("pass  # RETRY doesn't resume the routine"),
+            Node::TryNext => self.line("pass  # TRYNEXT doesn't resume the routine"),
+            Node::Connect { trap, .. } => self.line(&format!("pass  # CONNECT {} doesn't order interrupts", trap)),
+            Node::Raise(Some(errno)) => {
+                let line = format!("raise RapidError({})", self.expr(self.node(*errno), 0));
+                self.line(&line);
+            },
+            Node::Raise(None) => self.line("raise"),
+            Node::If { branches, otherwise } => {
+                for (idx, (condition, body)) in branches.iter().enumerate() {
+                    let line = format!("{} {}:", if idx == 0 { "if" } else { "elif" }, self.expr(condition, 0));
+                    self.line(&line);
+                    self.body(body);
+                }
+                if !otherwise.is_empty() {
+                    self.line("else:");
+                    self.body(otherwise);
+                }
+            },
+            Node::While { condition, body } => {
+                let line = format!("while {}:", self.expr(self.node(*condition), 0));
+                self.line(&line);
+                self.body(body);
+            },
+            Node::For { var, from, to, step, body } => {
+                // The loop variable is declared by the loop
+                let (var, to) = (self.node(*var), self.node(*to));
+                if let Node::Id(name, _) = var {
+                    self.locals.insert(name.to_ascii_lowercase(), identifier(name));
+                }
+                let from = self.expr(self.node(*from), 0);
+                let range = match step.map(|step| self.node(step)) {
+                    None => format!("range({}, {} + 1)", from, self.expr(to, 6)),
+                    Some(Node::OpNeg(step)) => format!("range({}, {} - 1, -{})", from, self.expr(to, 6), self.expr(self.node(*step), NEG)),
+                    Some(step) => format!("range({}, {} + 1, {})", from, self.expr(to, 6), self.expr(step, 0)),
+                };
+                let line = format!("for {} in {}:", self.expr(var, 0), range);
+                self.line(&line);
+                self.body(body);
+            },
+            Node::Test { value, cases, default } => {
+                let value = self.expr(self.node(*value), 5);
+                for (idx, (values, body)) in cases.iter().enumerate() {
+                    let condition = match values.as_slice() {
+                        [single] => format!("{} == {}", value, self.expr(single, 5)),
+                        values => format!("{} in ({})", value, values.iter().map(|case| self.expr(case, 0)).collect::<Vec<_>>().join(", ")),
+                    };
+                    self.line(&format!("{} {}:", if idx == 0 { "if" } else { "elif" }, condition));
+                    self.body(body);
+                }
+                match (cases.is_empty(), default.is_empty()) {
+                    (true, _) => self.statements(default),
+                    (false, true) => (),
+                    (false, false) => {
+                        self.line("else:");
+                        self.body(default);
+                    },
+                }
+            },
+            node => {
+                let line = self.expr(node, 0);
+                self.line(&line);
+            },
+        }
+    }
+
+    /// Python of an expression, in parentheses when its precedence is below
+    /// the given one
+    fn expr(&mut self, node: &Node, min: u8) -> String {
+        let (prec, text) = match node {
+            Node::BinOp { op, lhs, rhs } => {
+                let (prec, symbol) = precedence(*op);
+                // Comparisons would chain in Python
+                let left = if prec == 4 { prec + 1 } else { prec };
+                (prec, format!("{} {} {}", self.expr(self.node(*lhs), left), symbol, self.expr(self.node(*rhs), prec + 1)))
+            },
+            Node::OpNeg(node) => (NEG, format!("-{}", self.expr(self.node(*node), NEG))),
+            Node::OpNot(node) => (NOT, format!("not {}", self.expr(self.node(*node), NOT))),
+            Node::Value(value) => (ATOM, literal(value)),
+            Node::Aggregate(items) => (ATOM, format!("[{}]", items.iter().map(|item| self.expr(item, 0)).collect::<Vec<_>>().join(", "))),
+            Node::Id(name, _) => match self.name(name) {
+                Some(name) => (ATOM, name),
+                None if name.eq_ignore_ascii_case("ERRNO") && self.handler => (ATOM, String::from("error.errno")),
+                None => {
+                    let name = self.undeclared(name);
+                    if self.signals.contains(&name.to_ascii_lowercase()) {
+                        (ATOM, format!("signal({})", name))
+                    } else {
+                        (ATOM, name)
+                    }
+                },
+            },
+            Node::Index { base, indices } => {
+                let mut text = self.expr(self.node(*base), ATOM);
+                for index in indices {
+                    // Arrays count from 1 in RAPID
+                    match index {
+                        Node::Value(Variable::Num(value)) => text.push_str(&format!("[{}]", number(value - 1.0))),
+                        index => text.push_str(&format!("[{} - 1]", self.expr(index, 6))),
+                    }
+                }
+                (ATOM, text)
+            },
+            Node::FuncCall { name, args, .. } => (ATOM, self.call(name, args, true)),
+            // Slots of the resolver, and statements
+            _ => (ATOM, String::from("None")),
+        };
+        if prec < min {
+            format!("({})", text)
+        } else {
+            text
+        }
+    }
+
+    /// Name of a parameter, data or routine of the program
+    fn name(&self, name: &str) -> Option<String> {
+        let name = name.to_ascii_lowercase();
+        self.locals.get(&name).or_else(|| self.globals.get(&name)).cloned()
+    }
+
+    /// Name of data the program doesn't declare, as first written
+    fn undeclared(&mut self, name: &str) -> String {
+        match self.undeclared.iter().find(|other| other.eq_ignore_ascii_case(name)) {
+            Some(other) => other.clone(),
+            None => {
+                self.undeclared.push(identifier(name));
+                identifier(name)
+            },
+        }
+    }
+
+    /// Call of a routine of the program, a Python function like `abs` for
+    /// the built-in functions with one, or a stub
+    fn call(&mut self, name: &str, args: &[Argument], function: bool) -> String {
+        if let Some(routine) = self.routines.get(&name.to_ascii_lowercase()).cloned() {
+            return format!("{}({})", routine, self.arguments(args));
+        }
+        if function {
+            if let Some(call) = self.python_function(name, args) {
+                return call;
+            }
+        }
+        let stub = match self.stubs.iter().find(|(other, _)| other.eq_ignore_ascii_case(name)) {
+            Some((stub, _)) => stub.clone(),
+            None => {
+                self.stubs.push((identifier(name), function));
+                identifier(name)
+            },
+        };
+        format!("{}({})", stub, self.arguments(args))
+    }
+
+    /// Built-in function with a Python equivalent
+    fn python_function(&mut self, name: &str, args: &[Argument]) -> Option<String> {
+        let positional: Vec<&Node> = args.iter().filter(|arg| arg.name.is_none()).filter_map(|arg| arg.value.as_ref()).collect();
+        let named = |option: &str| args.iter().find(|arg| arg.name.as_deref().is_some_and(|name| name.eq_ignore_ascii_case(option)));
+        let call = match (name.to_ascii_lowercase().as_str(), positional.as_slice()) {
+            ("present", [Node::Id(param, _)]) => format!("{} is not None", self.name(param)?),
+            ("abs", [value]) => format!("abs({})", self.expr(value, 0)),
+            ("sqrt", [value]) => format!("math.sqrt({})", self.expr(value, 0)),
+            ("pow", [base, exponent]) => format!("math.pow({}, {})", self.expr(base, 0), self.expr(exponent, 0)),
+            // RAPID takes the angles in degrees
+            ("sin" | "cos" | "tan", [value]) => format!("math.{}(math.radians({}))", name.to_ascii_lowercase(), self.expr(value, 0)),
+            ("round", [value]) => match named("Dec").and_then(|arg| arg.value.as_ref()) {
+                Some(dec) => format!("round({}, {})", self.expr(value, 0), self.expr(dec, 0)),
+                None => format!("round({})", self.expr(value, 0)),
+            },
+            ("trunc", [value]) if named("Dec").is_none() => format!("math.trunc({})", self.expr(value, 0)),
+            ("strlen", [text]) => format!("len({})", self.expr(text, 0)),
+            ("valtostr", [value]) => format!("str({})", self.expr(value, 0)),
+            ("numtostr", [value, Node::Value(Variable::Num(dec))]) => format!("format({}, \".{}f\")", self.expr(value, 0), number(*dec)),
+            _ => return None,
+        };
+        // `not` binds looser than a comparison
+        Some(if name.eq_ignore_ascii_case("present") { format!("({})", call) } else { call })
+    }
+
+    /// Arguments of a call, the optional arguments by name and a switch as
+    /// True
+    fn arguments(&mut self, args: &[Argument]) -> String {
+        let mut texts = Vec::new();
+        for arg in args {
+            let value = match &arg.value {
+                // Undeclared data like a signal is passed by its name
+                Some(Node::Id(name, _)) if self.name(name).is_none() && !(name.eq_ignore_ascii_case("ERRNO") && self.handler) => self.undeclared(name),
+                Some(value) => self.expr(value, 0),
+                None => String::from("True"),
+            };
+            match &arg.name {
+                Some(name) => texts.push(format!("{}={}", identifier(name), value)),
+                None => texts.push(value),
+            }
+        }
+        texts.join(", ")
+    }
+}
+
+// ------------------ Structured Text -----------------------/
+
+/// Structured Text of IEC 61131-3 of the logic of a parsed program, to
+/// migrate it to a PLC. Module data are globals and routines functions,
+/// with the assignments, IF, WHILE, FOR and TEST of their statements and
+/// the arithmetic and comparisons of their expressions. The I/O
+/// instructions that set a signal assign to it. Other statements, like
+/// the motion instructions, are comments to port by hand.
+///
+/// A num is a REAL and a FOR loop counts in a DINT, records keep the name
+/// of their type, which the PLC project declares as a structure.
+pub fn structured_text(program: &Program) -> String {
+    let mut writer = StWriter { text: String::new(), depth: 0, routines: Vec::new(), function: None, nodes: NO_NODES };
+    for module in program.modules.iter() {
+        writer.routines.extend(module.routines.iter().map(|routine| routine.name.to_ascii_lowercase()));
+    }
+
+    writer.text.push_str(&format!("(* Structured Text of the RAPID modules {}.\n", program.modules.iter().map(|module| module.name.as_str()).collect::<Vec<_>>().join(", ")));
+    writer.text.push_str("   Generated by rapid-rust. *)\n");
+
+    let mut records = Vec::new();
+    for module in program.modules.iter() {
+        let routine_types = module.routines.iter().flat_map(|routine| {
+            routine.arguments.iter().map(|param| &param.decl).chain(routine.variables.iter()).map(|decl| &decl.data_type)
+                .chain(routine.return_type.iter())
+        });
+        for data_type in module.variables.iter().map(|decl| &decl.data_type).chain(routine_types) {
+            declare_record(data_type, &mut records);
+        }
+    }
+    if !records.is_empty() {
+        writer.text.push_str("\n(* ------------------ Types ----------------------- *)\n");
+    }
+    for (name, components) in records {
+        writer.text.push('\n');
+        writer.line(&format!("TYPE {} : STRUCT", name));
+        writer.depth += 1;
+        for (component, data_type) in components.iter() {
+            writer.line(&format!("{} : {};", component, st_type(data_type)));
+        }
+        writer.depth -= 1;
+        writer.line("END_STRUCT;");
+        writer.line("END_TYPE");
+    }
+
+    for module in program.modules.iter() {
+        writer.text.push_str(&format!("\n(* ------------------ Module {} ----------------------- *)\n", module.name));
+        for constant in [false, true] {
+            let decls: Vec<&DataDecl> = module.variables.iter().filter(|decl| (decl.storage == Storage::Const) == constant).collect();
+            if decls.is_empty() {
+                continue;
+            }
+            writer.text.push('\n');
+            writer.line(if constant { "VAR_GLOBAL CONSTANT" } else { "VAR_GLOBAL" });
+            writer.declarations(&decls, true);
+            writer.line("END_VAR");
+        }
+        for routine in module.routines.iter() {
+            writer.routine(routine);
+        }
+    }
+    writer.text
+}
+
+/// Add a built-in record type to the structures to declare, after the
+/// record types of its components
+fn declare_record(data_type: &str, records: &mut Vec<(&'static str, &'static [(&'static str, &'static str)])>) {
+    if let Some(record) = variable::record_type(data_type) {
+        if records.iter().any(|declared| declared.0 == record.0) {
+            return;
+        }
+        for (_, component) in record.1.iter() {
+            declare_record(component, records);
+        }
+        records.push(record);
+    }
+}
+
+/// Type of RAPID data in Structured Text
+fn st_type(data_type: &str) -> String {
+    match data_type.to_ascii_lowercase().as_str() {
+        "num" => String::from("REAL"),
+        "dnum" => String::from("LREAL"),
+        "bool" | "switch" => String::from("BOOL"),
+        "string" => String::from("STRING"),
+        "byte" => String::from("BYTE"),
+        "signaldi" | "signaldo" => String::from("BOOL"),
+        "signalai" | "signalao" => String::from("REAL"),
+        "signalgi" | "signalgo" => String::from("DWORD"),
+        _ => String::from(data_type),
+    }
+}
+
+/// Literal of a value, a record is a structure with its components by name.
+/// None for a value Structured Text has no literal of.
+fn st_literal(value: &Variable) -> Option<String> {
+    match value {
+        Variable::Bool(true) => Some(String::from("TRUE")),
+        Variable::Bool(false) => Some(String::from("FALSE")),
+        Variable::Num(value) | Variable::Dnum(value) => Some(number(*value)),
+        Variable::Byte(value) => Some(value.to_string()),
+        Variable::Str(text) => {
+            let mut quoted = String::from("'");
+            for c in text.chars() {
+                match c {
+                    '\'' => quoted.push_str("$'"),
+                    '$' => quoted.push_str("$$"),
+                    '\n' => quoted.push_str("$N"),
+                    c if c.is_control() => quoted.push_str(&format!("${:02X}", c as u32)),
+                    c => quoted.push(c),
+                }
+            }
+            quoted.push('\'');
+            Some(quoted)
+        },
+        Variable::Array(items) => Some(format!("[{}]", items.iter().map(st_literal).collect::<Option<Vec<_>>>()?.join(", "))),
+        Variable::Record(name, items) => {
+            let (_, components) = variable::record_type(name)?;
+            let components = components.iter().zip(items.iter())
+                .map(|((component, _), item)| Some(format!("{} := {}", component, st_literal(item)?)))
+                .collect::<Option<Vec<_>>>()?;
+            Some(format!("({})", components.join(", ")))
+        },
+        _ => None,
+    }
+}
+
+/// Precedence of the Structured Text of an operator, from OR up
+fn st_precedence(op: Operator) -> (u8, &'static str) {
+    match op {
+        Operator::Or => (1, "OR"),
+        Operator::Xor => (2, "XOR"),
+        Operator::And => (3, "AND"),
+        Operator::Equal => (4, "="),
+        Operator::NotEqual => (4, "<>"),
+        Operator::Less => (5, "<"),
+        Operator::LessEqual => (5, "<="),
+        Operator::Greater => (5, ">"),
+        Operator::GreaterEqual => (5, ">="),
+        Operator::Add => (6, "+"),
+        Operator::Sub => (6, "-"),
+        Operator::Mul => (7, "*"),
+        Operator::Div | Operator::IntDiv => (7, "/"),
+        Operator::Mod => (7, "MOD"),
+    }
+}
+
+/// Precedence of NOT and a unary minus, which bind tighter than the
+/// operators in Structured Text
+const ST_UNARY: u8 = 8;
+
+/// Structured Text of the modules
+struct StWriter<'a> {
+    text: String,
+    depth: usize,
+    // Routines of the program by lower case name
+    routines: Vec<String>,
+    // Name of the FUNC being written, which its RETURN assigns to
+    function: Option<String>,
+    // Operands of the routine being written
+    nodes: &'a Arena,
+}
+
+impl<'a> StWriter<'a> {
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.depth {
+            self.text.push_str("    ");
+        }
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    /// Declarations of a VAR section, one level in, with the values of the
+    /// data unless they are parameters
+    fn declarations(&mut self, decls: &[&DataDecl], values: bool) {
+        self.depth += 1;
+        for decl in decls {
+            let mut data_type = String::new();
+            let mut value = &decl.value;
+            while let Variable::Array(items) = value {
+                data_type.push_str(&format!("ARRAY[1..{}] OF ", items.len()));
+                value = items.first().unwrap_or(&Variable::Void);
+            }
+            data_type.push_str(&st_type(&decl.data_type));
+            let line = match (values, st_literal(&decl.value)) {
+                (true, Some(literal)) => format!("{} : {} := {};", decl.name, data_type, literal),
+                (true, None) if matches!(decl.value, Variable::Record(..) | Variable::Array(_)) =>
+                    format!("{} : {}; (* Not translated: initial value *)", decl.name, data_type),
+                _ => format!("{} : {};", decl.name, data_type),
+            };
+            self.line(&line);
+        }
+        self.depth -= 1;
+    }
+
+    /// Operand node of the routine being written
+    fn node(&self, id: NodeId) -> &'a Node {
+        &self.nodes[id]
+    }
+
+    fn routine(&mut self, routine: &'a Routine) {
+        self.nodes = &routine.nodes;
+        self.text.push('\n');
+        match &routine.return_type {
+            Some(return_type) => self.line(&format!("FUNCTION {} : {}", routine.name, st_type(return_type))),
+            None => self.line(&format!("FUNCTION {}", routine.name)),
+        }
+        let inputs: Vec<&DataDecl> = routine.arguments.iter().filter(|param| param.mode == ParamMode::In).map(|param| &param.decl).collect();
+        let in_outs: Vec<&DataDecl> = routine.arguments.iter().filter(|param| param.mode != ParamMode::In).map(|param| &param.decl).collect();
+        // The loop variables are declared by their loops in RAPID
+        let mut counters = Vec::new();
+        for statement in routine.statements.iter() {
+            for_counters(statement, &routine.nodes, &mut counters);
+        }
+        counters.retain(|counter: &String| !routine.arguments.iter().map(|param| &param.decl).chain(routine.variables.iter()).any(|decl| decl.name.eq_ignore_ascii_case(counter)));
+        for (section, decls) in [("VAR_INPUT", inputs), ("VAR_IN_OUT", in_outs)] {
+            if !decls.is_empty() {
+                self.line(section);
+                self.declarations(&decls, false);
+                self.line("END_VAR");
+            }
+        }
+        if !routine.variables.is_empty() || !counters.is_empty() {
+            self.line("VAR");
+            self.declarations(&routine.variables.iter().collect::<Vec<_>>(), true);
+            for counter in counters {
+                self.line(&format!("    {} : DINT;", counter));
+            }
+            self.line("END_VAR");
+        }
+
+        self.function = routine.return_type.as_ref().map(|_| routine.name.clone());
+        self.statements(&routine.statements);
+        if routine.handler.is_some() {
+            self.line("(* Not translated: ERROR handler *)");
+        }
+        self.line("END_FUNCTION");
+    }
+
+    /// Statements one level in
+    fn statements(&mut self, statements: &[Statement]) {
+        self.depth += 1;
+        for statement in statements {
+            if let Err(construct) = self.statement(&statement.node) {
+                self.line(&format!("(* Not translated: {} *)", construct));
+            }
+        }
+        self.depth -= 1;
+    }
+
+    /// Write a statement, or give the construct it has no translation for
+    fn statement(&mut self, node: &Node) -> Result<(), String> {
+        match node {
+            Node::Assign { lhs, rhs } => {
+                let line = format!("{} := {};", self.expr(self.node(*lhs), 0)?, self.expr(self.node(*rhs), 0)?);
+                self.line(&line);
+            },
+            Node::ProcCall { name, args, .. } if self.routines.contains(&name.to_ascii_lowercase()) => {
+                let line = format!("{}({});", name, self.arguments(args)?);
+                self.line(&line);
+            },
+            Node::ProcCall { name, args, .. } => {
+                let values = args.iter().map(|arg| arg.value.as_ref().filter(|_| arg.name.is_none())).collect::<Vec<_>>();
+                let (signal, value) = match (name.to_ascii_lowercase().as_str(), values.as_slice()) {
+                    ("set", [Some(signal)]) => (signal, String::from("TRUE")),
+                    ("reset", [Some(signal)]) => (signal, String::from("FALSE")),
+                    ("setdo", [Some(signal), Some(Node::Value(Variable::Num(value)))]) => (signal, String::from(if *value == 0.0 { "FALSE" } else { "TRUE" })),
+                    ("setdo", [Some(signal), Some(value)]) => (signal, format!("{} <> 0", self.expr(value, 5)?)),
+                    ("setao" | "setgo", [Some(signal), Some(value)]) => (signal, self.expr(value, 0)?),
+                    _ => return Err(name.clone()),
+                };
+                let line = format!("{} := {};", self.expr(signal, 0)?, value);
+                self.line(&line);
+            },
+            Node::Return(value) => {
+                if let (Some(value), Some(function)) = (value, self.function.clone()) {
+                    let line = format!("{} := {};", function, self.expr(self.node(*value), 0)?);
+                    self.line(&line);
+                }
+                self.line("RETURN;");
+            },
+            Node::If { branches, otherwise } => {
+                for (idx, (condition, body)) in branches.iter().enumerate() {
+                    let line = format!("{} {} THEN", if idx == 0 { "IF" } else { "ELSIF" }, self.expr(condition, 0)?);
+                    self.line(&line);
+                    self.statements(body);
+                }
+                if !otherwise.is_empty() {
+                    self.line("ELSE");
+                    self.statements(otherwise);
+                }
+                self.line("END_IF;");
+            },
+            Node::While { condition, body } => {
+                let line = format!("WHILE {} DO", self.expr(self.node(*condition), 0)?);
+                self.line(&line);
+                self.statements(body);
+                self.line("END_WHILE;");
+            },
+            Node::For { var, from, to, step, body } => {
+                let mut line = format!("FOR {} := {} TO {}", self.expr(self.node(*var), 0)?, self.expr(self.node(*from), 0)?, self.expr(self.node(*to), 0)?);
+                if let Some(step) = step {
+                    line.push_str(&format!(" BY {}", self.expr(self.node(*step), 0)?));
+                }
+                line.push_str(" DO");
+                self.line(&line);
+                self.statements(body);
+                self.line("END_FOR;");
+            },
+            Node::Test { value, cases, default } => {
+                let line = format!("CASE {} OF", self.expr(self.node(*value), 0)?);
+                self.line(&line);
+                for (values, body) in cases.iter() {
+                    let line = format!("{}:", values.iter().map(|value| self.expr(value, 0)).collect::<Result<Vec<_>, _>>()?.join(", "));
+                    self.line(&line);
+                    self.statements(body);
+                }
+                if !default.is_empty() {
+                    self.line("ELSE");
+                    self.statements(default);
+                }
+                self.line("END_CASE;");
+            },
+            Node::Print { .. } => return Err(String::from("TPWrite")),
+            Node::LateCall { .. } => return Err(String::from("late binding call")),
+            Node::Retry => return Err(String::from("RETRY")),
+            Node::TryNext => return Err(String::from("TRYNEXT")),
+            Node::Connect { .. } => return Err(String::from("CONNECT")),
+            Node::Raise(_) => return Err(String::from("RAISE")),
+            _ => return Err(String::from("statement")),
+        }
+        Ok(())
+    }
+
+    /// Structured Text of an expression, in parentheses when its precedence
+    /// is below the given one
+    fn expr(&mut self, node: &Node, min: u8) -> Result<String, String> {
+        let (prec, text) = match node {
+            Node::BinOp { op: Operator::IntDiv, lhs, rhs } => (ATOM, format!("TRUNC({} / {})", self.expr(self.node(*lhs), 7)?, self.expr(self.node(*rhs), 8)?)),
+            Node::BinOp { op, lhs, rhs } => {
+                let (prec, symbol) = st_precedence(*op);
+                (prec, format!("{} {} {}", self.expr(self.node(*lhs), prec)?, symbol, self.expr(self.node(*rhs), prec + 1)?))
+            },
+            Node::OpNeg(node) => (ST_UNARY, format!("-{}", self.expr(self.node(*node), ST_UNARY)?)),
+            Node::OpNot(node) => (ST_UNARY, format!("NOT {}", self.expr(self.node(*node), ST_UNARY)?)),
+            Node::Value(value) => (ATOM, st_literal(value).ok_or_else(|| format!("{} literal", value.type_name()))?),
+            Node::Aggregate(items) => (ATOM, format!("[{}]", items.iter().map(|item| self.expr(item, 0)).collect::<Result<Vec<_>, _>>()?.join(", "))),
+            Node::Id(name, _) => (ATOM, name.clone()),
+            Node::Index { base, indices } => {
+                let indices = indices.iter().map(|index| self.expr(index, 0)).collect::<Result<Vec<_>, _>>()?;
+                (ATOM, format!("{}[{}]", self.expr(self.node(*base), ATOM)?, indices.join(", ")))
+            },
+            Node::FuncCall { name, args, .. } if self.routines.contains(&name.to_ascii_lowercase()) => (ATOM, format!("{}({})", name, self.arguments(args)?)),
+            Node::FuncCall { name, args, .. } => {
+                let values = args.iter().map(|arg| arg.value.as_ref().filter(|_| arg.name.is_none())).collect::<Vec<_>>();
+                let text = match (name.to_ascii_lowercase().as_str(), values.as_slice()) {
+                    // A signal reads as the variable of its I/O
+                    ("dinput" | "doutput" | "ainput" | "aoutput" | "ginput" | "goutput", [Some(signal)]) => self.expr(signal, ATOM)?,
+                    ("abs", [Some(value)]) => format!("ABS({})", self.expr(value, 0)?),
+                    ("sqrt", [Some(value)]) => format!("SQRT({})", self.expr(value, 0)?),
+                    ("trunc", [Some(value)]) => format!("TRUNC({})", self.expr(value, 0)?),
+                    ("strlen", [Some(text)]) => format!("LEN({})", self.expr(text, 0)?),
+                    _ => return Err(format!("function {}", name)),
+                };
+                (ATOM, text)
+            },
+            _ => return Err(String::from("expression")),
+        };
+        Ok(if prec < min { format!("({})", text) } else { text })
+    }
+
+    /// Arguments of a call of a routine of the program, by position
+    fn arguments(&mut self, args: &[Argument]) -> Result<String, String> {
+        let mut texts = Vec::new();
+        for arg in args {
+            match (&arg.name, &arg.value) {
+                (None, Some(value)) => texts.push(self.expr(value, 0)?),
+                (Some(name), _) => return Err(format!("optional argument \\{}", name)),
+                (None, None) => return Err(String::from("argument")),
+            }
+        }
+        Ok(texts.join(", "))
+    }
+}
+
+/// Names of the loop variables of the FOR loops of a statement
+fn for_counters(statement: &Statement, nodes: &Arena, counters: &mut Vec<String>) {
+    if let Node::For { var, .. } = &statement.node {
+        if let Node::Id(name, _) = &nodes[*var] {
+            if !counters.iter().any(|counter| counter.eq_ignore_ascii_case(name)) {
+                counters.push(name.clone());
+            }
+        }
+    }
+    for body in statement.node.bodies() {
+        for statement in body {
+            for_counters(statement, nodes, counters);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lexer, parser};
+
+    #[test]
+    fn writes_python() {
+        let program = parser::parse_tokens(lexer::parse("MODULE Cell
+    VAR num nParts := 0;
+    CONST num nMax{2} := [4, 8];
+
+    PROC main()
+        FOR i FROM 1 TO nMax{2} DO
+            rPick i, \\Fast;
+        ENDFOR
+        WaitDI diPartReady, 1;
+        IF NOT diPartReady = 1 AND nParts > 0 THEN
+            TPWrite \"Parts: \" \\Num:=nParts;
+        ENDIF
+    ENDPROC
+
+    PROC rPick(num nPart, \\switch Fast)
+        VAR num nFrom;
+        MoveL Offs(pPick, 0, 0, 10 * nPart), v1000, fine, tool0;
+        SetDO doGripper, 1;
+        nParts := nParts + 1;
+        TEST nPart
+        CASE 1, 2:
+            IF Present(Fast) THEN
+                nFrom := Abs(-nPart);
+            ENDIF
+        DEFAULT:
+            RAISE 1;
+        ENDTEST
+    ERROR
+        IF ERRNO = ERR_DIVZERO THEN
+            RETRY;
+        ENDIF
+    ENDPROC
+ENDMODULE")).unwrap();
+        let text = python(&program);
+        assert!(text.starts_with("# Python of the RAPID modules Cell, with stubs of the\n"));
+        let generated = text.split_once("    return signals.get(name, 0)\n").unwrap().1;
+        assert_eq!(generated, "
+
+def WaitDI(*args, **options):
+    calls.append((\"WaitDI\", args, options))
+
+
+def MoveL(*args, **options):
+    calls.append((\"MoveL\", args, options))
+
+
+def Offs(*args, **options):
+    calls.append((\"Offs\", args, options))
+    return 0
+
+
+def SetDO(*args, **options):
+    calls.append((\"SetDO\", args, options))
+    signals[args[0]] = args[1]
+
+
+# Data the program doesn't declare
+diPartReady = \"diPartReady\"
+pPick = \"pPick\"
+v1000 = \"v1000\"
+fine = \"fine\"
+tool0 = \"tool0\"
+doGripper = \"doGripper\"
+ERR_DIVZERO = 1002
+
+
+# ------------------ Module Cell -----------------------
+
+nParts = 0  # VAR num
+nMax = [4, 8]  # CONST num
+
+
+# PROC main()
+def main():
+    for i in range(1, nMax[1] + 1):
+        rPick(i, Fast=True)
+    WaitDI(diPartReady, 1)
+    if not signal(diPartReady) == 1 and nParts > 0:
+        print(\"Parts: \" + str(nParts))
+
+
+# PROC rPick(num nPart, \\switch Fast)
+def rPick(nPart, *, Fast=None):
+    global nParts
+    nFrom = 0  # VAR num
+    try:
+        MoveL(Offs(pPick, 0, 0, 10 * nPart), v1000, fine, tool0)
+        SetDO(doGripper, 1)
+        nParts = nParts + 1
+        if nPart in (1, 2):
+            if (Fast is not None):
+                nFrom = abs(-nPart)
+        else:
+            raise RapidError(1)
+    except RapidError as error:
+        if error.errno == ERR_DIVZERO:
+            pass  # RETRY doesn't resume the routine
+
+
+if __name__ == \"__main__\":
+    main()
+");
+    }
+
+    #[test]
+    fn writes_structured_text() {
+        let program = parser::parse_tokens(lexer::parse("MODULE Cell
+    CONST num nMax := 4;
+    VAR num nParts{2};
+    VAR string sState := \"It's $5\";
+
+    PROC rCycle()
+        FOR i FROM 1 TO nMax DO
+            IF NOT nParts{1} = i AND (DInput(diReady) = 1 OR nParts{2} > 2) THEN
+                nParts{1} := nParts{1} + Half(i DIV 2);
+                Set doGrip;
+            ELSEIF i MOD 2 = 0 THEN
+                SetDO doGrip, 0;
+            ENDIF
+        ENDFOR
+        MoveL pPick, v1000, fine, tool0;
+        TEST nParts{1}
+        CASE 1, 2:
+            WHILE -nParts{2} < 0 DO
+                nParts{2} := nParts{2} - 1;
+            ENDWHILE
+        DEFAULT:
+            TPWrite \"Full\";
+        ENDTEST
+    ENDPROC
+
+    FUNC num Half(num nValue)
+        RETURN nValue / 2;
+    ENDFUNC
+ENDMODULE")).unwrap();
+        assert_eq!(structured_text(&program), "\
+(* Structured Text of the RAPID modules Cell.
+   Generated by rapid-rust. *)
+
+(* ------------------ Module Cell ----------------------- *)
+
+VAR_GLOBAL
+    nParts : ARRAY[1..2] OF REAL := [0, 0];
+    sState : STRING := 'It$'s $$5';
+END_VAR
+
+VAR_GLOBAL CONSTANT
+    nMax : REAL := 4;
+END_VAR
+
+FUNCTION rCycle
+VAR
+    i : DINT;
+END_VAR
+    FOR i := 1 TO nMax DO
+        IF NOT (nParts[1] = i) AND (diReady = 1 OR nParts[2] > 2) THEN
+            nParts[1] := nParts[1] + Half(TRUNC(i / 2));
+            doGrip := TRUE;
+        ELSIF i MOD 2 = 0 THEN
+            doGrip := FALSE;
+        END_IF;
+    END_FOR;
+    (* Not translated: MoveL *)
+    CASE nParts[1] OF
+    1, 2:
+        WHILE -nParts[2] < 0 DO
+            nParts[2] := nParts[2] - 1;
+        END_WHILE;
+    ELSE
+        (* Not translated: TPWrite *)
+    END_CASE;
+END_FUNCTION
+
+FUNCTION Half : REAL
+VAR_INPUT
+    nValue : REAL;
+END_VAR
+    Half := nValue / 2;
+    RETURN;
+END_FUNCTION
+");
+
+        // Records are structures, declared once after those of their components
+        let program = parser::parse_tokens(lexer::parse("MODULE Cell
+    PERS robtarget pPick := [[500, 0, 400], [0, 0, 1, 0], [0, 0, 0, 0], [9E9, 9E9, 9E9, 9E9, 9E9, 9E9]];
+    CONST pos pCorners{2} := [[0, 0, 0], [100, 50.5, -10]];
+
+    FUNC pos Offset(robtarget pTarget, pose pFrame)
+        VAR pos pOffset;
+        RETURN pOffset;
+    ENDFUNC
+ENDMODULE")).unwrap();
+        let text = structured_text(&program);
+        assert!(text.contains("(* ------------------ Types ----------------------- *)
+
+TYPE pos : STRUCT
+    x : REAL;
+    y : REAL;
+    z : REAL;
+END_STRUCT;
+END_TYPE
+
+TYPE orient : STRUCT
+"));
+        assert!(text.contains("TYPE robtarget : STRUCT
+    trans : pos;
+    rot : orient;
+    robconf : confdata;
+    extax : extjoint;
+END_STRUCT;
+END_TYPE
+
+TYPE pose : STRUCT
+    trans : pos;
+    rot : orient;
+END_STRUCT;
+END_TYPE
+
+(* ------------------ Module Cell ----------------------- *)
+
+VAR_GLOBAL
+    pPick : robtarget := (trans := (x := 500, y := 0, z := 400), rot := (q1 := 0, q2 := 0, q3 := 1, q4 := 0), robconf := (cf1 := 0, cf4 := 0, cf6 := 0, cfx := 0), \
+extax := (eax_a := 9000000000, eax_b := 9000000000, eax_c := 9000000000, eax_d := 9000000000, eax_e := 9000000000, eax_f := 9000000000));
+END_VAR
+
+VAR_GLOBAL CONSTANT
+    pCorners : ARRAY[1..2] OF pos := [(x := 0, y := 0, z := 0), (x := 100, y := 50.5, z := -10)];
+END_VAR
+"));
+        assert_eq!(text.matches("TYPE pos :").count(), 1);
+        assert!(structured_text(&parser::parse_tokens(lexer::parse("MODULE Cell\nENDMODULE")).unwrap()).find("TYPE").is_none());
+        // A value of a type it doesn't know the components of is left out
+        let text = structured_text(&parser::parse_tokens(lexer::parse("MODULE Cell\n    VAR mydata dItem := [1, 2];\nENDMODULE")).unwrap());
+        assert!(text.contains("    dItem : mydata; (* Not translated: initial value *)\n"));
+    }
+}
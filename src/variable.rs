@@ -0,0 +1,635 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops;
+use std::time::Duration;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::host;
+use crate::interpreter::RuntimeError;
+use crate::lexer::{self, TokenType};
+use crate::parser::Operator;
+
+// ------------------ Variables -----------------------/
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Variable {
+    Void,
+    Bool(bool),
+    Num(f64),
+    // Double precision number. Operators with a dnum and a num operand give
+    // a dnum, a num takes one from DnumToNum only.
+    Dnum(f64),
+    // Integer 0 to 255, a num everywhere except when assigned to
+    Byte(u8),
+    Str(String),
+    // Record type name and components, an aggregate like `[1, 2, 3]` has
+    // no type name until it's assigned to a record
+    Record(#[serde(deserialize_with = "record_name")] RecordName, Vec<Variable>),
+    // Array of data of one type, the elements of a multi-dimensional array
+    // are arrays themselves
+    Array(Vec<Variable>),
+    // Stopwatch of the clock instructions: the time measured while it ran
+    // before, and the host time it was started at while running
+    Clock { elapsed: Duration, started: Option<Duration> },
+    // I/O signal, by its name on the I/O board. Signal data the program
+    // declares itself has no name and isn't connected to a signal.
+    Signal(SignalKind, String),
+    // Synchronization point of WaitSyncTask, known by the name of its data
+    // in every task
+    SyncIdent(String),
+    // File opened with Open, by its index in the files of the host. `None`
+    // while it's closed.
+    IoDev(Option<usize>),
+    // Module StartLoad is loading, by its index in the load sessions of the
+    // host. `None` while no module is being loaded.
+    LoadSession(Option<usize>),
+    // Socket created with SocketCreate, by its index in the sockets of the
+    // host. `None` while it's closed.
+    SocketDev(Option<usize>),
+}
+
+/// Data types of the I/O signals
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SignalKind {
+    DigitalInput,
+    DigitalOutput,
+    AnalogInput,
+    AnalogOutput,
+    GroupInput,
+    GroupOutput,
+}
+
+static SIGNAL_TYPES: &[(&str, SignalKind)] = &[
+    ("signaldi", SignalKind::DigitalInput),
+    ("signaldo", SignalKind::DigitalOutput),
+    ("signalai", SignalKind::AnalogInput),
+    ("signalao", SignalKind::AnalogOutput),
+    ("signalgi", SignalKind::GroupInput),
+    ("signalgo", SignalKind::GroupOutput),
+];
+
+impl SignalKind {
+    /// Name of the RAPID data type of the signal
+    pub fn type_name(self) -> &'static str {
+        SIGNAL_TYPES.iter()
+            .find(|signal| signal.1 == self)
+            .map_or("signal", |signal| signal.0)
+    }
+
+    pub fn input(self) -> bool {
+        matches!(self, SignalKind::DigitalInput | SignalKind::AnalogInput | SignalKind::GroupInput)
+    }
+}
+
+/// Components of the built-in record types
+static RECORDS: &[(&str, &[(&str, &str)])] = &[
+    ("pos", &[("x", "num"), ("y", "num"), ("z", "num")]),
+    ("tasks", &[("taskname", "string")]),
+    ("orient", &[("q1", "num"), ("q2", "num"), ("q3", "num"), ("q4", "num")]),
+    ("pose", &[("trans", "pos"), ("rot", "orient")]),
+    ("confdata", &[("cf1", "num"), ("cf4", "num"), ("cf6", "num"), ("cfx", "num")]),
+    ("extjoint", &[("eax_a", "num"), ("eax_b", "num"), ("eax_c", "num"), ("eax_d", "num"), ("eax_e", "num"), ("eax_f", "num")]),
+    ("robjoint", &[("rax_1", "num"), ("rax_2", "num"), ("rax_3", "num"), ("rax_4", "num"), ("rax_5", "num"), ("rax_6", "num")]),
+    ("robtarget", &[("trans", "pos"), ("rot", "orient"), ("robconf", "confdata"), ("extax", "extjoint")]),
+    ("jointtarget", &[("robax", "robjoint"), ("extax", "extjoint")]),
+    ("speeddata", &[("v_tcp", "num"), ("v_ori", "num"), ("v_leax", "num"), ("v_reax", "num")]),
+    ("zonedata", &[("finep", "bool"), ("pzone_tcp", "num"), ("pzone_ori", "num"), ("pzone_eax", "num"),
+        ("zone_ori", "num"), ("zone_leax", "num"), ("zone_reax", "num")]),
+    ("loaddata", &[("mass", "num"), ("cog", "pos"), ("aom", "orient"), ("ix", "num"), ("iy", "num"), ("iz", "num")]),
+    ("tooldata", &[("robhold", "bool"), ("tframe", "pose"), ("tload", "loaddata")]),
+    ("wobjdata", &[("robhold", "bool"), ("ufprog", "bool"), ("ufmec", "string"), ("uframe", "pose"), ("oframe", "pose")]),
+];
+
+/// Name and components of a record type
+pub fn record_type(data_type: &str) -> Option<(&'static str, &'static [(&'static str, &'static str)])> {
+    RECORDS.iter()
+        .find(|record| record.0.eq_ignore_ascii_case(data_type))
+        .copied()
+}
+
+/// Type name of a record. Serde takes a `&str` field to borrow from the
+/// data it reads unless it's named otherwise; the names are read back with
+/// `record_name`.
+pub type RecordName = &'static str;
+
+/// Name of a record type read back, the type names of records are those of
+/// `record_type`, or empty for an aggregate
+fn record_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<&'static str, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    if name.is_empty() {
+        return Ok("");
+    }
+    record_type(&name).map(|(name, _)| name).ok_or_else(|| D::Error::custom(format!("Unknown record type {}", name)))
+}
+
+impl Variable {
+    pub fn set(&mut self, other: Variable) -> Result<(), RuntimeError> {
+        match (self, other) {
+            (Variable::Bool(ref mut value), Variable::Bool(value2)) => *value = value2,
+            (Variable::Num(ref mut value), Variable::Num(value2)) => *value = value2,
+            (Variable::Num(ref mut value), Variable::Byte(value2)) => *value = value2 as f64,
+            (Variable::Dnum(ref mut value), Variable::Dnum(value2) | Variable::Num(value2)) => *value = value2,
+            (Variable::Dnum(ref mut value), Variable::Byte(value2)) => *value = value2 as f64,
+            (Variable::Byte(ref mut value), Variable::Byte(value2)) => *value = value2,
+            (Variable::Byte(ref mut value), Variable::Num(value2)) => *value = to_byte(value2)?,
+            (Variable::Str(ref mut value), Variable::Str(value2)) => *value = check_length(value2)?,
+            (Variable::Array(ref mut items), Variable::Array(items2) | Variable::Record("", items2))
+                if items.len() == items2.len() => {
+                let mut copy = items.clone();
+                for (item, value) in copy.iter_mut().zip(items2) {
+                    item.set(value)?;
+                }
+                *items = copy;
+            },
+            (clock @ Variable::Clock { .. }, other @ Variable::Clock { .. }) => *clock = other,
+            (Variable::Signal(kind, ref mut name), Variable::Signal(kind2, name2)) if *kind == kind2 => *name = name2,
+            (Variable::SyncIdent(ref mut name), Variable::SyncIdent(name2)) => *name = name2,
+            (Variable::IoDev(ref mut file), Variable::IoDev(file2)) => *file = file2,
+            (Variable::LoadSession(ref mut session), Variable::LoadSession(session2)) => *session = session2,
+            (Variable::SocketDev(ref mut socket), Variable::SocketDev(socket2)) => *socket = socket2,
+            // Switch arguments carry no value
+            (Variable::Void, Variable::Void) => (),
+            (Variable::Record(name, ref mut fields), Variable::Record(name2, fields2))
+                if (name2.is_empty() || *name == name2) && fields.len() == fields2.len() => {
+                // Assign to a copy so a mismatch halfway leaves the record untouched
+                let mut copy = fields.clone();
+                for (field, value) in copy.iter_mut().zip(fields2) {
+                    field.set(value)?;
+                }
+                *fields = copy;
+            },
+            (var, other) => return Err(RuntimeError::type_mismatch(format!("Cannot assign {} to {}", other.type_name(), var.type_name()))),
+        }
+        Ok(())
+    }
+
+    /// Name of the RAPID data type of the value
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Variable::Void => "void",
+            Variable::Bool(_) => "bool",
+            Variable::Num(_) => "num",
+            Variable::Dnum(_) => "dnum",
+            Variable::Byte(_) => "byte",
+            Variable::Str(_) => "string",
+            Variable::Record("", _) => "aggregate",
+            Variable::Record(name, _) => name,
+            Variable::Array(items) => items.first().map_or("void", Variable::type_name),
+            Variable::Clock { .. } => "clock",
+            Variable::Signal(kind, _) => kind.type_name(),
+            Variable::SyncIdent(_) => "syncident",
+            Variable::IoDev(_) => "iodev",
+            Variable::LoadSession(_) => "loadsession",
+            Variable::SocketDev(_) => "socketdev",
+        }
+    }
+
+    pub fn from(data_type: &str) -> Result<Variable,String> {
+        let var = match data_type.to_ascii_lowercase().as_str() {
+            "num" => Variable::Num(0.0),
+            "dnum" => Variable::Dnum(0.0),
+            "byte" => Variable::Byte(0),
+            "clock" => Variable::Clock { elapsed: Duration::ZERO, started: None },
+            "bool" => Variable::Bool(false),
+            "string" => Variable::Str(String::default()),
+            "switch" => Variable::Void,
+            "syncident" => Variable::SyncIdent(String::new()),
+            "iodev" => Variable::IoDev(None),
+            "loadsession" => Variable::LoadSession(None),
+            "socketdev" => Variable::SocketDev(None),
+            // Number CONNECT gives an interrupt, 0 until then
+            "intnum" => Variable::Num(0.0),
+            _ => match (record_type(data_type), SIGNAL_TYPES.iter().find(|signal| signal.0.eq_ignore_ascii_case(data_type))) {
+                (Some((name, fields)), _) => {
+                    let fields = fields.iter().map(|field| Variable::from(field.1)).collect::<Result<_, _>>()?;
+                    Variable::Record(name, fields)
+                },
+                (None, Some((_, kind))) => Variable::Signal(*kind, String::new()),
+                (None, None) => return Err(format!("Unknown data type {}", data_type)),
+            },
+        };
+
+        Ok(var)
+    }
+
+    pub fn from_value(data_type: &str, value: &TokenType, negative: bool) -> Result<Variable,String> {
+        let var = match (data_type.to_ascii_lowercase().as_str(), value) {
+            ("bool", TokenType::True) => Variable::Bool(true),
+            ("bool", TokenType::False) => Variable::Bool(false),
+            ("num", TokenType::NumValue(val)) => {
+                let val = lexer::num_value(val).ok_or_else(|| format!("Invalid number {}", val))?;
+                Variable::Num(if negative { -val } else { val })
+            },
+            ("dnum", TokenType::NumValue(val)) => {
+                let val = lexer::num_value(val).ok_or_else(|| format!("Invalid number {}", val))?;
+                Variable::Dnum(if negative { -val } else { val })
+            },
+            ("byte", TokenType::NumValue(val)) => {
+                let val = lexer::num_value(val).ok_or_else(|| format!("Invalid number {}", val))?;
+                let val = if negative { -val } else { val };
+                Variable::Byte(to_byte(val).map_err(|_| format!("Value {} out of range for byte", val))?)
+            },
+            ("string", TokenType::StringValue(val)) => Variable::Str(val.clone()),
+            _ => return Err(format!("Invalid value for {}", data_type)),
+        };
+
+        Ok(var)
+    }
+
+    /// Array of the given dimensions, with every element set to `value`
+    pub fn array(dims: &[usize], value: Variable) -> Variable {
+        match dims.split_first() {
+            Some((len, inner)) => Variable::Array(vec![Variable::array(inner, value); *len]),
+            None => value,
+        }
+    }
+
+    /// Element of an array at the RAPID indices, counting from 1, one per dimension
+    pub fn element(&self, indices: &[f64]) -> Result<&Variable, RuntimeError> {
+        let mut var = self;
+        for (dim, index) in indices.iter().enumerate() {
+            var = match var {
+                Variable::Array(items) => &items[array_index(*index, items.len())?],
+                var => return Err(not_indexable(var, dim)),
+            };
+        }
+        Ok(var)
+    }
+
+    pub fn element_mut(&mut self, indices: &[f64]) -> Result<&mut Variable, RuntimeError> {
+        let mut var = self;
+        for (dim, index) in indices.iter().enumerate() {
+            var = match var {
+                Variable::Array(items) => {
+                    let idx = array_index(*index, items.len())?;
+                    &mut items[idx]
+                },
+                var => return Err(not_indexable(var, dim)),
+            };
+        }
+        Ok(var)
+    }
+
+    /// Component of a record by name
+    pub fn component(&self, name: &str) -> Result<&Variable, RuntimeError> {
+        let idx = self.component_index(name)?;
+        match self {
+            Variable::Record(_, fields) => Ok(&fields[idx]),
+            var => Err(no_component(var, name)),
+        }
+    }
+
+    pub fn component_mut(&mut self, name: &str) -> Result<&mut Variable, RuntimeError> {
+        let idx = self.component_index(name)?;
+        match self {
+            Variable::Record(_, fields) => Ok(&mut fields[idx]),
+            var => Err(no_component(var, name)),
+        }
+    }
+
+    fn component_index(&self, name: &str) -> Result<usize, RuntimeError> {
+        let components = match self {
+            Variable::Record(record, fields) => record_type(record).map(|(_, components)| components).filter(|components| components.len() == fields.len()),
+            _ => None,
+        };
+        components
+            .and_then(|components| components.iter().position(|(component, _)| component.eq_ignore_ascii_case(name)))
+            .ok_or_else(|| no_component(self, name))
+    }
+
+    /// Bytes take part in expressions as num
+    fn numeric(self) -> Variable {
+        match self {
+            Variable::Byte(value) => Variable::Num(value as f64),
+            var => var,
+        }
+    }
+
+    /// Num result of an operator as a dnum, other values as they are
+    fn into_dnum(self) -> Variable {
+        match self {
+            Variable::Num(value) => Variable::Dnum(value),
+            var => var,
+        }
+    }
+
+    pub fn operate(op: Operator, lhs: Variable, rhs: Variable) -> Result<Variable, RuntimeError> {
+        let (lhs, rhs) = (lhs.numeric(), rhs.numeric());
+        if let Some((n1, n2)) = dnum_operands(&lhs, &rhs) {
+            return Variable::operate(op, Variable::Num(n1), Variable::Num(n2)).map(Variable::into_dnum);
+        }
+        let var = match (op, lhs, rhs) {
+            (Operator::Add, lhs, rhs) => return lhs + rhs,
+            (Operator::Sub, lhs, rhs) => return lhs - rhs,
+            (Operator::Mul, lhs, rhs) => return lhs * rhs,
+            (Operator::Div, lhs, rhs) => return lhs / rhs,
+            (Operator::IntDiv | Operator::Mod, Variable::Num(_), Variable::Num(0.0)) => return Err(RuntimeError::div_zero()),
+            (Operator::IntDiv, Variable::Num(n1), Variable::Num(n2)) => Variable::Num((n1 / n2).trunc()),
+            (Operator::Mod, Variable::Num(n1), Variable::Num(n2)) => Variable::Num(n1 % n2),
+            (Operator::Equal, lhs, rhs) => Variable::Bool(lhs.equals(&rhs)?),
+            (Operator::NotEqual, lhs, rhs) => Variable::Bool(!lhs.equals(&rhs)?),
+            (Operator::Less, Variable::Num(n1), Variable::Num(n2)) => Variable::Bool(n1 < n2),
+            (Operator::LessEqual, Variable::Num(n1), Variable::Num(n2)) => Variable::Bool(n1 <= n2),
+            (Operator::Greater, Variable::Num(n1), Variable::Num(n2)) => Variable::Bool(n1 > n2),
+            (Operator::GreaterEqual, Variable::Num(n1), Variable::Num(n2)) => Variable::Bool(n1 >= n2),
+            (Operator::And, Variable::Bool(b1), Variable::Bool(b2)) => Variable::Bool(b1 && b2),
+            (Operator::Or, Variable::Bool(b1), Variable::Bool(b2)) => Variable::Bool(b1 || b2),
+            (Operator::Xor, Variable::Bool(b1), Variable::Bool(b2)) => Variable::Bool(b1 != b2),
+            (op, lhs, rhs) => return Err(operand_mismatch(op, &lhs, &rhs)),
+        };
+
+        Ok(var)
+    }
+
+    pub fn equals(&self, other: &Variable) -> Result<bool, RuntimeError> {
+        match (self, other) {
+            (Variable::Byte(b1), Variable::Byte(b2)) => Ok(b1 == b2),
+            (Variable::Byte(b), Variable::Num(n)) | (Variable::Num(n), Variable::Byte(b)) => Ok(*b as f64 == *n),
+            (Variable::Bool(b1), Variable::Bool(b2)) => Ok(b1 == b2),
+            (Variable::Num(n1), Variable::Num(n2)) => Ok(n1 == n2),
+            (Variable::Dnum(n1), Variable::Dnum(n2) | Variable::Num(n2)) | (Variable::Num(n1), Variable::Dnum(n2)) => Ok(n1 == n2),
+            (Variable::Str(s1), Variable::Str(s2)) => Ok(s1 == s2),
+            (Variable::Record(n1, f1), Variable::Record(n2, f2))
+                if (n1.is_empty() || n2.is_empty() || n1 == n2) && f1.len() == f2.len() => {
+                for (lhs, rhs) in f1.iter().zip(f2.iter()) {
+                    if !lhs.equals(rhs)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            },
+            (lhs, rhs) => Err(operand_mismatch(Operator::Equal, lhs, rhs)),
+        }
+    }
+}
+
+/// Position in the items of an array of a RAPID index
+fn array_index(index: f64, len: usize) -> Result<usize, RuntimeError> {
+    if index != index.trunc() || index < 1.0 || index > len as f64 {
+        return Err(RuntimeError::IndexOutOfBounds { index, len, span: Default::default() });
+    }
+    Ok(index as usize - 1)
+}
+
+fn not_indexable(var: &Variable, dim: usize) -> RuntimeError {
+    if dim == 0 {
+        RuntimeError::type_mismatch(format!("Cannot index {}", var.type_name()))
+    } else {
+        RuntimeError::type_mismatch(format!("Array has {} dimensions", dim))
+    }
+}
+
+fn no_component(var: &Variable, name: &str) -> RuntimeError {
+    RuntimeError::type_mismatch(format!("{} has no component {}", var.type_name(), name))
+}
+
+/// Maximum number of characters in a string
+pub const MAX_STRING: usize = 80;
+
+fn check_length(text: String) -> Result<String, RuntimeError> {
+    let len = text.chars().count();
+    if len > MAX_STRING {
+        return Err(RuntimeError::StringTooLong { len, span: Default::default() });
+    }
+    Ok(text)
+}
+
+/// Value of a num assigned to a byte
+pub fn to_byte(value: f64) -> Result<u8, RuntimeError> {
+    if value != value.trunc() || !(0.0..=255.0).contains(&value) {
+        return Err(RuntimeError::type_mismatch(format!("Value {} out of range for byte", value)));
+    }
+    Ok(value as u8)
+}
+
+/// Values of the operands of an operator with a dnum operand and a num or
+/// dnum one
+fn dnum_operands(lhs: &Variable, rhs: &Variable) -> Option<(f64, f64)> {
+    match (lhs, rhs) {
+        (Variable::Dnum(n1), Variable::Dnum(n2) | Variable::Num(n2)) | (Variable::Num(n1), Variable::Dnum(n2)) => Some((*n1, *n2)),
+        _ => None,
+    }
+}
+
+fn operand_mismatch(op: Operator, lhs: &Variable, rhs: &Variable) -> RuntimeError {
+    RuntimeError::type_mismatch(format!("Operator {:?} is not defined for {} and {}", op, lhs.type_name(), rhs.type_name()))
+}
+
+impl ops::Add for Variable {
+    type Output = Result<Variable, RuntimeError>;
+
+    fn add(self, other: Variable) -> Self::Output {
+        match (self.numeric(), other.numeric()) {
+            (Variable::Num(n1), Variable::Num(n2)) => Ok(Variable::Num(n1 + n2)),
+            (Variable::Str(s1), Variable::Str(s2)) => Ok(Variable::Str(check_length(s1 + &s2)?)),
+            (lhs, rhs) => match dnum_operands(&lhs, &rhs) {
+                Some((n1, n2)) => Ok(Variable::Dnum(n1 + n2)),
+                None => Err(operand_mismatch(Operator::Add, &lhs, &rhs)),
+            },
+        }
+    }
+}
+
+impl ops::Sub for Variable {
+    type Output = Result<Variable, RuntimeError>;
+
+    fn sub(self, other: Variable) -> Self::Output {
+        match (self.numeric(), other.numeric()) {
+            (Variable::Num(n1), Variable::Num(n2)) => Ok(Variable::Num(n1 - n2)),
+            (lhs, rhs) => match dnum_operands(&lhs, &rhs) {
+                Some((n1, n2)) => Ok(Variable::Dnum(n1 - n2)),
+                None => Err(operand_mismatch(Operator::Sub, &lhs, &rhs)),
+            },
+        }
+    }
+}
+
+impl ops::Mul for Variable {
+    type Output = Result<Variable, RuntimeError>;
+
+    fn mul(self, other: Variable) -> Self::Output {
+        match (self.numeric(), other.numeric()) {
+            (Variable::Num(n1), Variable::Num(n2)) => Ok(Variable::Num(n1 * n2)),
+            (lhs, rhs) => match dnum_operands(&lhs, &rhs) {
+                Some((n1, n2)) => Ok(Variable::Dnum(n1 * n2)),
+                None => Err(operand_mismatch(Operator::Mul, &lhs, &rhs)),
+            },
+        }
+    }
+}
+
+impl ops::Div for Variable {
+    type Output = Result<Variable, RuntimeError>;
+
+    fn div(self, other: Variable) -> Self::Output {
+        match (self.numeric(), other.numeric()) {
+            (Variable::Num(_), Variable::Num(0.0)) => Err(RuntimeError::div_zero()),
+            (Variable::Num(n1), Variable::Num(n2)) => Ok(Variable::Num(n1 / n2)),
+            (lhs, rhs) => match dnum_operands(&lhs, &rhs) {
+                Some((_, 0.0)) => Err(RuntimeError::div_zero()),
+                Some((n1, n2)) => Ok(Variable::Dnum(n1 / n2)),
+                None => Err(operand_mismatch(Operator::Div, &lhs, &rhs)),
+            },
+        }
+    }
+}
+
+/// Value as ValToStr and TPWrite show it on the controller: numbers with 6
+/// significant digits, TRUE and FALSE, strings in quotes and aggregates in
+/// brackets without spaces, like `[500,0,400]`. Data without a value, like
+/// a clock or a signal, is empty.
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Variable::Void | Variable::Clock { .. } | Variable::Signal(..) | Variable::SyncIdent(_)
+            | Variable::IoDev(_) | Variable::LoadSession(_) | Variable::SocketDev(_) => Ok(()),
+            Variable::Bool(value) => f.write_str(if *value { "TRUE" } else { "FALSE" }),
+            Variable::Num(value) => f.write_str(&host::format_num(*value)),
+            Variable::Dnum(value) => f.write_str(&host::format_dnum(*value)),
+            Variable::Byte(value) => write!(f, "{}", value),
+            Variable::Str(text) => write!(f, "\"{}\"", text),
+            Variable::Record(_, fields) | Variable::Array(fields) => {
+                f.write_str("[")?;
+                for (idx, field) in fields.iter().enumerate() {
+                    if idx > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", field)?;
+                }
+                f.write_str("]")
+            },
+        }
+    }
+}
+
+// ------------------ Conversions -----------------------/
+
+// Values of Rust types for embedding code, like the arguments of
+// `Program::call`. `Variable::from` is the value of a data type, so these
+// are used with `into()` and `try_into()`.
+
+impl From<f64> for Variable {
+    fn from(value: f64) -> Variable {
+        Variable::Num(value)
+    }
+}
+
+impl From<u8> for Variable {
+    fn from(value: u8) -> Variable {
+        Variable::Byte(value)
+    }
+}
+
+impl From<bool> for Variable {
+    fn from(value: bool) -> Variable {
+        Variable::Bool(value)
+    }
+}
+
+impl From<&str> for Variable {
+    fn from(value: &str) -> Variable {
+        Variable::Str(String::from(value))
+    }
+}
+
+impl From<String> for Variable {
+    fn from(value: String) -> Variable {
+        Variable::Str(value)
+    }
+}
+
+/// Array of the values
+impl<T: Into<Variable>> From<Vec<T>> for Variable {
+    fn from(values: Vec<T>) -> Variable {
+        Variable::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl TryFrom<Variable> for f64 {
+    type Error = RuntimeError;
+
+    fn try_from(value: Variable) -> Result<f64, RuntimeError> {
+        value.number()
+    }
+}
+
+impl TryFrom<Variable> for u8 {
+    type Error = RuntimeError;
+
+    fn try_from(value: Variable) -> Result<u8, RuntimeError> {
+        match value {
+            Variable::Byte(value) => Ok(value),
+            Variable::Num(value) => to_byte(value),
+            var => Err(RuntimeError::type_mismatch(format!("Expected byte, found {}", var.type_name()))),
+        }
+    }
+}
+
+impl TryFrom<Variable> for bool {
+    type Error = RuntimeError;
+
+    fn try_from(value: Variable) -> Result<bool, RuntimeError> {
+        value.condition()
+    }
+}
+
+impl TryFrom<Variable> for String {
+    type Error = RuntimeError;
+
+    fn try_from(value: Variable) -> Result<String, RuntimeError> {
+        match value {
+            Variable::Str(text) => Ok(text),
+            var => Err(RuntimeError::type_mismatch(format!("Expected string, found {}", var.type_name()))),
+        }
+    }
+}
+
+/// Elements of an array, or the components of a record in order, like the
+/// x, y and z of a pos
+impl<T: TryFrom<Variable, Error = RuntimeError>> TryFrom<Variable> for Vec<T> {
+    type Error = RuntimeError;
+
+    fn try_from(value: Variable) -> Result<Vec<T>, RuntimeError> {
+        match value {
+            Variable::Array(items) | Variable::Record(_, items) => items.into_iter().map(T::try_from).collect(),
+            var => Err(RuntimeError::type_mismatch(format!("Expected array, found {}", var.type_name()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn converts_rust_values() {
+        assert!(matches!(2.5.into(), Variable::Num(n) if n == 2.5));
+        assert!(matches!(true.into(), Variable::Bool(true)));
+        assert!(matches!("text".into(), Variable::Str(text) if text == "text"));
+        assert!(matches!(vec![1.0, 2.0].into(), Variable::Array(items) if items.len() == 2));
+
+        let value: f64 = Variable::Byte(7).try_into().unwrap();
+        assert_eq!(value, 7.0);
+        let text: String = Variable::Str(String::from("text")).try_into().unwrap();
+        assert_eq!(text, "text");
+        let pos: Vec<f64> = Variable::Record("pos", vec![Variable::Num(1.0), Variable::Num(2.0), Variable::Num(3.0)]).try_into().unwrap();
+        assert_eq!(pos, vec![1.0, 2.0, 3.0]);
+        let flags: Result<Vec<bool>, RuntimeError> = Variable::Array(vec![Variable::Bool(true), Variable::Num(1.0)]).try_into();
+        assert_eq!(flags.unwrap_err().to_string(), "0:0: ERR_ARGVALERR: Expected bool condition, found num");
+        assert!(u8::try_from(Variable::Num(256.0)).is_err());
+    }
+
+    #[test]
+    fn displays_values_like_val_to_str() {
+        let pose = Variable::Record("pose", vec![
+            Variable::Record("pos", vec![Variable::Num(500.0), Variable::Num(1.0 / 3.0), Variable::Num(-0.0)]),
+            Variable::Array(vec![Variable::Num(9E+09), Variable::Byte(7), Variable::Bool(true)]),
+        ]);
+        assert_eq!(pose.to_string(), "[[500,0.333333,0],[9E+09,7,TRUE]]");
+        assert_eq!(Variable::Str(String::from("Part A")).to_string(), "\"Part A\"");
+        assert_eq!(Variable::IoDev(None).to_string(), "");
+    }
+}
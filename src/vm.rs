@@ -0,0 +1,1313 @@
+use std::borrow::Cow;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::builtins::{self, Builtin};
+use crate::compiler::{self, Bytecode, Code, DataName, Instr};
+use crate::host::{self, Host, Location, ProgramData};
+use crate::interpreter::{self, Budget, InterpreterOptions, LateArg, Passed, RuntimeError};
+use crate::lexer::Span;
+use crate::loader::{self, LoadedModule};
+use crate::parser::{Callee, Module, Param, ParamMode, Program, Storage};
+use crate::persistence;
+use crate::variable::Variable;
+
+/// Activation record of a compiled routine
+struct Frame {
+    code: Rc<Code>,
+    // Next instruction
+    pc: usize,
+    locals: Vec<Variable>,
+    // Storage of the data passed for each parameter slot, `None` when no
+    // argument was passed
+    passed: Vec<Option<Storage>>,
+    // Height of the value stack when the routine was called
+    base: usize,
+    // Slots copied back to the caller on return
+    outputs: Outputs,
+    // Error being handled and the instructions of the statement that
+    // raised it, errors raised meanwhile go to the caller
+    error: Option<(RuntimeError, (usize, usize))>,
+}
+
+/// Arguments a routine gives back to its caller on return
+enum Outputs {
+    // Values of the slots, pushed last first
+    Slots(Vec<usize>),
+    // Of a late bound call: for each data argument the slot it's an
+    // output of, pushed last first with whether it's an output
+    Late(Vec<Option<usize>>),
+}
+
+impl Outputs {
+    fn push(&self, values: &mut Vec<Variable>, take: impl FnMut(usize) -> Variable) {
+        match self {
+            Outputs::Slots(slots) => push_outputs(values, slots, take),
+            Outputs::Late(slots) => push_late_outputs(values, slots, take),
+        }
+    }
+}
+
+fn push_outputs(values: &mut Vec<Variable>, slots: &[usize], mut take: impl FnMut(usize) -> Variable) {
+    for slot in slots.iter().rev() {
+        values.push(take(*slot));
+    }
+}
+
+fn push_late_outputs(values: &mut Vec<Variable>, slots: &[Option<usize>], mut take: impl FnMut(usize) -> Variable) {
+    for slot in slots.iter().rev() {
+        values.push(slot.map_or(Variable::Void, &mut take));
+        values.push(Variable::Bool(slot.is_some()));
+    }
+}
+
+/// Call stack and data of a compiled program that's being run. The host is
+/// lent to each `execute`, so several programs can take turns on it.
+pub struct Vm<'a> {
+    // Compiled again when modules are loaded or unloaded
+    bytecode: Cow<'a, Bytecode>,
+    program: &'a Program,
+    // Modules of the program and the loaded ones, once they changed
+    linked: Option<(Vec<Module>, Vec<LoadedModule>)>,
+    frames: Vec<Frame>,
+    values: Vec<Variable>,
+    pub globals: Vec<Variable>,
+    // Routine the run started with, where PP to Main goes
+    entry: usize,
+    // Whether the observer was told the bottom routine of the stack started
+    entered: bool,
+    // Whether the last instruction jumped back, to a loop or RETRY. The
+    // observer only hears about statements the program goes on with.
+    looped: bool,
+    // Number of the last error handled, read by ERRNO
+    errno: i32,
+    // Number of frames with the TRAP routine that runs, interrupts that
+    // occur wait until it returns. An error it doesn't handle ends the run.
+    trap: Option<usize>,
+    max_depth: usize,
+    budget: Budget,
+}
+
+/// Statement the program pointer is at: the one executed next
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramPointer {
+    pub module: String,
+    pub routine: String,
+    pub span: Span,
+    // Number of routine calls on the stack, 1 in the entry routine
+    pub depth: usize,
+}
+
+/// Why `Vm::execute` returned
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Finished,
+    // A wait instruction can't finish before another task runs, or before
+    // the host time `wake`. It's executed again on the next `execute`.
+    Waiting { wake: Option<Duration>, span: Span },
+    // Stopped before the statement at `span` for `execute_until`
+    Paused { span: Span },
+}
+
+/// Whether `Vm::execute_until` stops before a statement, given its
+/// routine, its span and the number of calls on the stack
+pub type Pause<'p> = dyn FnMut(&Code, Span, usize) -> bool + 'p;
+
+/// Run a compiled routine without arguments on the global data of `program`
+pub fn run(program: &mut Program, bytecode: &Bytecode, entry: &str, options: &InterpreterOptions, host: &mut Host) -> Result<(), RuntimeError> {
+    if let Some(path) = &options.pers_file {
+        persistence::load_file(program, path, options.strict_pers)?;
+    }
+    host.error_location = None;
+    let globals = std::mem::take(&mut program.variables);
+    let mut vm = match Vm::new(bytecode, program, globals, entry, options) {
+        Ok(vm) => vm,
+        Err((err, globals)) => {
+            program.variables = globals;
+            return Err(err);
+        },
+    };
+    let result = vm.execute(host).and_then(|status| match status {
+        Status::Waiting { wake, span } => Err(RuntimeError::Blocked { wake, span }),
+        Status::Finished | Status::Paused { .. } => Ok(()),
+    });
+
+    let (globals, linked) = (vm.globals, vm.linked);
+    program.variables = globals;
+    if let Some((modules, loaded)) = linked {
+        program.modules = modules;
+        program.loaded = loaded;
+    }
+    let stored = match &options.pers_file {
+        Some(path) => persistence::store_file(program, path),
+        None => Ok(()),
+    };
+    result.and(stored)
+}
+
+impl<'a> Vm<'a> {
+    /// Prepare a call of a routine without arguments on the global data,
+    /// which is given back when the routine doesn't exist
+    pub fn new(bytecode: &'a Bytecode, program: &'a Program, globals: Vec<Variable>, entry: &str, options: &InterpreterOptions) -> Result<Vm<'a>, (RuntimeError, Vec<Variable>)> {
+        let mut vm = Vm {
+            bytecode: Cow::Borrowed(bytecode),
+            program,
+            linked: None,
+            frames: Vec::new(),
+            values: Vec::new(),
+            globals,
+            entry: 0,
+            entered: false,
+            looped: false,
+            errno: 0,
+            trap: None,
+            max_depth: options.max_depth,
+            budget: Budget::new(options),
+        };
+        match vm.pp_to_routine(entry) {
+            Ok(()) => {
+                vm.entry = bytecode.find(entry).unwrap_or_default();
+                Ok(vm)
+            },
+            Err(err) => Err((err, vm.globals)),
+        }
+    }
+
+    /// Where execution continues, none once the run finished
+    pub fn pp(&self) -> Option<ProgramPointer> {
+        let frame = self.frames.last()?;
+        Some(ProgramPointer {
+            module: frame.code.module.clone(),
+            routine: frame.code.name.clone(),
+            span: frame.code.spans.get(frame.pc).copied().unwrap_or_default(),
+            depth: self.frames.len(),
+        })
+    }
+
+    pub fn bytecode(&self) -> &Bytecode {
+        &self.bytecode
+    }
+
+    /// Data a name refers to in the routine being executed: its own data,
+    /// the data of its module, the global data of other modules and then
+    /// the data of the system
+    pub fn lookup(&self, name: &str) -> Option<(&DataName, &Variable)> {
+        match self.find(name)? {
+            (true, slot) => {
+                let frame = self.frames.last()?;
+                Some((frame.code.data.get(slot)?, frame.locals.get(slot)?))
+            },
+            (false, slot) => Some((self.bytecode.globals.get(slot)?, self.globals.get(slot)?)),
+        }
+    }
+
+    pub fn lookup_mut(&mut self, name: &str) -> Option<(&DataName, &mut Variable)> {
+        match self.find(name)? {
+            (true, slot) => {
+                let frame = self.frames.last_mut()?;
+                Some((frame.code.data.get(slot)?, frame.locals.get_mut(slot)?))
+            },
+            (false, slot) => Some((self.bytecode.globals.get(slot)?, self.globals.get_mut(slot)?)),
+        }
+    }
+
+    /// Whether the data is local to the routine, and its slot
+    fn find(&self, name: &str) -> Option<(bool, usize)> {
+        let code = self.frames.last().map(|frame| &frame.code);
+        if let Some(slot) = code.and_then(|code| code.data.iter().position(|data| data.name.eq_ignore_ascii_case(name))) {
+            return Some((true, slot));
+        }
+
+        let module = code.map_or("", |code| code.module.as_str());
+        self.bytecode.globals.iter().enumerate()
+            .filter(|(_, data)| data.name.eq_ignore_ascii_case(name))
+            .filter_map(|(slot, data)| Some((data.rank(module)?, slot)))
+            .min()
+            .map(|(_, slot)| (false, slot))
+    }
+
+    /// Number of routine calls on the stack
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Start over at the routine the run started with, the data keep their values
+    pub fn pp_to_main(&mut self) -> Result<(), RuntimeError> {
+        let code = self.code(self.entry)?;
+        self.restart(code)
+    }
+
+    /// Start over at a routine without arguments
+    pub fn pp_to_routine(&mut self, name: &str) -> Result<(), RuntimeError> {
+        let code = match self.bytecode.find(name) {
+            Some(routine) => self.code(routine)?,
+            None => return Err(RuntimeError::UnknownRoutine { name: String::from(name), span: Span::default() }),
+        };
+        self.restart(code)
+    }
+
+    /// Continue at the statement on a line of a routine. Within the routine
+    /// being executed the call stack stays, otherwise the routine starts
+    /// over like PP to Routine.
+    pub fn pp_to_cursor(&mut self, routine: &str, line: usize) -> Result<(), RuntimeError> {
+        let current = self.frames.last().is_some_and(|frame| frame.code.name.eq_ignore_ascii_case(routine));
+        if !current {
+            self.pp_to_routine(routine)?;
+        }
+        let frame = match self.frames.last_mut() {
+            Some(frame) => frame,
+            None => return Err(RuntimeError::UnknownRoutine { name: String::from(routine), span: Span::default() }),
+        };
+        match frame.code.statement_at(line) {
+            Some(pc) => {
+                frame.pc = pc;
+                frame.error = None;
+                self.values.truncate(frame.base);
+                Ok(())
+            },
+            None => Err(RuntimeError::Unsupported { message: format!("No statement of {} on line {}", frame.code.name, line), span: Span::default() }),
+        }
+    }
+
+    fn restart(&mut self, code: Rc<Code>) -> Result<(), RuntimeError> {
+        self.frames.clear();
+        self.values.clear();
+        self.errno = 0;
+        self.entered = false;
+        let locals = code.locals.clone();
+        self.push_frame(code, locals, Vec::new(), Outputs::Slots(Vec::new()))
+    }
+
+    /// Run until the routine returns or has to wait for another task
+    pub fn execute(&mut self, host: &mut Host) -> Result<Status, RuntimeError> {
+        self.execute_until(host, None)
+    }
+
+    /// Run like `execute`, but stop before the first statement after the
+    /// current one that `pause` accepts
+    pub fn execute_until(&mut self, host: &mut Host, mut pause: Option<&mut Pause>) -> Result<Status, RuntimeError> {
+        if !self.entered {
+            self.entered = true;
+            if let Some(frame) = self.frames.first() {
+                host.observe(|observer| observer.enter_routine(&frame.code.module, &frame.code.name));
+            }
+        }
+
+        let mut first = true;
+        loop {
+            if self.trap.is_some() || host.interrupts.armed() {
+                self.interrupt(host)?;
+            }
+            let depth = self.frames.len();
+            let frame = match self.frames.last_mut() {
+                Some(frame) => frame,
+                None => return Ok(Status::Finished),
+            };
+            let code = Rc::clone(&frame.code);
+            if let (false, Some(pause)) = (first, pause.as_mut()) {
+                if code.starts_statement(frame.pc) {
+                    let span = code.spans.get(frame.pc).copied().unwrap_or_default();
+                    if pause(&code, span, depth) {
+                        return Ok(Status::Paused { span });
+                    }
+                }
+            }
+            first = false;
+            if host.observer.is_some() && !self.looped && code.starts_statement(frame.pc) {
+                let span = code.spans.get(frame.pc).copied().unwrap_or_default();
+                host.observe(|observer| observer.statement(span));
+            }
+            self.looped = false;
+
+            let instr = match code.instrs.get(frame.pc) {
+                Some(instr) => instr,
+                None => return Err(RuntimeError::Unsupported { message: format!("Missing end of {}", code.name), span: Span::default() }),
+            };
+            frame.pc += 1;
+
+            if let Err(err) = self.budget.tick().and_then(|_| self.step(instr, host)) {
+                // The span of the instruction being executed, or of the call when the routine ended
+                let span = self.frames.last()
+                    .and_then(|frame| frame.code.spans.get(frame.pc.checked_sub(1)?))
+                    .copied()
+                    .unwrap_or_default();
+                if let RuntimeError::Blocked { wake, .. } = err {
+                    self.rewind();
+                    return Ok(Status::Waiting { wake, span });
+                }
+                let err = err.at(span);
+                host.error_location = self.frames.last().map(|frame| Location {
+                    module: frame.code.module.clone(),
+                    routine: frame.code.name.clone(),
+                    span: err.span(),
+                });
+                host.observe(|observer| observer.error(&err));
+                self.handle(err, host)?;
+            }
+        }
+    }
+
+    /// Go back to the start of the statement being executed, so the wait
+    /// it's blocked in evaluates its arguments again
+    fn rewind(&mut self) {
+        if let Some(frame) = self.frames.last_mut() {
+            let pc = frame.pc.saturating_sub(1);
+            if let Some((start, _)) = frame.code.statements.iter().find(|(start, end)| (*start..*end).contains(&pc)) {
+                frame.pc = *start;
+                self.values.truncate(frame.base);
+            } else {
+                frame.pc = pc;
+            }
+        }
+    }
+
+    /// Jump to the ERROR handler of the innermost routine that can handle
+    /// the error, leaving the routines in between. Errors without a number,
+    /// errors no handler takes and errors that leave a TRAP routine end the
+    /// run.
+    fn handle(&mut self, err: RuntimeError, host: &mut Host) -> Result<(), RuntimeError> {
+        let errno = match err.errno() {
+            Some(errno) => errno,
+            None => return Err(err),
+        };
+        while let Some(frame) = self.frames.last_mut() {
+            if let (Some(handler), None) = (frame.code.handler, &frame.error) {
+                // The call in a caller, or the instruction that failed
+                let pc = frame.pc.saturating_sub(1);
+                let statement = frame.code.statements.iter()
+                    .find(|(start, end)| (*start..*end).contains(&pc))
+                    .copied()
+                    .unwrap_or((pc, frame.pc));
+                frame.error = Some((err, statement));
+                frame.pc = handler;
+                self.values.truncate(frame.base);
+                self.errno = errno;
+                return Ok(());
+            }
+            self.exit(host);
+            self.frames.pop();
+            if self.trap.is_some_and(|depth| depth > self.frames.len()) {
+                break;
+            }
+        }
+        Err(err)
+    }
+
+    fn step(&mut self, instr: &Instr, host: &mut Host) -> Result<(), RuntimeError> {
+        match instr {
+            Instr::Push(var) => self.values.push(var.clone()),
+            Instr::Pop => {
+                self.pop()?;
+            },
+            Instr::Dup => {
+                let var = self.pop()?;
+                self.values.push(var.clone());
+                self.values.push(var);
+            },
+            Instr::Load(slot) => {
+                let var = self.local(*slot)?.clone();
+                self.values.push(var);
+            },
+            Instr::Store(slot) => {
+                let var = self.pop()?;
+                self.local(*slot)?.set(var)?;
+                self.stored(*slot, false, host);
+            },
+            Instr::LoadGlobal(slot) => {
+                let var = self.global(*slot)?.clone();
+                self.values.push(var);
+            },
+            Instr::StoreGlobal(slot) => {
+                let var = self.pop()?;
+                self.global(*slot)?.set(var)?;
+                self.stored(*slot, true, host);
+            },
+            Instr::LoadElement { slot, global, dims } => {
+                let indices = self.indices(*dims)?;
+                let var = self.data(*slot, *global)?.element(&indices)?.clone();
+                self.values.push(var);
+            },
+            Instr::StoreElement { slot, global, dims } => {
+                let indices = self.indices(*dims)?;
+                let var = self.pop()?;
+                self.data(*slot, *global)?.element_mut(&indices)?.set(var)?;
+                self.stored(*slot, *global, host);
+            },
+            Instr::Present(slot) => {
+                let passed = self.frames.last().and_then(|frame| frame.passed.get(*slot));
+                self.values.push(Variable::Bool(passed.is_some_and(Option::is_some)));
+            },
+            Instr::IsStorage(slot, storage) => {
+                let passed = self.frames.last().and_then(|frame| frame.passed.get(*slot));
+                self.values.push(Variable::Bool(passed == Some(&Some(*storage))));
+            },
+            Instr::Errno => self.values.push(Variable::Num(self.errno as f64)),
+            Instr::BinOp(op) => {
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                self.values.push(Variable::operate(*op, lhs, rhs)?);
+            },
+            Instr::Neg => {
+                let var = (Variable::Num(0.0) - self.pop()?)?;
+                self.values.push(var);
+            },
+            Instr::Not => {
+                let var = Variable::Bool(!self.pop()?.condition()?);
+                self.values.push(var);
+            },
+            Instr::Aggregate(count) => {
+                let base = self.values.len().saturating_sub(*count);
+                let values = self.values.split_off(base);
+                self.values.push(Variable::Record("", values));
+            },
+            Instr::Print(kind) => {
+                let arg = match kind {
+                    Some(kind) => Some((*kind, self.pop()?)),
+                    None => None,
+                };
+                let text = self.pop()?;
+                let line = host::write_line(text, arg)?;
+                host.output.write(&line);
+            },
+            Instr::Jump(target) => self.jump(*target),
+            Instr::JumpIfFalse(target) => {
+                if !self.pop()?.condition()? {
+                    self.jump(*target);
+                }
+            },
+            Instr::JumpIfTrue(target) => {
+                if self.pop()?.condition()? {
+                    self.jump(*target);
+                }
+            },
+            Instr::DefaultStep { counter, end, step } => {
+                let down = self.local(*counter)?.number()? > self.local(*end)?.number()?;
+                *self.local(*step)? = Variable::Num(if down { -1.0 } else { 1.0 });
+            },
+            Instr::ForCheck { counter, end, step, exit } => {
+                let counter = self.local(*counter)?.number()?;
+                let end = self.local(*end)?.number()?;
+                let done = if self.local(*step)?.number()? < 0.0 { counter < end } else { counter > end };
+                if done {
+                    self.jump(*exit);
+                }
+            },
+            Instr::Call { routine, args, outputs, passed } => {
+                let code = self.code(*routine)?;
+                self.call(code, args, passed, Outputs::Slots(outputs.clone()), host)?;
+            },
+            Instr::CallBuiltin { builtin, args, outputs, names } => {
+                let builtin = match builtins::builtins().get(*builtin) {
+                    Some(builtin) => builtin,
+                    None => return Err(RuntimeError::UnknownRoutine { name: format!("#{}", builtin), span: Span::default() }),
+                };
+                if !names.is_empty() {
+                    host.arg_names = names.clone();
+                }
+                let mut arguments = self.call_builtin(builtin, args, host)?;
+                push_outputs(&mut self.values, outputs, |slot| arguments[slot].take().unwrap_or(Variable::Void));
+                if !host.module_changes.is_empty() {
+                    self.link(host)?;
+                }
+            },
+            Instr::CallHost { routine, args, outputs } => {
+                let routine = match self.program.host_routines.get(*routine) {
+                    Some(routine) => routine,
+                    None => return Err(RuntimeError::UnknownRoutine { name: format!("#{}", routine), span: Span::default() }),
+                };
+                let mut arguments = self.call_native(&routine.params, args, |values| routine.call(values))?;
+                push_outputs(&mut self.values, outputs, |slot| arguments[slot].take().unwrap_or(Variable::Void));
+            },
+            Instr::LateCall { args } => {
+                let name = match self.values.len().checked_sub(args.len() + 1) {
+                    Some(at) => self.values.remove(at),
+                    None => return Err(RuntimeError::Unsupported { message: String::from("Value stack is empty"), span: Span::default() }),
+                };
+                match name {
+                    Variable::Str(name) => self.late_call(&name, args, host)?,
+                    var => return Err(RuntimeError::type_mismatch(format!("Late binding expects a string, found {}", var.type_name()))),
+                }
+            },
+            Instr::Connect { module, trap } => {
+                if host.interrupts.is_connected(self.pop()?.number()?) {
+                    return Err(RuntimeError::AlreadyConnected { span: Span::default() });
+                }
+                let number = host.interrupts.connect(module, trap);
+                self.values.push(Variable::Num(number));
+            },
+            Instr::Return => {
+                self.exit(host);
+                self.return_from(None)?;
+            },
+            Instr::ReturnValue => {
+                let var = self.pop()?;
+                self.exit(host);
+                self.return_from(Some(var))?;
+            },
+            Instr::MissingReturn => {
+                self.exit(host);
+                let frame = self.frames.pop();
+                let name = frame.map(|frame| frame.code.name.clone()).unwrap_or_default();
+                return Err(RuntimeError::MissingReturn { name, span: Span::default() });
+            },
+            Instr::Retry | Instr::TryNext => {
+                let statement = self.frames.last_mut().and_then(|frame| frame.error.take()).map(|(_, statement)| statement);
+                match statement {
+                    Some((start, end)) => {
+                        let retry = matches!(instr, Instr::Retry);
+                        self.jump(if retry { start } else { end });
+                        // The handler comes after the statement, but only RETRY executes it again
+                        self.looped = retry;
+                    },
+                    None => return Err(RuntimeError::Unsupported { message: String::from("No error to resume from"), span: Span::default() }),
+                }
+            },
+            Instr::Raise => {
+                let errno = self.pop()?.number()?;
+                return Err(RuntimeError::raise(errno));
+            },
+            Instr::Reraise => {
+                return match self.frames.last().and_then(|frame| frame.error.as_ref()) {
+                    Some((err, _)) => Err(err.clone()),
+                    None => Err(RuntimeError::Unsupported { message: String::from("RAISE without an error to pass on"), span: Span::default() }),
+                };
+            },
+        }
+        Ok(())
+    }
+
+    /// Call a compiled routine with the values on the stack for the
+    /// parameter slots in `args`, which pass what `passed` tells
+    fn call(&mut self, code: Rc<Code>, args: &[usize], passed: &[Passed], outputs: Outputs, host: &mut Host) -> Result<(), RuntimeError> {
+        let base = self.values.len().saturating_sub(args.len());
+        let values = self.values.split_off(base);
+        let mut locals = code.locals.clone();
+        let mut storage = vec![None; args.iter().max().map_or(0, |slot| slot + 1)];
+        for ((slot, value), passed) in args.iter().zip(values).zip(passed) {
+            match (locals.get_mut(*slot), code.params.get(*slot)) {
+                (Some(var), Some(param)) => param.bind(var, value)?,
+                (Some(var), None) => var.set(value)?,
+                (None, _) => return Err(RuntimeError::UnknownData { slot: *slot, span: Span::default() }),
+            }
+            storage[*slot] = Some(match passed {
+                Passed::Data(storage) => *storage,
+                Passed::InOut(caller) => self.frames.last().and_then(|frame| *frame.passed.get(*caller)?).unwrap_or(Storage::Var),
+            });
+        }
+        self.push_frame(Rc::clone(&code), locals, storage, outputs)?;
+        host.observe(|observer| observer.enter_routine(&code.module, &code.name));
+        Ok(())
+    }
+
+    /// Call the TRAP routine of the next interrupt that occurred, when the
+    /// routine being executed is about to start a statement
+    #[inline(never)]
+    fn interrupt(&mut self, host: &mut Host) -> Result<(), RuntimeError> {
+        match self.trap {
+            Some(depth) if self.frames.len() >= depth => return Ok(()),
+            _ => self.trap = None,
+        }
+        if !self.frames.last().is_some_and(|frame| frame.code.starts_statement(frame.pc)) {
+            return Ok(());
+        }
+        let (module, trap) = match host.interrupts.next(host.io, host.clock.now())? {
+            Some(interrupt) => interrupt,
+            None => return Ok(()),
+        };
+        let code = self.bytecode.routines.iter()
+            .find(|code| code.trap && code.module == module && code.name.eq_ignore_ascii_case(&trap))
+            .cloned();
+        let code = code.ok_or(RuntimeError::UnknownRoutine { name: trap, span: Span::default() })?;
+        self.call(code, &[], &[], Outputs::Slots(Vec::new()), host)?;
+        self.trap = Some(self.frames.len());
+        Ok(())
+    }
+
+    /// Call the procedure a string names, the arguments on the stack are
+    /// checked against its parameters now
+    fn late_call(&mut self, name: &str, args: &[LateArg], host: &mut Host) -> Result<(), RuntimeError> {
+        let span = Span::default();
+        // Slot of each data argument that's an output
+        let outputs = |params: &[Param], slots: &[usize]| -> Vec<Option<usize>> {
+            args.iter().zip(slots)
+                .filter(|(arg, _)| arg.data)
+                .map(|(_, slot)| Some(*slot).filter(|slot| params[*slot].mode != ParamMode::In))
+                .collect()
+        };
+        if let Some(code) = self.late_routine(name)? {
+            let slots = interpreter::late_slots(&code.params, name, args, span)?;
+            let outputs = outputs(&code.params, &slots);
+            let passed: Vec<Passed> = args.iter().map(|arg| arg.passed).collect();
+            return self.call(code, &slots, &passed, Outputs::Late(outputs), host);
+        }
+
+        let program = self.program;
+        match builtins::native_procedure(name, &program.host_routines) {
+            Some(Callee::Builtin(idx)) => {
+                let builtin = &builtins::builtins()[idx];
+                let slots = interpreter::late_slots(&builtin.params, name, args, span)?;
+                let mut arguments = self.call_builtin(builtin, &slots, host)?;
+                push_late_outputs(&mut self.values, &outputs(&builtin.params, &slots), |slot| arguments[slot].take().unwrap_or(Variable::Void));
+                if !host.module_changes.is_empty() {
+                    self.link(host)?;
+                }
+                Ok(())
+            },
+            Some(Callee::Host(idx)) => {
+                let routine = &program.host_routines[idx];
+                let slots = interpreter::late_slots(&routine.params, name, args, span)?;
+                let mut arguments = self.call_native(&routine.params, &slots, |values| routine.call(values))?;
+                push_late_outputs(&mut self.values, &outputs(&routine.params, &slots), |slot| arguments[slot].take().unwrap_or(Variable::Void));
+                Ok(())
+            },
+            _ => Err(RuntimeError::UnknownRoutine { name: String::from(name), span }),
+        }
+    }
+
+    /// Compiled procedure a late bound call names: one of the module of the
+    /// routine being executed, or a global one of another module, those of
+    /// system modules last. Functions can't be called late bound.
+    fn late_routine(&self, name: &str) -> Result<Option<Rc<Code>>, RuntimeError> {
+        let module = self.frames.last().map(|frame| frame.code.module.as_str());
+        let found = self.bytecode.routines.iter()
+            .filter(|code| code.name.eq_ignore_ascii_case(name) && (!code.local || Some(code.module.as_str()) == module))
+            .min_by_key(|code| (Some(code.module.as_str()) != module, code.system));
+        match found {
+            Some(code) if code.func => Err(RuntimeError::UnknownRoutine { name: String::from(name), span: Span::default() }),
+            found => Ok(found.cloned()),
+        }
+    }
+
+    /// Link the modules the instruction that returned loaded or unloaded,
+    /// and compile the program again. The routines being executed keep
+    /// running the code they started with.
+    #[inline(never)]
+    fn link(&mut self, host: &mut Host) -> Result<(), RuntimeError> {
+        let program = self.program;
+        let (modules, loaded) = self.linked.get_or_insert_with(|| (program.modules.clone(), program.loaded.clone()));
+        let busy = self.frames.iter()
+            .filter_map(|frame| modules.iter().position(|module| module.name == frame.code.module))
+            .map(|module| module + 1)
+            .max()
+            .unwrap_or(0);
+        let globals = &mut self.globals;
+        let linked = std::mem::take(&mut host.module_changes).into_iter()
+            .try_for_each(|change| loader::link(change, modules, loaded, globals, &program.system, &program.host_routines, busy));
+        self.bytecode = Cow::Owned(compiler::compile_modules(modules, &program.system, &program.host_routines)?);
+        linked
+    }
+
+    /// Call a built-in or host routine with the values on the stack, push
+    /// its return value. Returns the arguments, to push the output ones
+    /// like a compiled routine.
+    fn call_native(&mut self, params: &[Param], args: &[usize],
+        invoke: impl FnOnce(&mut [Option<Variable>]) -> Result<Option<Variable>, RuntimeError>) -> Result<Vec<Option<Variable>>, RuntimeError> {
+        let base = self.values.len().saturating_sub(args.len());
+        let values = self.values.split_off(base);
+        let mut arguments = vec![None; params.len()];
+        for (slot, value) in args.iter().zip(values) {
+            match params.get(*slot) {
+                Some(param) => arguments[*slot] = Some(builtins::argument(param, value)?),
+                None => return Err(RuntimeError::UnknownData { slot: *slot, span: Span::default() }),
+            }
+        }
+
+        if let Some(result) = invoke(&mut arguments)? {
+            self.values.push(result);
+        }
+        Ok(arguments)
+    }
+
+    /// Call a built-in routine, lending the global data to the ones that
+    /// access data by name
+    fn call_builtin(&mut self, builtin: &Builtin, args: &[usize], host: &mut Host) -> Result<Vec<Option<Variable>>, RuntimeError> {
+        if !builtins::accesses_data(builtin) {
+            return self.call_native(&builtin.params, args, |values| (builtin.call)(&mut *host, values));
+        }
+        let module = self.frames.last().map(|frame| frame.code.module.clone()).unwrap_or_default();
+        host.data = ProgramData { names: Rc::clone(&self.bytecode.globals), values: std::mem::take(&mut self.globals), module };
+        let arguments = self.call_native(&builtin.params, args, |values| (builtin.call)(&mut *host, values));
+        self.globals = std::mem::take(&mut host.data).values;
+        arguments
+    }
+
+    /// Tell the observer the current routine ends
+    fn exit(&self, host: &mut Host) {
+        if let Some(frame) = self.frames.last() {
+            host.observe(|observer| observer.exit_routine(&frame.code.module, &frame.code.name));
+        }
+    }
+
+    /// Tell the observer about the new value of named data
+    fn stored(&self, slot: usize, global: bool, host: &mut Host) {
+        if host.observer.is_none() {
+            return;
+        }
+        let data = if global {
+            self.bytecode.globals.get(slot).zip(self.globals.get(slot))
+        } else {
+            self.frames.last().and_then(|frame| frame.code.data.get(slot).zip(frame.locals.get(slot)))
+        };
+        if let Some((data, var)) = data {
+            host.observe(|observer| observer.assign(&data.name, var));
+        }
+    }
+
+    fn code(&self, routine: usize) -> Result<Rc<Code>, RuntimeError> {
+        match self.bytecode.routines.get(routine) {
+            Some(code) => Ok(Rc::clone(code)),
+            None => Err(RuntimeError::UnknownRoutine { name: format!("#{}", routine), span: Span::default() }),
+        }
+    }
+
+    fn push_frame(&mut self, code: Rc<Code>, locals: Vec<Variable>, passed: Vec<Option<Storage>>, outputs: Outputs) -> Result<(), RuntimeError> {
+        if self.frames.len() >= self.max_depth {
+            return Err(RuntimeError::StackOverflow { depth: self.max_depth, span: Span::default() });
+        }
+        self.frames.push(Frame { code, pc: 0, locals, passed, base: self.values.len(), outputs, error: None });
+        Ok(())
+    }
+
+    /// Pop the current frame, push the return value of a FUNC and the output arguments
+    fn return_from(&mut self, result: Option<Variable>) -> Result<(), RuntimeError> {
+        let mut frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return Err(RuntimeError::Unsupported { message: String::from("Call stack is empty"), span: Span::default() }),
+        };
+        self.values.truncate(frame.base);
+        if frame.code.func {
+            self.values.push(result.unwrap_or(Variable::Void));
+        }
+        let locals = &mut frame.locals;
+        frame.outputs.push(&mut self.values, |slot| std::mem::replace(&mut locals[slot], Variable::Void));
+        Ok(())
+    }
+
+    fn jump(&mut self, target: usize) {
+        if let Some(frame) = self.frames.last_mut() {
+            self.looped = target < frame.pc;
+            frame.pc = target;
+        }
+    }
+
+    fn pop(&mut self) -> Result<Variable, RuntimeError> {
+        match self.values.pop() {
+            Some(var) => Ok(var),
+            None => Err(RuntimeError::Unsupported { message: String::from("Value stack is empty"), span: Span::default() }),
+        }
+    }
+
+    fn local(&mut self, slot: usize) -> Result<&mut Variable, RuntimeError> {
+        match self.frames.last_mut().and_then(|frame| frame.locals.get_mut(slot)) {
+            Some(var) => Ok(var),
+            None => Err(RuntimeError::UnknownData { slot, span: Span::default() }),
+        }
+    }
+
+    fn data(&mut self, slot: usize, global: bool) -> Result<&mut Variable, RuntimeError> {
+        if global {
+            self.global(slot)
+        } else {
+            self.local(slot)
+        }
+    }
+
+    /// Pop the indices of an array element, the last dimension is on top
+    fn indices(&mut self, dims: usize) -> Result<Vec<f64>, RuntimeError> {
+        let base = self.values.len().saturating_sub(dims);
+        self.values.split_off(base).iter().map(Variable::number).collect()
+    }
+
+    fn global(&mut self, slot: usize) -> Result<&mut Variable, RuntimeError> {
+        match self.globals.get_mut(slot) {
+            Some(var) => Ok(var),
+            None => Err(RuntimeError::UnknownData { slot, span: Span::default() }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler;
+    use crate::interpreter;
+    use crate::lexer;
+    use crate::parser;
+    use crate::resolver::{self, ResolveOptions};
+
+    fn compile_source(source: &str) -> (Program, Bytecode) {
+        let mut program = parser::parse_tokens(lexer::parse(source)).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let bytecode = compiler::compile(&program).unwrap();
+        (program, bytecode)
+    }
+
+    /// Run `main` on the VM and return the global data afterwards
+    fn run_source(source: &str) -> Result<Vec<Variable>, RuntimeError> {
+        let (mut program, bytecode) = compile_source(source);
+        run(&mut program, &bytecode, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new()))?;
+        Ok(program.variables)
+    }
+
+    #[test]
+    fn matches_interpreter() {
+        let source = "
+MODULE Vm
+    VAR num nFact := 0;
+    VAR num nSum := 0;
+    VAR string sCase := \"\";
+    FUNC num Fact(num n)
+        IF n <= 1 RETURN 1;
+        RETURN n * Fact(n - 1);
+    ENDFUNC
+    PROC rAdd(INOUT num nTotal, num nValue)
+        nTotal := nTotal + nValue;
+    ENDPROC
+    PROC main()
+        nFact := Fact(6);
+        FOR i FROM 10 TO 1 STEP -3 DO
+            rAdd nSum, i;
+        ENDFOR
+        WHILE nSum < 100 DO
+            nSum := nSum * 2;
+        ENDWHILE
+        TEST nSum MOD 5
+        CASE 0, 1:
+            sCase := \"low\";
+        CASE 2:
+            sCase := \"two\";
+        DEFAULT:
+            sCase := \"high\";
+        ENDTEST
+    ENDPROC
+ENDMODULE";
+        let globals = run_source(source).unwrap();
+        assert_eq!(format!("{:?}", globals), r#"[Num(720.0), Num(176.0), Str("low")]"#);
+
+        let mut program = parser::parse_tokens(lexer::parse(source)).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        interpreter::run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).into_result().unwrap();
+        assert_eq!(format!("{:?}", globals), format!("{:?}", program.variables));
+    }
+
+    #[test]
+    fn bytecode_runs_repeatedly() {
+        let (mut program, bytecode) = compile_source("
+MODULE Vm
+    VAR num nCount := 0;
+    PROC main()
+        nCount := nCount + 1;
+    ENDPROC
+ENDMODULE");
+        for _ in 0..3 {
+            run(&mut program, &bytecode, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).unwrap();
+        }
+        assert!(matches!(program.variables[0], Variable::Num(n) if n == 3.0));
+    }
+
+    #[test]
+    fn reports_errors_with_span() {
+        let err = run_source("
+MODULE Vm
+    VAR num nResult := 0;
+    PROC main()
+        nResult := 1;
+        nResult := nResult / 0;
+    ENDPROC
+ENDMODULE").unwrap_err();
+        assert_eq!(err.to_string(), "6:9: ERR_DIVZERO: Division by zero");
+
+        let err = run_source("
+MODULE Vm
+    VAR num nResult := 0;
+    FUNC num Missing()
+    ENDFUNC
+    PROC main()
+        nResult := Missing();
+    ENDPROC
+ENDMODULE").unwrap_err();
+        assert_eq!(err.to_string(), "7:20: ERR_FNCNORET: FUNC Missing ended without RETURN");
+
+        let err = run_source("
+MODULE Vm
+    PROC main()
+        main;
+    ENDPROC
+ENDMODULE").unwrap_err();
+        assert_eq!(err.to_string(), "4:9: ERR_STACKOVERFLOW: Routine calls nested deeper than 250 levels");
+    }
+
+    #[test]
+    fn runaway_loops_hit_limits() {
+        let (mut program, bytecode) = compile_source("
+MODULE Limits
+    PROC main()
+        WHILE TRUE DO
+        ENDWHILE
+    ENDPROC
+ENDMODULE");
+        let options = InterpreterOptions { max_instructions: Some(1000), ..InterpreterOptions::default() };
+        let err = run(&mut program, &bytecode, "main", &options, &mut Host::new(&mut Vec::new())).unwrap_err();
+        assert_eq!(err.to_string(), "4:9: ERR_EXECLIMIT: Execution limit exceeded: more than 1000 instructions");
+
+        let options = InterpreterOptions { timeout: Some(std::time::Duration::from_millis(10)), ..InterpreterOptions::default() };
+        let err = run(&mut program, &bytecode, "main", &options, &mut Host::new(&mut Vec::new())).unwrap_err();
+        assert!(matches!(err, RuntimeError::LimitExceeded { .. }));
+    }
+
+    #[test]
+    fn writes_to_output_sink() {
+        let (mut program, bytecode) = compile_source("
+MODULE Output
+    VAR pos pHome := [100, 0, -50.5];
+    PROC main()
+        TPWrite \"Home \" \\Pos:=pHome;
+        TPWrite \"Moved \" \\Pos:=[1, 2, 3];
+        TPWrite \"Flag \" \\Bool:=pHome = [100, 0, -50.5];
+    ENDPROC
+ENDMODULE");
+        let mut output = Vec::new();
+        run(&mut program, &bytecode, "main", &InterpreterOptions::default(), &mut Host::new(&mut output)).unwrap();
+        assert_eq!(output, vec!["Home [100,0,-50.5]", "Moved [1,2,3]", "Flag TRUE"]);
+
+        let err = run_source("
+MODULE Output
+    VAR pos pHome := [100, 0, 0];
+    PROC main()
+        pHome := [1, 2];
+    ENDPROC
+ENDMODULE").unwrap_err();
+        assert_eq!(err.to_string(), "5:9: ERR_ARGVALERR: Cannot assign aggregate to pos");
+    }
+
+    #[test]
+    fn reads_operator_input() {
+        let (mut program, bytecode) = compile_source("
+MODULE Input
+    VAR num nCount := 0;
+    VAR num nKey := 0;
+    PROC main()
+        TPReadNum nCount, \"How many?\";
+        TPReadFK nKey, \"Continue?\", \"Yes\", \"\", \"\", \"\", \"No\";
+        TPWrite \"Count \" \\Num:=nCount + nKey;
+    ENDPROC
+ENDMODULE");
+        let mut output = Vec::new();
+        let mut answers = std::collections::VecDeque::from(vec![3.0, 1.0]);
+        let mut host = Host::new(&mut output).with_input(&mut answers);
+        run(&mut program, &bytecode, "main", &InterpreterOptions::default(), &mut host).unwrap();
+        assert_eq!(output, vec!["Count 4"]);
+
+        let err = run(&mut program, &bytecode, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).unwrap_err();
+        assert_eq!(err.to_string(), "6:9: ERR_TP_NO_CLIENT: No operator to answer the dialog");
+    }
+
+    #[test]
+    fn arrays_and_optional_arguments() {
+        let source = "
+MODULE Arrays
+    VAR num nGrid{2, 3} := [[1, 2, 3], [4, 5, 6]];
+    VAR num nSum := 0;
+    VAR num nFlags := 0;
+    PROC rCount(\\num nStep, \\switch Fast)
+        IF Present(nStep) nFlags := nFlags + 1;
+        IF Present(Fast) nFlags := nFlags + 10;
+    ENDPROC
+    PROC rIncr(INOUT num nValue)
+        nValue := nValue + 1;
+    ENDPROC
+    PROC main()
+        VAR num nList{4};
+        FOR i FROM 1 TO Dim(nList, 1) DO
+            nList{i} := i * i;
+        ENDFOR
+        FOR i FROM 1 TO Dim(nGrid, 1) DO
+            FOR j FROM 1 TO Dim(nGrid, 2) DO
+                nSum := nSum + nGrid{i, j};
+            ENDFOR
+        ENDFOR
+        nSum := nSum + nList{4};
+        rIncr nList{1};
+        nGrid{2, 3} := nList{1};
+        rCount;
+        rCount \\nStep:=1;
+        rCount \\Fast;
+    ENDPROC
+ENDMODULE";
+        let globals = run_source(source).unwrap();
+        let mut program = parser::parse_tokens(lexer::parse(source)).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        interpreter::run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).into_result().unwrap();
+        assert_eq!(format!("{:?}", globals), format!("{:?}", program.variables));
+
+        let err = run_source("
+MODULE Arrays
+    VAR num nGrid{2, 2};
+    PROC main()
+        nGrid{1, 0} := nGrid{1, 1};
+    ENDPROC
+ENDMODULE").unwrap_err();
+        assert_eq!(err.to_string(), "5:9: ERR_INDEX: Index 0 out of bounds 1 to 2");
+    }
+
+    #[test]
+    fn array_parameters_take_any_length() {
+        let source = "
+MODULE Utils
+    VAR num nTotal := 0;
+    VAR num nCells := 0;
+    VAR num nGrid{2, 3} := [[1, 2, 3], [4, 5, 6]];
+    VAR num nShort{2} := [1, 2];
+    VAR num nLong{5} := [1, 2, 3, 4, 5];
+    FUNC num Sum(num nValues{*})
+        VAR num nSum := 0;
+        FOR i FROM 1 TO Dim(nValues, 1) DO
+            nSum := nSum + nValues{i};
+        ENDFOR
+        RETURN nSum;
+    ENDFUNC
+    PROC rDouble(INOUT num nValues{*})
+        FOR i FROM 1 TO Dim(nValues, 1) DO
+            nValues{i} := 2 * nValues{i};
+        ENDFOR
+    ENDPROC
+    PROC rCount(num nMatrix{*, *})
+        nCells := Dim(nMatrix, 1) * Dim(nMatrix, 2);
+    ENDPROC
+    PROC main()
+        rDouble nShort;
+        nTotal := Sum(nShort) + Sum(nLong);
+        rCount nGrid;
+    ENDPROC
+ENDMODULE";
+        let globals = run_source(source).unwrap();
+        assert_eq!(format!("{:?}", &globals[..2]), "[Num(21.0), Num(6.0)]");
+        assert_eq!(format!("{:?}", globals[3]), "Array([Num(2.0), Num(4.0)])");
+        let mut program = parser::parse_tokens(lexer::parse(source)).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        interpreter::run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).into_result().unwrap();
+        assert_eq!(format!("{:?}", globals), format!("{:?}", program.variables));
+        assert_eq!(program.modules[0].routines[2].declaration(), "PROC rCount(num nMatrix{*,*})");
+
+        let source = source.replace("Sum(nLong)", "Sum(nGrid)");
+        assert_eq!(run_source(&source).unwrap_err().to_string(), "25:33: ERR_ARGVALERR: Array of num has too many dimensions");
+        let source = source.replace("Sum(nGrid)", "Sum(nTotal)");
+        assert_eq!(run_source(&source).unwrap_err().to_string(), "25:33: ERR_ARGVALERR: Expected array of num, found num");
+    }
+
+    #[test]
+    fn conditional_arguments_follow_the_caller() {
+        let source = "
+MODULE Conditional
+    VAR num nFlags := 0;
+    VAR num nRounded := 0;
+    VAR num nMoved := 0;
+    VAR num nLate := 0;
+    PROC rMove(\\num nSpeed, \\switch Fast)
+        nFlags := 0;
+        IF Present(nSpeed) nFlags := nSpeed;
+        IF Present(Fast) nFlags := nFlags + 1;
+    ENDPROC
+    PROC rPath(num nValue, \\num nSpeed, \\switch Fast, \\num nDec)
+        rMove \\nSpeed?nSpeed \\Fast?Fast;
+        nMoved := nMoved + nFlags;
+        nRounded := nRounded + Round(nValue \\Dec?nDec);
+        %\"rMove\"% \\nSpeed?nSpeed;
+        nLate := nLate + nFlags;
+    ENDPROC
+    PROC main()
+        rPath 1.26 \\nSpeed := 10 \\Fast \\nDec := 1;
+        rPath 1.26;
+        rPath 1.26 \\Fast;
+    ENDPROC
+ENDMODULE";
+        let globals = run_source(source).unwrap();
+        assert_eq!(format!("{:?}", globals), "[Num(0.0), Num(3.3), Num(12.0), Num(10.0)]");
+        let mut program = parser::parse_tokens(lexer::parse(source)).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        interpreter::run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).into_result().unwrap();
+        assert_eq!(format!("{:?}", globals), format!("{:?}", program.variables));
+    }
+
+    #[test]
+    fn error_handlers_recover() {
+        let source = "
+MODULE Errors
+    VAR num nDivisor := 0;
+    VAR num nResult := 0;
+    VAR num nErrno := 0;
+    VAR string sText := \"\";
+    FUNC num Divide(num nValue)
+        RETURN nValue / nDivisor;
+    ENDFUNC
+    PROC rSkip()
+        VAR num nList{2};
+        WHILE nResult < 3 DO
+            nResult := nResult + 1;
+            nList{nResult} := 1;
+        ENDWHILE
+    ERROR
+        nErrno := ERRNO;
+        TRYNEXT;
+    ENDPROC
+    PROC main()
+        rSkip;
+        nResult := nResult + Divide(10);
+        sText := \"Divided\" + ValToStr(nResult);
+        IF nResult > 0 RAISE 1;
+        nResult := 0;
+    ERROR
+        IF ERRNO = ERR_DIVZERO THEN
+            nDivisor := 2;
+            RETRY;
+        ENDIF
+        sText := sText + \" and raised\";
+    ENDPROC
+ENDMODULE";
+        let globals = run_source(source).unwrap();
+        assert_eq!(format!("{:?}", globals), r#"[Num(2.0), Num(8.0), Num(1004.0), Str("Divided8 and raised")]"#);
+
+        let mut program = parser::parse_tokens(lexer::parse(source)).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        interpreter::run(&mut program, "main", &InterpreterOptions::default(), &mut Host::new(&mut Vec::new())).into_result().unwrap();
+        assert_eq!(format!("{:?}", globals), format!("{:?}", program.variables));
+
+        let err = run_source("
+MODULE Errors
+    VAR string sText := \"\";
+    PROC rFail()
+        WHILE TRUE DO
+            sText := sText + \"0123456789\";
+        ENDWHILE
+    ERROR
+        RAISE;
+    ENDPROC
+    PROC main()
+        rFail;
+    ERROR
+        RAISE 10;
+    ENDPROC
+ENDMODULE").unwrap_err();
+        assert_eq!(err.to_string(), "14:9: ERR_USER: Error 10 raised and not handled");
+    }
+
+    #[test]
+    fn program_pointer_moves() {
+        let (mut program, bytecode) = compile_source("
+MODULE Pointer
+    VAR string sTrace := \"\";
+    PROC rHome()
+        sTrace := sTrace + \"H\";
+    ENDPROC
+    PROC main()
+        sTrace := sTrace + \"1\";
+        rHome;
+        sTrace := sTrace + \"2\";
+    ENDPROC
+ENDMODULE");
+        let mut output = Vec::new();
+        let mut host = Host::new(&mut output);
+        let globals = std::mem::take(&mut program.variables);
+        let mut vm = Vm::new(&bytecode, &program, globals, "main", &InterpreterOptions::default()).unwrap();
+        let pp = vm.pp().unwrap();
+        assert_eq!((pp.module.as_str(), pp.routine.as_str(), pp.span.to_string(), pp.depth), ("Pointer", "main", String::from("8:9"), 1));
+
+        vm.pp_to_cursor("main", 10).unwrap();
+        assert_eq!(vm.execute(&mut host).unwrap(), Status::Finished);
+        assert_eq!(vm.pp(), None);
+        vm.pp_to_routine("rhome").unwrap();
+        assert_eq!(vm.pp().unwrap().routine, "rHome");
+        vm.execute(&mut host).unwrap();
+        vm.pp_to_main().unwrap();
+        vm.execute(&mut host).unwrap();
+        assert_eq!(format!("{:?}", vm.globals), r#"[Str("2H1H2")]"#);
+
+        vm.pp_to_cursor("rHome", 5).unwrap();
+        assert_eq!(vm.pp().unwrap().span.line, 5);
+        assert_eq!(vm.pp_to_cursor("main", 7).unwrap_err().to_string(), "0:0: ERR_UNSUPPORTED: No statement of main on line 7");
+        assert_eq!(vm.pp_to_routine("rAway").unwrap_err().name(), "ERR_REFUNKPRC");
+    }
+
+    /// Events of a run as text, to compare the backends
+    #[derive(Default)]
+    struct Trace(Vec<String>);
+
+    impl host::Observer for Trace {
+        fn statement(&mut self, span: Span) {
+            self.0.push(format!("line {}", span.line));
+        }
+
+        fn enter_routine(&mut self, module: &str, routine: &str) {
+            self.0.push(format!("enter {}.{}", module, routine));
+        }
+
+        fn exit_routine(&mut self, module: &str, routine: &str) {
+            self.0.push(format!("exit {}.{}", module, routine));
+        }
+
+        fn assign(&mut self, name: &str, value: &Variable) {
+            self.0.push(format!("{} := {}", name, value));
+        }
+
+        fn error(&mut self, error: &RuntimeError) {
+            self.0.push(error.to_string());
+        }
+    }
+
+    #[test]
+    fn observers_follow_both_backends() {
+        let source = "
+MODULE Events
+    VAR num nTotal := 0;
+    VAR num nValues{2} := [0, 0];
+    PROC rAdd(INOUT num nSum, num nValue)
+        nSum := nSum + nValue;
+    ENDPROC
+    PROC main()
+        VAR num nIdx := 1;
+        WHILE nIdx <= 2 DO
+            nValues{nIdx} := nIdx;
+            nIdx := nIdx + 1;
+        ENDWHILE
+        rAdd nTotal, 3;
+        nTotal := nTotal / 0;
+        TPWrite \"Done\";
+    ERROR
+        TRYNEXT;
+    ENDPROC
+ENDMODULE";
+        let (mut program, bytecode) = compile_source(source);
+        let mut trace = Trace::default();
+        let mut output = Vec::new();
+        let mut host = Host::new(&mut output).with_observer(&mut trace);
+        run(&mut program, &bytecode, "main", &InterpreterOptions::default(), &mut host).unwrap();
+        assert_eq!(trace.0.join("\n"), "enter Events.main
+line 10
+line 11
+nValues := [1,0]
+line 12
+nIdx := 2
+line 11
+nValues := [1,2]
+line 12
+nIdx := 3
+line 14
+enter Events.rAdd
+line 6
+nSum := 3
+exit Events.rAdd
+nTotal := 3
+line 15
+15:9: ERR_DIVZERO: Division by zero
+line 18
+line 16
+exit Events.main");
+
+        let mut program = parser::parse_tokens(lexer::parse(source)).unwrap();
+        resolver::resolve(&mut program, &ResolveOptions::default()).unwrap();
+        let mut interpreted = Trace::default();
+        let mut host = Host::new(&mut output).with_observer(&mut interpreted);
+        interpreter::run(&mut program, "main", &InterpreterOptions::default(), &mut host).into_result().unwrap();
+        assert_eq!(interpreted.0, trace.0);
+    }
+}
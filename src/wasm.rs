@@ -0,0 +1,39 @@
+use wasm_bindgen::prelude::*;
+
+use crate::embed::{self, RunOptions};
+use crate::resolver::ResolveOptions;
+
+// JavaScript functions of the WebAssembly module, for a playground or an
+// editor in the browser. They take source text and return JSON text, which
+// `JSON.parse` turns into objects:
+//
+//     import init, { run } from "./rapid_rust.js";
+//     await init();
+//     const result = JSON.parse(run(source, "main", 1000000));
+
+/// Tokens of a module, or throws the error of the lexer
+#[wasm_bindgen]
+pub fn tokenize(source: &str) -> Result<String, JsValue> {
+    embed::tokenize(source).map(|json| json.to_string()).map_err(|err| JsValue::from_str(&err))
+}
+
+/// Modules with spans, or throws the error of the lexer or the parser
+#[wasm_bindgen]
+pub fn parse(source: &str) -> Result<String, JsValue> {
+    embed::parse(source).map(|json| json.to_string()).map_err(|err| JsValue::from_str(&err))
+}
+
+/// Errors, warnings and lints of a module, with a warning for routine data
+/// that shadows a PERS or CONST of the module when asked
+#[wasm_bindgen]
+pub fn check(source: &str, warn_shadowing: bool) -> String {
+    embed::check(source, &ResolveOptions { warn_shadowing, ..ResolveOptions::default() }).to_string()
+}
+
+/// Output, moves and error of a run on simulated time. The browser has no
+/// operator to answer dialogs, they take the answers in order.
+#[wasm_bindgen]
+pub fn run(source: &str, entry: &str, max_instructions: u32, answers: Vec<f64>) -> String {
+    let options = RunOptions { entry: entry.to_string(), max_instructions: max_instructions.into(), answers, ..RunOptions::default() };
+    embed::run(source, &options).to_string()
+}
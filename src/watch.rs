@@ -0,0 +1,124 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+// ------------------ Watcher -----------------------/
+
+/// Files of a program with their modification time and size, to find the
+/// ones that change. It polls, which works for folders RobotStudio or a
+/// network drive writes to as well.
+pub struct Watcher {
+    path: PathBuf,
+    files: Vec<(PathBuf, Option<SystemTime>, u64)>,
+}
+
+impl Watcher {
+    /// Watch a module file, or the files of the program a .pgf file or a
+    /// directory holds
+    pub fn new(path: &Path) -> Watcher {
+        Watcher { path: path.to_path_buf(), files: snapshot(path) }
+    }
+
+    /// Files added, removed or modified since the last look
+    pub fn changes(&mut self) -> Vec<PathBuf> {
+        let files = snapshot(&self.path);
+        let mut changed: Vec<PathBuf> = files.iter()
+            .filter(|file| !self.files.contains(file))
+            .map(|(path, _, _)| path.clone())
+            .collect();
+        for (path, _, _) in self.files.iter() {
+            if !files.iter().any(|(other, _, _)| other == path) {
+                changed.push(path.clone());
+            }
+        }
+        self.files = files;
+        changed
+    }
+
+    /// Block until files change, looking at an interval. Changes within the
+    /// next interval are part of it, editors often save in steps.
+    pub fn wait(&mut self, interval: Duration) -> Vec<PathBuf> {
+        loop {
+            let mut changed = self.changes();
+            if !changed.is_empty() {
+                thread::sleep(interval);
+                for path in self.changes() {
+                    if !changed.contains(&path) {
+                        changed.push(path);
+                    }
+                }
+                return changed;
+            }
+            thread::sleep(interval);
+        }
+    }
+}
+
+fn snapshot(path: &Path) -> Vec<(PathBuf, Option<SystemTime>, u64)> {
+    let mut paths = Vec::new();
+    let is_pgf = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("pgf"));
+    match path.parent() {
+        _ if path.is_dir() => program_files(path, &mut paths),
+        Some(dir) if is_pgf => program_files(if dir.as_os_str().is_empty() { Path::new(".") } else { dir }, &mut paths),
+        _ => paths.push(path.to_path_buf()),
+    }
+    paths.into_iter()
+        .map(|path| {
+            let metadata = fs::metadata(&path).ok();
+            let modified = metadata.as_ref().and_then(|metadata| metadata.modified().ok());
+            let size = metadata.map_or(0, |metadata| metadata.len());
+            (path, modified, size)
+        })
+        .collect()
+}
+
+/// Module, system module and program files in a directory and the
+/// directories in it
+fn program_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect(),
+        Err(_) => return,
+    };
+    entries.sort();
+    for path in entries {
+        let hidden = path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with('.'));
+        let extension = path.extension().and_then(|extension| extension.to_str()).map(str::to_ascii_lowercase);
+        if path.is_dir() && !hidden {
+            program_files(&path, files);
+        } else if matches!(extension.as_deref(), Some("mod" | "modx" | "sys" | "sysx" | "pgf")) {
+            files.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_changed_files() {
+        let dir = std::env::temp_dir().join(format!("rapid_rust_watch_{}", std::process::id()));
+        fs::create_dir_all(dir.join("SYSMOD")).unwrap();
+        fs::write(dir.join("Cell.mod"), "MODULE Cell\nENDMODULE").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        let mut watcher = Watcher::new(&dir);
+        assert!(watcher.changes().is_empty());
+
+        fs::write(dir.join("Cell.mod"), "MODULE Cell\n    VAR num n;\nENDMODULE").unwrap();
+        fs::write(dir.join("SYSMOD").join("user.sys"), "MODULE user(SYSMODULE)\nENDMODULE").unwrap();
+        fs::write(dir.join("notes.txt"), "Not a module").unwrap();
+        assert_eq!(watcher.changes(), vec![dir.join("Cell.mod"), dir.join("SYSMOD").join("user.sys")]);
+        assert!(watcher.changes().is_empty());
+
+        fs::remove_file(dir.join("Cell.mod")).unwrap();
+        assert_eq!(watcher.wait(Duration::from_millis(1)), vec![dir.join("Cell.mod")]);
+
+        // A module file alone
+        let mut watcher = Watcher::new(&dir.join("Main.mod"));
+        fs::write(dir.join("Main.mod"), "MODULE Main\nENDMODULE").unwrap();
+        fs::write(dir.join("Other.mod"), "MODULE Other\nENDMODULE").unwrap();
+        assert_eq!(watcher.changes(), vec![dir.join("Main.mod")]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}